@@ -1,3 +1,83 @@
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 fn main() {
-    embuild::espidf::sysenv::output();
+    // The simulator builds for the host, where there is no ESP-IDF environment
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("espidf") {
+        embuild::espidf::sysenv::output();
+    }
+
+    // Public key for signed OTA updates, embedded by src/ota.rs
+    println!("cargo:rerun-if-env-changed=OTA_PUBLIC_KEY");
+    let out = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("ota_public_key.pem");
+    let key = match std::env::var("OTA_PUBLIC_KEY") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            std::fs::read(&path).unwrap_or_else(|e| panic!("OTA_PUBLIC_KEY {}: {}", path, e))
+        }
+        Err(_) => {
+            if std::env::var("CARGO_FEATURE_OTA").is_ok() {
+                println!("cargo:warning=OTA_PUBLIC_KEY not set, firmware updates will be rejected");
+            }
+            Vec::new()
+        }
+    };
+    std::fs::write(out, key).unwrap();
+
+    // Default MQTT CA certificate, embedded by src/homeassistant.rs (NUL
+    // terminated, as ESP-TLS expects PEM)
+    println!("cargo:rerun-if-env-changed=MQTT_CA_CERT");
+    let out = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("mqtt_ca_cert.pem");
+    let cert = match std::env::var("MQTT_CA_CERT") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            let mut cert = std::fs::read(&path).unwrap_or_else(|e| panic!("MQTT_CA_CERT {}: {}", path, e));
+            cert.push(0);
+            cert
+        }
+        Err(_) => Vec::new(),
+    };
+    std::fs::write(out, cert).unwrap();
+
+    // Commit and date of the build, for the attributes in src/build_info.rs
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rustc-env=BUILD_GIT_HASH={}", git_hash());
+    let epoch_secs = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(secs) => secs.parse().unwrap_or_else(|e| panic!("SOURCE_DATE_EPOCH {}: {}", secs, e)),
+        Err(_) => SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+    };
+    println!("cargo:rustc-env=BUILD_DATE={}", iso_date(epoch_secs));
+}
+
+/// Short hash of HEAD, `-dirty` with uncommitted changes
+fn git_hash() -> String {
+    let git = |args: &[&str]| Command::new("git").args(args).output().ok().filter(|out| out.status.success());
+    let Some(head) = git(&["rev-parse", "--short=8", "HEAD"]) else {
+        return "unknown".to_string();
+    };
+    let hash = String::from_utf8_lossy(&head.stdout).trim().to_string();
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"]).is_some_and(|out| !out.stdout.is_empty());
+    if dirty {
+        format!("{}-dirty", hash)
+    } else {
+        hash
+    }
+}
+
+/// UTC date of a Unix time, as YYYY-MM-DD
+fn iso_date(epoch_secs: u64) -> String {
+    // Civil-from-days (Howard Hinnant), for days since 1970-01-01
+    let z = (epoch_secs / 86400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}", year, month, day)
 }
@@ -0,0 +1,499 @@
+//! Alarm conditions
+//!
+//! Watched on every sensor update, shown in Home Assistant as problem binary
+//! sensors and on the display as a banner across the top of the screen:
+//!
+//! - low level: the tank is at `alarm_low` percent or below
+//! - high pressure: line pressure is at `alarm_high_psi` or above
+//! - sensor fault: a fitted sensor has failed every read for `alarm_fault_secs`
+//! - pipe burst: the pressure collapsed with the pumps off (`pipe_burst`
+//!   builds, see `pipe_burst`); raised and cleared by the detector itself
+//! - filter clogged: the pressure lost across the whole-house filter has
+//!   been `alarm_clog_psi` or more for `CLOG_AFTER` (`filter_pressure`
+//!   builds); the delay rides out the swing when a pump starts
+//!
+//! Each alarm clears with its own hysteresis, so a reading hovering at a
+//! threshold doesn't make it flap: the level has to recover 5% above the
+//! threshold, the pressure has to fall 5 PSI below it, and a faulted sensor
+//! has to answer for 10 seconds. The filter drop falls to nothing whenever
+//! the water stops, so a clogged filter stays flagged until
+//! `reset_filter_clog` (the filter was changed) or the threshold is set to 0.
+//! Untrusted readings (sensor warm-up, failed
+//! reads) neither raise nor clear the level and pressure alarms.
+//!
+//! With `runbook_url` set, the display also shows a QR code linking to the
+//! troubleshooting steps for the alarm (see `runbook_link`): for `SPLASH_ON`
+//! when it's raised, then again every `SPLASH_EVERY` while it stays active.
+//!
+//! The latest `LOG_LEN` raises and clears are kept in an `AlarmLog` for the
+//! diagnostic bundle.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::clock::Timestamp;
+#[cfg(target_os = "espidf")]
+use crate::config::Config;
+
+/// Level rise above the low-level threshold that clears the alarm (%)
+pub const LOW_LEVEL_HYSTERESIS: u8 = 5;
+/// Pressure drop below the high-pressure threshold that clears the alarm (PSI)
+pub const HIGH_PRESSURE_HYSTERESIS: u16 = 5;
+/// How long every sensor has to answer again before a fault clears
+pub const SENSOR_FAULT_CLEAR: Duration = Duration::from_secs(10);
+/// How long the filter drop has to stay at the threshold to raise the alarm
+pub const CLOG_AFTER: Duration = Duration::from_secs(30);
+
+/// How long the runbook QR code is shown each time
+pub const SPLASH_ON: Duration = Duration::from_secs(20);
+/// How often the QR code comes back while the alarm stays active
+pub const SPLASH_EVERY: Duration = Duration::from_secs(60);
+/// Placeholder in `runbook_url` replaced by the alarm code
+pub const CODE_PLACEHOLDER: &str = "{code}";
+/// Alarm transitions kept in the `AlarmLog`
+pub const LOG_LEN: usize = 32;
+
+/// Alarm condition
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alarm {
+    LowLevel,
+    HighPressure,
+    SensorFault,
+    PipeBurst,
+    FilterClogged,
+}
+
+impl Alarm {
+    pub const ALL: [Alarm; 5] =
+        [Alarm::LowLevel, Alarm::HighPressure, Alarm::SensorFault, Alarm::PipeBurst, Alarm::FilterClogged];
+
+    /// Key in the MQTT state document
+    pub fn key(self) -> &'static str {
+        match self {
+            Alarm::LowLevel => "alarm_low_level",
+            Alarm::HighPressure => "alarm_high_pressure",
+            Alarm::SensorFault => "alarm_sensor_fault",
+            Alarm::PipeBurst => "alarm_pipe_burst",
+            Alarm::FilterClogged => "alarm_filter_clogged",
+        }
+    }
+
+    /// Short code printed on the display and passed to the runbook
+    pub fn code(self) -> &'static str {
+        match self {
+            Alarm::LowLevel => "AL01",
+            Alarm::HighPressure => "AL02",
+            Alarm::SensorFault => "AL03",
+            Alarm::PipeBurst => "AL04",
+            Alarm::FilterClogged => "AL05",
+        }
+    }
+
+    /// Name in Home Assistant and on the display banner
+    pub fn label(self) -> &'static str {
+        match self {
+            Alarm::LowLevel => "Low Level",
+            Alarm::HighPressure => "High Pressure",
+            Alarm::SensorFault => "Sensor Fault",
+            Alarm::PipeBurst => "Pipe Burst",
+            Alarm::FilterClogged => "Filter Clogged",
+        }
+    }
+}
+
+/// Runbook link for `alarm`, `None` while `runbook_url` is empty
+///
+/// `{code}` in the URL is replaced by the alarm code; without it the code is
+/// added as an `alarm` query parameter.
+pub fn runbook_link(url: &str, alarm: Alarm) -> Option<String> {
+    let url = url.trim();
+    if url.is_empty() {
+        return None;
+    }
+    if url.contains(CODE_PLACEHOLDER) {
+        return Some(url.replace(CODE_PLACEHOLDER, alarm.code()));
+    }
+    let separator = if url.contains('?') { '&' } else { '?' };
+    Some(format!("{}{}alarm={}", url, separator, alarm.code()))
+}
+
+/// Alarm thresholds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// Low-level threshold (%, 0 = off)
+    pub low_percent: u16,
+    /// High-pressure threshold (PSI, 0 = off)
+    pub high_psi: u16,
+    /// How long a sensor may fail before it raises a fault
+    pub fault_after: Duration,
+    /// Filter pressure drop threshold (PSI, 0 = off)
+    pub clog_psi: u16,
+}
+
+#[cfg(target_os = "espidf")]
+impl Thresholds {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            low_percent: config.alarm_low_percent,
+            high_psi: config.alarm_high_psi,
+            fault_after: Duration::from_secs(config.alarm_fault_secs as u64),
+            clog_psi: config.alarm_clog_psi,
+        }
+    }
+}
+
+/// Readings for one update
+#[derive(Debug, Clone, Default)]
+pub struct Readings<'a> {
+    /// Tank level (%), `None` while it isn't trusted
+    pub level: Option<u8>,
+    /// Line pressure (PSI), `None` while it isn't trusted
+    pub pressure_psi: Option<u16>,
+    /// Whether each fitted sensor answered its last read
+    pub sensors_ok: &'a [bool],
+    /// Pipe burst detector tripped
+    pub pipe_burst: bool,
+    /// Pressure lost across the filter (PSI), `None` while it isn't trusted
+    pub filter_drop_psi: Option<f32>,
+}
+
+/// Read history of one sensor
+#[derive(Debug, Clone, Default)]
+struct SensorWatch {
+    /// Start of the current run of failed reads
+    failing_since: Option<Duration>,
+    /// Start of the current run of good reads
+    answering_since: Option<Duration>,
+}
+
+/// Tracks the alarm conditions
+#[derive(Debug, Default)]
+pub struct Alarms {
+    low_level: bool,
+    high_pressure: bool,
+    sensor_fault: bool,
+    pipe_burst: bool,
+    filter_clogged: bool,
+    /// Start of the current run of filter drops at the threshold
+    clogging_since: Option<Duration>,
+    sensors: Vec<SensorWatch>,
+    /// When each active alarm was raised, in `Alarm::ALL` order
+    raised_at: [Option<Duration>; Alarm::ALL.len()],
+}
+
+impl Alarms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the alarms at `now`, returning the ones that were raised (`true`) or cleared
+    pub fn update(&mut self, readings: &Readings, thresholds: &Thresholds, now: Duration) -> Vec<(Alarm, bool)> {
+        let before = Alarm::ALL.map(|alarm| self.is_active(alarm));
+
+        if thresholds.low_percent == 0 {
+            self.low_level = false;
+        } else if let Some(level) = readings.level {
+            let low = thresholds.low_percent.min(100) as u8;
+            if level <= low {
+                self.low_level = true;
+            } else if level >= low.saturating_add(LOW_LEVEL_HYSTERESIS) {
+                self.low_level = false;
+            }
+        }
+
+        if thresholds.high_psi == 0 {
+            self.high_pressure = false;
+        } else if let Some(psi) = readings.pressure_psi {
+            if psi >= thresholds.high_psi {
+                self.high_pressure = true;
+            } else if psi.saturating_add(HIGH_PRESSURE_HYSTERESIS) <= thresholds.high_psi {
+                self.high_pressure = false;
+            }
+        }
+
+        self.sensors.resize(readings.sensors_ok.len(), SensorWatch::default());
+        for (watch, &ok) in self.sensors.iter_mut().zip(readings.sensors_ok) {
+            if ok {
+                watch.failing_since = None;
+                watch.answering_since.get_or_insert(now);
+            } else {
+                watch.answering_since = None;
+                watch.failing_since.get_or_insert(now);
+            }
+        }
+        let held = |since: Option<Duration>, period: Duration| since.is_some_and(|t| now.saturating_sub(t) >= period);
+        self.sensor_fault = if self.sensor_fault {
+            !self.sensors.iter().all(|watch| held(watch.answering_since, SENSOR_FAULT_CLEAR))
+        } else {
+            self.sensors.iter().any(|watch| held(watch.failing_since, thresholds.fault_after))
+        };
+        self.pipe_burst = readings.pipe_burst;
+
+        if thresholds.clog_psi == 0 {
+            self.filter_clogged = false;
+            self.clogging_since = None;
+        } else if let Some(drop) = readings.filter_drop_psi {
+            if drop >= thresholds.clog_psi as f32 {
+                let since = *self.clogging_since.get_or_insert(now);
+                self.filter_clogged |= now.saturating_sub(since) >= CLOG_AFTER;
+            } else {
+                self.clogging_since = None;
+            }
+        }
+        self.note_raised(now);
+
+        Alarm::ALL
+            .into_iter()
+            .zip(before)
+            .filter(|&(alarm, was)| self.is_active(alarm) != was)
+            .map(|(alarm, was)| (alarm, !was))
+            .collect()
+    }
+
+    /// Take over the alarms raised on another unit, in `Alarm::ALL` order (remote panel)
+    pub fn mirror(&mut self, active: [bool; Alarm::ALL.len()], now: Duration) {
+        [self.low_level, self.high_pressure, self.sensor_fault, self.pipe_burst, self.filter_clogged] = active;
+        self.note_raised(now);
+    }
+
+    /// Clear the clogged-filter alarm, after changing the filter; returns whether it was active
+    pub fn reset_filter_clog(&mut self) -> bool {
+        self.clogging_since = None;
+        std::mem::take(&mut self.filter_clogged)
+    }
+
+    pub fn is_active(&self, alarm: Alarm) -> bool {
+        match alarm {
+            Alarm::LowLevel => self.low_level,
+            Alarm::HighPressure => self.high_pressure,
+            Alarm::SensorFault => self.sensor_fault,
+            Alarm::PipeBurst => self.pipe_burst,
+            Alarm::FilterClogged => self.filter_clogged,
+        }
+    }
+
+    /// Alarm whose runbook QR code is due on the display at `now`
+    ///
+    /// The most recently raised of the active alarms, shown for `SPLASH_ON`
+    /// out of every `SPLASH_EVERY` since it was raised.
+    pub fn splash(&self, now: Duration) -> Option<Alarm> {
+        let (alarm, since) = Alarm::ALL
+            .into_iter()
+            .zip(self.raised_at)
+            .filter_map(|(alarm, at)| Some((alarm, at?)))
+            .max_by_key(|&(_, at)| at)?;
+        let elapsed = now.saturating_sub(since).as_millis();
+        (elapsed % SPLASH_EVERY.as_millis() < SPLASH_ON.as_millis()).then_some(alarm)
+    }
+
+    fn note_raised(&mut self, now: Duration) {
+        let active = Alarm::ALL.map(|alarm| self.is_active(alarm));
+        for (active, at) in active.into_iter().zip(self.raised_at.iter_mut()) {
+            if !active {
+                *at = None;
+            } else if at.is_none() {
+                *at = Some(now);
+            }
+        }
+    }
+
+    /// Display banner text, `None` while no alarm is active
+    pub fn banner(&self) -> Option<String> {
+        let active: Vec<&str> = Alarm::ALL.into_iter().filter(|&alarm| self.is_active(alarm)).map(Alarm::label).collect();
+        (!active.is_empty()).then(|| format!("ALARM: {}", active.join(", ")))
+    }
+}
+
+/// An alarm raised or cleared
+#[derive(Debug, Clone, PartialEq)]
+struct LogEntry {
+    uptime: Duration,
+    /// Wall-clock time, once SNTP has set the clock
+    time: Option<Timestamp>,
+    alarm: Alarm,
+    raised: bool,
+}
+
+/// The latest alarm transitions, oldest first
+#[derive(Debug, Default)]
+pub struct AlarmLog {
+    entries: VecDeque<LogEntry>,
+}
+
+impl AlarmLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note `alarm` raised (`true`) or cleared, dropping the oldest past `LOG_LEN`
+    pub fn record(&mut self, alarm: Alarm, raised: bool, uptime: Duration, time: Option<Timestamp>) {
+        if self.entries.len() == LOG_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LogEntry { uptime, time, alarm, raised });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// One line per transition: `2026-10-14T07:05:00-05:00 (up 3600 s) raised AL01 Low Level`
+    ///
+    /// The time is `-` for transitions before SNTP had set the clock.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for entry in &self.entries {
+            let time = entry.time.map_or_else(|| "-".to_string(), |t| t.iso8601());
+            let action = if entry.raised { "raised" } else { "cleared" };
+            let _ = writeln!(
+                text,
+                "{} (up {} s) {} {} {}",
+                time,
+                entry.uptime.as_secs(),
+                action,
+                entry.alarm.code(),
+                entry.alarm.label()
+            );
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: Thresholds =
+        Thresholds { low_percent: 20, high_psi: 80, fault_after: Duration::from_secs(60), clog_psi: 10 };
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_level_and_pressure_hysteresis() {
+        let mut alarms = Alarms::new();
+        let readings = |level, pressure_psi| Readings { level, pressure_psi, ..Default::default() };
+        assert_eq!(alarms.update(&readings(Some(50), Some(60)), &THRESHOLDS, secs(0)), vec![]);
+        assert_eq!(
+            alarms.update(&readings(Some(20), Some(80)), &THRESHOLDS, secs(5)),
+            vec![(Alarm::LowLevel, true), (Alarm::HighPressure, true)]
+        );
+        assert_eq!(alarms.banner().as_deref(), Some("ALARM: Low Level, High Pressure"));
+        // Inside the hysteresis band, or untrusted: no change
+        assert_eq!(alarms.update(&readings(Some(24), Some(76)), &THRESHOLDS, secs(10)), vec![]);
+        assert_eq!(alarms.update(&readings(None, None), &THRESHOLDS, secs(15)), vec![]);
+        assert_eq!(
+            alarms.update(&readings(Some(25), Some(75)), &THRESHOLDS, secs(20)),
+            vec![(Alarm::LowLevel, false), (Alarm::HighPressure, false)]
+        );
+        assert_eq!(alarms.banner(), None);
+
+        // A threshold of 0 turns the alarm off
+        let off = Thresholds { low_percent: 0, high_psi: 0, ..THRESHOLDS };
+        assert_eq!(alarms.update(&readings(Some(0), Some(150)), &off, secs(25)), vec![]);
+
+        // The burst detector does its own latching
+        let burst = Readings { pipe_burst: true, ..readings(None, Some(3)) };
+        assert_eq!(alarms.update(&burst, &THRESHOLDS, secs(30)), vec![(Alarm::PipeBurst, true)]);
+        assert_eq!(alarms.banner().as_deref(), Some("ALARM: Pipe Burst"));
+        assert_eq!(alarms.update(&readings(None, Some(3)), &THRESHOLDS, secs(35)), vec![(Alarm::PipeBurst, false)]);
+    }
+
+    #[test]
+    fn test_sensor_fault_after_delay() {
+        let mut alarms = Alarms::new();
+        let update = |alarms: &mut Alarms, sensors_ok: &[bool], at| {
+            alarms.update(&Readings { sensors_ok, ..Default::default() }, &THRESHOLDS, secs(at))
+        };
+        assert_eq!(update(&mut alarms, &[true, false], 0), vec![]);
+        assert_eq!(update(&mut alarms, &[true, false], 55), vec![]);
+        assert_eq!(update(&mut alarms, &[true, false], 60), vec![(Alarm::SensorFault, true)]);
+        // Clears only once the sensor has answered for a while
+        assert_eq!(update(&mut alarms, &[true, true], 65), vec![]);
+        assert_eq!(update(&mut alarms, &[true, false], 70), vec![]);
+        assert_eq!(update(&mut alarms, &[true, true], 75), vec![]);
+        assert_eq!(update(&mut alarms, &[true, true], 85), vec![(Alarm::SensorFault, false)]);
+        // A single failed read is not a fault
+        assert_eq!(update(&mut alarms, &[false, true], 90), vec![]);
+        assert_eq!(update(&mut alarms, &[true, true], 200), vec![]);
+    }
+
+    #[test]
+    fn test_filter_clog_latches_until_reset() {
+        let mut alarms = Alarms::new();
+        let update = |alarms: &mut Alarms, drop, at| {
+            alarms.update(&Readings { filter_drop_psi: drop, ..Default::default() }, &THRESHOLDS, secs(at))
+        };
+        // A pump start spike shorter than the delay
+        assert_eq!(update(&mut alarms, Some(14.0), 0), vec![]);
+        assert_eq!(update(&mut alarms, Some(4.0), 5), vec![]);
+        assert_eq!(update(&mut alarms, Some(11.0), 10), vec![]);
+        assert_eq!(update(&mut alarms, None, 20), vec![]);
+        assert_eq!(update(&mut alarms, Some(10.0), 40), vec![(Alarm::FilterClogged, true)]);
+        // No flow, no drop: still clogged
+        assert_eq!(update(&mut alarms, Some(0.0), 45), vec![]);
+        assert_eq!(alarms.banner().as_deref(), Some("ALARM: Filter Clogged"));
+        assert!(alarms.reset_filter_clog());
+        assert!(!alarms.reset_filter_clog());
+        assert_eq!(update(&mut alarms, Some(12.0), 50), vec![]);
+        assert_eq!(update(&mut alarms, Some(12.0), 80), vec![(Alarm::FilterClogged, true)]);
+        // A threshold of 0 turns it off
+        let off = Thresholds { clog_psi: 0, ..THRESHOLDS };
+        assert_eq!(alarms.update(&Readings::default(), &off, secs(85)), vec![(Alarm::FilterClogged, false)]);
+    }
+
+    #[test]
+    fn test_runbook_link_and_splash() {
+        assert_eq!(runbook_link(" ", Alarm::LowLevel), None);
+        assert_eq!(
+            runbook_link("https://wiki.local/runbook/{code}.html", Alarm::SensorFault).as_deref(),
+            Some("https://wiki.local/runbook/AL03.html")
+        );
+        assert_eq!(runbook_link("http://kb/rb", Alarm::LowLevel).as_deref(), Some("http://kb/rb?alarm=AL01"));
+        assert_eq!(runbook_link("http://kb/?site=barn", Alarm::PipeBurst).as_deref(), Some("http://kb/?site=barn&alarm=AL04"));
+
+        let mut alarms = Alarms::new();
+        let readings = |level, pressure_psi| Readings { level, pressure_psi, ..Default::default() };
+        alarms.update(&readings(Some(10), Some(60)), &THRESHOLDS, secs(100));
+        assert_eq!(alarms.splash(secs(100)), Some(Alarm::LowLevel));
+        assert_eq!(alarms.splash(secs(125)), None);
+        assert_eq!(alarms.splash(secs(165)), Some(Alarm::LowLevel));
+        // A newer alarm takes over, the older one is back once it clears
+        alarms.update(&readings(Some(10), Some(90)), &THRESHOLDS, secs(130));
+        assert_eq!(alarms.splash(secs(130)), Some(Alarm::HighPressure));
+        alarms.update(&readings(Some(10), Some(70)), &THRESHOLDS, secs(140));
+        assert_eq!(alarms.splash(secs(165)), Some(Alarm::LowLevel));
+        alarms.update(&readings(Some(50), Some(70)), &THRESHOLDS, secs(170));
+        assert_eq!(alarms.splash(secs(170)), None);
+
+        alarms.mirror([false, false, true, false, false], secs(200));
+        assert_eq!(alarms.splash(secs(210)), Some(Alarm::SensorFault));
+    }
+    #[test]
+    fn test_alarm_log() {
+        let mut log = AlarmLog::new();
+        assert!(log.is_empty());
+        assert_eq!(log.to_text(), "");
+        log.record(Alarm::SensorFault, true, secs(5), None);
+        let time = Timestamp { epoch_secs: 1_791_979_500, utc_offset_secs: -5 * 3600 };
+        log.record(Alarm::SensorFault, false, secs(75), Some(time));
+        let text = log.to_text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("- (up 5 s) raised "));
+        assert!(lines[1].starts_with("2026-10-14T07:05:00-05:00 (up 75 s) cleared "));
+        assert!(lines[1].ends_with(Alarm::SensorFault.label()));
+
+        for i in 0..LOG_LEN as u64 {
+            log.record(Alarm::LowLevel, i % 2 == 0, secs(100 + i), None);
+        }
+        assert_eq!(log.len(), LOG_LEN);
+        assert!(log.to_text().starts_with("- (up 100 s) raised "));
+    }
+}
@@ -5,22 +5,29 @@ use std::time::Duration;
 #[cfg(feature = "ethernet")]
 use std::net::Ipv4Addr;
 #[cfg(feature = "ethernet")]
-use std::sync::mpsc::{self, Receiver};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+#[cfg(all(feature = "buttons", not(feature = "ethernet")))]
+use std::sync::mpsc;
+#[cfg(feature = "ethernet")]
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(feature = "display")]
-use embedded_graphics::geometry::{Point, Size};
+use embedded_graphics::geometry::{Dimensions, Point, Size};
 #[cfg(feature = "display")]
 use embedded_graphics::{
   Drawable,
-  mono_font::{MonoTextStyleBuilder, ascii::FONT_10X20},
-  pixelcolor::BinaryColor,
+  mono_font::MonoTextStyleBuilder,
   text::Text,
 };
-#[cfg(feature = "display")]
+#[cfg(any(feature = "frame_overlay", all(feature = "display", feature = "ethernet")))]
+use embedded_graphics::text::{Alignment, Baseline, TextStyleBuilder};
+#[cfg(any(feature = "display", feature = "lora"))]
 use esp_idf_svc::hal::spi::{
   SpiDeviceDriver, SpiDriver, SpiDriverConfig,
-  config::{Config as SpiConfig, BitOrder},
+  config::Config as SpiConfig,
 };
+#[cfg(all(feature = "display", not(feature = "tft")))]
+use esp_idf_svc::hal::spi::config::BitOrder;
 
 #[cfg(feature = "ethernet")]
 use esp_idf_svc::eth::{
@@ -32,32 +39,157 @@ use esp_idf_svc::ipv4::{self, ClientConfiguration, DHCPClientSettings};
 use esp_idf_svc::netif::{EspNetif, IpEvent, NetifConfiguration};
 
 use esp_idf_svc::eventloop::EspSystemEventLoop;
-#[cfg(feature = "display")]
 use esp_idf_svc::hal::gpio::PinDriver;
+#[cfg(any(feature = "pump", feature = "heater", feature = "vfd"))]
+use esp_idf_svc::hal::gpio::AnyOutputPin;
+#[cfg(feature = "buttons")]
+use esp_idf_svc::hal::gpio::InputPin;
 #[cfg(feature = "ethernet")]
 use esp_idf_svc::hal::gpio::{AnyIOPin, Gpio0, Gpio16, Gpio17};
 #[cfg(all(feature = "radar", not(feature = "ethernet")))]
 use esp_idf_svc::hal::gpio::AnyIOPin;
 use esp_idf_svc::hal::prelude::*;
-#[cfg(feature = "radar")]
+#[cfg(any(feature = "radar", feature = "cellular"))]
 use esp_idf_svc::hal::uart::{self, UartDriver};
-use esp_idf_svc::log::EspLogger;
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
 use log::*;
 
+use watercontroller::alarms::Alarms;
+#[cfg(feature = "ethernet")]
+use watercontroller::alarms::AlarmLog;
+#[cfg(not(feature = "remote"))]
+use watercontroller::alarms::{Readings, Thresholds};
+#[cfg(any(feature = "mqtt", feature = "lora", feature = "notify"))]
+use watercontroller::alarms::Alarm;
+#[cfg(feature = "display")]
+use watercontroller::alarms::runbook_link;
+use watercontroller::logging;
 #[cfg(feature = "display")]
+use watercontroller::display::Panel;
+#[cfg(all(feature = "display", not(feature = "tft")))]
 use watercontroller::ls027b7dh01::Ls027b7dh01;
+#[cfg(feature = "tft")]
+use watercontroller::tft::{Model, Tft};
 #[cfg(feature = "display")]
-use watercontroller::ui::{WaterTank, Manometer};
+use watercontroller::ui::{AlertBanner, BootScreen, FillPattern, Manometer, Page, PageManager, QrScreen, TextPage, Theme, WaterTank};
+#[cfg(all(feature = "display", feature = "radar"))]
+use watercontroller::ui::{BudgetBar, TrendGraph};
+#[cfg(feature = "radar")]
+use watercontroller::sen0676::{DEFAULT_ADDRESS, Poll, Sen0676, Sen0676Options};
+#[cfg(feature = "radar")]
+use watercontroller::level::TankGeometry;
 #[cfg(feature = "radar")]
-use watercontroller::sen0676::{DEFAULT_ADDRESS, Sen0676};
+use watercontroller::filter::LevelFilter;
+#[cfg(feature = "radar")]
+use watercontroller::stats::UsageStats;
+#[cfg(feature = "radar")]
+use watercontroller::polling::RadarPolling;
+#[cfg(any(feature = "radar", feature = "pressure"))]
+use watercontroller::correction::Correction;
 #[cfg(feature = "pressure")]
-use watercontroller::pressure::PressureSensor;
+use watercontroller::correction::pressure_head;
+#[cfg(any(feature = "radar", feature = "pressure"))]
+use watercontroller::sensors;
+#[cfg(feature = "pressure")]
+use watercontroller::pressure::{shared_adc, PressureSensor, Tap, TransducerProfile};
+#[cfg(feature = "filter_pressure")]
+use watercontroller::pressure::read_differential;
 #[cfg(feature = "mqtt")]
-use watercontroller::homeassistant::{ConfigCommand, HomeAssistant, WaterState};
+use watercontroller::homeassistant::{ConfigCommand, HomeAssistant, MqttTls, Naming, WaterState};
+#[cfg(all(feature = "mqtt", feature = "pump"))]
+use watercontroller::homeassistant::PumpState;
+#[cfg(all(feature = "mqtt", feature = "radar"))]
+use watercontroller::homeassistant::RadarDistances;
+use watercontroller::board::BoardProfile;
+#[cfg(feature = "buttons")]
+use watercontroller::buttons::{self, ButtonId, Press};
 use watercontroller::config::Config;
+use watercontroller::provision;
+use watercontroller::memory::{self, MemoryGuard};
+#[cfg(feature = "ethernet")]
+use watercontroller::diag::{self, Diagnostics, StartupTiming};
+#[cfg(any(feature = "display", feature = "ethernet", feature = "pump", feature = "vfd", feature = "heater", feature = "radar", feature = "pressure", feature = "valve", feature = "lora"))]
+use watercontroller::clock::{Clock, SystemClock};
+#[cfg(feature = "ethernet")]
+use watercontroller::clock::Ticker;
+#[cfg(feature = "ethernet")]
+use watercontroller::web::{LiveStatus, WebServer};
+#[cfg(feature = "ethernet")]
+use watercontroller::net::{self, Advertisement};
+#[cfg(feature = "ethernet")]
+use watercontroller::testfire::{Output, Pulse, PULSE};
 #[cfg(feature = "ethernet")]
-use watercontroller::web::WebServer;
+use watercontroller::selftest::{Outcome, SelfTest, Step};
+#[cfg(feature = "lockout")]
+use watercontroller::lockout::Lockout;
+#[cfg(feature = "sim-sensors")]
+use watercontroller::playback::Playback;
+#[cfg(all(feature = "mqtt", feature = "ota"))]
+use watercontroller::ota::Updater;
+#[cfg(feature = "cellular")]
+use watercontroller::cellular::Cellular;
+#[cfg(feature = "wifi")]
+use watercontroller::wifi::WifiLink;
+#[cfg(feature = "wifi")]
+use watercontroller::captive::AP_ADDRESS;
+#[cfg(feature = "ethernet")]
+use watercontroller::schedule::RebootSchedule;
+#[cfg(feature = "ethernet")]
+use esp_idf_svc::sntp::EspSntp;
+#[cfg(feature = "history")]
+use watercontroller::history::{History, Sample};
+#[cfg(all(feature = "history", feature = "mqtt"))]
+use watercontroller::history::HistoryStats;
+#[cfg(any(feature = "radar", feature = "pressure"))]
+use watercontroller::warmup::Warmup;
+#[cfg(feature = "pump")]
+use watercontroller::pump::{PumpController, PumpSettings};
+#[cfg(feature = "heater")]
+use watercontroller::heater::{HeaterController, HeaterSettings};
+#[cfg(feature = "hammer")]
+use watercontroller::hammer::{Burst, Cause};
+#[cfg(feature = "well_pump")]
+use watercontroller::well_pump::WellPump;
+#[cfg(feature = "flow")]
+use watercontroller::flow::{FlowMeter, PulseCounter};
+#[cfg(feature = "pipe_burst")]
+use watercontroller::pipe_burst::BurstDetector;
+#[cfg(feature = "efficiency")]
+use watercontroller::efficiency::{CycleTracker, PumpTrend};
+#[cfg(feature = "efficiency")]
+use watercontroller::pump::PUMP_COUNT;
+#[cfg(feature = "pump_power")]
+use watercontroller::ct_clamp::{ClampSettings, CtClamp};
+#[cfg(feature = "notify")]
+use watercontroller::notify::{self, AlarmMonitor, Inputs, Notification, Vars, Webhook};
+#[cfg(feature = "influx")]
+use watercontroller::influx::{self, InfluxWriter, Point, Value};
+#[cfg(feature = "ds18b20")]
+use watercontroller::ds18b20::{self, ProbeBus};
+#[cfg(feature = "ds18b20")]
+use esp_idf_svc::hal::onewire::OWDriver;
+#[cfg(feature = "valve")]
+use watercontroller::valve::{Drive, ValveCommand, ValveController, ValveState};
+#[cfg(feature = "valve_limits")]
+use watercontroller::valve::Limits;
+#[cfg(feature = "vfd")]
+use watercontroller::pid::{AutotuneStep, Pid, RelayAutotune};
+#[cfg(feature = "vfd")]
+use watercontroller::vfd::{SpeedOutput, PWM_FREQUENCY_HZ};
+#[cfg(feature = "lora")]
+use watercontroller::codec::StateFrame;
+#[cfg(feature = "lora")]
+use watercontroller::lora::{Beacon, Role, FREQ_STEP_HZ};
+#[cfg(feature = "lora")]
+use watercontroller::sx1276::Sx1276;
+#[cfg(feature = "remote")]
+use watercontroller::remote::{PanelState, RemotePanel};
+#[cfg(feature = "vfd")]
+use esp_idf_svc::hal::ledc::{LedcDriver, LedcTimerDriver, config::{Resolution, TimerConfig}};
+
+/// How long a `wifi` build waits for Ethernet at boot before using WiFi
+#[cfg(feature = "wifi")]
+const ETH_LINK_TIMEOUT: Duration = Duration::from_secs(20);
 
 /// Network events communicated from event callbacks to main loop
 #[cfg(feature = "ethernet")]
@@ -71,7 +203,7 @@ enum NetEvent {
 
 fn main() -> anyhow::Result<()> {
   esp_idf_svc::sys::link_patches();
-  EspLogger::initialize_default();
+  logging::init();
   let app_name = env!("CARGO_PKG_NAME");
   esp_idf_svc::log::set_target_level(app_name, log::LevelFilter::Debug).unwrap();
   log::set_max_level(log::LevelFilter::Debug);
@@ -91,6 +223,10 @@ fn main() -> anyhow::Result<()> {
 }
 
 fn run() -> anyhow::Result<()> {
+  // Init phase timings, for the startup report once Home Assistant has discovery
+  #[cfg(feature = "ethernet")]
+  let mut startup = StartupTiming::new(SystemClock.uptime());
+
   // Log enabled features
   #[cfg(feature = "display")]
   info!("Feature enabled: display");
@@ -102,25 +238,72 @@ fn run() -> anyhow::Result<()> {
   info!("Feature enabled: pressure");
   #[cfg(feature = "mqtt")]
   info!("Feature enabled: mqtt");
+  #[cfg(feature = "history")]
+  info!("Feature enabled: history");
 
   let peripherals = Peripherals::take()?;
   let sysloop = EspSystemEventLoop::take()?;
 
+  // ============================================================
+  // Carrier board identification (ID straps on GPIO34/GPIO35)
+  // ============================================================
+  let board = {
+    let bit0 = PinDriver::input(peripherals.pins.gpio34)?;
+    let bit1 = PinDriver::input(peripherals.pins.gpio35)?;
+    let id = BoardProfile::id_from_straps(bit0.is_high(), bit1.is_high());
+    BoardProfile::from_id(id).unwrap_or_else(|| {
+      warn!("Board: unknown ID {}, using rev A pinout", id);
+      BoardProfile::REV_A
+    })
+  };
+  info!("Board: {:?}", board);
+
   // ============================================================
   // NVS configuration
   // ============================================================
+  #[cfg(feature = "ethernet")]
+  startup.begin("nvs", SystemClock.uptime());
   let nvs_partition = EspDefaultNvsPartition::take()?;
+  // The WiFi driver keeps its calibration data next to the settings
+  #[cfg(feature = "wifi")]
+  let wifi_nvs = nvs_partition.clone();
   let config = Arc::new(Mutex::new(Config::load(nvs_partition)?));
+  // Remote syslog; records go out once the network is up
+  {
+    let cfg = config.lock().unwrap();
+    if let Err(e) = logging::start_syslog(&cfg.syslog_server, logging::level_filter(cfg.syslog_level)) {
+      warn!("Syslog: can't start: {}", e);
+    }
+  }
+  #[cfg(feature = "ethernet")]
+  startup.end(SystemClock.uptime());
+
+  // JSON provisioning over the USB/UART console (works without a network)
+  provision::start(config.clone())?;
 
   // ============================================================
   // Display initialization (feature: display) - hardware SPI
   // ============================================================
-  #[cfg(feature = "display")]
+  #[cfg(all(feature = "display", feature = "ethernet"))]
+  startup.begin("display", SystemClock.uptime());
+  // With a LoRa radio the bus also gets MISO (GPIO32) and is shared
+  #[cfg(feature = "lora")]
+  let spi_bus = std::rc::Rc::new(SpiDriver::new(
+    peripherals.spi2,
+    peripherals.pins.gpio18, // SCLK
+    peripherals.pins.gpio23, // MOSI
+    Some(peripherals.pins.gpio32), // MISO
+    &SpiDriverConfig::default(),
+  )?);
+  #[cfg(all(feature = "display", not(feature = "tft")))]
   let mut display = {
     // CS: GPIO5, SCLK: GPIO18, MOSI: GPIO23 (VSPI)
     info!("Initializing Sharp Memory Display (hardware SPI)...");
 
     // Create SPI driver (VSPI = SPI2)
+    #[cfg(feature = "lora")]
+    let spi_driver = spi_bus.clone();
+    #[cfg(not(feature = "lora"))]
     let spi_driver = SpiDriver::new(
       peripherals.spi2,
       peripherals.pins.gpio18, // SCLK
@@ -143,53 +326,194 @@ fn run() -> anyhow::Result<()> {
 
     let mut display = Ls027b7dh01::new(spi_device, cs_pin);
     display.init()?;
+    display.set_flush_budget(config.lock().unwrap().display_flush_lines);
+    display.set_refresh_interval(Duration::from_secs(config.lock().unwrap().display_refresh_minutes as u64 * 60));
     info!("Display initialized");
 
     display
   };
 
-  // Create UI components
+  // Color TFT alternative (feature: ili9341 / st7789) - same SPI bus
+  #[cfg(feature = "tft")]
+  let mut display = {
+    // CS: GPIO5, SCLK: GPIO18, MOSI: GPIO23 (VSPI), DC: GPIO32, RST: GPIO33
+    info!("Initializing color TFT (hardware SPI)...");
+
+    let spi_driver = SpiDriver::new(
+      peripherals.spi2,
+      peripherals.pins.gpio18, // SCLK
+      peripherals.pins.gpio23, // MOSI
+      Option::<esp_idf_svc::hal::gpio::AnyIOPin>::None, // MISO not used
+      &SpiDriverConfig::default(),
+    )?;
+
+    // SPI Mode 0, MSB-first, CS driven by the SPI driver (active LOW)
+    let spi_config = SpiConfig::default()
+      .baudrate(20.MHz().into())
+      .write_only(true);
+
+    let spi_device = SpiDeviceDriver::new(spi_driver, Some(peripherals.pins.gpio5), &spi_config)?;
+
+    let dc_pin = PinDriver::output(peripherals.pins.gpio32)?;
+    let rst_pin = PinDriver::output(peripherals.pins.gpio33)?;
+
+    #[cfg(feature = "st7789")]
+    let model = Model::St7789;
+    #[cfg(not(feature = "st7789"))]
+    let model = Model::Ili9341;
+
+    let mut display = Tft::new(spi_device, dc_pin, rst_pin, model);
+    display.init()?;
+    display.set_flush_budget(config.lock().unwrap().display_flush_lines);
+    info!("Display initialized ({:?})", model);
+
+    display
+  };
+
+  #[cfg(all(feature = "display", feature = "ethernet"))]
+  startup.end(SystemClock.uptime());
+
+  // Create UI components, themed for the active panel
+  #[cfg(feature = "display")]
+  let theme = Theme {
+    fill: FillPattern::from_code(config.lock().unwrap().tank_fill_pattern),
+    ..Theme::new(display.palette())
+  };
+
+  // 400px wide LCD: tank, 24h level trend, gauge
+  #[cfg(all(feature = "display", not(feature = "tft")))]
+  let (mut tank, mut manometer) = (
+    WaterTank::new(Point::new(10, 20), Size::new(100, 200), theme),
+    Manometer::new(Point::new(305, 120), 90, theme),
+  );
+  #[cfg(all(feature = "display", feature = "radar", not(feature = "tft")))]
+  let mut trend = TrendGraph::new(Point::new(116, 70), Size::new(98, 100), TREND_SAMPLES, "24h", theme);
+
+  // 320px wide panel: narrower tank, smaller gauge
+  #[cfg(feature = "tft")]
+  let (mut tank, mut manometer) = (
+    WaterTank::new(Point::new(15, 20), Size::new(100, 200), theme),
+    Manometer::new(Point::new(218, 120), 95, theme),
+  );
   #[cfg(feature = "display")]
-  let mut tank = WaterTank::new(Point::new(20, 20), Size::new(120, 200));
+  {
+    tank.name = config.lock().unwrap().tank_label.clone();
+  }
 
+  // Further pages for what the enabled features can fill, cycled by timer or button
+  #[cfg(feature = "display")]
+  let mut pages = PageManager::new(
+    [
+      Some(Page::Overview),
+      cfg!(feature = "ethernet").then_some(Page::Network),
+      cfg!(feature = "radar").then_some(Page::History),
+      // A remote panel's own settings don't describe the tank it shows
+      (!cfg!(feature = "remote")).then_some(Page::Config),
+    ].into_iter().flatten().collect(),
+    Duration::from_secs(config.lock().unwrap().page_interval_secs as u64),
+  );
+  #[cfg(feature = "display")]
+  let page_size = display.bounding_box().size - Size::new(20, 8);
+  #[cfg(all(feature = "display", feature = "ethernet"))]
+  let mut network_page = TextPage::new(Point::new(10, 4), page_size, "Network", theme);
+  #[cfg(all(feature = "display", feature = "radar"))]
+  let (mut history_page, mut budget_bar, mut history_trend) = (
+    TextPage::new(Point::new(10, 4), Size::new(page_size.width, 80), "History", theme),
+    BudgetBar::new(Point::new(10, 88), Size::new(page_size.width, 26), theme),
+    TrendGraph::new(Point::new(10, 130), Size::new(page_size.width, 100), TREND_SAMPLES, "Level, 24h", theme),
+  );
   #[cfg(feature = "display")]
-  let mut manometer = Manometer::new(Point::new(280, 120), 100);
+  let mut config_page = TextPage::new(Point::new(10, 4), page_size, "Settings", theme);
+  // Replaces all pages while a lockout is engaged
+  #[cfg(all(feature = "display", feature = "lockout"))]
+  let mut lockout_page = TextPage::new(Point::new(10, 4), page_size, "*** LOCKED OUT ***", theme);
+  // Drawn across the top of any page while an alarm is active
+  #[cfg(feature = "display")]
+  let mut alert_banner = AlertBanner::new(Point::zero(), Size::new(display.bounding_box().size.width, 20), theme);
+  // Covers the page with a QR code to the alarm's runbook now and then (see `alarms`)
+  #[cfg(feature = "display")]
+  let mut runbook_screen = QrScreen::new(Point::new(10, 4), page_size, theme);
+  #[cfg(feature = "display")]
+  let mut showing_runbook = false;
 
   // Boot status display helper
   #[cfg(feature = "display")]
   let boot_text_style = MonoTextStyleBuilder::new()
-    .font(&FONT_10X20)
-    .text_color(BinaryColor::Off)
+    .font(theme.font)
+    .text_color(theme.colors().foreground)
+    .build();
+
+  // Local time in the top right corner once SNTP has set the clock
+  #[cfg(all(feature = "display", feature = "ethernet"))]
+  let status_text_style = MonoTextStyleBuilder::new()
+    .font(theme.label_font)
+    .text_color(theme.colors().foreground)
+    .background_color(theme.colors().background)
+    .build();
+  #[cfg(all(feature = "display", feature = "ethernet"))]
+  let status_alignment = TextStyleBuilder::new().alignment(Alignment::Right).baseline(Baseline::Top).build();
+
+  // Frame and flush time in the bottom right corner, on a background so it overwrites itself
+  #[cfg(feature = "frame_overlay")]
+  let overlay_text_style = MonoTextStyleBuilder::new()
+    .font(theme.label_font)
+    .text_color(theme.colors().foreground)
+    .background_color(theme.colors().background)
     .build();
+  #[cfg(feature = "frame_overlay")]
+  let overlay_alignment = TextStyleBuilder::new().alignment(Alignment::Right).baseline(Baseline::Bottom).build();
 
+  // Boot progress, one line per step; scrolls once the steps outgrow the screen
   #[cfg(feature = "display")]
-  let mut boot_line = 0i32;
+  let mut boot_screen = BootScreen::new(
+    Point::new(10, 4),
+    page_size,
+    format!("Water Controller v{}", env!("CARGO_PKG_VERSION")),
+    theme,
+  );
+  #[cfg(feature = "display")]
+  {
+    display.clear_framebuffer();
+    boot_screen.draw(&mut display).ok();
+    display.flush_all().ok();
+  }
+
+  /// Start a boot step on the display; it shows as done once the next line appears
+  macro_rules! boot_step {
+    ($($arg:tt)*) => {
+      #[cfg(feature = "display")]
+      {
+        boot_screen.step(format!($($arg)*));
+        boot_screen.draw(&mut display).ok();
+        display.flush_all().ok();
+      }
+    };
+  }
 
-  /// Show a boot progress line on the display, appending each new line
+  /// Show a boot progress line without a step icon (addresses, settings)
   macro_rules! boot_status {
     ($($arg:tt)*) => {
       #[cfg(feature = "display")]
       {
-        use core::fmt::Write;
-        let mut buf = [0u8; 40];
-        let mut w = LineBuf::new(&mut buf);
-        let _ = write!(w, $($arg)*);
-        if boot_line == 0 {
-          display.clear_framebuffer();
-        }
-        let y = 26 + boot_line * 26;
-        Text::new(
-          w.as_str(),
-          Point::new(10, y as i32),
-          boot_text_style,
-        ).draw(&mut display).ok();
-        display.flush().ok();
-        boot_line += 1;
+        boot_screen.info(format!($($arg)*));
+        boot_screen.draw(&mut display).ok();
+        display.flush_all().ok();
       }
     };
   }
 
-  boot_status!("Water Controller v{}", env!("CARGO_PKG_VERSION"));
+  /// Mark the current boot step failed when boot goes on without it
+  #[allow(unused_macros)]
+  macro_rules! boot_failed {
+    () => {
+      #[cfg(feature = "display")]
+      {
+        boot_screen.fail();
+        boot_screen.draw(&mut display).ok();
+        display.flush_all().ok();
+      }
+    };
+  }
 
   // From here on, errors can be shown on the display.
   // Wrap the rest in a closure so we can catch errors.
@@ -198,13 +522,17 @@ fn run() -> anyhow::Result<()> {
   // ============================================================
   // Ethernet initialization (feature: ethernet)
   // ============================================================
+  // WiFi station or setup portal, when Ethernet has no link at boot
+  #[cfg(feature = "wifi")]
+  let mut wifi: Option<WifiLink> = None;
   #[cfg(feature = "ethernet")]
-  let (rx, _ip_addr, _eth, _eth_subscription, _ip_subscription) = {
+  let (rx, initial_addr, _eth, _eth_subscription, _ip_subscription) = {
     // RTL8201 PHY for wESP32 rev7+
     // Pin mapping:
     //   MDC: GPIO16, MDIO: GPIO17, Clock: GPIO0 (input from PHY), PHY Address: 0
     //   https://wesp32.com/files/wESP32-Product-Brief.pdf
-    boot_status!("Ethernet...");
+    boot_step!("Ethernet...");
+    startup.begin("ethernet", SystemClock.uptime());
     info!("Initializing Ethernet (RTL8201 PHY)...");
 
     let eth_driver = EthDriver::new_rmii(
@@ -227,7 +555,7 @@ fn run() -> anyhow::Result<()> {
     let netif_config = NetifConfiguration {
       ip_configuration: Some(ipv4::Configuration::Client(
         ClientConfiguration::DHCP(DHCPClientSettings {
-          hostname: Some("watercontroller".try_into().unwrap()),
+          hostname: Some(net::HOSTNAME.try_into().unwrap()),
         }),
       )),
       ..NetifConfiguration::eth_default_client()
@@ -288,13 +616,43 @@ fn run() -> anyhow::Result<()> {
     eth.start()?;
 
     // Wait for initial network connection
-    boot_status!("Waiting for DHCP...");
+    boot_step!("Waiting for DHCP...");
+    startup.begin("dhcp", SystemClock.uptime());
     info!("Waiting for network...");
-    let (ip, gateway) = wait_for_network(&rx)?;
-    boot_status!("IP: {}", ip);
-    info!("Network ready!");
-    info!("  IP address: {}", ip);
-    info!("  Gateway: {}", gateway);
+    #[cfg(not(feature = "wifi"))]
+    let addr = wait_for_network(&rx, None)?;
+    #[cfg(feature = "wifi")]
+    let addr = match wait_for_network(&rx, Some(ETH_LINK_TIMEOUT))? {
+      Some(addr) => Some(addr),
+      None => {
+        warn!("No Ethernet network after {:?}, using WiFi", ETH_LINK_TIMEOUT);
+        boot_failed!();
+        startup.begin("wifi", SystemClock.uptime());
+        let (ssid, password) = {
+          let cfg = config.lock().unwrap();
+          (cfg.wifi_ssid.clone(), cfg.wifi_pass.clone())
+        };
+        if !ssid.is_empty() {
+          boot_step!("WiFi: {}...", ssid);
+        }
+        let link = WifiLink::start(peripherals.modem, sysloop.clone(), wifi_nvs, &ssid, &password)?;
+        if link.is_portal() {
+          boot_failed!();
+          boot_status!("Join WiFi {}", link.portal_ssid());
+          info!("WiFi setup portal: join {} and visit http://{}/", link.portal_ssid(), AP_ADDRESS);
+        }
+        let addr = link.address();
+        wifi = Some(link);
+        addr
+      }
+    };
+    startup.end(SystemClock.uptime());
+    if let Some((ip, gateway)) = addr {
+      boot_status!("IP: {}", ip);
+      info!("Network ready!");
+      info!("  IP address: {}", ip);
+      info!("  Gateway: {}", gateway);
+    }
 
     // Log DNS servers received from DHCP
     let dns1 = eth.netif().get_dns();
@@ -302,32 +660,62 @@ fn run() -> anyhow::Result<()> {
     info!("  DNS primary: {}", dns1);
     info!("  DNS secondary: {}", dns2);
 
-    (rx, ip, eth, eth_subscription, ip_subscription)
+    (rx, addr, eth, eth_subscription, ip_subscription)
+  };
+  // Address and gateway for the network page and diagnostics (None while
+  // only the WiFi setup portal is up)
+  #[cfg(feature = "ethernet")]
+  let mut net_addr = initial_addr;
+  // watercontroller.local; the page is reachable by address without it
+  #[cfg(feature = "ethernet")]
+  let _mdns = match _eth.netif().get_mac().and_then(|mac| Advertisement::start(mac, env!("CARGO_PKG_VERSION"), board.revision.name())) {
+    Ok(advertisement) => Some(advertisement),
+    Err(e) => {
+      warn!("mDNS: not advertising: {:?}", e);
+      None
+    }
   };
+  // Where the setup page is reached
+  #[cfg(feature = "mqtt")]
+  let setup_addr = net_addr.map_or(Ipv4Addr::UNSPECIFIED, |(ip, _)| ip);
+  #[cfg(all(feature = "mqtt", feature = "wifi"))]
+  let setup_addr = if wifi.as_ref().is_some_and(WifiLink::is_portal) { AP_ADDRESS } else { setup_addr };
 
   // ============================================================
   // Radar sensor initialization (feature: radar)
   // ============================================================
   #[cfg(feature = "radar")]
   let mut radar = {
-    // TX: GPIO12, RX: GPIO13, 115200 baud, 8N1
-    boot_status!("Radar sensor...");
-    info!("Initializing UART1 for radar sensor...");
+    // TX/RX from the board profile (rev A: GPIO12/GPIO13), 115200 baud, 8N1
+    boot_step!("Radar sensor...");
+    info!("Initializing UART1 for radar sensor on GPIO{}/GPIO{}...", board.radar_tx, board.radar_rx);
     let uart_config = uart::config::Config::default().baudrate(Hertz(115200));
+    // SAFETY: board profile pins are not claimed anywhere else
     let uart = UartDriver::new(
       peripherals.uart1,
-      peripherals.pins.gpio12, // TX
-      peripherals.pins.gpio13, // RX
+      unsafe { AnyIOPin::new(board.radar_tx) }, // TX
+      unsafe { AnyIOPin::new(board.radar_rx) }, // RX
       Option::<AnyIOPin>::None,
       Option::<AnyIOPin>::None,
       &uart_config,
     )?;
 
-    let mut radar = Sen0676::new(uart, DEFAULT_ADDRESS);
+    // Transient CRC errors and missed replies are retried inside the driver
+    let options = Sen0676Options::new().response_timeout(Duration::from_millis(200)).retries(2);
+    let mut radar = Sen0676::with_options(uart, DEFAULT_ADDRESS, options);
+    // New sensors sometimes ship on another address; scan for it if the default stays silent
+    match radar.detect_address() {
+      Ok(DEFAULT_ADDRESS) => {}
+      Ok(addr) => warn!("Radar: no reply on address 0x{:02X}, using sensor found on 0x{:02X}", DEFAULT_ADDRESS, addr),
+      Err(e) => warn!("Radar: no sensor found on the bus: {:?}", e),
+    }
     let height_cm = config.lock().unwrap().radar_height_cm;
     match radar.configure_height(height_cm) {
       Ok(range) => info!("Radar: height {} cm, range {} m", height_cm, range),
-      Err(e) => warn!("Failed to configure radar height: {:?}", e),
+      Err(e) => {
+        warn!("Failed to configure radar height: {:?}", e);
+        boot_failed!();
+      }
     }
     info!("Radar sensor initialized");
 
@@ -337,45 +725,340 @@ fn run() -> anyhow::Result<()> {
   // ============================================================
   // Pressure sensor initialization (feature: pressure)
   // ============================================================
+  // ADC1 is shared with the post-filter sensor (feature: filter_pressure)
+  // and the pump CT clamp (feature: pump_power)
+  #[cfg(feature = "pressure")]
+  let pressure_adc = shared_adc(peripherals.adc1)?;
   #[cfg(feature = "pressure")]
   let mut pressure_sensor = {
     // GPIO36 (A0) with 10k/12k voltage divider
-    // Sensor: 0.5V = 0 PSI, 4.5V = 100 PSI
-    boot_status!("Pressure sensor...");
+    // Sensor: 0.5V = 0 PSI, 4.5V = 100 PSI unless the profile says otherwise
+    boot_step!("Pressure sensor...");
     info!("Initializing pressure sensor on GPIO36...");
-    let sensor = PressureSensor::new(peripherals.adc1, peripherals.pins.gpio36)?;
-    info!("Pressure sensor ready");
+    let mut sensor = PressureSensor::new(pressure_adc.clone(), peripherals.pins.gpio36, Tap::Line)?;
+    let (profile, supply_mv) = {
+      let cfg = config.lock().unwrap();
+      (TransducerProfile::from_config(&cfg), cfg.psi_supply_mv)
+    };
+    sensor.set_profile(profile);
+    sensor.set_supply(supply_mv);
+    info!(
+      "Pressure sensor ready, {}-{} mV = 0-{} PSI{}",
+      profile.min_mv,
+      profile.max_mv,
+      profile.max_psi,
+      if profile.ratiometric { format!(", ratiometric at {} mV supply", supply_mv) } else { String::new() }
+    );
+    sensor
+  };
+
+  // Second transducer after the whole-house filter, GPIO39 with the same divider
+  #[cfg(feature = "filter_pressure")]
+  let mut filter_sensor = {
+    info!("Initializing post-filter pressure sensor on GPIO39...");
+    let mut sensor = PressureSensor::new(pressure_adc.clone(), peripherals.pins.gpio39, Tap::PostFilter)?;
+    let cfg = config.lock().unwrap();
+    sensor.set_profile(TransducerProfile::post_filter(&cfg));
+    sensor.set_supply(cfg.psi_supply_mv);
+    info!("Post-filter pressure sensor ready, 0 PSI at {} mV", cfg.psi2_min_mv);
     sensor
   };
 
+  // CT clamp on the pump supply, GPIO32 biased to mid-supply
+  #[cfg(feature = "pump_power")]
+  let mut ct_clamp = {
+    info!("Initializing pump CT clamp on GPIO32...");
+    CtClamp::new(pressure_adc, peripherals.pins.gpio32)?
+  };
+
+  // ============================================================
+  // Flow meter (feature: flow)
+  // ============================================================
+  // Hall sensor pulses on GPIO15, counted by PCNT unit 0
+  #[cfg(feature = "flow")]
+  let (flow_counter, mut flow_meter) = {
+    boot_step!("Flow meter...");
+    let counter = PulseCounter::new(peripherals.pcnt0, peripherals.pins.gpio15)?;
+    let cfg = config.lock().unwrap();
+    info!("Flow meter on GPIO15, {} pulses/gal, {:.1} gal so far", cfg.flow_k_factor, cfg.flow_total_gallons);
+    (counter, FlowMeter::new(cfg.flow_k_factor, cfg.flow_total_gallons))
+  };
+
+  // ============================================================
+  // Front panel buttons (feature: buttons)
+  // ============================================================
+  // GPIO39 is input only: needs an external pull-up, pressed pulls it low
+  #[cfg(feature = "buttons")]
+  let button_rx = {
+    let (tx, rx) = mpsc::channel();
+    buttons::start(vec![(ButtonId::Primary, PinDriver::input(peripherals.pins.gpio39.downgrade_input())?)], tx)?;
+    rx
+  };
+
+  // ============================================================
+  // Sample history on the flash partition (feature: history)
+  // ============================================================
+  #[cfg(feature = "history")]
+  let mut history = {
+    boot_step!("History...");
+    History::open()?
+  };
+
+  // ============================================================
+  // Duplex fill pumps on relay outputs (feature: pump)
+  // ============================================================
+  #[cfg(feature = "pump")]
+  let (mut pumps, mut pump_relays) = {
+    boot_step!("Pumps...");
+    // Pump 1 and pump 2 relays (active HIGH), rev A: GPIO4/GPIO14
+    // SAFETY: board profile pins are not claimed anywhere else
+    let mut relays = [
+      PinDriver::output(unsafe { AnyOutputPin::new(board.pump_relays[0]) })?,
+      PinDriver::output(unsafe { AnyOutputPin::new(board.pump_relays[1]) })?,
+    ];
+    for relay in relays.iter_mut() {
+      relay.set_low()?;
+    }
+    let settings = PumpSettings::from_config(&config.lock().unwrap());
+    info!("Pumps: start {}%, stop {}%", settings.start_percent, settings.stop_percent);
+    (PumpController::new(settings), relays)
+  };
+
+  // ============================================================
+  // Radar antenna condensation heater (feature: heater)
+  // ============================================================
+  #[cfg(feature = "heater")]
+  let (mut heater, mut heater_pin) = {
+    boot_step!("Heater...");
+    // GPIO15 = heater MOSFET gate (active HIGH); strapping pin, only sampled at reset
+    // SAFETY: board profile pins are not claimed anywhere else
+    let mut pin = PinDriver::output(unsafe { AnyOutputPin::new(board.heater) })?;
+    pin.set_low()?;
+    let settings = HeaterSettings::from_config(&config.lock().unwrap());
+    info!("Heater: mode {:?}", settings.mode);
+    (HeaterController::new(settings), pin)
+  };
+
+  // ============================================================
+  // VFD speed reference on GPIO2 PWM (feature: vfd)
+  // ============================================================
+  #[cfg(feature = "vfd")]
+  let (mut speed_output, mut speed_pid) = {
+    boot_step!("VFD output...");
+    let timer_config = TimerConfig::new()
+      .frequency(PWM_FREQUENCY_HZ.Hz())
+      .resolution(Resolution::Bits10);
+    let timer = LedcTimerDriver::new(peripherals.ledc.timer0, &timer_config)?;
+    // SAFETY: board profile pins are not claimed anywhere else
+    let pwm = LedcDriver::new(peripherals.ledc.channel0, timer, unsafe { AnyOutputPin::new(board.vfd_pwm) })?;
+    let output = SpeedOutput::new(pwm)?;
+    let cfg = config.lock().unwrap();
+    info!("VFD: setpoint {} PSI", cfg.vfd_setpoint_psi);
+    let pid = Pid::new(
+      cfg.vfd_kp_milli as f32 / 1000.0,
+      cfg.vfd_ki_milli as f32 / 1000.0,
+      cfg.vfd_kd_milli as f32 / 1000.0,
+      0.0,
+      1.0,
+    );
+    (output, pid)
+  };
+
+  // ============================================================
+  // DS18B20 temperature probes on GPIO33 (feature: ds18b20)
+  // ============================================================
+  #[cfg(feature = "ds18b20")]
+  let probes = {
+    boot_step!("Probes...");
+    // Open drain with an external 4.7k pull-up
+    let bus = OWDriver::new(peripherals.pins.gpio33, peripherals.rmt.channel0)?;
+    let probes = Arc::new(Mutex::new(Vec::new()));
+    ds18b20::start(ProbeBus::new(bus), probes.clone())?;
+    probes
+  };
+
+  // ============================================================
+  // Motorized supply valve on GPIO32/GPIO33 (feature: valve)
+  // ============================================================
+  #[cfg(feature = "valve")]
+  let (mut valve, mut valve_open, mut valve_close) = {
+    boot_step!("Valve...");
+    // Open and close relays (active HIGH), never on together
+    let mut open = PinDriver::output(peripherals.pins.gpio32)?;
+    let mut close = PinDriver::output(peripherals.pins.gpio33)?;
+    open.set_low()?;
+    close.set_low()?;
+    let travel = config.lock().unwrap().valve_travel_secs;
+    info!("Valve: travel time {} s", travel);
+    (ValveController::new(Duration::from_secs(travel as u64)), open, close)
+  };
+  // Limit switches to ground, external pull-ups (input-only pins)
+  #[cfg(feature = "valve_limits")]
+  let (valve_open_limit, valve_closed_limit) =
+    (PinDriver::input(peripherals.pins.gpio36)?, PinDriver::input(peripherals.pins.gpio39)?);
+
+  // ============================================================
+  // LoRa radio on the display SPI bus, NSS GPIO33 (feature: lora)
+  // ============================================================
+  #[cfg(feature = "lora")]
+  let mut lora = {
+    let (role, node, freq, interval) = {
+      let cfg = config.lock().unwrap();
+      (Role::from_code(cfg.lora_mode), cfg.lora_node as u8, cfg.lora_freq as u32 * FREQ_STEP_HZ, cfg.lora_interval_secs)
+    };
+    if role == Role::Off {
+      info!("LoRa: off (set lora_mode to use the radio)");
+      None
+    } else {
+      boot_step!("LoRa...");
+      let spi_config = SpiConfig::default().baudrate(4.MHz().into());
+      let spi_device = SpiDeviceDriver::new(spi_bus.clone(), Some(peripherals.pins.gpio33), &spi_config)?;
+      let mut radio = Sx1276::new(spi_device);
+      let ready = radio.init(freq).and_then(|_| if role == Role::Gateway { radio.start_receive() } else { Ok(()) });
+      match ready {
+        Ok(()) => {
+          info!("LoRa: {:?} on {} Hz, node {}", role, freq, node);
+          Some((radio, role, Beacon::new(Duration::from_secs(interval as u64), node)))
+        }
+        Err(e) => {
+          warn!("LoRa: radio not ready: {:?}", e);
+          None
+        }
+      }
+    }
+  };
+
   // ============================================================
   // Web server (feature: ethernet) — always available for config
   // ============================================================
-  boot_status!("Web server...");
+  boot_step!("Web server...");
+  #[cfg(feature = "ethernet")]
+  let live_status = Arc::new(Mutex::new(LiveStatus::default()));
+  // Maintenance mode: pauses automation and MQTT state, display stays live
+  #[cfg(feature = "ethernet")]
+  let maintenance = Arc::new(AtomicBool::new(false));
+  #[cfg(feature = "ethernet")]
+  let diag_status = Arc::new(Mutex::new(Diagnostics::default()));
+  // Output confirmed for a test-fire pulse on the web UI
+  #[cfg(feature = "ethernet")]
+  let test_fire = Arc::new(Mutex::new(None::<Output>));
+  // Self-test requested on the web UI, run and reported by the main loop
+  #[cfg(feature = "ethernet")]
+  let selftest = Arc::new(Mutex::new(SelfTest::default()));
+  // Alarm transitions for the diagnostic bundle
+  #[cfg(feature = "ethernet")]
+  let alarm_log = Arc::new(Mutex::new(AlarmLog::new()));
+  // Lockout/tagout interlock, restored from NVS so a reboot doesn't release it
+  #[cfg(feature = "lockout")]
+  let lockout = Arc::new(Mutex::new(Lockout::new(&config.lock().unwrap().lockout_pin)));
+  // Sensor trace uploaded for playback on a bench unit
+  #[cfg(feature = "sim-sensors")]
+  let playback = Arc::new(Mutex::new(None::<Playback>));
+  #[cfg(feature = "ethernet")]
+  #[cfg_attr(not(feature = "mqtt"), allow(unused_variables))]
+  let web_server = WebServer::start(
+    config.clone(),
+    live_status.clone(),
+    maintenance.clone(),
+    diag_status.clone(),
+    test_fire.clone(),
+    selftest.clone(),
+    alarm_log.clone(),
+    #[cfg(feature = "lockout")]
+    lockout.clone(),
+    #[cfg(feature = "ds18b20")]
+    probes.clone(),
+    #[cfg(feature = "sim-sensors")]
+    playback.clone(),
+  )?;
+  // Phones on the setup portal are sent to the setup page
+  #[cfg(feature = "wifi")]
+  if wifi.as_ref().is_some_and(WifiLink::is_portal) {
+    web_server.redirect_unknown()?;
+  }
+
+  // Network time for the maintenance reboot schedule, daily statistics and
+  // timestamped telemetry; syncs in the background once a server answers
   #[cfg(feature = "ethernet")]
-  let _web_server = WebServer::start(config.clone())?;
+  let _sntp = {
+    watercontroller::clock::set_timezone(&config.lock().unwrap().timezone);
+    EspSntp::new_default()?
+  };
+
+  // Alarm notifications: the webhook thread posts them off the main loop
+  #[cfg(feature = "notify")]
+  let webhook = Webhook::start(config.clone())?;
+
+  // Long-term history: the influx thread batches samples and writes them once a minute
+  #[cfg(feature = "influx")]
+  let influx_writer = InfluxWriter::start(config.clone())?;
+
+  // Cellular backup uplink: UART2 on GPIO32 (TX) / GPIO33 (RX), 115200 baud, 8N1
+  #[cfg(feature = "cellular")]
+  let cellular = {
+    info!("Initializing UART2 for the cellular modem on GPIO32/GPIO33...");
+    let uart = UartDriver::new(
+      peripherals.uart2,
+      peripherals.pins.gpio32, // TX
+      peripherals.pins.gpio33, // RX
+      Option::<AnyIOPin>::None,
+      Option::<AnyIOPin>::None,
+      &uart::config::Config::default().baudrate(Hertz(115200)),
+    )?;
+    Cellular::start(uart, _eth.netif().get_index(), config.clone())?
+  };
+  #[cfg(feature = "cellular")]
+  let mut last_cellular_publish: Option<std::time::Instant> = None;
+
+  // Remote panel: follows the main controller's state on MQTT instead of sensors
+  #[cfg(feature = "remote")]
+  let mut panel = {
+    let cfg = config.lock().unwrap();
+    if cfg.mqtt_configured() {
+      boot_step!("Following {}...", cfg.mqtt_broker);
+      Some(RemotePanel::start(&cfg.mqtt_broker, cfg.mqtt_port, &cfg.mqtt_username, &cfg.mqtt_password, cfg.mqtt_tls == 1)?)
+    } else {
+      warn!("Remote panel: no MQTT broker configured, set one on the web UI");
+      None
+    }
+  };
+  // Latest state from the controller (None = not heard from it recently)
+  #[cfg(feature = "remote")]
+  let mut remote_state: Option<PanelState> = None;
 
   // ============================================================
   // MQTT / Home Assistant initialization (feature: mqtt)
   // ============================================================
   #[cfg(feature = "mqtt")]
   let (cmd_tx, cmd_rx) = std::sync::mpsc::channel::<ConfigCommand>();
+  // Firmware installs requested by the Home Assistant update entity
+  #[cfg(all(feature = "mqtt", feature = "ota"))]
+  let updater = Updater::new(config.clone());
 
   #[cfg(feature = "mqtt")]
   let mqtt_configured = config.lock().unwrap().mqtt_configured();
+  // No broker to reach from the WiFi setup portal
+  #[cfg(all(feature = "mqtt", feature = "wifi"))]
+  let mqtt_configured = mqtt_configured && !wifi.as_ref().is_some_and(WifiLink::is_portal);
 
   #[cfg(feature = "mqtt")]
   let mut ha_client: Option<HomeAssistant> = if mqtt_configured {
-    let (broker, port, username, password) = {
+    let (broker, port, username, password, tls) = {
       let cfg = config.lock().unwrap();
-      (cfg.mqtt_broker.clone(), cfg.mqtt_port, cfg.mqtt_username.clone(), cfg.mqtt_password.clone())
+      let tls = (cfg.mqtt_tls == 1).then(|| MqttTls {
+        ca_cert: cfg.mqtt_ca_cert.clone(),
+        client_cert: cfg.mqtt_client_cert.clone(),
+        client_key: cfg.mqtt_client_key.clone(),
+      });
+      (cfg.mqtt_broker.clone(), cfg.mqtt_port, cfg.mqtt_username.clone(), cfg.mqtt_password.clone(), tls)
     };
 
     // Verify DNS resolution before attempting MQTT connection
     {
       use std::net::ToSocketAddrs;
 
-      boot_status!("DNS: {}...", broker);
+      boot_step!("DNS: {}...", broker);
+      startup.begin("dns", SystemClock.uptime());
       info!("Resolving {}...", broker);
       let mut resolved = false;
       for attempt in 1..=5 {
@@ -397,41 +1080,50 @@ fn run() -> anyhow::Result<()> {
       }
     }
 
-    boot_status!("MQTT connecting...");
+    boot_step!("MQTT connecting...");
+    startup.begin("mqtt", SystemClock.uptime());
     info!("Initializing MQTT client for Home Assistant...");
-    let mut client = HomeAssistant::new(&broker, port, &username, &password, cmd_tx)
+    let mut client = HomeAssistant::new(&broker, port, &username, &password, tls.as_ref(), cmd_tx)
       .map_err(|e| anyhow::anyhow!("MQTT init failed: {}", e))?;
-    // Give MQTT time to connect before sending discovery
+    {
+      let cfg = config.lock().unwrap();
+      // Entities retired by an upgrade are cleared with the first discovery
+      client.set_cleared_revision(cfg.discovery_rev);
+      client.set_naming(Naming {
+        device_name: cfg.ha_device_name.clone(),
+        area: cfg.ha_area.clone(),
+        prefix: cfg.ha_prefix.clone(),
+        tank: cfg.tank_label.clone(),
+      });
+      client.set_read_only(cfg.mqtt_read_only == 1);
+      client.set_board(board.revision.name());
+    }
+    // Give MQTT time to connect
     thread::sleep(Duration::from_secs(2));
     // Check if connection failed during the wait
     if let Some(err) = client.connection_error() {
       anyhow::bail!("MQTT: {}", err);
     }
-    client.send_discovery()
-      .map_err(|e| anyhow::anyhow!("MQTT discovery failed: {}", e))?;
-    client.subscribe()
-      .map_err(|e| anyhow::anyhow!("MQTT subscribe failed: {}", e))?;
+    // Subscriptions and discovery follow from the first poll in the main loop
     info!("Home Assistant MQTT ready");
+    startup.end(SystemClock.uptime());
     Some(client)
   } else {
-    boot_status!("Setup: http://{}/", _ip_addr);
-    info!("MQTT not configured — visit http://{}/", _ip_addr);
+    boot_status!("Setup: http://{}/", setup_addr);
+    info!("MQTT not configured — visit http://{}/", setup_addr);
     None
   };
 
   // ============================================================
-  // Finalize boot screen with MQTT status + config parameters
+  // Finalize boot screen with config parameters
   // ============================================================
-  #[cfg(feature = "mqtt")]
-  if ha_client.is_some() {
-    boot_status!("MQTT: connected");
-  }
 
   {
     let cfg = config.lock().unwrap();
     boot_status!("Tank:{} gal  H:{} ft", cfg.tank_capacity_gallons, cfg.sensor_height_feet);
     boot_status!("PSI:{}  Radar:{} cm", cfg.max_psi, cfg.radar_height_cm);
   }
+  boot_status!("Board: {}", board.revision.name());
 
   // Keep boot screen visible for 2 seconds before switching to normal display
   #[cfg(feature = "display")]
@@ -446,29 +1138,203 @@ fn run() -> anyhow::Result<()> {
   // Read by MQTT publish gate and display overlay on link loss
   #[cfg(feature = "ethernet")]
   #[allow(unused_assignments, unused_variables)]
-  let mut network_up = true;
+  let mut network_up = net_addr.is_some();
 
   // Demo values (only when no real sensors are enabled)
-  #[cfg(all(any(feature = "display", feature = "mqtt"), not(feature = "pressure"), not(feature = "radar")))]
+  #[cfg(all(any(feature = "display", feature = "ethernet", feature = "history", feature = "pump"), not(feature = "pressure"), not(feature = "radar"), not(feature = "remote")))]
   let mut demo_percent: u8 = 0;
-  #[cfg(all(any(feature = "display", feature = "mqtt"), not(feature = "pressure"), not(feature = "radar")))]
+  #[cfg(all(any(feature = "display", feature = "ethernet", feature = "history", feature = "pump"), not(feature = "pressure"), not(feature = "radar"), not(feature = "remote")))]
   let mut demo_psi: u16 = 0;
-  #[cfg(all(any(feature = "display", feature = "mqtt"), not(feature = "pressure"), not(feature = "radar")))]
+  #[cfg(all(any(feature = "display", feature = "ethernet", feature = "history", feature = "pump"), not(feature = "pressure"), not(feature = "radar"), not(feature = "remote")))]
   let mut demo_rising = true;
 
-  // Sensor/MQTT update interval (5s — radar needs time to settle)
+  // Time source for sensor warm-up, pump and heater timers, PID, display pages, the reboot schedule and LoRa frames
+  #[cfg(any(feature = "display", feature = "ethernet", feature = "pump", feature = "vfd", feature = "heater", feature = "radar", feature = "pressure", feature = "valve", feature = "lora"))]
+  let clock = SystemClock;
+  // Once-a-minute housekeeping (reboot schedule)
+  #[cfg(feature = "ethernet")]
+  let mut minute_tick = Ticker::every_minute();
+  // Whether SNTP has set the clock yet, to log it once
+  #[cfg(feature = "ethernet")]
+  let mut clock_synced = false;
+  // Startup ends with discovery in the main loop, or here without a broker
+  #[cfg(feature = "mqtt")]
+  let discovering = ha_client.is_some();
+  #[cfg(all(feature = "ethernet", not(feature = "mqtt")))]
+  let discovering = false;
+  #[cfg(feature = "ethernet")]
+  if discovering {
+    startup.begin("discovery", clock.uptime());
+  } else {
+    startup.finish(clock.uptime());
+    info!("Startup: {}", startup.summary());
+  }
+  // Whether the finished startup report has gone out since the last resync
+  #[cfg(feature = "mqtt")]
+  let mut startup_published = false;
+
+  // Pressure, control and MQTT update interval (5s)
   const UPDATE_INTERVAL: Duration = Duration::from_secs(5);
   let mut last_update = std::time::Instant::now();
+  // The radar is polled on its own schedule: fast while water moves, slow while the level holds
+  #[cfg(feature = "radar")]
+  let mut radar_polling = RadarPolling::new();
 
   // Current sensor values (persist across loop iterations)
-  #[cfg(any(feature = "display", feature = "mqtt"))]
+  #[cfg(any(feature = "display", feature = "ethernet", feature = "history", feature = "pump"))]
   let mut capacity_percent: u8 = 0;
-  #[cfg(any(feature = "display", feature = "mqtt"))]
+  #[cfg(any(feature = "display", feature = "ethernet", feature = "history", feature = "pump"))]
   let mut current_psi: u16 = 0;
-  #[cfg(any(feature = "display", feature = "mqtt"))]
+  #[cfg(any(feature = "display", feature = "ethernet", feature = "history", feature = "pump"))]
   let mut gallons: u16 = 0;
+  // Pressure lost across the filter, `None` until read or after a failed read
+  #[cfg(feature = "filter_pressure")]
+  let mut filter_drop_psi: Option<f32> = None;
+  #[cfg(feature = "filter_pressure")]
+  let mut filter_responding = true;
+
+  // Sensor warm-up: readings are shown but not acted on until settled
+  #[cfg(feature = "radar")]
+  let mut radar_warmup = Warmup::new(Duration::from_secs(config.lock().unwrap().radar_warmup_secs as u64));
+  #[cfg(feature = "pressure")]
+  let mut pressure_warmup = Warmup::new(Duration::from_secs(config.lock().unwrap().pressure_warmup_secs as u64));
+  #[cfg(any(feature = "radar", feature = "pressure"))]
+  let mut stabilizing = true;
+
+  // Well pump run state, read from the pressure rise (no relay of ours drives it)
+  #[cfg(feature = "well_pump")]
+  let mut well_pump = WellPump::new(config.lock().unwrap().well_rise_psi);
+
+  // Flow meter total, saved now and then so a restart carries on from it
+  #[cfg(feature = "flow")]
+  const FLOW_SAVE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+  #[cfg(feature = "flow")]
+  let mut last_flow_save = std::time::Instant::now();
+
+  // Radar median/EWMA filter (parameters reloaded before each read)
+  #[cfg(feature = "radar")]
+  let mut level_filter = {
+    let cfg = config.lock().unwrap();
+    LevelFilter::new(cfg.level_median_window as usize, cfg.level_smoothing_percent as f32 / 100.0)
+  };
+  // Sensor-side and smoothed distances of the last reading, published with the state
+  #[cfg(all(feature = "radar", feature = "mqtt"))]
+  let mut radar_distances: Option<RadarDistances> = None;
+  // Daily gallons consumed/refilled, rolled at local midnight
+  #[cfg(feature = "radar")]
+  let mut usage = UsageStats::new();
+  // Totals saved before a restart, added back once the clock shows it is the same day
+  #[cfg(feature = "radar")]
+  let mut saved_usage = config.lock().unwrap().saved_usage;
+  #[cfg(feature = "radar")]
+  const USAGE_SAVE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+  #[cfg(feature = "radar")]
+  let mut last_usage_save = std::time::Instant::now();
+
+  // Heap watchdog: stretches history and display intervals when memory is low
+  let mut memory_guard = MemoryGuard::default();
+  #[cfg(feature = "display")]
+  let mut last_frame = std::time::Instant::now();
+  // Draw and flush time of the previous frame
+  #[cfg(feature = "frame_overlay")]
+  let mut frame_time = Duration::ZERO;
+
+  // History sampling interval (5 min — sized for the flash wear budget)
+  #[cfg(feature = "history")]
+  const HISTORY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+  #[cfg(feature = "history")]
+  let mut last_history = std::time::Instant::now();
+  // 1 h / 24 h statistics, recomputed after each append and published with the next state
+  #[cfg(all(feature = "history", feature = "mqtt"))]
+  const HISTORY_DAY_SAMPLES: usize = (24 * 60 * 60 / HISTORY_INTERVAL.as_secs()) as usize + 1;
+  #[cfg(all(feature = "history", feature = "mqtt"))]
+  let mut history_stats: Option<HistoryStats> = None;
+
+  // Fill cycle delivery rates; the trend is recomputed from the stored cycles after each one
+  #[cfg(feature = "efficiency")]
+  const EFFICIENCY_MAX_CYCLES: usize = 2000;
+  #[cfg(feature = "efficiency")]
+  let mut cycle_tracker = CycleTracker::new();
+  #[cfg(feature = "efficiency")]
+  let mut pump_degraded = [false; PUMP_COUNT];
+  #[cfg(all(feature = "efficiency", feature = "mqtt"))]
+  let mut pump_trends: Option<[PumpTrend; PUMP_COUNT]> = None;
+
+  // Alarm conditions shown in Home Assistant and on the display banner
+  let mut alarm_state = Alarms::new();
+  // Alarm transitions that send a notification
+  #[cfg(feature = "notify")]
+  let mut alarms = AlarmMonitor::new();
+  // Pipe burst check on a pressure reading every loop pass; a trip runs the
+  // 5 s update straight away so the alarm and notification go out with it
+  #[cfg(feature = "pipe_burst")]
+  let mut burst_detector = BurstDetector::new();
+  #[cfg(feature = "pipe_burst")]
+  let mut update_now = false;
+
+  // Level trend charts: 24 hours at one sample per 15 minutes, kept in RAM
+  #[cfg(all(feature = "display", feature = "radar"))]
+  const TREND_SAMPLES: usize = 96;
+  #[cfg(all(feature = "display", feature = "radar"))]
+  const TREND_INTERVAL: Duration = Duration::from_secs(15 * 60);
+  #[cfg(all(feature = "display", feature = "radar"))]
+  let mut last_trend: Option<std::time::Instant> = None;
+
+  // Constant-pressure loop rate (faster than the sensor/MQTT cycle)
+  #[cfg(feature = "vfd")]
+  const VFD_INTERVAL: Duration = Duration::from_secs(1);
+  #[cfg(feature = "vfd")]
+  let mut last_vfd = std::time::Instant::now();
+  // Relay autotune experiment in progress, started from HA
+  #[cfg(feature = "vfd")]
+  let mut autotune: Option<RelayAutotune> = None;
+
+  // Diagnostic sensors are published less often than the state
+  #[cfg(feature = "mqtt")]
+  const DIAG_INTERVAL: Duration = Duration::from_secs(60);
+  #[cfg(feature = "mqtt")]
+  let mut last_diag: Option<std::time::Instant> = None;
+  #[cfg(feature = "ethernet")]
+  let reset_reason = diag::reset_reason();
+  #[cfg(feature = "ethernet")]
+  info!("Reset reason: {}", reset_reason);
+
+  // Last maintenance state announced (None = not yet published)
+  #[cfg(feature = "ethernet")]
+  let mut last_maintenance: Option<bool> = None;
+  // Last valve state announced
+  #[cfg(feature = "valve")]
+  let mut last_valve: Option<(ValveState, bool)> = None;
+  // Output test-fire in progress
+  #[cfg(feature = "ethernet")]
+  let mut test_pulse: Option<Pulse> = None;
+  // Last lockout state announced
+  #[cfg(feature = "lockout")]
+  let mut last_lockout: Option<bool> = None;
+  // Last firmware update state announced
+  #[cfg(all(feature = "mqtt", feature = "ota"))]
+  let mut last_firmware_state: Option<bool> = None;
+  // Firmware update progress, shown over the pages while an install runs
+  #[cfg(all(feature = "display", feature = "mqtt", feature = "ota"))]
+  let mut ota_screen: Option<BootScreen<_>> = None;
+  // Self-test progress, shown over the pages while a run goes
+  #[cfg(all(feature = "display", feature = "ethernet"))]
+  let mut selftest_screen: Option<BootScreen<_>> = None;
+
+  // Start-up got this far, so an update just installed stays
+  #[cfg(feature = "ota")]
+  watercontroller::ota::mark_valid();
 
   loop {
+    // WiFi setup portal: once the stored network is joined, start over on it
+    #[cfg(feature = "wifi")]
+    if let Some(link) = wifi.as_mut() {
+      if link.poll() {
+        info!("Restarting in WiFi station mode");
+        unsafe { esp_idf_svc::sys::esp_restart(); }
+      }
+    }
+
     // Check for network events (non-blocking)
     // network_up is read when mqtt feature is enabled
     #[cfg(feature = "ethernet")]
@@ -490,6 +1356,7 @@ fn run() -> anyhow::Result<()> {
         NetEvent::LostIp => {
           warn!("IP address lost");
           network_up = false;
+          net_addr = None;
           #[cfg(feature = "display")]
           {
             display.clear_framebuffer();
@@ -505,12 +1372,102 @@ fn run() -> anyhow::Result<()> {
         NetEvent::GotIp { ip, gateway } => {
           info!("Network restored: {} (gateway: {})", ip, gateway);
           network_up = true;
+          net_addr = Some((ip, gateway));
           #[cfg(feature = "display")]
           {
             // Clear overlay so normal display resumes
             info_until = None;
-            display.clear_framebuffer();
-            display.mark_all_dirty();
+            pages.invalidate();
+          }
+        }
+      }
+    }
+
+    // Lockout/tagout: outputs forced off on every pass, so it takes effect at once
+    #[cfg(feature = "lockout")]
+    let locked = {
+      let locked = lockout.lock().unwrap().is_engaged();
+      if last_lockout != Some(locked) {
+        if last_lockout.is_some() || locked {
+          warn!("Lockout {}", if locked { "engaged: all outputs off" } else { "released" });
+        }
+        last_lockout = Some(locked);
+        #[cfg(feature = "mqtt")]
+        if let Some(ref mut client) = ha_client {
+          if let Err(e) = client.publish_lockout(locked) {
+            warn!("MQTT publish error: {:?}", e);
+          }
+        }
+        #[cfg(feature = "valve")]
+        if locked {
+          valve.command(ValveCommand::Stop, clock.uptime());
+        }
+        #[cfg(feature = "display")]
+        pages.invalidate();
+      }
+      if locked {
+        #[cfg(feature = "pump")]
+        {
+          pumps.stop(clock.uptime());
+          for relay in pump_relays.iter_mut() {
+            relay.set_low()?;
+          }
+        }
+        #[cfg(feature = "heater")]
+        {
+          heater.stop();
+          heater_pin.set_low()?;
+        }
+      }
+      locked
+    };
+    #[cfg(not(feature = "lockout"))]
+    #[allow(unused_variables)]
+    let locked = false;
+
+    // Reconnect to the broker with backoff; a new connection is resynced
+    #[cfg(feature = "mqtt")]
+    if let Some(ref mut client) = ha_client {
+      if client.poll() {
+        // A restarted broker may have lost the retained states, so announce them again
+        #[cfg(feature = "ethernet")]
+        {
+          last_maintenance = None;
+        }
+        last_diag = None;
+        #[cfg(feature = "valve")]
+        {
+          last_valve = None;
+        }
+        #[cfg(feature = "lockout")]
+        {
+          last_lockout = None;
+        }
+        #[cfg(feature = "ota")]
+        {
+          last_firmware_state = None;
+        }
+        startup_published = false;
+      }
+
+      // Startup ends once Home Assistant has the discovery configs
+      if !startup.is_finished() && client.is_discovered() {
+        startup.finish(clock.uptime());
+        info!("Startup: {}", startup.summary());
+      }
+      if startup.is_finished() && !startup_published && client.is_connected() {
+        match client.publish_startup(&startup) {
+          Ok(()) => startup_published = true,
+          Err(e) => warn!("MQTT publish error: {:?}", e),
+        }
+      }
+
+      // Retired entities are cleared once per upgrade
+      if client.is_discovered() {
+        let mut cfg = config.lock().unwrap();
+        if cfg.discovery_rev != client.cleared_revision() {
+          if let Err(e) = cfg.set_discovery_rev(client.cleared_revision()) {
+            warn!("Failed to store the discovery revision: {:?}", e);
           }
         }
       }
@@ -519,7 +1476,10 @@ fn run() -> anyhow::Result<()> {
     // Process MQTT configuration commands
     #[cfg(feature = "mqtt")]
     if ha_client.is_some() {
-      while let Ok(cmd) = cmd_rx.try_recv() {
+      // A bulk config document expands into its settings, applied back to back
+      for cmd in std::iter::from_fn(|| cmd_rx.try_recv().ok()).flat_map(ConfigCommand::into_commands) {
+        #[allow(unused_mut, unused_assignments, unused_variables)]
+        let mut show_diag = false;
         let msg: Option<&str> = {
           let mut cfg = config.lock().unwrap();
           macro_rules! apply_cfg {
@@ -544,20 +1504,181 @@ fn run() -> anyhow::Result<()> {
               label
             }
             ConfigCommand::SetRadarDeadzone(val) => apply_cfg!(set_radar_deadzone, val, "Radar Deadzone"),
-          }
-        };
-
-        // Show config change on display
-        #[cfg(feature = "display")]
-        if let Some(label) = msg {
-          use core::fmt::Write;
-
-          let text_style = MonoTextStyleBuilder::new()
-            .font(&FONT_10X20)
-            .text_color(BinaryColor::Off)
-            .build();
-
-          display.clear_framebuffer();
+            ConfigCommand::SetFlushLines(val) => {
+              let label = apply_cfg!(set_display_flush_lines, val, "Flush Lines");
+              #[cfg(feature = "display")]
+              display.set_flush_budget(cfg.display_flush_lines);
+              label
+            }
+            ConfigCommand::SetDisplayRefresh(val) => {
+              let label = apply_cfg!(set_display_refresh, val, "Full Refresh");
+              #[cfg(all(feature = "display", not(feature = "tft")))]
+              display.set_refresh_interval(Duration::from_secs(cfg.display_refresh_minutes as u64 * 60));
+              label
+            }
+            ConfigCommand::SetTankFill(val) => {
+              let label = apply_cfg!(set_tank_fill_pattern, val, "Tank Fill");
+              #[cfg(feature = "display")]
+              {
+                tank.theme.fill = FillPattern::from_code(cfg.tank_fill_pattern);
+              }
+              label
+            }
+            ConfigCommand::SetRebootDay(val) => apply_cfg!(set_reboot_day, val, "Reboot Day"),
+            ConfigCommand::SetRebootHour(val) => apply_cfg!(set_reboot_hour, val, "Reboot Hour"),
+            ConfigCommand::SetMaintenance(on) => {
+              maintenance.store(on, Ordering::Relaxed);
+              None
+            }
+            ConfigCommand::SetPumpStart(val) => apply_cfg!(set_pump_start, val, "Pump Start"),
+            ConfigCommand::SetPumpStop(val) => apply_cfg!(set_pump_stop, val, "Pump Stop"),
+            ConfigCommand::SetPumpAssist(val) => apply_cfg!(set_pump_assist_drop, val, "Pump Assist"),
+            ConfigCommand::SetPumpFailMinutes(val) => apply_cfg!(set_pump_fail_minutes, val, "Pump Fail Time"),
+            ConfigCommand::SetVfdSetpoint(val) => apply_cfg!(set_vfd_setpoint, val, "Setpoint"),
+            ConfigCommand::SetVfdKp(val) => apply_cfg!(set_vfd_kp, val, "VFD Kp"),
+            ConfigCommand::SetVfdKi(val) => apply_cfg!(set_vfd_ki, val, "VFD Ki"),
+            ConfigCommand::SetVfdKd(val) => apply_cfg!(set_vfd_kd, val, "VFD Kd"),
+            ConfigCommand::StartVfdAutotune => {
+              #[cfg(feature = "vfd")]
+              if locked {
+                warn!("VFD: autotune refused, outputs are locked out");
+              } else {
+                info!("VFD: starting relay autotune at {} PSI", cfg.vfd_setpoint_psi);
+                // Relay between 20% and 90% speed, 1 PSI band, 10 minute limit
+                autotune = Some(RelayAutotune::new(cfg.vfd_setpoint_psi as f32, 0.2, 0.9, 1.0, 600.0));
+              }
+              None
+            }
+            ConfigCommand::OpenValve | ConfigCommand::CloseValve | ConfigCommand::StopValve => {
+              #[cfg(feature = "valve")]
+              if locked {
+                warn!("Valve: command refused, outputs are locked out");
+              } else {
+                valve.command(
+                  match cmd {
+                    ConfigCommand::OpenValve => ValveCommand::Open,
+                    ConfigCommand::CloseValve => ValveCommand::Close,
+                    _ => ValveCommand::Stop,
+                  },
+                  clock.uptime(),
+                );
+              }
+              None
+            }
+            ConfigCommand::ResetPumpFaults => {
+              #[cfg(feature = "pump")]
+              pumps.reset_faults();
+              None
+            }
+            ConfigCommand::ShowDiagnostics => {
+              show_diag = true;
+              None
+            }
+            ConfigCommand::CalibratePressureZero => {
+              #[cfg(feature = "pressure")]
+              if let Err(e) = pressure_sensor.calibrate_zero(&mut cfg) {
+                warn!("Pressure: zero calibration failed: {:?}", e);
+              }
+              #[cfg(feature = "filter_pressure")]
+              if let Err(e) = filter_sensor.calibrate_zero(&mut cfg) {
+                warn!("Pressure: post-filter zero calibration failed: {:?}", e);
+              }
+              None
+            }
+            ConfigCommand::ResetFilterAlarm => {
+              if alarm_state.reset_filter_clog() {
+                info!("Alarm: {} cleared by reset", Alarm::FilterClogged.label());
+                alarm_log.lock().unwrap().record(Alarm::FilterClogged, false, clock.uptime(), clock.timestamp());
+              }
+              None
+            }
+            ConfigCommand::Reboot => {
+              warn!("Reboot requested from Home Assistant");
+              cfg.flush();
+              unsafe { esp_idf_svc::sys::esp_restart(); }
+            }
+            ConfigCommand::FactoryReset => {
+              // The lockout PIN is a setting too; erasing it would release the lockout
+              if locked {
+                warn!("Factory reset refused, outputs are locked out");
+              } else {
+                warn!("Factory reset requested from Home Assistant");
+                match cfg.factory_reset() {
+                  Ok(()) => unsafe { esp_idf_svc::sys::esp_restart(); },
+                  Err(e) => warn!("Factory reset failed: {:?}", e),
+                }
+              }
+              None
+            }
+            ConfigCommand::InstallFirmware(url) => {
+              // Downloads in the background; restarts once the image is installed
+              #[cfg(feature = "ota")]
+              if updater.start(url) {
+                warn!("Firmware update requested from Home Assistant");
+              }
+              #[cfg(not(feature = "ota"))]
+              warn!("Firmware update from {} ignored, built without ota", url);
+              None
+            }
+            ConfigCommand::SetTankShape(val) => apply_cfg!(set_tank_shape, val, "Tank Shape"),
+            ConfigCommand::SetRadarWarmup(val) => apply_cfg!(set_radar_warmup, val, "Radar Warm-up"),
+            ConfigCommand::SetPressureWarmup(val) => apply_cfg!(set_pressure_warmup, val, "PSI Warm-up"),
+            ConfigCommand::SetHeaterMode(val) => apply_cfg!(set_heater_mode, val, "Heater Mode"),
+            ConfigCommand::SetHeaterSpread(val) => apply_cfg!(set_heater_spread, val, "Heater Spread"),
+            ConfigCommand::SetHeaterDuty(val) => apply_cfg!(set_heater_duty, val, "Heater Duty"),
+            ConfigCommand::SetLevelMedian(val) => apply_cfg!(set_level_median, val, "Level Median"),
+            ConfigCommand::SetLevelSmoothing(val) => apply_cfg!(set_level_smoothing, val, "Level Smoothing"),
+            ConfigCommand::SetPageInterval(val) => apply_cfg!(set_page_interval, val, "Page Interval"),
+            ConfigCommand::SetPumpMinOn(val) => apply_cfg!(set_pump_min_on, val, "Pump Min On"),
+            ConfigCommand::SetPumpMinOff(val) => apply_cfg!(set_pump_min_off, val, "Pump Min Off"),
+            ConfigCommand::SetPumpDryPsi(val) => apply_cfg!(set_pump_dry_psi, val, "Dry Run PSI"),
+            ConfigCommand::SetPumpDrySecs(val) => apply_cfg!(set_pump_dry_secs, val, "Dry Run Delay"),
+            ConfigCommand::SetValveTravel(val) => apply_cfg!(set_valve_travel, val, "Valve Travel"),
+            ConfigCommand::SetHammerPsi(val) => apply_cfg!(set_hammer_psi, val, "Hammer PSI"),
+            ConfigCommand::SetBurstPsi(val) => apply_cfg!(set_burst_psi, val, "Burst PSI"),
+            ConfigCommand::SetEfficiencyDrop(val) => apply_cfg!(set_efficiency_drop, val, "Efficiency Drop"),
+            ConfigCommand::SetAlarmLow(val) => apply_cfg!(set_alarm_low, val, "Low Level Alarm"),
+            ConfigCommand::SetAlarmHighPsi(val) => apply_cfg!(set_alarm_high_psi, val, "High PSI Alarm"),
+            ConfigCommand::SetAlarmClogPsi(val) => apply_cfg!(set_alarm_clog_psi, val, "Clog Alarm"),
+            ConfigCommand::SetAlarmFaultSecs(val) => apply_cfg!(set_alarm_fault_secs, val, "Fault Delay"),
+            ConfigCommand::SetBudget(val) => apply_cfg!(set_budget, val, "Water Budget"),
+            ConfigCommand::SetFlowKFactor(val) => apply_cfg!(set_flow_k_factor, val, "Flow K-Factor"),
+            ConfigCommand::AmbientTemperature(celsius) => {
+              #[cfg(feature = "heater")]
+              heater.set_temperature(celsius, clock.uptime());
+              #[cfg(not(feature = "heater"))]
+              let _ = celsius;
+              None
+            }
+            ConfigCommand::AmbientHumidity(percent) => {
+              #[cfg(feature = "heater")]
+              heater.set_humidity(percent, clock.uptime());
+              #[cfg(not(feature = "heater"))]
+              let _ = percent;
+              None
+            }
+            // Already expanded by into_commands()
+            ConfigCommand::ApplyConfig(_) => None,
+          }
+        };
+
+        // Diagnostics are on the network page
+        #[cfg(feature = "display")]
+        if show_diag {
+          pages.show(Page::Network, clock.uptime());
+        }
+
+        // Show config change on display
+        #[cfg(feature = "display")]
+        if let Some(label) = msg {
+          use core::fmt::Write;
+
+          let text_style = MonoTextStyleBuilder::new()
+            .font(theme.font)
+            .text_color(theme.colors().foreground)
+            .build();
+
+          display.clear_framebuffer();
 
           let cfg = config.lock().unwrap();
           let mut line_buf = [0u8; 40];
@@ -567,12 +1688,60 @@ fn run() -> anyhow::Result<()> {
             "Max PSI" => cfg.max_psi,
             "Radar Height" => cfg.radar_height_cm,
             "Radar Deadzone" => cfg.radar_deadzone_cm,
+            "Flush Lines" => cfg.display_flush_lines,
+            "Full Refresh" => cfg.display_refresh_minutes,
+            "Tank Fill" => cfg.tank_fill_pattern,
+            "Reboot Day" => cfg.reboot_day,
+            "Reboot Hour" => cfg.reboot_hour,
+            "Pump Start" => cfg.pump_start_percent,
+            "Pump Stop" => cfg.pump_stop_percent,
+            "Pump Assist" => cfg.pump_assist_drop_percent,
+            "Pump Fail Time" => cfg.pump_fail_minutes,
+            "Setpoint" => cfg.vfd_setpoint_psi,
+            "VFD Kp" => cfg.vfd_kp_milli,
+            "VFD Ki" => cfg.vfd_ki_milli,
+            "VFD Kd" => cfg.vfd_kd_milli,
+            "Tank Shape" => cfg.tank_shape,
+            "Radar Warm-up" => cfg.radar_warmup_secs,
+            "PSI Warm-up" => cfg.pressure_warmup_secs,
+            "Heater Mode" => cfg.heater_mode,
+            "Heater Spread" => cfg.heater_spread_c,
+            "Heater Duty" => cfg.heater_duty_percent,
+            "Level Median" => cfg.level_median_window,
+            "Level Smoothing" => cfg.level_smoothing_percent,
+            "Page Interval" => cfg.page_interval_secs,
+            "Pump Min On" => cfg.pump_min_on_secs,
+            "Pump Min Off" => cfg.pump_min_off_secs,
+            "Dry Run PSI" => cfg.pump_dry_psi,
+            "Dry Run Delay" => cfg.pump_dry_secs,
+            "Valve Travel" => cfg.valve_travel_secs,
+            "Hammer PSI" => cfg.hammer_psi,
+            "Burst PSI" => cfg.burst_psi,
+            "Efficiency Drop" => cfg.efficiency_drop_percent,
+            "Low Level Alarm" => cfg.alarm_low_percent,
+            "High PSI Alarm" => cfg.alarm_high_psi,
+            "Clog Alarm" => cfg.alarm_clog_psi,
+            "Fault Delay" => cfg.alarm_fault_secs,
+            "Water Budget" => cfg.budget_gallons,
+            "Flow K-Factor" => cfg.flow_k_factor,
             _ => 0,
           };
           let unit = match label {
-            "Tank Capacity" => " gal",
+            "Tank Capacity" | "Water Budget" => " gal",
+            "Flow K-Factor" => " p/gal",
             "Sensor Height" => " ft",
             "Radar Height" | "Radar Deadzone" => " cm",
+            "Flush Lines" => " lines",
+            "Reboot Hour" => ":00",
+            "Pump Start" | "Pump Stop" | "Pump Assist" | "Heater Duty" | "Efficiency Drop" | "Low Level Alarm" => "%",
+            "Heater Spread" => " C",
+            "Radar Warm-up" | "PSI Warm-up" => " s",
+            "Pump Fail Time" | "Full Refresh" => " min",
+            "Setpoint" | "Dry Run PSI" | "Hammer PSI" | "Burst PSI" | "High PSI Alarm" | "Clog Alarm" => " PSI",
+            "VFD Kp" | "VFD Ki" | "VFD Kd" => "e-3",
+            "Level Smoothing" => "%",
+            "Page Interval" => " s",
+            "Pump Min On" | "Pump Min Off" | "Dry Run Delay" | "Valve Travel" | "Fault Delay" => " s",
             _ => "",
           };
           let mut w = LineBuf::new(&mut line_buf);
@@ -591,58 +1760,607 @@ fn run() -> anyhow::Result<()> {
       }
     }
 
-    // Sensor readings and MQTT publish every 5 seconds
-    if last_update.elapsed() >= UPDATE_INTERVAL {
-      last_update = std::time::Instant::now();
+    // Announce maintenance mode changes (from HA or the web toggle)
+    #[cfg(feature = "ethernet")]
+    {
+      let active = maintenance.load(Ordering::Relaxed);
+      if last_maintenance != Some(active) {
+        if last_maintenance.is_some() {
+          info!("Maintenance mode {}", if active { "on" } else { "off" });
+        }
+        last_maintenance = Some(active);
+        #[cfg(feature = "mqtt")]
+        if let Some(ref mut client) = ha_client {
+          if let Err(e) = client.publish_maintenance(active) {
+            warn!("MQTT publish error: {:?}", e);
+          }
+        }
+        // Redraw without (or with) the maintenance banner
+        #[cfg(feature = "display")]
+        pages.invalidate();
+      }
+    }
 
-      // Read radar sensor
-      #[cfg(feature = "radar")]
-      {
-        match radar.read_empty_height() {
-          Ok(empty_mm) => {
-            let cfg = config.lock().unwrap();
-            let install_mm = cfg.radar_height_cm as u32 * 10;
-            let deadzone_mm = cfg.radar_deadzone_cm as u32 * 10;
-            // useful_mm = max water depth (from 100% level to bottom)
-            let useful_mm = install_mm.saturating_sub(deadzone_mm);
-            // water_mm = actual water depth (from surface to bottom)
-            let water_mm = install_mm.saturating_sub(empty_mm as u32);
-            capacity_percent = if useful_mm > 0 {
-              // empty_mm == deadzone_mm → water_mm == useful_mm → 100%
-              // empty_mm == install_mm  → water_mm == 0          → 0%
-              (water_mm * 100 / useful_mm).min(100) as u8
+    // Announce the update entity state: running version, and whether an install is under way
+    #[cfg(all(feature = "mqtt", feature = "ota"))]
+    {
+      let in_progress = updater.in_progress();
+      if last_firmware_state != Some(in_progress) {
+        if let Some(ref mut client) = ha_client {
+          match client.publish_update(in_progress) {
+            Ok(()) => last_firmware_state = Some(in_progress),
+            Err(e) => warn!("MQTT publish error: {:?}", e),
+          }
+        }
+      }
+
+      // The unit restarts into the new image once it is installed, so the
+      // screen only has to come down again when the install failed
+      #[cfg(feature = "display")]
+      if in_progress && ota_screen.is_none() {
+        let mut screen = BootScreen::new(Point::new(10, 4), page_size, "Firmware update", theme);
+        screen.info(format!("Running v{}", env!("CARGO_PKG_VERSION")));
+        screen.step("Downloading...");
+        display.clear_framebuffer();
+        screen.draw(&mut display)?;
+        display.flush_all()?;
+        ota_screen = Some(screen);
+        info_until = Some(std::time::Instant::now() + Duration::from_secs(3600));
+      } else if !in_progress {
+        if let Some(mut screen) = ota_screen.take() {
+          screen.fail();
+          screen.info("Update failed, see the log");
+          screen.draw(&mut display)?;
+          display.flush_all()?;
+          info_until = Some(std::time::Instant::now() + Duration::from_secs(10));
+        }
+      }
+    }
+
+    // Output test-fire from the web UI, only while maintenance mode keeps automation off
+    #[cfg(feature = "ethernet")]
+    #[allow(unused_variables)]
+    let firing = {
+      let active = maintenance.load(Ordering::Relaxed) && !locked;
+      if let Some(output) = test_fire.lock().unwrap().take() {
+        if active {
+          info!("Test-fire: {} on", output.label());
+          test_pulse = Some(Pulse::new(output, clock.uptime()));
+          // The valve controller runs the valve pulses, so it knows the motor ran
+          #[cfg(feature = "valve")]
+          match output {
+            Output::ValveOpen => valve.pulse(true, PULSE, clock.uptime()),
+            Output::ValveClose => valve.pulse(false, PULSE, clock.uptime()),
+            Output::Heater => {}
+          }
+        }
+      }
+      let firing = test_pulse.filter(|_| active).and_then(|pulse| pulse.output(clock.uptime()));
+      if let (Some(pulse), None) = (test_pulse, firing) {
+        info!("Test-fire: {} off", pulse.target().label());
+        test_pulse = None;
+      }
+      // The heater is stopped in maintenance mode, so only the pulse turns it on
+      #[cfg(feature = "heater")]
+      if active && heater_pin.is_set_high() != (firing == Some(Output::Heater)) {
+        if firing == Some(Output::Heater) {
+          heater_pin.set_high()?;
+        } else {
+          heater_pin.set_low()?;
+        }
+      }
+      firing
+    };
+
+    // Supply valve moves run every pass (also in maintenance mode, so the line can always be shut)
+    #[cfg(feature = "valve")]
+    {
+      valve.set_travel(Duration::from_secs(config.lock().unwrap().valve_travel_secs as u64));
+      #[cfg(feature = "valve_limits")]
+      let limits = Some(Limits { open: valve_open_limit.is_low(), closed: valve_closed_limit.is_low() });
+      #[cfg(not(feature = "valve_limits"))]
+      let limits = None;
+      let drive = valve.update(limits, clock.uptime());
+      let drive = if locked { Drive::default() } else { drive };
+      // Release before energizing so both relays are never on at once
+      if !drive.open {
+        valve_open.set_low()?;
+      }
+      if !drive.close {
+        valve_close.set_low()?;
+      }
+      if drive.open {
+        valve_open.set_high()?;
+      }
+      if drive.close {
+        valve_close.set_high()?;
+      }
+      let current = (valve.state(), valve.is_fault());
+      if last_valve != Some(current) {
+        last_valve = Some(current);
+        if let Some(ref mut client) = ha_client {
+          if let Err(e) = client.publish_valve(current.0, current.1) {
+            warn!("MQTT publish error: {:?}", e);
+          }
+        }
+      }
+    }
+
+    // Constant-pressure speed control every second (stopped in maintenance mode and by a lockout)
+    #[cfg(feature = "vfd")]
+    if last_vfd.elapsed() >= VFD_INTERVAL {
+      let dt = last_vfd.elapsed().as_secs_f32();
+      last_vfd = std::time::Instant::now();
+      let (setpoint, head_psi, correction) = {
+        let cfg = config.lock().unwrap();
+        speed_pid.set_gains(
+          cfg.vfd_kp_milli as f32 / 1000.0,
+          cfg.vfd_ki_milli as f32 / 1000.0,
+          cfg.vfd_kd_milli as f32 / 1000.0,
+        );
+        (cfg.vfd_setpoint_psi as f32, pressure_head(&cfg), Correction::pressure(&cfg))
+      };
+      #[cfg(feature = "ethernet")]
+      let paused = maintenance.load(Ordering::Relaxed);
+      #[cfg(not(feature = "ethernet"))]
+      let paused = false;
+      // Hold the pump until the pressure reading has settled
+      let paused = paused || locked || !pressure_warmup.ready(clock.uptime());
+      if paused {
+        if autotune.take().is_some() {
+          warn!("VFD: autotune aborted (maintenance mode, lockout or sensor warm-up)");
+        }
+        speed_pid.reset();
+        speed_output.set_speed(0.0)?;
+      } else {
+        match sensors::read_pressure(&mut pressure_sensor, head_psi, &correction) {
+          Ok(psi) => match autotune.as_mut().map(|tune| tune.update(psi, clock.uptime().as_secs_f32())) {
+            Some(AutotuneStep::Output(speed)) => speed_output.set_speed(speed)?,
+            Some(AutotuneStep::Done(gains)) => {
+              info!("VFD: autotune Kp={:.3} Ki={:.3} Kd={:.3}", gains.kp, gains.ki, gains.kd);
+              let mut cfg = config.lock().unwrap();
+              for result in [
+                cfg.set_vfd_kp((gains.kp * 1000.0).round() as u16),
+                cfg.set_vfd_ki((gains.ki * 1000.0).round() as u16),
+                cfg.set_vfd_kd((gains.kd * 1000.0).round() as u16),
+              ] {
+                if let Err(e) = result {
+                  warn!("Failed to save autotuned gains: {:?}", e);
+                }
+              }
+              speed_pid.set_gains(
+                cfg.vfd_kp_milli as f32 / 1000.0,
+                cfg.vfd_ki_milli as f32 / 1000.0,
+                cfg.vfd_kd_milli as f32 / 1000.0,
+              );
+              drop(cfg);
+              speed_pid.bumpless_transfer(speed_output.speed(), setpoint, psi);
+              autotune = None;
+            }
+            Some(AutotuneStep::Failed) => {
+              warn!("VFD: autotune found no stable oscillation, keeping gains");
+              speed_pid.bumpless_transfer(speed_output.speed(), setpoint, psi);
+              autotune = None;
+            }
+            None => speed_output.set_speed(speed_pid.update(setpoint, psi, dt))?,
+          },
+          Err(e) => warn!("VFD: pressure read error: {:?}", e),
+        }
+      }
+    }
+
+    // A trace being played back stands in for the sensors
+    #[cfg(all(feature = "sim-sensors", any(feature = "radar", feature = "pressure")))]
+    let simulating = playback.lock().unwrap().is_some();
+    #[cfg(all(not(feature = "sim-sensors"), any(feature = "radar", feature = "pressure")))]
+    let simulating = false;
+
+    // Self-test from the web UI: one step per pass, with fresh sensor reads
+    #[cfg(feature = "ethernet")]
+    if let Some(step) = {
+      let mut test = selftest.lock().unwrap();
+      if test.start(clock.uptime().as_secs()) {
+        info!("Self-test: started");
+        #[cfg(feature = "display")]
+        {
+          selftest_screen = Some(BootScreen::new(Point::new(10, 4), page_size, "Self-test", theme));
+        }
+      }
+      test.next()
+    } {
+      let outcome = match step {
+        Step::Heap => {
+          let free = memory::free_heap();
+          let detail = format!("{} KB free", free / 1024);
+          if free >= memory::LOW_HEAP_BYTES {
+            Outcome::Passed(detail)
+          } else {
+            Outcome::Failed(detail)
+          }
+        }
+        Step::Network => match net_addr {
+          Some((ip, _)) if network_up => Outcome::Passed(ip.to_string()),
+          _ => Outcome::Failed("no link or no address".to_string()),
+        },
+        #[cfg(feature = "mqtt")]
+        Step::Mqtt => match ha_client.as_ref() {
+          Some(client) if client.is_connected() => Outcome::Passed(config.lock().unwrap().mqtt_broker.clone()),
+          Some(client) => Outcome::Failed(client.connection_error().unwrap_or_else(|| "not connected".to_string())),
+          None => Outcome::Skipped("not configured"),
+        },
+        #[cfg(feature = "radar")]
+        Step::Radar if simulating => Outcome::Skipped("trace playback running"),
+        #[cfg(feature = "radar")]
+        Step::Radar => match radar.read_empty_height() {
+          Ok(mm) => Outcome::Passed(format!("{} mm to the surface", mm)),
+          Err(e) => Outcome::Failed(format!("{:?}", e)),
+        },
+        #[cfg(feature = "pressure")]
+        Step::Pressure if simulating => Outcome::Skipped("trace playback running"),
+        #[cfg(feature = "pressure")]
+        Step::Pressure => {
+          let range = config.lock().unwrap().psi_range as f32;
+          match pressure_sensor.read_psi() {
+            // Well below the zero: open circuit or no supply to the transducer
+            Ok(psi) if psi < -0.05 * range => Outcome::Failed(format!("{:.1} PSI, sensor disconnected?", psi)),
+            Ok(psi) if psi > 1.05 * range => Outcome::Failed(format!("{:.1} PSI, above the {} PSI range", psi, range)),
+            Ok(psi) => Outcome::Passed(format!("{:.1} PSI", psi.max(0.0))),
+            Err(e) => Outcome::Failed(format!("{:?}", e)),
+          }
+        }
+        #[cfg(feature = "display")]
+        Step::Display => match selftest_screen.as_mut() {
+          Some(screen) => {
+            screen.step(step.label());
+            display.clear_framebuffer();
+            if screen.draw(&mut display).is_ok() && display.flush_all().is_ok() {
+              Outcome::Passed("progress screen sent".to_string())
             } else {
-              0
-            };
-            gallons = (cfg.tank_capacity_gallons as u32 * capacity_percent as u32 / 100) as u16;
-            info!("Radar: empty {} mm, water {} mm / {} mm, {}%, {} gal", empty_mm, water_mm, useful_mm, capacity_percent, gallons);
+              Outcome::Failed("panel write failed".to_string())
+            }
+          }
+          None => Outcome::Skipped("no screen"),
+        },
+        #[allow(unreachable_patterns)]
+        _ => Outcome::Skipped("not in this build"),
+      };
+      if matches!(outcome, Outcome::Failed(_)) {
+        warn!("Self-test: {} failed: {}", step.key(), outcome.detail());
+      } else {
+        info!("Self-test: {} {}: {}", step.key(), outcome.status(), outcome.detail());
+      }
+      let (json, summary) = {
+        let mut test = selftest.lock().unwrap();
+        test.record(step, outcome.clone());
+        let report = test.report().expect("self-test running");
+        let summary = report.is_finished().then(|| match report.failures() {
+          0 => "All checks passed".to_string(),
+          n => format!("{} check(s) failed", n),
+        });
+        (report.to_json(), summary)
+      };
+      web_server.publish_selftest(&json);
+      #[cfg(feature = "display")]
+      if let Some(screen) = selftest_screen.as_mut() {
+        match &outcome {
+          Outcome::Skipped(reason) => screen.info(format!("{}: {}", step.label(), reason)),
+          Outcome::Failed(_) => {
+            // The display step put its line up already
+            if step != Step::Display {
+              screen.step(step.label());
+            }
+            screen.fail();
+          }
+          _ => {
+            if step != Step::Display {
+              screen.step(step.label());
+            }
+            screen.finish();
           }
-          Err(e) => warn!("Radar read error: {:?}", e),
+        }
+        if let Some(summary) = &summary {
+          screen.info(summary.clone());
+        }
+        display.clear_framebuffer();
+        screen.draw(&mut display).ok();
+        display.flush_all().ok();
+        info_until = Some(std::time::Instant::now() + Duration::from_secs(if summary.is_some() { 30 } else { 3600 }));
+      }
+      if let Some(summary) = summary {
+        info!("Self-test: {}", summary);
+        #[cfg(feature = "display")]
+        {
+          selftest_screen = None;
         }
       }
+    }
+
+    // Read radar sensor, as often as the level activity calls for. The reply
+    // is polled for, so a hung sensor doesn't hold up the display and the rest
+    // of the loop while it times out and retries.
+    #[cfg(feature = "radar")]
+    if !simulating && !radar.is_busy() && radar_polling.due(clock.uptime()) {
+      radar.start_measurements();
+    }
+    #[cfg(feature = "radar")]
+    let radar_result = match radar.is_busy().then(|| radar.poll_measurements()) {
+      Some(Poll::Ready(measurements)) => Some(Ok(measurements)),
+      Some(Poll::Err(e)) => Some(Err(e)),
+      Some(Poll::Pending) | None => None,
+    };
+    #[cfg(feature = "radar")]
+    if let Some(result) = radar_result {
+      let (correction, geometry) = {
+        let cfg = config.lock().unwrap();
+        level_filter.set_params(cfg.level_median_window as usize, cfg.level_smoothing_percent as f32 / 100.0);
+        (Correction::radar(&cfg), TankGeometry::from_config(&cfg))
+      };
+      let reading = result.map(|m| sensors::level_reading(m.empty_height_mm, &mut level_filter, &correction, &geometry));
+      let polled_level = match reading {
+        Ok(reading) => {
+          if radar_warmup.record(true, clock.uptime()) {
+            info!("Radar: responding again, stabilizing");
+          }
+          #[cfg(feature = "mqtt")]
+          if let Some(m) = radar.last_measurements() {
+            radar_distances = Some(RadarDistances {
+              radar_empty_mm: m.empty_height_mm,
+              radar_empty_raw_mm: m.empty_height_raw_mm,
+              radar_level_mm: m.water_level_mm,
+              radar_level_raw_mm: m.water_level_raw_mm,
+              radar_smoothed_mm: reading.empty_mm,
+            });
+          }
+          let level = reading.level;
+          capacity_percent = level.percent;
+          gallons = level.gallons;
+          // Warm-up readings would show up as phantom flow
+          if radar_warmup.ready(clock.uptime()) {
+            usage.update(level.gallons, clock.local_time().map(|t| t.weekday));
+          }
+          if let Some(now) = clock.timestamp() {
+            if let Some((day, saved)) = saved_usage.take().filter(|&(day, _)| day == now.local_day()) {
+              info!("Usage: {} gal consumed earlier today (day {})", saved.consumed, day);
+              usage.restore(saved);
+            }
+            // Only while the totals belong to the clock's day, not in the moments before a rollover
+            let current = usage.weekday() == clock.local_time().map(|t| t.weekday);
+            if current && last_usage_save.elapsed() >= USAGE_SAVE_INTERVAL {
+              last_usage_save = std::time::Instant::now();
+              let mut cfg = config.lock().unwrap();
+              let entry = (now.local_day(), usage.today());
+              if cfg.saved_usage != Some(entry) {
+                if let Err(e) = cfg.set_saved_usage(entry.0, entry.1) {
+                  warn!("Usage: could not store today's totals: {:?}", e);
+                }
+              }
+            }
+          }
+          info!(
+            "Radar: empty {} mm (raw {}, corrected {}), water {} mm / {} mm, {}%, {} gal",
+            reading.empty_mm, reading.raw_mm, reading.corrected_mm, level.water_mm, level.useful_mm, capacity_percent, gallons
+          );
+          Some(capacity_percent)
+        }
+        Err(e) => {
+          radar_warmup.record(false, clock.uptime());
+          warn!("Radar read error: {:?}", e);
+          None
+        }
+      };
+
+      #[cfg(feature = "display")]
+      if radar_warmup.ready(clock.uptime()) && last_trend.map_or(true, |t| t.elapsed() >= TREND_INTERVAL) {
+        last_trend = Some(std::time::Instant::now());
+        #[cfg(not(feature = "tft"))]
+        trend.push(capacity_percent);
+        history_trend.push(capacity_percent);
+      }
+
+      #[cfg(feature = "pump")]
+      let pumping = !pumps.is_idle();
+      #[cfg(not(feature = "pump"))]
+      let pumping = false;
+      let interval = radar_polling.update(polled_level, pumping, clock.uptime());
+      debug!("Radar: next poll in {} s", interval.as_secs());
+    }
+
+    // Frames from LoRa nodes, republished to MQTT
+    #[cfg(feature = "lora")]
+    if let Some((radio, Role::Gateway, _)) = lora.as_mut() {
+      let mut buf = [0u8; 64];
+      match radio.receive(&mut buf) {
+        Ok(Some(packet)) => match StateFrame::decode(&buf[..packet.len]) {
+          Ok(frame) => {
+            debug!("LoRa: node {} frame {} ({} dBm, {:.1} dB)", frame.node, frame.seq, packet.rssi, packet.snr);
+            #[cfg(feature = "mqtt")]
+            if let Some(ref mut client) = ha_client {
+              if let Err(e) = client.publish_sibling(&frame, packet.rssi, packet.snr) {
+                warn!("MQTT publish error: {:?}", e);
+              }
+            }
+          }
+          Err(e) => debug!("LoRa: ignored a {} byte packet ({:?})", packet.len, e),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("LoRa: receive failed: {:?}", e),
+      }
+    }
+
+    // Pipe burst: a fast pressure reading every pass, ahead of the filtered 5 s one
+    #[cfg(all(feature = "pipe_burst", feature = "ethernet"))]
+    let paused = maintenance.load(Ordering::Relaxed);
+    #[cfg(all(feature = "pipe_burst", not(feature = "ethernet")))]
+    let paused = false;
+    #[cfg(feature = "pipe_burst")]
+    if !simulating && !paused && pressure_warmup.ready(clock.uptime()) {
+      let (head_psi, correction, burst_psi) = {
+        let cfg = config.lock().unwrap();
+        (pressure_head(&cfg), Correction::pressure(&cfg), cfg.burst_psi)
+      };
+      #[cfg(feature = "pump")]
+      let pumps_off = !pumps.running().contains(&true);
+      #[cfg(not(feature = "pump"))]
+      let pumps_off = true;
+      #[cfg(feature = "well_pump")]
+      let pumps_off = pumps_off && !well_pump.is_running();
+      #[cfg(feature = "vfd")]
+      let pumps_off = pumps_off && speed_output.speed() <= 0.0;
+      match sensors::read_pressure(&mut pressure_sensor, head_psi, &correction) {
+        Ok(psi) => match burst_detector.update(psi, pumps_off, burst_psi, clock.uptime()) {
+          Some(true) => {
+            if let Some(collapse) = burst_detector.tripped() {
+              warn!("Pipe burst: pressure fell from {:.1} to {:.1} PSI with the pumps off", collapse.from_psi, collapse.to_psi);
+            }
+            #[cfg(feature = "valve")]
+            if locked {
+              warn!("Pipe burst: valve left alone, outputs are locked out");
+            } else {
+              valve.command(ValveCommand::Close, clock.uptime());
+            }
+            update_now = true;
+          }
+          Some(false) => {
+            info!("Pipe burst: pressure recovered");
+            update_now = true;
+          }
+          None => {}
+        },
+        Err(e) => debug!("Pipe burst: pressure read error: {:?}", e),
+      }
+    }
+
+    // Pump CT clamp, a slice per pass while a pump runs (feature: pump_power)
+    #[cfg(feature = "pump_power")]
+    if pump_relays.iter().any(|relay| relay.is_set_high()) {
+      if let Err(e) = ct_clamp.poll() {
+        warn!("CT clamp read error: {:?}", e);
+      }
+    }
+
+    // Other sensor readings and MQTT publish every 5 seconds, or at once after a pipe burst
+    #[cfg(feature = "pipe_burst")]
+    let update_due = std::mem::take(&mut update_now);
+    #[cfg(not(feature = "pipe_burst"))]
+    let update_due = false;
+    if update_due || last_update.elapsed() >= UPDATE_INTERVAL {
+      last_update = std::time::Instant::now();
+
+      // Shed optional work while the heap is low
+      memory_guard.update(memory::free_heap());
 
       // Read pressure sensor
       #[cfg(feature = "pressure")]
-      {
-        current_psi = match pressure_sensor.read_psi_u16(config.lock().unwrap().sensor_height_feet as f32) {
+      if !simulating {
+        let (head_psi, correction) = {
+          let cfg = config.lock().unwrap();
+          // A changed profile applies from the next reading, here and in the
+          // VFD and hammer reads
+          pressure_sensor.set_profile(TransducerProfile::from_config(&cfg));
+          pressure_sensor.set_supply(cfg.psi_supply_mv);
+          (pressure_head(&cfg), Correction::pressure(&cfg))
+        };
+        current_psi = match sensors::read_pressure(&mut pressure_sensor, head_psi, &correction).map(|psi| psi.round() as u16) {
           Ok(psi) => {
+            if pressure_warmup.record(true, clock.uptime()) {
+              info!("Pressure: responding again, stabilizing");
+            }
             debug!("Pressure: {} PSI", psi);
+            #[cfg(feature = "well_pump")]
+            if pressure_warmup.ready(clock.uptime()) {
+              well_pump.set_rise(config.lock().unwrap().well_rise_psi);
+              if let Some(running) = well_pump.update(psi, clock.uptime()) {
+                info!("Well pump: {} (from pressure)", if running { "started" } else { "stopped" });
+              }
+            }
             psi
           }
           Err(e) => {
+            pressure_warmup.record(false, clock.uptime());
             warn!("Pressure read error: {:?}", e);
             0
           }
         };
       }
 
+      // Pressure lost across the filter, both sides read back to back
+      #[cfg(feature = "filter_pressure")]
+      if !simulating {
+        {
+          let cfg = config.lock().unwrap();
+          filter_sensor.set_profile(TransducerProfile::post_filter(&cfg));
+          filter_sensor.set_supply(cfg.psi_supply_mv);
+        }
+        filter_drop_psi = match read_differential(&mut pressure_sensor, &mut filter_sensor) {
+          Ok(drop) => {
+            debug!("Filter drop: {:.1} PSI", drop);
+            Some((drop * 10.0).round() / 10.0)
+          }
+          Err(e) => {
+            warn!("Filter pressure read error: {:?}", e);
+            None
+          }
+        };
+        filter_responding = filter_drop_psi.is_some();
+      }
+
+      // Flow meter: pulses counted since the last update
+      #[cfg(feature = "flow")]
+      if !simulating {
+        let k_factor = config.lock().unwrap().flow_k_factor;
+        flow_meter.set_k_factor(k_factor);
+        match flow_counter.count() {
+          Ok(count) => {
+            let gpm = flow_meter.update(count, clock.uptime());
+            debug!("Flow: {:.2} GPM, {:.1} gal total", gpm, flow_meter.total_gallons());
+          }
+          Err(e) => warn!("Flow meter read error: {:?}", e),
+        }
+        if last_flow_save.elapsed() >= FLOW_SAVE_INTERVAL {
+          last_flow_save = std::time::Instant::now();
+          let mut cfg = config.lock().unwrap();
+          let total = flow_meter.total_gallons();
+          // Tenths are what is stored; nothing new to write below that
+          if (total - cfg.flow_total_gallons).abs() >= 0.1 {
+            if let Err(e) = cfg.set_flow_total(total) {
+              warn!("Flow: could not store the total: {:?}", e);
+            }
+          }
+        }
+      }
+
+      // Sensor warm-up state (periods reloaded so changes apply immediately)
+      #[cfg(any(feature = "radar", feature = "pressure"))]
+      {
+        let now = clock.uptime();
+        let cfg = config.lock().unwrap();
+        let was_stabilizing = stabilizing;
+        stabilizing = false;
+        #[cfg(feature = "radar")]
+        {
+          radar_warmup.set_period(Duration::from_secs(cfg.radar_warmup_secs as u64));
+          stabilizing |= !radar_warmup.ready(now);
+        }
+        #[cfg(feature = "pressure")]
+        {
+          pressure_warmup.set_period(Duration::from_secs(cfg.pressure_warmup_secs as u64));
+          stabilizing |= !pressure_warmup.ready(now);
+        }
+        if was_stabilizing != stabilizing {
+          info!("Sensors {}", if stabilizing { "stabilizing" } else { "settled" });
+          // Redraw without (or with) the banner
+          #[cfg(feature = "display")]
+          pages.invalidate();
+        }
+      }
+
       // No pressure sensor — PSI stays at 0 (may be overwritten by demo mode below)
       #[cfg(not(feature = "pressure"))]
       #[allow(unused_assignments)]
       { current_psi = 0; }
 
       // Demo mode (no real sensors)
-      #[cfg(all(not(feature = "pressure"), not(feature = "radar")))]
+      #[cfg(all(not(feature = "pressure"), not(feature = "radar"), not(feature = "remote")))]
       {
         if demo_rising {
           demo_percent = demo_percent.saturating_add(5);
@@ -659,16 +2377,437 @@ fn run() -> anyhow::Result<()> {
         gallons = (cfg.tank_capacity_gallons as u32 * capacity_percent as u32 / 100) as u16;
       }
 
-      // Publish to Home Assistant via MQTT (skip when network is down)
+      // Uploaded trace in place of the sensors, until it ends or is stopped
+      #[cfg(feature = "sim-sensors")]
+      {
+        let mut playback = playback.lock().unwrap();
+        match playback.as_mut().map(|p| p.sample(clock.uptime())) {
+          Some(Some(point)) => {
+            capacity_percent = point.capacity_percent;
+            gallons = point.gallons;
+            current_psi = point.pressure_psi;
+          }
+          Some(None) => {
+            info!("Playback: trace ended, back to the sensors");
+            *playback = None;
+          }
+          None => {}
+        }
+      }
+
+      // Remote panel: readings and alarms as the controller last published them
+      #[cfg(feature = "remote")]
+      if let Some(ref mut panel) = panel {
+        panel.poll();
+        let state = panel.state();
+        if state.is_some() != remote_state.is_some() {
+          info!("Remote panel: controller {}", if state.is_some() { "online" } else { "offline" });
+        }
+        let shown = state.unwrap_or_default();
+        capacity_percent = shown.capacity_percent;
+        gallons = shown.gallons;
+        current_psi = shown.pressure_psi;
+        alarm_state.mirror(shown.alarms(), clock.uptime());
+        remote_state = state;
+      }
+
+      // Pump control (paused in maintenance mode and by a lockout)
+      #[cfg(feature = "pump")]
+      {
+        pumps.set_settings(PumpSettings::from_config(&config.lock().unwrap()));
+        let uptime = clock.uptime();
+        // The dry-run guard only trusts a settled pressure sensor
+        #[cfg(feature = "pressure")]
+        pumps.set_pressure(pressure_warmup.ready(uptime).then_some(current_psi));
+        #[cfg(feature = "ethernet")]
+        let paused = maintenance.load(Ordering::Relaxed);
+        #[cfg(not(feature = "ethernet"))]
+        let paused = false;
+        let paused = paused || locked;
+        // Don't act on levels from a radar that is still settling
+        #[cfg(feature = "radar")]
+        let paused = paused || !radar_warmup.ready(uptime);
+        let outputs = if paused {
+          pumps.stop(uptime)
+        } else {
+          pumps.update(capacity_percent, uptime)
+        };
+        #[cfg(any(feature = "hammer", feature = "efficiency"))]
+        let timestamp = std::time::SystemTime::now()
+          .duration_since(std::time::UNIX_EPOCH)
+          .map(|d| d.as_secs() as u32)
+          .unwrap_or(0);
+        #[cfg(feature = "hammer")]
+        let before: Vec<bool> = pump_relays.iter().map(|relay| relay.is_set_high()).collect();
+        // Pump input power since the last update, taken before the relays change
+        #[cfg(feature = "pump_power")]
+        {
+          let settings = ClampSettings::from_config(&config.lock().unwrap());
+          if let Some(watts) = ct_clamp.take_watts(&settings) {
+            cycle_tracker.meter(watts, uptime);
+          }
+        }
+        for (relay, on) in pump_relays.iter_mut().zip(outputs) {
+          if on {
+            relay.set_high()?;
+          } else {
+            relay.set_low()?;
+          }
+        }
+
+        // Catch the pressure transient of a pump start or stop
+        #[cfg(feature = "hammer")]
+        if let Some(cause) = Cause::from_relays(&before, &outputs).filter(|_| pressure_warmup.ready(uptime)) {
+          let (head_psi, correction, threshold) = {
+            let cfg = config.lock().unwrap();
+            (pressure_head(&cfg), Correction::pressure(&cfg), cfg.hammer_psi)
+          };
+          match Burst::capture(cause, || sensors::read_pressure(&mut pressure_sensor, head_psi, &correction)) {
+            Ok(burst) => {
+              if let Some(event) = burst.analyze(threshold, timestamp) {
+                warn!(
+                  "Water hammer on {}: {:.1} -> {:.1} PSI after {} ms",
+                  cause.name(), event.baseline as f32 / 10.0, event.peak as f32 / 10.0, event.peak_ms
+                );
+                if let Err(e) = history.append_event(&event) {
+                  warn!("History write error: {:?}", e);
+                }
+                #[cfg(feature = "mqtt")]
+                if let Some(ref mut client) = ha_client {
+                  if let Err(e) = client.publish_hammer(&event) {
+                    warn!("MQTT publish error: {:?}", e);
+                  }
+                }
+              }
+            }
+            Err(e) => warn!("Pressure read error during hammer capture: {:?}", e),
+          }
+        }
+
+        // Delivery rate of each completed fill cycle, trended over weeks
+        #[cfg(feature = "efficiency")]
+        if let Some(cycle) = cycle_tracker.update(outputs, gallons, uptime, timestamp) {
+          info!("Pump {}: delivered {:.1} gal/min over {} s", cycle.pump + 1, cycle.gpm(), cycle.runtime_secs);
+          if let Some(kwh_per_gal) = cycle.kwh_per_gal() {
+            info!("Pump {}: {:.4} kWh/gal", cycle.pump + 1, kwh_per_gal);
+          }
+          if let Err(e) = history.append_cycle(&cycle) {
+            warn!("History write error: {:?}", e);
+          }
+          let drop_percent = config.lock().unwrap().efficiency_drop_percent;
+          match history.recent_cycles(EFFICIENCY_MAX_CYCLES) {
+            Ok(cycles) => {
+              let trends: [PumpTrend; PUMP_COUNT] =
+                std::array::from_fn(|pump| PumpTrend::compute(&cycles, pump as u8, timestamp, drop_percent));
+              for (pump, (trend, degraded)) in trends.iter().zip(pump_degraded.iter_mut()).enumerate() {
+                if trend.degraded && !*degraded {
+                  warn!(
+                    "Pump {}: weekly delivery {:.1} gal/min, {}% or more below its {:.1} gal/min baseline",
+                    pump + 1, trend.week_gpm.unwrap_or(0.0), drop_percent, trend.baseline_gpm.unwrap_or(0.0)
+                  );
+                }
+                *degraded = trend.degraded;
+              }
+              #[cfg(feature = "mqtt")]
+              {
+                pump_trends = Some(trends);
+              }
+            }
+            Err(e) => warn!("History read error: {:?}", e),
+          }
+        }
+      }
+
+      // Radar heater (off in maintenance mode and by a lockout)
+      #[cfg(feature = "heater")]
+      {
+        heater.set_settings(HeaterSettings::from_config(&config.lock().unwrap()));
+        #[cfg(feature = "ethernet")]
+        let (paused, test_on) = (maintenance.load(Ordering::Relaxed), firing == Some(Output::Heater));
+        #[cfg(not(feature = "ethernet"))]
+        let (paused, test_on) = (false, false);
+        let on = if paused || locked { heater.stop() || test_on } else { heater.update(clock.uptime()) };
+        if on {
+          heater_pin.set_high()?;
+        } else {
+          heater_pin.set_low()?;
+        }
+      }
+
+      // Alarm conditions: only settled readings count, and a sensor that stops answering is a fault
+      #[cfg(not(feature = "remote"))]
+      {
+        let now = clock.uptime();
+        #[allow(unused_mut)]
+        let mut sensors_ok = Vec::new();
+        #[cfg(feature = "radar")]
+        sensors_ok.push(radar_warmup.responding());
+        #[cfg(feature = "pressure")]
+        sensors_ok.push(pressure_warmup.responding());
+        #[cfg(feature = "filter_pressure")]
+        sensors_ok.push(filter_responding);
+        let readings = Readings {
+          #[cfg(feature = "radar")]
+          level: radar_warmup.ready(now).then_some(capacity_percent),
+          #[cfg(feature = "pressure")]
+          pressure_psi: pressure_warmup.ready(now).then_some(current_psi),
+          sensors_ok: &sensors_ok,
+          #[cfg(feature = "pipe_burst")]
+          pipe_burst: burst_detector.is_tripped(),
+          #[cfg(feature = "filter_pressure")]
+          filter_drop_psi: filter_drop_psi.filter(|_| pressure_warmup.ready(now)),
+          ..Default::default()
+        };
+        let thresholds = Thresholds::from_config(&config.lock().unwrap());
+        for (alarm, active) in alarm_state.update(&readings, &thresholds, now) {
+          if active {
+            warn!("Alarm: {} raised", alarm.label());
+          } else {
+            info!("Alarm: {} cleared", alarm.label());
+          }
+          #[cfg(feature = "ethernet")]
+          alarm_log.lock().unwrap().record(alarm, active, now, clock.timestamp());
+        }
+      }
+
+      // Alarm notifications (not raised in maintenance mode), following the alarms above
+      #[cfg(feature = "notify")]
+      if !maintenance.load(Ordering::Relaxed) {
+        #[cfg(feature = "pump")]
+        let (pump_failed, dry_run): (Vec<bool>, bool) =
+          (pumps.stats().iter().map(|stats| stats.failed).collect(), pumps.is_dry_run());
+        #[cfg(not(feature = "pump"))]
+        let (pump_failed, dry_run): (Vec<bool>, bool) = (Vec::new(), false);
+        #[cfg(feature = "radar")]
+        let over_budget = usage.today().over_budget(config.lock().unwrap().budget_gallons);
+        #[cfg(not(feature = "radar"))]
+        let over_budget = false;
+        let inputs = Inputs {
+          low_level: alarm_state.is_active(Alarm::LowLevel),
+          pump_failed: &pump_failed,
+          dry_run,
+          burst: alarm_state.is_active(Alarm::PipeBurst),
+          over_budget,
+        };
+        let events = alarms.update(&inputs);
+        if !events.is_empty() {
+          let (tank, templates) = {
+            let cfg = config.lock().unwrap();
+            (cfg.tank_name.clone(), notify::parse_templates(&cfg.notify_templates))
+          };
+          for (event, pump) in events {
+            let vars = Vars {
+              tank: tank.clone(),
+              level: capacity_percent,
+              gallons,
+              psi: current_psi,
+              pump: pump.map(|i| i as u8 + 1),
+              time: clock.local_time(),
+            };
+            let notification = Notification { event, message: notify::render(notify::template(&templates, event), &vars) };
+            warn!("Alarm: {}", notification.message);
+            #[cfg(feature = "mqtt")]
+            if let Some(ref mut client) = ha_client {
+              if let Err(e) = client.publish_notification(&notification) {
+                warn!("MQTT publish error: {:?}", e);
+              }
+            }
+            webhook.send(notification);
+          }
+        }
+      }
+
+      // Refresh diagnostics for /api/diag and the display page
+      #[cfg(feature = "ethernet")]
+      {
+        #[cfg(feature = "mqtt")]
+        let mqtt = ha_client.as_ref().map(|client| client.diagnostics());
+        #[cfg(not(feature = "mqtt"))]
+        let mqtt = None;
+        #[cfg(feature = "display")]
+        let flush = Some(display.flush_stats().clone());
+        #[cfg(not(feature = "display"))]
+        let flush = None;
+        #[cfg(feature = "cellular")]
+        let cell = Some(cellular.diagnostics());
+        #[cfg(not(feature = "cellular"))]
+        let cell = None;
+        #[cfg(feature = "radar")]
+        let radar_diag = Some(radar.diagnostics());
+        #[cfg(not(feature = "radar"))]
+        let radar_diag = None;
+        #[cfg(feature = "pressure")]
+        let pressure_diag = Some(pressure_sensor.diagnostics());
+        #[cfg(not(feature = "pressure"))]
+        let pressure_diag = None;
+        *diag_status.lock().unwrap() = Diagnostics {
+          uptime_secs: clock.uptime().as_secs(),
+          free_heap: memory::free_heap(),
+          min_free_heap: memory_guard.min_free(),
+          board: board.revision.name(),
+          firmware: env!("CARGO_PKG_VERSION"),
+          reset_reason,
+          link_up: network_up,
+          ip: net_addr.map(|(ip, _)| ip),
+          mqtt,
+          display: flush,
+          cellular: cell,
+          radar: radar_diag,
+          pressure: pressure_diag,
+          startup: Some(startup.clone()),
+        };
+      }
+
+      // Send this tank's state to a LoRa gateway
+      #[cfg(feature = "lora")]
+      if let Some((radio, Role::Node, beacon)) = lora.as_mut() {
+        if let Some(seq) = beacon.due(clock.uptime()) {
+          #[allow(unused_mut)]
+          let mut frame = StateFrame {
+            node: config.lock().unwrap().lora_node as u8,
+            seq,
+            capacity_percent,
+            gallons,
+            pressure_psi: current_psi,
+            alarms: Alarm::ALL.map(|alarm| alarm_state.is_active(alarm)),
+            uptime_secs: clock.uptime().as_secs() as u32,
+            ..Default::default()
+          };
+          #[cfg(feature = "pump")]
+          {
+            frame.pumps = pumps.running();
+            frame.dry_run = pumps.is_dry_run();
+          }
+          #[cfg(feature = "heater")]
+          {
+            frame.heater_on = heater.is_on();
+          }
+          #[cfg(feature = "radar")]
+          {
+            frame.used_today = usage.today().consumed.min(u16::MAX as u32) as u16;
+            frame.refilled_today = usage.today().refilled.min(u16::MAX as u32) as u16;
+          }
+          #[cfg(feature = "ethernet")]
+          {
+            frame.maintenance = maintenance.load(Ordering::Relaxed);
+          }
+          match radio.transmit(&frame.encode()) {
+            Ok(()) => debug!("LoRa: sent frame {}", seq),
+            Err(e) => warn!("LoRa: send failed: {:?}", e),
+          }
+        }
+      }
+
+      // Share readings with the web status page
+      #[cfg(feature = "ethernet")]
+      {
+        *live_status.lock().unwrap() = LiveStatus {
+          capacity_percent,
+          gallons,
+          pressure_psi: current_psi,
+        };
+      }
+
+      // Every reading goes to InfluxDB once SNTP has set the clock
+      #[cfg(feature = "influx")]
+      if let Some(now) = clock.timestamp() {
+        #[allow(unused_mut)]
+        let mut point = Point::new(influx::MEASUREMENT)
+          .tag("host", net::HOSTNAME)
+          .field("level_pct", Value::Int(capacity_percent as i64))
+          .field("gallons", Value::Int(gallons as i64))
+          .field("pressure_psi", Value::Int(current_psi as i64));
+        #[cfg(feature = "pump")]
+        for (key, running) in ["pump1_on", "pump2_on"].into_iter().zip(pumps.running()) {
+          point = point.field(key, Value::Bool(running));
+        }
+        #[cfg(feature = "vfd")]
+        {
+          point = point.field("vfd_speed", Value::Float(speed_output.speed()));
+        }
+        #[cfg(feature = "radar")]
+        {
+          point = point
+            .field("used_today", Value::Int(usage.today().consumed as i64))
+            .field("refilled_today", Value::Int(usage.today().refilled as i64));
+        }
+        if let Some(line) = point.line(now.epoch_secs) {
+          influx_writer.send(line);
+        }
+      }
+
+      // Planned maintenance reboot, checked on the per-minute tick
+      #[cfg(feature = "ethernet")]
+      if minute_tick.poll(&clock) {
+        if !clock_synced {
+          if let Some(now) = clock.timestamp() {
+            clock_synced = true;
+            info!("SNTP: clock set, local time {}", now.iso8601());
+          }
+        }
+        let reboot = {
+          let cfg = config.lock().unwrap();
+          RebootSchedule { day: cfg.reboot_day, hour: cfg.reboot_hour }
+        };
+        // Never restart in the middle of a fill cycle or with an alarm up
+        #[cfg(feature = "pump")]
+        let idle = pumps.is_idle();
+        #[cfg(not(feature = "pump"))]
+        let idle = true;
+        let alarm_active = Alarm::ALL.into_iter().any(|alarm| alarm_state.is_active(alarm));
+        if reboot.should_reboot(&clock, idle, alarm_active) {
+          warn!("Scheduled maintenance reboot (uptime {} h)", clock.uptime().as_secs() / 3600);
+          config.lock().unwrap().flush();
+          unsafe { esp_idf_svc::sys::esp_restart(); }
+        }
+      }
+
+      // Append to flash history every 5 minutes (less often under heap pressure)
+      #[cfg(feature = "history")]
+      if last_history.elapsed() >= memory_guard.history_interval(HISTORY_INTERVAL) {
+        last_history = std::time::Instant::now();
+        let timestamp = std::time::SystemTime::now()
+          .duration_since(std::time::UNIX_EPOCH)
+          .map(|d| d.as_secs() as u32)
+          .unwrap_or(0);
+        let sample = Sample {
+          timestamp,
+          capacity_percent,
+          pressure_psi: current_psi,
+          gallons,
+        };
+        if let Err(e) = history.append(&sample) {
+          warn!("History write error: {:?}", e);
+        }
+        #[cfg(feature = "mqtt")]
+        match history.recent(HISTORY_DAY_SAMPLES) {
+          Ok(samples) => history_stats = Some(HistoryStats::compute(&samples, timestamp)),
+          Err(e) => warn!("History read error: {:?}", e),
+        }
+      }
+
+      // Publish to Home Assistant via MQTT (state is queued while the network or broker is down)
       #[cfg(feature = "mqtt")]
       if let Some(ref mut client) = ha_client {
         #[cfg(feature = "ethernet")]
-        let can_publish = network_up;
+        let (can_publish, link_up) = (!maintenance.load(Ordering::Relaxed), network_up);
         #[cfg(not(feature = "ethernet"))]
-        let can_publish = true;
+        let (can_publish, link_up) = (true, true);
+        // Over the cellular link, state goes out only as often as the data budget allows
+        #[cfg(feature = "cellular")]
+        let (can_publish, link_up) = if cellular.is_online() {
+          let due = last_cellular_publish.map_or(true, |t| t.elapsed() >= cellular.publish_interval(UPDATE_INTERVAL));
+          if can_publish && due {
+            last_cellular_publish = Some(std::time::Instant::now());
+          }
+          (can_publish && due, true)
+        } else {
+          (can_publish, link_up)
+        };
         if can_publish {
           let cfg = config.lock().unwrap();
-          let state = WaterState {
+          #[allow(unused_mut)]
+          let mut state = WaterState {
             capacity_percent,
             capacity_gallons: gallons,
             pressure_psi: current_psi,
@@ -677,11 +2816,176 @@ fn run() -> anyhow::Result<()> {
             max_psi: cfg.max_psi,
             radar_height: cfg.radar_height_cm,
             radar_deadzone: cfg.radar_deadzone_cm,
+            flush_lines: cfg.display_flush_lines,
+            display_refresh: cfg.display_refresh_minutes,
+            tank_fill: cfg.tank_fill_pattern,
+            reboot_day: cfg.reboot_day,
+            reboot_hour: cfg.reboot_hour,
+            pump_start: cfg.pump_start_percent,
+            pump_stop: cfg.pump_stop_percent,
+            pump_assist: cfg.pump_assist_drop_percent,
+            pump_fail_min: cfg.pump_fail_minutes,
+            vfd_setpoint: cfg.vfd_setpoint_psi,
+            vfd_kp: cfg.vfd_kp_milli,
+            vfd_ki: cfg.vfd_ki_milli,
+            vfd_kd: cfg.vfd_kd_milli,
+            tank_shape: cfg.tank_shape,
+            radar_warmup: cfg.radar_warmup_secs,
+            psi_warmup: cfg.pressure_warmup_secs,
+            heater_mode: cfg.heater_mode,
+            heater_spread: cfg.heater_spread_c,
+            heater_duty: cfg.heater_duty_percent,
+            level_median: cfg.level_median_window,
+            level_alpha: cfg.level_smoothing_percent,
+            page_interval: cfg.page_interval_secs,
+            pump_min_on: cfg.pump_min_on_secs,
+            pump_min_off: cfg.pump_min_off_secs,
+            pump_dry_psi: cfg.pump_dry_psi,
+            pump_dry_secs: cfg.pump_dry_secs,
+            valve_travel: cfg.valve_travel_secs,
+            hammer_psi: cfg.hammer_psi,
+            burst_psi: cfg.burst_psi,
+            efficiency_drop: cfg.efficiency_drop_percent,
+            alarm_low: cfg.alarm_low_percent,
+            alarm_high_psi: cfg.alarm_high_psi,
+            alarm_clog_psi: cfg.alarm_clog_psi,
+            alarm_fault_secs: cfg.alarm_fault_secs,
+            budget_gal: cfg.budget_gallons,
+            flow_k_factor: cfg.flow_k_factor,
+            alarm_low_level: alarm_state.is_active(Alarm::LowLevel),
+            alarm_high_pressure: alarm_state.is_active(Alarm::HighPressure),
+            alarm_sensor_fault: alarm_state.is_active(Alarm::SensorFault),
+            alarm_pipe_burst: alarm_state.is_active(Alarm::PipeBurst),
+            alarm_filter_clogged: alarm_state.is_active(Alarm::FilterClogged),
+            timestamp: clock.timestamp().map(|t| t.iso8601()),
+            ..Default::default()
           };
           drop(cfg);
-          if let Err(e) = client.publish_state(&state) {
+          #[cfg(feature = "pump")]
+          {
+            for ((pump, stats), running) in state.pumps.iter_mut().zip(pumps.stats()).zip(pumps.running()) {
+              *pump = PumpState { running, runtime_min: (stats.runtime.as_secs() / 60) as u32, starts: stats.starts, failed: stats.failed };
+            }
+            state.pump_dry_run = pumps.is_dry_run();
+          }
+          #[cfg(feature = "vfd")]
+          {
+            state.vfd_speed = (speed_output.speed() * 100.0).round() as u8;
+          }
+          #[cfg(feature = "heater")]
+          {
+            state.heater_on = heater.is_on();
+          }
+          #[cfg(feature = "flow")]
+          {
+            state.flow_gpm = Some((flow_meter.gpm() * 100.0).round() / 100.0);
+            state.flow_total_gal = Some((flow_meter.total_gallons() * 10.0).round() / 10.0);
+          }
+          #[cfg(feature = "filter_pressure")]
+          {
+            state.filter_drop_psi = filter_drop_psi.filter(|_| pressure_warmup.ready(clock.uptime()));
+          }
+          #[cfg(feature = "well_pump")]
+          {
+            state.well_pump_on = well_pump.is_running();
+            state.well_pump_runtime_min = (well_pump.runtime(clock.uptime()).as_secs() / 60) as u32;
+            state.well_pump_starts = well_pump.starts();
+          }
+          #[cfg(feature = "radar")]
+          {
+            state.used_today = usage.today().consumed;
+            state.refilled_today = usage.today().refilled;
+            state.budget_pct = usage.today().budget_percent(state.budget_gal);
+            state.budget_exceeded = usage.today().over_budget(state.budget_gal);
+            state.radar = radar_distances;
+          }
+          if !link_up {
+            client.queue_state(&state);
+          } else if let Err(e) = client.publish_state(&state) {
             warn!("MQTT publish error: {:?}", e);
           }
+          // Same document for the live dashboard, whether or not the broker is up
+          #[cfg(feature = "ethernet")]
+          web_server.publish_state(&state.to_json());
+          // The rest only matters while current, so it is skipped while offline
+          let online = link_up && client.is_connected();
+          if online && last_diag.map_or(true, |t| t.elapsed() >= DIAG_INTERVAL) {
+            last_diag = Some(std::time::Instant::now());
+            let diag = diag_status.lock().unwrap().clone();
+            if let Err(e) = client.publish_diagnostics(&diag) {
+              warn!("MQTT publish error: {:?}", e);
+            }
+          }
+          #[cfg(feature = "ds18b20")]
+          if online {
+            let names = config.lock().unwrap().probe_names.clone();
+            let probes = probes.lock().unwrap().clone();
+            if let Err(e) = client.publish_probes(&probes, &names) {
+              warn!("MQTT publish error: {:?}", e);
+            }
+          }
+          #[cfg(feature = "history")]
+          if let Some(stats) = history_stats.take().filter(|_| online) {
+            if let Err(e) = client.publish_history_stats(&stats) {
+              warn!("MQTT publish error: {:?}", e);
+            }
+          }
+          #[cfg(feature = "efficiency")]
+          if let Some(trends) = pump_trends.take().filter(|_| online) {
+            if let Err(e) = client.publish_efficiency(&trends) {
+              warn!("MQTT publish error: {:?}", e);
+            }
+          }
+        }
+      }
+    }
+
+    // Buttons: short = next page, long = acknowledge alarms, hold = setup mode
+    #[cfg(feature = "buttons")]
+    for event in std::iter::from_fn(|| button_rx.try_recv().ok()) {
+      info!("Button: {:?} {:?}", event.button, event.press);
+      // Any press at the unit allows the lockout to be released on the web UI
+      #[cfg(feature = "lockout")]
+      if locked {
+        lockout.lock().unwrap().confirm_presence(clock.uptime());
+        info!("Lockout: presence confirmed at the unit");
+      }
+      match (event.button, event.press) {
+        (ButtonId::Primary, Press::Short) => {
+          #[cfg(feature = "display")]
+          pages.next(clock.uptime());
+        }
+        (ButtonId::Primary, Press::Long) | (ButtonId::Secondary, Press::Short) => {
+          // Latched pump failures are the only alarms that need acknowledging
+          #[cfg(feature = "pump")]
+          pumps.reset_faults();
+        }
+        (_, Press::Hold) | (ButtonId::Secondary, Press::Long) => {
+          // Setup mode pauses automation (as maintenance mode) and shows where to configure the unit
+          #[cfg(feature = "ethernet")]
+          {
+            let setup = !maintenance.load(Ordering::Relaxed);
+            maintenance.store(setup, Ordering::Relaxed);
+            info!("Setup mode {}", if setup { "on" } else { "off" });
+            #[cfg(feature = "display")]
+            if setup {
+              let url = match net_addr {
+                Some((ip, _)) => format!("http://{}/", ip),
+                None => "Waiting for DHCP...".to_string(),
+              };
+              display.clear_framebuffer();
+              Text::new("SETUP MODE", Point::new(10, 60), boot_text_style).draw(&mut display)?;
+              Text::new(&url, Point::new(10, 100), boot_text_style).draw(&mut display)?;
+              Text::new("Hold button to exit", Point::new(10, 140), boot_text_style).draw(&mut display)?;
+              display.flush()?;
+              info_until = Some(std::time::Instant::now() + Duration::from_secs(600));
+            } else {
+              info_until = None;
+              pages.invalidate();
+            }
+          }
+          #[cfg(not(feature = "ethernet"))]
+          warn!("Setup mode needs the ethernet feature");
         }
       }
     }
@@ -693,26 +2997,175 @@ fn run() -> anyhow::Result<()> {
       let showing_info = match info_until {
         Some(until) if std::time::Instant::now() < until => true,
         Some(_) => {
-          // Info expired, clear and resume normal display
+          // Info expired, resume normal display
           info_until = None;
-          display.clear_framebuffer();
-          display.mark_all_dirty();
+          pages.invalidate();
           false
         }
         None => false,
       };
 
-      if !showing_info {
-        let max_psi = config.lock().unwrap().max_psi;
+      if !showing_info && last_frame.elapsed() >= memory_guard.frame_interval() {
+        last_frame = std::time::Instant::now();
+        pages.set_interval(Duration::from_secs(config.lock().unwrap().page_interval_secs as u64));
+        pages.update(clock.uptime());
+        // The page shows through again once the banner goes away
+        if alert_banner.set_text(alarm_state.banner()) {
+          pages.invalidate();
+        }
+        let runbook = alarm_state.splash(clock.uptime()).and_then(|alarm| {
+          Some((alarm, runbook_link(&config.lock().unwrap().runbook_url, alarm)?))
+        });
+        if runbook.is_some() != showing_runbook {
+          showing_runbook = runbook.is_some();
+          pages.invalidate();
+        }
 
-        // Update UI component values
-        tank.set_level(capacity_percent, gallons);
-        manometer.set_pressure(current_psi.min(max_psi));
+        // Page switched or an overlay covered it: start from a blank screen
+        if pages.take_invalidated() {
+          display.clear_framebuffer();
+          display.mark_all_dirty();
+          #[cfg(feature = "ethernet")]
+          network_page.invalidate();
+          #[cfg(feature = "radar")]
+          history_page.invalidate();
+          config_page.invalidate();
+          runbook_screen.invalidate();
+          #[cfg(feature = "lockout")]
+          lockout_page.invalidate();
+        }
 
-        // Draw UI (components clear their own areas)
-        tank.draw(&mut display)?;
-        manometer.draw(&mut display)?;
+        match pages.current() {
+          // The lockout screen stays up, whatever page is selected
+          #[cfg(feature = "lockout")]
+          _ if locked => {
+            let url = match net_addr {
+              Some((ip, _)) => format!("http://{}/lockout", ip),
+              None => "/lockout on the web UI".to_string(),
+            };
+            lockout_page.set_lines(vec![
+              "ALL OUTPUTS DISABLED".to_string(),
+              "Pump under service, do not operate".to_string(),
+              "To release: press the button,".to_string(),
+              "then enter the PIN at".to_string(),
+              url,
+            ]);
+            lockout_page.draw(&mut display)?;
+          }
+          _ if showing_runbook => {
+            if let Some((alarm, link)) = &runbook {
+              let lines = vec![alarm.label().to_string(), String::new(), "Scan for the".to_string(), "runbook".to_string()];
+              runbook_screen.set(&format!("ALARM {}", alarm.code()), lines, link);
+              runbook_screen.draw(&mut display)?;
+            }
+          }
+          Page::Overview => {
+            #[allow(unused_mut)]
+            let mut max_psi = config.lock().unwrap().max_psi;
+            // A remote panel uses the controller's gauge range
+            #[cfg(feature = "remote")]
+            if let Some(state) = remote_state.filter(|s| s.max_psi > 0) {
+              max_psi = state.max_psi;
+            }
+
+            // Update UI component values
+            tank.set_level(capacity_percent, gallons);
+            manometer.set_pressure(current_psi.min(max_psi));
+
+            // Draw UI (components clear their own areas)
+            tank.draw(&mut display)?;
+            manometer.draw(&mut display)?;
+            #[cfg(all(feature = "radar", not(feature = "tft")))]
+            trend.draw(&mut display)?;
+            #[cfg(feature = "ethernet")]
+            if maintenance.load(Ordering::Relaxed) {
+              Text::new("MAINTENANCE", Point::new(150, 16), boot_text_style).draw(&mut display)?;
+            }
+            #[cfg(feature = "remote")]
+            match remote_state {
+              Some(state) if state.maintenance => {
+                Text::new("MAINTENANCE", Point::new(150, 16), boot_text_style).draw(&mut display)?;
+              }
+              Some(_) => {}
+              None => {
+                Text::new("No data from controller", Point::new(150, 40), boot_text_style).draw(&mut display)?;
+              }
+            }
+            #[cfg(any(feature = "radar", feature = "pressure"))]
+            if stabilizing {
+              Text::new("Stabilizing...", Point::new(150, 40), boot_text_style).draw(&mut display)?;
+            }
+          }
+          Page::Network => {
+            #[cfg(feature = "ethernet")]
+            {
+              let mut lines = match net_addr {
+                Some((ip, gateway)) => vec![format!("IP: {}", ip), format!("Gateway: {}", gateway)],
+                None => vec!["IP: waiting for DHCP".to_string()],
+              };
+              lines.extend(diag_status.lock().unwrap().lines());
+              network_page.set_lines(lines);
+              network_page.draw(&mut display)?;
+            }
+          }
+          Page::History => {
+            #[cfg(feature = "radar")]
+            {
+              let today = usage.today();
+              let mut lines = vec![format!("Today: -{} +{} gal", today.consumed, today.refilled)];
+              if let Some(day) = usage.yesterday() {
+                lines.push(format!("Yesterday: -{} +{} gal", day.consumed, day.refilled));
+              }
+              history_page.set_lines(lines);
+              history_page.draw(&mut display)?;
+              budget_bar.set_usage(today.consumed, config.lock().unwrap().budget_gallons);
+              budget_bar.draw(&mut display)?;
+              history_trend.draw(&mut display)?;
+            }
+          }
+          Page::Config => {
+            let cfg = config.lock().unwrap();
+            #[allow(unused_mut)]
+            let mut lines = vec![
+              format!("Tank: {} gal", cfg.tank_capacity_gallons),
+              format!("Radar: {} cm, dz {} cm", cfg.radar_height_cm, cfg.radar_deadzone_cm),
+              format!("Gauge: {} PSI, {} ft", cfg.max_psi, cfg.sensor_height_feet),
+              format!("Filter: {} / {}%", cfg.level_median_window, cfg.level_smoothing_percent),
+            ];
+            #[cfg(feature = "pump")]
+            lines.push(format!("Pumps: {}-{}%", cfg.pump_start_percent, cfg.pump_stop_percent));
+            #[cfg(feature = "vfd")]
+            lines.push(format!("VFD: {} PSI", cfg.vfd_setpoint_psi));
+            drop(cfg);
+            config_page.set_lines(lines);
+            config_page.draw(&mut display)?;
+          }
+        }
+        // The lockout screen is not covered; the alert banner covers the clock
+        #[cfg(feature = "ethernet")]
+        if let Some(now) = clock.timestamp().filter(|_| !locked) {
+          let corner = Point::new(display.bounding_box().size.width as i32 - 2, 1);
+          Text::with_text_style(&now.hhmm(), corner, status_text_style, status_alignment).draw(&mut display)?;
+        }
+        if !locked && !showing_runbook {
+          alert_banner.draw(&mut display)?;
+        }
+        #[cfg(feature = "frame_overlay")]
+        {
+          let text = format!(
+            "frame {:>3} ms, flush {:>3} ms",
+            frame_time.as_millis(),
+            display.flush_stats().avg().as_millis()
+          );
+          let size = display.bounding_box().size;
+          let corner = Point::new(size.width as i32 - 2, size.height as i32 - 1);
+          Text::with_text_style(&text, corner, overlay_text_style, overlay_alignment).draw(&mut display)?;
+        }
         display.flush()?;
+        #[cfg(feature = "frame_overlay")]
+        {
+          frame_time = last_frame.elapsed();
+        }
       }
     }
 
@@ -727,8 +3180,8 @@ fn run() -> anyhow::Result<()> {
     use core::fmt::Write;
 
     let text_style = MonoTextStyleBuilder::new()
-      .font(&FONT_10X20)
-      .text_color(BinaryColor::Off)
+      .font(theme.font)
+      .text_color(theme.colors().foreground)
       .build();
 
     display.clear_framebuffer();
@@ -744,7 +3197,8 @@ fn run() -> anyhow::Result<()> {
 
     // Split long messages across lines
     let mut y = 70i32;
-    let chars_per_line = 38; // 400px / 10px per char ≈ 38
+    // 10px per char, minus margins: 38 on the 400px LCD, 30 on the TFT
+    let chars_per_line = (display.bounding_box().size.width / 10) as usize - 2;
     for chunk in msg.as_bytes().chunks(chars_per_line) {
       // Safe: splitting valid UTF-8 at ASCII boundaries (all our content is ASCII)
       let s = unsafe { core::str::from_utf8_unchecked(chunk) };
@@ -753,7 +3207,7 @@ fn run() -> anyhow::Result<()> {
       y += 26;
     }
 
-    display.flush().ok();
+    display.flush_all().ok();
 
     // Keep error visible, then reboot
     error!("Rebooting in 30 seconds...");
@@ -797,14 +3251,26 @@ impl core::fmt::Write for LineBuf<'_> {
 }
 
 /// Blocks until we have both link up and an IP address
+///
+/// With a timeout, `None` if the network isn't ready by then.
 #[cfg(feature = "ethernet")]
 fn wait_for_network(
   rx: &Receiver<NetEvent>,
-) -> anyhow::Result<(Ipv4Addr, Ipv4Addr)> {
+  timeout: Option<Duration>,
+) -> anyhow::Result<Option<(Ipv4Addr, Ipv4Addr)>> {
+  let deadline = timeout.map(|timeout| std::time::Instant::now() + timeout);
   let mut link_up = false;
 
   loop {
-    match rx.recv()? {
+    let event = match deadline {
+      Some(deadline) => match rx.recv_timeout(deadline.saturating_duration_since(std::time::Instant::now())) {
+        Ok(event) => event,
+        Err(RecvTimeoutError::Timeout) => return Ok(None),
+        Err(e) => return Err(e.into()),
+      },
+      None => rx.recv()?,
+    };
+    match event {
       NetEvent::LinkUp => {
         info!("Link up, waiting for DHCP...");
         link_up = true;
@@ -814,7 +3280,7 @@ fn wait_for_network(
         link_up = false;
       }
       NetEvent::GotIp { ip, gateway } if link_up => {
-        return Ok((ip, gateway));
+        return Ok(Some((ip, gateway)));
       }
       NetEvent::GotIp { .. } => {
         error!("Got IP but waiting for link...");
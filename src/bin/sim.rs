@@ -0,0 +1,160 @@
+//! Desktop preview of the display UI
+//!
+//! Renders the `WaterTank`, `Manometer` and (on the LCD) `TrendGraph` widgets into an
+//! `embedded-graphics-simulator` window, driven by synthetic sensor data, so
+//! layout and theme changes can be checked without flashing the board:
+//!
+//! ```text
+//! cargo run --bin sim --features simulator --target x86_64-unknown-linux-gnu
+//! cargo run --bin sim --features simulator,tft --target x86_64-unknown-linux-gnu
+//! ```
+//!
+//! Keys: `F` cycles the tank fill pattern, `I` toggles inversion, `Space`
+//! pauses the animation, `Q`/`Esc` quits. `--screenshot <file.png>` renders a
+//! single frame to a PNG instead of opening a window.
+
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use embedded_graphics::prelude::*;
+use embedded_graphics_simulator::sdl2::Keycode;
+use embedded_graphics_simulator::{
+  BinaryColorTheme, OutputSettings, OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent, Window,
+};
+
+use watercontroller::ui::{FillPattern, Manometer, Palette, Theme, WaterTank};
+#[cfg(not(feature = "tft"))]
+use watercontroller::ui::TrendGraph;
+
+/// Sharp LS027B7DH01 memory LCD
+#[cfg(not(feature = "tft"))]
+mod panel {
+  pub type Color = embedded_graphics::pixelcolor::BinaryColor;
+  pub const SIZE: (u32, u32) = (400, 240);
+  pub const PALETTE: super::Palette<Color> = super::Palette::MONO;
+}
+
+/// 320x240 color TFT
+#[cfg(feature = "tft")]
+mod panel {
+  pub type Color = embedded_graphics::pixelcolor::Rgb565;
+  pub const SIZE: (u32, u32) = (320, 240);
+  pub const PALETTE: super::Palette<Color> = super::Palette::COLOR;
+}
+
+use panel::Color;
+
+/// Synthetic tank capacity for the gallons readout
+const CAPACITY_GALLONS: f32 = 500.0;
+/// Time for the tank to fill and drain once
+const LEVEL_PERIOD_SECS: f32 = 60.0;
+/// Pressure swing period (pump cycling)
+const PRESSURE_PERIOD_SECS: f32 = 20.0;
+const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Widgets laid out as on the device
+#[cfg(not(feature = "tft"))]
+fn widgets(theme: Theme<Color>) -> (WaterTank<Color>, Manometer<Color>) {
+  (
+    WaterTank::new(Point::new(10, 20), Size::new(100, 200), theme),
+    Manometer::new(Point::new(305, 120), 90, theme),
+  )
+}
+
+/// Level trend filled with one synthetic fill/drain cycle ending at `t`
+#[cfg(not(feature = "tft"))]
+fn trend(theme: Theme<Color>, t: f32) -> TrendGraph<Color> {
+  const SAMPLES: usize = 96;
+  let mut trend = TrendGraph::new(Point::new(116, 70), Size::new(98, 100), SAMPLES, "24h", theme);
+  for age in (0..SAMPLES).rev() {
+    trend.push(synthetic(t - age as f32 * LEVEL_PERIOD_SECS / SAMPLES as f32).0);
+  }
+  trend
+}
+
+/// 320px wide panel: narrower tank, smaller gauge
+#[cfg(feature = "tft")]
+fn widgets(theme: Theme<Color>) -> (WaterTank<Color>, Manometer<Color>) {
+  (
+    WaterTank::new(Point::new(15, 20), Size::new(100, 200), theme),
+    Manometer::new(Point::new(218, 120), 95, theme),
+  )
+}
+
+/// Fill percentage and pressure (psi) at `t` seconds into the simulation
+fn synthetic(t: f32) -> (u8, u16) {
+  // Triangle wave: fill from empty to full, then drain
+  let phase = (t / LEVEL_PERIOD_SECS).rem_euclid(1.0);
+  let level = if phase < 0.5 { phase * 2.0 } else { 2.0 - phase * 2.0 };
+  let psi = 55.0 + 15.0 * (2.0 * PI * t / PRESSURE_PERIOD_SECS).sin();
+  ((level * 100.0).round() as u8, psi.round() as u16)
+}
+
+fn render(display: &mut SimulatorDisplay<Color>, theme: Theme<Color>, t: f32) -> anyhow::Result<()> {
+  let (mut tank, mut manometer) = widgets(theme);
+  let (percent, psi) = synthetic(t);
+  tank.set_level(percent, (CAPACITY_GALLONS * percent as f32 / 100.0).round() as u16);
+  manometer.set_pressure(psi);
+
+  display.clear(theme.colors().background)?;
+  tank.draw(display)?;
+  manometer.draw(display)?;
+  #[cfg(not(feature = "tft"))]
+  trend(theme, t).draw(display)?;
+  Ok(())
+}
+
+fn output_settings() -> OutputSettings {
+  // Default theme maps BinaryColor::On to white, matching the Sharp LCD
+  OutputSettingsBuilder::new().theme(BinaryColorTheme::Default).scale(2).build()
+}
+
+fn main() -> anyhow::Result<()> {
+  let (width, height) = panel::SIZE;
+  let mut display = SimulatorDisplay::<Color>::new(Size::new(width, height));
+  let mut theme = Theme::new(panel::PALETTE);
+
+  let args: Vec<String> = std::env::args().collect();
+  if let Some(i) = args.iter().position(|a| a == "--screenshot") {
+    let path = args.get(i + 1).ok_or_else(|| anyhow::anyhow!("--screenshot needs a file name"))?;
+    // Partly full tank, needle mid-scale
+    render(&mut display, theme, LEVEL_PERIOD_SECS * 0.3)?;
+    display.to_rgb_output_image(&output_settings()).save_png(path)?;
+    println!("Saved {}", path);
+    return Ok(());
+  }
+
+  let mut window = Window::new("Water Controller", &output_settings());
+  let start = Instant::now();
+  let mut paused_at: Option<f32> = None;
+  let mut offset = 0.0f32;
+
+  loop {
+    let now = start.elapsed().as_secs_f32() - offset;
+    render(&mut display, theme, paused_at.unwrap_or(now))?;
+    window.update(&display);
+
+    for event in window.events() {
+      match event {
+        SimulatorEvent::Quit => return Ok(()),
+        SimulatorEvent::KeyDown { keycode, .. } => match keycode {
+          Keycode::Q | Keycode::Escape => return Ok(()),
+          Keycode::F => {
+            theme.fill = FillPattern::from_code((theme.fill.code() + 1) % 3);
+            println!("Fill: {:?}", theme.fill);
+          }
+          Keycode::I => theme.inverted = !theme.inverted,
+          Keycode::Space => match paused_at.take() {
+            // Resume where the animation stopped
+            Some(t) => offset += now - t,
+            None => paused_at = Some(now),
+          },
+          _ => {}
+        },
+        _ => {}
+      }
+    }
+
+    std::thread::sleep(FRAME_INTERVAL);
+  }
+}
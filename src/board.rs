@@ -0,0 +1,110 @@
+//! Carrier board identification
+//!
+//! Carrier boards identify themselves with two resistor straps on the
+//! input-only pins GPIO34 (bit 0) and GPIO35 (bit 1), read once at boot. The
+//! ID selects the GPIO assignment for the peripherals that moved between
+//! board revisions, so a single firmware image runs on all of them:
+//!
+//! - ID 0, rev A: original pinout
+//! - ID 1, rev B: radar TX moved to GPIO14 (GPIO12 is the flash voltage
+//!   strap), pump 2 relay moved to GPIO12
+//!
+//! Ethernet, display and pressure sensor pins are the same on every board.
+//! Both ID pins lack internal pulls, so each board must strap them.
+
+/// Carrier board revision
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Revision {
+    A,
+    B,
+}
+
+impl Revision {
+    /// Name for logs and diagnostics
+    pub fn name(self) -> &'static str {
+        match self {
+            Revision::A => "rev A",
+            Revision::B => "rev B",
+        }
+    }
+}
+
+/// GPIO assignment of a carrier board
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoardProfile {
+    pub revision: Revision,
+    /// Radar UART TX/RX
+    pub radar_tx: i32,
+    pub radar_rx: i32,
+    /// Pump 1 and pump 2 relay outputs (active HIGH)
+    pub pump_relays: [i32; 2],
+    /// Heater MOSFET gate (active HIGH)
+    pub heater: i32,
+    /// VFD speed reference PWM
+    pub vfd_pwm: i32,
+}
+
+impl BoardProfile {
+    pub const REV_A: Self = Self {
+        revision: Revision::A,
+        radar_tx: 12,
+        radar_rx: 13,
+        pump_relays: [4, 14],
+        heater: 15,
+        vfd_pwm: 2,
+    };
+
+    pub const REV_B: Self = Self {
+        revision: Revision::B,
+        radar_tx: 14,
+        radar_rx: 13,
+        // The relay driver holds GPIO12 low through reset
+        pump_relays: [4, 12],
+        heater: 15,
+        vfd_pwm: 2,
+    };
+
+    /// Profile for the ID read from the straps (`None` for unknown boards)
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::REV_A),
+            1 => Some(Self::REV_B),
+            _ => None,
+        }
+    }
+
+    /// Board ID from the strap levels (GPIO34, GPIO35)
+    pub fn id_from_straps(bit0: bool, bit1: bool) -> u8 {
+        bit0 as u8 | (bit1 as u8) << 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selects_profile_from_straps() {
+        assert_eq!(BoardProfile::from_id(BoardProfile::id_from_straps(false, false)), Some(BoardProfile::REV_A));
+        let rev_b = BoardProfile::from_id(BoardProfile::id_from_straps(true, false)).unwrap();
+        assert_eq!(rev_b.revision.name(), "rev B");
+        assert_eq!(BoardProfile::from_id(BoardProfile::id_from_straps(false, true)), None);
+    }
+
+    #[test]
+    fn test_profiles_have_no_shared_outputs() {
+        for board in [BoardProfile::REV_A, BoardProfile::REV_B] {
+            let mut pins = vec![
+                board.radar_tx,
+                board.radar_rx,
+                board.pump_relays[0],
+                board.pump_relays[1],
+                board.heater,
+                board.vfd_pwm,
+            ];
+            pins.sort_unstable();
+            pins.dedup();
+            assert_eq!(pins.len(), 6, "{:?}", board.revision);
+        }
+    }
+}
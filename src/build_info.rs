@@ -0,0 +1,64 @@
+//! Firmware build information
+//!
+//! The commit, build date and enabled features of the running image, set by
+//! `build.rs`. Home Assistant gets them as attributes of the main sensors
+//! (see `homeassistant`), so the firmware of every unit in a fleet can be
+//! checked from HA without opening each web page.
+
+/// Firmware version from `Cargo.toml`
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short commit hash, `-dirty` with uncommitted changes, `unknown` outside a git checkout
+pub const GIT_HASH: &str = env!("BUILD_GIT_HASH");
+/// UTC build date (YYYY-MM-DD); `SOURCE_DATE_EPOCH` for reproducible builds
+pub const BUILD_DATE: &str = env!("BUILD_DATE");
+
+/// Every optional feature and whether this image has it
+const FEATURES: &[(&str, bool)] = &[
+    ("ethernet", cfg!(feature = "ethernet")),
+    ("display", cfg!(feature = "display")),
+    ("tft", cfg!(feature = "tft")),
+    ("ili9341", cfg!(feature = "ili9341")),
+    ("st7789", cfg!(feature = "st7789")),
+    ("frame_overlay", cfg!(feature = "frame_overlay")),
+    ("buttons", cfg!(feature = "buttons")),
+    ("radar", cfg!(feature = "radar")),
+    ("pressure", cfg!(feature = "pressure")),
+    ("mqtt", cfg!(feature = "mqtt")),
+    ("history", cfg!(feature = "history")),
+    ("pump", cfg!(feature = "pump")),
+    ("hammer", cfg!(feature = "hammer")),
+    ("efficiency", cfg!(feature = "efficiency")),
+    ("well_pump", cfg!(feature = "well_pump")),
+    ("vfd", cfg!(feature = "vfd")),
+    ("pipe_burst", cfg!(feature = "pipe_burst")),
+    ("heater", cfg!(feature = "heater")),
+    ("ds18b20", cfg!(feature = "ds18b20")),
+    ("valve", cfg!(feature = "valve")),
+    ("valve_limits", cfg!(feature = "valve_limits")),
+    ("notify", cfg!(feature = "notify")),
+    ("influx", cfg!(feature = "influx")),
+    ("lockout", cfg!(feature = "lockout")),
+    ("ota", cfg!(feature = "ota")),
+    ("cellular", cfg!(feature = "cellular")),
+    ("lora", cfg!(feature = "lora")),
+    ("remote", cfg!(feature = "remote")),
+    ("sim-sensors", cfg!(feature = "sim-sensors")),
+    ("wifi", cfg!(feature = "wifi")),
+];
+
+/// Features enabled in this image, in `Cargo.toml` order
+pub fn features() -> Vec<&'static str> {
+    FEATURES.iter().filter(|&&(_, enabled)| enabled).map(|&(name, _)| name).collect()
+}
+
+/// Plain-text summary for the diagnostic bundle: version, commit, build date
+/// and every feature, enabled or not
+pub fn report() -> String {
+    let mut text = format!("version {VERSION}\ncommit {GIT_HASH}\nbuilt {BUILD_DATE}\n\nfeatures:\n");
+    for &(name, enabled) in FEATURES {
+        text.push_str(if enabled { "  + " } else { "  - " });
+        text.push_str(name);
+        text.push('\n');
+    }
+    text
+}
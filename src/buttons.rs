@@ -0,0 +1,163 @@
+//! Front panel buttons
+//!
+//! Up to two active-low buttons are polled every 10 ms in a background
+//! thread. A press has to be stable for `DEBOUNCE` to count, and is reported
+//! over a channel as one of:
+//!
+//! - `Short`: released before `LONG_PRESS`
+//! - `Long`: released between `LONG_PRESS` and `HOLD`
+//! - `Hold`: still down after `HOLD`, sent without waiting for the release
+//!
+//! What a press does is up to the main loop.
+
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::hal::gpio::{AnyInputPin, Input, PinDriver};
+use log::*;
+
+/// Level changes shorter than this are contact bounce
+const DEBOUNCE: Duration = Duration::from_millis(30);
+/// Shortest long press
+const LONG_PRESS: Duration = Duration::from_secs(1);
+/// Press reported while still held
+const HOLD: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Which button was pressed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ButtonId {
+    Primary,
+    Secondary,
+}
+
+/// Kind of press
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Press {
+    Short,
+    Long,
+    Hold,
+}
+
+/// Press event sent to the main loop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ButtonEvent {
+    pub button: ButtonId,
+    pub press: Press,
+}
+
+/// Debounce and press classification for one button
+#[derive(Debug, Default)]
+pub struct Debouncer {
+    /// Last raw level and when it was first seen
+    raw: bool,
+    raw_since: Duration,
+    /// Debounced state: when the current press started
+    pressed_at: Option<Duration>,
+    /// `Hold` already reported for the current press
+    held: bool,
+}
+
+impl Debouncer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a raw reading (`true` = down), returning a press once classified
+    pub fn update(&mut self, down: bool, now: Duration) -> Option<Press> {
+        if down != self.raw {
+            self.raw = down;
+            self.raw_since = now;
+        }
+        let stable = now.saturating_sub(self.raw_since) >= DEBOUNCE;
+
+        match self.pressed_at {
+            None if down && stable => {
+                // The press started when the level first changed
+                self.pressed_at = Some(self.raw_since);
+                self.held = false;
+                None
+            }
+            Some(start) if down => {
+                if !self.held && now.saturating_sub(start) >= HOLD {
+                    self.held = true;
+                    return Some(Press::Hold);
+                }
+                None
+            }
+            Some(start) if stable => {
+                self.pressed_at = None;
+                let duration = self.raw_since.saturating_sub(start);
+                match duration {
+                    _ if self.held => None,
+                    d if d >= LONG_PRESS => Some(Press::Long),
+                    _ => Some(Press::Short),
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Poll the buttons (active low) in a background thread
+pub fn start(
+    buttons: Vec<(ButtonId, PinDriver<'static, AnyInputPin, Input>)>,
+    tx: Sender<ButtonEvent>,
+) -> std::io::Result<()> {
+    let count = buttons.len();
+    std::thread::Builder::new()
+        .name("buttons".into())
+        .stack_size(3 * 1024)
+        .spawn(move || {
+            let started = Instant::now();
+            let mut buttons: Vec<_> = buttons.into_iter().map(|(id, pin)| (id, pin, Debouncer::new())).collect();
+            loop {
+                let now = started.elapsed();
+                for (button, pin, debouncer) in buttons.iter_mut() {
+                    if let Some(press) = debouncer.update(pin.is_low(), now) {
+                        if tx.send(ButtonEvent { button: *button, press }).is_err() {
+                            return;
+                        }
+                    }
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        })?;
+    info!("Buttons: polling {}", count);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `down` from `from` to `to` ms in 10 ms steps, collecting presses
+    fn feed(b: &mut Debouncer, down: bool, from: u64, to: u64) -> Vec<Press> {
+        (from..to)
+            .step_by(10)
+            .filter_map(|ms| b.update(down, Duration::from_millis(ms)))
+            .collect()
+    }
+
+    #[test]
+    fn test_short_press_ignores_bounce() {
+        let mut b = Debouncer::new();
+        assert!(feed(&mut b, false, 0, 100).is_empty());
+        // Bounces shorter than the debounce time
+        assert!(feed(&mut b, true, 100, 110).is_empty());
+        assert!(feed(&mut b, false, 110, 120).is_empty());
+        assert!(feed(&mut b, true, 120, 300).is_empty());
+        assert_eq!(feed(&mut b, false, 300, 400), vec![Press::Short]);
+        assert!(feed(&mut b, false, 400, 500).is_empty());
+    }
+
+    #[test]
+    fn test_long_press_and_hold() {
+        let mut b = Debouncer::new();
+        assert!(feed(&mut b, true, 0, 1500).is_empty());
+        assert_eq!(feed(&mut b, false, 1500, 1600), vec![Press::Long]);
+        // Hold fires while down, and the release adds nothing
+        assert_eq!(feed(&mut b, true, 2000, 7500), vec![Press::Hold]);
+        assert!(feed(&mut b, false, 7500, 7600).is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+//! Captive portal DNS
+//!
+//! While the setup access point is up (see `wifi`), every name a joining
+//! phone or laptop looks up resolves to the unit. The OS connectivity check
+//! then lands on the web server, which redirects it to the setup page, and
+//! the phone pops the page up by itself. A queries get one record pointing at
+//! the unit; other types get an empty answer so clients fall back to A.
+
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::*;
+
+/// Address of the setup access point (the soft AP default)
+pub const AP_ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 1);
+
+/// How long clients may cache an answer (s)
+const TTL_SECS: u32 = 60;
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+/// Response to a DNS query resolving its name to `ip`
+///
+/// `None` for anything but a standard query with a single question.
+pub fn answer(query: &[u8], ip: Ipv4Addr) -> Option<Vec<u8>> {
+    if query.len() < HEADER_LEN {
+        return None;
+    }
+    let is_response = query[2] & 0x80 != 0;
+    let opcode = (query[2] >> 3) & 0x0F;
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    if is_response || opcode != 0 || questions != 1 {
+        return None;
+    }
+    // Name labels, up to the root label
+    let mut pos = HEADER_LEN;
+    loop {
+        let len = *query.get(pos)? as usize;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers don't belong in a question
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        pos += 1 + len;
+    }
+    let question_end = pos + 1 + 4;
+    let question = query.get(HEADER_LEN..question_end)?;
+    let qtype = u16::from_be_bytes([query[pos + 1], query[pos + 2]]);
+    let qclass = u16::from_be_bytes([query[pos + 3], query[pos + 4]]);
+    let answers = u16::from(qtype == TYPE_A && qclass == CLASS_IN);
+
+    let mut response = Vec::with_capacity(question_end + 16);
+    response.extend_from_slice(&query[..2]);
+    // Response, recursion desired copied, recursion available, no error
+    response.extend_from_slice(&[0x80 | (query[2] & 0x01), 0x80]);
+    response.extend_from_slice(&1u16.to_be_bytes());
+    response.extend_from_slice(&answers.to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]);
+    response.extend_from_slice(question);
+    if answers == 1 {
+        // Name as a pointer to the question
+        response.extend_from_slice(&[0xC0, HEADER_LEN as u8]);
+        response.extend_from_slice(&TYPE_A.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&TTL_SECS.to_be_bytes());
+        response.extend_from_slice(&4u16.to_be_bytes());
+        response.extend_from_slice(&ip.octets());
+    }
+    Some(response)
+}
+
+/// Answer DNS queries on port 53 in a background thread until `stop` is set
+pub fn start(ip: Ipv4Addr, stop: Arc<AtomicBool>) -> std::io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 53))?;
+    // Wakes up now and then to check `stop`
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    std::thread::Builder::new()
+        .name("captive-dns".into())
+        .stack_size(4 * 1024)
+        .spawn(move || {
+            let mut buf = [0u8; 512];
+            while !stop.load(Ordering::Relaxed) {
+                let Ok((len, peer)) = socket.recv_from(&mut buf) else { continue };
+                if let Some(response) = answer(&buf[..len], ip) {
+                    if let Err(e) = socket.send_to(&response, peer) {
+                        debug!("Captive DNS: reply to {} failed: {}", peer, e);
+                    }
+                }
+            }
+            info!("Captive DNS: stopped");
+        })?;
+    info!("Captive DNS: answering every name with {}", ip);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Query for `name` with the given type, id 0x1234, recursion desired
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut q = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            q.push(label.len() as u8);
+            q.extend_from_slice(label.as_bytes());
+        }
+        q.push(0);
+        q.extend_from_slice(&qtype.to_be_bytes());
+        q.extend_from_slice(&CLASS_IN.to_be_bytes());
+        q
+    }
+
+    #[test]
+    fn test_answers_every_name() {
+        let q = query("connectivitycheck.gstatic.com", TYPE_A);
+        let r = answer(&q, AP_ADDRESS).unwrap();
+        assert_eq!(r[..12], [0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(r[12..q.len()], q[12..]);
+        assert_eq!(r[q.len()..], [0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 168, 71, 1]);
+
+        // AAAA: no records, so the client asks for A next
+        let q = query("captive.apple.com", 28);
+        let r = answer(&q, AP_ADDRESS).unwrap();
+        assert_eq!(r[6..8], [0, 0]);
+        assert_eq!(r.len(), q.len());
+    }
+
+    #[test]
+    fn test_ignores_other_packets() {
+        let q = query("example.com", TYPE_A);
+        let mut response = q.clone();
+        response[2] |= 0x80;
+        assert_eq!(answer(&response, AP_ADDRESS), None);
+        assert_eq!(answer(&q[..q.len() - 3], AP_ADDRESS), None);
+        assert_eq!(answer(&q[..8], AP_ADDRESS), None);
+        let mut two = q.clone();
+        two[5] = 2;
+        assert_eq!(answer(&two, AP_ADDRESS), None);
+        let mut pointer = q;
+        pointer[12] = 0xC0;
+        assert_eq!(answer(&pointer, AP_ADDRESS), None);
+    }
+}
@@ -0,0 +1,539 @@
+//! Cellular backup WAN
+//!
+//! A SIM7000-style modem on UART2 (TX GPIO32, RX GPIO33, 115200 8N1) takes
+//! over as the uplink when Ethernet stops reaching the internet, for tank
+//! sites whose only other way out is a flaky radio bridge or none at all.
+//!
+//! # Failover
+//! Every 30 seconds `PROBE_ADDR` is pinged through the Ethernet interface
+//! only. Once it has failed for a minute (link down, no DHCP lease or no
+//! upstream behind the switch), the modem is dialled with the APN from
+//! `cell_apn` and the PPP link becomes the default route; MQTT, SNTP and
+//! webhooks reconnect over it by themselves. Ethernet keeps being probed, and
+//! after it has answered for 5 minutes the modem hangs up again.
+//!
+//! The modem is in data mode while online, so the signal strength shown in
+//! the diagnostics is the one read just before dialling.
+//!
+//! # Data budget
+//! Every PPP byte in either direction counts against `cell_budget_mb` per
+//! calendar month (UTC; 0 = unlimited). While the unit is on the cellular
+//! link, state publishes are spread out once usage runs ahead of an even
+//! spread over the month: at twice that pace, state goes out half as often,
+//! down to every 15 minutes, which is also the rate once the budget is
+//! spent. The count is stored every 10 minutes and on hang-up, so reboots
+//! don't reset it.
+
+use std::time::Duration;
+
+#[cfg(target_os = "espidf")]
+use std::net::Ipv4Addr;
+#[cfg(target_os = "espidf")]
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+#[cfg(target_os = "espidf")]
+use std::sync::{Arc, Mutex};
+#[cfg(target_os = "espidf")]
+use std::time::Instant;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::handle::RawHandle;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::hal::delay::TickType;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::hal::uart::UartDriver;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::netif::{EspNetif, EspNetifDriver, NetifStack, PppConfiguration};
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::ping::{self, EspPing};
+#[cfg(target_os = "espidf")]
+use log::*;
+
+#[cfg(target_os = "espidf")]
+use crate::config::Config;
+#[cfg(target_os = "espidf")]
+use crate::diag::CellularDiag;
+
+/// How often the Ethernet uplink is probed
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+/// Ethernet failing this long brings the cellular link up
+pub const FAILOVER_AFTER: Duration = Duration::from_secs(60);
+/// Ethernet answering this long takes it down again
+pub const FAILBACK_AFTER: Duration = Duration::from_secs(300);
+/// Slowest state publish rate on the cellular link
+pub const MAX_PUBLISH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Decides which link carries the traffic
+#[derive(Debug, Clone, Default)]
+pub struct Failover {
+    cellular: bool,
+    /// Since when the probes have disagreed with the current link
+    since: Option<Duration>,
+}
+
+impl Failover {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an Ethernet probe at `now`, returning whether the cellular link should be up
+    pub fn update(&mut self, ethernet_ok: bool, now: Duration) -> bool {
+        // Failing while on Ethernet, or answering while on cellular
+        if ethernet_ok != self.cellular {
+            self.since = None;
+            return self.cellular;
+        }
+        let since = *self.since.get_or_insert(now);
+        let hold = if self.cellular { FAILBACK_AFTER } else { FAILOVER_AFTER };
+        if now.saturating_sub(since) >= hold {
+            self.cellular = !self.cellular;
+            self.since = None;
+        }
+        self.cellular
+    }
+
+    pub fn on_cellular(&self) -> bool {
+        self.cellular
+    }
+}
+
+/// Monthly data allowance of the cellular link
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DataBudget {
+    /// Bytes per month (0 = unlimited)
+    pub limit: u64,
+    /// Bytes used in `month`
+    pub used: u64,
+    /// Month the usage belongs to (see `month_progress`)
+    pub month: u16,
+}
+
+impl DataBudget {
+    pub fn new(limit_mb: u16, used: u64, month: u16) -> Self {
+        Self { limit: limit_mb as u64 * 1_000_000, used, month }
+    }
+
+    /// Count `bytes` in `month` (`None` while the clock isn't set), starting over in a new month
+    pub fn add(&mut self, bytes: u64, month: Option<u16>) {
+        if let Some(month) = month.filter(|&m| m != self.month) {
+            self.month = month;
+            self.used = 0;
+        }
+        self.used += bytes;
+    }
+
+    /// Interval between state publishes at `progress` (fraction of the month gone, `None` if unknown)
+    pub fn publish_interval(&self, base: Duration, progress: Option<f32>) -> Duration {
+        if self.limit == 0 {
+            return base;
+        }
+        if self.used >= self.limit {
+            return MAX_PUBLISH_INTERVAL;
+        }
+        let Some(progress) = progress else {
+            return base;
+        };
+        // Share of the budget used against the share of the month gone
+        let pace = self.used as f32 / self.limit as f32 / progress.max(0.001);
+        if pace <= 1.0 {
+            return base;
+        }
+        base.mul_f32(pace).min(MAX_PUBLISH_INTERVAL)
+    }
+}
+
+/// Month key (`year * 12 + month - 1`) and the fraction of it gone at `unix_secs`
+///
+/// `None` before 2020, i.e. while the clock hasn't been set.
+pub fn month_progress(unix_secs: u64) -> Option<(u16, f32)> {
+    if unix_secs < 1_577_836_800 {
+        return None;
+    }
+    let (year, month, day) = civil_from_days((unix_secs / 86400) as i64);
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    let elapsed = (day as u64 - 1) * 86400 + unix_secs % 86400;
+    Some(((year * 12 + month as i64 - 1) as u16, elapsed as f32 / (days_in_month * 86400) as f32))
+}
+
+/// (year, month 1-12, day 1-31) of a day count since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Signal strength (dBm) from a `+CSQ: <rssi>,<ber>` reply, `None` if unknown
+pub fn parse_csq(reply: &str) -> Option<i16> {
+    let line = reply.lines().find_map(|line| line.trim().strip_prefix("+CSQ:"))?;
+    let rssi: i16 = line.split(',').next()?.trim().parse().ok()?;
+    match rssi {
+        0..=31 => Some(-113 + 2 * rssi),
+        _ => None,
+    }
+}
+
+/// Address pinged through Ethernet to check the uplink (a public DNS server)
+#[cfg(target_os = "espidf")]
+const PROBE_ADDR: Ipv4Addr = Ipv4Addr::new(8, 8, 8, 8);
+/// How often the usage count is stored while online
+#[cfg(target_os = "espidf")]
+const SAVE_INTERVAL: Duration = Duration::from_secs(600);
+/// Wait before dialling again after a failed attempt
+#[cfg(target_os = "espidf")]
+const REDIAL_DELAY: Duration = Duration::from_secs(60);
+/// UART read timeout (ticks at 1 kHz)
+#[cfg(target_os = "espidf")]
+const READ_TIMEOUT: TickType = TickType::new_millis(100);
+
+/// Link state
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LinkState {
+    #[default]
+    Standby,
+    Dialing,
+    Online,
+}
+
+impl LinkState {
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkState::Standby => "standby",
+            LinkState::Dialing => "dialing",
+            LinkState::Online => "online",
+        }
+    }
+}
+
+/// State shared between the modem threads and the main loop
+#[cfg(target_os = "espidf")]
+#[derive(Default)]
+struct Shared {
+    state: LinkState,
+    signal_dbm: Option<i16>,
+    budget: DataBudget,
+}
+
+/// Cellular backup link, run on its own thread
+#[cfg(target_os = "espidf")]
+pub struct Cellular {
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[cfg(target_os = "espidf")]
+impl Cellular {
+    /// Start probing Ethernet (`ethernet_index` is its netif index) and dial the modem when it fails
+    pub fn start(uart: UartDriver<'static>, ethernet_index: u32, config: Arc<Mutex<Config>>) -> std::io::Result<Self> {
+        let budget = {
+            let cfg = config.lock().unwrap();
+            DataBudget::new(cfg.cell_budget_mb, cfg.cell_usage_bytes, cfg.cell_usage_month)
+        };
+        let shared = Arc::new(Mutex::new(Shared { budget, ..Default::default() }));
+        let thread_shared = shared.clone();
+        let uart = Arc::new(uart);
+        std::thread::Builder::new()
+            .name("cellular".into())
+            .stack_size(8 * 1024)
+            .spawn(move || run(uart, ethernet_index, config, thread_shared))?;
+        Ok(Self { shared })
+    }
+
+    /// Whether traffic is going over the cellular link
+    pub fn is_online(&self) -> bool {
+        self.shared.lock().unwrap().state == LinkState::Online
+    }
+
+    /// Interval between state publishes that keeps to the data budget
+    pub fn publish_interval(&self, base: Duration) -> Duration {
+        let progress = month_progress(unix_now()).map(|(_, progress)| progress);
+        self.shared.lock().unwrap().budget.publish_interval(base, progress)
+    }
+
+    pub fn diagnostics(&self) -> CellularDiag {
+        let shared = self.shared.lock().unwrap();
+        CellularDiag {
+            state: shared.state.name(),
+            signal_dbm: shared.signal_dbm,
+            used_bytes: shared.budget.used,
+            budget_bytes: shared.budget.limit,
+        }
+    }
+}
+
+#[cfg(target_os = "espidf")]
+fn unix_now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Open PPP session, pumped by its own thread
+#[cfg(target_os = "espidf")]
+struct Session {
+    stop: Arc<AtomicBool>,
+    thread: std::thread::JoinHandle<()>,
+    /// Bytes moved since last counted
+    bytes: Arc<AtomicU32>,
+}
+
+/// Probe, fail over and back, and count usage
+#[cfg(target_os = "espidf")]
+fn run(uart: Arc<UartDriver<'static>>, ethernet_index: u32, config: Arc<Mutex<Config>>, shared: Arc<Mutex<Shared>>) {
+    let started = Instant::now();
+    let mut failover = Failover::new();
+    let mut ping = EspPing::new(ethernet_index);
+    let probe = ping::Configuration { count: 3, ..Default::default() };
+    let mut session: Option<Session> = None;
+    let mut next_dial = Duration::ZERO;
+    let mut last_save = Instant::now();
+    loop {
+        let ethernet_ok = ping.ping(PROBE_ADDR, &probe).is_ok_and(|summary| summary.received > 0);
+        let want = failover.update(ethernet_ok, started.elapsed());
+
+        // A session whose thread has ended lost its carrier
+        if session.as_ref().is_some_and(|s| s.thread.is_finished()) {
+            warn!("Cellular: link dropped");
+            hang_up(&mut session, &config, &shared);
+            next_dial = started.elapsed() + REDIAL_DELAY;
+        }
+        if want && session.is_none() && started.elapsed() >= next_dial {
+            warn!("Cellular: Ethernet uplink down, dialling the modem");
+            shared.lock().unwrap().state = LinkState::Dialing;
+            let apn = config.lock().unwrap().cell_apn.clone();
+            match dial(&uart, &apn) {
+                Ok(signal_dbm) => {
+                    shared.lock().unwrap().signal_dbm = signal_dbm;
+                    match open_session(uart.clone(), shared.clone()) {
+                        Ok(s) => session = Some(s),
+                        Err(e) => warn!("Cellular: could not start the PPP thread: {}", e),
+                    }
+                }
+                Err(e) => warn!("Cellular: dial failed: {}", e),
+            }
+            if session.is_none() {
+                shared.lock().unwrap().state = LinkState::Standby;
+                next_dial = started.elapsed() + REDIAL_DELAY;
+            }
+        } else if !want && session.is_some() {
+            info!("Cellular: Ethernet uplink back, hanging up");
+            hang_up(&mut session, &config, &shared);
+        }
+
+        if let Some(s) = &session {
+            count(s, &shared);
+            if last_save.elapsed() >= SAVE_INTERVAL {
+                last_save = Instant::now();
+                save_usage(&config, &shared);
+            }
+        }
+        std::thread::sleep(PROBE_INTERVAL);
+    }
+}
+
+/// Move the session's byte count into the budget
+#[cfg(target_os = "espidf")]
+fn count(session: &Session, shared: &Mutex<Shared>) {
+    let bytes = session.bytes.swap(0, Ordering::Relaxed) as u64;
+    let month = month_progress(unix_now()).map(|(month, _)| month);
+    shared.lock().unwrap().budget.add(bytes, month);
+}
+
+#[cfg(target_os = "espidf")]
+fn save_usage(config: &Mutex<Config>, shared: &Mutex<Shared>) {
+    let (month, used) = {
+        let budget = &shared.lock().unwrap().budget;
+        (budget.month, budget.used)
+    };
+    if let Err(e) = config.lock().unwrap().set_cell_usage(month, used) {
+        warn!("Cellular: could not store data usage: {:?}", e);
+    }
+}
+
+/// Stop the session, count and store its usage
+#[cfg(target_os = "espidf")]
+fn hang_up(session: &mut Option<Session>, config: &Mutex<Config>, shared: &Mutex<Shared>) {
+    if let Some(s) = session.take() {
+        s.stop.store(true, Ordering::Relaxed);
+        let _ = s.thread.join();
+        count(&s, shared);
+    }
+    shared.lock().unwrap().state = LinkState::Standby;
+    save_usage(config, shared);
+}
+
+/// Send an AT command and wait for `expect`, returning the reply
+#[cfg(target_os = "espidf")]
+fn command(uart: &UartDriver, cmd: &str, expect: &str, timeout: Duration) -> Result<String, String> {
+    uart.clear_rx().map_err(|e| format!("{}: {}", cmd, e))?;
+    uart.write(format!("{}\r", cmd).as_bytes()).map_err(|e| format!("{}: {}", cmd, e))?;
+    let deadline = Instant::now() + timeout;
+    let mut reply = Vec::new();
+    let mut buf = [0u8; 64];
+    while Instant::now() < deadline {
+        let n = uart.read(&mut buf, READ_TIMEOUT.ticks()).unwrap_or(0);
+        reply.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&reply);
+        if text.contains(expect) {
+            return Ok(text.into_owned());
+        }
+        if text.contains("ERROR") || text.contains("NO CARRIER") {
+            return Err(format!("{}: {}", cmd, text.trim()));
+        }
+    }
+    Err(format!("{}: no answer", cmd))
+}
+
+/// Bring the modem to data mode, returning the signal strength read on the way
+#[cfg(target_os = "espidf")]
+fn dial(uart: &UartDriver, apn: &str) -> Result<Option<i16>, String> {
+    let short = Duration::from_secs(2);
+    // A modem left in data mode needs the escape sequence first
+    if command(uart, "AT", "OK", short).is_err() {
+        std::thread::sleep(Duration::from_secs(1));
+        let _ = uart.write(b"+++");
+        std::thread::sleep(Duration::from_secs(1));
+        command(uart, "AT", "OK", short)?;
+    }
+    command(uart, "ATE0", "OK", short)?;
+    command(uart, "AT+CPIN?", "READY", Duration::from_secs(5))?;
+    let signal = parse_csq(&command(uart, "AT+CSQ", "OK", short)?);
+    info!("Cellular: signal {:?} dBm", signal);
+    command(uart, &format!("AT+CGDCONT=1,\"IP\",\"{}\"", apn), "OK", short)?;
+    command(uart, "ATD*99#", "CONNECT", Duration::from_secs(30))?;
+    Ok(signal)
+}
+
+/// Netif whose PPP frames go out over the modem UART
+#[cfg(target_os = "espidf")]
+fn ppp_driver(uart: Arc<UartDriver<'static>>, bytes: Arc<AtomicU32>) -> Result<EspNetifDriver<'static, EspNetif>, esp_idf_svc::sys::EspError> {
+    let mut driver = EspNetifDriver::new(
+        EspNetif::new(NetifStack::Ppp)?,
+        |netif| netif.set_ppp_conf(&PppConfiguration { phase_events_enabled: false, ..Default::default() }),
+        move |data| {
+            bytes.fetch_add(data.len() as u32, Ordering::Relaxed);
+            uart.write(data).map(|_| ())
+        },
+    )?;
+    driver.start()?;
+    Ok(driver)
+}
+
+/// Attach a PPP netif to the modem and pump its bytes until told to stop or the carrier drops
+#[cfg(target_os = "espidf")]
+fn open_session(uart: Arc<UartDriver<'static>>, shared: Arc<Mutex<Shared>>) -> std::io::Result<Session> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let bytes = Arc::new(AtomicU32::new(0));
+    let thread_stop = stop.clone();
+    let thread_bytes = bytes.clone();
+    // The netif driver isn't `Send`, so it lives on the thread that feeds it
+    let thread = std::thread::Builder::new()
+        .name("ppp".into())
+        .stack_size(6 * 1024)
+        .spawn(move || {
+            let driver = match ppp_driver(uart.clone(), thread_bytes.clone()) {
+                Ok(driver) => driver,
+                Err(e) => {
+                    warn!("Cellular: PPP failed: {:?}", e);
+                    return;
+                }
+            };
+            let mut buf = [0u8; 512];
+            let mut online = false;
+            while !thread_stop.load(Ordering::Relaxed) {
+                let n = uart.read(&mut buf, READ_TIMEOUT.ticks()).unwrap_or(0);
+                if n > 0 {
+                    thread_bytes.fetch_add(n as u32, Ordering::Relaxed);
+                    // The modem drops back to command mode when the carrier goes
+                    if buf[..n].windows(10).any(|w| w == b"NO CARRIER") {
+                        break;
+                    }
+                    if let Err(e) = driver.rx(&buf[..n]) {
+                        warn!("Cellular: PPP receive failed: {:?}", e);
+                    }
+                }
+                if !online && driver.netif().is_up().unwrap_or(false) {
+                    online = true;
+                    info!("Cellular: online as {}", driver.netif().get_ip_info().map(|i| i.ip).unwrap_or(Ipv4Addr::UNSPECIFIED));
+                    // Take the default route even while the Ethernet link is still up
+                    unsafe { esp_idf_svc::sys::esp_netif_set_default_netif(driver.netif().handle()) };
+                    shared.lock().unwrap().state = LinkState::Online;
+                }
+            }
+            // Dropping the netif gives the default route back to Ethernet
+            drop(driver);
+            std::thread::sleep(Duration::from_secs(1));
+            let _ = uart.write(b"+++");
+            std::thread::sleep(Duration::from_secs(1));
+            let _ = uart.write(b"ATH\r");
+        })?;
+    Ok(Session { stop, thread, bytes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_failover_and_back() {
+        let mut failover = Failover::new();
+        assert!(!failover.update(false, secs(0)));
+        assert!(!failover.update(false, secs(30)));
+        // One good probe starts the wait over
+        assert!(!failover.update(true, secs(60)));
+        assert!(!failover.update(false, secs(90)));
+        assert!(!failover.update(false, secs(120)));
+        assert!(failover.update(false, secs(150)));
+        // Back only after Ethernet has answered for 5 minutes
+        assert!(failover.update(true, secs(180)));
+        assert!(failover.update(false, secs(210)));
+        assert!(failover.update(true, secs(240)));
+        assert!(failover.update(true, secs(510)));
+        assert!(!failover.update(true, secs(540)));
+        assert!(!failover.on_cellular());
+    }
+
+    #[test]
+    fn test_budget_paces_publishes() {
+        let base = secs(5);
+        // 100 MB, half used halfway through the month: on pace
+        let mut budget = DataBudget::new(100, 50_000_000, 24_312);
+        assert_eq!(budget.publish_interval(base, Some(0.5)), base);
+        assert_eq!(budget.publish_interval(base, Some(0.25)), secs(10));
+        assert_eq!(budget.publish_interval(base, Some(0.001)), MAX_PUBLISH_INTERVAL);
+        // Without a clock only the spent budget slows publishing
+        assert_eq!(budget.publish_interval(base, None), base);
+        budget.add(50_000_000, Some(24_312));
+        assert_eq!(budget.publish_interval(base, None), MAX_PUBLISH_INTERVAL);
+        // A new month starts over
+        budget.add(1_000, Some(24_313));
+        assert_eq!((budget.month, budget.used), (24_313, 1_000));
+        budget.add(1_000, None);
+        assert_eq!(budget.used, 2_000);
+        assert_eq!(DataBudget::new(0, 1 << 40, 0).publish_interval(base, Some(0.1)), base);
+    }
+
+    #[test]
+    fn test_month_progress_and_csq() {
+        assert_eq!(month_progress(1_767_225_600), Some((2026 * 12, 0.0)));
+        // 2024-02-15 12:00 UTC, halfway through a leap February
+        assert_eq!(month_progress(1_707_998_400), Some((2024 * 12 + 1, 0.5)));
+        assert_eq!(month_progress(1_793_469_600).map(|(m, _)| m), Some(2026 * 12 + 9));
+        assert_eq!(month_progress(0), None);
+
+        assert_eq!(parse_csq("\r\n+CSQ: 14,99\r\n\r\nOK\r\n"), Some(-85));
+        assert_eq!(parse_csq("+CSQ: 99,99"), None);
+        assert_eq!(parse_csq("OK"), None);
+    }
+}
@@ -0,0 +1,400 @@
+//! Time source abstraction
+//!
+//! Time-dependent logic (reboot schedule, pump timers, alarms) reads time
+//! through the `Clock` trait instead of calling the system directly, so host
+//! tests can drive it with `MockClock` and get deterministic results.
+//!
+//! - `SystemClock`: `esp_timer` uptime and the SNTP-synced local time
+//! - `MockClock`: manually advanced, for tests and simulation
+//! - `Ticker`: fires once per period (e.g. the per-minute scheduler tick)
+//! - `Timestamp`: wall-clock instant with its UTC offset, formatted as
+//!   ISO 8601 for telemetry and as `HH:MM` for the display
+//!
+//! The clock is set over SNTP once the network is up; until then, and on a
+//! unit that never reaches a time server, the wall-clock methods return
+//! `None`. The UTC offset follows the configured POSIX `TZ` string
+//! (`timezone`), so it includes daylight saving time.
+
+use std::cell::Cell;
+use std::time::Duration;
+
+/// Clock values below this (Nov 2023) mean SNTP has not synced yet
+#[cfg(target_os = "espidf")]
+const MIN_VALID_EPOCH: i64 = 1_700_000_000;
+
+/// Broken-down local time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime {
+    /// Day of week, 1 = Monday ... 7 = Sunday
+    pub weekday: u8,
+    /// Hour of day (0-23)
+    pub hour: u8,
+    /// Minute of hour (0-59)
+    pub minute: u8,
+}
+
+/// Wall-clock instant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch (UTC)
+    pub epoch_secs: i64,
+    /// Local time minus UTC at that instant (seconds)
+    pub utc_offset_secs: i32,
+}
+
+impl Timestamp {
+    /// Local date and time with the offset, e.g. `2026-10-14T07:05:00-05:00`
+    pub fn iso8601(&self) -> String {
+        let local = self.epoch_secs + self.utc_offset_secs as i64;
+        let (year, month, day) = civil_from_days(local.div_euclid(86_400));
+        let secs = local.rem_euclid(86_400);
+        let offset = self.utc_offset_secs.unsigned_abs() / 60;
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            if self.utc_offset_secs < 0 { '-' } else { '+' },
+            offset / 60,
+            offset % 60
+        )
+    }
+
+    /// Local date as days since 1970-01-01
+    pub fn local_day(&self) -> i64 {
+        (self.epoch_secs + self.utc_offset_secs as i64).div_euclid(86_400)
+    }
+
+    /// Local time of day, e.g. `07:05`
+    pub fn hhmm(&self) -> String {
+        let secs = (self.epoch_secs + self.utc_offset_secs as i64).rem_euclid(86_400);
+        format!("{:02}:{:02}", secs / 3600, secs / 60 % 60)
+    }
+}
+
+/// Date from days since 1970-01-01 (proleptic Gregorian)
+///
+/// Howard Hinnant's `civil_from_days`; valid for any day of the `i64` range
+/// that fits a year.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Days since 1970-01-01 of a date; inverse of `civil_from_days`
+#[cfg(any(target_os = "espidf", test))]
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Source of monotonic and wall-clock time
+pub trait Clock {
+    /// Monotonic time since boot
+    fn uptime(&self) -> Duration;
+    /// Local wall-clock time, or `None` until the clock has been set
+    fn local_time(&self) -> Option<LocalTime>;
+    /// Current instant, or `None` until the clock has been set
+    fn timestamp(&self) -> Option<Timestamp>;
+}
+
+/// The device clock
+#[cfg(target_os = "espidf")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+#[cfg(target_os = "espidf")]
+impl Clock for SystemClock {
+    fn uptime(&self) -> Duration {
+        let micros = unsafe { esp_idf_svc::sys::esp_timer_get_time() };
+        Duration::from_micros(micros as u64)
+    }
+
+    fn local_time(&self) -> Option<LocalTime> {
+        use esp_idf_svc::sys::{localtime_r, time, time_t, tm};
+
+        let mut now: time_t = 0;
+        unsafe { time(&mut now) };
+        if (now as i64) < MIN_VALID_EPOCH {
+            return None;
+        }
+
+        let mut local: tm = unsafe { core::mem::zeroed() };
+        unsafe { localtime_r(&now, &mut local) };
+        Some(LocalTime {
+            // tm_wday counts from Sunday = 0
+            weekday: if local.tm_wday == 0 { 7 } else { local.tm_wday as u8 },
+            hour: local.tm_hour as u8,
+            minute: local.tm_min as u8,
+        })
+    }
+
+    fn timestamp(&self) -> Option<Timestamp> {
+        use esp_idf_svc::sys::{localtime_r, time, time_t, tm};
+
+        let mut now: time_t = 0;
+        unsafe { time(&mut now) };
+        if (now as i64) < MIN_VALID_EPOCH {
+            return None;
+        }
+
+        // newlib's `tm` has no `tm_gmtoff`: the offset is the broken-down
+        // local time read back as if it were UTC, minus the real UTC time
+        let mut local: tm = unsafe { core::mem::zeroed() };
+        unsafe { localtime_r(&now, &mut local) };
+        let days = days_from_civil(local.tm_year as i64 + 1900, local.tm_mon as u32 + 1, local.tm_mday as u32);
+        let local_secs = days * 86_400 + local.tm_hour as i64 * 3600 + local.tm_min as i64 * 60 + local.tm_sec as i64;
+        Some(Timestamp { epoch_secs: now as i64, utc_offset_secs: (local_secs - now as i64) as i32 })
+    }
+}
+
+/// Apply a POSIX `TZ` string (e.g. `CET-1CEST,M3.5.0,M10.5.0/3`) to local time
+#[cfg(target_os = "espidf")]
+pub fn set_timezone(tz: &str) {
+    std::env::set_var("TZ", tz);
+    unsafe { esp_idf_svc::sys::tzset() };
+}
+
+/// Whether `tz` is a POSIX `TZ` string, `std offset [dst [offset] [,start,end]]`
+///
+/// newlib has no zone database: anything else, such as `Europe/Berlin`,
+/// would quietly run on UTC.
+pub fn valid_timezone(tz: &str) -> bool {
+    let mut rest = tz.as_bytes();
+    if !tz_name(&mut rest) || !tz_time(&mut rest, 24) {
+        return false;
+    }
+    if rest.is_empty() {
+        return true;
+    }
+    if !tz_name(&mut rest) {
+        return false;
+    }
+    if rest.first().is_some_and(|&c| c != b',') && !tz_time(&mut rest, 24) {
+        return false;
+    }
+    rest.is_empty() || (tz_rule(&mut rest) && tz_rule(&mut rest) && rest.is_empty())
+}
+
+/// Zone abbreviation: three or more letters, or `<...>` with digits and signs
+fn tz_name(rest: &mut &[u8]) -> bool {
+    let len = if rest.first() == Some(&b'<') {
+        let Some(end) = rest.iter().position(|&c| c == b'>') else {
+            return false;
+        };
+        if end < 4 || !rest[1..end].iter().all(|&c| c.is_ascii_alphanumeric() || c == b'+' || c == b'-') {
+            return false;
+        }
+        end + 1
+    } else {
+        let end = rest.iter().position(|c| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        if end < 3 {
+            return false;
+        }
+        end
+    };
+    *rest = &rest[len..];
+    true
+}
+
+/// Up to three digits, at most `max`
+fn tz_number(rest: &mut &[u8], max: u32) -> Option<u32> {
+    let len = rest.iter().take(3).take_while(|c| c.is_ascii_digit()).count();
+    if len == 0 {
+        return None;
+    }
+    let value = rest[..len].iter().fold(0, |n, &c| n * 10 + (c - b'0') as u32);
+    *rest = &rest[len..];
+    (value <= max).then_some(value)
+}
+
+/// `[+-]hh[:mm[:ss]]`, hours up to `max_hours`
+fn tz_time(rest: &mut &[u8], max_hours: u32) -> bool {
+    if matches!(rest.first(), Some(b'+' | b'-')) {
+        *rest = &rest[1..];
+    }
+    if tz_number(rest, max_hours).is_none() {
+        return false;
+    }
+    for _ in 0..2 {
+        if rest.first() != Some(&b':') {
+            break;
+        }
+        *rest = &rest[1..];
+        if tz_number(rest, 59).is_none() {
+            return false;
+        }
+    }
+    true
+}
+
+/// `,date[/time]`, the date as `Jn`, `n` or `Mm.w.d`
+fn tz_rule(rest: &mut &[u8]) -> bool {
+    if rest.first() != Some(&b',') {
+        return false;
+    }
+    *rest = &rest[1..];
+    let date = match rest.first() {
+        Some(b'J') => {
+            *rest = &rest[1..];
+            tz_number(rest, 365).is_some_and(|day| day >= 1)
+        }
+        Some(b'M') => {
+            *rest = &rest[1..];
+            let month = tz_number(rest, 12).is_some_and(|month| month >= 1);
+            let week = rest.first() == Some(&b'.') && {
+                *rest = &rest[1..];
+                tz_number(rest, 5).is_some_and(|week| week >= 1)
+            };
+            let day = rest.first() == Some(&b'.') && {
+                *rest = &rest[1..];
+                tz_number(rest, 6).is_some()
+            };
+            month && week && day
+        }
+        _ => tz_number(rest, 365).is_some(),
+    };
+    if !date {
+        return false;
+    }
+    if rest.first() == Some(&b'/') {
+        *rest = &rest[1..];
+        // Transition times may run past midnight, up to a week
+        return tz_time(rest, 167);
+    }
+    true
+}
+
+/// Manually driven clock for tests and simulation
+#[derive(Debug, Default)]
+pub struct MockClock {
+    uptime: Cell<Duration>,
+    local_time: Cell<Option<LocalTime>>,
+    timestamp: Cell<Option<Timestamp>>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move uptime forward
+    pub fn advance(&self, by: Duration) {
+        self.uptime.set(self.uptime.get() + by);
+    }
+
+    /// Set (or clear, for "not synced") the local time
+    pub fn set_local_time(&self, local_time: Option<LocalTime>) {
+        self.local_time.set(local_time);
+    }
+
+    /// Set (or clear) the wall-clock instant
+    pub fn set_timestamp(&self, timestamp: Option<Timestamp>) {
+        self.timestamp.set(timestamp);
+    }
+}
+
+impl Clock for MockClock {
+    fn uptime(&self) -> Duration {
+        self.uptime.get()
+    }
+
+    fn local_time(&self) -> Option<LocalTime> {
+        self.local_time.get()
+    }
+
+    fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp.get()
+    }
+}
+
+/// Fires once every `period` of uptime
+#[derive(Debug, Clone)]
+pub struct Ticker {
+    period: Duration,
+    next: Option<Duration>,
+}
+
+impl Ticker {
+    pub fn new(period: Duration) -> Self {
+        Self { period, next: None }
+    }
+
+    /// Ticker for once-a-minute housekeeping
+    pub fn every_minute() -> Self {
+        Self::new(Duration::from_secs(60))
+    }
+
+    /// Whether a tick is due; the first poll always ticks
+    pub fn poll(&mut self, clock: &impl Clock) -> bool {
+        let now = clock.uptime();
+        match self.next {
+            Some(next) if now < next => false,
+            _ => {
+                self.next = Some(now + self.period);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticker_fires_once_per_period() {
+        let clock = MockClock::new();
+        let mut ticker = Ticker::every_minute();
+        assert!(ticker.poll(&clock));
+        clock.advance(Duration::from_secs(59));
+        assert!(!ticker.poll(&clock));
+        clock.advance(Duration::from_secs(1));
+        assert!(ticker.poll(&clock));
+        assert!(!ticker.poll(&clock));
+    }
+
+    #[test]
+    fn test_timestamp_iso8601() {
+        // 2026-10-14 12:05:09 UTC
+        let utc = Timestamp { epoch_secs: 1_791_979_509, utc_offset_secs: 0 };
+        assert_eq!(utc.iso8601(), "2026-10-14T12:05:09+00:00");
+        let cdt = Timestamp { utc_offset_secs: -5 * 3600, ..utc };
+        assert_eq!(cdt.iso8601(), "2026-10-14T07:05:09-05:00");
+        assert_eq!(cdt.hhmm(), "07:05");
+        // India, across midnight
+        let ist = Timestamp { epoch_secs: 1_791_979_509 - 45_000, utc_offset_secs: 19_800 };
+        assert_eq!(ist.iso8601(), "2026-10-14T05:05:09+05:30");
+        // Leap day
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+        assert_eq!(days_from_civil(2026, 10, 14), 1_791_979_509 / 86_400);
+    }
+
+    #[test]
+    fn test_valid_timezone() {
+        for tz in ["UTC0", "CET-1CEST,M3.5.0,M10.5.0/3", "EST5EDT,M3.2.0/2,M11.1.0", "<+0530>-5:30", "AEST-10AEDT,M10.1.0,M4.1.0/3", "NZST-12NZDT-13,J60/2:00:00,300", "PST8PDT"] {
+            assert!(valid_timezone(tz), "{}", tz);
+        }
+        for tz in ["", "Europe/Berlin", "UTC", "CET-1CEST,M3.5.0", "CET-1CEST,M13.5.0,M10.5.0", "EST5EDT,M3.2.0,M11.1.0/", "<+5>-5", "EST25", "EST5:60", "UTC0\"><script>"] {
+            assert!(!valid_timezone(tz), "{}", tz);
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! Compact binary state frame
+//!
+//! One wire format for the transports too small or too slow for the JSON
+//! state document: LoRa today, a UDP beacon or a serial link later. A frame
+//! carries the live readings and flags of one controller in 22 bytes, framed
+//! by a magic byte, a version and the body length, and closed by a CRC, so it
+//! stands on its own on links without their own checksum.
+//!
+//! # Frame
+//! | Byte | Field |
+//! |------|-------|
+//! | 0 | magic `W` |
+//! | 1 | version (1) |
+//! | 2 | body length (17 in version 1) |
+//! | 3 | node id |
+//! | 4 | sequence number |
+//! | 5 | tank level (%) |
+//! | 6-7 | volume (gal) |
+//! | 8-9 | pressure (PSI) |
+//! | 10-11 | flags, see `flag` |
+//! | 12-13 | used today (gal, saturating) |
+//! | 14-15 | refilled today (gal, saturating) |
+//! | 16-19 | uptime (s) |
+//! | 20-21 | CRC-16/CCITT-FALSE of bytes 0-19 |
+//!
+//! Numbers are little endian. A version 1 decoder accepts longer bodies and
+//! skips the bytes it doesn't know, so fields can be added at the end without
+//! a version change; the version only goes up when a field changes meaning.
+
+use crate::alarms::Alarm;
+#[cfg(feature = "mqtt")]
+use crate::payload::WaterState;
+
+/// First byte of every frame
+pub const MAGIC: u8 = b'W';
+/// Format version written by `encode`
+pub const VERSION: u8 = 1;
+/// Magic, version and body length
+const HEADER_LEN: usize = 3;
+/// Body length of this version
+const BODY_LEN: usize = 17;
+const CRC_LEN: usize = 2;
+/// Encoded frame length
+pub const FRAME_LEN: usize = HEADER_LEN + BODY_LEN + CRC_LEN;
+
+/// Bits of the flags field
+mod flag {
+    pub const PUMP1: u16 = 1 << 0;
+    pub const PUMP2: u16 = 1 << 1;
+    pub const DRY_RUN: u16 = 1 << 2;
+    pub const LOW_LEVEL: u16 = 1 << 3;
+    pub const HIGH_PRESSURE: u16 = 1 << 4;
+    pub const SENSOR_FAULT: u16 = 1 << 5;
+    pub const MAINTENANCE: u16 = 1 << 6;
+    pub const HEATER: u16 = 1 << 7;
+    pub const PIPE_BURST: u16 = 1 << 8;
+    pub const FILTER_CLOGGED: u16 = 1 << 9;
+}
+
+/// Why a frame was rejected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodeError {
+    /// Shorter than its header says
+    Truncated,
+    /// Not a state frame
+    Magic,
+    /// Written by a newer format version
+    Version(u8),
+    /// Body shorter than this version needs
+    Length,
+    /// Corrupted on the way
+    Crc,
+}
+
+/// Live state of one controller
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StateFrame {
+    pub node: u8,
+    /// Counts up with each frame, so a receiver can tell lost frames
+    pub seq: u8,
+    pub capacity_percent: u8,
+    pub gallons: u16,
+    pub pressure_psi: u16,
+    pub pumps: [bool; 2],
+    pub dry_run: bool,
+    /// Active alarms, in `Alarm::ALL` order
+    pub alarms: [bool; Alarm::ALL.len()],
+    pub maintenance: bool,
+    pub heater_on: bool,
+    pub used_today: u16,
+    pub refilled_today: u16,
+    pub uptime_secs: u32,
+}
+
+impl StateFrame {
+    /// State frame of a published state document
+    #[cfg(feature = "mqtt")]
+    pub fn from_state(state: &WaterState, node: u8, seq: u8) -> Self {
+        Self {
+            node,
+            seq,
+            capacity_percent: state.capacity_percent,
+            gallons: state.capacity_gallons,
+            pressure_psi: state.pressure_psi,
+            pumps: [state.pumps[0].running, state.pumps[1].running],
+            dry_run: state.pump_dry_run,
+            alarms: [
+                state.alarm_low_level,
+                state.alarm_high_pressure,
+                state.alarm_sensor_fault,
+                state.alarm_pipe_burst,
+                state.alarm_filter_clogged,
+            ],
+            maintenance: false,
+            heater_on: state.heater_on,
+            used_today: state.used_today.min(u16::MAX as u32) as u16,
+            refilled_today: state.refilled_today.min(u16::MAX as u32) as u16,
+            uptime_secs: 0,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; FRAME_LEN] {
+        let mut flags = 0;
+        for (on, bit) in [
+            (self.pumps[0], flag::PUMP1),
+            (self.pumps[1], flag::PUMP2),
+            (self.dry_run, flag::DRY_RUN),
+            (self.alarms[0], flag::LOW_LEVEL),
+            (self.alarms[1], flag::HIGH_PRESSURE),
+            (self.alarms[2], flag::SENSOR_FAULT),
+            (self.alarms[3], flag::PIPE_BURST),
+            (self.alarms[4], flag::FILTER_CLOGGED),
+            (self.maintenance, flag::MAINTENANCE),
+            (self.heater_on, flag::HEATER),
+        ] {
+            if on {
+                flags |= bit;
+            }
+        }
+        let mut frame = [0u8; FRAME_LEN];
+        frame[..6].copy_from_slice(&[MAGIC, VERSION, BODY_LEN as u8, self.node, self.seq, self.capacity_percent]);
+        frame[6..8].copy_from_slice(&self.gallons.to_le_bytes());
+        frame[8..10].copy_from_slice(&self.pressure_psi.to_le_bytes());
+        frame[10..12].copy_from_slice(&flags.to_le_bytes());
+        frame[12..14].copy_from_slice(&self.used_today.to_le_bytes());
+        frame[14..16].copy_from_slice(&self.refilled_today.to_le_bytes());
+        frame[16..20].copy_from_slice(&self.uptime_secs.to_le_bytes());
+        let crc = crc16(&frame[..FRAME_LEN - CRC_LEN]);
+        frame[FRAME_LEN - CRC_LEN..].copy_from_slice(&crc.to_le_bytes());
+        frame
+    }
+
+    /// Decode a frame; bytes after it are ignored
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        if data.len() < HEADER_LEN {
+            return Err(DecodeError::Truncated);
+        }
+        if data[0] != MAGIC {
+            return Err(DecodeError::Magic);
+        }
+        if data[1] != VERSION {
+            return Err(DecodeError::Version(data[1]));
+        }
+        let body_len = data[2] as usize;
+        if body_len < BODY_LEN {
+            return Err(DecodeError::Length);
+        }
+        let len = HEADER_LEN + body_len;
+        if data.len() < len + CRC_LEN {
+            return Err(DecodeError::Truncated);
+        }
+        if crc16(&data[..len]) != u16::from_le_bytes([data[len], data[len + 1]]) {
+            return Err(DecodeError::Crc);
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let flags = u16_at(10);
+        let set = |bit: u16| flags & bit != 0;
+        Ok(Self {
+            node: data[3],
+            seq: data[4],
+            capacity_percent: data[5],
+            gallons: u16_at(6),
+            pressure_psi: u16_at(8),
+            pumps: [set(flag::PUMP1), set(flag::PUMP2)],
+            dry_run: set(flag::DRY_RUN),
+            alarms: [
+                set(flag::LOW_LEVEL),
+                set(flag::HIGH_PRESSURE),
+                set(flag::SENSOR_FAULT),
+                set(flag::PIPE_BURST),
+                set(flag::FILTER_CLOGGED),
+            ],
+            maintenance: set(flag::MAINTENANCE),
+            heater_on: set(flag::HEATER),
+            used_today: u16_at(12),
+            refilled_today: u16_at(14),
+            uptime_secs: u32::from_le_bytes([data[16], data[17], data[18], data[19]]),
+        })
+    }
+
+    /// Whether `alarm` is active
+    pub fn alarm(&self, alarm: Alarm) -> bool {
+        Alarm::ALL.iter().position(|a| *a == alarm).is_some_and(|i| self.alarms[i])
+    }
+
+    /// Any alarm or a dry-run fault
+    pub fn has_problem(&self) -> bool {
+        self.dry_run || self.alarms.contains(&true)
+    }
+}
+
+/// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF)
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> StateFrame {
+        StateFrame {
+            node: 3,
+            seq: 200,
+            capacity_percent: 72,
+            gallons: 1234,
+            pressure_psi: 58,
+            pumps: [true, false],
+            alarms: [false, true, false, false, true],
+            maintenance: true,
+            used_today: 310,
+            refilled_today: 65535,
+            uptime_secs: 86_400 * 40,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        let frame = sample();
+        let data = frame.encode();
+        assert_eq!(data.len(), 22);
+        assert_eq!(data[..6], [b'W', 1, 17, 3, 200, 72]);
+        assert_eq!(data[10..12], [0b0101_0001, 0b10]);
+        assert_eq!(StateFrame::decode(&data), Ok(frame));
+        assert!(frame.alarm(Alarm::HighPressure));
+        assert!(!frame.alarm(Alarm::LowLevel));
+        assert!(frame.alarm(Alarm::FilterClogged));
+        assert!(frame.has_problem());
+        assert!(!StateFrame::default().has_problem());
+
+        // Fields added later in version 1 are skipped by this decoder
+        let mut longer = data[..20].to_vec();
+        longer[2] = 19;
+        longer.extend_from_slice(&[0xAB, 0xCD]);
+        let crc = crc16(&longer);
+        longer.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(StateFrame::decode(&longer), Ok(frame));
+
+        #[cfg(feature = "mqtt")]
+        {
+            let mut state = WaterState { capacity_percent: 72, capacity_gallons: 1234, used_today: 70_000, ..Default::default() };
+            state.pumps[1].running = true;
+            let frame = StateFrame::from_state(&state, 3, 9);
+            assert_eq!((frame.node, frame.seq, frame.gallons, frame.used_today), (3, 9, 1234, u16::MAX));
+            assert_eq!(frame.pumps, [false, true]);
+        }
+    }
+
+    #[test]
+    fn test_rejects_damaged_frames() {
+        let data = sample().encode();
+        assert_eq!(StateFrame::decode(&data[..21]), Err(DecodeError::Truncated));
+        assert_eq!(StateFrame::decode(&data[..2]), Err(DecodeError::Truncated));
+        for i in 3..FRAME_LEN {
+            let mut damaged = data;
+            damaged[i] ^= 0x10;
+            assert_eq!(StateFrame::decode(&damaged), Err(DecodeError::Crc), "byte {}", i);
+        }
+        let mut other = data;
+        other[0] = 0x40;
+        assert_eq!(StateFrame::decode(&other), Err(DecodeError::Magic));
+        let mut newer = data;
+        newer[1] = 2;
+        assert_eq!(StateFrame::decode(&newer), Err(DecodeError::Version(2)));
+        let mut short = data;
+        short[2] = 12;
+        assert_eq!(StateFrame::decode(&short), Err(DecodeError::Length));
+    }
+}
@@ -2,15 +2,31 @@
 //!
 //! Stores configurable parameters that persist across reboots.
 //! Parameters can be updated via MQTT from Home Assistant.
+//!
+//! An NVS commit takes a few hundred milliseconds when a page has to be
+//! erased, so setters only update the value and queue the write; a
+//! low-priority thread stores it. Call `flush` before rebooting.
+//!
+//! `factory_reset` erases the whole `wc_config` namespace, so the next boot
+//! starts from the defaults.
 
+use std::sync::mpsc::{self, Sender, SyncSender};
+use std::time::Duration;
+
+use esp_idf_svc::hal::task::thread::ThreadSpawnConfiguration;
 use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsDefault};
+use esp_idf_svc::sys::{esp, EspError, ESP_ERR_INVALID_STATE, ESP_ERR_NO_MEM};
 use log::*;
 
+use crate::stats::DailyUsage;
+
 const NVS_NAMESPACE: &str = "wc_config";
 
 // NVS keys (max 15 chars)
 const KEY_TANK_CAPACITY: &str = "tank_cap";
 const KEY_SENSOR_HEIGHT: &str = "height_ft";
+const KEY_HEAD_MODE: &str = "head_mode";
+const KEY_PSI_RANGE: &str = "psi_range";
 const KEY_MAX_PSI: &str = "max_psi";
 const KEY_RADAR_HEIGHT: &str = "radar_ht_cm";
 const KEY_RADAR_DEADZONE: &str = "radar_dz_cm";
@@ -18,20 +34,288 @@ const KEY_MQTT_BROKER: &str = "mqtt_host";
 const KEY_MQTT_PORT: &str = "mqtt_port";
 const KEY_MQTT_USERNAME: &str = "mqtt_user";
 const KEY_MQTT_PASSWORD: &str = "mqtt_pass";
+const KEY_MQTT_TLS: &str = "mqtt_tls";
+const KEY_MQTT_READ_ONLY: &str = "mqtt_ro";
+const KEY_MQTT_CA_CERT: &str = "mqtt_ca";
+const KEY_MQTT_CLIENT_CERT: &str = "mqtt_cert";
+const KEY_MQTT_CLIENT_KEY: &str = "mqtt_key";
+const KEY_FLUSH_LINES: &str = "flush_lines";
+const KEY_DISPLAY_REFRESH: &str = "disp_refresh";
+const KEY_TANK_FILL: &str = "tank_fill";
+const KEY_ADMIN_TOKEN: &str = "admin_token";
+const KEY_REBOOT_DAY: &str = "reboot_day";
+const KEY_REBOOT_HOUR: &str = "reboot_hour";
+const KEY_TIMEZONE: &str = "tz";
+const KEY_PUMP_START: &str = "pump_start";
+const KEY_PUMP_STOP: &str = "pump_stop";
+const KEY_PUMP_ASSIST: &str = "pump_assist";
+const KEY_PUMP_FAIL_MIN: &str = "pump_fail_min";
+const KEY_VFD_SETPOINT: &str = "vfd_setpoint";
+const KEY_VFD_KP: &str = "vfd_kp";
+const KEY_VFD_KI: &str = "vfd_ki";
+const KEY_VFD_KD: &str = "vfd_kd";
+const KEY_HEATER_MODE: &str = "heater_mode";
+const KEY_HEATER_SPREAD: &str = "heater_spread";
+const KEY_HEATER_DUTY: &str = "heater_duty";
+const KEY_TANK_SHAPE: &str = "tank_shape";
+const KEY_RADAR_OFFSET: &str = "radar_offset";
+const KEY_RADAR_GAIN: &str = "radar_gain";
+const KEY_RADAR_TABLE: &str = "radar_table";
+const KEY_PSI_OFFSET: &str = "psi_offset";
+const KEY_PSI_GAIN: &str = "psi_gain";
+const KEY_PSI_TABLE: &str = "psi_table";
+const KEY_PSI_MIN_MV: &str = "psi_min_mv";
+const KEY_PSI_MAX_MV: &str = "psi_max_mv";
+const KEY_PSI_RATIO: &str = "psi_ratiometric";
+const KEY_PSI_SUPPLY: &str = "psi_supply_mv";
+const KEY_PSI2_MIN_MV: &str = "psi2_min_mv";
+const KEY_PROBE_NAMES: &str = "probe_names";
+const KEY_TANK_NAME: &str = "tank_name";
+const KEY_TANK_LABEL: &str = "tank_label";
+const KEY_WEBHOOK_URL: &str = "webhook_url";
+const KEY_NOTIFY_TEMPLATES: &str = "notify_tpl";
+const KEY_LOCKOUT_PIN: &str = "lockout_pin";
+const KEY_RADAR_WARMUP: &str = "radar_warmup";
+const KEY_PSI_WARMUP: &str = "psi_warmup";
+const KEY_LEVEL_MEDIAN: &str = "level_median";
+const KEY_LEVEL_ALPHA: &str = "level_alpha";
+const KEY_PAGE_INTERVAL: &str = "page_secs";
+const KEY_PUMP_MIN_ON: &str = "pump_min_on";
+const KEY_PUMP_MIN_OFF: &str = "pump_min_off";
+const KEY_PUMP_DRY_PSI: &str = "pump_dry_psi";
+const KEY_PUMP_DRY_SECS: &str = "pump_dry_secs";
+const KEY_VALVE_TRAVEL: &str = "valve_travel";
+const KEY_HAMMER_PSI: &str = "hammer_psi";
+const KEY_BURST_PSI: &str = "burst_psi";
+const KEY_EFFICIENCY_DROP: &str = "eff_drop";
+const KEY_CT_AMPS: &str = "ct_amps";
+const KEY_PUMP_VOLTS: &str = "pump_volts";
+const KEY_PUMP_PF: &str = "pump_pf";
+const KEY_ALARM_LOW: &str = "alarm_low";
+const KEY_ALARM_HIGH_PSI: &str = "alarm_high";
+const KEY_ALARM_CLOG_PSI: &str = "alarm_clog";
+const KEY_ALARM_FAULT_SECS: &str = "alarm_fault";
+const KEY_BUDGET: &str = "budget_gal";
+const KEY_USAGE_TODAY: &str = "usage_today";
+const KEY_FLOW_K: &str = "flow_k";
+const KEY_FLOW_TOTAL: &str = "flow_total";
+const KEY_CELL_APN: &str = "cell_apn";
+const KEY_CELL_BUDGET: &str = "cell_budget";
+const KEY_CELL_USAGE: &str = "cell_usage";
+const KEY_LORA_MODE: &str = "lora_mode";
+const KEY_LORA_NODE: &str = "lora_node";
+const KEY_LORA_FREQ: &str = "lora_freq";
+const KEY_LORA_INTERVAL: &str = "lora_interval";
+const KEY_WEB_USER: &str = "web_user";
+const KEY_WEB_LOGIN: &str = "web_login";
+const KEY_WELL_RISE: &str = "well_rise";
+const KEY_WIFI_SSID: &str = "wifi_ssid";
+const KEY_WIFI_PASS: &str = "wifi_pass";
+const KEY_DISCOVERY_REV: &str = "disc_rev";
+const KEY_INFLUX_URL: &str = "influx_url";
+const KEY_INFLUX_ORG: &str = "influx_org";
+const KEY_INFLUX_BUCKET: &str = "influx_bucket";
+const KEY_INFLUX_TOKEN: &str = "influx_token";
+const KEY_HA_NAME: &str = "ha_name";
+const KEY_HA_AREA: &str = "ha_area";
+const KEY_HA_PREFIX: &str = "ha_prefix";
+const KEY_SYSLOG_SERVER: &str = "syslog_server";
+const KEY_SYSLOG_LEVEL: &str = "syslog_level";
+const KEY_RUNBOOK_URL: &str = "runbook_url";
 
 // Defaults
 const DEFAULT_TANK_CAPACITY: u16 = 500;
 const DEFAULT_SENSOR_HEIGHT: u16 = 11;
+/// Sensor above the reference point, as before the setting existed
+const DEFAULT_HEAD_MODE: u16 = 1;
+/// The 100 PSI transducer the board was designed around
+const DEFAULT_PSI_RANGE: u16 = 100;
 const DEFAULT_MAX_PSI: u16 = 150;
+/// Transducer output at 0 PSI and at full scale (mV)
+const DEFAULT_PSI_MIN_MV: u16 = 500;
+const DEFAULT_PSI_MAX_MV: u16 = 4500;
+/// Transducer supply (mV)
+const DEFAULT_PSI_SUPPLY_MV: u16 = 5000;
 const DEFAULT_RADAR_HEIGHT: u16 = 200;
 const DEFAULT_RADAR_DEADZONE: u16 = 20;
 const DEFAULT_MQTT_PORT: u16 = 1883;
+const DEFAULT_FLUSH_LINES: u16 = 0;
+const DEFAULT_DISPLAY_REFRESH: u16 = 10;
+const DEFAULT_TANK_FILL: u16 = 0;
+const DEFAULT_REBOOT_DAY: u16 = 0;
+const DEFAULT_REBOOT_HOUR: u16 = 3;
+const DEFAULT_TIMEZONE: &str = "UTC0";
+const DEFAULT_WEB_USER: &str = "admin";
+const DEFAULT_TANK_NAME: &str = "Water tank";
+const DEFAULT_HA_NAME: &str = "Water Controller";
+/// Info and above, once a server is set
+const DEFAULT_SYSLOG_LEVEL: u16 = 3;
+const DEFAULT_PUMP_START: u16 = 30;
+const DEFAULT_PUMP_STOP: u16 = 90;
+const DEFAULT_PUMP_ASSIST: u16 = 10;
+const DEFAULT_PUMP_FAIL_MIN: u16 = 10;
+const DEFAULT_VFD_SETPOINT: u16 = 50;
+const DEFAULT_VFD_KP: u16 = 50;
+const DEFAULT_VFD_KI: u16 = 10;
+const DEFAULT_VFD_KD: u16 = 0;
+const DEFAULT_HEATER_MODE: u16 = 1;
+const DEFAULT_HEATER_SPREAD: u16 = 3;
+const DEFAULT_HEATER_DUTY: u16 = 25;
+const DEFAULT_TANK_SHAPE: u16 = 0;
+const DEFAULT_GAIN_MILLI: u16 = 1000;
+const DEFAULT_RADAR_WARMUP: u16 = 30;
+const DEFAULT_PSI_WARMUP: u16 = 5;
+const DEFAULT_LEVEL_MEDIAN: u16 = 5;
+const DEFAULT_LEVEL_ALPHA: u16 = 30;
+const DEFAULT_PAGE_INTERVAL: u16 = 15;
+const DEFAULT_PUMP_MIN_ON: u16 = 60;
+const DEFAULT_PUMP_MIN_OFF: u16 = 120;
+const DEFAULT_PUMP_DRY_PSI: u16 = 0;
+const DEFAULT_PUMP_DRY_SECS: u16 = 30;
+const DEFAULT_VALVE_TRAVEL: u16 = 30;
+const DEFAULT_HAMMER_PSI: u16 = 15;
+const DEFAULT_BURST_PSI: u16 = 20;
+const DEFAULT_EFFICIENCY_DROP: u16 = 20;
+/// SCT-013-030 clamp (A at 1 V)
+const DEFAULT_CT_AMPS: u16 = 30;
+const DEFAULT_PUMP_VOLTS: u16 = 240;
+const DEFAULT_PUMP_PF: u16 = 80;
+const DEFAULT_ALARM_LOW: u16 = 0;
+const DEFAULT_ALARM_HIGH_PSI: u16 = 0;
+const DEFAULT_ALARM_CLOG_PSI: u16 = 10;
+const DEFAULT_ALARM_FAULT_SECS: u16 = 60;
+const DEFAULT_BUDGET: u16 = 0;
+/// YF-S201 flow sensor (pulses per gallon)
+const DEFAULT_FLOW_K: u16 = 1703;
+const DEFAULT_CELL_BUDGET: u16 = 0;
+const DEFAULT_LORA_MODE: u16 = 0;
+const DEFAULT_LORA_NODE: u16 = 1;
+const DEFAULT_LORA_FREQ: u16 = 9150;
+const DEFAULT_LORA_INTERVAL: u16 = 60;
+const DEFAULT_WELL_RISE: u16 = 3;
+
+/// Longest stored PEM certificate or key (NVS strings hold up to 4000 bytes)
+pub const MAX_PEM_LEN: usize = 3999;
+/// Longest tank label, as much as fits above the tank in the label font
+pub const MAX_TANK_LABEL_LEN: usize = 16;
+
+/// Writes queued before a setter waits for the writer thread
+const WRITE_QUEUE_LEN: usize = 16;
+/// Writer thread priority: below the other threads (5), level with the main task
+const WRITER_PRIORITY: u8 = 1;
+/// Longest `flush` waits for the queue to drain
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A queued NVS write
+enum Write {
+    U16(&'static str, u16),
+    I16(&'static str, i16),
+    Str(&'static str, String),
+    /// Answered once everything queued before it is stored
+    Flush(Sender<()>),
+    /// Erase the namespace, answered with the result
+    Erase(Sender<Result<(), EspError>>),
+}
+
+/// Queue to the NVS writer thread
+///
+/// The queue is bounded, so a burst of settings (a bulk configuration, the
+/// web form) waits for the writer only once it is full.
+struct Writer {
+    tx: SyncSender<Write>,
+}
+
+impl Writer {
+    fn start(mut nvs: EspNvs<NvsDefault>) -> Result<Self, EspError> {
+        let (tx, rx) = mpsc::sync_channel::<Write>(WRITE_QUEUE_LEN);
+        ThreadSpawnConfiguration {
+            priority: WRITER_PRIORITY,
+            ..Default::default()
+        }
+        .set()?;
+        let spawned = std::thread::Builder::new()
+            .name("nvs".into())
+            .stack_size(4 * 1024)
+            .spawn(move || {
+                for write in rx {
+                    let (key, result) = match write {
+                        Write::U16(key, value) => (key, nvs.set_u16(key, value)),
+                        Write::I16(key, value) => (key, nvs.set_i16(key, value)),
+                        Write::Str(key, value) => (key, nvs.set_str(key, &value)),
+                        Write::Flush(done) => {
+                            let _ = done.send(());
+                            continue;
+                        }
+                        Write::Erase(done) => {
+                            let _ = done.send(erase_namespace());
+                            continue;
+                        }
+                    };
+                    if let Err(e) = result {
+                        warn!("Config: storing {} failed: {:?}", key, e);
+                    }
+                }
+            });
+        // Later threads get the default priority again
+        ThreadSpawnConfiguration::default().set()?;
+        spawned.map_err(|_| EspError::from_infallible::<ESP_ERR_NO_MEM>())?;
+        Ok(Self { tx })
+    }
+
+    fn set_u16(&self, key: &'static str, value: u16) -> Result<(), EspError> {
+        self.queue(Write::U16(key, value))
+    }
+
+    fn set_i16(&self, key: &'static str, value: i16) -> Result<(), EspError> {
+        self.queue(Write::I16(key, value))
+    }
+
+    fn set_str(&self, key: &'static str, value: &str) -> Result<(), EspError> {
+        self.queue(Write::Str(key, value.to_string()))
+    }
+
+    fn queue(&self, write: Write) -> Result<(), EspError> {
+        self.tx.send(write).map_err(|_| EspError::from_infallible::<ESP_ERR_INVALID_STATE>())
+    }
+}
+
+/// Erase every key in the config namespace
+///
+/// `EspNvs` has no erase-all, so this goes through a second handle.
+fn erase_namespace() -> Result<(), EspError> {
+    use esp_idf_svc::sys::{nvs_close, nvs_commit, nvs_erase_all, nvs_open, nvs_open_mode_t_NVS_READWRITE};
+
+    let namespace = std::ffi::CString::new(NVS_NAMESPACE).unwrap();
+    let mut handle = 0;
+    esp!(unsafe { nvs_open(namespace.as_ptr(), nvs_open_mode_t_NVS_READWRITE, &mut handle) })?;
+    let result = esp!(unsafe { nvs_erase_all(handle) }).and_then(|_| esp!(unsafe { nvs_commit(handle) }));
+    unsafe { nvs_close(handle) };
+    result
+}
 
 /// Persistent configuration
 pub struct Config {
-    nvs: EspNvs<NvsDefault>,
+    writer: Writer,
     pub tank_capacity_gallons: u16,
+    /// Pressure sensor height difference to the reference point (ft)
     pub sensor_height_feet: u16,
+    /// Hydrostatic head compensation (0 = off, 1 = sensor above the
+    /// reference point, 2 = below, see `correction::HeadMode`)
+    pub head_mode: u16,
+    /// Pressure transducer full scale, the PSI it reads at `psi_max_mv`
+    pub psi_range: u16,
+    /// Transducer output at 0 PSI (mV, at 5 V supply if ratiometric), moved
+    /// by `PressureSensor::calibrate_zero`
+    pub psi_min_mv: u16,
+    /// Transducer output at full scale (mV, at 5 V supply if ratiometric)
+    pub psi_max_mv: u16,
+    /// Post-filter transducer output at 0 PSI (mV, at 5 V supply if
+    /// ratiometric); the same part as the line transducer otherwise
+    pub psi2_min_mv: u16,
+    /// Transducer output proportional to its supply (0 = no, 1 = yes)
+    pub psi_ratiometric: u16,
+    /// Transducer supply, for ratiometric transducers (mV)
+    pub psi_supply_mv: u16,
     pub max_psi: u16,
     pub radar_height_cm: u16,
     pub radar_deadzone_cm: u16,
@@ -39,6 +323,169 @@ pub struct Config {
     pub mqtt_port: u16,
     pub mqtt_username: String,
     pub mqtt_password: String,
+    /// Broker connection scheme (0 = `mqtt://`, 1 = `mqtts://`)
+    pub mqtt_tls: u16,
+    /// Publish only: no command subscriptions or controllable HA entities (0 = off, 1 = on)
+    pub mqtt_read_only: u16,
+    /// CA certificate (PEM) for TLS (empty = certificate built in or ESP-IDF bundle)
+    pub mqtt_ca_cert: String,
+    /// Client certificate and private key (PEM) for TLS (empty = none)
+    pub mqtt_client_cert: String,
+    pub mqtt_client_key: String,
+    /// Max display lines sent per flush (0 = unlimited)
+    pub display_flush_lines: u16,
+    /// Minutes between full display refreshes against ghosting (0 = off)
+    pub display_refresh_minutes: u16,
+    /// Tank water fill pattern (0 = solid, 1 = hatched, 2 = dithered)
+    pub tank_fill_pattern: u16,
+    /// Web admin token (empty = web config unprotected)
+    pub admin_token: String,
+    /// Web login user name, with the admin token as its password
+    pub web_user: String,
+    /// Login required for the status pages too (0 = open to the LAN, 1 = on)
+    pub web_login: u16,
+    /// Maintenance reboot day (0 = disabled, 1-7 = Monday-Sunday, 8 = daily)
+    pub reboot_day: u16,
+    /// Maintenance reboot local hour (0-23)
+    pub reboot_hour: u16,
+    /// POSIX TZ string for local time
+    pub timezone: String,
+    /// Pump start level (%)
+    pub pump_start_percent: u16,
+    /// Pump stop level (%)
+    pub pump_stop_percent: u16,
+    /// Lag pump assist drop below the start level (%)
+    pub pump_assist_drop_percent: u16,
+    /// Minutes without level rise before a pump is flagged failed
+    pub pump_fail_minutes: u16,
+    /// Constant-pressure setpoint (PSI)
+    pub vfd_setpoint_psi: u16,
+    /// PID gains in thousandths (speed fraction per PSI, per PSI·s, per PSI/s)
+    pub vfd_kp_milli: u16,
+    pub vfd_ki_milli: u16,
+    pub vfd_kd_milli: u16,
+    /// Radar heater mode (0 = off, 1 = auto, 2 = duty cycle)
+    pub heater_mode: u16,
+    /// Heat below this dew-point spread (°C)
+    pub heater_spread_c: u16,
+    /// Heater duty cycle (%), also the auto-mode fallback
+    pub heater_duty_percent: u16,
+    /// Tank shape (0 = vertical, 1 = horizontal cylinder)
+    pub tank_shape: u16,
+    /// Radar empty-height correction offset (mm)
+    pub radar_offset_mm: i16,
+    /// Radar empty-height correction gain (thousandths)
+    pub radar_gain_milli: u16,
+    /// Radar calibration table (`raw:actual,...` in mm, empty = none)
+    pub radar_table: String,
+    /// Pressure correction offset (hundredths of a PSI)
+    pub pressure_offset_centi: i16,
+    /// Pressure correction gain (thousandths)
+    pub pressure_gain_milli: u16,
+    /// Pressure calibration table (`raw:actual,...` in PSI, empty = none)
+    pub pressure_table: String,
+    /// DS18B20 probe names (`ROM=name;...`, see `ds18b20`)
+    pub probe_names: String,
+    /// Tank name used in notifications
+    pub tank_name: String,
+    /// Short name drawn above the tank and put before its Home Assistant
+    /// entity names (empty = none)
+    pub tank_label: String,
+    /// URL notifications are POSTed to (empty = none)
+    pub webhook_url: String,
+    /// Notification message templates (`event=template` lines, see `notify`)
+    pub notify_templates: String,
+    /// PIN of the engaged lockout (empty = released, see `lockout`)
+    pub lockout_pin: String,
+    /// Radar warm-up after boot or sensor recovery (seconds)
+    pub radar_warmup_secs: u16,
+    /// Pressure sensor warm-up after boot or sensor recovery (seconds)
+    pub pressure_warmup_secs: u16,
+    /// Radar readings in the level median filter (1 = off)
+    pub level_median_window: u16,
+    /// Level smoothing factor (weight of each new reading, %; 100 = off)
+    pub level_smoothing_percent: u16,
+    /// Seconds on each display page before advancing (0 = button only)
+    pub page_interval_secs: u16,
+    /// Shortest pump run before the stop level is obeyed (s)
+    pub pump_min_on_secs: u16,
+    /// Shortest pause between pump cycles (s)
+    pub pump_min_off_secs: u16,
+    /// Pressure below which a running pump is running dry (PSI, 0 = disabled)
+    pub pump_dry_psi: u16,
+    /// How long low pressure is tolerated before the dry-run guard trips (s)
+    pub pump_dry_secs: u16,
+    /// Supply valve full travel time (seconds)
+    pub valve_travel_secs: u16,
+    /// Rise above line pressure recorded as water hammer (PSI)
+    pub hammer_psi: u16,
+    /// Pressure collapse within two seconds, pumps off, taken as a pipe burst (PSI, 0 = off)
+    pub burst_psi: u16,
+    /// Drop of a pump's weekly delivery rate below its baseline that raises an alert (%)
+    pub efficiency_drop_percent: u16,
+    /// Pump CT clamp current at 1 V RMS output (A)
+    pub ct_amps: u16,
+    /// Pump supply voltage (V RMS)
+    pub pump_volts: u16,
+    /// Pump power factor (%)
+    pub pump_pf_percent: u16,
+    /// Tank level that raises the low-level alarm and notification (%, 0 = off)
+    pub alarm_low_percent: u16,
+    /// Line pressure that raises the high-pressure alarm (PSI, 0 = off)
+    pub alarm_high_psi: u16,
+    /// Pressure lost across the whole-house filter that raises the clogged-filter alarm (PSI, 0 = off)
+    pub alarm_clog_psi: u16,
+    /// How long a sensor may fail to answer before it raises a fault (s)
+    pub alarm_fault_secs: u16,
+    /// Daily water budget the day's consumption is measured against (gal, 0 = none)
+    pub budget_gallons: u16,
+    /// Local day and usage totals saved for a restart (see `stats`)
+    pub saved_usage: Option<(i64, DailyUsage)>,
+    /// Flow meter K-factor (pulses per gallon)
+    pub flow_k_factor: u16,
+    /// Flow meter total saved for a restart (gal)
+    pub flow_total_gallons: f64,
+    /// Cellular access point name (empty = let the network choose)
+    pub cell_apn: String,
+    /// Cellular data budget per month (MB, 0 = unlimited)
+    pub cell_budget_mb: u16,
+    /// Month (see `cellular::month_progress`) and bytes of the cellular usage count
+    pub cell_usage_month: u16,
+    pub cell_usage_bytes: u64,
+    /// LoRa radio role (0 = off, 1 = node, 2 = gateway)
+    pub lora_mode: u16,
+    /// LoRa node id sent in each frame (1-254)
+    pub lora_node: u16,
+    /// LoRa carrier frequency (100 kHz steps, e.g. 9150 = 915.0 MHz)
+    pub lora_freq: u16,
+    /// Seconds between frames sent by a LoRa node
+    pub lora_interval_secs: u16,
+    /// Pressure rise over 15 s taken as the well pump cutting in (PSI)
+    pub well_rise_psi: u16,
+    /// WiFi network joined when Ethernet has no link (empty = setup portal)
+    pub wifi_ssid: String,
+    pub wifi_pass: String,
+    /// Home Assistant discovery revision whose retired entities were cleared
+    /// (see `homeassistant::DISCOVERY_REVISION`)
+    pub discovery_rev: u16,
+    /// InfluxDB server samples are written to (empty = none, see `influx`)
+    pub influx_url: String,
+    pub influx_org: String,
+    pub influx_bucket: String,
+    /// InfluxDB API token with write access to the bucket
+    pub influx_token: String,
+    /// Home Assistant device name
+    pub ha_device_name: String,
+    /// Area suggested to Home Assistant for the device (empty = none)
+    pub ha_area: String,
+    /// Put in front of every entity name (empty = none)
+    pub ha_prefix: String,
+    /// Syslog server, `host` or `host:port` (empty = none, see `logging`)
+    pub syslog_server: String,
+    /// Least severe level sent to syslog (0 = off, 1 = error ... 5 = trace)
+    pub syslog_level: u16,
+    /// Troubleshooting page linked from the alarm QR code (empty = none, see `alarms::runbook_link`)
+    pub runbook_url: String,
 }
 
 impl Config {
@@ -54,6 +501,17 @@ impl Config {
         let sensor_height_feet = nvs
             .get_u16(KEY_SENSOR_HEIGHT)?
             .unwrap_or(DEFAULT_SENSOR_HEIGHT);
+        let head_mode = nvs
+            .get_u16(KEY_HEAD_MODE)?
+            .unwrap_or(DEFAULT_HEAD_MODE);
+        let psi_range = nvs
+            .get_u16(KEY_PSI_RANGE)?
+            .unwrap_or(DEFAULT_PSI_RANGE);
+        let psi_min_mv = nvs.get_u16(KEY_PSI_MIN_MV)?.unwrap_or(DEFAULT_PSI_MIN_MV);
+        let psi_max_mv = nvs.get_u16(KEY_PSI_MAX_MV)?.unwrap_or(DEFAULT_PSI_MAX_MV);
+        let psi2_min_mv = nvs.get_u16(KEY_PSI2_MIN_MV)?.unwrap_or(DEFAULT_PSI_MIN_MV);
+        let psi_ratiometric = nvs.get_u16(KEY_PSI_RATIO)?.unwrap_or(0);
+        let psi_supply_mv = nvs.get_u16(KEY_PSI_SUPPLY)?.unwrap_or(DEFAULT_PSI_SUPPLY_MV);
         let max_psi = nvs.get_u16(KEY_MAX_PSI)?.unwrap_or(DEFAULT_MAX_PSI);
         let radar_height_cm = nvs
             .get_u16(KEY_RADAR_HEIGHT)?
@@ -71,6 +529,231 @@ impl Config {
             .unwrap_or("").to_string();
         let mqtt_password = nvs.get_str(KEY_MQTT_PASSWORD, &mut buf)?
             .unwrap_or("").to_string();
+        let mqtt_tls = nvs.get_u16(KEY_MQTT_TLS)?.unwrap_or(0);
+        let mqtt_read_only = nvs.get_u16(KEY_MQTT_READ_ONLY)?.unwrap_or(0);
+        // PEM files are too large for the stack
+        let mut pem_buf = vec![0u8; MAX_PEM_LEN + 1];
+        let mqtt_ca_cert = nvs.get_str(KEY_MQTT_CA_CERT, &mut pem_buf)?
+            .unwrap_or("").to_string();
+        let mqtt_client_cert = nvs.get_str(KEY_MQTT_CLIENT_CERT, &mut pem_buf)?
+            .unwrap_or("").to_string();
+        let mqtt_client_key = nvs.get_str(KEY_MQTT_CLIENT_KEY, &mut pem_buf)?
+            .unwrap_or("").to_string();
+        let admin_token = nvs.get_str(KEY_ADMIN_TOKEN, &mut buf)?
+            .unwrap_or("").to_string();
+        let web_user = nvs.get_str(KEY_WEB_USER, &mut buf)?
+            .unwrap_or(DEFAULT_WEB_USER).to_string();
+        let web_login = nvs.get_u16(KEY_WEB_LOGIN)?.unwrap_or(0);
+        let well_rise_psi = nvs
+            .get_u16(KEY_WELL_RISE)?
+            .unwrap_or(DEFAULT_WELL_RISE);
+        let wifi_ssid = nvs.get_str(KEY_WIFI_SSID, &mut buf)?
+            .unwrap_or("").to_string();
+        let wifi_pass = nvs.get_str(KEY_WIFI_PASS, &mut buf)?
+            .unwrap_or("").to_string();
+        let discovery_rev = nvs.get_u16(KEY_DISCOVERY_REV)?.unwrap_or(0);
+        let ha_device_name = nvs.get_str(KEY_HA_NAME, &mut buf)?
+            .unwrap_or(DEFAULT_HA_NAME).to_string();
+        let ha_area = nvs.get_str(KEY_HA_AREA, &mut buf)?
+            .unwrap_or("").to_string();
+        let ha_prefix = nvs.get_str(KEY_HA_PREFIX, &mut buf)?
+            .unwrap_or("").to_string();
+        let syslog_server = nvs.get_str(KEY_SYSLOG_SERVER, &mut buf)?
+            .unwrap_or("").to_string();
+        let syslog_level = nvs
+            .get_u16(KEY_SYSLOG_LEVEL)?
+            .unwrap_or(DEFAULT_SYSLOG_LEVEL);
+        let timezone = nvs.get_str(KEY_TIMEZONE, &mut buf)?
+            .unwrap_or(DEFAULT_TIMEZONE).to_string();
+        let display_flush_lines = nvs
+            .get_u16(KEY_FLUSH_LINES)?
+            .unwrap_or(DEFAULT_FLUSH_LINES);
+        let display_refresh_minutes = nvs
+            .get_u16(KEY_DISPLAY_REFRESH)?
+            .unwrap_or(DEFAULT_DISPLAY_REFRESH);
+        let tank_fill_pattern = nvs
+            .get_u16(KEY_TANK_FILL)?
+            .unwrap_or(DEFAULT_TANK_FILL);
+        let pump_start_percent = nvs
+            .get_u16(KEY_PUMP_START)?
+            .unwrap_or(DEFAULT_PUMP_START);
+        let pump_stop_percent = nvs
+            .get_u16(KEY_PUMP_STOP)?
+            .unwrap_or(DEFAULT_PUMP_STOP);
+        let pump_assist_drop_percent = nvs
+            .get_u16(KEY_PUMP_ASSIST)?
+            .unwrap_or(DEFAULT_PUMP_ASSIST);
+        let pump_fail_minutes = nvs
+            .get_u16(KEY_PUMP_FAIL_MIN)?
+            .unwrap_or(DEFAULT_PUMP_FAIL_MIN);
+        let vfd_setpoint_psi = nvs
+            .get_u16(KEY_VFD_SETPOINT)?
+            .unwrap_or(DEFAULT_VFD_SETPOINT);
+        let vfd_kp_milli = nvs.get_u16(KEY_VFD_KP)?.unwrap_or(DEFAULT_VFD_KP);
+        let vfd_ki_milli = nvs.get_u16(KEY_VFD_KI)?.unwrap_or(DEFAULT_VFD_KI);
+        let vfd_kd_milli = nvs.get_u16(KEY_VFD_KD)?.unwrap_or(DEFAULT_VFD_KD);
+        let heater_mode = nvs
+            .get_u16(KEY_HEATER_MODE)?
+            .unwrap_or(DEFAULT_HEATER_MODE);
+        let heater_spread_c = nvs
+            .get_u16(KEY_HEATER_SPREAD)?
+            .unwrap_or(DEFAULT_HEATER_SPREAD);
+        let heater_duty_percent = nvs
+            .get_u16(KEY_HEATER_DUTY)?
+            .unwrap_or(DEFAULT_HEATER_DUTY);
+        let radar_offset_mm = nvs.get_i16(KEY_RADAR_OFFSET)?.unwrap_or(0);
+        let radar_gain_milli = nvs
+            .get_u16(KEY_RADAR_GAIN)?
+            .unwrap_or(DEFAULT_GAIN_MILLI);
+        let radar_table = nvs.get_str(KEY_RADAR_TABLE, &mut buf)?
+            .unwrap_or("").to_string();
+        let pressure_offset_centi = nvs.get_i16(KEY_PSI_OFFSET)?.unwrap_or(0);
+        let pressure_gain_milli = nvs
+            .get_u16(KEY_PSI_GAIN)?
+            .unwrap_or(DEFAULT_GAIN_MILLI);
+        let pressure_table = nvs.get_str(KEY_PSI_TABLE, &mut buf)?
+            .unwrap_or("").to_string();
+        // Up to 8 names of 32 characters don't fit the shared buffer
+        let mut names_buf = [0u8; 512];
+        let probe_names = nvs.get_str(KEY_PROBE_NAMES, &mut names_buf)?
+            .unwrap_or("").to_string();
+        let tank_name = nvs.get_str(KEY_TANK_NAME, &mut buf)?
+            .unwrap_or(DEFAULT_TANK_NAME).to_string();
+        let tank_label = nvs.get_str(KEY_TANK_LABEL, &mut buf)?
+            .unwrap_or("").to_string();
+        let mut url_buf = [0u8; 256];
+        let webhook_url = nvs.get_str(KEY_WEBHOOK_URL, &mut url_buf)?
+            .unwrap_or("").to_string();
+        let influx_url = nvs.get_str(KEY_INFLUX_URL, &mut url_buf)?
+            .unwrap_or("").to_string();
+        let runbook_url = nvs.get_str(KEY_RUNBOOK_URL, &mut url_buf)?
+            .unwrap_or("").to_string();
+        let influx_org = nvs.get_str(KEY_INFLUX_ORG, &mut buf)?
+            .unwrap_or("").to_string();
+        let influx_bucket = nvs.get_str(KEY_INFLUX_BUCKET, &mut buf)?
+            .unwrap_or("").to_string();
+        let influx_token = nvs.get_str(KEY_INFLUX_TOKEN, &mut buf)?
+            .unwrap_or("").to_string();
+        // One template of up to 160 bytes per event
+        let mut templates_buf = [0u8; 1024];
+        let notify_templates = nvs.get_str(KEY_NOTIFY_TEMPLATES, &mut templates_buf)?
+            .unwrap_or("").to_string();
+        let lockout_pin = nvs.get_str(KEY_LOCKOUT_PIN, &mut buf)?
+            .unwrap_or("").to_string();
+        let tank_shape = nvs
+            .get_u16(KEY_TANK_SHAPE)?
+            .unwrap_or(DEFAULT_TANK_SHAPE);
+        let radar_warmup_secs = nvs
+            .get_u16(KEY_RADAR_WARMUP)?
+            .unwrap_or(DEFAULT_RADAR_WARMUP);
+        let pressure_warmup_secs = nvs
+            .get_u16(KEY_PSI_WARMUP)?
+            .unwrap_or(DEFAULT_PSI_WARMUP);
+        let reboot_day = nvs
+            .get_u16(KEY_REBOOT_DAY)?
+            .unwrap_or(DEFAULT_REBOOT_DAY);
+        let reboot_hour = nvs
+            .get_u16(KEY_REBOOT_HOUR)?
+            .unwrap_or(DEFAULT_REBOOT_HOUR);
+
+        let level_median_window = nvs
+            .get_u16(KEY_LEVEL_MEDIAN)?
+            .unwrap_or(DEFAULT_LEVEL_MEDIAN);
+
+        let level_smoothing_percent = nvs
+            .get_u16(KEY_LEVEL_ALPHA)?
+            .unwrap_or(DEFAULT_LEVEL_ALPHA);
+
+        let page_interval_secs = nvs
+            .get_u16(KEY_PAGE_INTERVAL)?
+            .unwrap_or(DEFAULT_PAGE_INTERVAL);
+
+        let pump_min_on_secs = nvs
+            .get_u16(KEY_PUMP_MIN_ON)?
+            .unwrap_or(DEFAULT_PUMP_MIN_ON);
+
+        let pump_min_off_secs = nvs
+            .get_u16(KEY_PUMP_MIN_OFF)?
+            .unwrap_or(DEFAULT_PUMP_MIN_OFF);
+
+        let pump_dry_psi = nvs
+            .get_u16(KEY_PUMP_DRY_PSI)?
+            .unwrap_or(DEFAULT_PUMP_DRY_PSI);
+
+        let pump_dry_secs = nvs
+            .get_u16(KEY_PUMP_DRY_SECS)?
+            .unwrap_or(DEFAULT_PUMP_DRY_SECS);
+
+        let valve_travel_secs = nvs
+            .get_u16(KEY_VALVE_TRAVEL)?
+            .unwrap_or(DEFAULT_VALVE_TRAVEL);
+
+        let hammer_psi = nvs
+            .get_u16(KEY_HAMMER_PSI)?
+            .unwrap_or(DEFAULT_HAMMER_PSI);
+
+        let burst_psi = nvs
+            .get_u16(KEY_BURST_PSI)?
+            .unwrap_or(DEFAULT_BURST_PSI);
+
+        let efficiency_drop_percent = nvs
+            .get_u16(KEY_EFFICIENCY_DROP)?
+            .unwrap_or(DEFAULT_EFFICIENCY_DROP);
+        let ct_amps = nvs.get_u16(KEY_CT_AMPS)?.unwrap_or(DEFAULT_CT_AMPS);
+        let pump_volts = nvs.get_u16(KEY_PUMP_VOLTS)?.unwrap_or(DEFAULT_PUMP_VOLTS);
+        let pump_pf_percent = nvs.get_u16(KEY_PUMP_PF)?.unwrap_or(DEFAULT_PUMP_PF);
+
+        let alarm_low_percent = nvs
+            .get_u16(KEY_ALARM_LOW)?
+            .unwrap_or(DEFAULT_ALARM_LOW);
+
+        let alarm_high_psi = nvs
+            .get_u16(KEY_ALARM_HIGH_PSI)?
+            .unwrap_or(DEFAULT_ALARM_HIGH_PSI);
+
+        let alarm_clog_psi = nvs
+            .get_u16(KEY_ALARM_CLOG_PSI)?
+            .unwrap_or(DEFAULT_ALARM_CLOG_PSI);
+
+        let alarm_fault_secs = nvs
+            .get_u16(KEY_ALARM_FAULT_SECS)?
+            .unwrap_or(DEFAULT_ALARM_FAULT_SECS);
+
+        let budget_gallons = nvs
+            .get_u16(KEY_BUDGET)?
+            .unwrap_or(DEFAULT_BUDGET);
+        let saved_usage = nvs.get_str(KEY_USAGE_TODAY, &mut buf)?
+            .and_then(DailyUsage::parse_saved);
+        let flow_k_factor = nvs.get_u16(KEY_FLOW_K)?.unwrap_or(DEFAULT_FLOW_K);
+        let flow_total_gallons = nvs.get_str(KEY_FLOW_TOTAL, &mut buf)?
+            .and_then(|total| total.parse().ok())
+            .unwrap_or(0.0);
+
+        let cell_apn = nvs.get_str(KEY_CELL_APN, &mut buf)?
+            .unwrap_or("").to_string();
+        let cell_budget_mb = nvs
+            .get_u16(KEY_CELL_BUDGET)?
+            .unwrap_or(DEFAULT_CELL_BUDGET);
+        // Stored as `month:bytes`, no 64-bit NVS writes are queued
+        let (cell_usage_month, cell_usage_bytes) = nvs.get_str(KEY_CELL_USAGE, &mut buf)?
+            .and_then(|usage| {
+                let (month, bytes) = usage.split_once(':')?;
+                Some((month.parse().ok()?, bytes.parse().ok()?))
+            })
+            .unwrap_or((0, 0));
+
+        let lora_mode = nvs
+            .get_u16(KEY_LORA_MODE)?
+            .unwrap_or(DEFAULT_LORA_MODE);
+        let lora_node = nvs
+            .get_u16(KEY_LORA_NODE)?
+            .unwrap_or(DEFAULT_LORA_NODE);
+        let lora_freq = nvs
+            .get_u16(KEY_LORA_FREQ)?
+            .unwrap_or(DEFAULT_LORA_FREQ);
+        let lora_interval_secs = nvs
+            .get_u16(KEY_LORA_INTERVAL)?
+            .unwrap_or(DEFAULT_LORA_INTERVAL);
 
         info!(
             "Config loaded: tank={}gal, height={}ft, max_psi={}, radar={}cm, deadzone={}cm",
@@ -81,11 +764,21 @@ impl Config {
         } else {
             info!("MQTT: {}@{}:{}", mqtt_username, mqtt_broker, mqtt_port);
         }
+        if admin_token.is_empty() {
+            warn!("Web: no admin token set, config page is unprotected");
+        }
 
         Ok(Self {
-            nvs,
+            writer: Writer::start(nvs)?,
             tank_capacity_gallons,
             sensor_height_feet,
+            head_mode,
+            psi_range,
+            psi_min_mv,
+            psi_max_mv,
+            psi2_min_mv,
+            psi_ratiometric,
+            psi_supply_mv,
             max_psi,
             radar_height_cm,
             radar_deadzone_cm,
@@ -93,6 +786,90 @@ impl Config {
             mqtt_port,
             mqtt_username,
             mqtt_password,
+            mqtt_tls,
+            mqtt_read_only,
+            mqtt_ca_cert,
+            mqtt_client_cert,
+            mqtt_client_key,
+            display_flush_lines,
+            display_refresh_minutes,
+            tank_fill_pattern,
+            admin_token,
+            web_user,
+            web_login,
+            reboot_day,
+            reboot_hour,
+            timezone,
+            pump_start_percent,
+            pump_stop_percent,
+            pump_assist_drop_percent,
+            pump_fail_minutes,
+            vfd_setpoint_psi,
+            vfd_kp_milli,
+            vfd_ki_milli,
+            vfd_kd_milli,
+            heater_mode,
+            heater_spread_c,
+            heater_duty_percent,
+            tank_shape,
+            radar_offset_mm,
+            radar_gain_milli,
+            radar_table,
+            pressure_offset_centi,
+            pressure_gain_milli,
+            pressure_table,
+            probe_names,
+            tank_name,
+            tank_label,
+            webhook_url,
+            notify_templates,
+            lockout_pin,
+            radar_warmup_secs,
+            pressure_warmup_secs,
+            level_median_window,
+            level_smoothing_percent,
+            page_interval_secs,
+            pump_min_on_secs,
+            pump_min_off_secs,
+            pump_dry_psi,
+            pump_dry_secs,
+            valve_travel_secs,
+            hammer_psi,
+            burst_psi,
+            efficiency_drop_percent,
+            ct_amps,
+            pump_volts,
+            pump_pf_percent,
+            alarm_low_percent,
+            alarm_high_psi,
+            alarm_clog_psi,
+            alarm_fault_secs,
+            budget_gallons,
+            saved_usage,
+            flow_k_factor,
+            flow_total_gallons,
+            cell_apn,
+            cell_budget_mb,
+            cell_usage_month,
+            cell_usage_bytes,
+            lora_mode,
+            lora_node,
+            lora_freq,
+            lora_interval_secs,
+            well_rise_psi,
+            wifi_ssid,
+            wifi_pass,
+            discovery_rev,
+            influx_url,
+            influx_org,
+            influx_bucket,
+            influx_token,
+            ha_device_name,
+            ha_area,
+            ha_prefix,
+            syslog_server,
+            syslog_level,
+            runbook_url,
         })
     }
 
@@ -103,7 +880,7 @@ impl Config {
     ) -> Result<(), esp_idf_svc::sys::EspError> {
         let gallons = gallons.clamp(100, 2000);
         self.tank_capacity_gallons = gallons;
-        self.nvs.set_u16(KEY_TANK_CAPACITY, gallons)?;
+        self.writer.set_u16(KEY_TANK_CAPACITY, gallons)?;
         info!("Config: tank capacity = {} gal", gallons);
         Ok(())
     }
@@ -115,11 +892,98 @@ impl Config {
     ) -> Result<(), esp_idf_svc::sys::EspError> {
         let feet = feet.clamp(0, 50);
         self.sensor_height_feet = feet;
-        self.nvs.set_u16(KEY_SENSOR_HEIGHT, feet)?;
+        self.writer.set_u16(KEY_SENSOR_HEIGHT, feet)?;
         info!("Config: sensor height = {} ft", feet);
         Ok(())
     }
 
+    /// Set the hydrostatic head compensation (0 = off, 1 = sensor above the
+    /// reference point, 2 = below) and persist to NVS
+    pub fn set_head_mode(
+        &mut self,
+        mode: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let mode = mode.clamp(0, 2);
+        self.head_mode = mode;
+        self.writer.set_u16(KEY_HEAD_MODE, mode)?;
+        info!("Config: head compensation mode = {}", mode);
+        Ok(())
+    }
+
+    /// Set the pressure transducer full scale (PSI at `psi_max_mv`) and persist to NVS
+    pub fn set_psi_range(
+        &mut self,
+        psi: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let psi = psi.clamp(30, 300);
+        self.psi_range = psi;
+        self.writer.set_u16(KEY_PSI_RANGE, psi)?;
+        info!("Config: transducer full scale = {} PSI", psi);
+        Ok(())
+    }
+
+    /// Set the transducer output at 0 PSI and persist to NVS
+    pub fn set_psi_min_mv(
+        &mut self,
+        mv: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let mv = mv.clamp(0, 2500);
+        self.psi_min_mv = mv;
+        self.writer.set_u16(KEY_PSI_MIN_MV, mv)?;
+        info!("Config: transducer output at 0 PSI = {} mV", mv);
+        Ok(())
+    }
+
+    /// Set the post-filter transducer output at 0 PSI and persist to NVS
+    pub fn set_psi2_min_mv(
+        &mut self,
+        mv: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let mv = mv.clamp(0, 2500);
+        self.psi2_min_mv = mv;
+        self.writer.set_u16(KEY_PSI2_MIN_MV, mv)?;
+        info!("Config: post-filter transducer output at 0 PSI = {} mV", mv);
+        Ok(())
+    }
+
+    /// Set the transducer output at full scale and persist to NVS
+    ///
+    /// Up to 5.5 V, the most the divider keeps inside the ADC range.
+    pub fn set_psi_max_mv(
+        &mut self,
+        mv: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let mv = mv.clamp(1000, 5500);
+        self.psi_max_mv = mv;
+        self.writer.set_u16(KEY_PSI_MAX_MV, mv)?;
+        info!("Config: transducer output at full scale = {} mV", mv);
+        Ok(())
+    }
+
+    /// Set whether the transducer output is ratiometric and persist to NVS
+    pub fn set_psi_ratiometric(
+        &mut self,
+        on: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let on = on.min(1);
+        self.psi_ratiometric = on;
+        self.writer.set_u16(KEY_PSI_RATIO, on)?;
+        info!("Config: ratiometric transducer = {}", on == 1);
+        Ok(())
+    }
+
+    /// Set the transducer supply and persist to NVS
+    pub fn set_psi_supply_mv(
+        &mut self,
+        mv: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let mv = mv.clamp(3000, 5500);
+        self.psi_supply_mv = mv;
+        self.writer.set_u16(KEY_PSI_SUPPLY, mv)?;
+        info!("Config: transducer supply = {} mV", mv);
+        Ok(())
+    }
+
     /// Set manometer max PSI and persist to NVS
     pub fn set_max_psi(
         &mut self,
@@ -127,7 +991,7 @@ impl Config {
     ) -> Result<(), esp_idf_svc::sys::EspError> {
         let psi = psi.clamp(50, 300);
         self.max_psi = psi;
-        self.nvs.set_u16(KEY_MAX_PSI, psi)?;
+        self.writer.set_u16(KEY_MAX_PSI, psi)?;
         info!("Config: max PSI = {}", psi);
         Ok(())
     }
@@ -139,7 +1003,7 @@ impl Config {
     ) -> Result<(), esp_idf_svc::sys::EspError> {
         let cm = cm.clamp(10, 500);
         self.radar_height_cm = cm;
-        self.nvs.set_u16(KEY_RADAR_HEIGHT, cm)?;
+        self.writer.set_u16(KEY_RADAR_HEIGHT, cm)?;
         info!("Config: radar height = {} cm", cm);
         Ok(())
     }
@@ -151,11 +1015,715 @@ impl Config {
     ) -> Result<(), esp_idf_svc::sys::EspError> {
         let cm = cm.clamp(0, 200);
         self.radar_deadzone_cm = cm;
-        self.nvs.set_u16(KEY_RADAR_DEADZONE, cm)?;
+        self.writer.set_u16(KEY_RADAR_DEADZONE, cm)?;
         info!("Config: radar deadzone = {} cm", cm);
         Ok(())
     }
 
+    /// Set tank shape (0 = vertical, 1 = horizontal cylinder) and persist to NVS
+    pub fn set_tank_shape(
+        &mut self,
+        shape: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let shape = shape.clamp(0, 1);
+        self.tank_shape = shape;
+        self.writer.set_u16(KEY_TANK_SHAPE, shape)?;
+        info!("Config: tank shape = {}", shape);
+        Ok(())
+    }
+
+    /// Set radar correction (offset mm, gain in thousandths, table) and persist to NVS
+    pub fn set_radar_correction(
+        &mut self,
+        offset_mm: i16,
+        gain_milli: u16,
+        table: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let gain_milli = gain_milli.clamp(500, 2000);
+        self.radar_offset_mm = offset_mm;
+        self.radar_gain_milli = gain_milli;
+        self.radar_table = table.to_string();
+        self.writer.set_i16(KEY_RADAR_OFFSET, offset_mm)?;
+        self.writer.set_u16(KEY_RADAR_GAIN, gain_milli)?;
+        self.writer.set_str(KEY_RADAR_TABLE, table)?;
+        info!("Config: radar correction = {} mm, x{}e-3, table '{}'", offset_mm, gain_milli, table);
+        Ok(())
+    }
+
+    /// Set pressure correction (offset centi-PSI, gain in thousandths, table) and persist to NVS
+    pub fn set_pressure_correction(
+        &mut self,
+        offset_centi: i16,
+        gain_milli: u16,
+        table: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let gain_milli = gain_milli.clamp(500, 2000);
+        self.pressure_offset_centi = offset_centi;
+        self.pressure_gain_milli = gain_milli;
+        self.pressure_table = table.to_string();
+        self.writer.set_i16(KEY_PSI_OFFSET, offset_centi)?;
+        self.writer.set_u16(KEY_PSI_GAIN, gain_milli)?;
+        self.writer.set_str(KEY_PSI_TABLE, table)?;
+        info!("Config: pressure correction = {}e-2 PSI, x{}e-3, table '{}'", offset_centi, gain_milli, table);
+        Ok(())
+    }
+
+    /// Set DS18B20 probe names (`ROM=name;...`) and persist to NVS
+    pub fn set_probe_names(
+        &mut self,
+        names: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.probe_names = names.to_string();
+        self.writer.set_str(KEY_PROBE_NAMES, names)?;
+        info!("Config: probe names = '{}'", names);
+        Ok(())
+    }
+
+    /// Set the tank name used in notifications and persist to NVS
+    pub fn set_tank_name(
+        &mut self,
+        name: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.tank_name = name.to_string();
+        self.writer.set_str(KEY_TANK_NAME, name)?;
+        info!("Config: tank name = '{}'", name);
+        Ok(())
+    }
+
+    /// Set the tank label (empty = none) and persist to NVS
+    pub fn set_tank_label(
+        &mut self,
+        label: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.tank_label = label.to_string();
+        self.writer.set_str(KEY_TANK_LABEL, label)?;
+        info!("Config: tank label = '{}'", label);
+        Ok(())
+    }
+
+    /// Set the notification webhook URL (empty = none) and persist to NVS
+    pub fn set_webhook_url(
+        &mut self,
+        url: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.webhook_url = url.to_string();
+        self.writer.set_str(KEY_WEBHOOK_URL, url)?;
+        info!("Config: webhook URL = '{}'", url);
+        Ok(())
+    }
+
+    /// Set the notification templates (`event=template` lines) and persist to NVS
+    pub fn set_notify_templates(
+        &mut self,
+        templates: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.notify_templates = templates.to_string();
+        self.writer.set_str(KEY_NOTIFY_TEMPLATES, templates)?;
+        info!("Config: {} notification template(s)", templates.lines().count());
+        Ok(())
+    }
+
+    /// Store the lockout PIN (empty = released) and persist to NVS
+    pub fn set_lockout_pin(
+        &mut self,
+        pin: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.lockout_pin = pin.to_string();
+        self.writer.set_str(KEY_LOCKOUT_PIN, pin)?;
+        info!("Config: lockout {}", if pin.is_empty() { "released" } else { "engaged" });
+        Ok(())
+    }
+
+    /// Set radar warm-up period and persist to NVS
+    pub fn set_radar_warmup(
+        &mut self,
+        secs: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let secs = secs.clamp(0, 600);
+        self.radar_warmup_secs = secs;
+        self.writer.set_u16(KEY_RADAR_WARMUP, secs)?;
+        info!("Config: radar warm-up = {} s", secs);
+        Ok(())
+    }
+
+    /// Set pressure sensor warm-up period and persist to NVS
+    pub fn set_pressure_warmup(
+        &mut self,
+        secs: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let secs = secs.clamp(0, 600);
+        self.pressure_warmup_secs = secs;
+        self.writer.set_u16(KEY_PSI_WARMUP, secs)?;
+        info!("Config: pressure warm-up = {} s", secs);
+        Ok(())
+    }
+
+    /// Set level median window and persist to NVS
+    pub fn set_level_median(
+        &mut self,
+        window: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let window = window.clamp(1, 15);
+        self.level_median_window = window;
+        self.writer.set_u16(KEY_LEVEL_MEDIAN, window)?;
+        info!("Config: level median window = {}", window);
+        Ok(())
+    }
+
+    /// Set level smoothing factor and persist to NVS
+    pub fn set_level_smoothing(
+        &mut self,
+        percent: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let percent = percent.clamp(1, 100);
+        self.level_smoothing_percent = percent;
+        self.writer.set_u16(KEY_LEVEL_ALPHA, percent)?;
+        info!("Config: level smoothing = {}%", percent);
+        Ok(())
+    }
+
+    /// Set display page interval (0 = button only)
+    pub fn set_page_interval(
+        &mut self,
+        secs: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let secs = secs.clamp(0, 600);
+        self.page_interval_secs = secs;
+        self.writer.set_u16(KEY_PAGE_INTERVAL, secs)?;
+        info!("Config: display page interval = {} s", secs);
+        Ok(())
+    }
+
+    /// Set minimum pump on time
+    pub fn set_pump_min_on(
+        &mut self,
+        secs: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let secs = secs.clamp(0, 1800);
+        self.pump_min_on_secs = secs;
+        self.writer.set_u16(KEY_PUMP_MIN_ON, secs)?;
+        info!("Config: pump min on time = {} s", secs);
+        Ok(())
+    }
+
+    /// Set minimum pump off time
+    pub fn set_pump_min_off(
+        &mut self,
+        secs: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let secs = secs.clamp(0, 1800);
+        self.pump_min_off_secs = secs;
+        self.writer.set_u16(KEY_PUMP_MIN_OFF, secs)?;
+        info!("Config: pump min off time = {} s", secs);
+        Ok(())
+    }
+
+    /// Set dry-run pressure threshold (0 = disabled)
+    pub fn set_pump_dry_psi(
+        &mut self,
+        psi: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let psi = psi.clamp(0, 150);
+        self.pump_dry_psi = psi;
+        self.writer.set_u16(KEY_PUMP_DRY_PSI, psi)?;
+        info!("Config: pump dry-run pressure = {} PSI", psi);
+        Ok(())
+    }
+
+    /// Set dry-run delay
+    pub fn set_pump_dry_secs(
+        &mut self,
+        secs: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let secs = secs.clamp(1, 600);
+        self.pump_dry_secs = secs;
+        self.writer.set_u16(KEY_PUMP_DRY_SECS, secs)?;
+        info!("Config: pump dry-run delay = {} s", secs);
+        Ok(())
+    }
+
+    /// Set supply valve travel time
+    pub fn set_valve_travel(
+        &mut self,
+        secs: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let secs = secs.clamp(1, 300);
+        self.valve_travel_secs = secs;
+        self.writer.set_u16(KEY_VALVE_TRAVEL, secs)?;
+        info!("Config: valve travel time = {} s", secs);
+        Ok(())
+    }
+
+    /// Set water hammer threshold
+    pub fn set_hammer_psi(
+        &mut self,
+        psi: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let psi = psi.clamp(1, 100);
+        self.hammer_psi = psi;
+        self.writer.set_u16(KEY_HAMMER_PSI, psi)?;
+        info!("Config: water hammer threshold = {} PSI", psi);
+        Ok(())
+    }
+
+    /// Set pipe burst pressure drop (0 = off)
+    pub fn set_burst_psi(
+        &mut self,
+        psi: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let psi = psi.min(100);
+        self.burst_psi = psi;
+        self.writer.set_u16(KEY_BURST_PSI, psi)?;
+        info!("Config: pipe burst pressure drop = {} PSI", psi);
+        Ok(())
+    }
+
+    /// Set pump efficiency alert threshold
+    pub fn set_efficiency_drop(
+        &mut self,
+        percent: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let percent = percent.clamp(5, 90);
+        self.efficiency_drop_percent = percent;
+        self.writer.set_u16(KEY_EFFICIENCY_DROP, percent)?;
+        info!("Config: pump efficiency drop alert = {}%", percent);
+        Ok(())
+    }
+
+    /// Set the pump CT clamp rating (A at 1 V RMS output)
+    pub fn set_ct_amps(
+        &mut self,
+        amps: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let amps = amps.clamp(1, 200);
+        self.ct_amps = amps;
+        self.writer.set_u16(KEY_CT_AMPS, amps)?;
+        info!("Config: pump CT clamp = {} A/V", amps);
+        Ok(())
+    }
+
+    /// Set the pump supply voltage
+    pub fn set_pump_volts(
+        &mut self,
+        volts: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let volts = volts.clamp(90, 480);
+        self.pump_volts = volts;
+        self.writer.set_u16(KEY_PUMP_VOLTS, volts)?;
+        info!("Config: pump supply = {} V", volts);
+        Ok(())
+    }
+
+    /// Set the pump power factor
+    pub fn set_pump_pf(
+        &mut self,
+        percent: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let percent = percent.clamp(30, 100);
+        self.pump_pf_percent = percent;
+        self.writer.set_u16(KEY_PUMP_PF, percent)?;
+        info!("Config: pump power factor = {}%", percent);
+        Ok(())
+    }
+
+    /// Set low-level notification threshold
+    pub fn set_alarm_low(
+        &mut self,
+        percent: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let percent = percent.clamp(0, 99);
+        self.alarm_low_percent = percent;
+        self.writer.set_u16(KEY_ALARM_LOW, percent)?;
+        info!("Config: low-level alarm = {}%", percent);
+        Ok(())
+    }
+
+    /// Set high-pressure alarm threshold (0 = off)
+    pub fn set_alarm_high_psi(
+        &mut self,
+        psi: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let psi = psi.clamp(0, 300);
+        self.alarm_high_psi = psi;
+        self.writer.set_u16(KEY_ALARM_HIGH_PSI, psi)?;
+        info!("Config: high-pressure alarm = {} PSI", psi);
+        Ok(())
+    }
+
+    /// Set clogged-filter alarm threshold (0 = off)
+    pub fn set_alarm_clog_psi(
+        &mut self,
+        psi: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let psi = psi.clamp(0, 50);
+        self.alarm_clog_psi = psi;
+        self.writer.set_u16(KEY_ALARM_CLOG_PSI, psi)?;
+        info!("Config: clogged-filter alarm = {} PSI", psi);
+        Ok(())
+    }
+
+    /// Set how long a sensor may fail before it raises a fault
+    pub fn set_alarm_fault_secs(
+        &mut self,
+        secs: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let secs = secs.clamp(5, 3600);
+        self.alarm_fault_secs = secs;
+        self.writer.set_u16(KEY_ALARM_FAULT_SECS, secs)?;
+        info!("Config: sensor fault delay = {} s", secs);
+        Ok(())
+    }
+
+    /// Set the daily water budget (0 = none) and persist to NVS
+    pub fn set_budget(
+        &mut self,
+        gallons: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let gallons = gallons.min(50_000);
+        self.budget_gallons = gallons;
+        self.writer.set_u16(KEY_BUDGET, gallons)?;
+        info!("Config: daily water budget = {} gal", gallons);
+        Ok(())
+    }
+
+    /// Save the day's usage totals, restored after a restart on the same day
+    pub fn set_saved_usage(
+        &mut self,
+        day: i64,
+        usage: DailyUsage,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.saved_usage = Some((day, usage));
+        self.writer.set_str(KEY_USAGE_TODAY, &usage.to_saved(day))?;
+        debug!("Config: usage today = {} gal consumed", usage.consumed);
+        Ok(())
+    }
+
+    /// Set the flow meter K-factor (pulses per gallon) and persist to NVS
+    pub fn set_flow_k_factor(
+        &mut self,
+        k_factor: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let k_factor = k_factor.clamp(100, 20000);
+        self.flow_k_factor = k_factor;
+        self.writer.set_u16(KEY_FLOW_K, k_factor)?;
+        info!("Config: flow meter K-factor = {} pulses/gal", k_factor);
+        Ok(())
+    }
+
+    /// Save the flow meter total, restored after a restart
+    pub fn set_flow_total(
+        &mut self,
+        gallons: f64,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.flow_total_gallons = gallons;
+        self.writer.set_str(KEY_FLOW_TOTAL, &format!("{:.1}", gallons))?;
+        debug!("Config: flow total = {:.1} gal", gallons);
+        Ok(())
+    }
+
+    /// Set the cellular APN (empty = network default) and persist to NVS
+    pub fn set_cell_apn(
+        &mut self,
+        apn: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.cell_apn = apn.to_string();
+        self.writer.set_str(KEY_CELL_APN, apn)?;
+        info!("Config: cellular APN = '{}'", apn);
+        Ok(())
+    }
+
+    /// Set the monthly cellular data budget (MB, 0 = unlimited) and persist to NVS
+    pub fn set_cell_budget(
+        &mut self,
+        mb: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.cell_budget_mb = mb;
+        self.writer.set_u16(KEY_CELL_BUDGET, mb)?;
+        info!("Config: cellular data budget = {} MB", mb);
+        Ok(())
+    }
+
+    /// Store the cellular usage count of `month`
+    pub fn set_cell_usage(
+        &mut self,
+        month: u16,
+        bytes: u64,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.cell_usage_month = month;
+        self.cell_usage_bytes = bytes;
+        self.writer.set_str(KEY_CELL_USAGE, &format!("{}:{}", month, bytes))?;
+        debug!("Config: cellular usage = {} bytes", bytes);
+        Ok(())
+    }
+
+    /// Set the LoRa radio role (0 = off, 1 = node, 2 = gateway) and persist to NVS
+    pub fn set_lora_mode(
+        &mut self,
+        mode: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.lora_mode = mode;
+        self.writer.set_u16(KEY_LORA_MODE, mode)?;
+        info!("Config: LoRa mode = {}", mode);
+        Ok(())
+    }
+
+    /// Set the LoRa node id and persist to NVS
+    pub fn set_lora_node(
+        &mut self,
+        node: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.lora_node = node;
+        self.writer.set_u16(KEY_LORA_NODE, node)?;
+        info!("Config: LoRa node = {}", node);
+        Ok(())
+    }
+
+    /// Set the LoRa carrier frequency (100 kHz steps) and persist to NVS
+    pub fn set_lora_freq(
+        &mut self,
+        freq: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.lora_freq = freq;
+        self.writer.set_u16(KEY_LORA_FREQ, freq)?;
+        info!("Config: LoRa frequency = {}.{} MHz", freq / 10, freq % 10);
+        Ok(())
+    }
+
+    /// Set the LoRa node frame interval (seconds) and persist to NVS
+    pub fn set_lora_interval(
+        &mut self,
+        secs: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.lora_interval_secs = secs;
+        self.writer.set_u16(KEY_LORA_INTERVAL, secs)?;
+        info!("Config: LoRa interval = {} s", secs);
+        Ok(())
+    }
+
+    /// Set display flush budget (lines per flush, 0 = unlimited) and persist to NVS
+    pub fn set_display_flush_lines(
+        &mut self,
+        lines: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let lines = lines.clamp(0, 240);
+        self.display_flush_lines = lines;
+        self.writer.set_u16(KEY_FLUSH_LINES, lines)?;
+        info!("Config: display flush lines = {}", lines);
+        Ok(())
+    }
+
+    /// Set full display refresh interval (minutes, 0 = off) and persist to NVS
+    pub fn set_display_refresh(
+        &mut self,
+        minutes: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let minutes = minutes.clamp(0, 1440);
+        self.display_refresh_minutes = minutes;
+        self.writer.set_u16(KEY_DISPLAY_REFRESH, minutes)?;
+        info!("Config: display full refresh = {} min", minutes);
+        Ok(())
+    }
+
+    /// Set tank fill pattern (0 = solid, 1 = hatched, 2 = dithered) and persist to NVS
+    pub fn set_tank_fill_pattern(
+        &mut self,
+        pattern: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let pattern = pattern.clamp(0, 2);
+        self.tank_fill_pattern = pattern;
+        self.writer.set_u16(KEY_TANK_FILL, pattern)?;
+        info!("Config: tank fill pattern = {}", pattern);
+        Ok(())
+    }
+
+    /// Set pump start level and persist to NVS (kept below the stop level)
+    pub fn set_pump_start(
+        &mut self,
+        percent: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let percent = percent.clamp(0, self.pump_stop_percent.saturating_sub(1));
+        self.pump_start_percent = percent;
+        self.writer.set_u16(KEY_PUMP_START, percent)?;
+        info!("Config: pump start = {}%", percent);
+        Ok(())
+    }
+
+    /// Set pump stop level and persist to NVS (kept above the start level)
+    pub fn set_pump_stop(
+        &mut self,
+        percent: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let percent = percent.clamp(self.pump_start_percent + 1, 100);
+        self.pump_stop_percent = percent;
+        self.writer.set_u16(KEY_PUMP_STOP, percent)?;
+        info!("Config: pump stop = {}%", percent);
+        Ok(())
+    }
+
+    /// Set lag pump assist drop and persist to NVS
+    pub fn set_pump_assist_drop(
+        &mut self,
+        percent: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let percent = percent.clamp(1, 50);
+        self.pump_assist_drop_percent = percent;
+        self.writer.set_u16(KEY_PUMP_ASSIST, percent)?;
+        info!("Config: pump assist drop = {}%", percent);
+        Ok(())
+    }
+
+    /// Set pump failure timeout and persist to NVS
+    pub fn set_pump_fail_minutes(
+        &mut self,
+        minutes: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let minutes = minutes.clamp(1, 120);
+        self.pump_fail_minutes = minutes;
+        self.writer.set_u16(KEY_PUMP_FAIL_MIN, minutes)?;
+        info!("Config: pump failure timeout = {} min", minutes);
+        Ok(())
+    }
+
+    /// Set constant-pressure setpoint and persist to NVS
+    pub fn set_vfd_setpoint(
+        &mut self,
+        psi: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let psi = psi.clamp(5, 150);
+        self.vfd_setpoint_psi = psi;
+        self.writer.set_u16(KEY_VFD_SETPOINT, psi)?;
+        info!("Config: VFD setpoint = {} PSI", psi);
+        Ok(())
+    }
+
+    /// Set VFD PID proportional gain (thousandths) and persist to NVS
+    pub fn set_vfd_kp(
+        &mut self,
+        milli: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let milli = milli.clamp(0, 10000);
+        self.vfd_kp_milli = milli;
+        self.writer.set_u16(KEY_VFD_KP, milli)?;
+        info!("Config: VFD Kp = {}e-3", milli);
+        Ok(())
+    }
+
+    /// Set VFD PID integral gain (thousandths) and persist to NVS
+    pub fn set_vfd_ki(
+        &mut self,
+        milli: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let milli = milli.clamp(0, 10000);
+        self.vfd_ki_milli = milli;
+        self.writer.set_u16(KEY_VFD_KI, milli)?;
+        info!("Config: VFD Ki = {}e-3", milli);
+        Ok(())
+    }
+
+    /// Set VFD PID derivative gain (thousandths) and persist to NVS
+    pub fn set_vfd_kd(
+        &mut self,
+        milli: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let milli = milli.clamp(0, 10000);
+        self.vfd_kd_milli = milli;
+        self.writer.set_u16(KEY_VFD_KD, milli)?;
+        info!("Config: VFD Kd = {}e-3", milli);
+        Ok(())
+    }
+
+    /// Set radar heater mode (0 = off, 1 = auto, 2 = duty cycle) and persist to NVS
+    pub fn set_heater_mode(
+        &mut self,
+        mode: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let mode = mode.clamp(0, 2);
+        self.heater_mode = mode;
+        self.writer.set_u16(KEY_HEATER_MODE, mode)?;
+        info!("Config: heater mode = {}", mode);
+        Ok(())
+    }
+
+    /// Set radar heater dew-point spread threshold and persist to NVS
+    pub fn set_heater_spread(
+        &mut self,
+        celsius: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let celsius = celsius.clamp(1, 10);
+        self.heater_spread_c = celsius;
+        self.writer.set_u16(KEY_HEATER_SPREAD, celsius)?;
+        info!("Config: heater spread = {} C", celsius);
+        Ok(())
+    }
+
+    /// Set radar heater duty cycle and persist to NVS
+    pub fn set_heater_duty(
+        &mut self,
+        percent: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let percent = percent.clamp(0, 100);
+        self.heater_duty_percent = percent;
+        self.writer.set_u16(KEY_HEATER_DUTY, percent)?;
+        info!("Config: heater duty = {}%", percent);
+        Ok(())
+    }
+
+    /// Set maintenance reboot day (0 = disabled, 1-7 = Monday-Sunday, 8 = daily) and persist to NVS
+    pub fn set_reboot_day(
+        &mut self,
+        day: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let day = day.clamp(0, 8);
+        self.reboot_day = day;
+        self.writer.set_u16(KEY_REBOOT_DAY, day)?;
+        info!("Config: reboot day = {}", day);
+        Ok(())
+    }
+
+    /// Set maintenance reboot hour and persist to NVS
+    pub fn set_reboot_hour(
+        &mut self,
+        hour: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let hour = hour.clamp(0, 23);
+        self.reboot_hour = hour;
+        self.writer.set_u16(KEY_REBOOT_HOUR, hour)?;
+        info!("Config: reboot hour = {}", hour);
+        Ok(())
+    }
+
+    /// Set POSIX timezone string and persist to NVS
+    pub fn set_timezone(
+        &mut self,
+        tz: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.timezone = tz.to_string();
+        self.writer.set_str(KEY_TIMEZONE, tz)?;
+        info!("Config: timezone = {}", tz);
+        Ok(())
+    }
+
+    /// Wait until the queued writes are stored, e.g. before a reboot
+    pub fn flush(&self) {
+        let (done_tx, done_rx) = mpsc::channel();
+        if self.writer.queue(Write::Flush(done_tx)).is_err() || done_rx.recv_timeout(FLUSH_TIMEOUT).is_err() {
+            warn!("Config: settings may not have been stored");
+        }
+    }
+
+    /// Erase all stored settings, once the writes queued before are stored
+    ///
+    /// The values in memory are kept until the reboot that should follow.
+    pub fn factory_reset(&self) -> Result<(), EspError> {
+        let (done_tx, done_rx) = mpsc::channel();
+        self.writer.queue(Write::Erase(done_tx))?;
+        let result = done_rx.recv_timeout(FLUSH_TIMEOUT).map_err(|_| EspError::from_infallible::<ESP_ERR_INVALID_STATE>())?;
+        if result.is_ok() {
+            warn!("Config: all settings erased");
+        }
+        result
+    }
+
     /// Whether MQTT broker is configured
     pub fn mqtt_configured(&self) -> bool {
         !self.mqtt_broker.is_empty()
@@ -167,7 +1735,7 @@ impl Config {
         host: &str,
     ) -> Result<(), esp_idf_svc::sys::EspError> {
         self.mqtt_broker = host.to_string();
-        self.nvs.set_str(KEY_MQTT_BROKER, host)?;
+        self.writer.set_str(KEY_MQTT_BROKER, host)?;
         info!("Config: MQTT broker = {}", host);
         Ok(())
     }
@@ -178,7 +1746,7 @@ impl Config {
         port: u16,
     ) -> Result<(), esp_idf_svc::sys::EspError> {
         self.mqtt_port = port;
-        self.nvs.set_u16(KEY_MQTT_PORT, port)?;
+        self.writer.set_u16(KEY_MQTT_PORT, port)?;
         info!("Config: MQTT port = {}", port);
         Ok(())
     }
@@ -189,7 +1757,7 @@ impl Config {
         username: &str,
     ) -> Result<(), esp_idf_svc::sys::EspError> {
         self.mqtt_username = username.to_string();
-        self.nvs.set_str(KEY_MQTT_USERNAME, username)?;
+        self.writer.set_str(KEY_MQTT_USERNAME, username)?;
         info!("Config: MQTT username = {}", username);
         Ok(())
     }
@@ -200,8 +1768,256 @@ impl Config {
         password: &str,
     ) -> Result<(), esp_idf_svc::sys::EspError> {
         self.mqtt_password = password.to_string();
-        self.nvs.set_str(KEY_MQTT_PASSWORD, password)?;
+        self.writer.set_str(KEY_MQTT_PASSWORD, password)?;
         info!("Config: MQTT password updated");
         Ok(())
     }
+
+    /// Set MQTT TLS (0 = off, 1 = on) and persist to NVS
+    pub fn set_mqtt_tls(
+        &mut self,
+        tls: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let tls = tls.min(1);
+        self.mqtt_tls = tls;
+        self.writer.set_u16(KEY_MQTT_TLS, tls)?;
+        info!("Config: MQTT TLS = {}", if tls == 1 { "on" } else { "off" });
+        Ok(())
+    }
+
+    /// Set MQTT read-only mode (0 = off, 1 = on) and persist to NVS
+    pub fn set_mqtt_read_only(
+        &mut self,
+        read_only: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let read_only = read_only.min(1);
+        self.mqtt_read_only = read_only;
+        self.writer.set_u16(KEY_MQTT_READ_ONLY, read_only)?;
+        info!("Config: MQTT read-only = {}", if read_only == 1 { "on" } else { "off" });
+        Ok(())
+    }
+
+    /// Set MQTT CA certificate (PEM, empty = default) and persist to NVS
+    pub fn set_mqtt_ca_cert(
+        &mut self,
+        pem: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.mqtt_ca_cert = pem.to_string();
+        self.writer.set_str(KEY_MQTT_CA_CERT, pem)?;
+        info!("Config: MQTT CA certificate {}", if pem.is_empty() { "cleared" } else { "updated" });
+        Ok(())
+    }
+
+    /// Set MQTT client certificate (PEM, empty = none) and persist to NVS
+    pub fn set_mqtt_client_cert(
+        &mut self,
+        pem: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.mqtt_client_cert = pem.to_string();
+        self.writer.set_str(KEY_MQTT_CLIENT_CERT, pem)?;
+        info!("Config: MQTT client certificate {}", if pem.is_empty() { "cleared" } else { "updated" });
+        Ok(())
+    }
+
+    /// Set MQTT client private key (PEM, empty = none) and persist to NVS
+    pub fn set_mqtt_client_key(
+        &mut self,
+        pem: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.mqtt_client_key = pem.to_string();
+        self.writer.set_str(KEY_MQTT_CLIENT_KEY, pem)?;
+        info!("Config: MQTT client key {}", if pem.is_empty() { "cleared" } else { "updated" });
+        Ok(())
+    }
+
+    /// Set web admin token and persist to NVS
+    pub fn set_admin_token(
+        &mut self,
+        token: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.admin_token = token.to_string();
+        self.writer.set_str(KEY_ADMIN_TOKEN, token)?;
+        info!("Config: admin token updated");
+        Ok(())
+    }
+
+    /// Set web login user name and persist to NVS
+    pub fn set_web_user(
+        &mut self,
+        user: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.web_user = user.to_string();
+        self.writer.set_str(KEY_WEB_USER, user)?;
+        info!("Config: web user = {}", user);
+        Ok(())
+    }
+
+    /// Set web login for the status pages (0 = off, 1 = on) and persist to NVS
+    pub fn set_web_login(
+        &mut self,
+        login: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let login = login.min(1);
+        self.web_login = login;
+        self.writer.set_u16(KEY_WEB_LOGIN, login)?;
+        info!("Config: web login = {}", if login == 1 { "all pages" } else { "setup only" });
+        Ok(())
+    }
+
+    /// Set the well pump cut-in rise and persist to NVS
+    pub fn set_well_rise(
+        &mut self,
+        psi: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let psi = psi.clamp(1, 30);
+        self.well_rise_psi = psi;
+        self.writer.set_u16(KEY_WELL_RISE, psi)?;
+        info!("Config: well pump cut-in rise = {} PSI", psi);
+        Ok(())
+    }
+
+    /// Record the discovery revision cleaned up to and persist to NVS
+    pub fn set_discovery_rev(
+        &mut self,
+        rev: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.discovery_rev = rev;
+        self.writer.set_u16(KEY_DISCOVERY_REV, rev)?;
+        info!("Config: discovery revision = {}", rev);
+        Ok(())
+    }
+
+    /// Set the fallback WiFi network and persist to NVS
+    pub fn set_wifi_ssid(
+        &mut self,
+        ssid: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.wifi_ssid = ssid.to_string();
+        self.writer.set_str(KEY_WIFI_SSID, ssid)?;
+        info!("Config: WiFi network = '{}'", ssid);
+        Ok(())
+    }
+
+    /// Set the fallback WiFi password and persist to NVS
+    pub fn set_wifi_pass(
+        &mut self,
+        password: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.wifi_pass = password.to_string();
+        self.writer.set_str(KEY_WIFI_PASS, password)?;
+        info!("Config: WiFi password updated");
+        Ok(())
+    }
+
+    /// Set the InfluxDB server URL (empty = none) and persist to NVS
+    pub fn set_influx_url(
+        &mut self,
+        url: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.influx_url = url.to_string();
+        self.writer.set_str(KEY_INFLUX_URL, url)?;
+        info!("Config: InfluxDB URL = '{}'", url);
+        Ok(())
+    }
+
+    /// Set the InfluxDB organization and persist to NVS
+    pub fn set_influx_org(
+        &mut self,
+        org: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.influx_org = org.to_string();
+        self.writer.set_str(KEY_INFLUX_ORG, org)?;
+        info!("Config: InfluxDB org = '{}'", org);
+        Ok(())
+    }
+
+    /// Set the InfluxDB bucket and persist to NVS
+    pub fn set_influx_bucket(
+        &mut self,
+        bucket: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.influx_bucket = bucket.to_string();
+        self.writer.set_str(KEY_INFLUX_BUCKET, bucket)?;
+        info!("Config: InfluxDB bucket = '{}'", bucket);
+        Ok(())
+    }
+
+    /// Set the InfluxDB API token and persist to NVS
+    pub fn set_influx_token(
+        &mut self,
+        token: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.influx_token = token.to_string();
+        self.writer.set_str(KEY_INFLUX_TOKEN, token)?;
+        info!("Config: InfluxDB token updated");
+        Ok(())
+    }
+
+    /// Set the Home Assistant device name (empty = default) and persist to NVS
+    pub fn set_ha_device_name(
+        &mut self,
+        name: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let name = if name.is_empty() { DEFAULT_HA_NAME } else { name };
+        self.ha_device_name = name.to_string();
+        self.writer.set_str(KEY_HA_NAME, name)?;
+        info!("Config: HA device name = '{}'", name);
+        Ok(())
+    }
+
+    /// Set the Home Assistant suggested area (empty = none) and persist to NVS
+    pub fn set_ha_area(
+        &mut self,
+        area: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.ha_area = area.to_string();
+        self.writer.set_str(KEY_HA_AREA, area)?;
+        info!("Config: HA area = '{}'", area);
+        Ok(())
+    }
+
+    /// Set the Home Assistant entity name prefix (empty = none) and persist to NVS
+    pub fn set_ha_prefix(
+        &mut self,
+        prefix: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.ha_prefix = prefix.to_string();
+        self.writer.set_str(KEY_HA_PREFIX, prefix)?;
+        info!("Config: HA entity prefix = '{}'", prefix);
+        Ok(())
+    }
+
+    /// Set the syslog server (empty = none) and persist to NVS
+    pub fn set_syslog_server(
+        &mut self,
+        server: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.syslog_server = server.to_string();
+        self.writer.set_str(KEY_SYSLOG_SERVER, server)?;
+        info!("Config: syslog server = '{}'", server);
+        Ok(())
+    }
+
+    /// Set the syslog level (0 = off ... 5 = trace) and persist to NVS
+    pub fn set_syslog_level(
+        &mut self,
+        level: u16,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        let level = level.min(5);
+        self.syslog_level = level;
+        self.writer.set_u16(KEY_SYSLOG_LEVEL, level)?;
+        info!("Config: syslog level = {}", level);
+        Ok(())
+    }
+
+    /// Set the alarm runbook URL (empty = no QR code) and persist to NVS
+    pub fn set_runbook_url(
+        &mut self,
+        url: &str,
+    ) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.runbook_url = url.to_string();
+        self.writer.set_str(KEY_RUNBOOK_URL, url)?;
+        info!("Config: runbook URL = '{}'", url);
+        Ok(())
+    }
 }
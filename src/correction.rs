@@ -0,0 +1,190 @@
+//! Sensor reading correction
+//!
+//! Compensates known systematic sensor errors (mounting offsets, transducer
+//! gain error, a non-linear tank neck) without touching the drivers. The raw
+//! value is scaled and offset first, then optionally mapped through a small
+//! calibration table:
+//!
+//! ```text
+//! corrected = table(raw * gain + offset)
+//! ```
+//!
+//! Tables are written as `raw:actual` pairs with ascending raw values, e.g.
+//! `0:0,500:520,1000:1010`. Between points the value is interpolated
+//! linearly; beyond the ends the nearest segment is extended.
+//!
+//! Pressure is also compensated for the water column between the sensor and
+//! the point the reading is wanted for, usually the gauge on the pressure
+//! tank (`HeadMode`). That part is physics rather than sensor error, so it is
+//! added to the transducer reading before the correction above.
+
+#[cfg(target_os = "espidf")]
+use crate::config::Config;
+
+/// Pressure of one foot of water column (PSI)
+pub const PSI_PER_FOOT: f32 = 0.433;
+
+/// Maximum number of table points
+pub const MAX_TABLE_POINTS: usize = 8;
+
+/// Offset, gain and lookup-table correction for one sensor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Correction {
+    pub offset: f32,
+    pub gain: f32,
+    /// `(raw, actual)` points, ascending by raw value
+    pub table: Vec<(f32, f32)>,
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            gain: 1.0,
+            table: Vec::new(),
+        }
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl Correction {
+    /// Radar empty-height correction (mm)
+    pub fn radar(cfg: &Config) -> Self {
+        Self {
+            offset: cfg.radar_offset_mm as f32,
+            gain: cfg.radar_gain_milli as f32 / 1000.0,
+            table: parse_table(&cfg.radar_table).unwrap_or_default(),
+        }
+    }
+
+    /// Pressure correction (PSI)
+    pub fn pressure(cfg: &Config) -> Self {
+        Self {
+            offset: cfg.pressure_offset_centi as f32 / 100.0,
+            gain: cfg.pressure_gain_milli as f32 / 1000.0,
+            table: parse_table(&cfg.pressure_table).unwrap_or_default(),
+        }
+    }
+}
+
+impl Correction {
+    /// Apply the correction to a raw reading
+    pub fn apply(&self, raw: f32) -> f32 {
+        let value = raw * self.gain + self.offset;
+        if self.table.len() < 2 {
+            return value;
+        }
+        // Segment containing the value (end segments extend outwards)
+        let i = self.table[1..self.table.len() - 1]
+            .iter()
+            .take_while(|&&(x, _)| x < value)
+            .count();
+        let (x0, y0) = self.table[i];
+        let (x1, y1) = self.table[i + 1];
+        y0 + (value - x0) * (y1 - y0) / (x1 - x0)
+    }
+}
+
+/// Where the pressure sensor sits relative to the reference point
+///
+/// A sensor above the reference point reads low by the water column in
+/// between, so the column is added; one below it reads high, so the column
+/// is subtracted. `sensor_height` is the height difference either way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeadMode {
+    /// No compensation, for a sensor level with the reference point
+    Off,
+    /// Sensor above the reference point: the column is added
+    SensorAbove,
+    /// Sensor below the reference point: the column is subtracted
+    SensorBelow,
+}
+
+impl HeadMode {
+    /// Decode the stored config value (0 = off, 1 = above, 2 = below)
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            0 => HeadMode::Off,
+            2 => HeadMode::SensorBelow,
+            _ => HeadMode::SensorAbove,
+        }
+    }
+
+    /// Compensation added to the transducer reading (PSI)
+    pub fn head_psi(self, height_feet: f32) -> f32 {
+        match self {
+            HeadMode::Off => 0.0,
+            HeadMode::SensorAbove => height_feet * PSI_PER_FOOT,
+            HeadMode::SensorBelow => -height_feet * PSI_PER_FOOT,
+        }
+    }
+}
+
+/// Head compensation for the configured mode and sensor height (PSI)
+#[cfg(target_os = "espidf")]
+pub fn pressure_head(cfg: &Config) -> f32 {
+    HeadMode::from_code(cfg.head_mode).head_psi(cfg.sensor_height_feet as f32)
+}
+
+/// Parse a `raw:actual,...` table; `None` if malformed or not ascending
+pub fn parse_table(text: &str) -> Option<Vec<(f32, f32)>> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Some(Vec::new());
+    }
+    let mut table = Vec::new();
+    for pair in text.split(',') {
+        let (raw, actual) = pair.split_once(':')?;
+        table.push((raw.trim().parse().ok()?, actual.trim().parse().ok()?));
+    }
+    let ascending = table.windows(2).all(|w: &[(f32, f32)]| w[0].0 < w[1].0);
+    if table.len() > MAX_TABLE_POINTS || table.len() < 2 || !ascending {
+        return None;
+    }
+    Some(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_and_gain() {
+        let c = Correction { offset: -15.0, gain: 1.02, table: Vec::new() };
+        assert!((c.apply(1000.0) - 1005.0).abs() < 1e-3);
+        assert_eq!(Correction::default().apply(42.0), 42.0);
+    }
+
+    #[test]
+    fn test_table_interpolation() {
+        let c = Correction {
+            table: parse_table("0:0, 500:520, 1000:1010").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(c.apply(250.0), 260.0);
+        assert_eq!(c.apply(750.0), 765.0);
+        // Extrapolates along the end segments
+        assert_eq!(c.apply(1100.0), 1108.0);
+        assert_eq!(c.apply(-100.0), -104.0);
+    }
+
+    #[test]
+    fn test_head_compensation_sign() {
+        // 10 ft of column is 4.33 PSI, added above the gauge and taken off below it
+        assert!((HeadMode::SensorAbove.head_psi(10.0) - 4.33).abs() < 1e-4);
+        assert!((HeadMode::SensorBelow.head_psi(10.0) + 4.33).abs() < 1e-4);
+        assert_eq!(HeadMode::Off.head_psi(10.0), 0.0);
+        assert_eq!(HeadMode::from_code(0), HeadMode::Off);
+        assert_eq!(HeadMode::from_code(2), HeadMode::SensorBelow);
+        // Anything else is the original behaviour
+        assert_eq!(HeadMode::from_code(7), HeadMode::SensorAbove);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_tables() {
+        assert_eq!(parse_table(""), Some(Vec::new()));
+        assert!(parse_table("0:0").is_none());
+        assert!(parse_table("10:0,5:5").is_none());
+        assert!(parse_table("0:0,x:1").is_none());
+    }
+}
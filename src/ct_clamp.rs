@@ -0,0 +1,178 @@
+//! Pump current from a CT clamp
+//!
+//! A split-core current transformer with a voltage output (SCT-013-030 and
+//! the like: 1 V RMS at the rated 30 A) clipped around one conductor of the
+//! pump supply gives the current the pumps draw. Its AC output rides on a
+//! mid-supply bias and is sampled on GPIO32 (ADC1_CH4); the RMS of the
+//! samples about their mean is the current, whatever the bias actually is.
+//! A reading is `SLICES` slices of one 50 Hz mains cycle each, one per main
+//! loop pass, so the loop is never held up for more than a slice. The pump's power is that current times
+//! `pump_volts` and the power factor `pump_pf`, which a clamp on one wire
+//! can't measure (about 0.8 for a running induction motor; the nameplate
+//! gives it).
+//!
+//! `ct_amps` is the current at 1 V RMS output, the clamp's rating. The ADC
+//! noise reads as a few hundred milliamps with no current at all, so the
+//! clamp is only read while a pump relay is on.
+//!
+//! # Wiring
+//! ```text
+//!  3.3V ──[10kΩ]──┬──[10kΩ]── GND
+//!                 ├──[10µF]── GND
+//!  CT sleeve ─────┘
+//!  CT tip ─────────────────── GPIO32 (ADC1_CH4)
+//! ```
+//! A clamp with a current output (no burden resistor inside, e.g.
+//! SCT-013-000) needs a burden across its leads sized for 1 V RMS at full
+//! current, or it puts out dangerous voltages when open.
+
+#[cfg(target_os = "espidf")]
+use crate::config::Config;
+
+/// Parameters that turn the clamp signal into input power
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClampSettings {
+    /// Current at 1 V RMS output (A)
+    pub ct_amps: u16,
+    /// Supply voltage of the pumps (V RMS)
+    pub volts: u16,
+    /// Power factor (%)
+    pub power_factor_percent: u16,
+}
+
+#[cfg(target_os = "espidf")]
+impl ClampSettings {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self { ct_amps: cfg.ct_amps, volts: cfg.pump_volts, power_factor_percent: cfg.pump_pf_percent }
+    }
+}
+
+impl ClampSettings {
+    /// Input power in watts for an RMS clamp output in millivolts
+    pub fn watts(&self, rms_mv: f32) -> f32 {
+        let amps = rms_mv / 1000.0 * self.ct_amps as f32;
+        amps * self.volts as f32 * self.power_factor_percent as f32 / 100.0
+    }
+}
+
+/// Running RMS of samples about their own mean
+#[derive(Debug, Default, Clone)]
+pub struct RmsAccumulator {
+    count: u32,
+    sum: u64,
+    sum_squares: u64,
+}
+
+impl RmsAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, mv: u16) {
+        self.count += 1;
+        self.sum += mv as u64;
+        self.sum_squares += mv as u64 * mv as u64;
+    }
+
+    /// RMS of the AC part (mV), 0 without samples
+    pub fn rms_mv(&self) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let n = self.count as f64;
+        let mean = self.sum as f64 / n;
+        (self.sum_squares as f64 / n - mean * mean).max(0.0).sqrt() as f32
+    }
+}
+
+#[cfg(target_os = "espidf")]
+pub use clamp::CtClamp;
+
+#[cfg(target_os = "espidf")]
+mod clamp {
+    use std::time::{Duration, Instant};
+
+    use esp_idf_svc::hal::adc::attenuation::DB_11;
+    use esp_idf_svc::hal::adc::oneshot::config::{AdcChannelConfig, Calibration};
+    use esp_idf_svc::hal::adc::oneshot::AdcChannelDriver;
+    use esp_idf_svc::hal::adc::ADC1;
+    use esp_idf_svc::hal::gpio::{ADCPin, Gpio32};
+    use esp_idf_svc::hal::peripheral::Peripheral;
+    use esp_idf_svc::sys::EspError;
+
+    use super::{ClampSettings, RmsAccumulator};
+    use crate::pressure::{efuse_calibrated, SharedAdc};
+
+    /// Sampling time of one `poll`: a cycle at 50 Hz, 1.2 at 60 Hz
+    const SLICE: Duration = Duration::from_millis(20);
+    /// Slices in a reading, five cycles at 50 Hz
+    const SLICES: u32 = 5;
+
+    /// CT clamp on an ADC1 pin, GPIO32 unless another is given
+    pub struct CtClamp<'d, P: ADCPin<Adc = ADC1> = Gpio32> {
+        channel: AdcChannelDriver<'d, P, SharedAdc<'d>>,
+        rms: RmsAccumulator,
+        slices: u32,
+    }
+
+    impl<'d, P: ADCPin<Adc = ADC1>> CtClamp<'d, P> {
+        /// Clamp on `pin`, sharing the pressure sensors' ADC
+        pub fn new(adc: SharedAdc<'d>, pin: impl Peripheral<P = P> + 'd) -> Result<Self, EspError> {
+            let config = AdcChannelConfig {
+                attenuation: DB_11,
+                calibration: if efuse_calibrated() { Calibration::Line } else { Calibration::None },
+                ..Default::default()
+            };
+            Ok(Self { channel: AdcChannelDriver::new(adc, pin, &config)?, rms: RmsAccumulator::new(), slices: 0 })
+        }
+
+        /// Sample for one `SLICE`, unless the reading has all its slices
+        pub fn poll(&mut self) -> Result<(), EspError> {
+            if self.slices >= SLICES {
+                return Ok(());
+            }
+            let start = Instant::now();
+            while start.elapsed() < SLICE {
+                self.rms.add(self.channel.read()?);
+            }
+            self.slices += 1;
+            Ok(())
+        }
+
+        /// Input power (W) from the slices polled since the last call, `None`
+        /// without any
+        pub fn take_watts(&mut self, settings: &ClampSettings) -> Option<f32> {
+            let rms = std::mem::take(&mut self.rms);
+            (std::mem::take(&mut self.slices) > 0).then(|| settings.watts(rms.rms_mv()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rms_ignores_bias() {
+        let mut rms = RmsAccumulator::new();
+        assert_eq!(rms.rms_mv(), 0.0);
+        // 1 V RMS sine on a 1650 mV bias, 40 samples a cycle
+        for i in 0..400 {
+            let angle = i as f64 / 40.0 * std::f64::consts::TAU;
+            rms.add((1650.0 + 1414.2 * angle.sin()).round() as u16);
+        }
+        assert!((rms.rms_mv() - 1000.0).abs() < 1.0, "{}", rms.rms_mv());
+
+        let mut flat = RmsAccumulator::new();
+        (0..100).for_each(|_| flat.add(1650));
+        assert_eq!(flat.rms_mv(), 0.0);
+    }
+
+    #[test]
+    fn test_watts() {
+        let settings = ClampSettings { ct_amps: 30, volts: 240, power_factor_percent: 80 };
+        // 250 mV of a 30 A clamp: 7.5 A
+        assert_eq!(settings.watts(250.0), 1440.0);
+        assert_eq!(settings.watts(0.0), 0.0);
+    }
+}
@@ -0,0 +1,698 @@
+//! Device diagnostics snapshot
+//!
+//! Collected by the main loop and served at `/api/diag`, drawn as a text
+//! page on the display on request, and published to Home Assistant as
+//! diagnostic sensors. The MQTT section tells "device offline" (no
+//! connection, socket errors) apart from "broker rejected us" (connects
+//! refused with an error, counters stuck at zero). Its traffic counters
+//! split messages and bytes by topic class, to see what loads a slow broker
+//! link. The display section counts what the panel driver sends, to measure
+//! rendering changes by. The cellular section shows the backup link and its
+//! data budget. The radar section counts Modbus retries and failures, to
+//! tell a noisy sensor cable from a dead sensor. The startup section times
+//! the init phases of the last boot, to find what a slow boot waits on. The
+//! pressure section has the last ADC reading with and without calibration,
+//! to see how far the chip's own conversion is off.
+
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use crate::json::escape;
+
+/// MQTT client health
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MqttDiag {
+    /// Broker host name or address
+    pub broker: String,
+    pub port: u16,
+    /// Currently connected to the broker
+    pub connected: bool,
+    /// Seconds since the current connection was established (0 if down)
+    pub connected_secs: u64,
+    /// Messages published since boot
+    pub published: u32,
+    /// Messages received since boot
+    pub received: u32,
+    /// Most recent connection error, kept after reconnecting
+    pub last_error: Option<String>,
+    /// Messages and bytes by topic class
+    pub traffic: Traffic,
+}
+
+/// Kind of MQTT topic, for the traffic counters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TopicClass {
+    /// `watercontroller/state`, the sensor state document
+    State,
+    /// `homeassistant/...` discovery configs
+    Discovery,
+    /// `watercontroller/set/...` and `watercontroller/cmd/...`
+    Command,
+    /// `watercontroller/config/...` desired, reported and trial configuration
+    Config,
+    /// `watercontroller/diag` and `watercontroller/diag/...`
+    Diagnostics,
+    /// Everything else: statistics, probes, events, retained entity states
+    Other,
+}
+
+impl TopicClass {
+    pub const ALL: [TopicClass; 6] = [
+        TopicClass::State,
+        TopicClass::Discovery,
+        TopicClass::Command,
+        TopicClass::Config,
+        TopicClass::Diagnostics,
+        TopicClass::Other,
+    ];
+
+    pub fn of(topic: &str) -> Self {
+        match topic {
+            "watercontroller/state" => TopicClass::State,
+            "watercontroller/diag" => TopicClass::Diagnostics,
+            _ if topic.starts_with("watercontroller/diag/") => TopicClass::Diagnostics,
+            _ if topic.starts_with("homeassistant/") => TopicClass::Discovery,
+            _ if topic.starts_with("watercontroller/set/") || topic.starts_with("watercontroller/cmd/") => TopicClass::Command,
+            _ if topic.starts_with("watercontroller/config/") => TopicClass::Config,
+            _ => TopicClass::Other,
+        }
+    }
+
+    /// Key in `/api/diag`
+    pub fn key(self) -> &'static str {
+        match self {
+            TopicClass::State => "state",
+            TopicClass::Discovery => "discovery",
+            TopicClass::Command => "command",
+            TopicClass::Config => "config",
+            TopicClass::Diagnostics => "diag",
+            TopicClass::Other => "other",
+        }
+    }
+}
+
+/// Message count and size
+///
+/// Bytes are topic plus payload, leaving out the few bytes of MQTT framing.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counter {
+    pub messages: u32,
+    pub bytes: u64,
+}
+
+impl Counter {
+    fn add(&mut self, bytes: usize) {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+    }
+
+    /// Bytes per minute over `secs`
+    pub fn bytes_per_min(&self, secs: u64) -> u64 {
+        if secs == 0 {
+            return 0;
+        }
+        self.bytes * 60 / secs
+    }
+}
+
+/// MQTT traffic since boot, by topic class
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Traffic {
+    /// Indexed like `TopicClass::ALL`
+    pub published: [Counter; 6],
+    pub received: [Counter; 6],
+}
+
+impl Traffic {
+    pub fn record_published(&mut self, topic: &str, payload_len: usize) {
+        self.published[TopicClass::of(topic) as usize].add(topic.len() + payload_len);
+    }
+
+    /// `topic` is `None` for the later chunks of a fragmented message, counted as `Other`
+    pub fn record_received(&mut self, topic: Option<&str>, payload_len: usize) {
+        let class = topic.map(TopicClass::of).unwrap_or(TopicClass::Other);
+        self.received[class as usize].add(topic.map_or(0, str::len) + payload_len);
+    }
+
+    pub fn total_published(&self) -> Counter {
+        Self::total(&self.published)
+    }
+
+    pub fn total_received(&self) -> Counter {
+        Self::total(&self.received)
+    }
+
+    fn total(counters: &[Counter]) -> Counter {
+        counters.iter().fold(Counter::default(), |sum, c| Counter { messages: sum.messages + c.messages, bytes: sum.bytes + c.bytes })
+    }
+
+    /// `{"state":{"tx_msgs":..,"tx_bytes":..,"rx_msgs":..,"rx_bytes":..},...}`
+    fn to_json(&self) -> String {
+        let classes: Vec<String> = TopicClass::ALL
+            .iter()
+            .zip(self.published.iter().zip(&self.received))
+            .map(|(class, (tx, rx))| {
+                format!(
+                    r#""{}":{{"tx_msgs":{},"tx_bytes":{},"rx_msgs":{},"rx_bytes":{}}}"#,
+                    class.key(),
+                    tx.messages,
+                    tx.bytes,
+                    rx.messages,
+                    rx.bytes
+                )
+            })
+            .collect();
+        format!("{{{}}}", classes.join(","))
+    }
+}
+
+/// Cellular backup link (see `cellular`)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CellularDiag {
+    /// `standby`, `dialing` or `online`
+    pub state: &'static str,
+    /// Signal strength at the last dial, `None` if unknown
+    pub signal_dbm: Option<i16>,
+    /// Bytes sent and received over the link this month
+    pub used_bytes: u64,
+    /// Monthly data budget (0 = unlimited)
+    pub budget_bytes: u64,
+}
+
+/// Radar sensor Modbus counters (see `sen0676`)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RadarDiag {
+    /// Transactions since boot, not counting retries
+    pub requests: u32,
+    /// Attempts repeated after a transient error
+    pub retries: u32,
+    /// Replies with a bad CRC
+    pub crc_errors: u32,
+    /// Replies that didn't arrive in time
+    pub timeouts: u32,
+    /// Transactions that failed every attempt
+    pub failures: u32,
+}
+
+/// Pressure sensor ADC reading (see `pressure`), averaged at the ADC pin
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PressureDiag {
+    /// ADC counts, 0-4095
+    pub raw: u16,
+    /// Counts converted without calibration (mV)
+    pub uncalibrated_mv: u16,
+    /// Counts converted by the calibration scheme, as used for pressure (mV)
+    pub calibrated_mv: u16,
+    /// `line fitting`, or `none` without eFuse values
+    pub calibration: &'static str,
+}
+
+/// Display driver flush counters
+///
+/// Only flushes that sent at least one line are counted, so the timings
+/// aren't diluted by idle frames.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FlushStats {
+    /// Flushes that sent lines
+    pub flushes: u32,
+    /// Lines sent since boot
+    pub lines: u32,
+    /// Bytes written to the panel since boot
+    pub bytes: u64,
+    /// Shortest flush
+    pub min: Duration,
+    /// Longest flush
+    pub max: Duration,
+    /// Time spent flushing since boot
+    pub total: Duration,
+}
+
+impl FlushStats {
+    /// Count a flush that sent `lines` lines, `bytes` in all, in `elapsed`
+    pub fn record(&mut self, lines: u16, bytes: usize, elapsed: Duration) {
+        if lines == 0 {
+            return;
+        }
+        self.min = if self.flushes == 0 { elapsed } else { self.min.min(elapsed) };
+        self.max = self.max.max(elapsed);
+        self.total += elapsed;
+        self.flushes = self.flushes.saturating_add(1);
+        self.lines = self.lines.saturating_add(lines as u32);
+        self.bytes += bytes as u64;
+    }
+
+    /// Mean flush duration
+    pub fn avg(&self) -> Duration {
+        if self.flushes == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.flushes
+        }
+    }
+}
+
+/// Duration of each init phase of the last boot
+///
+/// Phases are timed from `begin` to `end`, or to the next `begin`. Init work
+/// that isn't in a phase (sensors, the web server) shows up as the rest of
+/// the total. The report stays open after the main loop starts, for the
+/// Home Assistant discovery phase, until `finish`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StartupTiming {
+    /// Uptime when `main` started: bootloader, image load and ESP-IDF init
+    pub before_main: Duration,
+    /// Finished phases in order
+    pub phases: Vec<(&'static str, Duration)>,
+    /// Uptime at `finish`, `None` while still starting up
+    pub total: Option<Duration>,
+    /// Phase being timed and its start
+    current: Option<(&'static str, Duration)>,
+}
+
+impl StartupTiming {
+    /// Start timing at uptime `now`
+    pub fn new(now: Duration) -> Self {
+        Self { before_main: now, ..Default::default() }
+    }
+
+    /// Start a phase, ending the one before
+    pub fn begin(&mut self, name: &'static str, now: Duration) {
+        self.end(now);
+        self.current = Some((name, now));
+    }
+
+    /// End the running phase
+    pub fn end(&mut self, now: Duration) {
+        if let Some((name, started)) = self.current.take() {
+            self.phases.push((name, now.saturating_sub(started)));
+        }
+    }
+
+    /// End the report; later calls keep the first total
+    pub fn finish(&mut self, now: Duration) {
+        if self.total.is_none() {
+            self.end(now);
+            self.total = Some(now);
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.total.is_some()
+    }
+
+    /// Longest phase
+    pub fn slowest(&self) -> Option<(&'static str, Duration)> {
+        self.phases.iter().copied().max_by_key(|&(_, d)| d)
+    }
+
+    /// Time outside `before_main` and the phases
+    pub fn rest(&self) -> Option<Duration> {
+        let timed = self.before_main + self.phases.iter().map(|&(_, d)| d).sum::<Duration>();
+        self.total.map(|total| total.saturating_sub(timed))
+    }
+
+    /// `{"total_ms":..,"before_main_ms":..,"rest_ms":..,"phases":{"nvs":..,...}}`
+    pub fn to_json(&self) -> String {
+        let ms = |d: Option<Duration>| d.map_or("null".to_string(), |d| d.as_millis().to_string());
+        let phases: Vec<String> = self.phases.iter().map(|(name, d)| format!(r#""{}":{}"#, name, d.as_millis())).collect();
+        format!(
+            r#"{{"total_ms":{},"before_main_ms":{},"rest_ms":{},"phases":{{{}}}}}"#,
+            ms(self.total),
+            self.before_main.as_millis(),
+            ms(self.rest()),
+            phases.join(",")
+        )
+    }
+
+    /// One line for the log: `4.2 s: before main 0.3 s, nvs 0.1 s, ...`
+    pub fn summary(&self) -> String {
+        let secs = |d: Duration| format!("{:.1} s", d.as_secs_f32());
+        let mut parts = vec![format!("before main {}", secs(self.before_main))];
+        parts.extend(self.phases.iter().map(|&(name, d)| format!("{} {}", name, secs(d))));
+        if let Some(rest) = self.rest() {
+            parts.push(format!("rest {}", secs(rest)));
+        }
+        match self.total {
+            Some(total) => format!("{}: {}", secs(total), parts.join(", ")),
+            None => format!("starting: {}", parts.join(", ")),
+        }
+    }
+}
+
+/// Diagnostics for `/api/diag` and the display page
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Diagnostics {
+    /// Seconds since boot
+    pub uptime_secs: u64,
+    /// Current free heap (bytes)
+    pub free_heap: u32,
+    /// Lowest free heap since boot (bytes)
+    pub min_free_heap: u32,
+    /// Carrier board revision, from the ID straps
+    pub board: &'static str,
+    /// Running firmware version
+    pub firmware: &'static str,
+    /// Why the chip last reset (see `reset_reason`)
+    pub reset_reason: &'static str,
+    /// Ethernet link up with an address
+    pub link_up: bool,
+    /// Current address, `None` while waiting for DHCP
+    pub ip: Option<Ipv4Addr>,
+    /// `None` when MQTT is not configured
+    pub mqtt: Option<MqttDiag>,
+    /// `None` without a display
+    pub display: Option<FlushStats>,
+    /// `None` without the `cellular` feature
+    pub cellular: Option<CellularDiag>,
+    /// `None` without the `radar` feature
+    pub radar: Option<RadarDiag>,
+    /// `None` without the `pressure` feature
+    pub pressure: Option<PressureDiag>,
+    /// Init phase timings of this boot
+    pub startup: Option<StartupTiming>,
+}
+
+impl Diagnostics {
+    /// JSON document served at `/api/diag`
+    pub fn to_json(&self) -> String {
+        let mqtt = match &self.mqtt {
+            Some(m) => format!(
+                r#"{{"broker":"{}","port":{},"connected":{},"connected_secs":{},"published":{},"received":{},"last_error":{},"tx_bytes_per_min":{},"rx_bytes_per_min":{},"traffic":{}}}"#,
+                escape(&m.broker),
+                m.port,
+                m.connected,
+                m.connected_secs,
+                m.published,
+                m.received,
+                match &m.last_error {
+                    Some(e) => format!(r#""{}""#, escape(e)),
+                    None => "null".to_string(),
+                },
+                m.traffic.total_published().bytes_per_min(self.uptime_secs),
+                m.traffic.total_received().bytes_per_min(self.uptime_secs),
+                m.traffic.to_json(),
+            ),
+            None => "null".to_string(),
+        };
+        let display = match &self.display {
+            Some(d) => format!(
+                r#"{{"flushes":{},"lines":{},"bytes":{},"flush_us_min":{},"flush_us_avg":{},"flush_us_max":{}}}"#,
+                d.flushes,
+                d.lines,
+                d.bytes,
+                d.min.as_micros(),
+                d.avg().as_micros(),
+                d.max.as_micros(),
+            ),
+            None => "null".to_string(),
+        };
+        let cellular = match &self.cellular {
+            Some(c) => format!(
+                r#"{{"state":"{}","signal_dbm":{},"used_bytes":{},"budget_bytes":{}}}"#,
+                c.state,
+                c.signal_dbm.map_or("null".to_string(), |dbm| dbm.to_string()),
+                c.used_bytes,
+                c.budget_bytes,
+            ),
+            None => "null".to_string(),
+        };
+        let radar = match &self.radar {
+            Some(r) => format!(
+                r#"{{"requests":{},"retries":{},"crc_errors":{},"timeouts":{},"failures":{}}}"#,
+                r.requests, r.retries, r.crc_errors, r.timeouts, r.failures,
+            ),
+            None => "null".to_string(),
+        };
+        let pressure = match &self.pressure {
+            Some(p) => format!(
+                r#"{{"raw":{},"uncalibrated_mv":{},"calibrated_mv":{},"calibration":"{}"}}"#,
+                p.raw, p.uncalibrated_mv, p.calibrated_mv, p.calibration,
+            ),
+            None => "null".to_string(),
+        };
+        let ip = match self.ip {
+            Some(ip) => format!(r#""{}""#, ip),
+            None => "null".to_string(),
+        };
+        let startup = self.startup.as_ref().map_or("null".to_string(), StartupTiming::to_json);
+        format!(
+            r#"{{"uptime_secs":{},"free_heap":{},"min_free_heap":{},"firmware":"{}","reset_reason":"{}","startup":{},"link_up":{},"ip":{},"board":"{}","mqtt":{},"display":{},"cellular":{},"radar":{},"pressure":{}}}"#,
+            self.uptime_secs,
+            self.free_heap,
+            self.min_free_heap,
+            self.firmware,
+            self.reset_reason,
+            startup,
+            self.link_up,
+            ip,
+            self.board,
+            mqtt,
+            display,
+            cellular,
+            radar,
+            pressure
+        )
+    }
+
+    /// Text lines for the display diagnostics page
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Uptime: {}", format_duration(self.uptime_secs)),
+            format!("Heap: {} KB (min {} KB)", self.free_heap / 1024, self.min_free_heap / 1024),
+            format!("Board: {}", self.board),
+            format!("FW: v{} ({})", self.firmware, self.reset_reason),
+        ];
+        match &self.mqtt {
+            Some(m) => {
+                lines.push(format!("MQTT: {}:{}", m.broker, m.port));
+                lines.push(if m.connected {
+                    format!("Connected {}", format_duration(m.connected_secs))
+                } else {
+                    "Disconnected".to_string()
+                });
+                lines.push(format!("Pub {}  Rx {}", m.published, m.received));
+                let (tx, rx) = (m.traffic.total_published(), m.traffic.total_received());
+                lines.push(format!(
+                    "B/min: tx {} rx {}",
+                    tx.bytes_per_min(self.uptime_secs),
+                    rx.bytes_per_min(self.uptime_secs)
+                ));
+                if let Some(e) = &m.last_error {
+                    lines.push(format!("Err: {}", e));
+                }
+            }
+            None => lines.push("MQTT: not configured".to_string()),
+        }
+        if let Some(d) = &self.display {
+            lines.push(format!("Flush: {} lines, {} KB", d.lines, d.bytes / 1024));
+            lines.push(format!(
+                "Flush ms: {:.1}/{:.1}/{:.1}",
+                d.min.as_secs_f32() * 1000.0,
+                d.avg().as_secs_f32() * 1000.0,
+                d.max.as_secs_f32() * 1000.0
+            ));
+        }
+        if let Some(c) = &self.cellular {
+            lines.push(match c.signal_dbm {
+                Some(dbm) => format!("Cell: {} ({} dBm)", c.state, dbm),
+                None => format!("Cell: {}", c.state),
+            });
+            let mb = |bytes: u64| bytes as f32 / 1_000_000.0;
+            lines.push(if c.budget_bytes > 0 {
+                format!("Data: {:.1}/{:.0} MB", mb(c.used_bytes), mb(c.budget_bytes))
+            } else {
+                format!("Data: {:.1} MB", mb(c.used_bytes))
+            });
+        }
+        if let Some(r) = &self.radar {
+            lines.push(format!("Radar: {} req, {} retry", r.requests, r.retries));
+            if r.failures > 0 {
+                lines.push(format!("Radar fail: {} ({} CRC, {} t/o)", r.failures, r.crc_errors, r.timeouts));
+            }
+        }
+        if let Some(p) = &self.pressure {
+            lines.push(format!("ADC: {} mV ({} raw {} mV)", p.calibrated_mv, p.raw, p.uncalibrated_mv));
+        }
+        if let Some(startup) = &self.startup {
+            let total = startup.total.map_or("...".to_string(), |t| format!("{:.1} s", t.as_secs_f32()));
+            lines.push(match startup.slowest() {
+                Some((name, d)) => format!("Boot: {} ({} {:.1} s)", total, name, d.as_secs_f32()),
+                None => format!("Boot: {}", total),
+            });
+        }
+        lines
+    }
+}
+
+/// Why the chip last reset
+#[cfg(target_os = "espidf")]
+pub fn reset_reason() -> &'static str {
+    reset_reason_name(unsafe { esp_idf_svc::sys::esp_reset_reason() })
+}
+
+/// Name of an `esp_reset_reason_t`
+pub fn reset_reason_name(reason: u32) -> &'static str {
+    match reason {
+        1 => "power on",
+        2 => "external pin",
+        3 => "software",
+        4 => "panic",
+        5 => "interrupt watchdog",
+        6 => "task watchdog",
+        7 => "watchdog",
+        8 => "deep sleep",
+        9 => "brownout",
+        10 => "SDIO",
+        _ => "unknown",
+    }
+}
+
+/// `3d 04:05` / `04:05:06`
+fn format_duration(secs: u64) -> String {
+    let (days, hours, mins) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{}d {:02}:{:02}", days, hours, mins)
+    } else {
+        format!("{:02}:{:02}:{:02}", hours, mins, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_escapes_error() {
+        let diag = Diagnostics {
+            uptime_secs: 90,
+            free_heap: 50_000,
+            min_free_heap: 40_000,
+            board: "rev B",
+            mqtt: Some(MqttDiag {
+                broker: "ha.local".to_string(),
+                port: 1883,
+                last_error: Some("bad \"auth\"\n".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let json = diag.to_json();
+        assert!(json.contains(r#""last_error":"bad \"auth\"\u000a""#), "{}", json);
+        assert!(json.contains(r#""board":"rev B","mqtt":{"broker":"ha.local","port":1883,"connected":false"#));
+        assert_eq!(
+            Diagnostics::default().to_json(),
+            r#"{"uptime_secs":0,"free_heap":0,"min_free_heap":0,"firmware":"","reset_reason":"","startup":null,"link_up":false,"ip":null,"board":"","mqtt":null,"display":null,"cellular":null,"radar":null,"pressure":null}"#
+        );
+        let linked = Diagnostics { link_up: true, ip: Some(Ipv4Addr::new(192, 168, 1, 20)), ..Default::default() };
+        assert!(linked.to_json().contains(r#""link_up":true,"ip":"192.168.1.20""#));
+
+        let cell = CellularDiag { state: "online", signal_dbm: Some(-85), used_bytes: 12_345_678, budget_bytes: 100_000_000 };
+        let cellular = Diagnostics { cellular: Some(cell), ..Default::default() };
+        assert!(cellular.to_json().ends_with(r#""cellular":{"state":"online","signal_dbm":-85,"used_bytes":12345678,"budget_bytes":100000000},"radar":null,"pressure":null}"#));
+        assert_eq!(cellular.lines()[5..], ["Cell: online (-85 dBm)", "Data: 12.3/100 MB"]);
+
+        let counters = RadarDiag { requests: 500, retries: 7, crc_errors: 5, timeouts: 4, failures: 1 };
+        let radar = Diagnostics { radar: Some(counters), ..Default::default() };
+        assert!(radar.to_json().ends_with(r#""radar":{"requests":500,"retries":7,"crc_errors":5,"timeouts":4,"failures":1},"pressure":null}"#));
+        assert_eq!(radar.lines()[5..], ["Radar: 500 req, 7 retry", "Radar fail: 1 (5 CRC, 4 t/o)"]);
+
+        let adc = PressureDiag { raw: 1200, uncalibrated_mv: 717, calibrated_mv: 781, calibration: "line fitting" };
+        let pressure = Diagnostics { pressure: Some(adc), ..Default::default() };
+        assert!(pressure.to_json().ends_with(
+            r#""pressure":{"raw":1200,"uncalibrated_mv":717,"calibrated_mv":781,"calibration":"line fitting"}}"#
+        ));
+        assert_eq!(pressure.lines()[5..], ["ADC: 781 mV (1200 raw 717 mV)"]);
+    }
+
+    #[test]
+    fn test_flush_stats() {
+        let mut stats = FlushStats::default();
+        assert_eq!(stats.avg(), Duration::ZERO);
+        stats.record(0, 0, Duration::from_micros(50));
+        assert_eq!(stats, FlushStats::default());
+        stats.record(10, 520, Duration::from_millis(4));
+        stats.record(240, 12_480, Duration::from_millis(30));
+        stats.record(2, 104, Duration::from_millis(2));
+        assert_eq!((stats.flushes, stats.lines, stats.bytes), (3, 252, 13_104));
+        let ms = Duration::from_millis;
+        assert_eq!((stats.min, stats.avg(), stats.max), (ms(2), ms(12), ms(30)));
+
+        let diag = Diagnostics { display: Some(stats), ..Default::default() };
+        assert!(diag.to_json().ends_with(
+            r#""display":{"flushes":3,"lines":252,"bytes":13104,"flush_us_min":2000,"flush_us_avg":12000,"flush_us_max":30000},"cellular":null,"radar":null,"pressure":null}"#
+        ));
+        assert_eq!(diag.lines()[5..], ["Flush: 252 lines, 12 KB", "Flush ms: 2.0/12.0/30.0"]);
+    }
+
+    #[test]
+    fn test_startup_timing() {
+        let ms = Duration::from_millis;
+        let mut startup = StartupTiming::new(ms(320));
+        startup.begin("nvs", ms(330));
+        startup.begin("display", ms(400));
+        startup.end(ms(1_200));
+        startup.begin("dhcp", ms(1_500));
+        startup.end(ms(9_500));
+        assert!(!startup.is_finished());
+        assert_eq!(startup.rest(), None);
+        assert!(startup.to_json().starts_with(r#"{"total_ms":null,"before_main_ms":320,"rest_ms":null,"#));
+
+        startup.begin("discovery", ms(10_000));
+        startup.finish(ms(12_000));
+        startup.finish(ms(15_000));
+        assert_eq!(startup.total, Some(ms(12_000)));
+        assert_eq!(startup.slowest(), Some(("dhcp", ms(8_000))));
+        // 330 to 400 ms is in nvs, 1.2 s to 1.5 s and 9.5 s to 10 s are not timed
+        assert_eq!(startup.rest(), Some(ms(12_000 - 320 - 70 - 800 - 8_000 - 2_000)));
+        assert_eq!(
+            startup.to_json(),
+            r#"{"total_ms":12000,"before_main_ms":320,"rest_ms":810,"phases":{"nvs":70,"display":800,"dhcp":8000,"discovery":2000}}"#
+        );
+        assert_eq!(
+            startup.summary(),
+            "12.0 s: before main 0.3 s, nvs 0.1 s, display 0.8 s, dhcp 8.0 s, discovery 2.0 s, rest 0.8 s"
+        );
+
+        let diag = Diagnostics { startup: Some(startup), ..Default::default() };
+        assert!(diag.to_json().contains(r#""reset_reason":"","startup":{"total_ms":12000,"#));
+        assert_eq!(diag.lines()[5..], ["Boot: 12.0 s (dhcp 8.0 s)"]);
+    }
+
+    #[test]
+    fn test_traffic_by_topic_class() {
+        let mut traffic = Traffic::default();
+        traffic.record_published("watercontroller/state", 179);
+        traffic.record_published("watercontroller/state", 179);
+        traffic.record_published("homeassistant/sensor/watercontroller_pressure/config", 248);
+        traffic.record_published("watercontroller/probes", 20);
+        traffic.record_received(Some("watercontroller/set/pump_start"), 2);
+        traffic.record_received(None, 100);
+        assert_eq!(traffic.published[TopicClass::State as usize], Counter { messages: 2, bytes: 400 });
+        assert_eq!(traffic.published[TopicClass::Discovery as usize], Counter { messages: 1, bytes: 300 });
+        assert_eq!(traffic.published[TopicClass::Other as usize], Counter { messages: 1, bytes: 42 });
+        assert_eq!(traffic.total_published(), Counter { messages: 4, bytes: 742 });
+        assert_eq!(traffic.received[TopicClass::Command as usize], Counter { messages: 1, bytes: 32 });
+        assert_eq!(traffic.received[TopicClass::Other as usize], Counter { messages: 1, bytes: 100 });
+        assert_eq!(TopicClass::of("watercontroller/config/reported"), TopicClass::Config);
+        assert_eq!(TopicClass::of("watercontroller/cmd/reboot"), TopicClass::Command);
+
+        let diag = Diagnostics {
+            uptime_secs: 120,
+            mqtt: Some(MqttDiag { published: 4, received: 2, traffic, ..Default::default() }),
+            ..Default::default()
+        };
+        let json = diag.to_json();
+        assert!(json.contains(r#""tx_bytes_per_min":371,"rx_bytes_per_min":66,"traffic":{"state":{"tx_msgs":2,"tx_bytes":400,"rx_msgs":0,"rx_bytes":0},"discovery":"#), "{}", json);
+        assert!(json.contains(r#""other":{"tx_msgs":1,"tx_bytes":42,"rx_msgs":1,"rx_bytes":100}}}"#), "{}", json);
+        assert!(diag.lines().contains(&"B/min: tx 371 rx 66".to_string()));
+        assert_eq!(Counter::default().bytes_per_min(0), 0);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(3725), "01:02:05");
+        assert_eq!(format_duration(2 * 86400 + 3 * 3600 + 4 * 60), "2d 03:04");
+        assert_eq!(reset_reason_name(9), "brownout");
+        assert_eq!(reset_reason_name(42), "unknown");
+    }
+}
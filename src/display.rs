@@ -0,0 +1,108 @@
+//! Display panel registry
+//!
+//! The UI widgets draw onto any embedded-graphics `DrawTarget`. `Panel` adds
+//! the buffering operations the main loop needs on top of that, plus the widget
+//! palette that suits the panel, so the same widgets render on each backend.
+//!
+//! | Feature   | Panel                             | Driver               |
+//! |-----------|-----------------------------------|----------------------|
+//! | `display` | Sharp LS027B7DH01 400x240 mono    | `ls027b7dh01`        |
+//! | `tft`     | ILI9341 / ST7789 320x240 RGB565   | `tft`                |
+
+use embedded_graphics::draw_target::DrawTarget;
+use embedded_graphics::pixelcolor::BinaryColor;
+use esp_idf_svc::hal::gpio::OutputPin;
+use esp_idf_svc::hal::spi::SpiDriver;
+use esp_idf_svc::sys::EspError;
+
+use crate::diag::FlushStats;
+use crate::ls027b7dh01::Ls027b7dh01;
+use crate::ui::Palette;
+
+/// A framebuffered display the UI can be drawn on
+pub trait Panel: DrawTarget<Error = core::convert::Infallible> {
+    /// Widget colors for this panel
+    fn palette(&self) -> Palette<Self::Color>;
+    /// Clear the framebuffer to the palette background
+    fn clear_framebuffer(&mut self);
+    /// Force every line to be resent on the next flush
+    fn mark_all_dirty(&mut self);
+    /// Limit the number of lines sent per `flush()` (0 = unlimited)
+    fn set_flush_budget(&mut self, lines: u16);
+    /// Send dirty lines, within the flush budget
+    fn flush(&mut self) -> Result<(), EspError>;
+    /// Send all dirty lines, ignoring the flush budget
+    fn flush_all(&mut self) -> Result<(), EspError>;
+    /// Flush counters since boot
+    fn flush_stats(&self) -> &FlushStats;
+}
+
+impl<'d, SPI, CS> Panel for Ls027b7dh01<'d, SPI, CS>
+where
+    SPI: std::borrow::Borrow<SpiDriver<'d>>,
+    CS: OutputPin,
+{
+    fn palette(&self) -> Palette<BinaryColor> {
+        Palette::MONO
+    }
+
+    fn clear_framebuffer(&mut self) {
+        Ls027b7dh01::clear_framebuffer(self)
+    }
+
+    fn mark_all_dirty(&mut self) {
+        Ls027b7dh01::mark_all_dirty(self)
+    }
+
+    fn set_flush_budget(&mut self, lines: u16) {
+        Ls027b7dh01::set_flush_budget(self, lines)
+    }
+
+    fn flush(&mut self) -> Result<(), EspError> {
+        Ls027b7dh01::flush(self)
+    }
+
+    fn flush_all(&mut self) -> Result<(), EspError> {
+        Ls027b7dh01::flush_all(self)
+    }
+
+    fn flush_stats(&self) -> &FlushStats {
+        Ls027b7dh01::stats(self)
+    }
+}
+
+#[cfg(feature = "tft")]
+impl<'d, SPI, DC, RST> Panel for crate::tft::Tft<'d, SPI, DC, RST>
+where
+    SPI: std::borrow::Borrow<SpiDriver<'d>>,
+    DC: OutputPin,
+    RST: OutputPin,
+{
+    fn palette(&self) -> Palette<embedded_graphics::pixelcolor::Rgb565> {
+        Palette::COLOR
+    }
+
+    fn clear_framebuffer(&mut self) {
+        crate::tft::Tft::clear_framebuffer(self)
+    }
+
+    fn mark_all_dirty(&mut self) {
+        crate::tft::Tft::mark_all_dirty(self)
+    }
+
+    fn set_flush_budget(&mut self, lines: u16) {
+        crate::tft::Tft::set_flush_budget(self, lines)
+    }
+
+    fn flush(&mut self) -> Result<(), EspError> {
+        crate::tft::Tft::flush(self)
+    }
+
+    fn flush_all(&mut self) -> Result<(), EspError> {
+        crate::tft::Tft::flush_all(self)
+    }
+
+    fn flush_stats(&self) -> &FlushStats {
+        crate::tft::Tft::stats(self)
+    }
+}
@@ -0,0 +1,231 @@
+//! DS18B20 temperature probes on a 1-Wire bus
+//!
+//! Any number of probes share one bus on GPIO33 (4.7 kΩ pull-up to 3.3 V,
+//! driven by the RMT 1-Wire driver). A background thread rescans the bus on
+//! every poll, so probes can be added or swapped at run time, starts a
+//! conversion on all of them at once and then reads each by ROM code.
+//!
+//! Probes are identified by their 64-bit ROM code, written as 16 hex digits
+//! in bus order (family code `28` first). Names are assigned on the web UI
+//! and stored in NVS as `ROM=name` pairs separated by `;`, e.g.
+//! `28FF641E8216C3A1=well head;28FF0B3C8216C3F2=tank water`.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use esp_idf_svc::hal::onewire::{OWCommand, OWDriver};
+use esp_idf_svc::sys::EspError;
+use log::*;
+
+/// DS18B20 family code (low byte of the ROM code)
+const FAMILY_CODE: u8 = 0x28;
+/// Function commands
+const CONVERT_T: u8 = 0x44;
+const READ_SCRATCHPAD: u8 = 0xBE;
+/// Conversion time at the default 12-bit resolution
+const CONVERSION_TIME: Duration = Duration::from_millis(750);
+/// Power-on value of the temperature register (85 °C): no conversion done yet
+const POWER_ON_RAW: i16 = 0x0550;
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Probes that can be named (bounds the stored string)
+pub const MAX_PROBES: usize = 8;
+/// Longest probe name
+pub const MAX_NAME_LEN: usize = 32;
+
+/// A probe found on the last scan
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Probe {
+    pub rom: u64,
+    /// `None` if the probe didn't answer with a valid scratchpad
+    pub celsius: Option<f32>,
+}
+
+/// ROM code as 16 hex digits, family code first
+pub fn rom_to_hex(rom: u64) -> String {
+    rom.to_le_bytes().iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Parse a ROM code written by `rom_to_hex` (either case)
+pub fn parse_rom(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if s.len() != 16 || !s.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(u64::from_le_bytes(bytes))
+}
+
+/// Make a user supplied name safe to store and embed in JSON
+pub fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ';' | '=' | '"' | '\\') && !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Parse stored `ROM=name` pairs, skipping malformed entries
+pub fn parse_names(s: &str) -> Vec<(u64, String)> {
+    s.split(';')
+        .filter_map(|entry| {
+            let (rom, name) = entry.split_once('=')?;
+            let name = sanitize_name(name);
+            Some((parse_rom(rom)?, name)).filter(|(_, name)| !name.is_empty())
+        })
+        .take(MAX_PROBES)
+        .collect()
+}
+
+/// Format names for storage (inverse of `parse_names`)
+pub fn format_names(names: &[(u64, String)]) -> String {
+    names
+        .iter()
+        .map(|(rom, name)| format!("{}={}", rom_to_hex(*rom), name))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Name of a probe, or its ROM code if it hasn't been named
+pub fn probe_name(names: &[(u64, String)], rom: u64) -> String {
+    match names.iter().find(|(r, _)| *r == rom) {
+        Some((_, name)) => name.clone(),
+        None => format!("Probe {}", rom_to_hex(rom)),
+    }
+}
+
+/// Dallas/Maxim CRC-8 (polynomial x^8 + x^5 + x^4 + 1)
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x8C } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// Temperature from a 9-byte scratchpad, `None` if it fails the CRC or holds
+/// the power-on value
+fn decode_scratchpad(scratchpad: &[u8; 9]) -> Option<f32> {
+    if crc8(&scratchpad[..8]) != scratchpad[8] {
+        return None;
+    }
+    let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+    if raw == POWER_ON_RAW {
+        return None;
+    }
+    Some(raw as f32 / 16.0)
+}
+
+/// DS18B20 probes on a 1-Wire bus
+pub struct ProbeBus {
+    driver: OWDriver<'static>,
+}
+
+impl ProbeBus {
+    pub fn new(driver: OWDriver<'static>) -> Self {
+        Self { driver }
+    }
+
+    /// Scan the bus and read every DS18B20 on it
+    pub fn read_all(&mut self) -> Result<Vec<Probe>, EspError> {
+        let roms: Vec<u64> = self
+            .driver
+            .search()?
+            .filter_map(|address| address.ok())
+            .filter(|address| address.family_code() == FAMILY_CODE)
+            .map(|address| address.address())
+            .collect();
+        if roms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Convert on all probes at once
+        self.driver.reset()?;
+        self.driver.write(&[OWCommand::SkipRom as u8, CONVERT_T])?;
+        std::thread::sleep(CONVERSION_TIME);
+
+        let mut probes = Vec::with_capacity(roms.len());
+        for rom in roms {
+            let mut command = [0u8; 10];
+            command[0] = OWCommand::MatchRom as u8;
+            command[1..9].copy_from_slice(&rom.to_le_bytes());
+            command[9] = READ_SCRATCHPAD;
+            let mut scratchpad = [0u8; 9];
+            let celsius = self
+                .driver
+                .reset()
+                .and_then(|()| self.driver.write(&command))
+                .and_then(|()| self.driver.read(&mut scratchpad))
+                .ok()
+                .and_then(|()| decode_scratchpad(&scratchpad));
+            if celsius.is_none() {
+                debug!("DS18B20 {}: no valid reading", rom_to_hex(rom));
+            }
+            probes.push(Probe { rom, celsius });
+        }
+        Ok(probes)
+    }
+}
+
+/// Poll the probes in a background thread, publishing each scan to `probes`
+pub fn start(mut bus: ProbeBus, probes: Arc<Mutex<Vec<Probe>>>) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name("ds18b20".into())
+        .stack_size(4 * 1024)
+        .spawn(move || {
+            let mut count = None;
+            loop {
+                match bus.read_all() {
+                    Ok(found) => {
+                        if count != Some(found.len()) {
+                            info!("DS18B20: {} probe(s) on the bus", found.len());
+                            count = Some(found.len());
+                        }
+                        *probes.lock().unwrap() = found;
+                    }
+                    Err(e) => warn!("DS18B20: bus error: {:?}", e),
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_scratchpad() {
+        // Maxim AN27 example ROM code
+        assert_eq!(crc8(&[0x02, 0x1C, 0xB8, 0x01, 0x00, 0x00, 0x00]), 0xA2);
+        let plus = [0x91, 0x01, 0x4B, 0x46, 0x7F, 0xFF, 0x0F, 0x10, 0x25];
+        assert_eq!(decode_scratchpad(&plus), Some(25.0625));
+        let minus = [0x5E, 0xFF, 0x4B, 0x46, 0x7F, 0xFF, 0x02, 0x10, 0xB6];
+        assert_eq!(decode_scratchpad(&minus), Some(-10.125));
+        let mut corrupt = plus;
+        corrupt[0] ^= 0x01;
+        assert_eq!(decode_scratchpad(&corrupt), None);
+        assert_eq!(decode_scratchpad(&[0xFF; 9]), None);
+    }
+
+    #[test]
+    fn test_names_round_trip() {
+        let rom = parse_rom("28FF641E8216C3A1").unwrap();
+        assert_eq!(rom & 0xFF, FAMILY_CODE as u64);
+        assert_eq!(rom_to_hex(rom), "28FF641E8216C3A1");
+
+        let names = parse_names("28ff641e8216c3a1=well head;bogus=x;28FF0B3C8216C3F2= tank \"water\" ");
+        assert_eq!(names, vec![(rom, "well head".to_string()), (parse_rom("28FF0B3C8216C3F2").unwrap(), "tank water".to_string())]);
+        assert_eq!(format_names(&names), "28FF641E8216C3A1=well head;28FF0B3C8216C3F2=tank water");
+        assert_eq!(probe_name(&names, rom), "well head");
+        assert_eq!(probe_name(&names, 0x28), "Probe 2800000000000000");
+    }
+}
@@ -0,0 +1,275 @@
+//! Pump efficiency tracking
+//!
+//! Every fill cycle run by a single pump yields a delivery rate: the tank
+//! volume gained between start and stop over the pump's run time. Cycles
+//! are stored in the flash history, which keeps weeks of them, and each
+//! pump's rate over the last week is compared with the four weeks before.
+//! A pump whose weekly rate falls `drop_percent` below that baseline is
+//! flagged: a worn impeller or a clogged foot valve shows up as a slow,
+//! steady decline long before the pump fails outright.
+//!
+//! Water drawn while the tank fills counts against the rate, so single
+//! cycles are noisy; only the weekly means are compared, and only once both
+//! windows hold `MIN_CYCLES` cycles. Cycles where the lag pump assisted
+//! can't be attributed to either pump and are left out.
+//!
+//! # Energy
+//! With `pump_power`, the input power from the CT clamp (see `ct_clamp`) is
+//! fed in on every update while a pump runs and summed over the cycle, which
+//! then carries its energy. Dividing by the volume gives kWh per gallon for
+//! the last cycle and the last week; a pump that draws as much as ever but
+//! moves less water shows up here before its delivery rate crosses the
+//! drop alert. Cycles without a single power reading carry no energy.
+
+use std::time::Duration;
+
+use crate::pump::PUMP_COUNT;
+
+/// Shortest cycle that gives a usable rate
+const MIN_RUNTIME: Duration = Duration::from_secs(120);
+/// Recent window, compared with the `BASELINE_WEEKS` before it
+const WEEK_SECS: u32 = 7 * 24 * 3600;
+const BASELINE_WEEKS: u32 = 4;
+/// Cycles needed in each window before rates are compared
+pub const MIN_CYCLES: usize = 5;
+
+/// A completed single-pump fill cycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cycle {
+    /// Seconds since the Unix epoch at the end of the cycle
+    pub timestamp: u32,
+    /// Pump index
+    pub pump: u8,
+    /// Volume gained (0.1 gal)
+    pub delivered: u16,
+    pub runtime_secs: u16,
+    /// Pump energy over the cycle (0.1 Wh), when it was metered
+    pub energy: Option<u32>,
+}
+
+impl Cycle {
+    /// Delivery rate (gal/min)
+    pub fn gpm(&self) -> f32 {
+        self.delivered as f32 / 10.0 / (self.runtime_secs.max(1) as f32 / 60.0)
+    }
+
+    /// Energy per volume delivered (kWh/gal), when metered
+    pub fn kwh_per_gal(&self) -> Option<f32> {
+        Some(self.energy? as f32 / 10_000.0 / (self.delivered.max(1) as f32 / 10.0))
+    }
+}
+
+/// A cycle in progress
+#[derive(Debug, Clone, Copy)]
+struct Started {
+    at: Duration,
+    gallons: u16,
+    /// Pumps that ran at any point in the cycle
+    ran: [bool; PUMP_COUNT],
+    /// Energy so far (Wh), from the first power reading
+    energy_wh: Option<f32>,
+    /// Time up to which the energy is counted
+    metered_at: Duration,
+}
+
+/// Turns relay states and tank volume into completed cycles
+#[derive(Debug, Default)]
+pub struct CycleTracker {
+    current: Option<Started>,
+}
+
+impl CycleTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the relay states and tank volume, returning a cycle when one ends
+    ///
+    /// `None` for cycles too short to measure, cycles that didn't raise the
+    /// level and cycles more than one pump ran in.
+    pub fn update(&mut self, running: [bool; PUMP_COUNT], gallons: u16, now: Duration, timestamp: u32) -> Option<Cycle> {
+        let any = running.iter().any(|&r| r);
+        match self.current.as_mut() {
+            None => {
+                if any {
+                    self.current = Some(Started { at: now, gallons, ran: running, energy_wh: None, metered_at: now });
+                }
+                None
+            }
+            Some(started) if any => {
+                for (ran, &on) in started.ran.iter_mut().zip(&running) {
+                    *ran |= on;
+                }
+                None
+            }
+            Some(_) => {
+                let started = self.current.take()?;
+                let runtime = now.saturating_sub(started.at);
+                let mut pumps = started.ran.iter().enumerate().filter(|(_, &ran)| ran);
+                let (pump, _) = pumps.next()?;
+                if pumps.next().is_some() || runtime < MIN_RUNTIME || gallons <= started.gallons {
+                    return None;
+                }
+                Some(Cycle {
+                    timestamp,
+                    pump: pump as u8,
+                    delivered: ((gallons - started.gallons) as u32 * 10).min(u16::MAX as u32) as u16,
+                    runtime_secs: runtime.as_secs().min(u16::MAX as u64) as u16,
+                    energy: started.energy_wh.map(|wh| (wh * 10.0).round().min(u32::MAX as f32) as u32),
+                })
+            }
+        }
+    }
+
+    /// Feed the pump input power read at `now`, taken as the power since the
+    /// previous reading; ignored outside a cycle
+    ///
+    /// Read it before the relays change, so the last reading of a cycle is
+    /// taken with the pump still running.
+    pub fn meter(&mut self, watts: f32, now: Duration) {
+        let Some(started) = self.current.as_mut() else {
+            return;
+        };
+        let hours = now.saturating_sub(started.metered_at).as_secs_f32() / 3600.0;
+        started.energy_wh = Some(started.energy_wh.unwrap_or(0.0) + watts.max(0.0) * hours);
+        started.metered_at = now;
+    }
+}
+
+/// Delivery rate trend of one pump
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PumpTrend {
+    /// Rate of the latest cycle (gal/min)
+    pub last_gpm: Option<f32>,
+    /// Mean rate over the last week
+    pub week_gpm: Option<f32>,
+    /// Mean rate over the four weeks before
+    pub baseline_gpm: Option<f32>,
+    /// Weekly rate `drop_percent` or more below the baseline
+    pub degraded: bool,
+    /// Energy per volume of the latest cycle (kWh/gal)
+    pub last_kwh_per_gal: Option<f32>,
+    /// Energy over volume of the last week's metered cycles
+    pub week_kwh_per_gal: Option<f32>,
+}
+
+impl PumpTrend {
+    /// Trend of `pump` from stored cycles (oldest first)
+    ///
+    /// Cycles stamped after `now` (from an earlier boot with a wrong clock)
+    /// are ignored.
+    pub fn compute(cycles: &[Cycle], pump: u8, now: u32, drop_percent: u16) -> Self {
+        let ours = || cycles.iter().filter(move |c| c.pump == pump && c.timestamp <= now);
+        let week_start = now.saturating_sub(WEEK_SECS);
+        let baseline_start = week_start.saturating_sub(BASELINE_WEEKS * WEEK_SECS);
+        let mean = |from: u32, to: u32| {
+            let rates: Vec<f32> = ours().filter(|c| c.timestamp > from && c.timestamp <= to).map(Cycle::gpm).collect();
+            (rates.len() >= MIN_CYCLES).then(|| rates.iter().sum::<f32>() / rates.len() as f32)
+        };
+
+        let week_gpm = mean(week_start, now);
+        let baseline_gpm = mean(baseline_start, week_start);
+        let degraded = match (week_gpm, baseline_gpm) {
+            (Some(week), Some(baseline)) => week <= baseline * (1.0 - drop_percent as f32 / 100.0),
+            _ => false,
+        };
+        let metered: Vec<&Cycle> =
+            ours().filter(|c| c.timestamp > week_start && c.energy.is_some()).collect();
+        let week_kwh_per_gal = (metered.len() >= MIN_CYCLES).then(|| {
+            let kwh: f32 = metered.iter().filter_map(|c| c.energy).map(|e| e as f32 / 10_000.0).sum();
+            let gallons: f32 = metered.iter().map(|c| c.delivered as f32 / 10.0).sum();
+            kwh / gallons.max(0.1)
+        });
+        let last = ours().next_back();
+        Self {
+            last_gpm: last.map(Cycle::gpm),
+            week_gpm,
+            baseline_gpm,
+            degraded,
+            last_kwh_per_gal: last.and_then(Cycle::kwh_per_gal),
+            week_kwh_per_gal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u32 = 24 * 3600;
+    const NOW: u32 = 1_767_225_600;
+
+    #[test]
+    fn test_tracks_single_pump_cycles() {
+        let mut tracker = CycleTracker::new();
+        let secs = Duration::from_secs;
+        assert_eq!(tracker.update([true, false], 200, secs(0), NOW), None);
+        assert_eq!(tracker.update([true, false], 260, secs(150), NOW), None);
+        let cycle = tracker.update([false, false], 300, secs(300), NOW).unwrap();
+        assert_eq!((cycle.pump, cycle.delivered, cycle.runtime_secs), (0, 1000, 300));
+        assert_eq!(cycle.gpm(), 20.0);
+        assert_eq!((cycle.energy, cycle.kwh_per_gal()), (None, None));
+
+        // Lag assist mixes both pumps; a short cycle is too noisy
+        tracker.update([false, true], 200, secs(400), NOW);
+        tracker.update([true, true], 220, secs(500), NOW);
+        assert_eq!(tracker.update([false, false], 300, secs(700), NOW), None);
+        tracker.update([false, true], 200, secs(800), NOW);
+        assert_eq!(tracker.update([false, false], 210, secs(860), NOW), None);
+    }
+
+    #[test]
+    fn test_flags_weekly_rate_drop() {
+        let cycle = |age_days: u32, pump: u8, gpm: u16| Cycle {
+            timestamp: NOW - age_days * DAY,
+            pump,
+            delivered: gpm * 10 * 5,
+            runtime_secs: 300,
+            energy: None,
+        };
+        let mut cycles: Vec<Cycle> = (8..30).map(|d| cycle(d, 0, 20)).collect();
+        cycles.extend((0..6).map(|d| cycle(d, 0, 15)));
+        cycles.push(cycle(1, 1, 18));
+
+        let trend = PumpTrend::compute(&cycles, 0, NOW, 20);
+        assert_eq!((trend.week_gpm, trend.baseline_gpm), (Some(15.0), Some(20.0)));
+        assert!(trend.degraded);
+        assert!(!PumpTrend::compute(&cycles, 0, NOW, 30).degraded);
+        // Too few cycles to judge pump 2
+        let trend = PumpTrend::compute(&cycles, 1, NOW, 20);
+        assert_eq!((trend.last_gpm, trend.week_gpm, trend.degraded), (Some(18.0), None, false));
+    }
+
+    #[test]
+    fn test_meters_cycle_energy() {
+        let mut tracker = CycleTracker::new();
+        let secs = Duration::from_secs;
+        // Outside a cycle, nothing to count
+        tracker.meter(900.0, secs(0));
+        tracker.update([true, false], 100, secs(0), NOW);
+        tracker.meter(1200.0, secs(180));
+        tracker.meter(1200.0, secs(360));
+        // Relay off: the last reading still has the pump running
+        let cycle = tracker.update([false, false], 160, secs(360), NOW).unwrap();
+        // 1.2 kW for 6 minutes: 120 Wh for 60 gal
+        let near = |kwh: Option<f32>| kwh.is_some_and(|kwh| (kwh - 0.002).abs() < 1e-6);
+        assert_eq!(cycle.energy, Some(1200));
+        assert!(near(cycle.kwh_per_gal()));
+
+        let metered = |age_days: u32, energy: u32| Cycle {
+            timestamp: NOW - age_days * DAY,
+            pump: 0,
+            delivered: 500,
+            runtime_secs: 300,
+            energy: Some(energy),
+        };
+        let mut cycles: Vec<Cycle> = (1..6).map(|d| metered(d, 1000)).collect();
+        let trend = PumpTrend::compute(&cycles, 0, NOW, 20);
+        assert!(near(trend.last_kwh_per_gal) && near(trend.week_kwh_per_gal));
+        // An unmetered cycle counts for the rate only
+        cycles.push(Cycle { energy: None, ..metered(0, 0) });
+        let trend = PumpTrend::compute(&cycles, 0, NOW, 20);
+        assert_eq!(trend.last_kwh_per_gal, None);
+        assert!(near(trend.week_kwh_per_gal));
+    }
+}
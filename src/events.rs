@@ -0,0 +1,114 @@
+//! Server-sent events stream
+//!
+//! Browsers and scripts subscribe to `/events` with `EventSource` (or
+//! `curl -N`) and get the state document pushed each time the main loop
+//! publishes it, instead of polling `/api/status`. This keeps the client list
+//! and frames the events; the web server owns the sockets.
+//!
+//! # Wire format
+//! ```text
+//! retry: 5000
+//!
+//! event: state
+//! data: {"capacity_pct":72,...}
+//!
+//! ```
+
+/// Most subscribers at once; each holds one of the HTTP server's sockets
+pub const MAX_CLIENTS: usize = 2;
+
+/// Reconnect delay suggested to clients after the stream drops (ms)
+const RETRY_MS: u32 = 5000;
+
+/// Response head sent on subscribe, ahead of the stream itself
+pub const RESPONSE_HEAD: &str = "HTTP/1.1 200 OK\r\n\
+Content-Type: text/event-stream\r\n\
+Cache-Control: no-cache\r\n\
+Connection: keep-alive\r\n\
+Access-Control-Allow-Origin: *\r\n\r\n";
+
+/// Subscribers and the latest event
+#[derive(Debug, Default)]
+pub struct EventStream {
+    clients: Vec<i32>,
+    latest: Option<String>,
+}
+
+impl EventStream {
+    /// Add `client` (its socket), unless the stream is full
+    ///
+    /// Returns what to send it first: the response head, the retry hint and
+    /// the latest state, so a new dashboard doesn't wait for the next update.
+    pub fn subscribe(&mut self, client: i32) -> Option<String> {
+        if !self.clients.contains(&client) {
+            if self.clients.len() >= MAX_CLIENTS {
+                return None;
+            }
+            self.clients.push(client);
+        }
+        let mut greeting = format!("{}retry: {}\n\n", RESPONSE_HEAD, RETRY_MS);
+        if let Some(latest) = &self.latest {
+            greeting.push_str(latest);
+        }
+        Some(greeting)
+    }
+
+    /// Forget `client`, after it hung up or fell behind
+    pub fn unsubscribe(&mut self, client: i32) {
+        self.clients.retain(|c| *c != client);
+    }
+
+    /// Subscribed sockets
+    pub fn clients(&self) -> &[i32] {
+        &self.clients
+    }
+
+    /// Frame `data` as a `state` event and keep it for new subscribers
+    pub fn publish(&mut self, data: &str) -> String {
+        let event = frame("state", data);
+        self.latest = Some(event.clone());
+        event
+    }
+}
+
+/// One event; every line of `data` gets its own `data:` field
+pub fn frame(event: &str, data: &str) -> String {
+    let mut out = format!("event: {}\n", event);
+    for line in data.lines() {
+        out.push_str("data: ");
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        assert_eq!(frame("state", r#"{"a":1}"#), "event: state\ndata: {\"a\":1}\n\n");
+        assert_eq!(frame("state", "one\ntwo"), "event: state\ndata: one\ndata: two\n\n");
+    }
+
+    #[test]
+    fn test_subscribe_gets_latest_state() {
+        let mut stream = EventStream::default();
+        let greeting = stream.subscribe(54).unwrap();
+        assert!(greeting.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(greeting.ends_with("\r\n\r\nretry: 5000\n\n"));
+
+        let event = stream.publish(r#"{"capacity_pct":72}"#);
+        assert!(stream.subscribe(55).unwrap().ends_with(&event));
+        // Subscribing twice keeps one entry
+        stream.subscribe(55).unwrap();
+        assert_eq!(stream.clients(), &[54, 55]);
+        assert_eq!(stream.subscribe(56), None);
+
+        stream.unsubscribe(54);
+        assert!(stream.subscribe(56).is_some());
+        assert_eq!(stream.clients(), &[55, 56]);
+    }
+}
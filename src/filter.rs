@@ -0,0 +1,101 @@
+//! Radar level filtering
+//!
+//! Ripples and foam make the radar report surface distances that jump by tens
+//! of millimetres between reads. Readings pass through two stages before being
+//! converted to percent and gallons:
+//!
+//! 1. Median of the last `window` readings, which discards single outliers
+//! 2. Exponential smoothing (EWMA) of the median, which removes the remaining
+//!    jitter at the cost of some lag
+//!
+//! A window of 1 and a smoothing factor of 1.0 pass readings through unchanged.
+
+use std::collections::VecDeque;
+
+/// Largest supported median window
+pub const MAX_WINDOW: usize = 15;
+
+/// Median-of-N followed by exponential smoothing
+#[derive(Debug, Clone)]
+pub struct LevelFilter {
+    window: usize,
+    /// EWMA weight of the newest median (0 < alpha <= 1)
+    alpha: f32,
+    samples: VecDeque<u16>,
+    smoothed: Option<f32>,
+}
+
+impl LevelFilter {
+    pub fn new(window: usize, alpha: f32) -> Self {
+        let mut filter = Self {
+            window: 1,
+            alpha: 1.0,
+            samples: VecDeque::with_capacity(MAX_WINDOW),
+            smoothed: None,
+        };
+        filter.set_params(window, alpha);
+        filter
+    }
+
+    /// Change the window and smoothing factor, keeping the filter state
+    pub fn set_params(&mut self, window: usize, alpha: f32) {
+        self.window = window.clamp(1, MAX_WINDOW);
+        self.alpha = alpha.clamp(0.01, 1.0);
+        while self.samples.len() > self.window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Forget previous readings
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.smoothed = None;
+    }
+
+    /// Add a reading and return the filtered value
+    pub fn update(&mut self, sample: u16) -> u16 {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+
+        let mut sorted: Vec<u16> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let median = sorted[sorted.len() / 2] as f32;
+
+        let smoothed = match self.smoothed {
+            Some(prev) => prev + self.alpha * (median - prev),
+            // Start from the first median instead of ramping up from zero
+            None => median,
+        };
+        self.smoothed = Some(smoothed);
+        smoothed.round() as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median_rejects_outlier() {
+        let mut filter = LevelFilter::new(5, 1.0);
+        for mm in [1000, 1002, 998] {
+            filter.update(mm);
+        }
+        // A foam echo far from the surface doesn't move the output
+        assert_eq!(filter.update(400), 1000);
+        assert_eq!(filter.update(1001), 1000);
+    }
+
+    #[test]
+    fn test_smoothing_lags_step() {
+        let mut filter = LevelFilter::new(1, 0.5);
+        assert_eq!(filter.update(1000), 1000);
+        assert_eq!(filter.update(1100), 1050);
+        assert_eq!(filter.update(1100), 1075);
+        // Pass-through
+        filter.set_params(1, 1.0);
+        assert_eq!(filter.update(1200), 1200);
+    }
+}
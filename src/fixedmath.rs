@@ -0,0 +1,89 @@
+//! Fixed-point trigonometry for drawing
+//!
+//! The gauge places its ticks, labels and needle on a circle. Sines and
+//! cosines come from a quarter-wave table of whole degrees instead of `f32`
+//! math, interpolated linearly in tenths of a degree: no FPU or soft-float
+//! trig library needed, and identical results on the ESP32 and the host, so
+//! pixel tests pass on both. Values are Q14 fixed point (`ONE` = 1.0); the
+//! error stays under 0.0002, far below a pixel on any radius the display
+//! fits.
+
+/// 1.0 in Q14
+pub const ONE: i32 = 1 << 14;
+
+/// `sin(n°)` in Q14, n = 0..=90
+const SIN_TABLE: [i16; 91] = [
+    0, 286, 572, 857, 1143, 1428, 1713, 1997, 2280, 2563,
+    2845, 3126, 3406, 3686, 3964, 4240, 4516, 4790, 5063, 5334,
+    5604, 5872, 6138, 6402, 6664, 6924, 7182, 7438, 7692, 7943,
+    8192, 8438, 8682, 8923, 9162, 9397, 9630, 9860, 10087, 10311,
+    10531, 10749, 10963, 11174, 11381, 11585, 11786, 11982, 12176, 12365,
+    12551, 12733, 12911, 13085, 13255, 13421, 13583, 13741, 13894, 14044,
+    14189, 14330, 14466, 14598, 14726, 14849, 14968, 15082, 15191, 15296,
+    15396, 15491, 15582, 15668, 15749, 15826, 15897, 15964, 16026, 16083,
+    16135, 16182, 16225, 16262, 16294, 16322, 16344, 16362, 16374, 16382,
+    16384,
+];
+
+/// Sine of `decidegrees` tenths of a degree, any sign or turn count, in Q14
+pub fn sin(decidegrees: i32) -> i32 {
+    let angle = decidegrees.rem_euclid(3600);
+    // Fold into the first quadrant, remembering the sign
+    let (folded, negative) = match angle {
+        0..=900 => (angle, false),
+        901..=1800 => (1800 - angle, false),
+        1801..=2700 => (angle - 1800, true),
+        _ => (3600 - angle, true),
+    };
+    let (degree, tenth) = ((folded / 10) as usize, folded % 10);
+    let low = SIN_TABLE[degree] as i32;
+    let value = if tenth == 0 { low } else { low + (SIN_TABLE[degree + 1] as i32 - low) * tenth / 10 };
+    if negative {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Cosine of `decidegrees` tenths of a degree, in Q14
+pub fn cos(decidegrees: i32) -> i32 {
+    sin(decidegrees + 900)
+}
+
+/// Offset `radius` pixels from the center at `decidegrees`, counterclockwise
+/// from 3 o'clock with y pointing down as on the display
+///
+/// Each coordinate is cut toward zero, like an `f32` cast.
+pub fn polar(radius: i32, decidegrees: i32) -> (i32, i32) {
+    (radius * cos(decidegrees) / ONE, -(radius * sin(decidegrees) / ONE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_float_trig() {
+        for decidegrees in -3600..=7200 {
+            let radians = (decidegrees as f64 / 10.0).to_radians();
+            let sin_err = (sin(decidegrees) as f64 / ONE as f64 - radians.sin()).abs();
+            let cos_err = (cos(decidegrees) as f64 / ONE as f64 - radians.cos()).abs();
+            assert!(sin_err < 2e-4 && cos_err < 2e-4, "{} decidegrees", decidegrees);
+        }
+        assert_eq!(sin(0), 0);
+        assert_eq!(sin(900), ONE);
+        assert_eq!(sin(-900), -ONE);
+        assert_eq!(cos(1800), -ONE);
+    }
+
+    #[test]
+    fn test_polar() {
+        assert_eq!(polar(90, 0), (90, 0));
+        assert_eq!(polar(90, 900), (0, -90));
+        assert_eq!(polar(90, 1800), (-90, 0));
+        // 225°, the gauge's 0 PSI end: down and to the left
+        assert_eq!(polar(100, 2250), (-70, 70));
+        // -45°, the full-scale end
+        assert_eq!(polar(100, -450), (70, 70));
+    }
+}
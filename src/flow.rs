@@ -0,0 +1,181 @@
+//! Flow meter on the pulse counter
+//!
+//! A hall-effect flow sensor (YF-S201, FS300A and the like) puts out one
+//! pulse per fixed volume of water. The ESP32's PCNT peripheral counts them
+//! on GPIO15 in hardware, behind its glitch filter, so no pulse is lost
+//! however busy the main loop is. `FlowMeter` takes the count read at each
+//! update and turns it into the flow over the interval since the previous
+//! read (GPM) and a running total (gallons).
+//!
+//! The K-factor is the pulses per gallon (`flow_k_factor`). Datasheets give
+//! it as a frequency per L/min instead: 7.5 Hz per L/min is 450 pulses per
+//! liter, or 1703 per gallon, for a YF-S201; 5.5 Hz (1249 per gallon) for an
+//! FS300A. Cheap sensors vary by 10% or so, so filling a bucket of known
+//! size is worth it.
+//!
+//! The counter counts rising edges up to `COUNTER_LIMIT` and starts over at
+//! 0, and every read is taken against the previous one; at the few hundred
+//! hertz these sensors reach at full flow, it goes round only after a
+//! minute and a half between reads.
+//!
+//! # Wiring
+//! The sensors run from 5 V. An open-collector output pulls GPIO15 low
+//! against the internal pull-up the counter enables; a part with a pull-up
+//! of its own to 5 V needs the same 10k/12k divider as the pressure sensor.
+
+use std::time::Duration;
+
+/// Counter value at which the pulse counter starts over at 0
+pub const COUNTER_LIMIT: i16 = i16::MAX;
+/// K-factor of a YF-S201 (pulses per gallon)
+pub const DEFAULT_K_FACTOR: u16 = 1703;
+
+/// Flow rate and total from successive pulse counts
+#[derive(Debug, Clone)]
+pub struct FlowMeter {
+    /// Pulses per gallon
+    k_factor: u16,
+    /// Previous counter value and when it was read
+    last: Option<(i16, Duration)>,
+    gpm: f32,
+    total_gallons: f64,
+}
+
+impl FlowMeter {
+    /// Meter continuing from `total_gallons`, as saved before a restart
+    pub fn new(k_factor: u16, total_gallons: f64) -> Self {
+        Self { k_factor: k_factor.max(1), last: None, gpm: 0.0, total_gallons }
+    }
+
+    /// Apply a changed K-factor, from the next pulses on
+    pub fn set_k_factor(&mut self, k_factor: u16) {
+        self.k_factor = k_factor.max(1);
+    }
+
+    /// Feed the counter value read at `now`; returns the flow since the
+    /// previous read (GPM), 0 on the first
+    pub fn update(&mut self, count: i16, now: Duration) -> f32 {
+        let Some((previous, at)) = self.last.replace((count, now)) else {
+            return 0.0;
+        };
+        let pulses = (count as i32 - previous as i32).rem_euclid(COUNTER_LIMIT as i32);
+        let gallons = pulses as f64 / self.k_factor as f64;
+        self.total_gallons += gallons;
+        let minutes = now.saturating_sub(at).as_secs_f64() / 60.0;
+        self.gpm = if minutes > 0.0 { (gallons / minutes) as f32 } else { 0.0 };
+        self.gpm
+    }
+
+    /// Flow over the last interval (GPM)
+    pub fn gpm(&self) -> f32 {
+        self.gpm
+    }
+
+    /// Water through the meter, including the saved total (gallons)
+    pub fn total_gallons(&self) -> f64 {
+        self.total_gallons
+    }
+}
+
+#[cfg(target_os = "espidf")]
+pub use counter::PulseCounter;
+
+#[cfg(target_os = "espidf")]
+mod counter {
+    use esp_idf_svc::hal::gpio::{AnyInputPin, InputPin};
+    use esp_idf_svc::hal::pcnt::{
+        Pcnt, PcntChannel, PcntChannelConfig, PcntControlMode, PcntCountMode, PcntDriver, PinIndex,
+    };
+    use esp_idf_svc::hal::peripheral::Peripheral;
+    use esp_idf_svc::sys::EspError;
+
+    use super::COUNTER_LIMIT;
+
+    /// Pulses shorter than this are ignored (APB cycles at 80 MHz: 12.8 µs),
+    /// the most the filter takes
+    const FILTER_CYCLES: u16 = 1023;
+
+    /// Rising edges on one pin, counted by a PCNT unit
+    pub struct PulseCounter<'d> {
+        driver: PcntDriver<'d>,
+    }
+
+    impl<'d> PulseCounter<'d> {
+        /// Start counting on `pin`
+        pub fn new<PCNT: Pcnt>(
+            pcnt: impl Peripheral<P = PCNT> + 'd,
+            pin: impl Peripheral<P = impl InputPin> + 'd,
+        ) -> Result<Self, EspError> {
+            let mut driver = PcntDriver::new(
+                pcnt,
+                Some(pin),
+                Option::<AnyInputPin>::None,
+                Option::<AnyInputPin>::None,
+                Option::<AnyInputPin>::None,
+            )?;
+            driver.channel_config(
+                PcntChannel::Channel0,
+                PinIndex::Pin0,
+                PinIndex::Pin1,
+                &PcntChannelConfig {
+                    lctrl_mode: PcntControlMode::Keep,
+                    hctrl_mode: PcntControlMode::Keep,
+                    pos_mode: PcntCountMode::Increment,
+                    neg_mode: PcntCountMode::Hold,
+                    counter_h_lim: COUNTER_LIMIT,
+                    counter_l_lim: 0,
+                },
+            )?;
+            driver.set_filter_value(FILTER_CYCLES)?;
+            driver.filter_enable()?;
+            driver.counter_pause()?;
+            driver.counter_clear()?;
+            driver.counter_resume()?;
+            Ok(Self { driver })
+        }
+
+        /// Present counter value, 0 up to `COUNTER_LIMIT`
+        pub fn count(&self) -> Result<i16, EspError> {
+            self.driver.get_counter_value()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_rate_and_total() {
+        let mut meter = FlowMeter::new(1000, 250.0);
+        assert_eq!(meter.update(40, secs(10)), 0.0);
+        assert_eq!(meter.total_gallons(), 250.0);
+        // 500 pulses in 15 s: half a gallon, 2 GPM
+        assert_eq!(meter.update(540, secs(25)), 2.0);
+        assert_eq!(meter.total_gallons(), 250.5);
+        // No flow
+        assert_eq!(meter.update(540, secs(30)), 0.0);
+        assert_eq!(meter.gpm(), 0.0);
+        // Same instant: counted, no rate
+        assert_eq!(meter.update(640, secs(30)), 0.0);
+        assert!((meter.total_gallons() - 250.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_counter_wrap_and_k_factor() {
+        let mut meter = FlowMeter::new(DEFAULT_K_FACTOR, 0.0);
+        meter.update(COUNTER_LIMIT - 100, secs(0));
+        // Went past the limit and started over
+        meter.set_k_factor(400);
+        let gpm = meter.update(300, secs(60));
+        assert_eq!(gpm, 1.0);
+        assert_eq!(meter.total_gallons(), 1.0);
+        // A K-factor of 0 can't divide by zero
+        meter.set_k_factor(0);
+        assert_eq!(meter.update(302, secs(120)), 2.0);
+    }
+}
@@ -0,0 +1,197 @@
+//! Water hammer capture
+//!
+//! Pump starts and stops are where pressure transients come from: a check
+//! valve slamming shut, or an arrestor that has lost its air charge, shows up
+//! as a sharp spike above line pressure within a few hundred milliseconds of
+//! the relay switching. The regular 5-second readings never see it, so every
+//! relay change is followed by a burst of `BURST_SAMPLES` fast readings,
+//! `SAMPLE_INTERVAL` apart (one second in all).
+//!
+//! The baseline is the median of the first `BASELINE_SAMPLES` of the burst,
+//! taken before the pump has had time to move any water. A burst whose peak
+//! rises above the baseline by the configured threshold becomes a
+//! `HammerEvent` holding `WAVEFORM_LEN` samples around the peak.
+//!
+//! Pressures are kept in tenths of a PSI. Readings aren't clipped at the
+//! transducer's full scale (see `pressure`), but a spike that drives its
+//! output to the limit still peaks lower than the real one.
+
+use std::time::{Duration, Instant};
+
+/// Time between burst samples
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(2);
+/// Samples in one burst
+pub const BURST_SAMPLES: usize = 500;
+/// Leading samples the baseline is taken from
+const BASELINE_SAMPLES: usize = 10;
+/// Samples kept in an event
+pub const WAVEFORM_LEN: usize = 64;
+/// Waveform samples before the peak
+const PRE_PEAK: usize = 16;
+
+/// Relay change that started a burst
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cause {
+    PumpStart,
+    PumpStop,
+}
+
+impl Cause {
+    /// Event type for Home Assistant
+    pub fn name(self) -> &'static str {
+        match self {
+            Cause::PumpStart => "pump_start",
+            Cause::PumpStop => "pump_stop",
+        }
+    }
+
+    /// Cause of a change from `before` to `after` relay states, if any
+    ///
+    /// A start wins when one pump starts while another stops.
+    pub fn from_relays(before: &[bool], after: &[bool]) -> Option<Self> {
+        let changed = || before.iter().zip(after);
+        if changed().any(|(was, is)| !was && *is) {
+            Some(Cause::PumpStart)
+        } else if changed().any(|(was, is)| *was && !is) {
+            Some(Cause::PumpStop)
+        } else {
+            None
+        }
+    }
+}
+
+/// A captured pressure spike
+#[derive(Debug, Clone, PartialEq)]
+pub struct HammerEvent {
+    /// Seconds since the Unix epoch
+    pub timestamp: u32,
+    pub cause: Cause,
+    /// Line pressure before the transient (0.1 PSI)
+    pub baseline: u16,
+    /// Highest sample (0.1 PSI)
+    pub peak: u16,
+    /// Time of the peak after the relay switched (ms)
+    pub peak_ms: u16,
+    /// Samples `SAMPLE_INTERVAL` apart around the peak (0.1 PSI)
+    pub waveform: [u16; WAVEFORM_LEN],
+}
+
+impl HammerEvent {
+    /// Rise above the baseline (0.1 PSI)
+    pub fn rise(&self) -> u16 {
+        self.peak.saturating_sub(self.baseline)
+    }
+}
+
+/// Fast pressure samples following a relay change
+#[derive(Debug, Clone)]
+pub struct Burst {
+    cause: Cause,
+    /// Samples in 0.1 PSI
+    samples: Vec<u16>,
+}
+
+impl Burst {
+    pub fn new(cause: Cause) -> Self {
+        Self { cause, samples: Vec::with_capacity(BURST_SAMPLES) }
+    }
+
+    /// Add a sample, returning `true` once the burst is complete
+    pub fn push(&mut self, psi: f32) -> bool {
+        if self.samples.len() < BURST_SAMPLES {
+            self.samples.push((psi.max(0.0) * 10.0).round() as u16);
+        }
+        self.samples.len() >= BURST_SAMPLES
+    }
+
+    /// Sample a whole burst from `read`, paced at `SAMPLE_INTERVAL`
+    ///
+    /// Called from the main loop, which it blocks for about a second after
+    /// each relay change: nothing else is read or published meanwhile. The
+    /// waits between samples are shorter than the scheduler tick, which
+    /// ESP-IDF sleeps as a busy-wait, so the pacing isn't rounded up to it.
+    pub fn capture<E>(cause: Cause, mut read: impl FnMut() -> Result<f32, E>) -> Result<Self, E> {
+        let mut burst = Self::new(cause);
+        let started = Instant::now();
+        for i in 1.. {
+            if burst.push(read()?) {
+                break;
+            }
+            if let Some(wait) = (started + SAMPLE_INTERVAL * i).checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
+        Ok(burst)
+    }
+
+    /// The event in this burst, if its peak rises `threshold_psi` above the baseline
+    pub fn analyze(&self, threshold_psi: u16, timestamp: u32) -> Option<HammerEvent> {
+        if self.samples.len() <= BASELINE_SAMPLES {
+            return None;
+        }
+        let mut leading = self.samples[..BASELINE_SAMPLES].to_vec();
+        leading.sort_unstable();
+        let baseline = leading[BASELINE_SAMPLES / 2];
+        // First sample of the highest value
+        let (peak_index, &peak) = self
+            .samples
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|&(_, psi)| *psi)?;
+        if peak.saturating_sub(baseline) < threshold_psi * 10 {
+            return None;
+        }
+
+        let start = peak_index.saturating_sub(PRE_PEAK).min(self.samples.len().saturating_sub(WAVEFORM_LEN));
+        let mut waveform = [*self.samples.last()?; WAVEFORM_LEN];
+        for (slot, psi) in waveform.iter_mut().zip(&self.samples[start..]) {
+            *slot = *psi;
+        }
+        Some(HammerEvent {
+            timestamp,
+            cause: self.cause,
+            baseline,
+            peak,
+            peak_ms: (peak_index as u32 * SAMPLE_INTERVAL.as_millis() as u32) as u16,
+            waveform,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cause_from_relays() {
+        assert_eq!(Cause::from_relays(&[false, false], &[true, false]), Some(Cause::PumpStart));
+        assert_eq!(Cause::from_relays(&[true, false], &[false, false]), Some(Cause::PumpStop));
+        assert_eq!(Cause::from_relays(&[true, false], &[false, true]), Some(Cause::PumpStart));
+        assert_eq!(Cause::from_relays(&[true, true], &[true, true]), None);
+    }
+
+    #[test]
+    fn test_detects_spike_above_baseline() {
+        let burst = |spike: f32| {
+            let mut burst = Burst::new(Cause::PumpStop);
+            for i in 0..BURST_SAMPLES {
+                // 60 PSI line with sensor noise, spike 100 ms after the relay
+                let noise = if i % 3 == 0 { 0.3 } else { 0.0 };
+                let psi = if i == 50 { 60.0 + spike } else { 60.0 + noise };
+                if burst.push(psi) {
+                    break;
+                }
+            }
+            burst
+        };
+
+        assert_eq!(burst(4.0).analyze(5, 0), None);
+        let event = burst(25.0).analyze(5, 1_700_000_000).unwrap();
+        assert_eq!((event.baseline, event.peak, event.rise()), (600, 850, 250));
+        assert_eq!(event.peak_ms, 100);
+        assert_eq!(event.waveform[PRE_PEAK], 850);
+        assert_eq!(event.waveform[PRE_PEAK - 1], 600);
+        assert_eq!(event.cause.name(), "pump_stop");
+    }
+}
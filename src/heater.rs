@@ -0,0 +1,200 @@
+//! Radar antenna condensation heater
+//!
+//! Water condensing on the radar lens scatters the beam and causes erratic
+//! level readings. A small heater ring around the antenna, switched by a
+//! MOSFET on GPIO15, keeps the lens above the dew point.
+//!
+//! # Modes
+//! - **Off**
+//! - **Auto**: heat while the ambient dew-point spread (temperature minus dew
+//!   point) is below `spread_c`. Ambient temperature and humidity are pushed
+//!   from Home Assistant; without fresh readings the heater falls back to the
+//!   duty cycle.
+//! - **Duty cycle**: heat `duty_percent` of every 10 minute period.
+//!
+//! The lens sits at roughly ambient temperature, so a small spread means the
+//! surface is close to collecting condensation.
+
+use std::time::Duration;
+
+use log::*;
+
+use crate::config::Config;
+
+/// Duty-cycle period
+const DUTY_PERIOD: Duration = Duration::from_secs(10 * 60);
+/// Ambient readings older than this are ignored
+const AMBIENT_MAX_AGE: Duration = Duration::from_secs(30 * 60);
+/// Extra spread (°C) required to switch off again in auto mode
+const SPREAD_HYSTERESIS_C: f32 = 1.0;
+
+/// Heater operating mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaterMode {
+    Off,
+    Auto,
+    DutyCycle,
+}
+
+impl HeaterMode {
+    /// Decode the stored config value (0 = off, 1 = auto, 2 = duty cycle)
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1 => HeaterMode::Auto,
+            2 => HeaterMode::DutyCycle,
+            _ => HeaterMode::Off,
+        }
+    }
+}
+
+/// Heater thresholds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeaterSettings {
+    pub mode: HeaterMode,
+    /// Heat while the dew-point spread is below this (°C)
+    pub spread_c: f32,
+    /// On-time share of each duty period (%)
+    pub duty_percent: u8,
+}
+
+impl HeaterSettings {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self {
+            mode: HeaterMode::from_code(cfg.heater_mode),
+            spread_c: cfg.heater_spread_c as f32,
+            duty_percent: cfg.heater_duty_percent as u8,
+        }
+    }
+}
+
+/// Dew point (°C) from temperature (°C) and relative humidity (%), Magnus formula
+pub fn dew_point(temperature_c: f32, humidity_percent: f32) -> f32 {
+    const B: f32 = 17.62;
+    const C: f32 = 243.12;
+    let rh = humidity_percent.clamp(1.0, 100.0) / 100.0;
+    let gamma = rh.ln() + B * temperature_c / (C + temperature_c);
+    C * gamma / (B - gamma)
+}
+
+/// Heater on/off decision
+pub struct HeaterController {
+    settings: HeaterSettings,
+    /// Latest ambient temperature (°C) and when it was received
+    temperature: Option<(f32, Duration)>,
+    /// Latest ambient relative humidity (%) and when it was received
+    humidity: Option<(f32, Duration)>,
+    on: bool,
+}
+
+impl HeaterController {
+    pub fn new(settings: HeaterSettings) -> Self {
+        Self {
+            settings,
+            temperature: None,
+            humidity: None,
+            on: false,
+        }
+    }
+
+    /// Replace the thresholds (takes effect on the next update)
+    pub fn set_settings(&mut self, settings: HeaterSettings) {
+        self.settings = settings;
+    }
+
+    pub fn set_temperature(&mut self, celsius: f32, now: Duration) {
+        self.temperature = Some((celsius, now));
+    }
+
+    pub fn set_humidity(&mut self, percent: f32, now: Duration) {
+        self.humidity = Some((percent, now));
+    }
+
+    /// Current heater state
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// Current dew-point spread (°C), if fresh ambient readings are available
+    pub fn spread(&self, now: Duration) -> Option<f32> {
+        let fresh = |reading: Option<(f32, Duration)>| {
+            reading.filter(|&(_, at)| now.saturating_sub(at) <= AMBIENT_MAX_AGE).map(|(v, _)| v)
+        };
+        let t = fresh(self.temperature)?;
+        let rh = fresh(self.humidity)?;
+        Some(t - dew_point(t, rh))
+    }
+
+    /// Run one control step and return the desired heater state
+    pub fn update(&mut self, now: Duration) -> bool {
+        let s = self.settings;
+        let on = match s.mode {
+            HeaterMode::Off => false,
+            HeaterMode::Auto => match self.spread(now) {
+                Some(spread) if self.on => spread < s.spread_c + SPREAD_HYSTERESIS_C,
+                Some(spread) => spread < s.spread_c,
+                None => Self::duty_on(s.duty_percent, now),
+            },
+            HeaterMode::DutyCycle => Self::duty_on(s.duty_percent, now),
+        };
+        if on != self.on {
+            info!("Heater: {}", if on { "on" } else { "off" });
+        }
+        self.on = on;
+        on
+    }
+
+    /// Force the heater off (e.g. in maintenance mode)
+    pub fn stop(&mut self) -> bool {
+        self.on = false;
+        self.on
+    }
+
+    fn duty_on(duty_percent: u8, now: Duration) -> bool {
+        let period = DUTY_PERIOD.as_secs();
+        now.as_secs() % period < period * duty_percent.min(100) as u64 / 100
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUTO: HeaterSettings = HeaterSettings {
+        mode: HeaterMode::Auto,
+        spread_c: 3.0,
+        duty_percent: 25,
+    };
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_dew_point() {
+        // 20 °C at 50% RH has a dew point of about 9.3 °C
+        assert!((dew_point(20.0, 50.0) - 9.3).abs() < 0.1);
+        assert!((dew_point(10.0, 100.0) - 10.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_auto_heats_near_dew_point() {
+        let mut heater = HeaterController::new(AUTO);
+        heater.set_temperature(10.0, secs(0));
+        heater.set_humidity(95.0, secs(0));
+        assert!(heater.update(secs(1)));
+        // Dry air: spread ~16 °C
+        heater.set_humidity(30.0, secs(2));
+        assert!(!heater.update(secs(3)));
+    }
+
+    #[test]
+    fn test_stale_readings_fall_back_to_duty_cycle() {
+        let mut heater = HeaterController::new(AUTO);
+        heater.set_temperature(10.0, secs(0));
+        heater.set_humidity(30.0, secs(0));
+        assert!(!heater.update(secs(60)));
+        // 40 minutes later the readings are stale; 25% of 600 s = first 150 s
+        assert!(heater.update(secs(2400 + 100)));
+        assert!(!heater.update(secs(2400 + 200)));
+    }
+}
@@ -0,0 +1,695 @@
+//! Sample history stored in a dedicated flash partition
+//!
+//! Samples are appended as fixed-size records to the raw `history` data
+//! partition rather than NVS. NVS rewrites whole pages on commit, which at a
+//! 5-minute cadence would wear out its sectors; here every sector is erased
+//! exactly once per trip around the ring.
+//!
+//! # Layout
+//! The partition is an array of 4 KiB sectors holding 256 records of 16 bytes.
+//! Each record carries a monotonically increasing sequence number and a CRC,
+//! so the newest record (and with it the write head) is recovered by scanning
+//! the partition on boot. No index has to be kept up to date.
+//!
+//! ```text
+//! | seq u32 | time u32 | pct u8 | kind u8 | psi u16 | gal u16 | crc u16 |
+//! ```
+//!
+//! The kind byte is 0 for samples; other kinds reuse the fields around it.
+//!
+//! # Wear budget
+//! 10 years of 5-minute samples is 1,051,200 records, i.e. 4,107 sector erases.
+//! Spread over the 64 sectors of the 256 KiB partition that is ~65 erases per
+//! sector, far below the 100,000 cycles NOR flash is rated for. One sector is
+//! always being recycled, so ~56 days of samples are retained.
+//!
+//! # Statistics
+//! `HistoryStats` summarises the last hour and day of samples (min, max and
+//! mean level and pressure), so consumers get windowed statistics from the
+//! device itself rather than from whatever subset of live readings they saw.
+//!
+//! # Water hammer events
+//! With the `hammer` feature, captured pressure spikes are stored in the same
+//! ring as a header record followed by the waveform, four points per record:
+//!
+//! ```text
+//! | seq u32 | time u32 | cause u8 | 1 | baseline u16 | peak ms u16 | crc u16 |
+//! | seq u32 | p0 u16 | p1 u16 | chunk u8 | 2 | p2 u16 | p3 u16 | crc u16 |
+//! ```
+//!
+//! An event takes 17 records, so even frequent spikes barely dent the sample
+//! retention.
+//!
+//! # Pump cycles
+//! With the `efficiency` feature, every completed single-pump fill cycle is
+//! stored as one record, for the weekly delivery rate trend:
+//!
+//! ```text
+//! | seq u32 | time u32 | pump u8 | 3 | delivered 0.1 gal u16 | runtime s u16 | crc u16 |
+//! ```
+//!
+//! A cycle metered with `pump_power` is followed by its energy, in the next
+//! sequence number:
+//!
+//! ```text
+//! | seq u32 | time u32 | pump u8 | 4 | energy 0.1 Wh u32 | crc u16 |
+//! ```
+//!
+//! # Recovery
+//! A record torn by power loss fails its CRC and is ignored. Slots that are
+//! neither valid nor erased are skipped on append, so a half-written record
+//! never blocks the ring, and sector erases are simply redone after reboot.
+
+use core::ffi::CStr;
+
+use esp_idf_svc::sys::{
+    esp, esp_partition_erase_range, esp_partition_find_first, esp_partition_read,
+    esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_ANY, esp_partition_t,
+    esp_partition_type_t_ESP_PARTITION_TYPE_DATA, esp_partition_write, EspError,
+    ESP_ERR_NOT_FOUND,
+};
+use log::*;
+
+#[cfg(feature = "efficiency")]
+use crate::efficiency::Cycle;
+#[cfg(feature = "hammer")]
+use crate::hammer::{Cause, HammerEvent, WAVEFORM_LEN};
+
+/// Partition label in `partitions.csv`
+const PARTITION_LABEL: &CStr = c"history";
+
+/// Flash erase granularity
+const SECTOR_SIZE: usize = 4096;
+/// Size of a single encoded record
+const RECORD_SIZE: usize = 16;
+const RECORDS_PER_SECTOR: usize = SECTOR_SIZE / RECORD_SIZE;
+
+/// Record kinds
+const KIND_SAMPLE: u8 = 0;
+#[cfg(feature = "hammer")]
+const KIND_HAMMER: u8 = 1;
+#[cfg(feature = "hammer")]
+const KIND_WAVEFORM: u8 = 2;
+#[cfg(feature = "efficiency")]
+const KIND_CYCLE: u8 = 3;
+#[cfg(feature = "efficiency")]
+const KIND_CYCLE_ENERGY: u8 = 4;
+/// Waveform points per record
+#[cfg(feature = "hammer")]
+const POINTS_PER_RECORD: usize = 4;
+/// Records taken by one water hammer event
+#[cfg(feature = "hammer")]
+const EVENT_RECORDS: usize = 1 + WAVEFORM_LEN / POINTS_PER_RECORD;
+
+/// A single history sample
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Seconds since the Unix epoch as reported by the system clock
+    pub timestamp: u32,
+    /// Tank capacity percentage (0-100)
+    pub capacity_percent: u8,
+    /// Water pressure in PSI
+    pub pressure_psi: u16,
+    /// Tank volume in gallons
+    pub gallons: u16,
+}
+
+/// Minimum, maximum and mean of one quantity over a time window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowStats {
+    pub min: u16,
+    pub max: u16,
+    pub avg: u16,
+}
+
+impl WindowStats {
+    /// Statistics of `value` over the samples taken in the `span` seconds up
+    /// to `now`, or `None` if there are none
+    ///
+    /// Samples stamped after `now` (written by an earlier boot whose clock was
+    /// synced while the current one isn't yet) fall outside every window.
+    pub fn compute(samples: &[Sample], now: u32, span: u32, value: impl Fn(&Sample) -> u16) -> Option<Self> {
+        let since = now.saturating_sub(span);
+        let mut values = samples
+            .iter()
+            .filter(|s| (since..=now).contains(&s.timestamp))
+            .map(value);
+        let first = values.next()?;
+        let (mut min, mut max, mut sum, mut count) = (first, first, first as u32, 1);
+        for v in values {
+            min = min.min(v);
+            max = max.max(v);
+            sum += v as u32;
+            count += 1;
+        }
+        Some(Self { min, max, avg: ((sum + count / 2) / count) as u16 })
+    }
+}
+
+/// Level and pressure statistics over the last hour and day
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HistoryStats {
+    pub level_1h: Option<WindowStats>,
+    pub level_24h: Option<WindowStats>,
+    pub pressure_1h: Option<WindowStats>,
+    pub pressure_24h: Option<WindowStats>,
+}
+
+impl HistoryStats {
+    const HOUR: u32 = 3600;
+    const DAY: u32 = 24 * Self::HOUR;
+
+    /// Compute the windows ending at `now` (seconds since the Unix epoch)
+    pub fn compute(samples: &[Sample], now: u32) -> Self {
+        let level = |s: &Sample| s.capacity_percent as u16;
+        let pressure = |s: &Sample| s.pressure_psi;
+        Self {
+            level_1h: WindowStats::compute(samples, now, Self::HOUR, level),
+            level_24h: WindowStats::compute(samples, now, Self::DAY, level),
+            pressure_1h: WindowStats::compute(samples, now, Self::HOUR, pressure),
+            pressure_24h: WindowStats::compute(samples, now, Self::DAY, pressure),
+        }
+    }
+}
+
+/// Raw handle to a data partition
+struct Partition(*const esp_partition_t);
+
+impl Partition {
+    fn find(label: &CStr) -> Result<Self, EspError> {
+        let ptr = unsafe {
+            esp_partition_find_first(
+                esp_partition_type_t_ESP_PARTITION_TYPE_DATA,
+                esp_partition_subtype_t_ESP_PARTITION_SUBTYPE_ANY,
+                label.as_ptr(),
+            )
+        };
+        if ptr.is_null() {
+            return Err(EspError::from_infallible::<ESP_ERR_NOT_FOUND>());
+        }
+        Ok(Self(ptr))
+    }
+
+    fn size(&self) -> usize {
+        unsafe { (*self.0).size as usize }
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), EspError> {
+        esp!(unsafe { esp_partition_read(self.0, offset as _, buf.as_mut_ptr() as *mut _, buf.len() as _) })
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), EspError> {
+        esp!(unsafe { esp_partition_write(self.0, offset as _, data.as_ptr() as *const _, data.len() as _) })
+    }
+
+    fn erase(&mut self, offset: usize, size: usize) -> Result<(), EspError> {
+        esp!(unsafe { esp_partition_erase_range(self.0, offset as _, size as _) })
+    }
+}
+
+/// Ring buffer of samples on the `history` partition
+pub struct History {
+    partition: Partition,
+    /// Total number of record slots in the partition
+    slots: usize,
+    /// Slot the next record is written to
+    head: usize,
+    /// Sequence number of the next record
+    next_seq: u32,
+    /// Number of valid records found on boot plus those appended since
+    len: usize,
+}
+
+impl History {
+    /// Open the history partition and recover the write head
+    pub fn open() -> Result<Self, EspError> {
+        let partition = Partition::find(PARTITION_LABEL)?;
+
+        let sectors = partition.size() / SECTOR_SIZE;
+        let mut history = Self {
+            partition,
+            slots: sectors * RECORDS_PER_SECTOR,
+            head: 0,
+            next_seq: 0,
+            len: 0,
+        };
+        history.scan()?;
+
+        info!(
+            "History: {} sectors, {} records, head at slot {}",
+            sectors, history.len, history.head
+        );
+        Ok(history)
+    }
+
+    /// Number of records currently stored
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append a sample, recycling the oldest sector when the head enters it
+    pub fn append(&mut self, sample: &Sample) -> Result<(), EspError> {
+        let mut record = [0u8; RECORD_SIZE];
+        encode(self.next_seq, sample, &mut record);
+        self.write_record(&record)
+    }
+
+    /// Append a water hammer event (header and waveform records)
+    #[cfg(feature = "hammer")]
+    pub fn append_event(&mut self, event: &HammerEvent) -> Result<(), EspError> {
+        for record in encode_event(self.next_seq, event) {
+            self.write_record(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Append a completed pump cycle, and its energy record when metered
+    #[cfg(feature = "efficiency")]
+    pub fn append_cycle(&mut self, cycle: &Cycle) -> Result<(), EspError> {
+        for record in encode_cycle(self.next_seq, cycle) {
+            self.write_record(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Write an encoded record at the head
+    ///
+    /// Its sequence number must be `next_seq`.
+    fn write_record(&mut self, encoded: &[u8; RECORD_SIZE]) -> Result<(), EspError> {
+        let mut record = [0u8; RECORD_SIZE];
+        // Bounded so a partition full of garbage still terminates
+        for _ in 0..self.slots {
+            if self.head % RECORDS_PER_SECTOR == 0 {
+                let sector = self.head / RECORDS_PER_SECTOR;
+                let dropped = self.count_valid(sector)?;
+                self.partition.erase(sector * SECTOR_SIZE, SECTOR_SIZE)?;
+                self.len = self.len.saturating_sub(dropped);
+                debug!("History: recycled sector {} ({} records)", sector, dropped);
+                break;
+            }
+            self.partition.read(self.head * RECORD_SIZE, &mut record)?;
+            if record.iter().all(|&b| b == 0xFF) {
+                break;
+            }
+            warn!("History: skipping dirty slot {}", self.head);
+            self.advance();
+        }
+
+        self.partition.write(self.head * RECORD_SIZE, encoded)?;
+        self.next_seq += 1;
+        self.len += 1;
+        self.advance();
+        Ok(())
+    }
+
+    /// Read up to `max` of the most recent samples, oldest first
+    pub fn recent(&mut self, max: usize) -> Result<Vec<Sample>, EspError> {
+        let mut out = Vec::with_capacity(max.min(self.len));
+        self.walk_back(|_, _, record| {
+            if out.len() >= max {
+                return Ok(false);
+            }
+            out.extend(decode(record).map(|(_, sample)| sample));
+            Ok(true)
+        })?;
+        out.reverse();
+        Ok(out)
+    }
+
+    /// Read up to `max` of the most recent water hammer events, oldest first
+    ///
+    /// Events whose records were torn or recycled are skipped.
+    #[cfg(feature = "hammer")]
+    pub fn recent_events(&mut self, max: usize) -> Result<Vec<HammerEvent>, EspError> {
+        let mut out = Vec::new();
+        self.walk_back(|history, slot, record| {
+            if out.len() >= max {
+                return Ok(false);
+            }
+            if record[9] == KIND_HAMMER {
+                let mut records = [[0u8; RECORD_SIZE]; EVENT_RECORDS];
+                for (i, r) in records.iter_mut().enumerate() {
+                    history.partition.read((slot + i) % history.slots * RECORD_SIZE, r)?;
+                }
+                out.extend(decode_event(&records));
+            }
+            Ok(true)
+        })?;
+        out.reverse();
+        Ok(out)
+    }
+
+    /// Read up to `max` of the most recent pump cycles, oldest first
+    ///
+    /// A cycle whose energy record was torn or recycled comes back unmetered.
+    #[cfg(feature = "efficiency")]
+    pub fn recent_cycles(&mut self, max: usize) -> Result<Vec<Cycle>, EspError> {
+        let mut out = Vec::new();
+        self.walk_back(|history, slot, record| {
+            if out.len() >= max {
+                return Ok(false);
+            }
+            if record[9] == KIND_CYCLE {
+                let mut energy = [0u8; RECORD_SIZE];
+                history.partition.read((slot + 1) % history.slots * RECORD_SIZE, &mut energy)?;
+                out.extend(decode_cycle(record, &energy));
+            }
+            Ok(true)
+        })?;
+        out.reverse();
+        Ok(out)
+    }
+
+    /// Visit valid records from the newest back until `visit` returns `false`
+    fn walk_back(
+        &mut self,
+        mut visit: impl FnMut(&mut Self, usize, &[u8; RECORD_SIZE]) -> Result<bool, EspError>,
+    ) -> Result<(), EspError> {
+        let mut record = [0u8; RECORD_SIZE];
+        let mut slot = self.head;
+        let mut last_seq = self.next_seq;
+
+        for _ in 0..self.slots {
+            slot = if slot == 0 { self.slots - 1 } else { slot - 1 };
+            self.partition.read(slot * RECORD_SIZE, &mut record)?;
+            let Some(seq) = check(&record) else { continue };
+            // Walking backwards past the oldest record wraps to newer ones
+            if seq >= last_seq {
+                break;
+            }
+            last_seq = seq;
+            if !visit(self, slot, &record)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self) {
+        self.head = (self.head + 1) % self.slots;
+    }
+
+    /// Count valid records in a sector
+    fn count_valid(&mut self, sector: usize) -> Result<usize, EspError> {
+        let mut sector_buf = vec![0u8; SECTOR_SIZE];
+        self.partition.read(sector * SECTOR_SIZE, &mut sector_buf)?;
+        Ok(sector_buf
+            .chunks_exact(RECORD_SIZE)
+            .filter(|record| check(record).is_some())
+            .count())
+    }
+
+    /// Find the newest valid record and place the head right after it
+    fn scan(&mut self) -> Result<(), EspError> {
+        let mut sector_buf = vec![0u8; SECTOR_SIZE];
+        let mut newest: Option<(u32, usize)> = None;
+        let mut valid = 0;
+
+        for sector in 0..self.slots / RECORDS_PER_SECTOR {
+            self.partition.read(sector * SECTOR_SIZE, &mut sector_buf)?;
+            for (i, record) in sector_buf.chunks_exact(RECORD_SIZE).enumerate() {
+                let Some(seq) = check(record) else { continue };
+                valid += 1;
+                if !matches!(newest, Some((best, _)) if best >= seq) {
+                    newest = Some((seq, sector * RECORDS_PER_SECTOR + i));
+                }
+            }
+        }
+
+        if let Some((seq, slot)) = newest {
+            self.next_seq = seq + 1;
+            self.head = (slot + 1) % self.slots;
+        }
+        self.len = valid;
+        Ok(())
+    }
+}
+
+/// Encode a record, little-endian, with trailing CRC
+fn encode(seq: u32, sample: &Sample, out: &mut [u8; RECORD_SIZE]) {
+    out[0..4].copy_from_slice(&seq.to_le_bytes());
+    out[4..8].copy_from_slice(&sample.timestamp.to_le_bytes());
+    out[8] = sample.capacity_percent;
+    out[9] = KIND_SAMPLE;
+    out[10..12].copy_from_slice(&sample.pressure_psi.to_le_bytes());
+    out[12..14].copy_from_slice(&sample.gallons.to_le_bytes());
+    let crc = crc16(&out[0..14]);
+    out[14..16].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Sequence number of a valid record of any kind, `None` for erased or
+/// corrupted slots
+fn check(record: &[u8]) -> Option<u32> {
+    let seq = u32::from_le_bytes(record[0..4].try_into().ok()?);
+    // Erased flash reads back as 0xFF; its CRC can't be trusted to mismatch
+    if seq == u32::MAX {
+        return None;
+    }
+    let crc = u16::from_le_bytes(record[14..16].try_into().ok()?);
+    if crc != crc16(&record[0..14]) {
+        return None;
+    }
+    Some(seq)
+}
+
+/// Decode a sample record, returning `None` for other kinds and invalid slots
+fn decode(record: &[u8]) -> Option<(u32, Sample)> {
+    let seq = check(record)?;
+    if record[9] != KIND_SAMPLE {
+        return None;
+    }
+
+    Some((
+        seq,
+        Sample {
+            timestamp: u32::from_le_bytes(record[4..8].try_into().ok()?),
+            capacity_percent: record[8],
+            pressure_psi: u16::from_le_bytes(record[10..12].try_into().ok()?),
+            gallons: u16::from_le_bytes(record[12..14].try_into().ok()?),
+        },
+    ))
+}
+
+/// Encode a water hammer event as consecutive records starting at `seq`
+#[cfg(feature = "hammer")]
+fn encode_event(seq: u32, event: &HammerEvent) -> Vec<[u8; RECORD_SIZE]> {
+    let seal = |out: &mut [u8; RECORD_SIZE]| {
+        let crc = crc16(&out[0..14]);
+        out[14..16].copy_from_slice(&crc.to_le_bytes());
+    };
+    let mut records = Vec::with_capacity(EVENT_RECORDS);
+
+    let mut header = [0u8; RECORD_SIZE];
+    header[0..4].copy_from_slice(&seq.to_le_bytes());
+    header[4..8].copy_from_slice(&event.timestamp.to_le_bytes());
+    header[8] = event.cause as u8;
+    header[9] = KIND_HAMMER;
+    header[10..12].copy_from_slice(&event.baseline.to_le_bytes());
+    header[12..14].copy_from_slice(&event.peak_ms.to_le_bytes());
+    seal(&mut header);
+    records.push(header);
+
+    for (chunk, points) in event.waveform.chunks_exact(POINTS_PER_RECORD).enumerate() {
+        let mut out = [0u8; RECORD_SIZE];
+        out[0..4].copy_from_slice(&(seq + 1 + chunk as u32).to_le_bytes());
+        out[8] = chunk as u8;
+        out[9] = KIND_WAVEFORM;
+        for (point, offset) in points.iter().zip([4, 6, 10, 12]) {
+            out[offset..offset + 2].copy_from_slice(&point.to_le_bytes());
+        }
+        seal(&mut out);
+        records.push(out);
+    }
+    records
+}
+
+/// Decode the records of a water hammer event, header first
+///
+/// `None` unless every record is valid and in sequence.
+#[cfg(feature = "hammer")]
+fn decode_event(records: &[[u8; RECORD_SIZE]]) -> Option<HammerEvent> {
+    let (header, chunks) = records.split_first()?;
+    let seq = check(header)?;
+    if header[9] != KIND_HAMMER || chunks.len() != EVENT_RECORDS - 1 {
+        return None;
+    }
+    let cause = match header[8] {
+        0 => Cause::PumpStart,
+        1 => Cause::PumpStop,
+        _ => return None,
+    };
+
+    let mut waveform = [0u16; WAVEFORM_LEN];
+    for (chunk, (record, points)) in chunks.iter().zip(waveform.chunks_exact_mut(POINTS_PER_RECORD)).enumerate() {
+        if check(record)? != seq + 1 + chunk as u32 || record[8] as usize != chunk || record[9] != KIND_WAVEFORM {
+            return None;
+        }
+        for (point, offset) in points.iter_mut().zip([4, 6, 10, 12]) {
+            *point = u16::from_le_bytes(record[offset..offset + 2].try_into().ok()?);
+        }
+    }
+
+    Some(HammerEvent {
+        timestamp: u32::from_le_bytes(header[4..8].try_into().ok()?),
+        cause,
+        baseline: u16::from_le_bytes(header[10..12].try_into().ok()?),
+        // The waveform always includes the peak
+        peak: waveform.iter().copied().max()?,
+        peak_ms: u16::from_le_bytes(header[12..14].try_into().ok()?),
+        waveform,
+    })
+}
+
+/// Encode a pump cycle as consecutive records starting at `seq`: the cycle,
+/// then its energy when metered
+#[cfg(feature = "efficiency")]
+fn encode_cycle(seq: u32, cycle: &Cycle) -> Vec<[u8; RECORD_SIZE]> {
+    let record = |seq: u32, kind: u8, fields: &[u8]| {
+        let mut out = [0u8; RECORD_SIZE];
+        out[0..4].copy_from_slice(&seq.to_le_bytes());
+        out[4..8].copy_from_slice(&cycle.timestamp.to_le_bytes());
+        out[8] = cycle.pump;
+        out[9] = kind;
+        out[10..14].copy_from_slice(fields);
+        let crc = crc16(&out[0..14]);
+        out[14..16].copy_from_slice(&crc.to_le_bytes());
+        out
+    };
+    let mut fields = [0u8; 4];
+    fields[0..2].copy_from_slice(&cycle.delivered.to_le_bytes());
+    fields[2..4].copy_from_slice(&cycle.runtime_secs.to_le_bytes());
+    let mut records = vec![record(seq, KIND_CYCLE, &fields)];
+    if let Some(energy) = cycle.energy {
+        records.push(record(seq + 1, KIND_CYCLE_ENERGY, &energy.to_le_bytes()));
+    }
+    records
+}
+
+/// Decode a pump cycle record and the record after it, `None` for other
+/// kinds and invalid slots
+///
+/// The energy is taken from `next` only if it belongs to this cycle.
+#[cfg(feature = "efficiency")]
+fn decode_cycle(record: &[u8], next: &[u8]) -> Option<Cycle> {
+    let seq = check(record)?;
+    if record[9] != KIND_CYCLE {
+        return None;
+    }
+    let metered = check(next) == Some(seq.wrapping_add(1)) && next[9] == KIND_CYCLE_ENERGY && next[4..9] == record[4..9];
+    let energy = if metered { Some(u32::from_le_bytes(next[10..14].try_into().ok()?)) } else { None };
+    Some(Cycle {
+        timestamp: u32::from_le_bytes(record[4..8].try_into().ok()?),
+        pump: record[8],
+        delivered: u16::from_le_bytes(record[10..12].try_into().ok()?),
+        runtime_secs: u16::from_le_bytes(record[12..14].try_into().ok()?),
+        energy,
+    })
+}
+
+/// CRC16/CCITT-FALSE over a record
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() {
+        let sample = Sample {
+            timestamp: 1_767_225_600,
+            capacity_percent: 73,
+            pressure_psi: 58,
+            gallons: 365,
+        };
+        let mut record = [0u8; RECORD_SIZE];
+        encode(42, &sample, &mut record);
+        assert_eq!(decode(&record), Some((42, sample)));
+    }
+
+    #[test]
+    fn test_torn_and_erased_records_rejected() {
+        let mut record = [0u8; RECORD_SIZE];
+        encode(7, &Sample::default(), &mut record);
+        record[10] ^= 0x01;
+        assert_eq!(decode(&record), None);
+        assert_eq!(decode(&[0xFF; RECORD_SIZE]), None);
+    }
+
+    #[cfg(feature = "efficiency")]
+    #[test]
+    fn test_cycle_round_trip() {
+        let cycle = Cycle { timestamp: 1_767_225_600, pump: 1, delivered: 1234, runtime_secs: 420, energy: None };
+        let records = encode_cycle(9, &cycle);
+        assert_eq!(records.len(), 1);
+        assert_eq!(decode_cycle(&records[0], &[0xFF; RECORD_SIZE]), Some(cycle));
+        assert_eq!(decode(&records[0]), None);
+
+        // Metered: the energy follows, and is dropped when torn
+        let metered = Cycle { energy: Some(98_765), ..cycle };
+        let mut records = encode_cycle(9, &metered);
+        assert_eq!(records.len(), 2);
+        assert_eq!(decode_cycle(&records[0], &records[1]), Some(metered));
+        assert_eq!(decode_cycle(&records[1], &records[0]), None);
+        records[1][11] ^= 0x01;
+        assert_eq!(decode_cycle(&records[0], &records[1]), Some(cycle));
+    }
+
+    #[cfg(feature = "hammer")]
+    #[test]
+    fn test_event_round_trip() {
+        let mut waveform = [600; WAVEFORM_LEN];
+        waveform[16] = 850;
+        waveform[17] = 720;
+        let event = HammerEvent {
+            timestamp: 1_767_225_600,
+            cause: Cause::PumpStop,
+            baseline: 600,
+            peak: 850,
+            peak_ms: 84,
+            waveform,
+        };
+        let mut records = encode_event(100, &event);
+        assert_eq!(records.len(), EVENT_RECORDS);
+        assert_eq!(decode_event(&records), Some(event));
+        // Event records are not samples, and a missing chunk drops the event
+        assert_eq!(decode(&records[0]), None);
+        records.remove(5);
+        assert_eq!(decode_event(&records), None);
+    }
+
+    #[test]
+    fn test_window_stats() {
+        const NOW: u32 = 1_767_225_600;
+        let sample = |age: u32, pct: u8, psi: u16| Sample {
+            timestamp: NOW - age,
+            capacity_percent: pct,
+            pressure_psi: psi,
+            gallons: 0,
+        };
+        let samples = [sample(7200, 90, 40), sample(1800, 60, 55), sample(600, 50, 60), sample(0, 41, 58)];
+        let stats = HistoryStats::compute(&samples, NOW);
+        assert_eq!(stats.level_1h, Some(WindowStats { min: 41, max: 60, avg: 50 }));
+        assert_eq!(stats.level_24h, Some(WindowStats { min: 41, max: 90, avg: 60 }));
+        assert_eq!(stats.pressure_1h, Some(WindowStats { min: 55, max: 60, avg: 58 }));
+        // Nothing in the last hour, and future samples don't count
+        assert_eq!(HistoryStats::compute(&samples, NOW + 7200).level_1h, None);
+        assert_eq!(HistoryStats::compute(&samples, NOW - 7200).level_24h, Some(WindowStats { min: 90, max: 90, avg: 90 }));
+    }
+}
@@ -7,24 +7,398 @@
 //! # Topics
 //! - Discovery (sensors): `homeassistant/sensor/watercontroller_<name>/config`
 //! - Discovery (numbers): `homeassistant/number/watercontroller_<name>/config`
+//! - Discovery (switches): `homeassistant/switch/watercontroller_<name>/config`
 //! - State: `watercontroller/state`
+//! - Maintenance mode state: `watercontroller/maintenance` (retained, `ON`/`OFF`)
+//! - Lockout state: `watercontroller/lockout` (retained, `ON`/`OFF`; see `lockout`)
 //! - Commands: `watercontroller/set/<parameter>`
+//! - Device commands: `watercontroller/cmd/reboot` and
+//!   `watercontroller/cmd/factory_reset`, from button entities; only the
+//!   `PRESS` payload the buttons send is acted on
+//! - Bulk configuration: `watercontroller/set/config`, a JSON object of
+//!   `<parameter>: value` pairs validated and applied together
+//! - Desired/reported configuration: `watercontroller/config/desired` and
+//!   `watercontroller/config/reported` (retained, same format)
+//! - Trial configuration: `watercontroller/set/trial`, the same format plus
+//!   an optional `trial_minutes`; reverted unless confirmed on
+//!   `watercontroller/set/trial_confirm` (see `trial`). Trial state:
+//!   `watercontroller/config/trial` (retained, `ON`/`OFF`)
+//! - History statistics: `watercontroller/stats` (retained), 1 h and 24 h
+//!   min/max/avg of level and pressure as `level_1h_min`, `pressure_24h_avg`,
+//!   ... (`null` until the window has a sample)
+//! - Alarms: `alarm_low_level`, `alarm_high_pressure`, `alarm_sensor_fault`,
+//!   `alarm_pipe_burst` and `alarm_filter_clogged` in the state, shown as
+//!   problem binary sensors (see `alarms`); with `filter_pressure`, the
+//!   filter drop `filter_drop_psi`, its alarm threshold and a reset button
+//! - Daily water budget: `budget_gal` (number), `budget_pct` (today's use as
+//!   a share of it, `null` without a budget) and `budget_exceeded` (problem
+//!   binary sensor) in the state
+//! - Flow meter (`flow`): `flow_gpm` and the running total `flow_total_gal`
+//!   in the state, the total as a `water` sensor for the energy dashboard,
+//!   and `flow_k_factor` (number)
+//! - DS18B20 probes: `watercontroller/probes`, temperatures keyed
+//!   `t_<rom>`, one sensor per probe named as on the web UI (see `ds18b20`)
+//! - Firmware update (`ota`): an update entity with the running version on
+//!   `watercontroller/update` (retained). Release tooling publishes
+//!   `{"latest_version": "...", "url": "..."}` retained on
+//!   `watercontroller/update/latest`; installing from HA sends `install` to
+//!   `watercontroller/cmd/update`, which downloads that URL (a URL sent there
+//!   directly works too). See `ota`
+//! - LoRa siblings (`lora` gateway): `watercontroller/lora/<node>`, the state
+//!   of each node heard over the radio, with level, volume, pressure,
+//!   signal and problem entities named "LoRa <node> ..." (see `lora`)
+//! - Diagnostics: `watercontroller/diag`, IP address, uptime, free heap,
+//!   firmware version, Ethernet link, reset reason and radar retries and
+//!   failures, shown as diagnostic entities of the device
+//! - Build info: `watercontroller/build` (retained), version, commit, build
+//!   date, enabled features and board profile, as attributes of the main
+//!   sensors and the firmware version sensor (see `build_info`)
+//!
+//! The device name, its suggested area and a prefix for the entity names are
+//! configurable (see `payload::Naming`), so two controllers show up as "Barn
+//! Water Capacity" and "House Water Capacity" rather than under one name.
+//! The tank label, when set, goes in front of the tank's own entities: its
+//! level, volume and capacity, and the level statistics.
+//!
+//! On a shared broker the unit can be made read-only (`set_read_only`): it
+//! subscribes to none of the command topics, and discovery leaves out every
+//! entity with a command topic (numbers, switches, buttons, the valve),
+//! clearing any config an earlier boot had retained for them. The firmware
+//! update entity stays, without its install command. Settings are then only
+//! changed on the web UI or the console.
+//!
+//! Connection state and message and byte counters by topic class are kept
+//! for the diagnostics page (see `diagnostics()`). Discovery configs and the state document are the
+//! serde types in `payload`.
+//!
+//! # Reconnecting
+//! ESP-MQTT's automatic reconnect is off; `poll()` reconnects with the
+//! backoff in `reconnect` and, on every new connection, subscribes again,
+//! re-sends discovery and flushes the state samples queued while offline.
+//!
+//! Discovery configs go out through the `outbox`, paced by `poll()`, so a
+//! slow link doesn't overflow the client's outbox at boot. Each is tracked
+//! until the broker acknowledges it and sent again if it gets lost;
+//! `is_discovered()` turns true once all of them are acknowledged. If some
+//! are given up, the whole discovery is sent again with the next state.
+//!
+//! # TLS
+//! With `MqttTls` the client connects to `mqtts://`. The broker certificate
+//! must chain to the configured CA certificate, else to the one embedded at
+//! build time from the PEM file named by `MQTT_CA_CERT`, else to a CA in the
+//! ESP-IDF certificate bundle. Its name must match the broker host name, so
+//! brokers have to be addressed by the name in their certificate rather than
+//! by IP address. A client certificate and key are sent if configured.
 
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use std::ffi::CString;
+
+use esp_idf_svc::handle::RawHandle;
 use esp_idf_svc::mqtt::client::{EspMqttClient, EspMqttEvent, MqttClientConfiguration, QoS};
+use esp_idf_svc::tls::X509;
 use log::*;
 
+use crate::alarms::Alarm;
+use crate::diag::{Diagnostics, MqttDiag, StartupTiming, Traffic};
+#[cfg(feature = "ds18b20")]
+use crate::ds18b20::{self, Probe};
+#[cfg(feature = "history")]
+use crate::history::{HistoryStats, WindowStats};
+#[cfg(feature = "efficiency")]
+use crate::efficiency::PumpTrend;
+#[cfg(feature = "hammer")]
+use crate::hammer::HammerEvent;
+#[cfg(feature = "hammer")]
+use crate::payload::HammerReport;
+#[cfg(feature = "notify")]
+use crate::notify::{Event, Notification};
+#[cfg(feature = "notify")]
+use crate::payload::NotificationEvent;
+#[cfg(feature = "valve")]
+use crate::payload::ValveStatus;
+#[cfg(feature = "valve")]
+use crate::valve::ValveState;
+#[cfg(feature = "ota")]
+use crate::payload::UpdateState;
+#[cfg(feature = "lora")]
+use crate::codec::StateFrame;
+#[cfg(feature = "lora")]
+use crate::payload::SiblingState;
+use crate::payload::{is_http_url, on_off_template, retired_since, value_template, BuildAttributes, DiagState, Discovery, LatestFirmware, Retired};
+use crate::outbox::{Delivery, Outbox};
+use crate::provision;
+use crate::reconnect::{Action, OfflineQueue, Reconnect};
+use crate::trial::{self, Trial};
+use crate::twin::{self, Settings};
+
+pub use crate::payload::{Naming, PumpState, RadarDistances, WaterState};
+
 /// Device identifier for Home Assistant
 const DEVICE_ID: &str = "watercontroller";
 
+/// Revision of the discovery entity set
+///
+/// When an entity is removed, or its component, object id or unique id
+/// changes, bump this and list the old entity in `RETIRED`. On the first
+/// discovery after an upgrade the unit clears the retired configs, so Home
+/// Assistant drops the old entities instead of keeping them as orphans or
+/// duplicates next to the new ones. Entries are never removed, since a unit
+/// may be upgraded from any older firmware.
+pub const DISCOVERY_REVISION: u16 = 0;
+/// Entities retired from discovery, by the revision that retired them
+const RETIRED: &[Retired] = &[];
+/// CA certificate (NUL-terminated PEM), empty when built without `MQTT_CA_CERT`
+const EMBEDDED_CA_CERT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/mqtt_ca_cert.pem"));
+
 /// Command topics to subscribe to
 const CMD_TOPIC_TANK_CAPACITY: &str = "watercontroller/set/tank_capacity";
 const CMD_TOPIC_SENSOR_HEIGHT: &str = "watercontroller/set/sensor_height";
 const CMD_TOPIC_MAX_PSI: &str = "watercontroller/set/max_psi";
 const CMD_TOPIC_RADAR_HEIGHT: &str = "watercontroller/set/radar_height";
 const CMD_TOPIC_RADAR_DEADZONE: &str = "watercontroller/set/radar_deadzone";
+const CMD_TOPIC_FLUSH_LINES: &str = "watercontroller/set/flush_lines";
+const CMD_TOPIC_DISPLAY_REFRESH: &str = "watercontroller/set/display_refresh";
+const CMD_TOPIC_TANK_FILL: &str = "watercontroller/set/tank_fill";
+const CMD_TOPIC_REBOOT_DAY: &str = "watercontroller/set/reboot_day";
+const CMD_TOPIC_REBOOT_HOUR: &str = "watercontroller/set/reboot_hour";
+const CMD_TOPIC_MAINTENANCE: &str = "watercontroller/set/maintenance";
+const CMD_TOPIC_PUMP_START: &str = "watercontroller/set/pump_start";
+const CMD_TOPIC_PUMP_STOP: &str = "watercontroller/set/pump_stop";
+const CMD_TOPIC_PUMP_ASSIST: &str = "watercontroller/set/pump_assist";
+const CMD_TOPIC_PUMP_FAIL_MIN: &str = "watercontroller/set/pump_fail_min";
+const CMD_TOPIC_PUMP_RESET: &str = "watercontroller/set/pump_reset";
+const CMD_TOPIC_VFD_SETPOINT: &str = "watercontroller/set/vfd_setpoint";
+const CMD_TOPIC_VFD_KP: &str = "watercontroller/set/vfd_kp";
+const CMD_TOPIC_VFD_KI: &str = "watercontroller/set/vfd_ki";
+const CMD_TOPIC_VFD_KD: &str = "watercontroller/set/vfd_kd";
+const CMD_TOPIC_VFD_AUTOTUNE: &str = "watercontroller/set/vfd_autotune";
+const CMD_TOPIC_SHOW_DIAG: &str = "watercontroller/set/show_diag";
+const CMD_TOPIC_PSI_ZERO: &str = "watercontroller/set/psi_zero";
+const CMD_TOPIC_FILTER_RESET: &str = "watercontroller/set/filter_reset";
+const CMD_TOPIC_TANK_SHAPE: &str = "watercontroller/set/tank_shape";
+const CMD_TOPIC_RADAR_WARMUP: &str = "watercontroller/set/radar_warmup";
+const CMD_TOPIC_PSI_WARMUP: &str = "watercontroller/set/psi_warmup";
+const CMD_TOPIC_HEATER_MODE: &str = "watercontroller/set/heater_mode";
+const CMD_TOPIC_HEATER_SPREAD: &str = "watercontroller/set/heater_spread";
+const CMD_TOPIC_HEATER_DUTY: &str = "watercontroller/set/heater_duty";
+/// JSON document setting several number parameters at once
+const CMD_TOPIC_CONFIG: &str = "watercontroller/set/config";
+const CMD_TOPIC_LEVEL_MEDIAN: &str = "watercontroller/set/level_median";
+const CMD_TOPIC_LEVEL_ALPHA: &str = "watercontroller/set/level_alpha";
+const CMD_TOPIC_PAGE_INTERVAL: &str = "watercontroller/set/page_interval";
+const CMD_TOPIC_PUMP_MIN_ON: &str = "watercontroller/set/pump_min_on";
+const CMD_TOPIC_PUMP_MIN_OFF: &str = "watercontroller/set/pump_min_off";
+const CMD_TOPIC_PUMP_DRY_PSI: &str = "watercontroller/set/pump_dry_psi";
+const CMD_TOPIC_PUMP_DRY_SECS: &str = "watercontroller/set/pump_dry_secs";
+const CMD_TOPIC_VALVE_TRAVEL: &str = "watercontroller/set/valve_travel";
+/// Supply valve commands: `OPEN`, `CLOSE` or `STOP`
+const CMD_TOPIC_VALVE: &str = "watercontroller/set/valve";
+const CMD_TOPIC_HAMMER_PSI: &str = "watercontroller/set/hammer_psi";
+const CMD_TOPIC_BURST_PSI: &str = "watercontroller/set/burst_psi";
+const CMD_TOPIC_EFFICIENCY_DROP: &str = "watercontroller/set/efficiency_drop";
+const CMD_TOPIC_ALARM_LOW: &str = "watercontroller/set/alarm_low";
+const CMD_TOPIC_ALARM_HIGH_PSI: &str = "watercontroller/set/alarm_high_psi";
+const CMD_TOPIC_ALARM_CLOG_PSI: &str = "watercontroller/set/alarm_clog_psi";
+const CMD_TOPIC_ALARM_FAULT_SECS: &str = "watercontroller/set/alarm_fault_secs";
+const CMD_TOPIC_BUDGET: &str = "watercontroller/set/budget_gal";
+const CMD_TOPIC_FLOW_K: &str = "watercontroller/set/flow_k_factor";
+/// Ambient readings pushed by a Home Assistant automation (signed, not persisted)
+const CMD_TOPIC_AMBIENT_TEMP: &str = "watercontroller/set/ambient_temp";
+const CMD_TOPIC_AMBIENT_HUMIDITY: &str = "watercontroller/set/ambient_humidity";
+
+/// Retained desired configuration, applied on connect (see `twin`)
+const DESIRED_CONFIG_TOPIC: &str = "watercontroller/config/desired";
+/// Retained configuration as applied on the device
+const REPORTED_CONFIG_TOPIC: &str = "watercontroller/config/reported";
+/// Settings to try, reverted unless confirmed
+const CMD_TOPIC_TRIAL: &str = "watercontroller/set/trial";
+const CMD_TOPIC_TRIAL_CONFIRM: &str = "watercontroller/set/trial_confirm";
+/// Whether a trial is running (retained)
+const TRIAL_STATE_TOPIC: &str = "watercontroller/config/trial";
+/// Restart the device
+const CMD_TOPIC_REBOOT: &str = "watercontroller/cmd/reboot";
+/// Erase the stored configuration and restart
+const CMD_TOPIC_FACTORY_RESET: &str = "watercontroller/cmd/factory_reset";
+/// Install firmware: `install` for the latest announced image, or an image URL
+const CMD_TOPIC_UPDATE: &str = "watercontroller/cmd/update";
+/// Latest firmware version and its URL (retained, published by release tooling)
+const UPDATE_LATEST_TOPIC: &str = "watercontroller/update/latest";
+/// Running version and whether an update is being installed (retained)
+#[cfg(feature = "ota")]
+const UPDATE_STATE_TOPIC: &str = "watercontroller/update";
+
+/// Sensor state, also queued while offline
+const STATE_TOPIC: &str = "watercontroller/state";
+/// Maintenance switch state, kept apart from the (paused) sensor state
+const MAINTENANCE_STATE_TOPIC: &str = "watercontroller/maintenance";
+/// Device diagnostics (IP, uptime, heap, firmware, link, reset reason)
+const DIAG_STATE_TOPIC: &str = "watercontroller/diag";
+/// Diagnostic sensors show unavailable after missing a few publishes
+const DIAG_EXPIRE_SECS: u32 = 180;
+/// Init phase timings of the last boot (retained)
+const STARTUP_TOPIC: &str = "watercontroller/diag/startup";
+/// Firmware build info and board profile, attributes of the main sensors (retained)
+const BUILD_TOPIC: &str = "watercontroller/build";
+/// Windowed statistics from the flash history (retained)
+#[cfg(feature = "history")]
+const HISTORY_STATS_TOPIC: &str = "watercontroller/stats";
+/// DS18B20 probe temperatures
+#[cfg(feature = "ds18b20")]
+const PROBES_STATE_TOPIC: &str = "watercontroller/probes";
+/// Pump delivery rate trend (retained)
+#[cfg(feature = "efficiency")]
+const EFFICIENCY_STATE_TOPIC: &str = "watercontroller/efficiency";
+/// Water hammer events (not retained: each message is one event)
+#[cfg(feature = "hammer")]
+const HAMMER_EVENT_TOPIC: &str = "watercontroller/hammer";
+/// Alarm notifications (not retained: each message is one event)
+#[cfg(feature = "notify")]
+const NOTIFY_EVENT_TOPIC: &str = "watercontroller/notify";
+/// Lockout/tagout interlock state (retained)
+#[cfg(feature = "lockout")]
+const LOCKOUT_STATE_TOPIC: &str = "watercontroller/lockout";
+/// Supply valve position and fault flag (retained)
+#[cfg(feature = "valve")]
+const VALVE_STATE_TOPIC: &str = "watercontroller/valve";
+/// Prefix of the LoRa sibling state topics, followed by the node id
+#[cfg(feature = "lora")]
+const SIBLING_STATE_TOPIC: &str = "watercontroller/lora";
+/// Sibling entities go unavailable after three of the longest node intervals (s)
+#[cfg(feature = "lora")]
+const SIBLING_EXPIRE_SECS: u32 = 900;
+/// Probe sensors go unavailable when a probe stops reporting for this long (s)
+#[cfg(feature = "ds18b20")]
+const PROBE_EXPIRE_SECS: u32 = 120;
+
+/// Number entity: (disc_name, ha_name, unique_id, value_key, cmd_topic_suffix, step, unit, icon)
+///
+/// The command topic suffix is the provisioning key, whose table holds the
+/// range (`provision::number_range`).
+type NumberEntity = (&'static str, &'static str, &'static str, &'static str, &'static str, u16, &'static str, &'static str);
+
+/// Number entities (configurable parameters) common to all builds
+const NUMBERS: &[NumberEntity] = &[
+    // (disc_name, ha_name, unique_id, value_key, cmd_topic_suffix, step, unit, icon)
+    ("tank_capacity", "Tank Capacity", "wc_tank_cap", "tank_capacity", "tank_capacity", 10, "gal", "mdi:storage-tank"),
+    ("sensor_height", "Pressure sensor Height", "wc_height", "sensor_height", "sensor_height", 1, "ft", "mdi:arrow-expand-vertical"),
+    ("max_psi", "Manometer Range", "wc_max_psi", "max_psi", "max_psi", 10, "psi", "mdi:gauge"),
+    ("radar_height", "Radar Height", "wc_radar_ht", "radar_height", "radar_height", 1, "cm", "mdi:signal-distance-variant"),
+    ("radar_deadzone", "Radar Deadzone", "wc_radar_dz", "radar_deadzone", "radar_deadzone", 1, "cm", "mdi:arrow-collapse-down"),
+    ("flush_lines", "Display Lines per Flush", "wc_flush_lines", "flush_lines", "flush_lines", 1, "lines", "mdi:monitor-shimmer"),
+    ("display_refresh", "Display Full Refresh", "wc_display_refresh", "display_refresh", "display_refresh", 1, "min", "mdi:monitor-eye"),
+    ("tank_fill", "Tank Fill Pattern", "wc_tank_fill", "tank_fill", "tank_fill", 1, "", "mdi:texture-box"),
+    ("reboot_day", "Maintenance Reboot Day", "wc_reboot_day", "reboot_day", "reboot_day", 1, "", "mdi:calendar-refresh"),
+    ("reboot_hour", "Maintenance Reboot Hour", "wc_reboot_hour", "reboot_hour", "reboot_hour", 1, "h", "mdi:clock-outline"),
+    ("tank_shape", "Tank Shape", "wc_tank_shape", "tank_shape", "tank_shape", 1, "", "mdi:storage-tank-outline"),
+    ("radar_warmup", "Radar Warm-up", "wc_radar_warmup", "radar_warmup", "radar_warmup", 5, "s", "mdi:timer-sand"),
+    ("psi_warmup", "Pressure Sensor Warm-up", "wc_psi_warmup", "psi_warmup", "psi_warmup", 1, "s", "mdi:timer-sand"),
+    ("level_median", "Level Median Window", "wc_level_median", "level_median", "level_median", 2, "", "mdi:filter-outline"),
+    ("level_alpha", "Level Smoothing Factor", "wc_level_alpha", "level_alpha", "level_alpha", 5, "%", "mdi:chart-bell-curve-cumulative"),
+    ("page_interval", "Display Page Interval", "wc_page_interval", "page_interval", "page_interval", 5, "s", "mdi:page-next-outline"),
+    ("alarm_low", "Low Level Alarm", "wc_alarm_low", "alarm_low", "alarm_low", 1, "%", "mdi:water-alert"),
+    ("alarm_high_psi", "High Pressure Alarm", "wc_alarm_high_psi", "alarm_high_psi", "alarm_high_psi", 1, "psi", "mdi:gauge-full"),
+    ("alarm_fault_secs", "Sensor Fault Delay", "wc_alarm_fault_secs", "alarm_fault_secs", "alarm_fault_secs", 5, "s", "mdi:timer-alert-outline"),
+    ("budget_gal", "Daily Water Budget", "wc_budget_gal", "budget_gal", "budget_gal", 10, "gal", "mdi:water-check"),
+];
+
+/// Pump controller thresholds, only exposed when pumps are fitted
+#[cfg(feature = "pump")]
+const PUMP_NUMBERS: &[NumberEntity] = &[
+    ("pump_start", "Pump Start Level", "wc_pump_start", "pump_start", "pump_start", 1, "%", "mdi:water-pump"),
+    ("pump_stop", "Pump Stop Level", "wc_pump_stop", "pump_stop", "pump_stop", 1, "%", "mdi:water-pump-off"),
+    ("pump_assist", "Lag Pump Assist Drop", "wc_pump_assist", "pump_assist", "pump_assist", 1, "%", "mdi:arrow-down-bold"),
+    ("pump_fail_min", "Pump Failure Timeout", "wc_pump_fail_min", "pump_fail_min", "pump_fail_min", 1, "min", "mdi:timer-alert-outline"),
+    ("pump_min_on", "Pump Minimum On Time", "wc_pump_min_on", "pump_min_on", "pump_min_on", 10, "s", "mdi:timer-play-outline"),
+    ("pump_min_off", "Pump Minimum Off Time", "wc_pump_min_off", "pump_min_off", "pump_min_off", 10, "s", "mdi:timer-pause-outline"),
+    ("pump_dry_psi", "Pump Dry-Run Pressure", "wc_pump_dry_psi", "pump_dry_psi", "pump_dry_psi", 1, "psi", "mdi:water-off-outline"),
+    ("pump_dry_secs", "Pump Dry-Run Delay", "wc_pump_dry_secs", "pump_dry_secs", "pump_dry_secs", 5, "s", "mdi:timer-alert-outline"),
+];
+#[cfg(not(feature = "pump"))]
+const PUMP_NUMBERS: &[NumberEntity] = &[];
+
+/// Constant-pressure loop tuning, only exposed when a VFD is fitted
+#[cfg(feature = "vfd")]
+const VFD_NUMBERS: &[NumberEntity] = &[
+    ("vfd_setpoint", "Pressure Setpoint", "wc_vfd_setpoint", "vfd_setpoint", "vfd_setpoint", 1, "psi", "mdi:gauge"),
+    ("vfd_kp", "Speed PID Kp (x0.001)", "wc_vfd_kp", "vfd_kp", "vfd_kp", 1, "", "mdi:tune-variant"),
+    ("vfd_ki", "Speed PID Ki (x0.001)", "wc_vfd_ki", "vfd_ki", "vfd_ki", 1, "", "mdi:tune-variant"),
+    ("vfd_kd", "Speed PID Kd (x0.001)", "wc_vfd_kd", "vfd_kd", "vfd_kd", 1, "", "mdi:tune-variant"),
+];
+#[cfg(not(feature = "vfd"))]
+const VFD_NUMBERS: &[NumberEntity] = &[];
+
+/// Radar heater settings, only exposed when the heater is fitted
+#[cfg(feature = "heater")]
+const HEATER_NUMBERS: &[NumberEntity] = &[
+    ("heater_mode", "Radar Heater Mode", "wc_heater_mode", "heater_mode", "heater_mode", 1, "", "mdi:heating-coil"),
+    ("heater_spread", "Radar Heater Dew Point Spread", "wc_heater_spread", "heater_spread", "heater_spread", 1, "°C", "mdi:water-thermometer"),
+    ("heater_duty", "Radar Heater Duty Cycle", "wc_heater_duty", "heater_duty", "heater_duty", 5, "%", "mdi:sine-wave"),
+];
+#[cfg(not(feature = "heater"))]
+const HEATER_NUMBERS: &[NumberEntity] = &[];
+
+/// Pump efficiency alert threshold, only exposed when tracking is built in
+#[cfg(feature = "efficiency")]
+const EFFICIENCY_NUMBERS: &[NumberEntity] = &[
+    ("efficiency_drop", "Pump Efficiency Drop Alert", "wc_efficiency_drop", "efficiency_drop", "efficiency_drop", 1, "%", "mdi:chart-line-variant"),
+];
+#[cfg(not(feature = "efficiency"))]
+const EFFICIENCY_NUMBERS: &[NumberEntity] = &[];
+
+/// Water hammer capture threshold, only exposed when capture is built in
+#[cfg(feature = "hammer")]
+const HAMMER_NUMBERS: &[NumberEntity] = &[
+    ("hammer_psi", "Water Hammer Threshold", "wc_hammer_psi", "hammer_psi", "hammer_psi", 1, "psi", "mdi:pipe-leak"),
+];
+#[cfg(not(feature = "hammer"))]
+const HAMMER_NUMBERS: &[NumberEntity] = &[];
+
+/// Pipe burst pressure drop, only exposed when detection is built in
+#[cfg(feature = "pipe_burst")]
+const BURST_NUMBERS: &[NumberEntity] = &[
+    ("burst_psi", "Pipe Burst Pressure Drop", "wc_burst_psi", "burst_psi", "burst_psi", 1, "psi", "mdi:pipe-disconnected"),
+];
+#[cfg(not(feature = "pipe_burst"))]
+const BURST_NUMBERS: &[NumberEntity] = &[];
+
+/// Clogged-filter alarm threshold, only exposed with the post-filter sensor
+#[cfg(feature = "filter_pressure")]
+const FILTER_NUMBERS: &[NumberEntity] = &[
+    ("alarm_clog_psi", "Filter Clog Alarm", "wc_alarm_clog_psi", "alarm_clog_psi", "alarm_clog_psi", 1, "psi", "mdi:filter-remove"),
+];
+#[cfg(not(feature = "filter_pressure"))]
+const FILTER_NUMBERS: &[NumberEntity] = &[];
+
+/// Flow meter calibration, only exposed with the flow meter
+#[cfg(feature = "flow")]
+const FLOW_NUMBERS: &[NumberEntity] = &[
+    ("flow_k_factor", "Flow Meter K-Factor", "wc_flow_k_factor", "flow_k_factor", "flow_k_factor", 1, "pulses/gal", "mdi:counter"),
+];
+#[cfg(not(feature = "flow"))]
+const FLOW_NUMBERS: &[NumberEntity] = &[];
+
+/// Supply valve settings, only exposed when the valve is fitted
+#[cfg(feature = "valve")]
+const VALVE_NUMBERS: &[NumberEntity] = &[
+    ("valve_travel", "Supply Valve Travel Time", "wc_valve_travel", "valve_travel", "valve_travel", 1, "s", "mdi:valve"),
+];
+#[cfg(not(feature = "valve"))]
+const VALVE_NUMBERS: &[NumberEntity] = &[];
+
+/// Entities of the tank itself, named after the tank label
+fn is_tank_entity(object_id: &str) -> bool {
+    matches!(object_id, "capacity_percent" | "capacity_gallons" | "tank_capacity")
+        || object_id.starts_with("level_1h_")
+        || object_id.starts_with("level_24h_")
+}
+
+/// All number entities in this build
+fn number_entities() -> impl Iterator<Item = &'static NumberEntity> {
+    NUMBERS.iter().chain(PUMP_NUMBERS).chain(VFD_NUMBERS).chain(HEATER_NUMBERS).chain(EFFICIENCY_NUMBERS).chain(HAMMER_NUMBERS).chain(BURST_NUMBERS).chain(FILTER_NUMBERS).chain(FLOW_NUMBERS).chain(VALVE_NUMBERS)
+}
 
 /// Configuration command received from Home Assistant
 #[derive(Debug)]
@@ -34,35 +408,278 @@ pub enum ConfigCommand {
     SetMaxPsi(u16),
     SetRadarHeight(u16),
     SetRadarDeadzone(u16),
+    SetFlushLines(u16),
+    SetDisplayRefresh(u16),
+    SetTankFill(u16),
+    SetRebootDay(u16),
+    SetRebootHour(u16),
+    SetMaintenance(bool),
+    SetPumpStart(u16),
+    SetPumpStop(u16),
+    SetPumpAssist(u16),
+    SetPumpFailMinutes(u16),
+    ResetPumpFaults,
+    SetVfdSetpoint(u16),
+    SetVfdKp(u16),
+    SetVfdKi(u16),
+    SetVfdKd(u16),
+    StartVfdAutotune,
+    ShowDiagnostics,
+    /// Take the present pressure reading as 0 PSI
+    CalibratePressureZero,
+    /// Clear the clogged-filter alarm after a filter change
+    ResetFilterAlarm,
+    Reboot,
+    FactoryReset,
+    /// Download and install the firmware image at this URL
+    InstallFirmware(String),
+    SetTankShape(u16),
+    SetRadarWarmup(u16),
+    SetPressureWarmup(u16),
+    SetHeaterMode(u16),
+    SetHeaterSpread(u16),
+    SetHeaterDuty(u16),
+    SetLevelMedian(u16),
+    SetLevelSmoothing(u16),
+    SetPageInterval(u16),
+    SetPumpMinOn(u16),
+    SetPumpMinOff(u16),
+    SetPumpDryPsi(u16),
+    SetPumpDrySecs(u16),
+    SetValveTravel(u16),
+    OpenValve,
+    CloseValve,
+    StopValve,
+    SetHammerPsi(u16),
+    SetBurstPsi(u16),
+    SetEfficiencyDrop(u16),
+    SetAlarmLow(u16),
+    SetAlarmHighPsi(u16),
+    SetAlarmClogPsi(u16),
+    SetAlarmFaultSecs(u16),
+    SetBudget(u16),
+    SetFlowKFactor(u16),
+    /// Ambient temperature (°C)
+    AmbientTemperature(f32),
+    /// Ambient relative humidity (%)
+    AmbientHumidity(f32),
+    /// Validated settings from a bulk configuration document, applied together
+    ApplyConfig(Vec<ConfigCommand>),
+}
+
+impl ConfigCommand {
+    /// Expand a bulk configuration into its individual settings
+    pub fn into_commands(self) -> Vec<ConfigCommand> {
+        match self {
+            ConfigCommand::ApplyConfig(cmds) => cmds,
+            cmd => vec![cmd],
+        }
+    }
+
+    /// Command for a number topic suffix (`watercontroller/set/<suffix>`)
+    fn for_number(suffix: &str, value: u16) -> Option<Self> {
+        Some(match suffix {
+            "tank_capacity" => ConfigCommand::SetTankCapacity(value),
+            "sensor_height" => ConfigCommand::SetSensorHeight(value),
+            "max_psi" => ConfigCommand::SetMaxPsi(value),
+            "radar_height" => ConfigCommand::SetRadarHeight(value),
+            "radar_deadzone" => ConfigCommand::SetRadarDeadzone(value),
+            "flush_lines" => ConfigCommand::SetFlushLines(value),
+            "display_refresh" => ConfigCommand::SetDisplayRefresh(value),
+            "tank_fill" => ConfigCommand::SetTankFill(value),
+            "reboot_day" => ConfigCommand::SetRebootDay(value),
+            "reboot_hour" => ConfigCommand::SetRebootHour(value),
+            "pump_start" => ConfigCommand::SetPumpStart(value),
+            "pump_stop" => ConfigCommand::SetPumpStop(value),
+            "pump_assist" => ConfigCommand::SetPumpAssist(value),
+            "pump_fail_min" => ConfigCommand::SetPumpFailMinutes(value),
+            "vfd_setpoint" => ConfigCommand::SetVfdSetpoint(value),
+            "vfd_kp" => ConfigCommand::SetVfdKp(value),
+            "vfd_ki" => ConfigCommand::SetVfdKi(value),
+            "vfd_kd" => ConfigCommand::SetVfdKd(value),
+            "tank_shape" => ConfigCommand::SetTankShape(value),
+            "radar_warmup" => ConfigCommand::SetRadarWarmup(value),
+            "psi_warmup" => ConfigCommand::SetPressureWarmup(value),
+            "heater_mode" => ConfigCommand::SetHeaterMode(value),
+            "heater_spread" => ConfigCommand::SetHeaterSpread(value),
+            "heater_duty" => ConfigCommand::SetHeaterDuty(value),
+            "level_median" => ConfigCommand::SetLevelMedian(value),
+            "level_alpha" => ConfigCommand::SetLevelSmoothing(value),
+            "page_interval" => ConfigCommand::SetPageInterval(value),
+            "pump_min_on" => ConfigCommand::SetPumpMinOn(value),
+            "pump_min_off" => ConfigCommand::SetPumpMinOff(value),
+            "pump_dry_psi" => ConfigCommand::SetPumpDryPsi(value),
+            "pump_dry_secs" => ConfigCommand::SetPumpDrySecs(value),
+            "valve_travel" => ConfigCommand::SetValveTravel(value),
+            "hammer_psi" => ConfigCommand::SetHammerPsi(value),
+            "burst_psi" => ConfigCommand::SetBurstPsi(value),
+            "efficiency_drop" => ConfigCommand::SetEfficiencyDrop(value),
+            "alarm_low" => ConfigCommand::SetAlarmLow(value),
+            "alarm_high_psi" => ConfigCommand::SetAlarmHighPsi(value),
+            "alarm_clog_psi" => ConfigCommand::SetAlarmClogPsi(value),
+            "alarm_fault_secs" => ConfigCommand::SetAlarmFaultSecs(value),
+            "budget_gal" => ConfigCommand::SetBudget(value),
+            "flow_k_factor" => ConfigCommand::SetFlowKFactor(value),
+            _ => return None,
+        })
+    }
+
+    /// Parse a bulk configuration document (see `parse_settings`)
+    fn from_document(doc: &str) -> Result<Self, String> {
+        let settings = parse_settings(doc)?;
+        if settings.is_empty() {
+            return Err("no settings".to_string());
+        }
+        Ok(Self::from_settings(&settings))
+    }
+
+    /// Apply a list of validated settings together
+    fn from_settings(settings: &[(&str, u16)]) -> Self {
+        // Every number entity has a command
+        ConfigCommand::ApplyConfig(settings.iter().filter_map(|&(key, value)| Self::for_number(key, value)).collect())
+    }
+}
+
+/// Parse and validate a configuration document
+///
+/// Keys are number topic suffixes, e.g. `{"tank_capacity": 800, "pump_start": 30}`.
+/// The whole document is rejected if any key is unknown (or not fitted in
+/// this build), repeated, or its value is outside the entity's range.
+fn parse_settings(doc: &str) -> Result<Settings, String> {
+    validate_settings(&crate::json::parse_number_object(doc)?)
+}
+
+/// Parse a trial document: settings plus an optional `trial_minutes`
+fn parse_trial(doc: &str) -> Result<(Settings, Duration), String> {
+    let mut members = crate::json::parse_number_object(doc)?;
+    let mut minutes = trial::DEFAULT_MINUTES;
+    if let Some(i) = members.iter().position(|(k, _)| k == "trial_minutes") {
+        let (_, value) = members.remove(i);
+        if value < 1.0 || value > trial::MAX_MINUTES as f32 {
+            return Err(format!("{} out of range 1..{} for \"trial_minutes\"", value, trial::MAX_MINUTES));
+        }
+        minutes = value.round() as u16;
+    }
+    let settings = validate_settings(&members)?;
+    if settings.is_empty() {
+        return Err("no settings".to_string());
+    }
+    Ok((settings, Duration::from_secs(minutes as u64 * 60)))
+}
+
+/// Check keys and ranges of number settings
+fn validate_settings(members: &[(String, f32)]) -> Result<Settings, String> {
+    let mut settings = Settings::with_capacity(members.len());
+    for (i, (key, value)) in members.iter().enumerate() {
+        if members[..i].iter().any(|(k, _)| k == key) {
+            return Err(format!("duplicate key \"{}\"", key));
+        }
+        let Some(suffix) = number_entities().map(|e| e.4).find(|&suffix| suffix == key) else {
+            return Err(format!("unknown key \"{}\"", key));
+        };
+        let (min, max) = provision::number_range(suffix).ok_or_else(|| format!("unknown key \"{}\"", key))?;
+        if *value < min as f32 || *value > max as f32 {
+            return Err(format!("{} out of range {}..{} for \"{}\"", value, min, max, key));
+        }
+        settings.push((suffix, value.round() as u16));
+    }
+    Ok(settings)
+}
+
+/// Trial request from the command topics, handled on the next state publish
+#[derive(Debug)]
+enum TrialRequest {
+    Start(Settings, Duration),
+    Confirm,
+}
+
+/// TLS settings for the broker connection (PEM, empty = not set)
+#[derive(Debug, Clone, Default)]
+pub struct MqttTls {
+    pub ca_cert: String,
+    pub client_cert: String,
+    pub client_key: String,
+}
+
+/// Retained discovery config topic of one of this device's entities
+fn discovery_topic(entity_type: &str, entity_name: &str) -> String {
+    format!("homeassistant/{}/{}_{}/config", entity_type, DEVICE_ID, entity_name)
+}
+
+/// PEM text as the `'static` certificate ESP-TLS keeps using
+///
+/// Leaks the copy: the client is created once per boot.
+fn leak_pem(pem: &str) -> Option<X509<'static>> {
+    let pem = CString::new(pem.trim()).ok()?;
+    Some(X509::pem(Box::leak(pem.into_boxed_c_str())))
 }
 
 /// Home Assistant MQTT client wrapper
 pub struct HomeAssistant {
     client: EspMqttClient<'static>,
+    /// Discovery acknowledged on the current connection
     discovery_sent: bool,
+    /// Discovery queued in the outbox and not yet all acknowledged
+    discovery_queued: bool,
+    /// Discovery configs waiting to be published or acknowledged
+    outbox: Outbox,
+    /// Discovery revision whose retired entities are cleared
+    cleared_revision: u16,
+    /// Device name, area and entity prefix applied to every discovery config
+    naming: Naming,
+    /// Publish only: no command subscriptions or controllable entities
+    read_only: bool,
+    /// Board profile name for the build attributes
+    board: &'static str,
     /// Last connection error from the MQTT event callback
     conn_error: Arc<Mutex<Option<String>>>,
+    broker: String,
+    port: u16,
+    /// Connection counters, shared with the event callback
+    stats: Arc<Mutex<ConnStats>>,
+    /// Desired configuration received but not yet reconciled
+    desired: Arc<Mutex<Option<Settings>>>,
+    /// Last published reported configuration
+    reported: Option<Settings>,
+    /// For settings derived from the desired configuration
+    cmd_tx: Sender<ConfigCommand>,
+    /// Trial request received but not yet handled
+    trial_request: Arc<Mutex<Option<TrialRequest>>>,
+    /// Running trial configuration
+    trial: Option<Trial>,
+    /// Last published trial state
+    trial_reported: Option<bool>,
+    /// Time base for trial deadlines
+    created: Instant,
+    /// Probes (ROM code, name) with published discovery
+    #[cfg(feature = "ds18b20")]
+    probes_discovered: Vec<(u64, String)>,
+    /// LoRa nodes with published discovery
+    #[cfg(feature = "lora")]
+    siblings_discovered: Vec<u8>,
+    reconnect: Reconnect,
+    /// State payloads not yet published, flushed on reconnect
+    offline: OfflineQueue,
+    /// Subscriptions and discovery still to be redone on this connection
+    resync_pending: bool,
 }
 
-/// Sensor state to publish
+/// State samples kept while the broker is unreachable
+const OFFLINE_QUEUE_LEN: usize = 12;
+
+/// Connection history for diagnostics
 #[derive(Default)]
-pub struct WaterState {
-    /// Tank capacity percentage (0-100)
-    pub capacity_percent: u8,
-    /// Tank capacity in gallons
-    pub capacity_gallons: u16,
-    /// Water pressure in PSI
-    pub pressure_psi: u16,
-    /// Configured tank capacity (gallons)
-    pub tank_capacity: u16,
-    /// Configured sensor height (feet)
-    pub sensor_height: u16,
-    /// Configured manometer max PSI
-    pub max_psi: u16,
-    /// Configured radar installation height (cm)
-    pub radar_height: u16,
-    /// Configured radar deadzone (cm) — distance from sensor to max water level
-    pub radar_deadzone: u16,
+struct ConnStats {
+    connected_since: Option<Instant>,
+    /// Connected and disconnected events since boot
+    connects: u32,
+    drops: u32,
+    /// Messages and bytes published and received, by topic class
+    traffic: Traffic,
+    /// Unlike `conn_error`, not cleared on reconnect
+    last_error: Option<String>,
+    /// Publish acknowledgements and deletions, taken by `poll()` for the outbox
+    deliveries: Vec<Delivery>,
 }
 
 impl HomeAssistant {
@@ -70,31 +687,62 @@ impl HomeAssistant {
     ///
     /// Commands received on `watercontroller/set/*` topics are parsed and
     /// forwarded to the main loop via the provided `cmd_tx` channel.
+    /// `tls` selects an `mqtts://` connection.
     pub fn new(
         broker: &str,
         port: u16,
         username: &str,
         password: &str,
+        tls: Option<&MqttTls>,
         cmd_tx: Sender<ConfigCommand>,
     ) -> Result<Self, esp_idf_svc::sys::EspError> {
-        let broker_url = format!("mqtt://{}:{}", broker, port);
+        let scheme = if tls.is_some() { "mqtts" } else { "mqtt" };
+        let broker_url = format!("{}://{}:{}", scheme, broker, port);
         info!("Connecting to MQTT broker at {}", broker_url);
 
-        let mqtt_config = MqttClientConfiguration {
+        let mut mqtt_config = MqttClientConfiguration {
             client_id: Some(DEVICE_ID),
             username: if username.is_empty() { None } else { Some(username) },
             password: if password.is_empty() { None } else { Some(password) },
+            // Reconnects are driven by `poll()`
+            reconnect_timeout: None,
             ..Default::default()
         };
+        if let Some(tls) = tls {
+            mqtt_config.server_certificate = if !tls.ca_cert.trim().is_empty() {
+                info!("MQTT: verifying broker against the configured CA");
+                leak_pem(&tls.ca_cert)
+            } else if !EMBEDDED_CA_CERT.is_empty() {
+                info!("MQTT: verifying broker against the built-in CA");
+                Some(X509::pem_until_nul(EMBEDDED_CA_CERT))
+            } else {
+                info!("MQTT: verifying broker against the certificate bundle");
+                mqtt_config.crt_bundle_attach = Some(esp_idf_svc::sys::esp_crt_bundle_attach);
+                None
+            };
+            if !tls.client_cert.trim().is_empty() && !tls.client_key.trim().is_empty() {
+                info!("MQTT: using client certificate");
+                mqtt_config.client_certificate = leak_pem(&tls.client_cert);
+                mqtt_config.private_key = leak_pem(&tls.client_key);
+            }
+        }
 
         let conn_error: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
         let conn_error_cb = conn_error.clone();
+        let stats: Arc<Mutex<ConnStats>> = Arc::new(Mutex::new(ConnStats::default()));
+        let stats_cb = stats.clone();
+        let desired: Arc<Mutex<Option<Settings>>> = Arc::new(Mutex::new(None));
+        let desired_cb = desired.clone();
+        let trial_request: Arc<Mutex<Option<TrialRequest>>> = Arc::new(Mutex::new(None));
+        let trial_request_cb = trial_request.clone();
+        let latest_firmware: Arc<Mutex<Option<LatestFirmware>>> = Arc::new(Mutex::new(None));
+        let cmd_tx_cb = cmd_tx.clone();
 
         let client = EspMqttClient::new_cb(
             &broker_url,
             &mqtt_config,
             move |event| {
-                Self::handle_event(&event, &cmd_tx, &conn_error_cb);
+                Self::handle_event(&event, &cmd_tx_cb, &conn_error_cb, &stats_cb, &desired_cb, &trial_request_cb, &latest_firmware);
             },
         )?;
 
@@ -103,7 +751,30 @@ impl HomeAssistant {
         Ok(Self {
             client,
             discovery_sent: false,
+            discovery_queued: false,
+            outbox: Outbox::new(),
+            cleared_revision: DISCOVERY_REVISION,
+            naming: Naming::default(),
+            read_only: false,
+            board: "",
             conn_error,
+            broker: broker.to_string(),
+            port,
+            stats,
+            desired,
+            reported: None,
+            cmd_tx,
+            trial_request,
+            trial: None,
+            trial_reported: None,
+            created: Instant::now(),
+            #[cfg(feature = "ds18b20")]
+            probes_discovered: Vec::new(),
+            #[cfg(feature = "lora")]
+            siblings_discovered: Vec::new(),
+            reconnect: Reconnect::new(Duration::ZERO),
+            offline: OfflineQueue::new(OFFLINE_QUEUE_LEN),
+            resync_pending: false,
         })
     }
 
@@ -112,32 +783,175 @@ impl HomeAssistant {
         event: &EspMqttEvent,
         cmd_tx: &Sender<ConfigCommand>,
         conn_error: &Arc<Mutex<Option<String>>>,
+        stats: &Arc<Mutex<ConnStats>>,
+        desired: &Arc<Mutex<Option<Settings>>>,
+        trial_request: &Arc<Mutex<Option<TrialRequest>>>,
+        latest_firmware: &Arc<Mutex<Option<LatestFirmware>>>,
     ) {
         use esp_idf_svc::mqtt::client::EventPayload;
 
         match event.payload() {
             EventPayload::Received { topic, data, .. } => {
+                if let Ok(mut stats) = stats.lock() {
+                    stats.traffic.record_received(topic, data.len());
+                }
                 let Some(topic) = topic else { return };
                 let Ok(value_str) = std::str::from_utf8(data) else {
                     warn!("MQTT: non-UTF8 payload on {}", topic);
                     return;
                 };
+                // Switch payloads are ON/OFF rather than numbers
+                if topic == CMD_TOPIC_MAINTENANCE {
+                    let cmd = ConfigCommand::SetMaintenance(value_str.trim().eq_ignore_ascii_case("ON"));
+                    info!("MQTT command: {:?}", cmd);
+                    let _ = cmd_tx.send(cmd);
+                    return;
+                }
+                // Button presses carry no value
+                if topic == CMD_TOPIC_PUMP_RESET {
+                    info!("MQTT command: {:?}", ConfigCommand::ResetPumpFaults);
+                    let _ = cmd_tx.send(ConfigCommand::ResetPumpFaults);
+                    return;
+                }
+                if topic == CMD_TOPIC_VFD_AUTOTUNE {
+                    info!("MQTT command: {:?}", ConfigCommand::StartVfdAutotune);
+                    let _ = cmd_tx.send(ConfigCommand::StartVfdAutotune);
+                    return;
+                }
+                if topic == CMD_TOPIC_SHOW_DIAG {
+                    info!("MQTT command: {:?}", ConfigCommand::ShowDiagnostics);
+                    let _ = cmd_tx.send(ConfigCommand::ShowDiagnostics);
+                    return;
+                }
+                if topic == CMD_TOPIC_PSI_ZERO {
+                    info!("MQTT command: {:?}", ConfigCommand::CalibratePressureZero);
+                    let _ = cmd_tx.send(ConfigCommand::CalibratePressureZero);
+                    return;
+                }
+                if topic == CMD_TOPIC_FILTER_RESET {
+                    info!("MQTT command: {:?}", ConfigCommand::ResetFilterAlarm);
+                    let _ = cmd_tx.send(ConfigCommand::ResetFilterAlarm);
+                    return;
+                }
+                // A stray publish must not restart or wipe the unit
+                if topic == CMD_TOPIC_REBOOT || topic == CMD_TOPIC_FACTORY_RESET {
+                    if value_str.trim() != "PRESS" {
+                        warn!("MQTT: ignoring {:?} on {}", value_str, topic);
+                        return;
+                    }
+                    let cmd = if topic == CMD_TOPIC_REBOOT { ConfigCommand::Reboot } else { ConfigCommand::FactoryReset };
+                    info!("MQTT command: {:?}", cmd);
+                    let _ = cmd_tx.send(cmd);
+                    return;
+                }
+                // Kept until HA asks to install it
+                if topic == UPDATE_LATEST_TOPIC {
+                    match LatestFirmware::parse(value_str) {
+                        Ok(latest) => {
+                            info!("MQTT: latest firmware {} at {}", latest.latest_version, latest.url);
+                            if let Ok(mut stored) = latest_firmware.lock() {
+                                *stored = Some(latest);
+                            }
+                        }
+                        Err(e) => warn!("MQTT: rejected latest firmware: {}", e),
+                    }
+                    return;
+                }
+                if topic == CMD_TOPIC_UPDATE {
+                    let payload = value_str.trim();
+                    let url = if payload == "install" {
+                        latest_firmware.lock().ok().and_then(|latest| latest.as_ref().map(|l| l.url.clone()))
+                    } else if is_http_url(payload) {
+                        Some(payload.to_string())
+                    } else {
+                        None
+                    };
+                    let Some(url) = url else {
+                        warn!("MQTT: no firmware to install for {:?}", payload);
+                        return;
+                    };
+                    let cmd = ConfigCommand::InstallFirmware(url);
+                    info!("MQTT command: {:?}", cmd);
+                    let _ = cmd_tx.send(cmd);
+                    return;
+                }
+                // Valve payloads name the action
+                if topic == CMD_TOPIC_VALVE {
+                    let cmd = match value_str.trim().to_ascii_uppercase().as_str() {
+                        "OPEN" => ConfigCommand::OpenValve,
+                        "CLOSE" => ConfigCommand::CloseValve,
+                        "STOP" => ConfigCommand::StopValve,
+                        _ => {
+                            warn!("MQTT: unknown valve command {:?}", value_str);
+                            return;
+                        }
+                    };
+                    info!("MQTT command: {:?}", cmd);
+                    let _ = cmd_tx.send(cmd);
+                    return;
+                }
+                if topic == CMD_TOPIC_CONFIG {
+                    match ConfigCommand::from_document(value_str) {
+                        Ok(cmd) => {
+                            info!("MQTT command: {:?}", cmd);
+                            let _ = cmd_tx.send(cmd);
+                        }
+                        Err(e) => warn!("MQTT: rejected config document: {}", e),
+                    }
+                    return;
+                }
+                // Reconciled against the reported configuration on the next state publish
+                if topic == DESIRED_CONFIG_TOPIC {
+                    match parse_settings(value_str) {
+                        Ok(settings) => {
+                            info!("MQTT: desired config with {} settings", settings.len());
+                            if let Ok(mut desired) = desired.lock() {
+                                *desired = Some(settings);
+                            }
+                        }
+                        Err(e) => warn!("MQTT: rejected desired config: {}", e),
+                    }
+                    return;
+                }
+                // Trials need the reported configuration, so are handled on the next state publish
+                if topic == CMD_TOPIC_TRIAL || topic == CMD_TOPIC_TRIAL_CONFIRM {
+                    let request = if topic == CMD_TOPIC_TRIAL_CONFIRM {
+                        TrialRequest::Confirm
+                    } else {
+                        match parse_trial(value_str) {
+                            Ok((settings, period)) => TrialRequest::Start(settings, period),
+                            Err(e) => {
+                                warn!("MQTT: rejected trial config: {}", e);
+                                return;
+                            }
+                        }
+                    };
+                    info!("MQTT command: {:?}", request);
+                    if let Ok(mut pending) = trial_request.lock() {
+                        *pending = Some(request);
+                    }
+                    return;
+                }
                 let Ok(value) = value_str.trim().parse::<f32>() else {
                     warn!("MQTT: invalid number '{}' on {}", value_str, topic);
                     return;
                 };
+                // Ambient readings keep their sign and fraction
+                if topic == CMD_TOPIC_AMBIENT_TEMP || topic == CMD_TOPIC_AMBIENT_HUMIDITY {
+                    let cmd = if topic == CMD_TOPIC_AMBIENT_TEMP {
+                        ConfigCommand::AmbientTemperature(value)
+                    } else {
+                        ConfigCommand::AmbientHumidity(value)
+                    };
+                    debug!("MQTT command: {:?}", cmd);
+                    let _ = cmd_tx.send(cmd);
+                    return;
+                }
                 let value = value.round() as u16;
 
-                let cmd = match topic {
-                    CMD_TOPIC_TANK_CAPACITY => ConfigCommand::SetTankCapacity(value),
-                    CMD_TOPIC_SENSOR_HEIGHT => ConfigCommand::SetSensorHeight(value),
-                    CMD_TOPIC_MAX_PSI => ConfigCommand::SetMaxPsi(value),
-                    CMD_TOPIC_RADAR_HEIGHT => ConfigCommand::SetRadarHeight(value),
-                    CMD_TOPIC_RADAR_DEADZONE => ConfigCommand::SetRadarDeadzone(value),
-                    _ => {
-                        debug!("MQTT: unknown topic {}", topic);
-                        return;
-                    }
+                let Some(cmd) = topic.strip_prefix("watercontroller/set/").and_then(|suffix| ConfigCommand::for_number(suffix, value)) else {
+                    debug!("MQTT: unknown topic {}", topic);
+                    return;
                 };
 
                 info!("MQTT command: {:?}", cmd);
@@ -149,14 +963,35 @@ impl HomeAssistant {
                 if let Ok(mut err) = conn_error.lock() {
                     *err = None;
                 }
+                if let Ok(mut stats) = stats.lock() {
+                    stats.connected_since = Some(Instant::now());
+                    stats.connects += 1;
+                }
+            }
+            EventPayload::Published(id) => {
+                if let Ok(mut stats) = stats.lock() {
+                    stats.deliveries.push(Delivery::Published(id));
+                }
+            }
+            EventPayload::Deleted(id) => {
+                if let Ok(mut stats) = stats.lock() {
+                    stats.deliveries.push(Delivery::Deleted(id));
+                }
             }
             EventPayload::Disconnected => {
                 warn!("MQTT disconnected");
+                if let Ok(mut stats) = stats.lock() {
+                    stats.connected_since = None;
+                    stats.drops += 1;
+                }
             }
             EventPayload::Error(_) => {
                 // Extract detailed error from the raw event's error_handle
                 let msg = Self::extract_error_detail(event);
                 warn!("MQTT error: {}", msg);
+                if let Ok(mut stats) = stats.lock() {
+                    stats.last_error = Some(msg.clone());
+                }
                 if let Ok(mut err) = conn_error.lock() {
                     *err = Some(msg);
                 }
@@ -202,122 +1037,1316 @@ impl HomeAssistant {
         self.conn_error.lock().ok().and_then(|e| e.clone())
     }
 
+    /// Broker, connection and counter snapshot for the diagnostics page
+    pub fn diagnostics(&self) -> MqttDiag {
+        let stats = self.stats.lock().unwrap();
+        MqttDiag {
+            broker: self.broker.clone(),
+            port: self.port,
+            connected: stats.connected_since.is_some(),
+            connected_secs: stats.connected_since.map(|t| t.elapsed().as_secs()).unwrap_or(0),
+            published: stats.traffic.total_published().messages,
+            received: stats.traffic.total_received().messages,
+            last_error: stats.last_error.clone(),
+            traffic: stats.traffic.clone(),
+        }
+    }
+
+    /// Whether the broker connection is up
+    pub fn is_connected(&self) -> bool {
+        self.reconnect.is_connected()
+    }
+
+    /// Whether the broker has acknowledged discovery on the current connection
+    pub fn is_discovered(&self) -> bool {
+        self.discovery_sent
+    }
+
+    /// Set the discovery revision cleared on an earlier boot (stored in config)
+    ///
+    /// Entities retired since then are cleared with the next discovery.
+    pub fn set_cleared_revision(&mut self, revision: u16) {
+        self.cleared_revision = revision;
+    }
+
+    /// Set how the device and its entities are named (stored in config)
+    ///
+    /// Applies from the next discovery; Home Assistant keeps the entity ids
+    /// it derived from the first names it saw.
+    pub fn set_naming(&mut self, naming: Naming) {
+        self.naming = naming;
+    }
+
+    /// Publish only, for a broker shared with clients that must not
+    /// reconfigure the unit; applies from the next connection
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Board profile reported with the build info
+    pub fn set_board(&mut self, board: &'static str) {
+        self.board = board;
+    }
+
+    /// Discovery revision cleared so far, to store once it changes
+    pub fn cleared_revision(&self) -> u16 {
+        self.cleared_revision
+    }
+
+    /// Drive reconnects, resync after a new connection and pace discovery
+    ///
+    /// Call on every loop pass. Returns true once a new connection has been
+    /// resynced, so the caller can re-publish its own retained state.
+    pub fn poll(&mut self) -> bool {
+        let (connected, connects, drops, deliveries) = {
+            let mut stats = self.stats.lock().unwrap();
+            (stats.connected_since.is_some(), stats.connects, stats.drops, std::mem::take(&mut stats.deliveries))
+        };
+        for delivery in deliveries {
+            self.outbox.report(delivery);
+        }
+        if self.reconnect.is_connected() && !self.resync_pending {
+            self.send_outbox();
+        }
+        match self.reconnect.update(connected, connects, drops, self.created.elapsed()) {
+            Action::Reconnect => {
+                info!(
+                    "MQTT: reconnecting (attempt {}, next wait {} s)",
+                    self.reconnect.attempts(),
+                    self.reconnect.delay().as_secs()
+                );
+                let err = unsafe { esp_idf_svc::sys::esp_mqtt_client_reconnect(self.client.handle()) };
+                if err != esp_idf_svc::sys::ESP_OK {
+                    warn!("MQTT: reconnect failed to start: {}", err);
+                }
+            }
+            Action::Resync => self.resync_pending = true,
+            Action::None => {}
+        }
+        if !self.resync_pending || !self.reconnect.is_connected() {
+            return false;
+        }
+        match self.resync() {
+            Ok(()) => {
+                self.resync_pending = false;
+                true
+            }
+            Err(e) => {
+                // Retried on the next pass
+                warn!("MQTT: resync failed: {:?}", e);
+                false
+            }
+        }
+    }
+
+    /// Subscribe, re-send discovery and flush the offline queue
+    fn resync(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.subscribe()?;
+        // A restarted broker may have lost the retained messages
+        self.discovery_sent = false;
+        self.discovery_queued = false;
+        self.outbox.clear();
+        self.reported = None;
+        self.trial_reported = None;
+        #[cfg(feature = "ds18b20")]
+        self.probes_discovered.clear();
+        #[cfg(feature = "lora")]
+        self.siblings_discovered.clear();
+        self.send_discovery()?;
+
+        if !self.offline.is_empty() {
+            info!("MQTT: flushing {} queued state samples ({} dropped)", self.offline.len(), self.offline.dropped());
+        }
+        while let Some(payload) = self.offline.pop() {
+            if let Err(e) = self.publish(STATE_TOPIC, QoS::AtMostOnce, false, payload.as_bytes()) {
+                self.offline.unpop(payload);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Publish and count the message, returning the client's message ID
+    fn publish(&mut self, topic: &str, qos: QoS, retain: bool, payload: &[u8]) -> Result<u32, esp_idf_svc::sys::EspError> {
+        let id = self.client.publish(topic, qos, retain, payload)?;
+        self.stats.lock().unwrap().traffic.record_published(topic, payload.len());
+        Ok(id)
+    }
+
+    /// Publish the outbox messages due now, and finish discovery once it's all acknowledged
+    fn send_outbox(&mut self) {
+        let now = self.created.elapsed();
+        while let Some(message) = self.outbox.next(now) {
+            match self.publish(&message.topic, QoS::AtLeastOnce, true, &message.payload) {
+                Ok(id) => self.outbox.sent(message, id, now),
+                Err(e) => {
+                    debug!("MQTT: could not queue {}: {:?}", message.topic, e);
+                    self.outbox.failed(message);
+                    break;
+                }
+            }
+        }
+        if !self.discovery_queued || !self.outbox.is_idle() {
+            return;
+        }
+        self.discovery_queued = false;
+        if self.outbox.abandoned() > 0 {
+            // Queued again with the next state
+            warn!("MQTT: {} discovery messages not acknowledged", self.outbox.abandoned());
+            self.outbox.clear();
+            return;
+        }
+        self.discovery_sent = true;
+        self.cleared_revision = self.cleared_revision.max(DISCOVERY_REVISION);
+        info!("Discovery messages acknowledged");
+    }
+
     /// Subscribe to command topics
     pub fn subscribe(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
-        info!("Subscribing to command topics...");
         const CMD_TOPICS: &[&str] = &[
             CMD_TOPIC_TANK_CAPACITY,
             CMD_TOPIC_SENSOR_HEIGHT,
             CMD_TOPIC_MAX_PSI,
             CMD_TOPIC_RADAR_HEIGHT,
             CMD_TOPIC_RADAR_DEADZONE,
+            CMD_TOPIC_FLUSH_LINES,
+            CMD_TOPIC_DISPLAY_REFRESH,
+            CMD_TOPIC_TANK_FILL,
+            CMD_TOPIC_REBOOT_DAY,
+            CMD_TOPIC_REBOOT_HOUR,
+            CMD_TOPIC_MAINTENANCE,
+            CMD_TOPIC_PUMP_START,
+            CMD_TOPIC_PUMP_STOP,
+            CMD_TOPIC_PUMP_ASSIST,
+            CMD_TOPIC_PUMP_FAIL_MIN,
+            CMD_TOPIC_PUMP_RESET,
+            CMD_TOPIC_VFD_SETPOINT,
+            CMD_TOPIC_VFD_KP,
+            CMD_TOPIC_VFD_KI,
+            CMD_TOPIC_VFD_KD,
+            CMD_TOPIC_VFD_AUTOTUNE,
+            CMD_TOPIC_SHOW_DIAG,
+            CMD_TOPIC_PSI_ZERO,
+            CMD_TOPIC_FILTER_RESET,
+            CMD_TOPIC_TANK_SHAPE,
+            CMD_TOPIC_RADAR_WARMUP,
+            CMD_TOPIC_PSI_WARMUP,
+            CMD_TOPIC_HEATER_MODE,
+            CMD_TOPIC_HEATER_SPREAD,
+            CMD_TOPIC_HEATER_DUTY,
+            CMD_TOPIC_AMBIENT_TEMP,
+            CMD_TOPIC_AMBIENT_HUMIDITY,
+            CMD_TOPIC_LEVEL_MEDIAN,
+            CMD_TOPIC_LEVEL_ALPHA,
+            CMD_TOPIC_PAGE_INTERVAL,
+            CMD_TOPIC_PUMP_MIN_ON,
+            CMD_TOPIC_PUMP_MIN_OFF,
+            CMD_TOPIC_PUMP_DRY_PSI,
+            CMD_TOPIC_PUMP_DRY_SECS,
+            CMD_TOPIC_VALVE_TRAVEL,
+            CMD_TOPIC_VALVE,
+            CMD_TOPIC_HAMMER_PSI,
+            CMD_TOPIC_BURST_PSI,
+            CMD_TOPIC_EFFICIENCY_DROP,
+            CMD_TOPIC_ALARM_LOW,
+            CMD_TOPIC_ALARM_HIGH_PSI,
+            CMD_TOPIC_ALARM_CLOG_PSI,
+            CMD_TOPIC_ALARM_FAULT_SECS,
+            CMD_TOPIC_BUDGET,
+            CMD_TOPIC_FLOW_K,
+            CMD_TOPIC_CONFIG,
+            DESIRED_CONFIG_TOPIC,
+            CMD_TOPIC_TRIAL,
+            CMD_TOPIC_TRIAL_CONFIRM,
+            CMD_TOPIC_REBOOT,
+            CMD_TOPIC_FACTORY_RESET,
         ];
-        for topic in CMD_TOPICS {
-            self.client.subscribe(topic, QoS::AtLeastOnce)?;
+        if self.read_only {
+            info!("Read-only: not subscribing to command topics");
+        } else {
+            info!("Subscribing to command topics...");
+            for topic in CMD_TOPICS {
+                self.client.subscribe(topic, QoS::AtLeastOnce)?;
+            }
+            #[cfg(feature = "ota")]
+            self.client.subscribe(CMD_TOPIC_UPDATE, QoS::AtLeastOnce)?;
+            info!("Subscribed to command topics");
         }
-        info!("Subscribed to command topics");
+        // The latest release only feeds the update entity
+        #[cfg(feature = "ota")]
+        self.client.subscribe(UPDATE_LATEST_TOPIC, QoS::AtLeastOnce)?;
         Ok(())
     }
 
     /// Send Home Assistant MQTT discovery messages
     ///
     /// This configures the sensors and number entities in Home Assistant automatically.
-    /// Should be called once after connection is established.
+    /// Should be called once after connection is established. The messages
+    /// are queued in the outbox and published by `poll()`.
     pub fn send_discovery(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
-        if self.discovery_sent {
+        if self.discovery_sent || self.discovery_queued {
             return Ok(());
         }
 
-        info!("Sending Home Assistant discovery messages...");
+        info!("Queueing Home Assistant discovery messages...");
 
-        // Common device info (shared by all entities)
-        let device_info = r#""dev":{"ids":"watercontroller","name":"Water Controller","mf":"DIY","mdl":"wESP32"}"#;
+        // Retired entities go first, so a config that keeps its topic under a
+        // new unique id replaces the old entity instead of clashing with it
+        for retired in retired_since(RETIRED, self.cleared_revision) {
+            let topic = discovery_topic(retired.component, retired.object_id);
+            info!("Removing retired entity {}", topic);
+            self.outbox.push(topic, &[]);
+        }
+
+        // Build info first, so the attributes are there when the sensors appear
+        let build = BuildAttributes::running(self.board).to_json();
+        self.outbox.push(BUILD_TOPIC, build.as_bytes());
 
         // Sensor entities (read-only)
-        const SENSORS: &[(&str, &str, &str, &str, &str, &str)] = &[
-            // (discovery_name, ha_name, unique_id, value_key, unit, extra)
-            ("capacity_percent", "Water Capacity", "wc_capacity_pct", "capacity_pct", "%", r#""dev_cla":"battery","stat_cla":"measurement""#),
-            ("capacity_gallons", "Water Volume", "wc_capacity_gal", "gallons", "gal", r#""ic":"mdi:water","stat_cla":"measurement""#),
-            ("pressure", "Water Pressure", "wc_pressure", "pressure_psi", "psi", r#""dev_cla":"pressure","stat_cla":"measurement""#),
+        type Sensor = (&'static str, &'static str, &'static str, &'static str, &'static str, Option<&'static str>, &'static str, Option<&'static str>);
+        const SENSORS: &[Sensor] = &[
+            // (discovery_name, ha_name, unique_id, value_key, unit, device_class, state_class, icon)
+            ("capacity_percent", "Water Capacity", "wc_capacity_pct", "capacity_pct", "%", Some("battery"), "measurement", None),
+            ("capacity_gallons", "Water Volume", "wc_capacity_gal", "gallons", "gal", None, "measurement", Some("mdi:water")),
+            ("pressure", "Water Pressure", "wc_pressure", "pressure_psi", "psi", Some("pressure"), "measurement", None),
+            ("used_today", "Water Used Today", "wc_used_today", "used_today", "gal", Some("water"), "total_increasing", None),
+            ("refilled_today", "Water Refilled Today", "wc_refilled_today", "refilled_today", "gal", Some("water"), "total_increasing", Some("mdi:water-plus")),
+            ("budget_used", "Water Budget Used", "wc_budget_used", "budget_pct", "%", None, "measurement", Some("mdi:water-percent")),
         ];
 
-        for &(disc_name, name, uid, val_key, unit, extra) in SENSORS {
-            self.publish_discovery(
-                "sensor",
-                disc_name,
-                &format!(
-                    r#"{{"name":"{name}","uniq_id":"{uid}","stat_t":"watercontroller/state","val_tpl":"{{{{ value_json.{val_key} }}}}","unit_of_meas":"{unit}",{extra},{device_info}}}"#,
-                ),
-            )?;
+        for &(disc_name, name, uid, val_key, unit, device_class, state_class, icon) in SENSORS {
+            let config = Discovery {
+                name: name.into(),
+                unique_id: uid.into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(value_template(val_key)),
+                unit: Some(unit),
+                device_class,
+                state_class: Some(state_class),
+                icon,
+                json_attributes_topic: Some(BUILD_TOPIC),
+                ..Default::default()
+            };
+            self.publish_discovery("sensor", disc_name, &config)?;
         }
 
-        // Number entities (configurable parameters)
-        const NUMBERS: &[(&str, &str, &str, &str, &str, u16, u16, u16, &str, &str)] = &[
-            // (disc_name, ha_name, unique_id, value_key, cmd_topic_suffix, min, max, step, unit, icon)
-            ("tank_capacity", "Tank Capacity", "wc_tank_cap", "tank_capacity", "tank_capacity", 100, 2000, 10, "gal", "mdi:storage-tank"),
-            ("sensor_height", "Pressure sensor Height", "wc_height", "sensor_height", "sensor_height", 0, 50, 1, "ft", "mdi:arrow-expand-vertical"),
-            ("max_psi", "Manometer Range", "wc_max_psi", "max_psi", "max_psi", 50, 300, 10, "psi", "mdi:gauge"),
-            ("radar_height", "Radar Height", "wc_radar_ht", "radar_height", "radar_height", 10, 500, 1, "cm", "mdi:signal-distance-variant"),
-            ("radar_deadzone", "Radar Deadzone", "wc_radar_dz", "radar_deadzone", "radar_deadzone", 0, 200, 1, "cm", "mdi:arrow-collapse-down"),
+        // Pressure lost across the whole-house filter
+        #[cfg(feature = "filter_pressure")]
+        self.publish_discovery(
+            "sensor",
+            "filter_drop",
+            &Discovery {
+                name: "Filter Differential Pressure".into(),
+                unique_id: "wc_filter_drop".into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(value_template("filter_drop_psi")),
+                unit: Some("psi"),
+                device_class: Some("pressure"),
+                state_class: Some("measurement"),
+                icon: Some("mdi:filter"),
+                ..Default::default()
+            },
+        )?;
+
+        // Flow meter rate and running total
+        #[cfg(feature = "flow")]
+        const FLOW_SENSORS: &[Sensor] = &[
+            ("flow_rate", "Water Flow", "wc_flow_rate", "flow_gpm", "gal/min", Some("volume_flow_rate"), "measurement", None),
+            ("flow_total", "Water Meter", "wc_flow_total", "flow_total_gal", "gal", Some("water"), "total_increasing", None),
         ];
+        #[cfg(not(feature = "flow"))]
+        const FLOW_SENSORS: &[Sensor] = &[];
+        for &(disc_name, name, uid, val_key, unit, device_class, state_class, icon) in FLOW_SENSORS {
+            let config = Discovery {
+                name: name.into(),
+                unique_id: uid.into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(value_template(val_key)),
+                unit: Some(unit),
+                device_class,
+                state_class: Some(state_class),
+                icon,
+                ..Default::default()
+            };
+            self.publish_discovery("sensor", disc_name, &config)?;
+        }
 
-        for &(disc_name, name, uid, val_key, cmd_suffix, min, max, step, unit, icon) in NUMBERS {
-            self.publish_discovery(
-                "number",
-                disc_name,
-                &format!(
-                    r#"{{"name":"{name}","uniq_id":"{uid}","stat_t":"watercontroller/state","val_tpl":"{{{{ value_json.{val_key} }}}}","cmd_t":"watercontroller/set/{cmd_suffix}","min":{min},"max":{max},"step":{step},"mode":"box","unit_of_meas":"{unit}","ic":"{icon}",{device_info}}}"#,
-                ),
-            )?;
+        // The sensor's filtered and real-time distances next to the firmware's smoothing
+        #[cfg(feature = "radar")]
+        const RADAR_DISTANCES: &[(&str, &str)] = &[
+            ("radar_empty_mm", "Radar Distance"),
+            ("radar_empty_raw_mm", "Radar Distance Real-time"),
+            ("radar_level_mm", "Radar Water Level"),
+            ("radar_level_raw_mm", "Radar Water Level Real-time"),
+            ("radar_smoothed_mm", "Radar Distance Smoothed"),
+        ];
+        #[cfg(not(feature = "radar"))]
+        const RADAR_DISTANCES: &[(&str, &str)] = &[];
+        for &(key, name) in RADAR_DISTANCES {
+            let config = Discovery {
+                name: name.into(),
+                unique_id: format!("wc_{key}"),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(value_template(key)),
+                unit: Some("mm"),
+                device_class: Some("distance"),
+                state_class: Some("measurement"),
+                entity_category: Some("diagnostic"),
+                ..Default::default()
+            };
+            self.publish_discovery("sensor", key, &config)?;
         }
 
-        self.discovery_sent = true;
-        info!("Discovery messages sent");
+        // Number entities (configurable parameters)
+        for &(disc_name, name, uid, val_key, cmd_suffix, step, unit, icon) in number_entities() {
+            let Some((min, max)) = provision::number_range(cmd_suffix) else {
+                warn!("HA: no range for number entity {}", cmd_suffix);
+                continue;
+            };
+            let config = Discovery {
+                name: name.into(),
+                unique_id: uid.into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(value_template(val_key)),
+                command_topic: Some(format!("watercontroller/set/{cmd_suffix}")),
+                min: Some(min),
+                max: Some(max),
+                step: Some(step),
+                mode: Some("box"),
+                unit: (!unit.is_empty()).then_some(unit),
+                icon: Some(icon),
+                ..Default::default()
+            };
+            self.publish_discovery("number", disc_name, &config)?;
+        }
+
+        self.send_alarm_discovery()?;
+        self.publish_discovery(
+            "binary_sensor",
+            "budget_exceeded",
+            &Discovery {
+                name: "Water Budget Exceeded".into(),
+                unique_id: "wc_budget_exceeded".into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(on_off_template("budget_exceeded")),
+                device_class: Some("problem"),
+                ..Default::default()
+            },
+        )?;
+
+        #[cfg(feature = "pump")]
+        self.send_pump_discovery()?;
+
+        #[cfg(feature = "history")]
+        self.send_history_stats_discovery()?;
+
+        #[cfg(feature = "efficiency")]
+        self.send_efficiency_discovery()?;
+
+        #[cfg(feature = "hammer")]
+        self.publish_discovery(
+            "event",
+            "water_hammer",
+            &Discovery {
+                name: "Water Hammer".into(),
+                unique_id: "wc_water_hammer".into(),
+                state_topic: Some(HAMMER_EVENT_TOPIC),
+                event_types: vec!["pump_start", "pump_stop"],
+                icon: Some("mdi:pipe-leak"),
+                ..Default::default()
+            },
+        )?;
+
+        #[cfg(feature = "valve")]
+        self.send_valve_discovery()?;
+
+        #[cfg(feature = "well_pump")]
+        self.send_well_pump_discovery()?;
+
+        #[cfg(feature = "lockout")]
+        self.publish_discovery(
+            "binary_sensor",
+            "lockout",
+            &Discovery {
+                name: "Lockout".into(),
+                unique_id: "wc_lockout".into(),
+                state_topic: Some(LOCKOUT_STATE_TOPIC),
+                device_class: Some("safety"),
+                icon: Some("mdi:lock-alert"),
+                ..Default::default()
+            },
+        )?;
+
+        #[cfg(feature = "notify")]
+        self.publish_discovery(
+            "event",
+            "notification",
+            &Discovery {
+                name: "Alarm".into(),
+                unique_id: "wc_notification".into(),
+                state_topic: Some(NOTIFY_EVENT_TOPIC),
+                event_types: Event::ALL.iter().map(|e| e.key()).collect(),
+                icon: Some("mdi:bell-alert"),
+                ..Default::default()
+            },
+        )?;
+
+        #[cfg(feature = "vfd")]
+        self.publish_discovery(
+            "sensor",
+            "vfd_speed",
+            &Discovery {
+                name: "Pump Speed".into(),
+                unique_id: "wc_vfd_speed".into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(value_template("vfd_speed")),
+                unit: Some("%"),
+                icon: Some("mdi:speedometer"),
+                state_class: Some("measurement"),
+                ..Default::default()
+            },
+        )?;
+        #[cfg(feature = "vfd")]
+        self.publish_discovery(
+            "button",
+            "vfd_autotune",
+            &Discovery {
+                name: "Autotune Pump Speed".into(),
+                unique_id: "wc_vfd_autotune".into(),
+                command_topic: Some(CMD_TOPIC_VFD_AUTOTUNE.into()),
+                icon: Some("mdi:auto-fix"),
+                ..Default::default()
+            },
+        )?;
+
+        #[cfg(feature = "heater")]
+        self.publish_discovery(
+            "binary_sensor",
+            "heater",
+            &Discovery {
+                name: "Radar Heater".into(),
+                unique_id: "wc_heater_on".into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(on_off_template("heater_on")),
+                device_class: Some("heat"),
+                ..Default::default()
+            },
+        )?;
+
+        // Pressed with the line depressurized
+        #[cfg(feature = "pressure")]
+        self.publish_discovery(
+            "button",
+            "psi_zero",
+            &Discovery {
+                name: "Calibrate Pressure Zero".into(),
+                unique_id: "wc_psi_zero".into(),
+                command_topic: Some(CMD_TOPIC_PSI_ZERO.into()),
+                icon: Some("mdi:gauge-empty"),
+                entity_category: Some("config"),
+                ..Default::default()
+            },
+        )?;
+
+        // Pressed after changing the filter
+        #[cfg(feature = "filter_pressure")]
+        self.publish_discovery(
+            "button",
+            "filter_reset",
+            &Discovery {
+                name: "Reset Filter Alarm".into(),
+                unique_id: "wc_filter_reset".into(),
+                command_topic: Some(CMD_TOPIC_FILTER_RESET.into()),
+                icon: Some("mdi:filter-check"),
+                ..Default::default()
+            },
+        )?;
+
+        self.send_diagnostics_discovery()?;
+
+        // Diagnostics page on the device display
+        self.publish_discovery(
+            "button",
+            "show_diag",
+            &Discovery {
+                name: "Show Diagnostics".into(),
+                unique_id: "wc_show_diag".into(),
+                command_topic: Some(CMD_TOPIC_SHOW_DIAG.into()),
+                icon: Some("mdi:stethoscope"),
+                entity_category: Some("diagnostic"),
+                ..Default::default()
+            },
+        )?;
+
+        // Remote maintenance
+        self.publish_discovery(
+            "button",
+            "reboot",
+            &Discovery {
+                name: "Reboot".into(),
+                unique_id: "wc_reboot".into(),
+                command_topic: Some(CMD_TOPIC_REBOOT.into()),
+                device_class: Some("restart"),
+                entity_category: Some("config"),
+                ..Default::default()
+            },
+        )?;
+        self.publish_discovery(
+            "button",
+            "factory_reset",
+            &Discovery {
+                name: "Factory Reset".into(),
+                unique_id: "wc_factory_reset".into(),
+                command_topic: Some(CMD_TOPIC_FACTORY_RESET.into()),
+                icon: Some("mdi:restore-alert"),
+                entity_category: Some("config"),
+                ..Default::default()
+            },
+        )?;
+        #[cfg(feature = "ota")]
+        self.publish_discovery(
+            "update",
+            "firmware",
+            &Discovery {
+                name: "Firmware".into(),
+                unique_id: "wc_firmware_update".into(),
+                state_topic: Some(UPDATE_STATE_TOPIC),
+                command_topic: Some(CMD_TOPIC_UPDATE.into()),
+                latest_version_topic: Some(UPDATE_LATEST_TOPIC),
+                latest_version_template: Some(value_template("latest_version")),
+                payload_install: Some("install"),
+                device_class: Some("firmware"),
+                entity_category: Some("config"),
+                ..Default::default()
+            },
+        )?;
+
+        // Trial configuration state and confirmation
+        self.publish_discovery(
+            "binary_sensor",
+            "config_trial",
+            &Discovery {
+                name: "Config Trial".into(),
+                unique_id: "wc_config_trial".into(),
+                state_topic: Some(TRIAL_STATE_TOPIC),
+                icon: Some("mdi:flask-outline"),
+                entity_category: Some("diagnostic"),
+                ..Default::default()
+            },
+        )?;
+        self.publish_discovery(
+            "button",
+            "trial_confirm",
+            &Discovery {
+                name: "Confirm Config Trial".into(),
+                unique_id: "wc_trial_confirm".into(),
+                command_topic: Some(CMD_TOPIC_TRIAL_CONFIRM.into()),
+                icon: Some("mdi:check-decagram"),
+                ..Default::default()
+            },
+        )?;
+
+        // Maintenance mode switch
+        self.publish_discovery(
+            "switch",
+            "maintenance",
+            &Discovery {
+                name: "Maintenance Mode".into(),
+                unique_id: "wc_maintenance".into(),
+                state_topic: Some(MAINTENANCE_STATE_TOPIC),
+                command_topic: Some(CMD_TOPIC_MAINTENANCE.into()),
+                icon: Some("mdi:wrench"),
+                ..Default::default()
+            },
+        )?;
+
+        self.discovery_queued = true;
+        info!("{} discovery messages queued", self.outbox.len());
         Ok(())
     }
 
-    /// Publish a discovery message for an entity
+    /// Queue a discovery message for an entity in the outbox
     fn publish_discovery(
         &mut self,
         entity_type: &str,
         entity_name: &str,
-        config_payload: &str,
+        config: &Discovery,
     ) -> Result<(), esp_idf_svc::sys::EspError> {
-        let topic = format!(
-            "homeassistant/{}/{}_{}/config",
-            entity_type, DEVICE_ID, entity_name
-        );
-        debug!("Publishing discovery to {}: {}", topic, config_payload);
-
-        self.client
-            .publish(&topic, QoS::AtLeastOnce, true, config_payload.as_bytes())?;
+        let topic = discovery_topic(entity_type, entity_name);
+        let mut config = config.clone();
+        if self.read_only && config.command_topic.is_some() {
+            if entity_type != "update" {
+                // Clears a config retained while the unit took commands
+                debug!("Read-only: removing {}", topic);
+                self.outbox.push(topic, &[]);
+                return Ok(());
+            }
+            config.command_topic = None;
+        }
+        if is_tank_entity(entity_name) {
+            config.name = self.naming.tank_entity(&config.name);
+        }
+        self.naming.apply(&mut config);
+        let config_payload = config.to_json();
+        debug!("Queueing discovery to {}: {}", topic, config_payload);
+
+        self.outbox.push(topic, config_payload.as_bytes());
         Ok(())
     }
 
     /// Publish current sensor state
+    ///
+    /// While disconnected the payload is queued for the next connection (see
+    /// `queue_state`).
     pub fn publish_state(&mut self, state: &WaterState) -> Result<(), esp_idf_svc::sys::EspError> {
-        // Ensure discovery is sent first
+        if !self.is_connected() || self.resync_pending {
+            self.queue_state(state);
+            return Ok(());
+        }
+        // Queue discovery if it hasn't gone out, or was given up
         if !self.discovery_sent {
             self.send_discovery()?;
         }
 
-        let payload = format!(
-            r#"{{"capacity_pct":{},"gallons":{},"pressure_psi":{},"tank_capacity":{},"sensor_height":{},"max_psi":{},"radar_height":{},"radar_deadzone":{}}}"#,
-            state.capacity_percent,
-            state.capacity_gallons,
-            state.pressure_psi,
-            state.tank_capacity,
-            state.sensor_height,
-            state.max_psi,
-            state.radar_height,
-            state.radar_deadzone
-        );
-
+        let payload = state.to_json();
         debug!("Publishing state: {}", payload);
 
-        self.client
-            .publish("watercontroller/state", QoS::AtMostOnce, false, payload.as_bytes())?;
+        if let Err(e) = self.publish(STATE_TOPIC, QoS::AtMostOnce, false, payload.as_bytes()) {
+            if state.timestamp.is_some() {
+                self.offline.push(payload);
+            }
+            return Err(e);
+        }
+
+        self.sync_config(state)?;
+        self.sync_trial(state)
+    }
+
+    /// Queue a state sample for the next connection
+    ///
+    /// For when the network is known to be down, so publishing would only
+    /// wait for the socket to time out. Only samples stamped with the time
+    /// they were taken are kept: before SNTP has set the clock, a flushed
+    /// sample would pass for a current reading.
+    pub fn queue_state(&mut self, state: &WaterState) {
+        if state.timestamp.is_some() {
+            self.offline.push(state.to_json());
+        }
+    }
+
+    /// Publish the reported configuration and apply pending desired settings
+    ///
+    /// Only desired settings that differ from the reported ones are sent to
+    /// the main loop; the next state publish reports the result.
+    fn sync_config(&mut self, state: &WaterState) -> Result<(), esp_idf_svc::sys::EspError> {
+        let reported: Settings = number_entities().filter_map(|e| state.setting(e.4).map(|v| (e.4, v))).collect();
+        if self.reported.as_ref() != Some(&reported) {
+            self.publish(REPORTED_CONFIG_TOPIC, QoS::AtLeastOnce, true, twin::to_json(&reported).as_bytes())?;
+            self.reported = Some(reported.clone());
+        }
+
+        let Some(desired) = self.desired.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let changes = twin::diff(&desired, &reported);
+        if changes.is_empty() {
+            info!("Config: reported configuration matches desired");
+            return Ok(());
+        }
+        for (key, value) in &changes {
+            info!("Config: desired {} = {}", key, value);
+        }
+        let _ = self.cmd_tx.send(ConfigCommand::from_settings(&changes));
+        Ok(())
+    }
+
+    /// Start, confirm or revert a trial configuration and publish its state
+    fn sync_trial(&mut self, state: &WaterState) -> Result<(), esp_idf_svc::sys::EspError> {
+        let now = self.created.elapsed();
+        let faults = state.fault_count();
+
+        let request = self.trial_request.lock().unwrap().take();
+        match request {
+            Some(TrialRequest::Start(settings, period)) => {
+                if let Some(active) = &self.trial {
+                    warn!("Config: trial already running ({} s left), ignoring new trial", active.remaining(now).as_secs());
+                } else if let Some(reported) = &self.reported {
+                    info!("Config: starting {} min trial of {}", period.as_secs() / 60, twin::to_json(&settings));
+                    self.trial = Some(Trial::new(&settings, reported, now, period, faults));
+                    let _ = self.cmd_tx.send(ConfigCommand::from_settings(&settings));
+                }
+            }
+            Some(TrialRequest::Confirm) => match self.trial.take() {
+                Some(_) => info!("Config: trial confirmed"),
+                None => info!("Config: no trial to confirm"),
+            },
+            None => {}
+        }
+
+        if let Some(reason) = self.trial.as_ref().and_then(|t| t.check(now, faults)) {
+            let previous = self.trial.take().map(|t| t.previous().to_vec()).unwrap_or_default();
+            warn!("Config: trial reverted ({:?}), restoring {}", reason, twin::to_json(&previous));
+            let _ = self.cmd_tx.send(ConfigCommand::from_settings(&previous));
+        }
+
+        let active = self.trial.is_some();
+        if self.trial_reported != Some(active) {
+            let payload = if active { "ON" } else { "OFF" };
+            self.publish(TRIAL_STATE_TOPIC, QoS::AtLeastOnce, true, payload.as_bytes())?;
+            self.trial_reported = Some(active);
+        }
+        Ok(())
+    }
+
+    /// Publish discovery for the alarm problem sensors
+    fn send_alarm_discovery(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+        for alarm in Alarm::ALL {
+            if alarm == Alarm::PipeBurst && !cfg!(feature = "pipe_burst") {
+                continue;
+            }
+            if alarm == Alarm::FilterClogged && !cfg!(feature = "filter_pressure") {
+                continue;
+            }
+            self.publish_discovery(
+                "binary_sensor",
+                alarm.key(),
+                &Discovery {
+                    name: alarm.label().into(),
+                    unique_id: format!("wc_{}", alarm.key()),
+                    state_topic: Some(STATE_TOPIC),
+                    value_template: Some(on_off_template(alarm.key())),
+                    device_class: Some("problem"),
+                    ..Default::default()
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Publish discovery for the device diagnostics
+    fn send_diagnostics_discovery(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+        type DiagSensor = (&'static str, &'static str, &'static str, Option<&'static str>, Option<&'static str>, Option<&'static str>);
+        const DIAG_SENSORS: &[DiagSensor] = &[
+            // (key, ha_name, unique_id, unit, device_class, icon)
+            ("ip", "IP Address", "wc_ip", None, None, Some("mdi:ip-network")),
+            ("uptime_secs", "Uptime", "wc_uptime", Some("s"), Some("duration"), None),
+            ("free_heap", "Free Heap", "wc_free_heap", Some("B"), Some("data_size"), Some("mdi:memory")),
+            ("firmware", "Firmware Version", "wc_firmware", None, None, Some("mdi:chip")),
+            ("reset_reason", "Reset Reason", "wc_reset_reason", None, None, Some("mdi:restart")),
+        ];
+        #[cfg(feature = "radar")]
+        const RADAR_DIAG_SENSORS: &[DiagSensor] = &[
+            ("radar_retries", "Radar Retries", "wc_radar_retries", None, None, Some("mdi:radar")),
+            ("radar_failures", "Radar Failures", "wc_radar_failures", None, None, Some("mdi:radar")),
+        ];
+        #[cfg(not(feature = "radar"))]
+        const RADAR_DIAG_SENSORS: &[DiagSensor] = &[];
+        for &(key, name, uid, unit, device_class, icon) in DIAG_SENSORS.iter().chain(RADAR_DIAG_SENSORS) {
+            let config = Discovery {
+                name: name.into(),
+                unique_id: uid.into(),
+                state_topic: Some(DIAG_STATE_TOPIC),
+                value_template: Some(value_template(key)),
+                unit,
+                device_class,
+                icon,
+                entity_category: Some("diagnostic"),
+                expire_after: Some(DIAG_EXPIRE_SECS),
+                json_attributes_topic: (key == "firmware").then_some(BUILD_TOPIC),
+                ..Default::default()
+            };
+            self.publish_discovery("sensor", key, &config)?;
+        }
+        self.publish_discovery(
+            "binary_sensor",
+            "link_up",
+            &Discovery {
+                name: "Ethernet Link".into(),
+                unique_id: "wc_link_up".into(),
+                state_topic: Some(DIAG_STATE_TOPIC),
+                value_template: Some(on_off_template("link_up")),
+                device_class: Some("connectivity"),
+                entity_category: Some("diagnostic"),
+                expire_after: Some(DIAG_EXPIRE_SECS),
+                ..Default::default()
+            },
+        )?;
+        // Phase durations as attributes, so a slow boot can be traced to its phase
+        self.publish_discovery(
+            "sensor",
+            "startup",
+            &Discovery {
+                name: "Startup Time".into(),
+                unique_id: "wc_startup".into(),
+                state_topic: Some(STARTUP_TOPIC),
+                value_template: Some("{{ value_json.total_ms / 1000 }}".into()),
+                json_attributes_topic: Some(STARTUP_TOPIC),
+                json_attributes_template: Some("{{ value_json.phases | tojson }}".into()),
+                unit: Some("s"),
+                device_class: Some("duration"),
+                entity_category: Some("diagnostic"),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Publish discovery for the well pump state and statistics inferred from pressure
+    #[cfg(feature = "well_pump")]
+    fn send_well_pump_discovery(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.publish_discovery(
+            "binary_sensor",
+            "well_pump_running",
+            &Discovery {
+                name: "Well Pump Running".into(),
+                unique_id: "wc_well_pump_on".into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(on_off_template("well_pump_on")),
+                device_class: Some("running"),
+                ..Default::default()
+            },
+        )?;
+        self.publish_discovery(
+            "sensor",
+            "well_pump_runtime",
+            &Discovery {
+                name: "Well Pump Runtime".into(),
+                unique_id: "wc_well_pump_runtime".into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(value_template("well_pump_runtime_min")),
+                unit: Some("min"),
+                device_class: Some("duration"),
+                state_class: Some("total_increasing"),
+                ..Default::default()
+            },
+        )?;
+        self.publish_discovery(
+            "sensor",
+            "well_pump_starts",
+            &Discovery {
+                name: "Well Pump Starts".into(),
+                unique_id: "wc_well_pump_starts".into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(value_template("well_pump_starts")),
+                state_class: Some("total_increasing"),
+                icon: Some("mdi:counter"),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Publish discovery for per-pump sensors, the dry-run fault and the fault reset button
+    #[cfg(feature = "pump")]
+    fn send_pump_discovery(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+        for n in 1..=2 {
+            self.publish_discovery(
+                "sensor",
+                &format!("pump{n}_runtime"),
+                &Discovery {
+                    name: format!("Pump {n} Runtime"),
+                    unique_id: format!("wc_pump{n}_runtime"),
+                    state_topic: Some(STATE_TOPIC),
+                    value_template: Some(value_template(&format!("pump{n}_runtime_min"))),
+                    unit: Some("min"),
+                    device_class: Some("duration"),
+                    state_class: Some("total_increasing"),
+                    ..Default::default()
+                },
+            )?;
+            self.publish_discovery(
+                "binary_sensor",
+                &format!("pump{n}_running"),
+                &Discovery {
+                    name: format!("Pump {n} Running"),
+                    unique_id: format!("wc_pump{n}_on"),
+                    state_topic: Some(STATE_TOPIC),
+                    value_template: Some(on_off_template(&format!("pump{n}_on"))),
+                    device_class: Some("running"),
+                    ..Default::default()
+                },
+            )?;
+            self.publish_discovery(
+                "binary_sensor",
+                &format!("pump{n}_failed"),
+                &Discovery {
+                    name: format!("Pump {n} Failure"),
+                    unique_id: format!("wc_pump{n}_failed"),
+                    state_topic: Some(STATE_TOPIC),
+                    value_template: Some(on_off_template(&format!("pump{n}_failed"))),
+                    device_class: Some("problem"),
+                    ..Default::default()
+                },
+            )?;
+        }
+        self.publish_discovery(
+            "binary_sensor",
+            "pump_dry_run",
+            &Discovery {
+                name: "Pump Dry Run".into(),
+                unique_id: "wc_pump_dry_run".into(),
+                state_topic: Some(STATE_TOPIC),
+                value_template: Some(on_off_template("pump_dry_run")),
+                device_class: Some("problem"),
+                ..Default::default()
+            },
+        )?;
+        self.publish_discovery(
+            "button",
+            "pump_reset",
+            &Discovery {
+                name: "Reset Pump Faults".into(),
+                unique_id: "wc_pump_reset".into(),
+                command_topic: Some(CMD_TOPIC_PUMP_RESET.into()),
+                icon: Some("mdi:restart-alert"),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Publish discovery for the per-pump delivery rates and efficiency alerts
+    #[cfg(feature = "efficiency")]
+    fn send_efficiency_discovery(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+        const RATES: &[(&str, &str)] = &[("last", "Last Cycle"), ("week", "Weekly"), ("baseline", "Baseline")];
+        for n in 1..=2 {
+            for &(key, label) in RATES {
+                self.publish_discovery(
+                    "sensor",
+                    &format!("pump{n}_gpm_{key}"),
+                    &Discovery {
+                        name: format!("Pump {n} {label} Flow Rate"),
+                        unique_id: format!("wc_pump{n}_gpm_{key}"),
+                        state_topic: Some(EFFICIENCY_STATE_TOPIC),
+                        value_template: Some(value_template(&format!("pump{n}_gpm_{key}"))),
+                        unit: Some("gal/min"),
+                        device_class: Some("volume_flow_rate"),
+                        state_class: Some("measurement"),
+                        ..Default::default()
+                    },
+                )?;
+            }
+            #[cfg(feature = "pump_power")]
+            for &(key, label) in &RATES[..2] {
+                self.publish_discovery(
+                    "sensor",
+                    &format!("pump{n}_kwh_gal_{key}"),
+                    &Discovery {
+                        name: format!("Pump {n} {label} Energy per Gallon"),
+                        unique_id: format!("wc_pump{n}_kwh_gal_{key}"),
+                        state_topic: Some(EFFICIENCY_STATE_TOPIC),
+                        value_template: Some(value_template(&format!("pump{n}_kwh_gal_{key}"))),
+                        unit: Some("kWh/gal"),
+                        state_class: Some("measurement"),
+                        ..Default::default()
+                    },
+                )?;
+            }
+            self.publish_discovery(
+                "binary_sensor",
+                &format!("pump{n}_degraded"),
+                &Discovery {
+                    name: format!("Pump {n} Efficiency Drop"),
+                    unique_id: format!("wc_pump{n}_degraded"),
+                    state_topic: Some(EFFICIENCY_STATE_TOPIC),
+                    value_template: Some(on_off_template(&format!("pump{n}_degraded"))),
+                    device_class: Some("problem"),
+                    ..Default::default()
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Publish discovery for the supply valve and its fault sensor
+    #[cfg(feature = "valve")]
+    fn send_valve_discovery(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+        self.publish_discovery(
+            "valve",
+            "supply_valve",
+            &Discovery {
+                name: "Supply Valve".into(),
+                unique_id: "wc_valve".into(),
+                command_topic: Some(CMD_TOPIC_VALVE.into()),
+                state_topic: Some(VALVE_STATE_TOPIC),
+                value_template: Some(value_template("state")),
+                payload_open: Some("OPEN"),
+                payload_close: Some("CLOSE"),
+                payload_stop: Some("STOP"),
+                device_class: Some("water"),
+                ..Default::default()
+            },
+        )?;
+        self.publish_discovery(
+            "binary_sensor",
+            "valve_fault",
+            &Discovery {
+                name: "Supply Valve Fault".into(),
+                unique_id: "wc_valve_fault".into(),
+                state_topic: Some(VALVE_STATE_TOPIC),
+                value_template: Some(on_off_template("fault")),
+                device_class: Some("problem"),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Publish discovery for the windowed level and pressure statistics
+    #[cfg(feature = "history")]
+    fn send_history_stats_discovery(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+        type Quantity = (&'static str, &'static str, &'static str, Option<&'static str>, Option<&'static str>);
+        const QUANTITIES: &[Quantity] = &[
+            // (key, ha_name, unit, device_class, icon)
+            ("level", "Water Capacity", "%", None, Some("mdi:water-percent")),
+            ("pressure", "Water Pressure", "psi", Some("pressure"), None),
+        ];
+        for &(key, name, unit, device_class, icon) in QUANTITIES {
+            for window in ["1h", "24h"] {
+                for (stat, stat_name) in [("min", "Min"), ("max", "Max"), ("avg", "Avg")] {
+                    let field = format!("{key}_{window}_{stat}");
+                    let config = Discovery {
+                        name: format!("{name} {window} {stat_name}"),
+                        unique_id: format!("wc_{field}"),
+                        state_topic: Some(HISTORY_STATS_TOPIC),
+                        value_template: Some(value_template(&field)),
+                        unit: Some(unit),
+                        state_class: Some("measurement"),
+                        device_class,
+                        icon,
+                        ..Default::default()
+                    };
+                    self.publish_discovery("sensor", &field, &config)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Publish the windowed statistics (retained, so they survive missed messages)
+    #[cfg(feature = "history")]
+    pub fn publish_history_stats(&mut self, stats: &HistoryStats) -> Result<(), esp_idf_svc::sys::EspError> {
+        let windows = [
+            ("level_1h", stats.level_1h),
+            ("level_24h", stats.level_24h),
+            ("pressure_1h", stats.pressure_1h),
+            ("pressure_24h", stats.pressure_24h),
+        ];
+        let fields: Vec<String> = windows
+            .iter()
+            .map(|(prefix, window)| match window {
+                Some(WindowStats { min, max, avg }) => {
+                    format!(r#""{prefix}_min":{min},"{prefix}_max":{max},"{prefix}_avg":{avg}"#)
+                }
+                None => format!(r#""{prefix}_min":null,"{prefix}_max":null,"{prefix}_avg":null"#),
+            })
+            .collect();
+        let payload = format!("{{{}}}", fields.join(","));
+        debug!("Publishing history stats: {}", payload);
+        self.publish(HISTORY_STATS_TOPIC, QoS::AtLeastOnce, true, payload.as_bytes())?;
+        Ok(())
+    }
+
+    /// Publish probe temperatures, announcing new or renamed probes first
+    ///
+    /// `names` is the stored `ROM=name` list; unnamed probes are announced
+    /// under their ROM code.
+    #[cfg(feature = "ds18b20")]
+    pub fn publish_probes(&mut self, probes: &[Probe], names: &str) -> Result<(), esp_idf_svc::sys::EspError> {
+        if probes.is_empty() {
+            return Ok(());
+        }
+        let names = ds18b20::parse_names(names);
+        for probe in probes {
+            let name = ds18b20::probe_name(&names, probe.rom);
+            if self.probes_discovered.iter().any(|(rom, n)| *rom == probe.rom && *n == name) {
+                continue;
+            }
+            let key = Self::probe_key(probe.rom);
+            self.publish_discovery(
+                "sensor",
+                &key,
+                &Discovery {
+                    name: name.clone(),
+                    unique_id: format!("wc_{key}"),
+                    state_topic: Some(PROBES_STATE_TOPIC),
+                    value_template: Some(value_template(&key)),
+                    unit: Some("°C"),
+                    device_class: Some("temperature"),
+                    state_class: Some("measurement"),
+                    expire_after: Some(PROBE_EXPIRE_SECS),
+                    ..Default::default()
+                },
+            )?;
+            self.probes_discovered.retain(|(rom, _)| *rom != probe.rom);
+            self.probes_discovered.push((probe.rom, name));
+        }
+
+        let fields: Vec<String> = probes
+            .iter()
+            .map(|probe| {
+                let key = Self::probe_key(probe.rom);
+                match probe.celsius {
+                    Some(celsius) => format!(r#""{key}":{:.2}"#, celsius),
+                    None => format!(r#""{key}":null"#),
+                }
+            })
+            .collect();
+        let payload = format!("{{{}}}", fields.join(","));
+        debug!("Publishing probes: {}", payload);
+        self.publish(PROBES_STATE_TOPIC, QoS::AtMostOnce, false, payload.as_bytes())?;
+        Ok(())
+    }
+
+    /// Republish a frame heard from a LoRa node
+    ///
+    /// Entities for a node are announced the first time it is heard on a
+    /// connection. Frames heard while disconnected are dropped; the next one
+    /// is at most one node interval away.
+    #[cfg(feature = "lora")]
+    pub fn publish_sibling(&mut self, frame: &StateFrame, rssi: i16, snr: f32) -> Result<(), esp_idf_svc::sys::EspError> {
+        if !self.is_connected() || self.resync_pending {
+            return Ok(());
+        }
+        let topic = format!("{}/{}", SIBLING_STATE_TOPIC, frame.node);
+        if !self.siblings_discovered.contains(&frame.node) {
+            // (key, name, state field, unit, device class, entity category)
+            let sensors = [
+                ("level", "Level", "capacity_pct", Some("%"), None, None),
+                ("volume", "Volume", "gallons", Some("gal"), Some("volume_storage"), None),
+                ("pressure", "Pressure", "pressure_psi", Some("psi"), Some("pressure"), None),
+                ("rssi", "Signal", "rssi", Some("dBm"), Some("signal_strength"), Some("diagnostic")),
+            ];
+            for (key, label, field, unit, device_class, entity_category) in sensors {
+                self.publish_discovery(
+                    "sensor",
+                    &format!("lora{}_{}", frame.node, key),
+                    &Discovery {
+                        name: format!("LoRa {} {}", frame.node, label),
+                        unique_id: format!("wc_lora{}_{}", frame.node, key),
+                        state_topic: Some(&topic),
+                        value_template: Some(value_template(field)),
+                        unit,
+                        device_class,
+                        state_class: Some("measurement"),
+                        entity_category,
+                        expire_after: Some(SIBLING_EXPIRE_SECS),
+                        ..Default::default()
+                    },
+                )?;
+            }
+            self.publish_discovery(
+                "binary_sensor",
+                &format!("lora{}_problem", frame.node),
+                &Discovery {
+                    name: format!("LoRa {} Problem", frame.node),
+                    unique_id: format!("wc_lora{}_problem", frame.node),
+                    state_topic: Some(&topic),
+                    value_template: Some(on_off_template("problem")),
+                    device_class: Some("problem"),
+                    expire_after: Some(SIBLING_EXPIRE_SECS),
+                    ..Default::default()
+                },
+            )?;
+            self.siblings_discovered.push(frame.node);
+        }
+
+        let payload = SiblingState::new(frame, rssi, snr).to_json();
+        debug!("Publishing LoRa node {}: {}", frame.node, payload);
+        self.publish(&topic, QoS::AtMostOnce, false, payload.as_bytes())?;
+        Ok(())
+    }
+
+    /// State field of a probe: `t_` and its ROM code
+    #[cfg(feature = "ds18b20")]
+    fn probe_key(rom: u64) -> String {
+        format!("t_{}", ds18b20::rom_to_hex(rom).to_lowercase())
+    }
+
+    /// Publish the per-pump delivery rate and energy trend (retained, it only changes per cycle)
+    #[cfg(feature = "efficiency")]
+    pub fn publish_efficiency(&mut self, trends: &[PumpTrend]) -> Result<(), esp_idf_svc::sys::EspError> {
+        let rate = |gpm: Option<f32>| gpm.map_or("null".to_string(), |gpm| format!("{:.1}", gpm));
+        let energy = |kwh: Option<f32>| kwh.map_or("null".to_string(), |kwh| format!("{:.4}", kwh));
+        let fields: Vec<String> = trends
+            .iter()
+            .enumerate()
+            .map(|(i, trend)| {
+                let n = i + 1;
+                format!(
+                    r#""pump{n}_gpm_last":{},"pump{n}_gpm_week":{},"pump{n}_gpm_baseline":{},"pump{n}_kwh_gal_last":{},"pump{n}_kwh_gal_week":{},"pump{n}_degraded":{}"#,
+                    rate(trend.last_gpm),
+                    rate(trend.week_gpm),
+                    rate(trend.baseline_gpm),
+                    energy(trend.last_kwh_per_gal),
+                    energy(trend.week_kwh_per_gal),
+                    trend.degraded
+                )
+            })
+            .collect();
+        let payload = format!("{{{}}}", fields.join(","));
+        debug!("Publishing efficiency: {}", payload);
+        self.publish(EFFICIENCY_STATE_TOPIC, QoS::AtLeastOnce, true, payload.as_bytes())?;
+        Ok(())
+    }
+
+    /// Publish a water hammer event with its waveform
+    #[cfg(feature = "hammer")]
+    pub fn publish_hammer(&mut self, event: &HammerEvent) -> Result<(), esp_idf_svc::sys::EspError> {
+        let payload = HammerReport::new(event).to_json();
+        self.publish(HAMMER_EVENT_TOPIC, QoS::AtLeastOnce, false, payload.as_bytes())?;
+        Ok(())
+    }
+
+    /// Publish an alarm notification as an HA event
+    #[cfg(feature = "notify")]
+    pub fn publish_notification(&mut self, notification: &Notification) -> Result<(), esp_idf_svc::sys::EspError> {
+        let payload = NotificationEvent::new(notification).to_json();
+        self.publish(NOTIFY_EVENT_TOPIC, QoS::AtLeastOnce, false, payload.as_bytes())?;
+        Ok(())
+    }
+
+    /// Publish the supply valve position (retained, so HA shows it after restarts)
+    #[cfg(feature = "valve")]
+    pub fn publish_valve(&mut self, state: ValveState, fault: bool) -> Result<(), esp_idf_svc::sys::EspError> {
+        let payload = ValveStatus::new(state, fault).to_json();
+        self.publish(VALVE_STATE_TOPIC, QoS::AtLeastOnce, true, payload.as_bytes())?;
+        Ok(())
+    }
+
+    /// Publish the lockout state (retained, so HA shows it after restarts)
+    ///
+    /// Shown as a safety sensor: on while outputs are locked out.
+    #[cfg(feature = "lockout")]
+    pub fn publish_lockout(&mut self, engaged: bool) -> Result<(), esp_idf_svc::sys::EspError> {
+        let payload = if engaged { "ON" } else { "OFF" };
+        self.publish(LOCKOUT_STATE_TOPIC, QoS::AtLeastOnce, true, payload.as_bytes())?;
+        Ok(())
+    }
+
+    /// Publish the running firmware version for the update entity (retained)
+    #[cfg(feature = "ota")]
+    pub fn publish_update(&mut self, in_progress: bool) -> Result<(), esp_idf_svc::sys::EspError> {
+        let payload = UpdateState { installed_version: env!("CARGO_PKG_VERSION"), in_progress }.to_json();
+        self.publish(UPDATE_STATE_TOPIC, QoS::AtLeastOnce, true, payload.as_bytes())?;
+        Ok(())
+    }
+
+    /// Publish the device diagnostics
+    pub fn publish_diagnostics(&mut self, diag: &Diagnostics) -> Result<(), esp_idf_svc::sys::EspError> {
+        let payload = DiagState::from(diag).to_json();
+        debug!("Publishing diagnostics: {}", payload);
+        self.publish(DIAG_STATE_TOPIC, QoS::AtMostOnce, false, payload.as_bytes())?;
+        Ok(())
+    }
+
+    /// Publish the init phase timings once startup has finished (retained)
+    pub fn publish_startup(&mut self, startup: &StartupTiming) -> Result<(), esp_idf_svc::sys::EspError> {
+        let payload = startup.to_json();
+        self.publish(STARTUP_TOPIC, QoS::AtLeastOnce, true, payload.as_bytes())?;
+        Ok(())
+    }
 
+    /// Publish maintenance mode state (retained, so HA shows it after restarts)
+    pub fn publish_maintenance(&mut self, active: bool) -> Result<(), esp_idf_svc::sys::EspError> {
+        let payload = if active { "ON" } else { "OFF" };
+        self.publish(MAINTENANCE_STATE_TOPIC, QoS::AtLeastOnce, true, payload.as_bytes())?;
         Ok(())
     }
 }
@@ -0,0 +1,334 @@
+//! Long-term history in InfluxDB
+//!
+//! The flash history only holds a few days of samples. An `influx` build also
+//! writes every reading to an InfluxDB 2 server as line protocol, one
+//! `water` measurement per sample:
+//!
+//! ```text
+//! water,host=watercontroller level_pct=64i,gallons=320i,pressure_psi=48i 1760000000
+//! ```
+//!
+//! The server is `influx_url` (e.g. `http://influx.local:8086`), writing to
+//! `influx_bucket` of `influx_org` with the API token `influx_token`, all set
+//! through console provisioning. Samples are batched and POSTed once a
+//! minute; while the server can't be reached they are kept and sent later,
+//! up to [`MAX_PENDING`] lines, dropping the oldest. Samples taken before
+//! SNTP has set the clock have no timestamp and are not written.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+#[cfg(target_os = "espidf")]
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+#[cfg(target_os = "espidf")]
+use std::sync::{Arc, Mutex};
+#[cfg(target_os = "espidf")]
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::http::Method;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::sys::EspError;
+#[cfg(target_os = "espidf")]
+use log::*;
+
+#[cfg(target_os = "espidf")]
+use crate::config::Config;
+
+/// Measurement the readings are written to
+pub const MEASUREMENT: &str = "water";
+/// Lines kept while the server is unreachable (20 minutes of 5 s samples)
+pub const MAX_PENDING: usize = 240;
+/// Lines sent in one request, to keep the body small
+pub const BATCH_LINES: usize = 60;
+#[cfg(target_os = "espidf")]
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+#[cfg(target_os = "espidf")]
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Field value of a point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f32),
+    Bool(bool),
+}
+
+/// One line of line protocol
+#[derive(Debug, Clone)]
+pub struct Point {
+    measurement: &'static str,
+    tags: Vec<(&'static str, String)>,
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl Point {
+    pub fn new(measurement: &'static str) -> Self {
+        Self { measurement, tags: Vec::new(), fields: Vec::new() }
+    }
+
+    pub fn tag(mut self, key: &'static str, value: &str) -> Self {
+        self.tags.push((key, value.to_string()));
+        self
+    }
+
+    pub fn field(mut self, key: &'static str, value: Value) -> Self {
+        self.fields.push((key, value));
+        self
+    }
+
+    /// Line protocol for the point at `epoch_secs`
+    ///
+    /// `None` without any fields to write; NaN and infinite floats are left
+    /// out, since InfluxDB rejects the whole line for them.
+    pub fn line(&self, epoch_secs: i64) -> Option<String> {
+        let mut line = escape(self.measurement, false);
+        for (key, value) in &self.tags {
+            let _ = write!(line, ",{}={}", escape(key, true), escape(value, true));
+        }
+        let mut separator = ' ';
+        for (key, value) in &self.fields {
+            let value = match *value {
+                Value::Int(v) => format!("{}i", v),
+                Value::Float(v) if v.is_finite() => format!("{}", v),
+                Value::Float(_) => continue,
+                Value::Bool(v) => v.to_string(),
+            };
+            let _ = write!(line, "{}{}={}", separator, escape(key, true), value);
+            separator = ',';
+        }
+        if separator == ' ' {
+            return None;
+        }
+        let _ = write!(line, " {}", epoch_secs);
+        Some(line)
+    }
+}
+
+/// Escape a measurement name, or a tag or field key or tag value (`key`)
+fn escape(s: &str, key: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == ',' || c == ' ' || (key && c == '=') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Write endpoint for `bucket` of `org` on the server at `base`, in seconds
+pub fn write_url(base: &str, org: &str, bucket: &str) -> String {
+    format!(
+        "{}/api/v2/write?org={}&bucket={}&precision=s",
+        base.trim_end_matches('/'),
+        percent_encode(org),
+        percent_encode(bucket)
+    )
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            out.push(b as char);
+        } else {
+            let _ = write!(out, "%{:02X}", b);
+        }
+    }
+    out
+}
+
+/// Lines waiting to be written, oldest first
+#[derive(Debug, Default)]
+pub struct Batch {
+    lines: VecDeque<String>,
+    dropped: usize,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a line, dropping the oldest beyond [`MAX_PENDING`]
+    pub fn push(&mut self, line: String) {
+        if self.lines.len() == MAX_PENDING {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+        self.lines.push_back(line);
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Body of the next request and the number of lines in it
+    pub fn next_body(&self) -> Option<(String, usize)> {
+        if self.lines.is_empty() {
+            return None;
+        }
+        let count = self.lines.len().min(BATCH_LINES);
+        let body = self.lines.iter().take(count).map(String::as_str).collect::<Vec<_>>().join("\n");
+        Some((body, count))
+    }
+
+    /// Forget the first `count` lines once they have been written
+    pub fn commit(&mut self, count: usize) {
+        self.lines.drain(..count.min(self.lines.len()));
+    }
+
+    /// Lines dropped since the last call
+    pub fn take_dropped(&mut self) -> usize {
+        std::mem::take(&mut self.dropped)
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+}
+
+/// Background writer; lines are queued here and POSTed by its thread
+#[cfg(target_os = "espidf")]
+pub struct InfluxWriter {
+    tx: Sender<String>,
+}
+
+#[cfg(target_os = "espidf")]
+impl InfluxWriter {
+    pub fn start(config: Arc<Mutex<Config>>) -> std::io::Result<Self> {
+        let (tx, rx) = mpsc::channel::<String>();
+        std::thread::Builder::new()
+            .name("influx".into())
+            .stack_size(8 * 1024)
+            .spawn(move || {
+                let mut batch = Batch::new();
+                let mut last_flush = Instant::now();
+                loop {
+                    match rx.recv_timeout(FLUSH_INTERVAL.saturating_sub(last_flush.elapsed())) {
+                        Ok(line) => batch.push(line),
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    if last_flush.elapsed() < FLUSH_INTERVAL {
+                        continue;
+                    }
+                    last_flush = Instant::now();
+                    let (url, token) = {
+                        let cfg = config.lock().unwrap();
+                        if cfg.influx_url.is_empty() {
+                            batch.clear();
+                            continue;
+                        }
+                        (write_url(&cfg.influx_url, &cfg.influx_org, &cfg.influx_bucket), cfg.influx_token.clone())
+                    };
+                    let dropped = batch.take_dropped();
+                    if dropped > 0 {
+                        warn!("InfluxDB: dropped {} samples while the server was unreachable", dropped);
+                    }
+                    while let Some((body, count)) = batch.next_body() {
+                        match post(&url, &token, &body) {
+                            Ok(status) if (200..300).contains(&status) => {
+                                debug!("InfluxDB: wrote {} samples", count);
+                                batch.commit(count);
+                            }
+                            Ok(status) => {
+                                warn!("InfluxDB: {} returned HTTP {}, {} samples pending", url, status, batch.len());
+                                // A rejected batch won't be accepted later either
+                                if (400..500).contains(&status) && status != 429 {
+                                    batch.commit(count);
+                                }
+                                break;
+                            }
+                            Err(e) => {
+                                warn!("InfluxDB: write failed: {:?}, {} samples pending", e, batch.len());
+                                break;
+                            }
+                        }
+                    }
+                }
+            })?;
+        Ok(Self { tx })
+    }
+
+    /// Queue a line for the next write
+    pub fn send(&self, line: String) {
+        if self.tx.send(line).is_err() {
+            warn!("InfluxDB: writer thread has stopped");
+        }
+    }
+}
+
+/// POST line protocol, returning the HTTP status
+#[cfg(target_os = "espidf")]
+fn post(url: &str, token: &str, body: &str) -> Result<u16, EspError> {
+    let mut connection = EspHttpConnection::new(&Configuration {
+        timeout: Some(WRITE_TIMEOUT),
+        // HTTPS servers are checked against the built-in CA bundle
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    let length = body.len().to_string();
+    let authorization = format!("Token {}", token);
+    let headers = [
+        ("Content-Type", "text/plain; charset=utf-8"),
+        ("Content-Length", length.as_str()),
+        ("Authorization", authorization.as_str()),
+    ];
+    connection.initiate_request(Method::Post, url, &headers)?;
+    connection.write_all(body.as_bytes())?;
+    connection.initiate_response()?;
+    Ok(connection.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_protocol() {
+        let point = Point::new(MEASUREMENT)
+            .tag("host", "watercontroller")
+            .field("level_pct", Value::Int(64))
+            .field("speed", Value::Float(0.5))
+            .field("bad", Value::Float(f32::NAN))
+            .field("pump1_on", Value::Bool(true));
+        assert_eq!(
+            point.line(1_760_000_000).unwrap(),
+            "water,host=watercontroller level_pct=64i,speed=0.5,pump1_on=true 1760000000"
+        );
+        let point = Point::new("my water").tag("tank name", "a=b,c").field("x", Value::Int(-1));
+        assert_eq!(point.line(0).unwrap(), r"my\ water,tank\ name=a\=b\,c x=-1i 0");
+        assert!(Point::new(MEASUREMENT).field("bad", Value::Float(f32::INFINITY)).line(0).is_none());
+    }
+
+    #[test]
+    fn test_batch_and_url() {
+        assert_eq!(
+            write_url("http://influx.local:8086/", "Home Lab", "water"),
+            "http://influx.local:8086/api/v2/write?org=Home%20Lab&bucket=water&precision=s"
+        );
+
+        let mut batch = Batch::new();
+        assert!(batch.next_body().is_none());
+        for i in 0..MAX_PENDING + 5 {
+            batch.push(format!("water x={}i {}", i, i));
+        }
+        assert_eq!(batch.len(), MAX_PENDING);
+        assert_eq!(batch.take_dropped(), 5);
+        assert_eq!(batch.take_dropped(), 0);
+        let (body, count) = batch.next_body().unwrap();
+        assert_eq!(count, BATCH_LINES);
+        assert!(body.starts_with("water x=5i 5\nwater x=6i 6\n"));
+        assert_eq!(body.lines().count(), BATCH_LINES);
+        batch.commit(count);
+        assert_eq!(batch.len(), MAX_PENDING - BATCH_LINES);
+        assert!(batch.next_body().unwrap().0.starts_with("water x=65i 65\n"));
+    }
+}
@@ -0,0 +1,186 @@
+//! Minimal JSON reader for flat configuration documents
+//!
+//! Only what the configuration channels need: a single object whose values
+//! are numbers or strings, e.g. `{"tank_capacity": 500, "mqtt_host": "ha"}`.
+//! Nested objects, arrays, booleans and null are rejected. Documents are
+//! written with `format!`, using `escape` for string values.
+
+/// Value of a flat object member
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Number(f32),
+    Text(String),
+}
+
+/// Parse a flat `{"key": number, ...}` object into its members, in order
+pub fn parse_number_object(doc: &str) -> Result<Vec<(String, f32)>, String> {
+    parse_object(doc)?
+        .into_iter()
+        .map(|(key, value)| match value {
+            JsonValue::Number(v) => Ok((key, v)),
+            JsonValue::Text(_) => Err(format!("expected a number for \"{}\"", key)),
+        })
+        .collect()
+}
+
+/// Parse a flat object with number and string values into its members, in order
+pub fn parse_object(doc: &str) -> Result<Vec<(String, JsonValue)>, String> {
+    let mut p = Parser { s: doc.as_bytes(), pos: 0 };
+    let mut members = Vec::new();
+
+    p.expect(b'{')?;
+    if !p.eat(b'}') {
+        loop {
+            let key = p.string()?;
+            p.expect(b':')?;
+            p.skip_ws();
+            let value = if p.s.get(p.pos) == Some(&b'"') {
+                JsonValue::Text(p.string()?)
+            } else {
+                JsonValue::Number(p.number().map_err(|e| format!("{} for \"{}\"", e, key))?)
+            };
+            members.push((key, value));
+            if p.eat(b'}') {
+                break;
+            }
+            p.expect(b',')?;
+        }
+    }
+    p.skip_ws();
+    if p.pos != p.s.len() {
+        return Err(format!("trailing data at offset {}", p.pos));
+    }
+    Ok(members)
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_ws(&mut self) {
+        while self.s.get(self.pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consume `c` (after whitespace) if it is next
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_ws();
+        if self.s.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}' at offset {}", c as char, self.pos))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            match self.s.get(self.pos) {
+                None => return Err("unterminated string".to_string()),
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.s.get(self.pos) {
+                        Some(&c @ (b'"' | b'\\' | b'/')) => out.push(c),
+                        Some(b'n') => out.push(b'\n'),
+                        Some(b't') => out.push(b'\t'),
+                        _ => return Err(format!("unsupported escape at offset {}", self.pos)),
+                    }
+                }
+                Some(&c) => out.push(c),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        String::from_utf8(out).map_err(|_| "invalid UTF-8 in string".to_string())
+    }
+
+    fn number(&mut self) -> Result<f32, String> {
+        self.skip_ws();
+        let start = self.pos;
+        while self
+            .s
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.' | b'e' | b'E'))
+        {
+            self.pos += 1;
+        }
+        // Only ASCII was consumed, so the slice is valid UTF-8
+        let text = core::str::from_utf8(&self.s[start..self.pos]).unwrap_or_default();
+        match text.parse::<f32>() {
+            Ok(v) if v.is_finite() => Ok(v),
+            _ => Err(format!("expected a number at offset {}", start)),
+        }
+    }
+}
+
+/// Escape a string for use inside a JSON string literal
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_number_object() {
+        let doc = r#" { "tank_capacity": 500, "vfd_kp" : 1.5e3,"sensor_height":-2 } "#;
+        assert_eq!(
+            parse_number_object(doc).unwrap(),
+            vec![
+                ("tank_capacity".to_string(), 500.0),
+                ("vfd_kp".to_string(), 1500.0),
+                ("sensor_height".to_string(), -2.0),
+            ]
+        );
+        assert_eq!(parse_number_object("{}").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_rejects_malformed_documents() {
+        assert!(parse_number_object(r#"{"a": 1,}"#).is_err());
+        assert!(parse_number_object(r#"{"a": "1"}"#).is_err());
+        assert!(parse_number_object(r#"{"a": {"b": 1}}"#).is_err());
+        assert!(parse_number_object(r#"{"a": 1} x"#).is_err());
+        assert!(parse_number_object(r#"{"a" 1}"#).is_err());
+        assert!(parse_number_object("[1]").is_err());
+    }
+
+    #[test]
+    fn test_parse_object_with_strings() {
+        let doc = r#"{"mqtt_host": "ha.local", "mqtt_port": 1883, "tz": "CET-1CEST,M3.5.0,M10.5.0/3", "pass": "a\"b"}"#;
+        assert_eq!(
+            parse_object(doc).unwrap(),
+            vec![
+                ("mqtt_host".to_string(), JsonValue::Text("ha.local".to_string())),
+                ("mqtt_port".to_string(), JsonValue::Number(1883.0)),
+                ("tz".to_string(), JsonValue::Text("CET-1CEST,M3.5.0,M10.5.0/3".to_string())),
+                ("pass".to_string(), JsonValue::Text("a\"b".to_string())),
+            ]
+        );
+        assert!(parse_object(r#"{"a": true}"#).is_err());
+    }
+}
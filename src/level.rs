@@ -0,0 +1,147 @@
+//! Tank level from the radar distance reading
+//!
+//! The SEN0676 reports the empty height: the distance from the sensor down to
+//! the water surface. With the sensor's installation height above the tank
+//! bottom and the deadzone (sensor to the 100% level), that gives the water
+//! depth, and the tank geometry turns depth into volume.
+//!
+//! ```text
+//!   sensor ──┬──────────────┬──
+//!            │ deadzone     │ empty height
+//!   100% ────┼──            │
+//!            │ useful      ─┴── water surface
+//!            │ depth        │ water depth
+//!   bottom ──┴──────────────┴──
+//! ```
+
+#[cfg(target_os = "espidf")]
+use crate::config::Config;
+
+/// Tank cross-section along its height
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TankShape {
+    /// Upright cylinder or box: volume proportional to depth
+    Vertical,
+    /// Cylinder lying on its side: volume follows the circular segment area
+    HorizontalCylinder,
+}
+
+impl TankShape {
+    /// Decode the stored config value (0 = vertical, 1 = horizontal cylinder)
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1 => TankShape::HorizontalCylinder,
+            _ => TankShape::Vertical,
+        }
+    }
+}
+
+/// Tank dimensions relevant to the level calculation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TankGeometry {
+    /// Sensor height above the tank bottom (mm)
+    pub install_mm: u32,
+    /// Sensor to the 100% level (mm)
+    pub deadzone_mm: u32,
+    /// Volume at 100% (gallons)
+    pub capacity_gallons: u16,
+    pub shape: TankShape,
+}
+
+/// Computed tank contents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TankLevel {
+    /// Water depth above the bottom (mm)
+    pub water_mm: u32,
+    /// Depth at 100% (mm)
+    pub useful_mm: u32,
+    /// Volume as a share of capacity (0-100)
+    pub percent: u8,
+    pub gallons: u16,
+}
+
+#[cfg(target_os = "espidf")]
+impl TankGeometry {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self {
+            install_mm: cfg.radar_height_cm as u32 * 10,
+            deadzone_mm: cfg.radar_deadzone_cm as u32 * 10,
+            capacity_gallons: cfg.tank_capacity_gallons,
+            shape: TankShape::from_code(cfg.tank_shape),
+        }
+    }
+}
+
+impl TankGeometry {
+    /// Tank contents for a radar empty-height reading (mm)
+    pub fn level(&self, empty_mm: u16) -> TankLevel {
+        let useful_mm = self.install_mm.saturating_sub(self.deadzone_mm);
+        let water_mm = self.install_mm.saturating_sub(empty_mm as u32).min(useful_mm);
+        let depth_fraction = if useful_mm > 0 {
+            water_mm as f32 / useful_mm as f32
+        } else {
+            0.0
+        };
+        let volume_fraction = match self.shape {
+            TankShape::Vertical => depth_fraction,
+            TankShape::HorizontalCylinder => horizontal_cylinder_fraction(depth_fraction),
+        };
+        TankLevel {
+            water_mm,
+            useful_mm,
+            percent: (volume_fraction * 100.0).round().clamp(0.0, 100.0) as u8,
+            gallons: (self.capacity_gallons as f32 * volume_fraction).round() as u16,
+        }
+    }
+}
+
+/// Filled share of a horizontal cylinder's volume at `depth` (share of the diameter)
+fn horizontal_cylinder_fraction(depth: f32) -> f32 {
+    use std::f32::consts::PI;
+
+    let depth = depth.clamp(0.0, 1.0);
+    // Central angle of the circular segment below the surface
+    let theta = 2.0 * (1.0 - 2.0 * depth).acos();
+    (theta - theta.sin()) / (2.0 * PI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERTICAL: TankGeometry = TankGeometry {
+        install_mm: 2000,
+        deadzone_mm: 200,
+        capacity_gallons: 500,
+        shape: TankShape::Vertical,
+    };
+
+    #[test]
+    fn test_vertical_tank() {
+        // Surface at the deadzone: full
+        let full = VERTICAL.level(200);
+        assert_eq!((full.percent, full.gallons), (100, 500));
+        // Surface at the bottom: empty
+        assert_eq!(VERTICAL.level(2000).percent, 0);
+        // Half the useful depth
+        let half = VERTICAL.level(1100);
+        assert_eq!((half.water_mm, half.percent, half.gallons), (900, 50, 250));
+        // Reading inside the deadzone clamps to full
+        assert_eq!(VERTICAL.level(50).percent, 100);
+    }
+
+    #[test]
+    fn test_horizontal_cylinder() {
+        let tank = TankGeometry { shape: TankShape::HorizontalCylinder, ..VERTICAL };
+        assert_eq!(tank.level(1100).percent, 50);
+        // A quarter of the diameter holds ~19.6% of the volume
+        assert_eq!(tank.level(2000 - 450).percent, 20);
+        assert_eq!(tank.level(200).gallons, 500);
+    }
+
+    #[test]
+    fn test_degenerate_geometry() {
+        let tank = TankGeometry { deadzone_mm: 3000, ..VERTICAL };
+        assert_eq!(tank.level(100).percent, 0);
+    }
+}
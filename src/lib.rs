@@ -1,19 +1,206 @@
+// Hardware-facing modules only build for the ESP32; the UI widgets and other
+// pure logic also build for the host (see the `sim` binary), with their
+// `Config` and esp-idf glue left out.
+
+pub mod alarms;
+
+pub mod board;
+
+pub mod build_info;
+
+#[cfg(all(target_os = "espidf", feature = "buttons"))]
+pub mod buttons;
+
+#[cfg(feature = "wifi")]
+pub mod captive;
+
+#[cfg(feature = "cellular")]
+pub mod cellular;
+
+pub mod clock;
+
+pub mod codec;
+
+#[cfg(target_os = "espidf")]
 pub mod config;
 
-#[cfg(feature = "display")]
+pub mod correction;
+
+pub mod diag;
+
+pub mod filter;
+
+pub mod fixedmath;
+
+pub mod json;
+
+#[cfg(feature = "influx")]
+pub mod influx;
+
+pub mod level;
+
+pub mod logging;
+
+#[cfg(feature = "lora")]
+pub mod lora;
+
+pub mod memory;
+
+pub mod outbox;
+
+#[cfg(feature = "mqtt")]
+pub mod payload;
+
+#[cfg(feature = "sim-sensors")]
+pub mod playback;
+
+pub mod polling;
+
+#[cfg(target_os = "espidf")]
+pub mod provision;
+
+pub mod qr;
+
+pub mod reconnect;
+
+pub mod sensors;
+
+pub mod stats;
+
+pub mod testfire;
+
+pub mod trace;
+
+pub mod trial;
+
+pub mod twin;
+
+pub mod warmup;
+
+pub mod zip;
+
+#[cfg(all(target_os = "espidf", feature = "display"))]
+pub mod display;
+
+#[cfg(all(target_os = "espidf", feature = "display"))]
 pub mod ls027b7dh01;
 
+#[cfg(all(target_os = "espidf", feature = "tft"))]
+pub mod tft;
+
 #[cfg(feature = "display")]
 pub mod ui;
 
 #[cfg(feature = "radar")]
 pub mod sen0676;
 
-#[cfg(feature = "pressure")]
+#[cfg(all(target_os = "espidf", feature = "lora"))]
+pub mod sx1276;
+
+#[cfg(all(target_os = "espidf", feature = "pressure"))]
 pub mod pressure;
 
-#[cfg(feature = "mqtt")]
+#[cfg(feature = "remote")]
+pub mod remote;
+
+#[cfg(all(target_os = "espidf", feature = "mqtt"))]
 pub mod homeassistant;
 
 #[cfg(feature = "ethernet")]
+pub mod events;
+
+#[cfg(feature = "ethernet")]
+pub mod net;
+
+#[cfg(feature = "ethernet")]
+pub mod selftest;
+
+#[cfg(all(target_os = "espidf", feature = "ethernet"))]
 pub mod web;
+
+#[cfg(feature = "ethernet")]
+pub mod schedule;
+
+#[cfg(all(target_os = "espidf", feature = "wifi"))]
+pub mod wifi;
+
+#[cfg(all(target_os = "espidf", feature = "ota"))]
+pub mod ota;
+
+#[cfg(feature = "notify")]
+pub mod notify;
+
+#[cfg(feature = "lockout")]
+pub mod lockout;
+
+#[cfg(all(target_os = "espidf", feature = "history"))]
+pub mod history;
+
+#[cfg(feature = "pump")]
+pub mod pump;
+
+#[cfg(feature = "efficiency")]
+pub mod efficiency;
+
+#[cfg(feature = "pump_power")]
+pub mod ct_clamp;
+
+#[cfg(all(feature = "pump", feature = "notify"))]
+pub mod replay;
+
+#[cfg(feature = "hammer")]
+pub mod hammer;
+
+#[cfg(feature = "well_pump")]
+pub mod well_pump;
+
+#[cfg(feature = "pipe_burst")]
+pub mod pipe_burst;
+
+#[cfg(feature = "flow")]
+pub mod flow;
+
+#[cfg(feature = "vfd")]
+pub mod pid;
+
+#[cfg(all(target_os = "espidf", feature = "vfd"))]
+pub mod vfd;
+
+#[cfg(all(target_os = "espidf", feature = "heater"))]
+pub mod heater;
+
+#[cfg(all(target_os = "espidf", feature = "ds18b20"))]
+pub mod ds18b20;
+
+#[cfg(feature = "valve")]
+pub mod valve;
+
+#[cfg(all(feature = "ds18b20", feature = "tft"))]
+compile_error!("the ds18b20 bus uses GPIO33, the TFT reset line");
+
+#[cfg(all(feature = "valve", feature = "tft"))]
+compile_error!("the valve relays use GPIO32/GPIO33, the TFT DC and reset lines");
+
+#[cfg(all(feature = "valve", feature = "ds18b20"))]
+compile_error!("the valve close relay uses GPIO33, the ds18b20 bus");
+
+#[cfg(all(feature = "cellular", any(feature = "tft", feature = "ds18b20", feature = "valve")))]
+compile_error!("the cellular modem UART uses GPIO32/GPIO33, the TFT, ds18b20 and valve pins");
+
+#[cfg(all(feature = "lora", any(feature = "tft", feature = "ds18b20", feature = "valve", feature = "cellular")))]
+compile_error!("the LoRa radio uses GPIO32/GPIO33, the TFT, ds18b20, valve and cellular pins");
+
+#[cfg(all(feature = "valve_limits", any(feature = "pressure", feature = "buttons")))]
+compile_error!("the valve limit switches use GPIO36/GPIO39, the pressure sensor and button inputs");
+
+#[cfg(all(feature = "flow", feature = "heater"))]
+compile_error!("the flow meter uses GPIO15, the radar heater MOSFET");
+
+#[cfg(all(feature = "filter_pressure", feature = "buttons"))]
+compile_error!("the post-filter pressure sensor uses GPIO39, the front panel button");
+
+#[cfg(all(feature = "pump_power", any(feature = "tft", feature = "valve", feature = "cellular", feature = "lora")))]
+compile_error!("the pump CT clamp uses GPIO32, the TFT DC line, valve open relay, modem TX and LoRa MISO");
+
+#[cfg(all(feature = "remote", any(feature = "mqtt", feature = "radar", feature = "pressure", feature = "pump", feature = "sim-sensors")))]
+compile_error!("a remote panel only shows the main controller's state; build it without mqtt, radar, pressure, pump or sim-sensors");
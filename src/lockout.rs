@@ -0,0 +1,161 @@
+//! Lockout/tagout interlock
+//!
+//! While a lockout is engaged every output stays off: pump relays, VFD
+//! speed, heater and supply valve, whatever automation, Home Assistant or
+//! the web UI ask for. It is meant for servicing the pump, where a remote
+//! command starting it could hurt someone working on it.
+//!
+//! Engaging takes a PIN of `PIN_LEN` digits. Releasing takes the same PIN
+//! and a press of the front panel button during the `PRESENCE_WINDOW`
+//! before, so it can only be done by someone standing at the unit. The PIN
+//! is kept in NVS, so a reboot doesn't release the lockout. After
+//! `MAX_FAILURES` wrong PINs, releasing is refused for `HOLDOFF`.
+
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+/// Allowed PIN length (digits)
+pub const PIN_LEN: RangeInclusive<usize> = 4..=8;
+/// How long a button press allows a release
+pub const PRESENCE_WINDOW: Duration = Duration::from_secs(120);
+/// Wrong PINs before releasing is held off
+const MAX_FAILURES: u32 = 5;
+const HOLDOFF: Duration = Duration::from_secs(5 * 60);
+
+/// Why a lockout request was refused
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockoutError {
+    AlreadyEngaged,
+    NotEngaged,
+    InvalidPin,
+    WrongPin,
+    /// No button press within `PRESENCE_WINDOW`
+    NotPresent,
+    /// Too many wrong PINs
+    HeldOff,
+}
+
+impl LockoutError {
+    pub fn message(self) -> &'static str {
+        match self {
+            LockoutError::AlreadyEngaged => "Lockout is already engaged",
+            LockoutError::NotEngaged => "Lockout is not engaged",
+            LockoutError::InvalidPin => "PIN must be 4 to 8 digits",
+            LockoutError::WrongPin => "Wrong PIN",
+            LockoutError::NotPresent => "Press the button on the unit first",
+            LockoutError::HeldOff => "Too many wrong PINs, try again in 5 minutes",
+        }
+    }
+}
+
+/// Whether `pin` is usable for a lockout
+pub fn valid_pin(pin: &str) -> bool {
+    PIN_LEN.contains(&pin.len()) && pin.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Lockout state
+#[derive(Debug, Default)]
+pub struct Lockout {
+    /// PIN of the engaged lockout
+    pin: Option<String>,
+    /// Last button press
+    presence: Option<Duration>,
+    failures: u32,
+    held_off_until: Option<Duration>,
+}
+
+impl Lockout {
+    /// Restore from the stored PIN (empty = released)
+    pub fn new(stored_pin: &str) -> Self {
+        Self { pin: valid_pin(stored_pin).then(|| stored_pin.to_string()), ..Default::default() }
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.pin.is_some()
+    }
+
+    pub fn engage(&mut self, pin: &str) -> Result<(), LockoutError> {
+        if self.is_engaged() {
+            return Err(LockoutError::AlreadyEngaged);
+        }
+        if !valid_pin(pin) {
+            return Err(LockoutError::InvalidPin);
+        }
+        self.pin = Some(pin.to_string());
+        self.failures = 0;
+        Ok(())
+    }
+
+    /// Record a front panel button press
+    pub fn confirm_presence(&mut self, now: Duration) {
+        self.presence = Some(now);
+    }
+
+    /// Whether a button press within `PRESENCE_WINDOW` allows a release
+    pub fn is_present(&self, now: Duration) -> bool {
+        self.presence.is_some_and(|at| now.saturating_sub(at) <= PRESENCE_WINDOW)
+    }
+
+    pub fn release(&mut self, pin: &str, now: Duration) -> Result<(), LockoutError> {
+        let Some(expected) = &self.pin else {
+            return Err(LockoutError::NotEngaged);
+        };
+        if self.held_off_until.is_some_and(|until| now < until) {
+            return Err(LockoutError::HeldOff);
+        }
+        if !self.is_present(now) {
+            return Err(LockoutError::NotPresent);
+        }
+        if pin != expected {
+            self.failures += 1;
+            if self.failures >= MAX_FAILURES {
+                self.failures = 0;
+                self.held_off_until = Some(now + HOLDOFF);
+            }
+            return Err(LockoutError::WrongPin);
+        }
+        *self = Self::default();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_release_needs_presence_and_pin() {
+        let mut lockout = Lockout::new("");
+        assert!(!lockout.is_engaged());
+        assert_eq!(lockout.engage("12a4"), Err(LockoutError::InvalidPin));
+        assert_eq!(lockout.engage("123"), Err(LockoutError::InvalidPin));
+        lockout.engage("2468").unwrap();
+        assert_eq!(lockout.engage("1357"), Err(LockoutError::AlreadyEngaged));
+
+        let now = Duration::from_secs(1000);
+        assert_eq!(lockout.release("2468", now), Err(LockoutError::NotPresent));
+        lockout.confirm_presence(now);
+        assert_eq!(lockout.release("2468", now + PRESENCE_WINDOW * 2), Err(LockoutError::NotPresent));
+        assert_eq!(lockout.release("1111", now), Err(LockoutError::WrongPin));
+        lockout.release("2468", now + Duration::from_secs(30)).unwrap();
+        assert!(!lockout.is_engaged());
+        assert_eq!(lockout.release("2468", now), Err(LockoutError::NotEngaged));
+
+        // A stored lockout survives a reboot
+        assert!(Lockout::new("2468").is_engaged());
+    }
+
+    #[test]
+    fn test_wrong_pins_hold_off_release() {
+        let mut lockout = Lockout::new("2468");
+        let now = Duration::from_secs(10);
+        lockout.confirm_presence(now);
+        for _ in 0..MAX_FAILURES {
+            assert_eq!(lockout.release("0000", now), Err(LockoutError::WrongPin));
+        }
+        assert_eq!(lockout.release("2468", now), Err(LockoutError::HeldOff));
+        let later = now + HOLDOFF;
+        lockout.confirm_presence(later);
+        lockout.release("2468", later).unwrap();
+    }
+}
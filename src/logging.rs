@@ -0,0 +1,343 @@
+//! Log output: the serial console and a remote syslog server
+//!
+//! Once the controller is sealed in its enclosure the serial console is out
+//! of reach. With `syslog_server` set (`host` or `host:port`, port 514 by
+//! default), every record at `syslog_level` or more severe is also sent to
+//! that server as an RFC 5424 message over UDP, from facility `local0`:
+//!
+//! ```text
+//! <132>1 2026-10-14T07:05:00-05:00 watercontroller watercontroller - - - sen0676: no echo
+//! ```
+//!
+//! The console keeps its own levels. Records are queued to a sender thread,
+//! so logging never waits on the network; when the queue is full, or before
+//! the network is up, records are only printed on the console. The time
+//! field is `-` until SNTP has set the clock.
+//!
+//! Info and more severe records are also kept in memory for the diagnostic
+//! bundle (see `LogBuffer`): the start of the boot, and the latest lines.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Duration;
+
+use log::{Level, LevelFilter};
+
+use crate::clock::Timestamp;
+
+/// Port of a server given without one
+pub const DEFAULT_PORT: u16 = 514;
+/// Syslog facility `local0`
+const FACILITY: u8 = 16;
+/// APP-NAME field of every message
+const APP_NAME: &str = "watercontroller";
+/// Longest message text sent, to stay well inside one datagram
+const MAX_MESSAGE_LEN: usize = 480;
+/// Lines kept from the start of the boot
+pub const BOOT_LINES: usize = 48;
+/// Latest lines kept after those
+pub const RECENT_LINES: usize = 96;
+/// Longest line text kept in memory
+const MAX_LINE_LEN: usize = 160;
+
+/// Level filter of the `syslog_level` setting: 0 = off, 1 = error ... 5 = trace
+pub fn level_filter(level: u16) -> LevelFilter {
+    match level {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Syslog severity of a record level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Host and port of the `syslog_server` setting, `None` when empty
+pub fn parse_server(server: &str) -> Option<(String, u16)> {
+    let server = server.trim();
+    if server.is_empty() {
+        return None;
+    }
+    match server.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() => Some((host.to_string(), port.parse().ok().filter(|&p| p > 0)?)),
+        Some(_) => None,
+        None => Some((server.to_string(), DEFAULT_PORT)),
+    }
+}
+
+/// RFC 5424 message for one record
+///
+/// The text is cut at `MAX_MESSAGE_LEN` bytes, on a character boundary.
+pub fn format_message(level: Level, hostname: &str, time: Option<Timestamp>, target: &str, text: &str) -> String {
+    let mut message = format!("<{}>1 ", FACILITY * 8 + severity(level));
+    match time {
+        Some(time) => message.push_str(&time.iso8601()),
+        None => message.push('-'),
+    }
+    // The module path after the crate name, like the console shows it
+    let module = target.split_once("::").map_or(target, |(_, module)| module);
+    let _ = write!(message, " {} {} - - - {}: ", hostname, APP_NAME, module);
+    message.push_str(truncate(text, MAX_MESSAGE_LEN));
+    message
+}
+
+/// Line kept in the `LogBuffer`, like the console prints it: `W (12345) sen0676: no echo`
+///
+/// The uptime is in milliseconds; the text is cut at `MAX_LINE_LEN` bytes.
+pub fn format_line(level: Level, uptime: Duration, target: &str, text: &str) -> String {
+    let module = target.split_once("::").map_or(target, |(_, module)| module);
+    let letter = &level.as_str()[..1];
+    format!("{} ({}) {}: {}", letter, uptime.as_millis(), module, truncate(text, MAX_LINE_LEN))
+}
+
+/// At most `max` bytes of `text`, cut on a character boundary
+fn truncate(text: &str, max: usize) -> &str {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Log lines kept in memory for the diagnostic bundle
+///
+/// The first `BOOT_LINES` of the boot stay for good, so the bundle shows how
+/// the unit came up; after them only the latest `RECENT_LINES` are kept.
+#[derive(Debug, Default)]
+pub struct LogBuffer {
+    boot: Vec<String>,
+    recent: VecDeque<String>,
+    /// Lines dropped between the boot and the recent ones
+    dropped: u32,
+}
+
+impl LogBuffer {
+    pub const fn new() -> Self {
+        Self { boot: Vec::new(), recent: VecDeque::new(), dropped: 0 }
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.boot.len() < BOOT_LINES {
+            self.boot.push(line);
+            return;
+        }
+        if self.recent.len() == RECENT_LINES {
+            self.recent.pop_front();
+            self.dropped = self.dropped.saturating_add(1);
+        }
+        self.recent.push_back(line);
+    }
+
+    /// The start of the boot, one line per record
+    pub fn boot(&self) -> String {
+        self.boot.iter().fold(String::new(), |text, line| text + line + "\n")
+    }
+
+    /// The latest lines, after a note of how many were dropped before them
+    pub fn recent(&self) -> String {
+        let note = if self.dropped > 0 { format!("({} earlier lines dropped)\n", self.dropped) } else { String::new() };
+        self.recent.iter().fold(note, |text, line| text + line + "\n")
+    }
+}
+
+#[cfg(target_os = "espidf")]
+pub use forward::{boot_log, init, recent_log, start_syslog};
+
+#[cfg(target_os = "espidf")]
+mod forward {
+    use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+    use std::sync::mpsc::{self, Receiver, SyncSender};
+    use std::sync::{Mutex, OnceLock};
+    use std::time::{Duration, Instant};
+
+    use esp_idf_svc::log::EspLogger;
+    use log::{info, Level, LevelFilter, Log, Metadata, Record};
+
+    use super::{format_line, format_message, parse_server, LogBuffer};
+    use crate::clock::{Clock, SystemClock};
+
+    /// Records waiting for the sender thread
+    const QUEUE_LEN: usize = 32;
+    /// How often an unresolved server name is looked up again
+    const LOOKUP_INTERVAL: Duration = Duration::from_secs(30);
+    /// DHCP and mDNS hostname, also before the network module is up
+    const HOSTNAME: &str = "watercontroller";
+
+    struct Syslog {
+        level: LevelFilter,
+        tx: SyncSender<String>,
+    }
+
+    struct Logger {
+        console: EspLogger,
+        syslog: OnceLock<Syslog>,
+    }
+
+    static LOGGER: Logger = Logger { console: EspLogger::new(), syslog: OnceLock::new() };
+    static BUFFER: Mutex<LogBuffer> = Mutex::new(LogBuffer::new());
+
+    impl Log for Logger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            self.console.enabled(metadata)
+                || metadata.level() <= Level::Info
+                || self.syslog.get().is_some_and(|s| metadata.level() <= s.level)
+        }
+
+        fn log(&self, record: &Record) {
+            if self.console.enabled(record.metadata()) {
+                self.console.log(record);
+            }
+            if record.level() <= Level::Info {
+                let line = format_line(record.level(), SystemClock.uptime(), record.target(), &record.args().to_string());
+                if let Ok(mut buffer) = BUFFER.lock() {
+                    buffer.push(line);
+                }
+            }
+            if let Some(syslog) = self.syslog.get().filter(|s| record.level() <= s.level) {
+                let text = record.args().to_string();
+                let message = format_message(record.level(), HOSTNAME, SystemClock.timestamp(), record.target(), &text);
+                // Full queue: the console has it
+                let _ = syslog.tx.try_send(message);
+            }
+        }
+
+        fn flush(&self) {
+            self.console.flush();
+        }
+    }
+
+    /// Install the logger; call first thing in `main`, in place of `EspLogger::initialize_default`
+    ///
+    /// Console levels are still set with `esp_idf_svc::log::set_target_level`,
+    /// which goes through the ESP-IDF log level table.
+    pub fn init() {
+        log::set_logger(&LOGGER).expect("logger installed once");
+        LOGGER.console.initialize();
+    }
+
+    /// Lines kept from the start of the boot (see `LogBuffer`)
+    pub fn boot_log() -> String {
+        BUFFER.lock().map(|buffer| buffer.boot()).unwrap_or_default()
+    }
+
+    /// Latest lines kept (see `LogBuffer`)
+    pub fn recent_log() -> String {
+        BUFFER.lock().map(|buffer| buffer.recent()).unwrap_or_default()
+    }
+
+    /// Forward records at `level` or more severe to `server` (see `parse_server`)
+    ///
+    /// Does nothing for an empty server or `LevelFilter::Off`. Only the first
+    /// call takes effect; the settings apply from the next boot.
+    pub fn start_syslog(server: &str, level: LevelFilter) -> std::io::Result<()> {
+        let Some((host, port)) = parse_server(server) else {
+            return Ok(());
+        };
+        if level == LevelFilter::Off {
+            return Ok(());
+        }
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        std::thread::Builder::new()
+            .name("syslog".into())
+            .stack_size(4 * 1024)
+            .spawn({
+                let host = host.clone();
+                move || send_loop(socket, &host, port, rx)
+            })?;
+        if LOGGER.syslog.set(Syslog { level, tx }).is_ok() {
+            log::set_max_level(log::max_level().max(level));
+            info!("Syslog: sending {} and above to {}:{}", level, host, port);
+        }
+        Ok(())
+    }
+
+    /// Send queued messages; must not log itself, or it would feed its own queue
+    fn send_loop(socket: UdpSocket, host: &str, port: u16, rx: Receiver<String>) {
+        let mut addr: Option<SocketAddr> = None;
+        let mut last_lookup: Option<Instant> = None;
+        for message in rx {
+            if addr.is_none() && last_lookup.map_or(true, |t| t.elapsed() >= LOOKUP_INTERVAL) {
+                last_lookup = Some(Instant::now());
+                addr = (host, port).to_socket_addrs().ok().and_then(|mut addrs| addrs.next());
+            }
+            // Dropped while the name doesn't resolve or the network is down
+            if let Some(addr) = addr {
+                let _ = socket.send_to(message.as_bytes(), addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_message() {
+        let time = Timestamp { epoch_secs: 1_791_979_500, utc_offset_secs: -5 * 3600 };
+        assert_eq!(
+            format_message(Level::Warn, "watercontroller", Some(time), "watercontroller::sen0676", "no echo"),
+            "<132>1 2026-10-14T07:05:00-05:00 watercontroller watercontroller - - - sen0676: no echo"
+        );
+        assert_eq!(
+            format_message(Level::Debug, "watercontroller", None, "esp_idf_svc::mqtt", "sent"),
+            "<135>1 - watercontroller watercontroller - - - mqtt: sent"
+        );
+        // Cut before the character that would cross the limit
+        let long = format!("a{}", "é".repeat(MAX_MESSAGE_LEN));
+        let message = format_message(Level::Error, "watercontroller", None, "main", &long);
+        assert!(message.starts_with("<131>1 - watercontroller watercontroller - - - main: aé"));
+        assert_eq!(message.len() - message.find(": ").unwrap() - 2, MAX_MESSAGE_LEN - 1);
+    }
+
+    #[test]
+    fn test_parse_server() {
+        assert_eq!(parse_server(" logs.local "), Some(("logs.local".to_string(), DEFAULT_PORT)));
+        assert_eq!(parse_server("192.168.1.5:1514"), Some(("192.168.1.5".to_string(), 1514)));
+        assert_eq!(parse_server(""), None);
+        assert_eq!(parse_server("logs.local:0"), None);
+        assert_eq!(parse_server(":514"), None);
+        assert_eq!(parse_server("logs.local:x"), None);
+        assert_eq!(level_filter(0), LevelFilter::Off);
+        assert_eq!(level_filter(2), LevelFilter::Warn);
+        assert_eq!(level_filter(9), LevelFilter::Trace);
+    }
+    #[test]
+    fn test_format_line() {
+        assert_eq!(
+            format_line(Level::Warn, Duration::from_millis(12_345), "watercontroller::sen0676", "no echo"),
+            "W (12345) sen0676: no echo"
+        );
+        assert_eq!(format_line(Level::Info, Duration::ZERO, "main", "boot"), "I (0) main: boot");
+        let long = "é".repeat(MAX_LINE_LEN);
+        assert_eq!(format_line(Level::Error, Duration::ZERO, "main", &long).len(), "E (0) main: ".len() + MAX_LINE_LEN);
+    }
+
+    #[test]
+    fn test_log_buffer() {
+        let mut buffer = LogBuffer::new();
+        assert_eq!(buffer.boot(), "");
+        assert_eq!(buffer.recent(), "");
+        for i in 0..BOOT_LINES + RECENT_LINES + 2 {
+            buffer.push(format!("line {i}"));
+        }
+        let boot = buffer.boot();
+        assert!(boot.starts_with("line 0\nline 1\n"));
+        assert!(boot.ends_with(&format!("line {}\n", BOOT_LINES - 1)));
+        let recent = buffer.recent();
+        assert!(recent.starts_with(&format!("(2 earlier lines dropped)\nline {}\n", BOOT_LINES + 2)));
+        assert!(recent.ends_with(&format!("line {}\n", BOOT_LINES + RECENT_LINES + 1)));
+        assert_eq!(recent.lines().count(), RECENT_LINES + 1);
+    }
+}
@@ -0,0 +1,83 @@
+//! LoRa telemetry between sibling controllers
+//!
+//! Tanks out of Ethernet reach run a controller in node mode, which sends a
+//! short state frame over an SX1276 radio every `lora_interval` seconds. A
+//! controller on the network runs in gateway mode, listens for those frames
+//! and republishes each node to MQTT, where it shows up in Home Assistant as
+//! "LoRa <node> ..." sensors.
+//!
+//! The radio sits on the display SPI bus (MISO GPIO32, NSS GPIO33) and is
+//! set up from `lora_mode`, `lora_node` and `lora_freq`. Frames are the
+//! `codec` state frame; other LoRa traffic on the channel fails its magic
+//! byte or CRC and is ignored.
+
+use std::time::Duration;
+
+/// Carrier frequency step of `lora_freq` (Hz)
+pub const FREQ_STEP_HZ: u32 = 100_000;
+
+/// What the radio is used for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    /// No radio fitted
+    Off,
+    /// Send this tank's state
+    Node,
+    /// Receive sibling frames and republish them to MQTT
+    Gateway,
+}
+
+impl Role {
+    /// Role from its config code (0 = off, 1 = node, 2 = gateway)
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1 => Role::Node,
+            2 => Role::Gateway,
+            _ => Role::Off,
+        }
+    }
+}
+
+/// When a node sends its next frame
+#[derive(Debug, Clone)]
+pub struct Beacon {
+    interval: Duration,
+    /// Uptime of the next frame
+    next: Duration,
+    seq: u8,
+}
+
+impl Beacon {
+    /// Nodes sending at the same interval are spread out by their id, one
+    /// second apart, so they don't keep colliding after a shared power cut.
+    pub fn new(interval: Duration, node: u8) -> Self {
+        Self { interval, next: interval + Duration::from_secs(node as u64), seq: 0 }
+    }
+
+    /// Sequence number of the frame to send at `now`, if one is due
+    pub fn due(&mut self, now: Duration) -> Option<u8> {
+        if now < self.next {
+            return None;
+        }
+        self.next = now + self.interval;
+        self.seq = self.seq.wrapping_add(1);
+        Some(self.seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beacon_spreads_nodes() {
+        let interval = Duration::from_secs(60);
+        let mut beacon = Beacon::new(interval, 5);
+        assert_eq!(beacon.due(Duration::from_secs(64)), None);
+        assert_eq!(beacon.due(Duration::from_secs(65)), Some(1));
+        assert_eq!(beacon.due(Duration::from_secs(100)), None);
+        assert_eq!(beacon.due(Duration::from_secs(126)), Some(2));
+        assert_eq!(Role::from_code(2), Role::Gateway);
+        assert_eq!(Role::from_code(7), Role::Off);
+    }
+}
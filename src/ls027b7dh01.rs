@@ -13,6 +13,11 @@
 //! - CS: Chip select (active HIGH - directly controlled, not via SPI driver)
 //! - DISP: Display on/off (directly controlled, active high)
 //! - EXTCOMIN: VCOM toggle (optional, can use software instead)
+//!
+//! # Ghosting
+//! Days of partial line updates leave faint ghosts of earlier screens. With a
+//! refresh interval set, `flush()` periodically clears the panel in hardware
+//! and rewrites every line from the framebuffer.
 
 use embedded_graphics::{
   Pixel,
@@ -25,6 +30,8 @@ use esp_idf_svc::hal::{
   spi::{SpiDeviceDriver, SpiDriver},
 };
 
+use crate::diag::FlushStats;
+
 /// Display width in pixels
 pub const WIDTH: u16 = 400;
 /// Display height in pixels
@@ -57,6 +64,15 @@ where
   framebuffer: [u8; FRAMEBUFFER_SIZE],
   dirty_lines: [u8; DIRTY_BITMAP_SIZE],
   vcom: bool,
+  /// Maximum lines sent per flush (0 = unlimited)
+  flush_budget: u16,
+  /// Line the next budgeted flush resumes from
+  flush_cursor: u16,
+  /// Flush counters for the diagnostics page
+  stats: FlushStats,
+  /// Time between full refreshes (zero = never)
+  refresh_interval: std::time::Duration,
+  last_refresh: std::time::Instant,
 }
 
 impl<'d, SPI, CS> Ls027b7dh01<'d, SPI, CS>
@@ -72,9 +88,36 @@ where
       framebuffer: [0xFF; FRAMEBUFFER_SIZE], // White (all 1s)
       dirty_lines: [0; DIRTY_BITMAP_SIZE],   // No dirty lines initially
       vcom: false,
+      flush_budget: 0,
+      flush_cursor: 0,
+      stats: FlushStats::default(),
+      refresh_interval: std::time::Duration::ZERO,
+      last_refresh: std::time::Instant::now(),
     }
   }
 
+  /// Force a full refresh every `interval` (zero = never)
+  ///
+  /// The next `flush()` after the interval has passed sends the hardware
+  /// clear and then all lines, regardless of the flush budget.
+  pub fn set_refresh_interval(&mut self, interval: std::time::Duration) {
+    self.refresh_interval = interval;
+  }
+
+  /// Limit the number of lines sent per `flush()` (0 = unlimited)
+  ///
+  /// With a slow SPI clock a full-screen refresh can take tens of milliseconds.
+  /// A budget spreads it across several calls; lines left over stay dirty and
+  /// are sent on the next flush, resuming where the previous one stopped.
+  pub fn set_flush_budget(&mut self, lines: u16) {
+    self.flush_budget = lines.min(HEIGHT);
+  }
+
+  /// Lines, bytes and time sent by flushes since boot
+  pub fn stats(&self) -> &FlushStats {
+    &self.stats
+  }
+
   /// Initialize the display
   pub fn init(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
     self.cs.set_low()?;
@@ -87,13 +130,25 @@ where
   pub fn clear_display(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
     self.framebuffer.fill(0xFF);
     self.dirty_lines.fill(0); // Hardware clear, so no dirty lines
+    self.send_clear()
+  }
+
+  /// Clear the panel in hardware and rewrite it from the framebuffer
+  pub fn refresh(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+    self.send_clear()?;
+    self.mark_all_dirty();
+    self.flush_lines(HEIGHT)
+  }
 
+  /// Send the clear command, leaving the framebuffer as it is
+  fn send_clear(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
     self.cs.set_high()?;
     let mode = cmd::CLEAR | if self.vcom { cmd::VCOM } else { 0 };
     self.spi.write(&[mode, 0x00])?;
     self.cs.set_low()?;
 
     self.vcom = !self.vcom;
+    self.last_refresh = std::time::Instant::now();
     Ok(())
   }
 
@@ -113,6 +168,14 @@ where
     (self.dirty_lines[byte_idx] & (1 << bit_idx)) != 0
   }
 
+  /// Clear the dirty flag of a line
+  #[inline]
+  fn clear_dirty(&mut self, line: u16) {
+    let byte_idx = line as usize / 8;
+    let bit_idx = line % 8;
+    self.dirty_lines[byte_idx] &= !(1 << bit_idx);
+  }
+
   /// Mark all lines as dirty (for full refresh)
   pub fn mark_all_dirty(&mut self) {
     self.dirty_lines.fill(0xFF);
@@ -143,8 +206,26 @@ where
     Ok(())
   }
 
-  /// Write only dirty lines to the display
+  /// Write dirty lines to the display, at most the flush budget per call
+  ///
+  /// Does a full `refresh()` instead once the refresh interval is up.
   pub fn flush(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+    if !self.refresh_interval.is_zero() && self.last_refresh.elapsed() >= self.refresh_interval {
+      return self.refresh();
+    }
+    let budget = if self.flush_budget == 0 { HEIGHT } else { self.flush_budget };
+    self.flush_lines(budget)
+  }
+
+  /// Write all dirty lines to the display, ignoring the flush budget
+  ///
+  /// For one-off screens (boot, fatal error) that must appear at once.
+  pub fn flush_all(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+    self.flush_lines(HEIGHT)
+  }
+
+  /// Write up to `budget` dirty lines, starting at the flush cursor
+  fn flush_lines(&mut self, budget: u16) -> Result<(), esp_idf_svc::sys::EspError> {
     // Check if any lines are dirty
     let has_dirty = self.dirty_lines.iter().any(|&b| b != 0);
     if !has_dirty {
@@ -152,37 +233,46 @@ where
       return self.toggle_vcom();
     }
 
+    let started = std::time::Instant::now();
     self.cs.set_high()?;
 
     // Send mode byte
     let mode = cmd::WRITE | if self.vcom { cmd::VCOM } else { 0 };
     self.spi.write(&[mode])?;
 
-    // Send only dirty lines
-    for line in 0..HEIGHT {
-      if !self.is_dirty(line) {
-        continue;
+    // Send only dirty lines, wrapping around from the cursor
+    let mut sent = 0;
+    let mut line = self.flush_cursor;
+    for _ in 0..HEIGHT {
+      if sent >= budget {
+        break;
       }
 
-      let mut line_buf = [0u8; 1 + BYTES_PER_LINE + 1];
-      line_buf[0] = (line + 1) as u8; // Line address (1-indexed)
+      if self.is_dirty(line) {
+        let mut line_buf = [0u8; 1 + BYTES_PER_LINE + 1];
+        line_buf[0] = (line + 1) as u8; // Line address (1-indexed)
 
-      // Copy pixel data
-      let start = line as usize * BYTES_PER_LINE;
-      line_buf[1..1 + BYTES_PER_LINE].copy_from_slice(&self.framebuffer[start..start + BYTES_PER_LINE]);
+        // Copy pixel data
+        let start = line as usize * BYTES_PER_LINE;
+        line_buf[1..1 + BYTES_PER_LINE].copy_from_slice(&self.framebuffer[start..start + BYTES_PER_LINE]);
 
-      // Trailing dummy byte already 0
-      self.spi.write(&line_buf)?;
+        // Trailing dummy byte already 0
+        self.spi.write(&line_buf)?;
+        self.clear_dirty(line);
+        sent += 1;
+      }
+
+      line = (line + 1) % HEIGHT;
     }
+    self.flush_cursor = line;
 
     // Final dummy byte
     self.spi.write(&[0x00])?;
 
     self.cs.set_low()?;
     self.vcom = !self.vcom;
-
-    // Clear dirty flags
-    self.dirty_lines.fill(0);
+    // Mode byte, address + data + dummy per line, final dummy
+    self.stats.record(sent, 2 + sent as usize * (BYTES_PER_LINE + 2), started.elapsed());
     Ok(())
   }
 
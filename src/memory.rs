@@ -0,0 +1,131 @@
+//! Graceful degradation under heap pressure
+//!
+//! The ESP32 has no swap and little heap once Wi-Fi/Ethernet, TLS and the
+//! HTTP server are up. Rather than letting a leaking or memory-hungry feature
+//! run the allocator dry and crash the controller, the main loop watches free
+//! heap and sheds optional work while it is low:
+//!
+//! - history samples are taken less often
+//! - the display is redrawn at a lower frame rate
+//!
+//! Two thresholds give hysteresis, so the mode doesn't flap around a single
+//! value. Both transitions are logged.
+
+use std::time::Duration;
+
+use log::*;
+
+/// Enter degraded mode below this much free heap
+pub const LOW_HEAP_BYTES: u32 = 32 * 1024;
+/// Leave degraded mode once free heap is back above this
+pub const RECOVER_HEAP_BYTES: u32 = 48 * 1024;
+
+/// History interval multiplier while degraded
+const HISTORY_INTERVAL_FACTOR: u32 = 4;
+/// Display frame interval in normal operation
+const FRAME_INTERVAL: Duration = Duration::from_millis(200);
+/// Display frame interval while degraded
+const DEGRADED_FRAME_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Current free heap in bytes
+#[cfg(target_os = "espidf")]
+pub fn free_heap() -> u32 {
+    unsafe { esp_idf_svc::sys::esp_get_free_heap_size() }
+}
+
+/// Tracks heap pressure and the resulting resource budget
+#[derive(Debug, Clone)]
+pub struct MemoryGuard {
+    low: u32,
+    recover: u32,
+    degraded: bool,
+    /// Lowest free heap observed
+    min_free: u32,
+}
+
+impl Default for MemoryGuard {
+    fn default() -> Self {
+        Self::new(LOW_HEAP_BYTES, RECOVER_HEAP_BYTES)
+    }
+}
+
+impl MemoryGuard {
+    pub fn new(low: u32, recover: u32) -> Self {
+        Self {
+            low,
+            recover: recover.max(low),
+            degraded: false,
+            min_free: u32::MAX,
+        }
+    }
+
+    /// Whether optional work is currently being shed
+    pub fn degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Lowest free heap seen so far
+    pub fn min_free(&self) -> u32 {
+        self.min_free
+    }
+
+    /// Feed the current free heap; returns whether degraded mode is active
+    pub fn update(&mut self, free: u32) -> bool {
+        self.min_free = self.min_free.min(free);
+        if !self.degraded && free < self.low {
+            self.degraded = true;
+            warn!(
+                "Memory: free heap {} B below {} B, reducing history and display rates",
+                free, self.low
+            );
+        } else if self.degraded && free >= self.recover {
+            self.degraded = false;
+            info!("Memory: free heap recovered to {} B, restoring normal rates", free);
+        }
+        self.degraded
+    }
+
+    /// History sampling interval given the normal one
+    pub fn history_interval(&self, normal: Duration) -> Duration {
+        if self.degraded {
+            normal * HISTORY_INTERVAL_FACTOR
+        } else {
+            normal
+        }
+    }
+
+    /// Minimum time between display redraws
+    pub fn frame_interval(&self) -> Duration {
+        if self.degraded {
+            DEGRADED_FRAME_INTERVAL
+        } else {
+            FRAME_INTERVAL
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hysteresis() {
+        let mut guard = MemoryGuard::new(1000, 2000);
+        assert!(!guard.update(1500));
+        assert!(guard.update(999));
+        // Still degraded between the thresholds
+        assert!(guard.update(1500));
+        assert!(!guard.update(2000));
+        assert_eq!(guard.min_free(), 999);
+    }
+
+    #[test]
+    fn test_degraded_budget() {
+        let normal = Duration::from_secs(300);
+        let mut guard = MemoryGuard::new(1000, 2000);
+        assert_eq!(guard.history_interval(normal), normal);
+        guard.update(500);
+        assert_eq!(guard.history_interval(normal), Duration::from_secs(1200));
+        assert!(guard.frame_interval() > FRAME_INTERVAL);
+    }
+}
@@ -0,0 +1,83 @@
+//! Network identity and mDNS advertisement
+//!
+//! The unit asks DHCP for the hostname `watercontroller` and, once the network
+//! is up, answers to `watercontroller.local` over mDNS, so the setup page is at
+//! http://watercontroller.local/ without looking through the router's leases.
+//! It also advertises two services:
+//!
+//! - `_http._tcp`: the web server, for browsers and network scanners
+//! - `_mqtt-device._tcp`: the device itself, with the firmware version and
+//!   board revision as TXT records, so tools can tell units and firmware
+//!   apart
+//!
+//! A second unit on the same network gets a numbered name from the mDNS
+//! conflict resolution (`watercontroller-2.local`); the instance names carry
+//! the last MAC digits to tell them apart in a service browser.
+
+/// DHCP and mDNS hostname
+pub const HOSTNAME: &str = "watercontroller";
+
+/// Port of the web server
+pub const HTTP_PORT: u16 = 80;
+
+/// Last two MAC bytes in hex, to tell units apart (`WaterController-A1B2`)
+pub fn unit_suffix(mac: [u8; 6]) -> String {
+    format!("{:02X}{:02X}", mac[4], mac[5])
+}
+
+/// TXT records of the `_mqtt-device._tcp` service
+pub fn device_txt<'a>(version: &'a str, board: &'a str) -> [(&'static str, &'a str); 2] {
+    [("version", version), ("board", board)]
+}
+
+#[cfg(target_os = "espidf")]
+pub use advertise::Advertisement;
+
+#[cfg(target_os = "espidf")]
+mod advertise {
+    use esp_idf_svc::mdns::EspMdns;
+    use esp_idf_svc::sys::EspError;
+    use log::*;
+
+    use super::{device_txt, unit_suffix, HOSTNAME, HTTP_PORT};
+
+    /// mDNS responder; advertises for as long as it is kept
+    pub struct Advertisement {
+        _mdns: EspMdns,
+    }
+
+    impl Advertisement {
+        /// Register the hostname and services; call once the network is up
+        pub fn start(mac: [u8; 6], version: &str, board: &str) -> Result<Self, EspError> {
+            let mut mdns = EspMdns::take()?;
+            let instance = format!("Water Controller {}", unit_suffix(mac));
+            mdns.set_hostname(HOSTNAME)?;
+            mdns.set_instance_name(&instance)?;
+            mdns.add_service(Some(&instance), "_http", "_tcp", HTTP_PORT, &[("path", "/")])?;
+            mdns.add_service(
+                Some(&instance),
+                "_mqtt-device",
+                "_tcp",
+                HTTP_PORT,
+                &device_txt(version, board),
+            )?;
+            info!("mDNS: advertising http://{}.local/ as '{}'", HOSTNAME, instance);
+            Ok(Self { _mdns: mdns })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_suffix() {
+        assert_eq!(unit_suffix([0x24, 0x0a, 0xc4, 0x12, 0xa1, 0x0b]), "A10B");
+    }
+
+    #[test]
+    fn test_device_txt() {
+        assert_eq!(device_txt("0.4.0", "Rev B"), [("version", "0.4.0"), ("board", "Rev B")]);
+    }
+}
@@ -0,0 +1,383 @@
+//! Alarm notifications
+//!
+//! Alarm conditions become short text messages, published on the MQTT
+//! notification topic (an HA event entity) and, if a webhook URL is set,
+//! POSTed there as `{"event": "low_level", "message": "..."}`. The wording of
+//! each message comes from a template edited on the web UI (`/notify`), so
+//! messages can be reworded or translated without new firmware.
+//!
+//! Templates substitute `{name}` placeholders:
+//!
+//! - `{tank}`: tank name
+//! - `{level}`: tank level (%), `{gallons}`: tank volume
+//! - `{psi}`: line pressure
+//! - `{pump}`: pump number (pump events only)
+//! - `{time}`: local time as `HH:MM` (`--:--` until the clock is set)
+//!
+//! `{{` and `}}` stand for literal braces; unknown placeholders are left as
+//! they are. Templates are stored in NVS as `event=template` lines, and an
+//! event without one uses its built-in English text.
+
+#[cfg(target_os = "espidf")]
+use std::sync::mpsc::{self, Sender};
+#[cfg(target_os = "espidf")]
+use std::sync::{Arc, Mutex};
+#[cfg(target_os = "espidf")]
+use std::time::Duration;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::http::Method;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::sys::EspError;
+#[cfg(target_os = "espidf")]
+use log::*;
+
+use crate::clock::LocalTime;
+#[cfg(target_os = "espidf")]
+use crate::config::Config;
+use crate::json;
+
+/// Longest template (bytes, so all of them fit the NVS buffer)
+pub const MAX_TEMPLATE_LEN: usize = 160;
+#[cfg(target_os = "espidf")]
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Alarm transition a notification is sent for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Level fell to the low-level threshold
+    LowLevel,
+    /// Level recovered after a low-level alarm
+    LevelRestored,
+    /// A pump was marked failed
+    PumpFailed,
+    /// The dry-run guard stopped the pumps
+    DryRun,
+    /// The line pressure collapsed with the pumps off (see `pipe_burst`)
+    PipeBurst,
+    /// The day's water use went past the daily budget
+    BudgetExceeded,
+}
+
+impl Event {
+    pub const ALL: [Event; 6] =
+        [Event::LowLevel, Event::LevelRestored, Event::PumpFailed, Event::DryRun, Event::PipeBurst, Event::BudgetExceeded];
+
+    /// Name used in storage, webhook payloads and HA event types
+    pub fn key(self) -> &'static str {
+        match self {
+            Event::LowLevel => "low_level",
+            Event::LevelRestored => "level_restored",
+            Event::PumpFailed => "pump_failed",
+            Event::DryRun => "dry_run",
+            Event::PipeBurst => "pipe_burst",
+            Event::BudgetExceeded => "budget_exceeded",
+        }
+    }
+
+    /// Label on the web form
+    pub fn label(self) -> &'static str {
+        match self {
+            Event::LowLevel => "Low level",
+            Event::LevelRestored => "Level restored",
+            Event::PumpFailed => "Pump failed",
+            Event::DryRun => "Dry run",
+            Event::PipeBurst => "Pipe burst",
+            Event::BudgetExceeded => "Budget exceeded",
+        }
+    }
+
+    pub fn default_template(self) -> &'static str {
+        match self {
+            Event::LowLevel => "{tank} is low: {level}% ({gallons} gal) at {time}",
+            Event::LevelRestored => "{tank} is back to {level}% at {time}",
+            Event::PumpFailed => "{tank}: pump {pump} failed at {time}",
+            Event::DryRun => "{tank}: pumps stopped, running dry at {psi} PSI at {time}",
+            Event::PipeBurst => "{tank}: pipe burst? Pressure fell to {psi} PSI with the pumps off at {time}",
+            Event::BudgetExceeded => "{tank}: daily water budget used up at {time}",
+        }
+    }
+
+    /// Event named by `key`
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.key() == key)
+    }
+}
+
+/// Values substituted into a template
+#[derive(Debug, Clone, Default)]
+pub struct Vars {
+    pub tank: String,
+    pub level: u8,
+    pub gallons: u16,
+    pub psi: u16,
+    /// Pump number (from 1)
+    pub pump: Option<u8>,
+    pub time: Option<LocalTime>,
+}
+
+/// Fill in the placeholders of `template`
+pub fn render(template: &str, vars: &Vars) -> String {
+    let mut out = String::with_capacity(template.len() + 16);
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        let value = tail.strip_prefix('{').and_then(|t| t.split_once('}')).and_then(|(name, _)| {
+            let value = match name {
+                "tank" => vars.tank.clone(),
+                "level" => vars.level.to_string(),
+                "gallons" => vars.gallons.to_string(),
+                "psi" => vars.psi.to_string(),
+                "pump" => vars.pump.map(|p| p.to_string()).unwrap_or_default(),
+                "time" => match vars.time {
+                    Some(t) => format!("{:02}:{:02}", t.hour, t.minute),
+                    None => "--:--".to_string(),
+                },
+                _ => return None,
+            };
+            Some((value, name.len() + 2))
+        });
+        match value {
+            Some((value, len)) => {
+                out.push_str(&value);
+                rest = &tail[len..];
+            }
+            None => {
+                out.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Make a user supplied template safe to store as one line
+pub fn sanitize_template(template: &str) -> String {
+    let mut out = String::with_capacity(template.len().min(MAX_TEMPLATE_LEN));
+    for c in template.chars().filter(|c| !c.is_control()) {
+        if out.len() + c.len_utf8() > MAX_TEMPLATE_LEN {
+            break;
+        }
+        out.push(c);
+    }
+    out.trim().to_string()
+}
+
+/// Parse stored `event=template` lines, skipping unknown events and empty templates
+pub fn parse_templates(s: &str) -> Vec<(Event, String)> {
+    s.lines()
+        .filter_map(|line| {
+            let (key, template) = line.split_once('=')?;
+            let template = sanitize_template(template);
+            Some((Event::from_key(key.trim())?, template)).filter(|(_, t)| !t.is_empty())
+        })
+        .collect()
+}
+
+/// Format templates for storage (inverse of `parse_templates`)
+pub fn format_templates(templates: &[(Event, String)]) -> String {
+    templates
+        .iter()
+        .map(|(event, template)| format!("{}={}", event.key(), template))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Template for `event`, or its default if none is stored
+pub fn template(templates: &[(Event, String)], event: Event) -> &str {
+    match templates.iter().find(|(e, _)| *e == event) {
+        Some((_, template)) => template,
+        None => event.default_template(),
+    }
+}
+
+/// Alarm conditions, sampled every sensor update
+///
+/// The low-level and pipe burst conditions are those of `Alarms`, so a
+/// notification goes out whenever those alarms are raised or cleared.
+#[derive(Debug, Clone, Default)]
+pub struct Inputs<'a> {
+    /// Low-level alarm active
+    pub low_level: bool,
+    /// Latched failure of each pump
+    pub pump_failed: &'a [bool],
+    pub dry_run: bool,
+    /// Pipe burst alarm active
+    pub burst: bool,
+    /// Today's use is past the daily budget
+    pub over_budget: bool,
+}
+
+/// Turns alarm conditions into events on their transitions
+#[derive(Debug, Default)]
+pub struct AlarmMonitor {
+    low: bool,
+    pump_failed: Vec<bool>,
+    dry_run: bool,
+    burst: bool,
+    over_budget: bool,
+}
+
+impl AlarmMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Events since the last update, with the pump index for pump events
+    pub fn update(&mut self, inputs: &Inputs) -> Vec<(Event, Option<usize>)> {
+        let mut events = Vec::new();
+        if inputs.low_level != self.low {
+            events.push((if inputs.low_level { Event::LowLevel } else { Event::LevelRestored }, None));
+        }
+        self.low = inputs.low_level;
+
+        self.pump_failed.resize(inputs.pump_failed.len(), false);
+        for (pump, (was, &is)) in self.pump_failed.iter_mut().zip(inputs.pump_failed).enumerate() {
+            if is && !*was {
+                events.push((Event::PumpFailed, Some(pump)));
+            }
+            *was = is;
+        }
+        if inputs.dry_run && !self.dry_run {
+            events.push((Event::DryRun, None));
+        }
+        self.dry_run = inputs.dry_run;
+        if inputs.burst && !self.burst {
+            events.push((Event::PipeBurst, None));
+        }
+        self.burst = inputs.burst;
+        if inputs.over_budget && !self.over_budget {
+            events.push((Event::BudgetExceeded, None));
+        }
+        self.over_budget = inputs.over_budget;
+        events
+    }
+}
+
+/// A rendered alarm message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub event: Event,
+    pub message: String,
+}
+
+impl Notification {
+    /// Webhook and MQTT payload
+    pub fn to_json(&self) -> String {
+        format!(r#"{{"event":"{}","message":"{}"}}"#, self.event.key(), json::escape(&self.message))
+    }
+}
+
+/// Webhook sender
+///
+/// Requests run on a background thread, so a slow or unreachable endpoint
+/// never stalls the control loop. The URL is read from the configuration for
+/// each notification.
+#[cfg(target_os = "espidf")]
+pub struct Webhook {
+    tx: Sender<Notification>,
+}
+
+#[cfg(target_os = "espidf")]
+impl Webhook {
+    pub fn start(config: Arc<Mutex<Config>>) -> std::io::Result<Self> {
+        let (tx, rx) = mpsc::channel::<Notification>();
+        std::thread::Builder::new()
+            .name("webhook".into())
+            .stack_size(8 * 1024)
+            .spawn(move || {
+                for notification in rx {
+                    let url = config.lock().unwrap().webhook_url.clone();
+                    if url.is_empty() {
+                        continue;
+                    }
+                    match post(&url, &notification.to_json()) {
+                        Ok(status) if (200..300).contains(&status) => {
+                            debug!("Webhook: {} sent", notification.event.key());
+                        }
+                        Ok(status) => warn!("Webhook: {} returned HTTP {}", url, status),
+                        Err(e) => warn!("Webhook: {} failed: {:?}", url, e),
+                    }
+                }
+            })?;
+        Ok(Self { tx })
+    }
+
+    /// Queue a notification
+    pub fn send(&self, notification: Notification) {
+        if self.tx.send(notification).is_err() {
+            warn!("Webhook: sender thread has stopped");
+        }
+    }
+}
+
+/// POST a JSON body, returning the HTTP status
+#[cfg(target_os = "espidf")]
+fn post(url: &str, body: &str) -> Result<u16, EspError> {
+    let mut connection = EspHttpConnection::new(&Configuration {
+        timeout: Some(WEBHOOK_TIMEOUT),
+        // HTTPS endpoints are checked against the built-in CA bundle
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    let length = body.len().to_string();
+    let headers = [("Content-Type", "application/json"), ("Content-Length", length.as_str())];
+    connection.initiate_request(Method::Post, url, &headers)?;
+    connection.write_all(body.as_bytes())?;
+    connection.initiate_response()?;
+    Ok(connection.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_template() {
+        let vars = Vars {
+            tank: "Cistern".to_string(),
+            level: 12,
+            gallons: 60,
+            psi: 3,
+            pump: Some(2),
+            time: Some(LocalTime { weekday: 1, hour: 7, minute: 5 }),
+        };
+        assert_eq!(render(Event::LowLevel.default_template(), &vars), "Cistern is low: 12% (60 gal) at 07:05");
+        assert_eq!(render("Pumpe {pump}: Füllstand {level} % – {{ok}} {unknown} {level", &vars), "Pumpe 2: Füllstand 12 % – {ok} {unknown} {level");
+        assert_eq!(render("{time}", &Vars::default()), "--:--");
+
+        let templates = parse_templates("low_level=Niedrig: {level}%\nbogus=x\ndry_run=\n pump_failed = Pompe {pump}\u{7} ");
+        assert_eq!(templates, vec![(Event::LowLevel, "Niedrig: {level}%".to_string()), (Event::PumpFailed, "Pompe {pump}".to_string())]);
+        assert_eq!(parse_templates(&format_templates(&templates)), templates);
+        assert_eq!(template(&templates, Event::DryRun), Event::DryRun.default_template());
+        let message = Notification { event: Event::LowLevel, message: "\"low\"".to_string() };
+        assert_eq!(message.to_json(), r#"{"event":"low_level","message":"\"low\""}"#);
+    }
+
+    #[test]
+    fn test_alarm_transitions() {
+        let mut alarms = AlarmMonitor::new();
+        let inputs = |low_level, pump_failed, dry_run| Inputs { low_level, pump_failed, dry_run, ..Default::default() };
+        assert_eq!(alarms.update(&inputs(false, &[false, false], false)), vec![]);
+        assert_eq!(alarms.update(&inputs(true, &[false, false], false)), vec![(Event::LowLevel, None)]);
+        assert_eq!(alarms.update(&inputs(true, &[false, false], false)), vec![]);
+        assert_eq!(
+            alarms.update(&inputs(false, &[false, true], true)),
+            vec![(Event::LevelRestored, None), (Event::PumpFailed, Some(1)), (Event::DryRun, None)]
+        );
+        // Latched faults notify once
+        assert_eq!(alarms.update(&inputs(false, &[false, true], true)), vec![]);
+        let burst = Inputs { low_level: false, pump_failed: &[false, true], dry_run: true, burst: true, over_budget: true };
+        assert_eq!(alarms.update(&burst), vec![(Event::PipeBurst, None), (Event::BudgetExceeded, None)]);
+        assert_eq!(alarms.update(&burst), vec![]);
+    }
+}
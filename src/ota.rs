@@ -0,0 +1,409 @@
+//! Signed firmware updates
+//!
+//! Firmware is uploaded over HTTP to the admin-only `/ota` endpoint and
+//! streamed into the inactive OTA slot. Before the new slot is made bootable
+//! the upload has to pass two checks:
+//!
+//! - **Signature**: an ECDSA P-256 signature over the SHA-256 of the whole
+//!   image, sent base64 encoded in the `X-Signature` header. It is verified
+//!   against the public key embedded at build time from the PEM file named by
+//!   the `OTA_PUBLIC_KEY` environment variable. Firmware built without a key
+//!   rejects every update.
+//! - **Version**: the `"MAJOR.MINOR.PATCH"` version in the image's app
+//!   descriptor must not be older than the running firmware, so a leaked old
+//!   (validly signed) image with known bugs can't be installed again.
+//!
+//! ```text
+//! openssl dgst -sha256 -sign ota_private.pem -out fw.sig fw.bin
+//! curl -u admin:TOKEN -H "X-Signature: $(base64 -w0 fw.sig)" \
+//!     --data-binary @fw.bin http://watercontroller.local/ota
+//! ```
+//!
+//! The `/ota` page of the web UI uploads the image and its signature file the
+//! same way from a browser. The Home Assistant update entity instead hands
+//! over a URL: `Updater` downloads the signature from `<url>.sig` and then
+//! streams the image from `<url>`, over HTTP or HTTPS (checked against the
+//! ESP-IDF certificate bundle), through the same checks.
+//!
+//! # Rollback
+//! `partitions.csv` has two OTA slots, written alternately. An installed
+//! image boots on trial: unless it calls `mark_valid` once start-up is done,
+//! the bootloader goes back to the previous slot on the next reset.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::Write;
+use esp_idf_svc::ota::EspOta;
+use esp_idf_svc::sys;
+use log::*;
+
+use crate::config::Config;
+
+/// Signing public key (PEM), empty when built without `OTA_PUBLIC_KEY`
+const PUBLIC_KEY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/ota_public_key.pem"));
+
+/// First byte of an ESP32 app image
+const IMAGE_MAGIC: u8 = 0xE9;
+/// `esp_app_desc_t::magic_word`
+const APP_DESC_MAGIC: u32 = 0xABCD_5432;
+/// App descriptor offset: image header (24 bytes) + first segment header (8)
+const APP_DESC_OFFSET: usize = 32;
+/// `esp_app_desc_t::version` offset within the descriptor, and its size
+const VERSION_OFFSET: usize = 16;
+const VERSION_LEN: usize = 32;
+/// Bytes buffered before the version can be checked
+const HEAD_LEN: usize = APP_DESC_OFFSET + VERSION_OFFSET + VERSION_LEN;
+/// A DER encoded P-256 signature is at most 72 bytes
+const MAX_SIGNATURE_LEN: usize = 128;
+/// Per-read timeout of firmware downloads
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Firmware version (major, minor, patch)
+pub type Version = (u16, u16, u16);
+
+/// Parse `"1.2.3"`, ignoring a leading `v` and any `-suffix`/`+build`
+pub fn parse_version(s: &str) -> Option<Version> {
+    let s = s.trim().trim_start_matches('v');
+    let core = s.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u16>().ok());
+    let version = (parts.next()??, parts.next()??, parts.next()??);
+    match parts.next() {
+        None => Some(version),
+        Some(_) => None,
+    }
+}
+
+/// Version string from the app descriptor at the start of an image
+pub fn image_version(head: &[u8]) -> Result<&str, String> {
+    if head.len() < HEAD_LEN {
+        return Err("image too short".to_string());
+    }
+    if head[0] != IMAGE_MAGIC {
+        return Err("not an ESP32 app image".to_string());
+    }
+    let desc = &head[APP_DESC_OFFSET..];
+    if u32::from_le_bytes([desc[0], desc[1], desc[2], desc[3]]) != APP_DESC_MAGIC {
+        return Err("missing app descriptor".to_string());
+    }
+    let field = &desc[VERSION_OFFSET..VERSION_OFFSET + VERSION_LEN];
+    let len = field.iter().position(|&b| b == 0).unwrap_or(VERSION_LEN);
+    core::str::from_utf8(&field[..len]).map_err(|_| "invalid version string".to_string())
+}
+
+/// Anti-rollback check: the new image may not be older than the running one
+pub fn check_version(new: &str, running: &str) -> Result<Version, String> {
+    let new_version = parse_version(new).ok_or_else(|| format!("unparseable image version \"{}\"", new))?;
+    // Firmware without a parseable version can't be downgraded from
+    let Some(current) = parse_version(running) else {
+        return Ok(new_version);
+    };
+    if new_version < current {
+        return Err(format!("version {} is older than running {}", new, running));
+    }
+    Ok(new_version)
+}
+
+/// Decode standard base64 (padding optional)
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in input.trim().trim_end_matches('=').bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Keep the running image, so a reset no longer rolls back to the previous slot
+pub fn mark_valid() {
+    match EspOta::new().and_then(|mut ota| ota.mark_running_slot_valid()) {
+        Ok(()) => info!("OTA: running image marked valid"),
+        Err(e) => warn!("OTA: could not mark the running image valid: {}", e),
+    }
+}
+
+/// Labels of the running slot and of the slot the next update is written to
+pub fn slots() -> Result<(String, String), String> {
+    let ota = EspOta::new().map_err(|e| format!("OTA unavailable: {}", e))?;
+    let running = ota.get_running_slot().map_err(|e| format!("no running slot: {}", e))?;
+    let update = ota.get_update_slot().map_err(|e| format!("no update slot: {}", e))?;
+    Ok((running.label.to_string(), update.label.to_string()))
+}
+
+/// Whether the firmware was built with a signing key, i.e. can accept updates
+pub fn signing_enabled() -> bool {
+    !PUBLIC_KEY.is_empty()
+}
+
+/// Version of the running firmware
+fn running_version() -> String {
+    let desc = unsafe { &*sys::esp_app_get_description() };
+    let bytes: Vec<u8> = desc.version.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Streaming SHA-256
+struct Sha256(sys::mbedtls_sha256_context);
+
+impl Sha256 {
+    fn new() -> Self {
+        let mut ctx = unsafe { core::mem::zeroed() };
+        unsafe {
+            sys::mbedtls_sha256_init(&mut ctx);
+            sys::mbedtls_sha256_starts(&mut ctx, 0);
+        }
+        Self(ctx)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        unsafe { sys::mbedtls_sha256_update(&mut self.0, data.as_ptr(), data.len()) };
+    }
+
+    fn finish(mut self) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        unsafe { sys::mbedtls_sha256_finish(&mut self.0, hash.as_mut_ptr()) };
+        hash
+    }
+}
+
+impl Drop for Sha256 {
+    fn drop(&mut self) {
+        unsafe { sys::mbedtls_sha256_free(&mut self.0) };
+    }
+}
+
+/// Verify a DER encoded ECDSA signature over `hash` with the embedded key
+fn verify_signature(hash: &[u8; 32], signature: &[u8]) -> Result<(), String> {
+    if PUBLIC_KEY.is_empty() {
+        return Err("firmware built without an OTA public key".to_string());
+    }
+    // The PEM parser wants the terminating NUL included in the length
+    let mut pem = PUBLIC_KEY.to_vec();
+    pem.push(0);
+    unsafe {
+        let mut pk: sys::mbedtls_pk_context = core::mem::zeroed();
+        sys::mbedtls_pk_init(&mut pk);
+        let ret = sys::mbedtls_pk_parse_public_key(&mut pk, pem.as_ptr(), pem.len());
+        let result = if ret != 0 {
+            Err(format!("invalid OTA public key (-0x{:04x})", -ret))
+        } else if sys::mbedtls_pk_verify(
+            &mut pk,
+            sys::mbedtls_md_type_t_MBEDTLS_MD_SHA256,
+            hash.as_ptr(),
+            hash.len(),
+            signature.as_ptr(),
+            signature.len(),
+        ) != 0
+        {
+            Err("signature mismatch".to_string())
+        } else {
+            Ok(())
+        };
+        sys::mbedtls_pk_free(&mut pk);
+        result
+    }
+}
+
+/// Stream an image into the inactive slot and make it bootable if it passes
+/// the signature and version checks
+///
+/// `read` fills the buffer with the next part of the image and returns 0 at
+/// the end. Returns the installed version; the caller restarts into it.
+pub fn install<E: core::fmt::Debug>(
+    signature: &[u8],
+    mut read: impl FnMut(&mut [u8]) -> Result<usize, E>,
+) -> Result<String, String> {
+    let mut ota = EspOta::new().map_err(|e| format!("OTA unavailable: {}", e))?;
+    let mut update = ota.initiate_update().map_err(|e| format!("OTA start failed: {}", e))?;
+    let running = running_version();
+
+    let mut sha = Sha256::new();
+    let mut buf = vec![0u8; 4096];
+    let mut head = Vec::with_capacity(HEAD_LEN);
+    let mut version = None;
+    let mut total = 0;
+    let result = loop {
+        let n = match read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(e) => break Err(format!("upload read error: {:?}", e)),
+        };
+        let chunk = &buf[..n];
+        // Reject a downgrade before anything is written to flash
+        if version.is_none() {
+            let missing = HEAD_LEN - head.len();
+            head.extend_from_slice(&chunk[..n.min(missing)]);
+            if head.len() == HEAD_LEN {
+                match image_version(&head).and_then(|v| check_version(v, &running).map(|_| v.to_string())) {
+                    Ok(v) => version = Some(v),
+                    Err(e) => break Err(e),
+                }
+            }
+        }
+        sha.update(chunk);
+        if let Err(e) = update.write_all(chunk) {
+            break Err(format!("flash write failed: {:?}", e));
+        }
+        total += n;
+    };
+
+    let result = result
+        .and_then(|()| version.ok_or_else(|| "image too short".to_string()))
+        .and_then(|v| verify_signature(&sha.finish(), signature).map(|()| v));
+    match result {
+        Ok(version) => {
+            update.complete().map_err(|e| format!("OTA finish failed: {}", e))?;
+            info!("OTA: installed {} ({} bytes), was {}", version, total, running);
+            Ok(version)
+        }
+        Err(e) => {
+            warn!("OTA: rejected image after {} bytes: {}", total, e);
+            let _ = update.abort();
+            Err(e)
+        }
+    }
+}
+
+/// GET `url`, failing unless it answers 200
+fn download(url: &str) -> Result<EspHttpConnection, String> {
+    let mut connection = EspHttpConnection::new(&Configuration {
+        timeout: Some(DOWNLOAD_TIMEOUT),
+        crt_bundle_attach: Some(sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })
+    .map_err(|e| format!("HTTP client failed: {}", e))?;
+    connection.initiate_request(Method::Get, url, &[]).map_err(|e| format!("{}: {}", url, e))?;
+    connection.initiate_response().map_err(|e| format!("{}: {}", url, e))?;
+    match connection.status() {
+        200 => Ok(connection),
+        status => Err(format!("{} returned HTTP {}", url, status)),
+    }
+}
+
+/// Download the signature from `<url>.sig`, then stream the image from `url` into the inactive slot
+pub fn install_from_url(url: &str) -> Result<String, String> {
+    let sig_url = format!("{}.sig", url);
+    let mut connection = download(&sig_url)?;
+    let mut signature = vec![0u8; MAX_SIGNATURE_LEN];
+    let mut len = 0;
+    loop {
+        match connection.read(&mut signature[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) => return Err(format!("{}: {}", sig_url, e)),
+        }
+        if len == MAX_SIGNATURE_LEN {
+            return Err(format!("{} is not a signature", sig_url));
+        }
+    }
+    signature.truncate(len);
+
+    let mut connection = download(url)?;
+    info!("OTA: downloading {}", url);
+    install(&signature, |buf| connection.read(buf))
+}
+
+/// Installs firmware from a URL on a background thread and restarts into it
+#[derive(Clone)]
+pub struct Updater {
+    busy: Arc<AtomicBool>,
+    config: Arc<Mutex<Config>>,
+}
+
+impl Updater {
+    pub fn new(config: Arc<Mutex<Config>>) -> Self {
+        Self { busy: Arc::new(AtomicBool::new(false)), config }
+    }
+
+    /// Start installing the image at `url`; `false` if an update is already running
+    pub fn start(&self, url: String) -> bool {
+        if self.busy.swap(true, Ordering::SeqCst) {
+            warn!("OTA: update already in progress, ignoring {}", url);
+            return false;
+        }
+        let updater = self.clone();
+        let spawned = std::thread::Builder::new()
+            .name("ota".into())
+            .stack_size(12 * 1024)
+            .spawn(move || match install_from_url(&url) {
+                Ok(version) => {
+                    info!("OTA: restarting into {}", version);
+                    updater.config.lock().unwrap().flush();
+                    unsafe { sys::esp_restart() };
+                }
+                Err(e) => {
+                    warn!("OTA: update from {} failed: {}", url, e);
+                    updater.busy.store(false, Ordering::SeqCst);
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("OTA: could not start update thread: {}", e);
+            self.busy.store(false, Ordering::SeqCst);
+            return false;
+        }
+        true
+    }
+
+    /// Whether an update is being downloaded and written
+    pub fn in_progress(&self) -> bool {
+        self.busy.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(version: &str) -> Vec<u8> {
+        let mut img = vec![0u8; 256];
+        img[0] = IMAGE_MAGIC;
+        img[APP_DESC_OFFSET..APP_DESC_OFFSET + 4].copy_from_slice(&APP_DESC_MAGIC.to_le_bytes());
+        let at = APP_DESC_OFFSET + VERSION_OFFSET;
+        img[at..at + version.len()].copy_from_slice(version.as_bytes());
+        img
+    }
+
+    #[test]
+    fn test_image_version() {
+        assert_eq!(image_version(&image("0.2.1")), Ok("0.2.1"));
+        let mut bad = image("0.2.1");
+        bad[0] = 0;
+        assert!(image_version(&bad).is_err());
+        assert!(image_version(&image("0.2.1")[..40]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_rollback() {
+        assert_eq!(parse_version("v1.10.2-rc1"), Some((1, 10, 2)));
+        assert_eq!(parse_version("1.2"), None);
+        assert_eq!(parse_version("1.2.3.4"), None);
+        assert_eq!(check_version("0.2.0", "0.1.9"), Ok((0, 2, 0)));
+        assert_eq!(check_version("0.1.0", "0.1.0"), Ok((0, 1, 0)));
+        assert!(check_version("0.1.0", "0.10.0").unwrap_err().contains("older"));
+        assert!(check_version("dev", "0.1.0").is_err());
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(base64_decode("YWRtaW46c2VjcmV0").unwrap(), b"admin:secret");
+        assert_eq!(base64_decode("MEUCIQ==").unwrap(), [0x30, 0x45, 0x02, 0x21]);
+        assert!(base64_decode("not base64!").is_none());
+    }
+}
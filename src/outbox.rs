@@ -0,0 +1,229 @@
+//! Paced publishing of retained MQTT messages
+//!
+//! Discovery is well over a hundred retained configs, and ESP-MQTT queues
+//! every publish in its outbox until the broker acknowledges it. Sent all at
+//! once over a slow link they overflow the outbox, and the configs that
+//! don't fit are lost. The `Outbox` holds them instead and hands them out
+//! one at a time, `PACE` apart and at most `WINDOW` unacknowledged.
+//!
+//! Each message is tracked by the message ID the client returns for it,
+//! until the client reports it published (the broker's PUBACK) or deleted
+//! (expired from its outbox). A message that fails to queue, is deleted or
+//! isn't acknowledged within `ACK_TIMEOUT` is sent again, up to
+//! `MAX_ATTEMPTS` times; after that it is given up and counted in
+//! `abandoned()`.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Time between two messages
+pub const PACE: Duration = Duration::from_millis(50);
+/// Most messages waiting for their acknowledgement
+pub const WINDOW: usize = 4;
+/// Wait for an acknowledgement before sending again
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Tries per message
+pub const MAX_ATTEMPTS: u8 = 3;
+
+/// Outcome of a publish, as reported by the client's events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Acknowledged by the broker
+    Published(u32),
+    /// Dropped from the client's outbox without an acknowledgement
+    Deleted(u32),
+}
+
+/// A message to publish retained
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outgoing {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// Tries so far
+    attempts: u8,
+}
+
+#[derive(Debug)]
+struct InFlight {
+    id: u32,
+    sent_at: Duration,
+    message: Outgoing,
+}
+
+/// Queue of retained messages published at a limited rate
+#[derive(Debug, Default)]
+pub struct Outbox {
+    queue: VecDeque<Outgoing>,
+    in_flight: Vec<InFlight>,
+    last_sent: Option<Duration>,
+    abandoned: u32,
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a message behind the ones already waiting
+    pub fn push(&mut self, topic: impl Into<String>, payload: &[u8]) {
+        self.queue.push_back(Outgoing { topic: topic.into(), payload: payload.to_vec(), attempts: 0 });
+    }
+
+    /// Next message to publish at `now`, if the pace and window allow one
+    ///
+    /// Report the result with `sent` or `failed`.
+    pub fn next(&mut self, now: Duration) -> Option<Outgoing> {
+        self.expire(now);
+        if self.in_flight.len() >= WINDOW || self.last_sent.is_some_and(|t| now.saturating_sub(t) < PACE) {
+            return None;
+        }
+        let mut message = self.queue.pop_front()?;
+        message.attempts += 1;
+        self.last_sent = Some(now);
+        Some(message)
+    }
+
+    /// `message` was queued by the client under `id`
+    pub fn sent(&mut self, message: Outgoing, id: u32, now: Duration) {
+        self.in_flight.push(InFlight { id, sent_at: now, message });
+    }
+
+    /// `message` couldn't be queued by the client
+    pub fn failed(&mut self, message: Outgoing) {
+        self.retry(message);
+    }
+
+    /// Apply an event from the client; IDs of other messages are ignored
+    pub fn report(&mut self, delivery: Delivery) {
+        let (Delivery::Published(id) | Delivery::Deleted(id)) = delivery;
+        let Some(index) = self.in_flight.iter().position(|m| m.id == id) else {
+            return;
+        };
+        let in_flight = self.in_flight.remove(index);
+        if let Delivery::Deleted(_) = delivery {
+            self.retry(in_flight.message);
+        }
+    }
+
+    /// Nothing queued or waiting for an acknowledgement
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// Messages queued or waiting for an acknowledgement
+    pub fn len(&self) -> usize {
+        self.queue.len() + self.in_flight.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Messages given up after `MAX_ATTEMPTS` since the last `clear`
+    pub fn abandoned(&self) -> u32 {
+        self.abandoned
+    }
+
+    /// Drop everything, for a new connection that sends it all again
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.in_flight.clear();
+        self.abandoned = 0;
+    }
+
+    /// Send again the messages not acknowledged in time
+    fn expire(&mut self, now: Duration) {
+        let mut index = 0;
+        while index < self.in_flight.len() {
+            if now.saturating_sub(self.in_flight[index].sent_at) >= ACK_TIMEOUT {
+                let in_flight = self.in_flight.remove(index);
+                self.retry(in_flight.message);
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    /// Back to the front of the queue, unless out of attempts
+    fn retry(&mut self, message: Outgoing) {
+        if message.attempts >= MAX_ATTEMPTS {
+            self.abandoned += 1;
+            return;
+        }
+        self.queue.push_front(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_paced_within_window() {
+        let mut outbox = Outbox::new();
+        for i in 0..6 {
+            outbox.push(format!("t/{i}"), b"{}");
+        }
+        let first = outbox.next(ms(0)).unwrap();
+        assert_eq!(first.topic, "t/0");
+        // Not before the pace has passed
+        assert_eq!(outbox.next(ms(10)), None);
+        outbox.sent(first, 1, ms(0));
+        for id in 2..=4 {
+            let message = outbox.next(ms(id as u64 * 50)).unwrap();
+            outbox.sent(message, id, ms(id as u64 * 50));
+        }
+        // Window full until an acknowledgement comes in
+        assert_eq!(outbox.next(ms(1000)), None);
+        outbox.report(Delivery::Published(99));
+        assert_eq!(outbox.next(ms(1000)), None);
+        outbox.report(Delivery::Published(2));
+        let message = outbox.next(ms(1000)).unwrap();
+        assert_eq!(message.topic, "t/4");
+        outbox.sent(message, 5, ms(1000));
+        for id in [1, 3, 4, 5] {
+            outbox.report(Delivery::Published(id));
+        }
+        assert_eq!(outbox.len(), 1);
+        let message = outbox.next(ms(1050)).unwrap();
+        outbox.sent(message, 6, ms(1050));
+        outbox.report(Delivery::Published(6));
+        assert!(outbox.is_idle());
+        assert_eq!(outbox.abandoned(), 0);
+    }
+
+    #[test]
+    fn test_retries_lost_messages() {
+        let mut outbox = Outbox::new();
+        outbox.push("t/a", b"a");
+        outbox.push("t/b", b"b");
+        // Not queued by the client: first again
+        let a = outbox.next(ms(0)).unwrap();
+        outbox.failed(a);
+        let a = outbox.next(ms(50)).unwrap();
+        assert_eq!(a.topic, "t/a");
+        outbox.sent(a, 1, ms(50));
+        // Deleted from the client's outbox
+        outbox.report(Delivery::Deleted(1));
+        let a = outbox.next(ms(100)).unwrap();
+        assert_eq!(a.topic, "t/a");
+        outbox.sent(a, 2, ms(100));
+        let b = outbox.next(ms(150)).unwrap();
+        outbox.sent(b, 3, ms(150));
+        outbox.report(Delivery::Published(3));
+        // Third try of `t/a` never acknowledged: given up
+        assert_eq!(outbox.next(ms(5000)), None);
+        assert_eq!(outbox.next(ms(10_100)), None);
+        assert!(outbox.is_idle());
+        assert_eq!(outbox.abandoned(), 1);
+
+        outbox.push("t/c", b"c");
+        outbox.clear();
+        assert!(outbox.is_empty());
+        assert_eq!(outbox.abandoned(), 0);
+    }
+}
@@ -0,0 +1,905 @@
+//! Home Assistant MQTT payloads
+//!
+//! Discovery configs and the state document for `homeassistant`, serialized
+//! with serde_json. Discovery fields use the abbreviated keys Home Assistant
+//! accepts (`stat_t`, `val_tpl`, ...) and unset ones are left out, so an
+//! entity only spells out what it needs:
+//!
+//! ```ignore
+//! Discovery {
+//!     name: "Water Pressure".into(),
+//!     unique_id: "wc_pressure".into(),
+//!     state_topic: Some(STATE_TOPIC),
+//!     value_template: Some(value_template("pressure_psi")),
+//!     unit: Some("psi"),
+//!     ..Default::default()
+//! }
+//! ```
+
+use std::net::Ipv4Addr;
+
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+
+#[cfg(feature = "lora")]
+use crate::alarms::Alarm;
+use crate::build_info;
+#[cfg(feature = "lora")]
+use crate::codec::StateFrame;
+use crate::diag::Diagnostics;
+#[cfg(feature = "hammer")]
+use crate::hammer::{HammerEvent, SAMPLE_INTERVAL};
+#[cfg(feature = "notify")]
+use crate::notify::Notification;
+#[cfg(feature = "valve")]
+use crate::valve::ValveState;
+
+/// Device block shared by all discovery payloads
+#[derive(Debug, Clone, Serialize)]
+pub struct Device {
+    pub ids: &'static str,
+    pub name: String,
+    pub mf: &'static str,
+    pub mdl: &'static str,
+    /// Area Home Assistant puts the device in when it is first discovered
+    #[serde(rename = "sa", skip_serializing_if = "Option::is_none")]
+    pub suggested_area: Option<String>,
+}
+
+/// Device name until one is configured
+pub const DEFAULT_DEVICE_NAME: &str = "Water Controller";
+
+impl Default for Device {
+    /// This unit
+    fn default() -> Self {
+        Self { ids: "watercontroller", name: DEFAULT_DEVICE_NAME.into(), mf: "DIY", mdl: "wESP32", suggested_area: None }
+    }
+}
+
+/// How the unit and its entities are named in Home Assistant
+///
+/// Tells several controllers in one household apart: the device name and
+/// area are set on every entity's device block, and the prefix is put in
+/// front of the entity names ("Barn Water Capacity"). The tank label goes
+/// between the prefix and the names of the tank's own entities ("Barn
+/// CISTERN Water Capacity", see `tank_entity`). Empty values keep the
+/// defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Naming {
+    pub device_name: String,
+    pub area: String,
+    pub prefix: String,
+    pub tank: String,
+}
+
+impl Naming {
+    /// Name of one of the tank's entities, after the tank label
+    pub fn tank_entity(&self, name: &str) -> String {
+        match self.tank.trim() {
+            "" => name.to_string(),
+            tank => format!("{} {}", tank, name),
+        }
+    }
+
+    /// Name the device and entity of a discovery config
+    pub fn apply(&self, config: &mut Discovery) {
+        if !self.device_name.trim().is_empty() {
+            config.device.name = self.device_name.trim().to_string();
+        }
+        if !self.area.trim().is_empty() {
+            config.device.suggested_area = Some(self.area.trim().to_string());
+        }
+        if !self.prefix.trim().is_empty() {
+            config.name = format!("{} {}", self.prefix.trim(), config.name);
+        }
+    }
+}
+
+/// Discovery config of one entity
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Discovery<'a> {
+    pub name: String,
+    #[serde(rename = "uniq_id")]
+    pub unique_id: String,
+    #[serde(rename = "stat_t", skip_serializing_if = "Option::is_none")]
+    pub state_topic: Option<&'a str>,
+    #[serde(rename = "val_tpl", skip_serializing_if = "Option::is_none")]
+    pub value_template: Option<String>,
+    #[serde(rename = "cmd_t", skip_serializing_if = "Option::is_none")]
+    pub command_topic: Option<String>,
+    /// Number range; numbers are shown as a box rather than a slider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<&'static str>,
+    #[serde(rename = "unit_of_meas", skip_serializing_if = "Option::is_none")]
+    pub unit: Option<&'a str>,
+    #[serde(rename = "dev_cla", skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<&'static str>,
+    #[serde(rename = "stat_cla", skip_serializing_if = "Option::is_none")]
+    pub state_class: Option<&'static str>,
+    #[serde(rename = "ic", skip_serializing_if = "Option::is_none")]
+    pub icon: Option<&'a str>,
+    #[serde(rename = "ent_cat", skip_serializing_if = "Option::is_none")]
+    pub entity_category: Option<&'static str>,
+    /// Event entity types
+    #[serde(rename = "evt_typ", skip_serializing_if = "Vec::is_empty")]
+    pub event_types: Vec<&'static str>,
+    /// Seconds without an update before the sensor shows unavailable
+    #[serde(rename = "exp_aft", skip_serializing_if = "Option::is_none")]
+    pub expire_after: Option<u32>,
+    /// Extra attributes from a JSON document
+    #[serde(rename = "json_attr_t", skip_serializing_if = "Option::is_none")]
+    pub json_attributes_topic: Option<&'a str>,
+    #[serde(rename = "json_attr_tpl", skip_serializing_if = "Option::is_none")]
+    pub json_attributes_template: Option<String>,
+    /// Valve commands
+    #[serde(rename = "pl_open", skip_serializing_if = "Option::is_none")]
+    pub payload_open: Option<&'static str>,
+    #[serde(rename = "pl_cls", skip_serializing_if = "Option::is_none")]
+    pub payload_close: Option<&'static str>,
+    #[serde(rename = "pl_stop", skip_serializing_if = "Option::is_none")]
+    pub payload_stop: Option<&'static str>,
+    /// Update entity: where the latest version comes from, and the install command
+    #[serde(rename = "l_ver_t", skip_serializing_if = "Option::is_none")]
+    pub latest_version_topic: Option<&'a str>,
+    #[serde(rename = "l_ver_tpl", skip_serializing_if = "Option::is_none")]
+    pub latest_version_template: Option<String>,
+    #[serde(rename = "pl_inst", skip_serializing_if = "Option::is_none")]
+    pub payload_install: Option<&'static str>,
+    #[serde(rename = "dev")]
+    pub device: Device,
+}
+
+impl Discovery<'_> {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("discovery config serializes")
+    }
+}
+
+/// Entity dropped from discovery, or moved to a new topic or unique id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retired {
+    /// Discovery revision that retired it
+    pub revision: u16,
+    /// `sensor`, `number`, ...
+    pub component: &'static str,
+    /// Object id in the old discovery topic (without the device prefix)
+    pub object_id: &'static str,
+}
+
+/// Entities retired after revision `cleared`, whose old configs may still be retained
+pub fn retired_since(retired: &'static [Retired], cleared: u16) -> impl Iterator<Item = &'static Retired> {
+    retired.iter().filter(move |r| r.revision > cleared)
+}
+
+/// Template reading `key` from a JSON state payload
+pub fn value_template(key: &str) -> String {
+    format!("{{{{ value_json.{} }}}}", key)
+}
+
+/// Template mapping a boolean `key` to the `ON`/`OFF` of a binary sensor
+pub fn on_off_template(key: &str) -> String {
+    format!("{{{{ 'ON' if value_json.{} else 'OFF' }}}}", key)
+}
+
+/// Per-pump part of the state
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PumpState {
+    /// Relay state
+    pub running: bool,
+    /// Run time since boot (minutes)
+    pub runtime_min: u32,
+    /// Starts since boot
+    pub starts: u32,
+    /// Latched failure
+    pub failed: bool,
+}
+
+/// Pump fields of the state as `pump1_on`, `pump1_runtime_min`,
+/// `pump1_starts`, `pump1_failed`, `pump2_on`, ...
+fn serialize_pumps<S: Serializer>(pumps: &[PumpState; 2], serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(pumps.len() * 4))?;
+    for (i, pump) in pumps.iter().enumerate() {
+        let n = i + 1;
+        map.serialize_entry(&format!("pump{n}_on"), &pump.running)?;
+        map.serialize_entry(&format!("pump{n}_runtime_min"), &pump.runtime_min)?;
+        map.serialize_entry(&format!("pump{n}_starts"), &pump.starts)?;
+        map.serialize_entry(&format!("pump{n}_failed"), &pump.failed)?;
+    }
+    map.end()
+}
+
+/// Distances of the last radar reading: the sensor's filtered and real-time
+/// registers next to the firmware's own smoothing, for comparing the two
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct RadarDistances {
+    /// Empty height, filtered by the sensor (mm)
+    pub radar_empty_mm: u16,
+    /// Empty height, real-time (mm)
+    pub radar_empty_raw_mm: u16,
+    /// Water level, filtered by the sensor (mm)
+    pub radar_level_mm: u16,
+    /// Water level, real-time (mm)
+    pub radar_level_raw_mm: u16,
+    /// Sensor's filtered empty height after the firmware's median and smoothing (mm)
+    pub radar_smoothed_mm: u16,
+}
+
+/// Sensor state to publish on `watercontroller/state`
+#[derive(Debug, Default, Serialize)]
+pub struct WaterState {
+    /// Tank capacity percentage (0-100)
+    #[serde(rename = "capacity_pct")]
+    pub capacity_percent: u8,
+    /// Tank capacity in gallons
+    #[serde(rename = "gallons")]
+    pub capacity_gallons: u16,
+    /// Water pressure in PSI
+    pub pressure_psi: u16,
+    /// Pressure lost across the whole-house filter (PSI), left out without
+    /// the post-filter sensor and while it isn't trusted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter_drop_psi: Option<f32>,
+    /// Flow meter rate (GPM) and running total (gal), left out without the
+    /// flow meter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow_gpm: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow_total_gal: Option<f64>,
+    /// Configured tank capacity (gallons)
+    pub tank_capacity: u16,
+    /// Configured sensor height (feet)
+    pub sensor_height: u16,
+    /// Configured manometer max PSI
+    pub max_psi: u16,
+    /// Configured radar installation height (cm)
+    pub radar_height: u16,
+    /// Configured radar deadzone (cm) — distance from sensor to max water level
+    pub radar_deadzone: u16,
+    /// Configured display lines per flush (0 = unlimited)
+    pub flush_lines: u16,
+    /// Configured minutes between full display refreshes (0 = off)
+    pub display_refresh: u16,
+    /// Configured tank fill pattern (0 = solid, 1 = hatched, 2 = dithered)
+    pub tank_fill: u16,
+    /// Configured maintenance reboot day (0 = disabled, 1-7 = Monday-Sunday, 8 = daily)
+    pub reboot_day: u16,
+    /// Configured maintenance reboot hour
+    pub reboot_hour: u16,
+    /// Configured pump start level (%)
+    pub pump_start: u16,
+    /// Configured pump stop level (%)
+    pub pump_stop: u16,
+    /// Configured lag assist drop (%)
+    pub pump_assist: u16,
+    /// Configured pump failure timeout (minutes)
+    pub pump_fail_min: u16,
+    /// Pump relays, run times, starts and failures
+    #[serde(flatten, serialize_with = "serialize_pumps")]
+    pub pumps: [PumpState; 2],
+    /// Latched dry-run fault
+    pub pump_dry_run: bool,
+    /// Configured constant-pressure setpoint (PSI)
+    pub vfd_setpoint: u16,
+    /// Configured PID gains (thousandths)
+    pub vfd_kp: u16,
+    pub vfd_ki: u16,
+    pub vfd_kd: u16,
+    /// Commanded VFD speed (%)
+    pub vfd_speed: u8,
+    /// Configured tank shape (0 = vertical, 1 = horizontal cylinder)
+    pub tank_shape: u16,
+    /// Configured radar warm-up (seconds)
+    pub radar_warmup: u16,
+    /// Configured pressure sensor warm-up (seconds)
+    pub psi_warmup: u16,
+    /// Configured radar heater mode (0 = off, 1 = auto, 2 = duty cycle)
+    pub heater_mode: u16,
+    /// Configured heater dew-point spread (°C)
+    pub heater_spread: u16,
+    /// Configured heater duty cycle (%)
+    pub heater_duty: u16,
+    /// Radar heater output state
+    pub heater_on: bool,
+    /// Well pump running, inferred from the pressure rise (see `well_pump`)
+    pub well_pump_on: bool,
+    /// Inferred well pump run time since boot (minutes)
+    pub well_pump_runtime_min: u32,
+    /// Inferred well pump starts since boot
+    pub well_pump_starts: u32,
+    /// Gallons drawn from the tank since local midnight
+    pub used_today: u32,
+    /// Gallons added to the tank since local midnight
+    pub refilled_today: u32,
+    /// Configured daily water budget (gal, 0 = none)
+    pub budget_gal: u16,
+    /// Gallons used today as a percentage of the budget (`null` without one)
+    pub budget_pct: Option<u32>,
+    /// Today's use has gone past the budget
+    pub budget_exceeded: bool,
+    /// Configured flow meter K-factor (pulses per gallon)
+    pub flow_k_factor: u16,
+    /// Configured level median window (readings)
+    pub level_median: u16,
+    /// Configured level smoothing factor (%)
+    pub level_alpha: u16,
+    /// Configured display page interval (s)
+    pub page_interval: u16,
+    /// Configured minimum pump on time (s)
+    pub pump_min_on: u16,
+    /// Configured minimum pump off time (s)
+    pub pump_min_off: u16,
+    /// Configured dry-run pressure threshold (PSI)
+    pub pump_dry_psi: u16,
+    /// Configured dry-run delay (s)
+    pub pump_dry_secs: u16,
+    /// Configured supply valve travel time (s)
+    pub valve_travel: u16,
+    /// Configured water hammer threshold (PSI)
+    pub hammer_psi: u16,
+    /// Configured pipe burst pressure drop (PSI, 0 = off)
+    pub burst_psi: u16,
+    /// Configured pump efficiency drop alert (%)
+    pub efficiency_drop: u16,
+    /// Configured low-level alarm threshold (%)
+    pub alarm_low: u16,
+    /// Configured high-pressure alarm threshold (PSI)
+    pub alarm_high_psi: u16,
+    /// Configured clogged-filter alarm threshold (PSI, 0 = off)
+    pub alarm_clog_psi: u16,
+    /// Configured sensor fault delay (s)
+    pub alarm_fault_secs: u16,
+    /// Active alarms (see `alarms`)
+    pub alarm_low_level: bool,
+    pub alarm_high_pressure: bool,
+    pub alarm_sensor_fault: bool,
+    pub alarm_pipe_burst: bool,
+    pub alarm_filter_clogged: bool,
+    /// Radar distances, left out until the first reading and without the radar
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub radar: Option<RadarDistances>,
+    /// Local time of the sample, ISO 8601 with offset; left out until SNTP
+    /// has set the clock. Queued samples keep the time they were taken.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+}
+
+impl WaterState {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("state serializes")
+    }
+
+    /// Configured value for a number topic suffix
+    pub fn setting(&self, key: &str) -> Option<u16> {
+        Some(match key {
+            "tank_capacity" => self.tank_capacity,
+            "sensor_height" => self.sensor_height,
+            "max_psi" => self.max_psi,
+            "radar_height" => self.radar_height,
+            "radar_deadzone" => self.radar_deadzone,
+            "flush_lines" => self.flush_lines,
+            "display_refresh" => self.display_refresh,
+            "tank_fill" => self.tank_fill,
+            "reboot_day" => self.reboot_day,
+            "reboot_hour" => self.reboot_hour,
+            "pump_start" => self.pump_start,
+            "pump_stop" => self.pump_stop,
+            "pump_assist" => self.pump_assist,
+            "pump_fail_min" => self.pump_fail_min,
+            "vfd_setpoint" => self.vfd_setpoint,
+            "vfd_kp" => self.vfd_kp,
+            "vfd_ki" => self.vfd_ki,
+            "vfd_kd" => self.vfd_kd,
+            "tank_shape" => self.tank_shape,
+            "radar_warmup" => self.radar_warmup,
+            "psi_warmup" => self.psi_warmup,
+            "heater_mode" => self.heater_mode,
+            "heater_spread" => self.heater_spread,
+            "heater_duty" => self.heater_duty,
+            "level_median" => self.level_median,
+            "level_alpha" => self.level_alpha,
+            "page_interval" => self.page_interval,
+            "pump_min_on" => self.pump_min_on,
+            "pump_min_off" => self.pump_min_off,
+            "pump_dry_psi" => self.pump_dry_psi,
+            "pump_dry_secs" => self.pump_dry_secs,
+            "valve_travel" => self.valve_travel,
+            "hammer_psi" => self.hammer_psi,
+            "burst_psi" => self.burst_psi,
+            "efficiency_drop" => self.efficiency_drop,
+            "alarm_low" => self.alarm_low,
+            "alarm_high_psi" => self.alarm_high_psi,
+            "alarm_clog_psi" => self.alarm_clog_psi,
+            "alarm_fault_secs" => self.alarm_fault_secs,
+            "budget_gal" => self.budget_gal,
+            "flow_k_factor" => self.flow_k_factor,
+            _ => return None,
+        })
+    }
+
+    /// Faults a configuration trial watches: failed pumps, the dry-run guard
+    /// and active alarms
+    pub fn fault_count(&self) -> usize {
+        let alarms = [self.alarm_low_level, self.alarm_high_pressure, self.alarm_sensor_fault, self.alarm_pipe_burst, self.alarm_filter_clogged];
+        self.pumps.iter().filter(|pump| pump.failed).count() + self.pump_dry_run as usize + alarms.iter().filter(|&&active| active).count()
+    }
+}
+
+/// Device diagnostics published on `watercontroller/diag`
+#[derive(Debug, Serialize)]
+pub struct DiagState {
+    pub ip: Option<Ipv4Addr>,
+    pub uptime_secs: u64,
+    pub free_heap: u32,
+    pub firmware: &'static str,
+    pub link_up: bool,
+    pub reset_reason: &'static str,
+    /// Radar Modbus retries and failed transactions since boot, left out without the radar
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub radar_retries: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub radar_failures: Option<u32>,
+}
+
+impl DiagState {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("diagnostics serialize")
+    }
+}
+
+impl From<&Diagnostics> for DiagState {
+    fn from(diag: &Diagnostics) -> Self {
+        Self {
+            ip: diag.ip,
+            uptime_secs: diag.uptime_secs,
+            free_heap: diag.free_heap,
+            firmware: diag.firmware,
+            link_up: diag.link_up,
+            reset_reason: diag.reset_reason,
+            radar_retries: diag.radar.as_ref().map(|r| r.retries),
+            radar_failures: diag.radar.as_ref().map(|r| r.failures),
+        }
+    }
+}
+
+/// Sibling tank state republished from a LoRa frame on `watercontroller/lora/<node>`
+#[cfg(feature = "lora")]
+#[derive(Debug, Serialize)]
+pub struct SiblingState {
+    pub seq: u8,
+    #[serde(rename = "capacity_pct")]
+    pub capacity_percent: u8,
+    pub gallons: u16,
+    pub pressure_psi: u16,
+    pub pump1_running: bool,
+    pub pump2_running: bool,
+    pub pump_dry_run: bool,
+    pub alarm_low_level: bool,
+    pub alarm_high_pressure: bool,
+    pub alarm_sensor_fault: bool,
+    pub alarm_pipe_burst: bool,
+    pub alarm_filter_clogged: bool,
+    pub maintenance: bool,
+    pub heater_on: bool,
+    pub used_today: u16,
+    pub refilled_today: u16,
+    pub uptime_secs: u32,
+    /// Any alarm or a dry-run fault, for the problem sensor
+    pub problem: bool,
+    pub rssi: i16,
+    pub snr: f32,
+}
+
+#[cfg(feature = "lora")]
+impl SiblingState {
+    pub fn new(frame: &StateFrame, rssi: i16, snr: f32) -> Self {
+        Self {
+            seq: frame.seq,
+            capacity_percent: frame.capacity_percent,
+            gallons: frame.gallons,
+            pressure_psi: frame.pressure_psi,
+            pump1_running: frame.pumps[0],
+            pump2_running: frame.pumps[1],
+            pump_dry_run: frame.dry_run,
+            alarm_low_level: frame.alarm(Alarm::LowLevel),
+            alarm_high_pressure: frame.alarm(Alarm::HighPressure),
+            alarm_sensor_fault: frame.alarm(Alarm::SensorFault),
+            alarm_pipe_burst: frame.alarm(Alarm::PipeBurst),
+            alarm_filter_clogged: frame.alarm(Alarm::FilterClogged),
+            maintenance: frame.maintenance,
+            heater_on: frame.heater_on,
+            used_today: frame.used_today,
+            refilled_today: frame.refilled_today,
+            uptime_secs: frame.uptime_secs,
+            problem: frame.has_problem(),
+            rssi,
+            snr,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("sibling state serializes")
+    }
+}
+
+/// Water hammer event published on `watercontroller/hammer`
+///
+/// Everything besides `event_type` shows up as an event attribute in HA.
+#[cfg(feature = "hammer")]
+#[derive(Debug, Serialize)]
+pub struct HammerReport {
+    pub event_type: &'static str,
+    pub timestamp: u32,
+    pub baseline_psi: f32,
+    pub peak_psi: f32,
+    pub rise_psi: f32,
+    pub peak_ms: u16,
+    /// Time between waveform samples
+    pub interval_ms: u128,
+    pub waveform_psi: Vec<f32>,
+}
+
+#[cfg(feature = "hammer")]
+impl HammerReport {
+    pub fn new(event: &HammerEvent) -> Self {
+        let psi = |tenths: u16| tenths as f32 / 10.0;
+        Self {
+            event_type: event.cause.name(),
+            timestamp: event.timestamp,
+            baseline_psi: psi(event.baseline),
+            peak_psi: psi(event.peak),
+            rise_psi: psi(event.rise()),
+            peak_ms: event.peak_ms,
+            interval_ms: SAMPLE_INTERVAL.as_millis(),
+            waveform_psi: event.waveform.iter().map(|&p| psi(p)).collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("hammer event serializes")
+    }
+}
+
+/// Alarm notification published on `watercontroller/notify`, an HA event
+#[cfg(feature = "notify")]
+#[derive(Debug, Serialize)]
+pub struct NotificationEvent<'a> {
+    pub event_type: &'static str,
+    /// Rendered text, the `message` attribute
+    pub message: &'a str,
+}
+
+#[cfg(feature = "notify")]
+impl<'a> NotificationEvent<'a> {
+    pub fn new(notification: &'a Notification) -> Self {
+        Self { event_type: notification.event.key(), message: &notification.message }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("notification serializes")
+    }
+}
+
+/// Supply valve position published on `watercontroller/valve`
+#[cfg(feature = "valve")]
+#[derive(Debug, Serialize)]
+pub struct ValveStatus {
+    pub state: &'static str,
+    /// A limit switch wasn't reached in time
+    pub fault: bool,
+}
+
+#[cfg(feature = "valve")]
+impl ValveStatus {
+    pub fn new(state: ValveState, fault: bool) -> Self {
+        Self { state: state.name(), fault }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("valve status serializes")
+    }
+}
+
+/// Firmware update state published on `watercontroller/update`
+#[derive(Debug, Serialize)]
+pub struct UpdateState {
+    pub installed_version: &'static str,
+    pub in_progress: bool,
+}
+
+impl UpdateState {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("update state serializes")
+    }
+}
+
+/// Firmware build and board published on `watercontroller/build`, as attributes of the main sensors
+#[derive(Debug, Serialize)]
+pub struct BuildAttributes {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub build_date: &'static str,
+    pub features: Vec<&'static str>,
+    /// Board profile selected by the ID straps
+    pub board: &'static str,
+}
+
+impl BuildAttributes {
+    /// The running image on `board`
+    pub fn running(board: &'static str) -> Self {
+        Self {
+            version: build_info::VERSION,
+            git_hash: build_info::GIT_HASH,
+            build_date: build_info::BUILD_DATE,
+            features: build_info::features(),
+            board,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("build attributes serialize")
+    }
+}
+
+/// Latest firmware announced on `watercontroller/update/latest`
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct LatestFirmware {
+    pub latest_version: String,
+    /// Image URL; the signature is fetched from the same URL with `.sig` appended
+    pub url: String,
+}
+
+impl LatestFirmware {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let latest: Self = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        if !is_http_url(&latest.url) {
+            return Err(format!("not an http(s) URL: {}", latest.url));
+        }
+        Ok(latest)
+    }
+}
+
+/// Whether `url` can be downloaded from
+pub fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::diag::RadarDiag;
+    use crate::trial::{RevertReason, Trial};
+
+    #[test]
+    fn test_discovery_json() {
+        let number = Discovery {
+            name: "Tank Capacity".into(),
+            unique_id: "wc_tank_cap".into(),
+            state_topic: Some("watercontroller/state"),
+            value_template: Some(value_template("tank_capacity")),
+            command_topic: Some("watercontroller/set/tank_capacity".into()),
+            min: Some(100),
+            max: Some(2000),
+            step: Some(10),
+            mode: Some("box"),
+            unit: Some("gal"),
+            icon: Some("mdi:storage-tank"),
+            ..Default::default()
+        };
+        assert_eq!(
+            number.to_json(),
+            concat!(
+                r#"{"name":"Tank Capacity","uniq_id":"wc_tank_cap","stat_t":"watercontroller/state","#,
+                r#""val_tpl":"{{ value_json.tank_capacity }}","cmd_t":"watercontroller/set/tank_capacity","#,
+                r#""min":100,"max":2000,"step":10,"mode":"box","unit_of_meas":"gal","ic":"mdi:storage-tank","#,
+                r#""dev":{"ids":"watercontroller","name":"Water Controller","mf":"DIY","mdl":"wESP32"}}"#,
+            )
+        );
+
+        let event = Discovery {
+            name: "Pump \"A\" Alarm".into(),
+            unique_id: "wc_notification".into(),
+            state_topic: Some("watercontroller/notify"),
+            event_types: vec!["low_level", "dry_run"],
+            expire_after: Some(120),
+            ..Default::default()
+        };
+        assert_eq!(
+            event.to_json(),
+            concat!(
+                r#"{"name":"Pump \"A\" Alarm","uniq_id":"wc_notification","stat_t":"watercontroller/notify","#,
+                r#""evt_typ":["low_level","dry_run"],"exp_aft":120,"#,
+                r#""dev":{"ids":"watercontroller","name":"Water Controller","mf":"DIY","mdl":"wESP32"}}"#,
+            )
+        );
+        assert_eq!(on_off_template("pump1_on"), "{{ 'ON' if value_json.pump1_on else 'OFF' }}");
+
+        let update = Discovery {
+            name: "Firmware".into(),
+            unique_id: "wc_firmware_update".into(),
+            latest_version_topic: Some("watercontroller/update/latest"),
+            latest_version_template: Some(value_template("latest_version")),
+            payload_install: Some("install"),
+            ..Default::default()
+        };
+        assert!(update.to_json().contains(concat!(
+            r#""l_ver_t":"watercontroller/update/latest","l_ver_tpl":"{{ value_json.latest_version }}","#,
+            r#""pl_inst":"install","#,
+        )));
+    }
+
+    #[test]
+    fn test_naming() {
+        let mut config = Discovery { name: "Water Capacity".into(), unique_id: "wc_capacity".into(), ..Default::default() };
+        Naming::default().apply(&mut config);
+        assert_eq!(config.name, "Water Capacity");
+        assert!(config.to_json().ends_with(r#""dev":{"ids":"watercontroller","name":"Water Controller","mf":"DIY","mdl":"wESP32"}}"#));
+
+        let naming = Naming { device_name: "Barn Tank".into(), area: " Barn ".into(), prefix: "Barn".into(), ..Default::default() };
+        naming.apply(&mut config);
+        assert_eq!(config.name, "Barn Water Capacity");
+        assert!(config.to_json().ends_with(r#""dev":{"ids":"watercontroller","name":"Barn Tank","mf":"DIY","mdl":"wESP32","sa":"Barn"}}"#));
+
+        assert_eq!(naming.tank_entity("Water Volume"), "Water Volume");
+        let naming = Naming { tank: " CISTERN ".into(), ..naming };
+        let mut config = Discovery { name: naming.tank_entity("Water Volume"), ..Default::default() };
+        naming.apply(&mut config);
+        assert_eq!(config.name, "Barn CISTERN Water Volume");
+    }
+
+    #[test]
+    fn test_update_payloads() {
+        let state = UpdateState { installed_version: "0.3.1", in_progress: true };
+        assert_eq!(state.to_json(), r#"{"installed_version":"0.3.1","in_progress":true}"#);
+
+        let latest = LatestFirmware::parse(r#"{"latest_version":"0.4.0","url":"https://fw.example/wc-0.4.0.bin"}"#);
+        assert_eq!(
+            latest,
+            Ok(LatestFirmware { latest_version: "0.4.0".into(), url: "https://fw.example/wc-0.4.0.bin".into() })
+        );
+        assert!(LatestFirmware::parse(r#"{"latest_version":"0.4.0","url":"ftp://fw.example/wc.bin"}"#).is_err());
+        assert!(LatestFirmware::parse(r#"{"latest_version":"0.4.0"}"#).is_err());
+
+        let build = BuildAttributes {
+            version: "0.3.1",
+            git_hash: "1a2b3c4d-dirty",
+            build_date: "2026-10-14",
+            features: vec!["ethernet", "radar", "mqtt"],
+            board: "rev B",
+        };
+        assert_eq!(
+            build.to_json(),
+            r#"{"version":"0.3.1","git_hash":"1a2b3c4d-dirty","build_date":"2026-10-14","features":["ethernet","radar","mqtt"],"board":"rev B"}"#
+        );
+        let running = BuildAttributes::running("rev A");
+        assert!(running.features.contains(&"mqtt"));
+        assert_eq!(running.version, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[cfg(feature = "hammer")]
+    #[test]
+    fn test_hammer_report() {
+        use crate::hammer::{Cause, WAVEFORM_LEN};
+
+        let mut waveform = [452; WAVEFORM_LEN];
+        waveform[16] = 1185;
+        let event = HammerEvent { timestamp: 1_760_000_000, cause: Cause::PumpStop, baseline: 452, peak: 1185, peak_ms: 38, waveform };
+        let json = HammerReport::new(&event).to_json();
+        assert!(
+            json.starts_with(
+                r#"{"event_type":"pump_stop","timestamp":1760000000,"baseline_psi":45.2,"peak_psi":118.5,"rise_psi":73.3,"peak_ms":38,"interval_ms":2,"waveform_psi":[45.2,"#
+            ),
+            "{}",
+            json
+        );
+        assert!(json.contains(",118.5,45.2,"), "{}", json);
+        assert!(json.ends_with(",45.2]}"), "{}", json);
+    }
+
+    #[cfg(feature = "notify")]
+    #[test]
+    fn test_notification_event() {
+        use crate::notify::Event;
+
+        let notification = Notification { event: Event::LowLevel, message: "Tank at 9%, \"Barn\"".into() };
+        assert_eq!(
+            NotificationEvent::new(&notification).to_json(),
+            r#"{"event_type":"low_level","message":"Tank at 9%, \"Barn\""}"#
+        );
+    }
+
+    #[cfg(feature = "valve")]
+    #[test]
+    fn test_valve_status() {
+        assert_eq!(ValveStatus::new(ValveState::Partial, false).to_json(), r#"{"state":"open","fault":false}"#);
+        assert_eq!(ValveStatus::new(ValveState::Unknown, true).to_json(), r#"{"state":"unknown","fault":true}"#);
+    }
+
+    #[test]
+    fn test_retired_since() {
+        const RETIRED: &[Retired] = &[
+            Retired { revision: 1, component: "sensor", object_id: "pump_running" },
+            Retired { revision: 2, component: "number", object_id: "max_psi" },
+            Retired { revision: 2, component: "sensor", object_id: "heap" },
+        ];
+        let ids = |cleared| retired_since(RETIRED, cleared).map(|r| r.object_id).collect::<Vec<_>>();
+        assert_eq!(ids(0), ["pump_running", "max_psi", "heap"]);
+        assert_eq!(ids(1), ["max_psi", "heap"]);
+        assert!(ids(2).is_empty());
+    }
+
+    #[test]
+    fn test_alarm_reverts_trial() {
+        let mut state = WaterState { pump_dry_run: true, ..Default::default() };
+        state.pumps[1].failed = true;
+        assert_eq!(state.fault_count(), 2);
+
+        let minute = Duration::from_secs(60);
+        let trial = Trial::new(&[("alarm_high_psi", 70)], &[("alarm_high_psi", 90)], Duration::ZERO, 10 * minute, state.fault_count());
+        assert_eq!(trial.check(minute, state.fault_count()), None);
+        state.alarm_high_pressure = true;
+        assert_eq!(trial.check(2 * minute, state.fault_count()), Some(RevertReason::Fault));
+    }
+
+    #[test]
+    fn test_state_json() {
+        let mut state = WaterState { capacity_percent: 42, capacity_gallons: 336, pump_dry_run: true, ..Default::default() };
+        state.pumps[1] = PumpState { running: true, runtime_min: 90, starts: 3, failed: false };
+        let json = state.to_json();
+        assert!(json.starts_with(r#"{"capacity_pct":42,"gallons":336,"pressure_psi":0,"#));
+        let filtered = WaterState { pressure_psi: 62, filter_drop_psi: Some(4.5), ..Default::default() };
+        assert!(filtered.to_json().contains(r#""pressure_psi":62,"filter_drop_psi":4.5,"tank_capacity":0,"#));
+        assert!(json.contains(concat!(
+            r#""pump_fail_min":0,"#,
+            r#""pump1_on":false,"pump1_runtime_min":0,"pump1_starts":0,"pump1_failed":false,"#,
+            r#""pump2_on":true,"pump2_runtime_min":90,"pump2_starts":3,"pump2_failed":false,"#,
+            r#""pump_dry_run":true,"vfd_setpoint":0,"#,
+        )));
+        assert!(json.contains(r#""budget_gal":0,"budget_pct":null,"budget_exceeded":false,"flow_k_factor":0,"#));
+        let metered = WaterState { flow_gpm: Some(3.25), flow_total_gal: Some(1042.5), ..Default::default() };
+        assert!(metered.to_json().contains(r#""pressure_psi":0,"flow_gpm":3.25,"flow_total_gal":1042.5,"tank_capacity":0,"#));
+        assert!(json.ends_with(concat!(
+            r#""efficiency_drop":0,"alarm_low":0,"alarm_high_psi":0,"alarm_clog_psi":0,"alarm_fault_secs":0,"#,
+            r#""alarm_low_level":false,"alarm_high_pressure":false,"alarm_sensor_fault":false,"alarm_pipe_burst":false,"#,
+            r#""alarm_filter_clogged":false}"#,
+        )));
+        let radar = RadarDistances { radar_empty_mm: 1200, radar_empty_raw_mm: 1185, radar_level_mm: 800, radar_level_raw_mm: 815, radar_smoothed_mm: 1198 };
+        let measured = WaterState { radar: Some(radar), ..Default::default() };
+        assert!(measured.to_json().ends_with(concat!(
+            r#""alarm_filter_clogged":false,"radar_empty_mm":1200,"radar_empty_raw_mm":1185,"#,
+            r#""radar_level_mm":800,"radar_level_raw_mm":815,"radar_smoothed_mm":1198}"#,
+        )));
+        let stamped = WaterState { timestamp: Some("2026-10-14T07:05:09-05:00".into()), ..Default::default() };
+        assert!(stamped.to_json().ends_with(r#""alarm_filter_clogged":false,"timestamp":"2026-10-14T07:05:09-05:00"}"#));
+        // Every number entity reads its value from the state document
+        for key in ["tank_capacity", "level_alpha", "valve_travel", "alarm_low", "alarm_clog_psi", "alarm_fault_secs", "budget_gal", "flow_k_factor"] {
+            assert!(state.setting(key).is_some());
+            assert!(json.contains(&format!(r#""{}":"#, key)));
+        }
+        assert_eq!(state.setting("capacity_pct"), None);
+
+        let diag = Diagnostics {
+            uptime_secs: 3600,
+            free_heap: 81_920,
+            firmware: "0.1.0",
+            reset_reason: "brownout",
+            link_up: true,
+            ip: Some(Ipv4Addr::new(10, 0, 0, 7)),
+            ..Default::default()
+        };
+        assert_eq!(
+            DiagState::from(&diag).to_json(),
+            r#"{"ip":"10.0.0.7","uptime_secs":3600,"free_heap":81920,"firmware":"0.1.0","link_up":true,"reset_reason":"brownout"}"#
+        );
+        let radar = RadarDiag { requests: 900, retries: 4, failures: 1, ..Default::default() };
+        let diag = Diagnostics { radar: Some(radar), ..diag };
+        assert!(DiagState::from(&diag).to_json().ends_with(r#""reset_reason":"brownout","radar_retries":4,"radar_failures":1}"#));
+    }
+}
@@ -0,0 +1,296 @@
+//! PID controller
+//!
+//! Textbook parallel-form PID with output clamping. The integral is clamped to
+//! the output range and frozen while the output saturates, so a pump running
+//! flat out during a long draw doesn't wind up and overshoot once demand
+//! drops. The derivative acts on the measurement rather than the error, so a
+//! setpoint change doesn't kick the output.
+//!
+//! # Bumpless transfer
+//! When switching from manual (or autotune) output to closed loop,
+//! `bumpless_transfer()` preloads the integral so the first automatic output
+//! equals the last manual one.
+//!
+//! # Relay autotune
+//! `RelayAutotune` implements the Åström-Hägglund relay experiment: the output
+//! is switched between two levels around the setpoint, which drives the plant
+//! into a limit cycle at its ultimate period `Pu`. With relay amplitude `d`
+//! and process oscillation amplitude `a`, the ultimate gain is
+//! `Ku = 4d / (πa)`, from which Ziegler-Nichols PID gains follow.
+
+use std::f32::consts::PI;
+
+/// PID controller state
+#[derive(Debug, Clone)]
+pub struct Pid {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// Output lower bound
+    pub out_min: f32,
+    /// Output upper bound
+    pub out_max: f32,
+    /// Accumulated integral term (in output units)
+    integral: f32,
+    /// Measurement at the previous update
+    prev_measurement: Option<f32>,
+}
+
+impl Pid {
+    pub fn new(kp: f32, ki: f32, kd: f32, out_min: f32, out_max: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            out_min,
+            out_max,
+            integral: 0.0,
+            prev_measurement: None,
+        }
+    }
+
+    /// Update the gains, keeping the accumulated state
+    pub fn set_gains(&mut self, kp: f32, ki: f32, kd: f32) {
+        self.kp = kp;
+        self.ki = ki;
+        self.kd = kd;
+    }
+
+    /// Clear the integral and derivative history
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.prev_measurement = None;
+    }
+
+    /// Preload the state so the next `update()` continues from `output`
+    pub fn bumpless_transfer(&mut self, output: f32, setpoint: f32, measurement: f32) {
+        let error = setpoint - measurement;
+        self.integral = (output - self.kp * error).clamp(self.out_min, self.out_max);
+        self.prev_measurement = Some(measurement);
+    }
+
+    /// Compute the output for one step of `dt` seconds
+    pub fn update(&mut self, setpoint: f32, measurement: f32, dt: f32) -> f32 {
+        let error = setpoint - measurement;
+
+        let derivative = match self.prev_measurement {
+            Some(prev) if dt > 0.0 => -(measurement - prev) / dt,
+            _ => 0.0,
+        };
+        self.prev_measurement = Some(measurement);
+
+        let unclamped = self.kp * error + self.integral + self.ki * error * dt + self.kd * derivative;
+        let output = unclamped.clamp(self.out_min, self.out_max);
+
+        // Conditional integration: only accumulate while not pushing further into saturation
+        let saturated_high = unclamped > self.out_max && error > 0.0;
+        let saturated_low = unclamped < self.out_min && error < 0.0;
+        if !saturated_high && !saturated_low {
+            self.integral = (self.integral + self.ki * error * dt).clamp(self.out_min, self.out_max);
+        }
+
+        output
+    }
+}
+
+/// Tuned controller gains
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+/// Result of one autotune step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AutotuneStep {
+    /// Experiment running: apply this output
+    Output(f32),
+    /// Limit cycle measured
+    Done(Gains),
+    /// No stable oscillation within the time limit
+    Failed,
+}
+
+/// Relay-feedback autotune experiment
+#[derive(Debug, Clone)]
+pub struct RelayAutotune {
+    setpoint: f32,
+    high: f32,
+    low: f32,
+    /// Measurement band around the setpoint that doesn't switch the relay
+    hysteresis: f32,
+    /// Give up after this many seconds
+    timeout: f32,
+    /// Relay currently at `high`
+    output_high: bool,
+    /// Time of the most recent switch to `high`
+    last_rise: Option<f32>,
+    /// Measurement extremes of the current cycle
+    cycle_max: f32,
+    cycle_min: f32,
+    /// Completed cycles: (period, peak-to-peak amplitude)
+    cycles: Vec<(f32, f32)>,
+    start: Option<f32>,
+}
+
+impl RelayAutotune {
+    /// Cycles discarded while the limit cycle settles
+    const SETTLE_CYCLES: usize = 2;
+    /// Cycles averaged for the result
+    const MEASURE_CYCLES: usize = 3;
+
+    pub fn new(setpoint: f32, low: f32, high: f32, hysteresis: f32, timeout: f32) -> Self {
+        Self {
+            setpoint,
+            high,
+            low,
+            hysteresis,
+            timeout,
+            output_high: true,
+            last_rise: None,
+            cycle_max: f32::MIN,
+            cycle_min: f32::MAX,
+            cycles: Vec::new(),
+            start: None,
+        }
+    }
+
+    /// Feed a measurement taken at `now` seconds
+    pub fn update(&mut self, measurement: f32, now: f32) -> AutotuneStep {
+        let start = *self.start.get_or_insert(now);
+        if now - start > self.timeout {
+            return AutotuneStep::Failed;
+        }
+
+        self.cycle_max = self.cycle_max.max(measurement);
+        self.cycle_min = self.cycle_min.min(measurement);
+
+        if self.output_high && measurement > self.setpoint + self.hysteresis {
+            self.output_high = false;
+        } else if !self.output_high && measurement < self.setpoint - self.hysteresis {
+            self.output_high = true;
+            // A full cycle ends on each switch back to high
+            if let Some(rise) = self.last_rise {
+                self.cycles.push((now - rise, self.cycle_max - self.cycle_min));
+            }
+            self.last_rise = Some(now);
+            self.cycle_max = measurement;
+            self.cycle_min = measurement;
+        }
+
+        if self.cycles.len() >= Self::SETTLE_CYCLES + Self::MEASURE_CYCLES {
+            return self.result();
+        }
+
+        AutotuneStep::Output(if self.output_high { self.high } else { self.low })
+    }
+
+    fn result(&self) -> AutotuneStep {
+        let measured = &self.cycles[Self::SETTLE_CYCLES..];
+        let n = measured.len() as f32;
+        let period = measured.iter().map(|c| c.0).sum::<f32>() / n;
+        let amplitude = measured.iter().map(|c| c.1).sum::<f32>() / n / 2.0;
+        if amplitude <= 0.0 || period <= 0.0 {
+            return AutotuneStep::Failed;
+        }
+
+        let d = (self.high - self.low) / 2.0;
+        let ku = 4.0 * d / (PI * amplitude);
+        // Classic Ziegler-Nichols: Kp = 0.6 Ku, Ti = Pu / 2, Td = Pu / 8
+        let kp = 0.6 * ku;
+        AutotuneStep::Done(Gains {
+            kp,
+            ki: kp / (period / 2.0),
+            kd: kp * period / 8.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First-order plant with dead time: a pump feeding a pressure tank
+    struct Plant {
+        gain: f32,
+        tau: f32,
+        value: f32,
+        delay: std::collections::VecDeque<f32>,
+    }
+
+    impl Plant {
+        fn new(gain: f32, tau: f32, dead_time_steps: usize) -> Self {
+            Self {
+                gain,
+                tau,
+                value: 0.0,
+                delay: vec![0.0; dead_time_steps].into(),
+            }
+        }
+
+        fn step(&mut self, input: f32, dt: f32) -> f32 {
+            self.delay.push_back(input);
+            let delayed = self.delay.pop_front().unwrap_or(input);
+            self.value += (self.gain * delayed - self.value) * dt / self.tau;
+            self.value
+        }
+    }
+
+    #[test]
+    fn test_proportional_and_clamp() {
+        let mut pid = Pid::new(0.1, 0.0, 0.0, 0.0, 1.0);
+        assert!((pid.update(50.0, 45.0, 1.0) - 0.5).abs() < 1e-6);
+        assert_eq!(pid.update(50.0, 0.0, 1.0), 1.0);
+        assert_eq!(pid.update(50.0, 80.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn test_integral_does_not_wind_up() {
+        let mut pid = Pid::new(0.0, 0.1, 0.0, 0.0, 1.0);
+        // Long saturation with a large error
+        for _ in 0..1000 {
+            pid.update(100.0, 0.0, 1.0);
+        }
+        // Output drops as soon as the error reverses
+        let out = pid.update(100.0, 105.0, 1.0);
+        assert!(out < 1.0, "integral wound up: {}", out);
+    }
+
+    #[test]
+    fn test_bumpless_transfer() {
+        let mut pid = Pid::new(0.05, 0.01, 0.0, 0.0, 1.0);
+        pid.bumpless_transfer(0.625, 50.0, 48.0);
+        let out = pid.update(50.0, 48.0, 0.1);
+        assert!((out - 0.625).abs() < 0.01, "output jumped to {}", out);
+    }
+
+    #[test]
+    fn test_autotune_on_simulated_plant() {
+        const DT: f32 = 0.1;
+        let mut plant = Plant::new(80.0, 5.0, 10);
+        let mut tune = RelayAutotune::new(50.0, 0.0, 1.0, 0.5, 600.0);
+
+        let mut pressure = 0.0;
+        let mut t = 0.0;
+        let gains = loop {
+            match tune.update(pressure, t) {
+                AutotuneStep::Output(u) => pressure = plant.step(u, DT),
+                AutotuneStep::Done(gains) => break gains,
+                AutotuneStep::Failed => panic!("autotune failed"),
+            }
+            t += DT;
+        };
+        assert!(gains.kp > 0.0 && gains.ki > 0.0 && gains.kd > 0.0);
+
+        // The tuned loop holds the setpoint against the same plant
+        let mut pid = Pid::new(gains.kp, gains.ki, gains.kd, 0.0, 1.0);
+        let mut u = 0.0;
+        pid.bumpless_transfer(u, 50.0, pressure);
+        for _ in 0..3000 {
+            pressure = plant.step(u, DT);
+            u = pid.update(50.0, pressure, DT);
+        }
+        assert!((pressure - 50.0).abs() < 1.0, "settled at {}", pressure);
+    }
+}
@@ -0,0 +1,139 @@
+//! Pipe burst detection
+//!
+//! A burst pipe empties the line faster than any fixture: the pressure
+//! collapses within a second or two, while the pumps are off and nothing
+//! should be drawing that much. The regular 5-second readings and the alarm
+//! filtering would take several cycles to notice, so this check runs on its
+//! own fast reading every main loop pass and trips as soon as the pressure
+//! has fallen by `burst_psi` or more within `WINDOW`.
+//!
+//! Only drops while every pump has been off for the whole window count, so
+//! a pump stopping (or the hammer after it) can't trip it. Once tripped the
+//! supply valve is closed and the "Pipe Burst" alarm raised; both stay until
+//! the pressure is back within half `burst_psi` of where it was before the
+//! drop, which takes the leak being fixed and the valve reopened.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Time the pressure has to collapse in
+pub const WINDOW: Duration = Duration::from_secs(2);
+
+/// A detected burst
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Collapse {
+    /// Highest pressure in the window before the drop (PSI)
+    pub from_psi: f32,
+    /// Pressure when it tripped (PSI)
+    pub to_psi: f32,
+}
+
+/// Watches fast pressure readings for a sudden collapse
+#[derive(Debug, Default)]
+pub struct BurstDetector {
+    /// Readings of the last `WINDOW`: (time, PSI)
+    samples: VecDeque<(Duration, f32)>,
+    pumps_off_since: Option<Duration>,
+    tripped: Option<Collapse>,
+}
+
+impl BurstDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The burst being signalled, if any
+    pub fn tripped(&self) -> Option<Collapse> {
+        self.tripped
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.tripped.is_some()
+    }
+
+    /// Feed one reading taken at `now`
+    ///
+    /// Returns `Some(true)` when a burst trips and `Some(false)` when it
+    /// clears. `drop_psi` 0 turns detection off and clears a tripped burst.
+    pub fn update(&mut self, psi: f32, pumps_off: bool, drop_psi: u16, now: Duration) -> Option<bool> {
+        if drop_psi == 0 {
+            self.samples.clear();
+            return self.tripped.take().map(|_| false);
+        }
+        if !pumps_off {
+            self.pumps_off_since = None;
+        } else if self.pumps_off_since.is_none() {
+            self.pumps_off_since = Some(now);
+        }
+        while self.samples.front().is_some_and(|&(t, _)| now.saturating_sub(t) > WINDOW) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, psi));
+
+        if let Some(collapse) = self.tripped {
+            if psi >= collapse.from_psi - drop_psi as f32 / 2.0 {
+                self.tripped = None;
+                return Some(false);
+            }
+            return None;
+        }
+        let quiet = self.pumps_off_since.is_some_and(|since| now.saturating_sub(since) >= WINDOW);
+        let peak = self.samples.iter().map(|&(_, psi)| psi).fold(f32::MIN, f32::max);
+        if quiet && peak - psi >= drop_psi as f32 {
+            self.tripped = Some(Collapse { from_psi: peak, to_psi: psi });
+            self.samples.clear();
+            return Some(true);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_trips_on_collapse_with_pumps_off() {
+        let mut detector = BurstDetector::new();
+        for i in 0..15 {
+            assert_eq!(detector.update(60.0, true, 20, ms(i * 200)), None);
+        }
+        // A slow draw-down over many windows never trips
+        for i in 0..50 {
+            assert_eq!(detector.update(60.0 - i as f32 * 0.5, true, 20, ms(3000 + i * 200)), None);
+        }
+        for (i, psi) in [35.0, 28.0, 20.0, 14.0].into_iter().enumerate() {
+            let tripped = detector.update(psi, true, 20, ms(13_200 + i as u64 * 200));
+            assert_eq!(tripped, (psi <= 15.5).then_some(true));
+        }
+        assert_eq!(detector.tripped(), Some(Collapse { from_psi: 38.0, to_psi: 14.0 }));
+        // Stays tripped with the valve closed, clears once pressure is back
+        assert_eq!(detector.update(10.0, true, 20, ms(20_000)), None);
+        assert_eq!(detector.update(27.0, true, 20, ms(60_000)), None);
+        assert_eq!(detector.update(29.0, true, 20, ms(61_000)), Some(false));
+        assert!(!detector.is_tripped());
+    }
+
+    #[test]
+    fn test_ignores_pump_stops_and_off_setting() {
+        let mut detector = BurstDetector::new();
+        detector.update(60.0, false, 20, ms(0));
+        detector.update(60.0, false, 20, ms(200));
+        // The pump has just stopped: not quiet for a whole window yet
+        detector.update(60.0, true, 20, ms(400));
+        assert_eq!(detector.update(30.0, true, 20, ms(600)), None);
+        assert_eq!(detector.update(30.0, true, 20, ms(3000)), None);
+
+        let mut detector = BurstDetector::new();
+        for i in 0..15 {
+            detector.update(60.0, true, 20, ms(i * 200));
+        }
+        assert_eq!(detector.update(10.0, true, 20, ms(3000)), Some(true));
+        assert_eq!(detector.update(10.0, true, 0, ms(3200)), Some(false));
+        assert_eq!(detector.update(10.0, true, 0, ms(3400)), None);
+    }
+}
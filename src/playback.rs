@@ -0,0 +1,118 @@
+//! Sensor trace playback
+//!
+//! Lets support reproduce a misbehaviour reported from the field on a bench
+//! unit. A trace in the `trace` CSV format is uploaded on `/playback`, and
+//! until it ends or is stopped the main loop takes the tank level, volume and
+//! pressure from it instead of the sensors, so the pumps, alarms, display and
+//! MQTT see what the customer's unit saw.
+//!
+//! The trace plays in real time or up to `MAX_SPEED` times faster. The loop
+//! samples every 5 seconds, so at high speeds samples in between are skipped,
+//! and the pump and alarm timers keep running in real time.
+
+use std::time::Duration;
+
+use crate::trace::TracePoint;
+
+/// Fastest playback, trace seconds per second
+pub const MAX_SPEED: u16 = 3600;
+/// Largest trace upload, about 2500 samples
+pub const MAX_UPLOAD: usize = 64 * 1024;
+
+/// A trace being played back
+#[derive(Debug, Clone)]
+pub struct Playback {
+    trace: Vec<TracePoint>,
+    speed: u16,
+    /// Uptime playback started at
+    started: Duration,
+    /// The last sample has been handed out
+    ended: bool,
+}
+
+impl Playback {
+    pub fn new(trace: Vec<TracePoint>, speed: u16, now: Duration) -> Result<Self, String> {
+        if trace.is_empty() {
+            return Err("trace has no samples".into());
+        }
+        if !(1..=MAX_SPEED).contains(&speed) {
+            return Err(format!("speed {} out of range 1..{}", speed, MAX_SPEED));
+        }
+        Ok(Self { trace, speed, started: now, ended: false })
+    }
+
+    /// Sample to use at `now`, or `None` once the trace has ended
+    ///
+    /// Each sample holds until the next one is due; the last one is used for
+    /// one more call.
+    pub fn sample(&mut self, now: Duration) -> Option<TracePoint> {
+        if self.ended {
+            return None;
+        }
+        let played = self.played(now);
+        let start = self.trace[0].timestamp;
+        let due = self.trace.partition_point(|p| (p.timestamp - start) as u64 <= played);
+        self.ended = due == self.trace.len();
+        Some(self.trace[due - 1])
+    }
+
+    /// Trace seconds played at `now` and the length of the trace
+    pub fn position(&self, now: Duration) -> (u32, u32) {
+        let length = self.trace[self.trace.len() - 1].timestamp - self.trace[0].timestamp;
+        (self.played(now).min(length as u64) as u32, length)
+    }
+
+    pub fn speed(&self) -> u16 {
+        self.speed
+    }
+
+    pub fn len(&self) -> usize {
+        self.trace.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trace.is_empty()
+    }
+
+    fn played(&self, now: Duration) -> u64 {
+        now.saturating_sub(self.started).as_secs() * self.speed as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::parse_trace;
+
+    const TRACE: &str = "timestamp,capacity_percent,pressure_psi,gallons\n\
+        1767225600,42,55,336\n\
+        1767225660,41,54,328\n\
+        1767225720,40,20,320\n";
+
+    #[test]
+    fn test_accelerated_playback() {
+        let start = Duration::from_secs(1000);
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut playback = Playback::new(parse_trace(TRACE).unwrap(), 12, start).unwrap();
+        assert_eq!(playback.sample(at(0)).map(|p| p.capacity_percent), Some(42));
+        assert_eq!(playback.sample(at(4)).map(|p| p.capacity_percent), Some(42));
+        assert_eq!(playback.sample(at(5)).map(|p| p.capacity_percent), Some(41));
+        assert_eq!(playback.position(at(5)), (60, 120));
+        assert_eq!(playback.sample(at(10)).map(|p| p.pressure_psi), Some(20));
+        assert_eq!(playback.sample(at(15)), None);
+        assert_eq!(playback.position(at(15)), (120, 120));
+    }
+
+    #[test]
+    fn test_rejects_unplayable_trace() {
+        let now = Duration::ZERO;
+        assert!(Playback::new(Vec::new(), 1, now).unwrap_err().contains("no samples"));
+        let trace = parse_trace(TRACE).unwrap();
+        assert!(Playback::new(trace.clone(), 0, now).unwrap_err().contains("out of range"));
+        assert!(Playback::new(trace.clone(), MAX_SPEED + 1, now).is_err());
+        // A single sample plays once
+        let mut single = Playback::new(trace[..1].to_vec(), MAX_SPEED, now).unwrap();
+        assert!(single.sample(now).is_some());
+        assert_eq!(single.sample(now), None);
+    }
+}
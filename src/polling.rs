@@ -0,0 +1,97 @@
+//! Radar polling rate
+//!
+//! The tank level only changes while a pump runs or water is drawn, so the
+//! radar is polled every 2 seconds then and backs off to every 30 seconds
+//! while the level holds still, saving RS-485 traffic and sensor power. Each
+//! reading without activity doubles the interval (2, 4, 8, 16, 30 s); a pump
+//! start or a level change of 1% or more between readings goes straight back
+//! to the fast rate. Failed reads keep the current rate.
+
+use std::time::Duration;
+
+/// Interval while a pump runs or the level is moving
+pub const FAST_POLL: Duration = Duration::from_secs(2);
+/// Interval while the level is static
+pub const SLOW_POLL: Duration = Duration::from_secs(30);
+/// Level change between two readings that counts as activity (%)
+pub const ACTIVE_CHANGE_PERCENT: u8 = 1;
+
+/// Picks when the radar is polled next
+#[derive(Debug, Clone)]
+pub struct RadarPolling {
+    interval: Duration,
+    /// Level of the last good reading
+    last_level: Option<u8>,
+    /// Uptime of the next poll
+    next: Duration,
+}
+
+impl RadarPolling {
+    /// Start at the fast rate, with the first poll due at once
+    pub fn new() -> Self {
+        Self { interval: FAST_POLL, last_level: None, next: Duration::ZERO }
+    }
+
+    /// Whether a poll is due at `now`
+    pub fn due(&self, now: Duration) -> bool {
+        now >= self.next
+    }
+
+    /// Record a poll at `now` (`level` is `None` if it failed), returning the interval to the next one
+    pub fn update(&mut self, level: Option<u8>, pumping: bool, now: Duration) -> Duration {
+        if let Some(level) = level {
+            let moving = self.last_level.is_some_and(|last| last.abs_diff(level) >= ACTIVE_CHANGE_PERCENT);
+            self.interval = if pumping || moving { FAST_POLL } else { (self.interval * 2).min(SLOW_POLL) };
+            self.last_level = Some(level);
+        } else if pumping {
+            self.interval = FAST_POLL;
+        }
+        self.next = now + self.interval;
+        self.interval
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+impl Default for RadarPolling {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_backs_off_while_static() {
+        let mut polling = RadarPolling::new();
+        assert!(polling.due(secs(0)));
+        let intervals: Vec<u64> = (0..6).map(|i| polling.update(Some(60), false, secs(i * 30)).as_secs()).collect();
+        assert_eq!(intervals, [4, 8, 16, 30, 30, 30]);
+        assert!(!polling.due(secs(179)));
+        assert!(polling.due(secs(180)));
+        // A failed read keeps the rate
+        assert_eq!(polling.update(None, false, secs(180)), SLOW_POLL);
+    }
+
+    #[test]
+    fn test_fast_while_pumping_or_moving() {
+        let mut polling = RadarPolling::new();
+        polling.update(Some(60), false, secs(0));
+        polling.update(Some(60), false, secs(4));
+        assert_eq!(polling.interval(), secs(8));
+        assert_eq!(polling.update(Some(60), true, secs(12)), FAST_POLL);
+        assert_eq!(polling.update(Some(61), false, secs(14)), FAST_POLL);
+        assert_eq!(polling.update(Some(61), false, secs(16)), secs(4));
+        // Water drawn off while the pumps are idle
+        assert_eq!(polling.update(Some(59), false, secs(20)), FAST_POLL);
+        assert_eq!(polling.update(None, true, secs(22)), FAST_POLL);
+    }
+}
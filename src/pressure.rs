@@ -1,75 +1,295 @@
 //! Pressure sensor driver using ADC
 //!
-//! Reads a 0.5V-4.5V pressure transducer via voltage divider.
-//! Sensor range: 0.5V = 0 PSI, 4.5V = 100 PSI
+//! Reads a voltage-output pressure transducer via voltage divider. The
+//! output range and full scale come from a `TransducerProfile` set in
+//! `Config` (and on the setup page): 0.5V = 0 PSI and 4.5V = 100 PSI by
+//! default, the common part; 150, 200 and 300 PSI versions only change the
+//! full scale, 1-5 V or 0-5 V parts the output range. Readings are not
+//! clamped to the range, so a line above full scale shows as such.
+//!
+//! # Ratiometric Transducers
+//! A ratiometric part's output is a fraction of its supply rather than a
+//! fixed voltage, e.g. 10% to 90%. Its profile is entered at a 5 V supply,
+//! as datasheets give it, and `psi_supply_mv` sets the supply it actually
+//! runs from, so a 3.3 V-powered sensor reads right without working out the
+//! voltages by hand.
+//!
+//! # Zero Calibration
+//! Cheap transducers drift, and a few PSI with the line depressurized is
+//! common. `calibrate_zero` takes the output voltage at that moment as the
+//! new 0 PSI point and stores it in `Config` (`psi_min_mv`), moving the
+//! full-scale voltage with it so the span is kept. Readings further than
+//! `MAX_ZERO_SHIFT_MV` from the present zero are refused, as the line is
+//! still under pressure or the sensor is faulty. The zero applies at the
+//! sensor, before the `correction` offset and gain.
+//!
+//! # ADC Calibration
+//! The ESP32 ADC's own millivolt conversion is a straight line through the
+//! nominal range, off by tens of millivolts from chip to chip and bent at
+//! both ends. Most modules carry the factory reference voltage or two-point
+//! calibration in eFuse; with it, readings go through the ESP-IDF line
+//! fitting scheme, so `read_raw_mv` and everything above it use corrected
+//! millivolts. Without it, the plain conversion is used and a warning
+//! logged. `diagnostics` has the raw counts of the last reading with both
+//! conversions, for `/api/diag`.
+//!
+//! # Filter Differential
+//! With `filter_pressure`, a second transducer after the whole-house filter
+//! on GPIO39 (ADC1_CH3, same divider) reads the filter's outlet, and
+//! `read_differential` the pressure lost across it. Both channels share one
+//! `AdcDriver` through a `SharedAdc`. The post-filter transducer is taken to
+//! be the same part as the line one, with its own zero (`psi2_min_mv`, set by
+//! its `calibrate_zero`). The drop is only meaningful while water flows;
+//! with no flow both sides read the same.
 //!
 //! # Voltage Divider
 //! With 10kΩ/12kΩ divider (ratio 0.545):
 //! - 0.5V sensor → 0.27V at ADC
 //! - 4.5V sensor → 2.45V at ADC
+//! - 5.5V sensor → 3.0V at ADC, the most the profile allows
 //!
 //! ```text
-//! Sensor out ──[10kΩ]──┬── GPIO36 (ADC1_CH0)
+//! Sensor out ──[10kΩ]──┬── GPIO36 (ADC1_CH0), GPIO39 (ADC1_CH3) after the filter
 //!                      │
 //!                    [12kΩ]
 //!                      │
 //!                     GND
 //! ```
+//!
+//! ADC1 is shared with the pump CT clamp (`ct_clamp`) through a `SharedAdc`.
+
+use std::sync::Arc;
 
 use esp_idf_svc::hal::{
     adc::{
         attenuation::DB_11,
-        oneshot::{config::AdcChannelConfig, AdcChannelDriver, AdcDriver},
+        oneshot::{
+            config::{AdcChannelConfig, Calibration},
+            AdcChannelDriver, AdcDriver,
+        },
         ADC1,
     },
-    gpio::Gpio36,
+    gpio::{ADCPin, Gpio36},
+    peripheral::Peripheral,
 };
+use esp_idf_svc::sys::{
+    adc_cali_line_fitting_efuse_val_t, adc_cali_line_fitting_efuse_val_t_ADC_CALI_LINE_FITTING_EFUSE_VAL_DEFAULT_VREF,
+    adc_cali_scheme_line_fitting_check_efuse, EspError, ESP_ERR_INVALID_STATE, ESP_OK,
+};
+use log::*;
+
+use crate::config::Config;
+use crate::diag::PressureDiag;
 
 /// Voltage divider ratio: R2/(R1+R2) = 12/(10+12)
 const DIVIDER_RATIO: f32 = 0.545;
 
-/// Sensor minimum voltage (0 PSI)
-const SENSOR_MIN_MV: f32 = 500.0;
-/// Sensor maximum voltage (100 PSI)
-const SENSOR_MAX_MV: f32 = 4500.0;
-/// Sensor pressure range
-const SENSOR_MAX_PSI: f32 = 100.0;
+/// Supply a ratiometric profile is given at (mV)
+pub const REFERENCE_SUPPLY_MV: u16 = 5000;
+/// Smallest span from the 0 PSI output to full scale (mV)
+pub const MIN_SPAN_MV: u16 = 500;
+/// Furthest a calibrated zero may be from the present one (mV)
+pub const MAX_ZERO_SHIFT_MV: f32 = 250.0;
+/// Readings averaged for one pressure value
+const SAMPLES: u32 = 8;
+/// Full-scale reading of the 12-bit ADC
+const ADC_MAX: u32 = 4095;
+/// Millivolts at `ADC_MAX` with 11 dB attenuation, as esp-idf-hal converts
+/// without calibration
+const UNCALIBRATED_FULL_SCALE_MV: u32 = 2450;
+
+/// Output range and full scale of a pressure transducer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransducerProfile {
+    /// Output at 0 PSI (mV)
+    pub min_mv: u16,
+    /// Output at full scale (mV)
+    pub max_mv: u16,
+    /// Full scale (PSI)
+    pub max_psi: u16,
+    /// Output proportional to the supply; `min_mv` and `max_mv` are at
+    /// `REFERENCE_SUPPLY_MV`
+    pub ratiometric: bool,
+}
+
+impl Default for TransducerProfile {
+    /// 0.5-4.5 V, 100 PSI
+    fn default() -> Self {
+        Self { min_mv: 500, max_mv: 4500, max_psi: 100, ratiometric: false }
+    }
+}
+
+impl TransducerProfile {
+    /// Profile stored in `config`; a full scale less than `MIN_SPAN_MV`
+    /// above the zero is moved up to it
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            min_mv: config.psi_min_mv,
+            max_mv: config.psi_max_mv.max(config.psi_min_mv + MIN_SPAN_MV),
+            max_psi: config.psi_range,
+            ratiometric: config.psi_ratiometric == 1,
+        }
+    }
+
+    /// Profile of the post-filter transducer: the line part, zeroed at
+    /// `psi2_min_mv` with the same span
+    pub fn post_filter(config: &Config) -> Self {
+        let line = Self::from_config(config);
+        Self { min_mv: config.psi2_min_mv, max_mv: config.psi2_min_mv + (line.max_mv - line.min_mv), ..line }
+    }
+
+    /// Profile stored in `config` for the transducer at `tap`
+    pub fn for_tap(config: &Config, tap: Tap) -> Self {
+        match tap {
+            Tap::Line => Self::from_config(config),
+            Tap::PostFilter => Self::post_filter(config),
+        }
+    }
+
+    /// Output voltages scale by this at `supply_mv`
+    fn scale(&self, supply_mv: u16) -> f32 {
+        if self.ratiometric {
+            supply_mv as f32 / REFERENCE_SUPPLY_MV as f32
+        } else {
+            1.0
+        }
+    }
+
+    /// Pressure for a sensor output of `sensor_mv` at `supply_mv`, by linear
+    /// interpolation; not clamped
+    fn psi(&self, sensor_mv: f32, supply_mv: u16) -> f32 {
+        let scale = self.scale(supply_mv);
+        let zero = self.min_mv as f32 * scale;
+        let span = (self.max_mv - self.min_mv) as f32 * scale;
+        (sensor_mv - zero) / span * self.max_psi as f32
+    }
+}
+
+/// ADC1 driver shared by the pressure channels and the pump CT clamp
+pub type SharedAdc<'d> = Arc<AdcDriver<'d, ADC1>>;
 
-/// PSI per foot of water column (hydrostatic pressure)
-const PSI_PER_FOOT: f32 = 0.433;
+/// Take ADC1 for the pressure channels and the pump CT clamp
+pub fn shared_adc<'d>(adc: impl Peripheral<P = ADC1> + 'd) -> Result<SharedAdc<'d>, EspError> {
+    Ok(Arc::new(AdcDriver::new(adc)?))
+}
+
+/// Where a transducer sits, deciding which zero `calibrate_zero` stores
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tap {
+    /// Line pressure, before the filter (`psi_min_mv`/`psi_max_mv`)
+    Line,
+    /// After the whole-house filter (`psi2_min_mv`)
+    PostFilter,
+}
 
-/// Pressure sensor driver for GPIO36 (ADC1_CH0)
-pub struct PressureSensor<'d> {
-    channel: AdcChannelDriver<'d, Gpio36, AdcDriver<'d, ADC1>>,
+/// Pressure sensor driver, GPIO36 (ADC1_CH0) unless another ADC1 pin is given
+pub struct PressureSensor<'d, P: ADCPin<Adc = ADC1> = Gpio36> {
+    channel: AdcChannelDriver<'d, P, SharedAdc<'d>>,
+    tap: Tap,
+    profile: TransducerProfile,
+    /// Transducer supply, for ratiometric profiles (mV)
+    supply_mv: u16,
+    /// Whether readings go through the eFuse calibration
+    calibrated: bool,
+    /// ADC values of the last reading
+    last: PressureDiag,
 }
 
-impl<'d> PressureSensor<'d> {
+impl<'d, P: ADCPin<Adc = ADC1>> PressureSensor<'d, P> {
     /// Create a new pressure sensor
     ///
     /// # Arguments
-    /// * `adc` - ADC1 peripheral
-    /// * `pin` - GPIO36 pin
+    /// * `adc` - ADC1 driver, from `shared_adc`
+    /// * `pin` - GPIO36, or GPIO39 for the post-filter transducer
+    /// * `tap` - where the transducer sits
     pub fn new(
-        adc: impl esp_idf_svc::hal::peripheral::Peripheral<P = ADC1> + 'd,
-        pin: impl esp_idf_svc::hal::peripheral::Peripheral<P = Gpio36> + 'd,
+        adc: SharedAdc<'d>,
+        pin: impl Peripheral<P = P> + 'd,
+        tap: Tap,
     ) -> Result<Self, esp_idf_svc::sys::EspError> {
-        let adc_driver = AdcDriver::new(adc)?;
-
-        // Configure channel with 11dB attenuation for 150-2450mV range
+        // Configure channel with 11dB attenuation for 150-3100mV range
+        let calibrated = efuse_calibrated();
+        if !calibrated {
+            warn!("Pressure: no ADC calibration in eFuse, readings may be off by tens of mV");
+        }
         let config = AdcChannelConfig {
             attenuation: DB_11,
+            calibration: if calibrated { Calibration::Line } else { Calibration::None },
             ..Default::default()
         };
-        let channel = AdcChannelDriver::new(adc_driver, pin, &config)?;
+        let channel = AdcChannelDriver::new(adc, pin, &config)?;
+
+        Ok(Self {
+            channel,
+            tap,
+            profile: TransducerProfile::default(),
+            supply_mv: REFERENCE_SUPPLY_MV,
+            calibrated,
+            last: PressureDiag::default(),
+        })
+    }
+
+    /// Set the transducer profile
+    pub fn set_profile(&mut self, profile: TransducerProfile) {
+        self.profile = profile;
+    }
+
+    /// Set the transducer supply (`Config::psi_supply_mv`), used by ratiometric profiles
+    pub fn set_supply(&mut self, mv: u16) {
+        self.supply_mv = mv;
+    }
 
-        Ok(Self { channel })
+    /// Take the present output as 0 PSI and persist it to `config`, as the
+    /// line or post-filter zero depending on the tap
+    ///
+    /// Call with the line depressurized. Returns the new zero in millivolts
+    /// (at `REFERENCE_SUPPLY_MV` for a ratiometric profile), or
+    /// `ESP_ERR_INVALID_STATE` with the reading more than `MAX_ZERO_SHIFT_MV`
+    /// from the present zero, leaving it in place.
+    pub fn calibrate_zero(&mut self, config: &mut Config) -> Result<u16, EspError> {
+        let sensor_mv = self.read_average_mv()?;
+        let scale = self.profile.scale(self.supply_mv);
+        let zero_mv = self.profile.min_mv as f32 * scale;
+        if (sensor_mv - zero_mv).abs() > MAX_ZERO_SHIFT_MV {
+            warn!(
+                "Pressure: {:.0} mV is too far from the {:.0} mV zero, is the line depressurized?",
+                sensor_mv, zero_mv
+            );
+            return Err(EspError::from_infallible::<ESP_ERR_INVALID_STATE>());
+        }
+        let before = self.profile;
+        let min_mv = (sensor_mv / scale).round() as u16;
+        match self.tap {
+            Tap::Line => {
+                let max_mv = (before.max_mv as i32 + min_mv as i32 - before.min_mv as i32) as u16;
+                config.set_psi_min_mv(min_mv)?;
+                config.set_psi_max_mv(max_mv)?;
+            }
+            // The span follows the line transducer's profile
+            Tap::PostFilter => config.set_psi2_min_mv(min_mv)?,
+        }
+        self.profile = TransducerProfile::for_tap(config, self.tap);
+        info!(
+            "Pressure: {:?} zero calibrated at {} mV, was {} mV ({:+.1} PSI)",
+            self.tap,
+            self.profile.min_mv,
+            before.min_mv,
+            before.psi(sensor_mv, self.supply_mv)
+        );
+        Ok(self.profile.min_mv)
     }
 
-    /// Read raw ADC value in millivolts (at the ADC pin, after divider)
+    /// Read the ADC in millivolts (at the ADC pin, after divider), calibrated
+    /// when the eFuse allows
     pub fn read_raw_mv(&mut self) -> Result<u16, esp_idf_svc::sys::EspError> {
         self.channel.read()
     }
 
+    /// ADC values of the last pressure reading, raw and converted both ways
+    pub fn diagnostics(&self) -> PressureDiag {
+        self.last.clone()
+    }
+
     /// Read sensor voltage in millivolts (before divider, actual sensor output)
     pub fn read_sensor_mv(&mut self) -> Result<u32, esp_idf_svc::sys::EspError> {
         let raw_mv = self.read_raw_mv()? as f32;
@@ -78,40 +298,62 @@ impl<'d> PressureSensor<'d> {
         Ok(sensor_mv as u32)
     }
 
-    /// Read pressure in PSI
+    /// Read pressure in PSI at the sensor
     ///
-    /// Returns pressure clamped to 0-100 PSI range.
-    /// Includes averaging for stability.
-    ///
-    /// # Arguments
-    /// * `height_feet` - Sensor height above ground level in feet (for hydrostatic compensation)
-    pub fn read_psi(&mut self, height_feet: f32) -> Result<f32, esp_idf_svc::sys::EspError> {
-        // Average multiple readings for stability
-        const SAMPLES: u32 = 8;
-        let mut sum: u32 = 0;
+    /// Averages several readings for stability. Not clamped: below the zero
+    /// the result is negative, past the full-scale output beyond full scale.
+    /// The hydrostatic head between the sensor and the gauge point is
+    /// compensated by the caller (see `correction::HeadMode`).
+    pub fn read_psi(&mut self) -> Result<f32, esp_idf_svc::sys::EspError> {
+        let sensor_mv = self.read_average_mv()?;
+        Ok(self.profile.psi(sensor_mv, self.supply_mv))
+    }
 
+    /// Read pressure as integer PSI (rounded)
+    pub fn read_psi_u16(&mut self) -> Result<u16, esp_idf_svc::sys::EspError> {
+        let psi = self.read_psi()?;
+        Ok(psi.round() as u16)
+    }
+
+    /// Sensor voltage averaged over `SAMPLES` readings (mV, before divider)
+    fn read_average_mv(&mut self) -> Result<f32, EspError> {
+        let (mut raw_sum, mut mv_sum) = (0u32, 0u32);
         for _ in 0..SAMPLES {
-            sum += self.read_raw_mv()? as u32;
+            let raw = self.channel.read_raw()?;
+            raw_sum += raw as u32;
+            mv_sum += self.channel.raw_to_mv(raw)? as u32;
         }
-
-        let avg_raw_mv = sum as f32 / SAMPLES as f32;
-
+        let raw = (raw_sum / SAMPLES) as u16;
+        self.last = PressureDiag {
+            raw,
+            uncalibrated_mv: (raw as u32 * UNCALIBRATED_FULL_SCALE_MV / ADC_MAX) as u16,
+            calibrated_mv: (mv_sum / SAMPLES) as u16,
+            calibration: if self.calibrated { "line fitting" } else { "none" },
+        };
         // Compensate for voltage divider
-        let sensor_mv = avg_raw_mv / DIVIDER_RATIO;
-
-        // Convert to PSI: linear interpolation from 500mV-4500mV to 0-100 PSI
-        let psi = (sensor_mv - SENSOR_MIN_MV) / (SENSOR_MAX_MV - SENSOR_MIN_MV) * SENSOR_MAX_PSI;
-
-        // Compensate for sensor height above ground level
-        let psi = psi + (height_feet * PSI_PER_FOOT);
-
-        // Clamp to valid range
-        Ok(psi.clamp(0.0, SENSOR_MAX_PSI))
+        Ok(mv_sum as f32 / SAMPLES as f32 / DIVIDER_RATIO)
     }
+}
 
-    /// Read pressure as integer PSI (rounded)
-    pub fn read_psi_u16(&mut self, height_feet: f32) -> Result<u16, esp_idf_svc::sys::EspError> {
-        let psi = self.read_psi(height_feet)?;
-        Ok(psi.round() as u16)
-    }
+/// Pressure lost between `upstream` and `downstream` (PSI), read back to back
+///
+/// Near 0 with no flow, and a little below it when the two transducers
+/// disagree within their tolerance.
+pub fn read_differential<P, Q>(
+    upstream: &mut PressureSensor<'_, P>,
+    downstream: &mut PressureSensor<'_, Q>,
+) -> Result<f32, EspError>
+where
+    P: ADCPin<Adc = ADC1>,
+    Q: ADCPin<Adc = ADC1>,
+{
+    Ok(upstream.read_psi()? - downstream.read_psi()?)
+}
+
+/// eFuse holds the ADC reference voltage or two-point values, as burnt at
+/// the factory on most modules
+pub(crate) fn efuse_calibrated() -> bool {
+    let mut value: adc_cali_line_fitting_efuse_val_t = 0;
+    let result = unsafe { adc_cali_scheme_line_fitting_check_efuse(&mut value) };
+    result == ESP_OK && value != adc_cali_line_fitting_efuse_val_t_ADC_CALI_LINE_FITTING_EFUSE_VAL_DEFAULT_VREF
 }
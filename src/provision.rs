@@ -0,0 +1,408 @@
+//! Console provisioning
+//!
+//! Lets a laptop configure a unit over the USB/UART console before it has
+//! ever seen a network. The protocol is line based: each line is a flat JSON
+//! object, answered with a single line:
+//!
+//! - `ACK <n>` once all `n` settings have been validated and stored
+//! - `NACK <reason>` if anything is invalid; nothing is stored in that case
+//! - `NACK storage error <code>` if storing fails part way, followed by the
+//!   keys stored before the failure (`after mqtt_host,mqtt_port`), if any
+//!
+//! Number keys are the same as on the MQTT bulk configuration topic, so one
+//! document works for both. String keys cover the settings needed to get
+//! online (`mqtt_host`, `mqtt_user`, `mqtt_pass`, `admin_token`, `timezone`,
+//! and the PEM files `mqtt_ca`, `mqtt_cert`, `mqtt_key` with `"\n"` escapes)
+//! and the notification targets (`tank_name`, `webhook_url`). The alarm QR
+//! code on the display links to `runbook_url`. `tank_label` is the short
+//! name drawn above the tank and put before its entity names. Home Assistant
+//! names the device `ha_name` in the area `ha_area`, with `ha_prefix` in
+//! front of the entity names; `mqtt_read_only` 1 makes the unit publish only. Logs also go to `syslog_server` from
+//! `syslog_level` up. The web login
+//! is `web_user` with `admin_token` as its password; `web_login` extends it
+//! to the status pages. The WiFi fallback joins `wifi_ssid` with
+//! `wifi_pass`, and InfluxDB writes go to `influx_url`, `influx_org` and
+//! `influx_bucket` with `influx_token`. The cellular backup link takes
+//! `cell_apn` and `cell_budget_mb`, the LoRa radio `lora_mode`, `lora_node`, `lora_freq` and
+//! `lora_interval`. The sensor corrections are `radar_offset` (mm),
+//! `radar_gain` (x1000) and `radar_table`, and `psi_offset` (1/100 PSI),
+//! `psi_gain` and `psi_table`, and the pressure transducer is `psi_range`,
+//! `psi_min_mv`, `psi_max_mv`, `psi_ratiometric` and `psi_supply_mv` (see
+//! `pressure`), with `psi2_min_mv` the zero of the post-filter transducer
+//! and `alarm_clog_psi` its clogged-filter alarm; `probe_names` and
+//! `notify_templates` take the stored text forms. `well_rise` tunes the well
+//! pump detection on pressure-only installs, `budget_gal` sets the daily
+//! water budget and `flow_k_factor` the flow meter's pulses per gallon (see
+//! `flow`). `ct_amps`, `pump_volts` and `pump_pf` turn the pump CT clamp
+//! into input power (see `ct_clamp`).
+//! `"reboot": 1` restarts the unit after the ACK so all settings take effect.
+//!
+//! ```text
+//! > {"mqtt_host": "ha.local", "mqtt_port": 1883, "tank_capacity": 800, "reboot": 1}
+//! < ACK 3
+//! ```
+//!
+//! Log output shares the console, so scripts should skip lines that don't
+//! start with `ACK`/`NACK`.
+
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+
+use esp_idf_svc::sys::EspError;
+use log::*;
+
+use crate::clock;
+use crate::config::{Config, MAX_PEM_LEN, MAX_TANK_LABEL_LEN};
+use crate::correction::parse_table;
+use crate::json::{self, JsonValue};
+
+type NumberGetter = fn(&Config) -> u16;
+type NumberSetter = fn(&mut Config, u16) -> Result<(), EspError>;
+type SignedGetter = fn(&Config) -> i16;
+type SignedSetter = fn(&mut Config, i16) -> Result<(), EspError>;
+type TextGetter = fn(&Config) -> &str;
+type TextSetter = fn(&mut Config, &str) -> Result<(), EspError>;
+
+/// Number settings: (key, min, max, getter, setter)
+///
+/// The ranges also bound the Home Assistant number entities (`number_range`).
+const NUMBERS: &[(&str, u16, u16, NumberGetter, NumberSetter)] = &[
+    ("tank_capacity", 100, 2000, |c| c.tank_capacity_gallons, Config::set_tank_capacity),
+    ("sensor_height", 0, 50, |c| c.sensor_height_feet, Config::set_sensor_height),
+    ("head_mode", 0, 2, |c| c.head_mode, Config::set_head_mode),
+    ("psi_range", 30, 300, |c| c.psi_range, Config::set_psi_range),
+    ("psi_min_mv", 0, 2500, |c| c.psi_min_mv, Config::set_psi_min_mv),
+    ("psi_max_mv", 1000, 5500, |c| c.psi_max_mv, Config::set_psi_max_mv),
+    ("psi_ratiometric", 0, 1, |c| c.psi_ratiometric, Config::set_psi_ratiometric),
+    ("psi_supply_mv", 3000, 5500, |c| c.psi_supply_mv, Config::set_psi_supply_mv),
+    ("psi2_min_mv", 0, 2500, |c| c.psi2_min_mv, Config::set_psi2_min_mv),
+    ("max_psi", 50, 300, |c| c.max_psi, Config::set_max_psi),
+    ("radar_height", 10, 500, |c| c.radar_height_cm, Config::set_radar_height),
+    ("radar_deadzone", 0, 200, |c| c.radar_deadzone_cm, Config::set_radar_deadzone),
+    ("flush_lines", 0, 240, |c| c.display_flush_lines, Config::set_display_flush_lines),
+    ("display_refresh", 0, 1440, |c| c.display_refresh_minutes, Config::set_display_refresh),
+    ("tank_fill", 0, 2, |c| c.tank_fill_pattern, Config::set_tank_fill_pattern),
+    ("reboot_day", 0, 8, |c| c.reboot_day, Config::set_reboot_day),
+    ("reboot_hour", 0, 23, |c| c.reboot_hour, Config::set_reboot_hour),
+    ("tank_shape", 0, 1, |c| c.tank_shape, Config::set_tank_shape),
+    ("radar_warmup", 0, 600, |c| c.radar_warmup_secs, Config::set_radar_warmup),
+    ("psi_warmup", 0, 600, |c| c.pressure_warmup_secs, Config::set_pressure_warmup),
+    ("level_median", 1, 15, |c| c.level_median_window, Config::set_level_median),
+    ("level_alpha", 1, 100, |c| c.level_smoothing_percent, Config::set_level_smoothing),
+    ("page_interval", 0, 600, |c| c.page_interval_secs, Config::set_page_interval),
+    ("pump_start", 0, 99, |c| c.pump_start_percent, Config::set_pump_start),
+    ("pump_stop", 1, 100, |c| c.pump_stop_percent, Config::set_pump_stop),
+    ("pump_assist", 1, 50, |c| c.pump_assist_drop_percent, Config::set_pump_assist_drop),
+    ("pump_fail_min", 1, 120, |c| c.pump_fail_minutes, Config::set_pump_fail_minutes),
+    ("pump_min_on", 0, 1800, |c| c.pump_min_on_secs, Config::set_pump_min_on),
+    ("pump_min_off", 0, 1800, |c| c.pump_min_off_secs, Config::set_pump_min_off),
+    ("pump_dry_psi", 0, 150, |c| c.pump_dry_psi, Config::set_pump_dry_psi),
+    ("pump_dry_secs", 1, 600, |c| c.pump_dry_secs, Config::set_pump_dry_secs),
+    ("efficiency_drop", 5, 90, |c| c.efficiency_drop_percent, Config::set_efficiency_drop),
+    ("ct_amps", 1, 200, |c| c.ct_amps, Config::set_ct_amps),
+    ("pump_volts", 90, 480, |c| c.pump_volts, Config::set_pump_volts),
+    ("pump_pf", 30, 100, |c| c.pump_pf_percent, Config::set_pump_pf),
+    ("alarm_low", 0, 99, |c| c.alarm_low_percent, Config::set_alarm_low),
+    ("alarm_high_psi", 0, 300, |c| c.alarm_high_psi, Config::set_alarm_high_psi),
+    ("alarm_clog_psi", 0, 50, |c| c.alarm_clog_psi, Config::set_alarm_clog_psi),
+    ("alarm_fault_secs", 5, 3600, |c| c.alarm_fault_secs, Config::set_alarm_fault_secs),
+    ("budget_gal", 0, 50000, |c| c.budget_gallons, Config::set_budget),
+    ("flow_k_factor", 100, 20000, |c| c.flow_k_factor, Config::set_flow_k_factor),
+    ("hammer_psi", 1, 100, |c| c.hammer_psi, Config::set_hammer_psi),
+    ("burst_psi", 0, 100, |c| c.burst_psi, Config::set_burst_psi),
+    ("vfd_setpoint", 5, 150, |c| c.vfd_setpoint_psi, Config::set_vfd_setpoint),
+    ("vfd_kp", 0, 10000, |c| c.vfd_kp_milli, Config::set_vfd_kp),
+    ("vfd_ki", 0, 10000, |c| c.vfd_ki_milli, Config::set_vfd_ki),
+    ("vfd_kd", 0, 10000, |c| c.vfd_kd_milli, Config::set_vfd_kd),
+    ("heater_mode", 0, 2, |c| c.heater_mode, Config::set_heater_mode),
+    ("heater_spread", 1, 10, |c| c.heater_spread_c, Config::set_heater_spread),
+    ("heater_duty", 0, 100, |c| c.heater_duty_percent, Config::set_heater_duty),
+    ("valve_travel", 1, 300, |c| c.valve_travel_secs, Config::set_valve_travel),
+    ("mqtt_port", 1, 65535, |c| c.mqtt_port, Config::set_mqtt_port),
+    ("mqtt_tls", 0, 1, |c| c.mqtt_tls, Config::set_mqtt_tls),
+    ("mqtt_read_only", 0, 1, |c| c.mqtt_read_only, Config::set_mqtt_read_only),
+    ("cell_budget_mb", 0, 10000, |c| c.cell_budget_mb, Config::set_cell_budget),
+    ("lora_mode", 0, 2, |c| c.lora_mode, Config::set_lora_mode),
+    ("lora_node", 1, 254, |c| c.lora_node, Config::set_lora_node),
+    ("lora_freq", 1370, 10200, |c| c.lora_freq, Config::set_lora_freq),
+    ("lora_interval", 10, 300, |c| c.lora_interval_secs, Config::set_lora_interval),
+    ("web_login", 0, 1, |c| c.web_login, Config::set_web_login),
+    ("well_rise", 1, 30, |c| c.well_rise_psi, Config::set_well_rise),
+    ("syslog_level", 0, 5, |c| c.syslog_level, Config::set_syslog_level),
+    ("radar_gain", 500, 2000, |c| c.radar_gain_milli, |c, v| {
+        let table = c.radar_table.clone();
+        c.set_radar_correction(c.radar_offset_mm, v, &table)
+    }),
+    ("psi_gain", 500, 2000, |c| c.pressure_gain_milli, |c, v| {
+        let table = c.pressure_table.clone();
+        c.set_pressure_correction(c.pressure_offset_centi, v, &table)
+    }),
+];
+
+/// Signed number settings: (key, min, max, getter, setter)
+const SIGNED: &[(&str, i16, i16, SignedGetter, SignedSetter)] = &[
+    ("radar_offset", -1000, 1000, |c| c.radar_offset_mm, |c, v| {
+        let table = c.radar_table.clone();
+        c.set_radar_correction(v, c.radar_gain_milli, &table)
+    }),
+    ("psi_offset", -2000, 2000, |c| c.pressure_offset_centi, |c, v| {
+        let table = c.pressure_table.clone();
+        c.set_pressure_correction(v, c.pressure_gain_milli, &table)
+    }),
+];
+
+/// String settings: (key, max length, secret, getter, setter)
+///
+/// Passwords, tokens and private keys are secret: they are only read back
+/// into a configuration export that asks for them.
+const TEXTS: &[(&str, usize, bool, TextGetter, TextSetter)] = &[
+    ("mqtt_host", 64, false, |c| &c.mqtt_broker, Config::set_mqtt_broker),
+    ("mqtt_user", 64, false, |c| &c.mqtt_username, Config::set_mqtt_username),
+    ("mqtt_pass", 64, true, |c| &c.mqtt_password, Config::set_mqtt_password),
+    ("mqtt_ca", MAX_PEM_LEN, false, |c| &c.mqtt_ca_cert, Config::set_mqtt_ca_cert),
+    ("mqtt_cert", MAX_PEM_LEN, false, |c| &c.mqtt_client_cert, Config::set_mqtt_client_cert),
+    ("mqtt_key", MAX_PEM_LEN, true, |c| &c.mqtt_client_key, Config::set_mqtt_client_key),
+    ("admin_token", 64, true, |c| &c.admin_token, Config::set_admin_token),
+    ("web_user", 32, false, |c| &c.web_user, Config::set_web_user),
+    ("timezone", 64, false, |c| &c.timezone, Config::set_timezone),
+    ("tank_name", 64, false, |c| &c.tank_name, Config::set_tank_name),
+    ("tank_label", MAX_TANK_LABEL_LEN, false, |c| &c.tank_label, Config::set_tank_label),
+    ("webhook_url", 200, false, |c| &c.webhook_url, Config::set_webhook_url),
+    ("runbook_url", 200, false, |c| &c.runbook_url, Config::set_runbook_url),
+    ("cell_apn", 64, false, |c| &c.cell_apn, Config::set_cell_apn),
+    ("wifi_ssid", 32, false, |c| &c.wifi_ssid, Config::set_wifi_ssid),
+    ("wifi_pass", 64, true, |c| &c.wifi_pass, Config::set_wifi_pass),
+    ("syslog_server", 64, false, |c| &c.syslog_server, Config::set_syslog_server),
+    ("ha_name", 64, false, |c| &c.ha_device_name, Config::set_ha_device_name),
+    ("ha_area", 64, false, |c| &c.ha_area, Config::set_ha_area),
+    ("ha_prefix", 32, false, |c| &c.ha_prefix, Config::set_ha_prefix),
+    ("influx_url", 200, false, |c| &c.influx_url, Config::set_influx_url),
+    ("influx_org", 64, false, |c| &c.influx_org, Config::set_influx_org),
+    ("influx_bucket", 64, false, |c| &c.influx_bucket, Config::set_influx_bucket),
+    ("influx_token", 127, true, |c| &c.influx_token, Config::set_influx_token),
+    ("radar_table", 127, false, |c| &c.radar_table, |c, v| {
+        c.set_radar_correction(c.radar_offset_mm, c.radar_gain_milli, v)
+    }),
+    ("psi_table", 127, false, |c| &c.pressure_table, |c, v| {
+        c.set_pressure_correction(c.pressure_offset_centi, c.pressure_gain_milli, v)
+    }),
+    ("probe_names", 511, false, |c| &c.probe_names, Config::set_probe_names),
+    ("notify_templates", 1023, false, |c| &c.notify_templates, Config::set_notify_templates),
+];
+
+/// Range of a number setting, `None` for an unknown key
+pub fn number_range(key: &str) -> Option<(u16, u16)> {
+    NUMBERS.iter().find(|e| e.0 == key).map(|&(_, min, max, _, _)| (min, max))
+}
+
+/// Validated value with the setter that stores it
+#[derive(Debug, Clone)]
+enum Setting {
+    Number(NumberSetter, u16),
+    Signed(SignedSetter, i16),
+    Text(TextSetter, String),
+}
+
+/// A validated provisioning line
+#[derive(Debug, Clone)]
+pub struct Request {
+    settings: Vec<(&'static str, Setting)>,
+    /// Restart once the settings are stored
+    pub reboot: bool,
+}
+
+impl Request {
+    /// Number of settings in the request
+    pub fn len(&self) -> usize {
+        self.settings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.settings.is_empty()
+    }
+
+    /// Store all settings, in request order
+    pub fn apply(&self, cfg: &mut Config) -> Result<(), ApplyError> {
+        for (i, (_, setting)) in self.settings.iter().enumerate() {
+            let result = match setting {
+                Setting::Number(set, value) => set(cfg, *value),
+                Setting::Signed(set, value) => set(cfg, *value),
+                Setting::Text(set, value) => set(cfg, value),
+            };
+            result.map_err(|error| ApplyError { stored: self.settings[..i].iter().map(|&(key, _)| key).collect(), error })?;
+        }
+        Ok(())
+    }
+}
+
+/// Storing a setting failed; the ones before it are kept
+#[derive(Debug)]
+pub struct ApplyError {
+    /// Keys stored before the failure, in request order
+    pub stored: Vec<&'static str>,
+    pub error: EspError,
+}
+
+impl std::fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "storage error {}", self.error.code())?;
+        if !self.stored.is_empty() {
+            write!(f, " after {}", self.stored.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse and validate one provisioning line
+pub fn parse_request(line: &str) -> Result<Request, String> {
+    let members = json::parse_object(line)?;
+    let mut request = Request { settings: Vec::new(), reboot: false };
+    for (i, (key, value)) in members.iter().enumerate() {
+        if members[..i].iter().any(|(k, _)| k == key) {
+            return Err(format!("duplicate key \"{}\"", key));
+        }
+        if key == "reboot" {
+            request.reboot = *value == JsonValue::Number(1.0);
+            continue;
+        }
+        let setting = if let Some(&(name, min, max, _, set)) = NUMBERS.iter().find(|e| e.0 == key) {
+            match value {
+                JsonValue::Number(v) if *v >= min as f32 && *v <= max as f32 => (name, Setting::Number(set, v.round() as u16)),
+                JsonValue::Number(v) => return Err(format!("{} out of range {}..{} for \"{}\"", v, min, max, key)),
+                JsonValue::Text(_) => return Err(format!("expected a number for \"{}\"", key)),
+            }
+        } else if let Some(&(name, min, max, _, set)) = SIGNED.iter().find(|e| e.0 == key) {
+            match value {
+                JsonValue::Number(v) if *v >= min as f32 && *v <= max as f32 => (name, Setting::Signed(set, v.round() as i16)),
+                JsonValue::Number(v) => return Err(format!("{} out of range {}..{} for \"{}\"", v, min, max, key)),
+                JsonValue::Text(_) => return Err(format!("expected a number for \"{}\"", key)),
+            }
+        } else if let Some(&(name, max_len, _, _, set)) = TEXTS.iter().find(|e| e.0 == key) {
+            match value {
+                JsonValue::Text(v) if v.len() > max_len => return Err(format!("\"{}\" longer than {} bytes", key, max_len)),
+                JsonValue::Text(v) if key.ends_with("_table") && parse_table(v).is_none() => {
+                    return Err(format!("invalid correction table for \"{}\"", key))
+                }
+                JsonValue::Text(v) if key == "timezone" && !clock::valid_timezone(v) => {
+                    return Err(format!("invalid POSIX TZ string for \"{}\"", key))
+                }
+                JsonValue::Text(v) => (name, Setting::Text(set, v.clone())),
+                JsonValue::Number(_) => return Err(format!("expected a string for \"{}\"", key)),
+            }
+        } else {
+            return Err(format!("unknown key \"{}\"", key));
+        };
+        request.settings.push(setting);
+    }
+    Ok(request)
+}
+
+/// Current settings as a JSON object, with the same keys a request takes
+///
+/// Secrets are left out unless `secrets` is set.
+pub fn config_json(cfg: &Config, secrets: bool) -> String {
+    let numbers = NUMBERS.iter().map(|&(key, _, _, get, _)| format!("\"{}\":{}", key, get(cfg)));
+    let signed = SIGNED.iter().map(|&(key, _, _, get, _)| format!("\"{}\":{}", key, get(cfg)));
+    let texts = TEXTS
+        .iter()
+        .filter(|&&(_, _, secret, _, _)| secrets || !secret)
+        .map(|&(key, _, _, get, _)| format!("\"{}\":\"{}\"", key, json::escape(get(cfg))));
+    format!("{{{}}}", numbers.chain(signed).chain(texts).collect::<Vec<_>>().join(","))
+}
+
+/// Handle one console line
+///
+/// Returns the response and whether to reboot, or `None` for blank lines.
+pub fn handle_line(config: &Mutex<Config>, line: &str) -> Option<(String, bool)> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    let request = match parse_request(line) {
+        Ok(request) => request,
+        Err(e) => return Some((format!("NACK {}", e), false)),
+    };
+    if let Err(e) = request.apply(&mut config.lock().unwrap()) {
+        warn!("Provisioning: failed to store settings: {:?}", e);
+        return Some((format!("NACK {}", e), false));
+    }
+    info!("Provisioning: stored {} settings", request.len());
+    Some((format!("ACK {}", request.len()), request.reboot))
+}
+
+/// Listen for provisioning lines on the console in a background thread
+pub fn start(config: Arc<Mutex<Config>>) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name("provision".into())
+        .stack_size(6 * 1024)
+        .spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else { continue };
+                let Some((response, reboot)) = handle_line(&config, &line) else { continue };
+                println!("{}", response);
+                if reboot {
+                    info!("Provisioning: rebooting");
+                    config.lock().unwrap().flush();
+                    // Let the ACK drain from the UART
+                    std::thread::sleep(std::time::Duration::from_millis(500));
+                    unsafe { esp_idf_svc::sys::esp_restart() };
+                }
+            }
+        })?;
+    info!("Provisioning: listening on console");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request = parse_request(r#"{"mqtt_host": "ha.local", "mqtt_port": 1883, "tank_capacity": 800, "reboot": 1}"#).unwrap();
+        assert_eq!(request.len(), 3);
+        assert!(request.reboot);
+        assert!(parse_request("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rejects_invalid_request() {
+        assert!(parse_request(r#"{"tank_capacity": 5000}"#).unwrap_err().contains("out of range"));
+        assert!(parse_request(r#"{"tank_capacity": "800"}"#).unwrap_err().contains("expected a number"));
+        assert!(parse_request(r#"{"mqtt_host": 1}"#).unwrap_err().contains("expected a string"));
+        assert!(parse_request(r#"{"wifi_channel": 6}"#).unwrap_err().contains("unknown key"));
+        assert!(parse_request(r#"{"max_psi": 100, "max_psi": 150}"#).unwrap_err().contains("duplicate"));
+        assert!(parse_request("tank_capacity=800").is_err());
+    }
+
+    #[test]
+    fn test_number_range() {
+        assert_eq!(number_range("tank_capacity"), Some((100, 2000)));
+        assert_eq!(number_range("radar_offset"), None);
+        assert_eq!(number_range("mqtt_host"), None);
+    }
+
+    #[test]
+    fn test_correction_settings() {
+        let request = parse_request(r#"{"radar_offset": -250, "psi_gain": 1010, "radar_table": "0:0,500:520"}"#).unwrap();
+        assert_eq!(request.len(), 3);
+        assert!(parse_request(r#"{"psi_offset": -2500}"#).unwrap_err().contains("out of range"));
+        assert!(parse_request(r#"{"radar_table": "500:520"}"#).unwrap_err().contains("invalid correction table"));
+        assert!(parse_request(r#"{"psi_table": ""}"#).is_ok());
+    }
+
+    #[test]
+    fn test_secrets_are_marked() {
+        for key in ["mqtt_pass", "mqtt_key", "admin_token", "wifi_pass"] {
+            assert!(TEXTS.iter().any(|e| e.0 == key && e.2), "{} not secret", key);
+        }
+        assert!(TEXTS.iter().any(|e| e.0 == "mqtt_host" && !e.2));
+        let keys: Vec<&str> = NUMBERS
+            .iter()
+            .map(|e| e.0)
+            .chain(SIGNED.iter().map(|e| e.0))
+            .chain(TEXTS.iter().map(|e| e.0))
+            .collect();
+        assert!(keys.iter().enumerate().all(|(i, key)| !keys[..i].contains(key)));
+    }
+}
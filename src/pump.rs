@@ -0,0 +1,363 @@
+//! Duplex pump controller
+//!
+//! Keeps the tank between a start and a stop level using two fill pumps
+//! plumbed in parallel (a duplex booster station).
+//!
+//! - **Alternation**: the lead pump swaps every cycle to even out wear.
+//! - **Lag assist**: if the level keeps falling to `assist_drop` below the
+//!   start level while the lead runs, the lag pump starts as well. Both stop
+//!   at the stop level.
+//! - **Failure detection**: a pump running alone that has not raised the level
+//!   within `fail_timeout` is flagged failed and the other pump takes over.
+//!   Faults stay latched until `reset_faults()`.
+//! - **Anti-short-cycling**: a cycle runs for at least `min_on` before the
+//!   stop level is obeyed, and the next cycle waits `min_off` after the last
+//!   stop. A full tank (100%) always stops the pumps.
+//! - **Dry-run guard**: if the pressure stays below `dry_psi` for `dry_delay`
+//!   while a pump runs, the source has run dry (or the level reading that
+//!   started the pumps is wrong). All pumps are forced off and stay off until
+//!   `reset_faults()`. Without a pressure reading the guard is inactive.
+//!
+//! The controller is pure logic: it is fed the tank level, the pressure and a
+//! monotonic timestamp, and returns the desired relay states.
+
+use std::time::Duration;
+
+use log::*;
+
+#[cfg(target_os = "espidf")]
+use crate::config::Config;
+
+/// Number of pumps managed
+pub const PUMP_COUNT: usize = 2;
+
+/// Control thresholds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PumpSettings {
+    /// Start filling at or below this level (%)
+    pub start_percent: u8,
+    /// Stop filling at or above this level (%)
+    pub stop_percent: u8,
+    /// Start the lag pump this many percent below the start level
+    pub assist_drop_percent: u8,
+    /// A lone pump that doesn't raise the level for this long has failed
+    pub fail_timeout: Duration,
+    /// Shortest cycle before the stop level is obeyed
+    pub min_on: Duration,
+    /// Shortest pause between cycles
+    pub min_off: Duration,
+    /// Pressure below which a running pump counts as dry (PSI, 0 = disabled)
+    pub dry_psi: u16,
+    /// How long the pressure may stay low before the guard trips
+    pub dry_delay: Duration,
+}
+
+#[cfg(target_os = "espidf")]
+impl PumpSettings {
+    pub fn from_config(cfg: &Config) -> Self {
+        Self {
+            start_percent: cfg.pump_start_percent as u8,
+            stop_percent: cfg.pump_stop_percent as u8,
+            assist_drop_percent: cfg.pump_assist_drop_percent as u8,
+            fail_timeout: Duration::from_secs(cfg.pump_fail_minutes as u64 * 60),
+            min_on: Duration::from_secs(cfg.pump_min_on_secs as u64),
+            min_off: Duration::from_secs(cfg.pump_min_off_secs as u64),
+            dry_psi: cfg.pump_dry_psi,
+            dry_delay: Duration::from_secs(cfg.pump_dry_secs as u64),
+        }
+    }
+}
+
+/// Per-pump counters
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PumpStats {
+    /// Total run time since boot
+    pub runtime: Duration,
+    /// Number of starts since boot
+    pub starts: u32,
+    /// Latched failure flag
+    pub failed: bool,
+}
+
+/// Lead/lag controller for two pumps
+pub struct PumpController {
+    settings: PumpSettings,
+    /// Pump that starts first in the next (or current) cycle
+    lead: usize,
+    running: [bool; PUMP_COUNT],
+    stats: [PumpStats; PUMP_COUNT],
+    /// Time and level of the last observed rise while pumping
+    progress: Option<(Duration, u8)>,
+    /// Start of the current cycle
+    cycle_started: Option<Duration>,
+    /// End of the last cycle
+    stopped_at: Option<Duration>,
+    /// Latest pressure reading, `None` while the sensor isn't usable
+    pressure: Option<u16>,
+    /// When the pressure first dropped below the dry-run threshold
+    dry_since: Option<Duration>,
+    /// Latched dry-run fault
+    dry_run: bool,
+    last_update: Option<Duration>,
+}
+
+impl PumpController {
+    pub fn new(settings: PumpSettings) -> Self {
+        Self {
+            settings,
+            lead: 0,
+            running: [false; PUMP_COUNT],
+            stats: [PumpStats::default(); PUMP_COUNT],
+            progress: None,
+            cycle_started: None,
+            stopped_at: None,
+            pressure: None,
+            dry_since: None,
+            dry_run: false,
+            last_update: None,
+        }
+    }
+
+    /// Replace the thresholds (takes effect on the next update)
+    pub fn set_settings(&mut self, settings: PumpSettings) {
+        self.settings = settings;
+    }
+
+    /// Current relay states
+    pub fn running(&self) -> [bool; PUMP_COUNT] {
+        self.running
+    }
+
+    /// Whether no pump is running
+    pub fn is_idle(&self) -> bool {
+        !self.running.iter().any(|&r| r)
+    }
+
+    pub fn stats(&self) -> [PumpStats; PUMP_COUNT] {
+        self.stats
+    }
+
+    /// Latest pressure reading for the dry-run guard (`None` if unavailable)
+    pub fn set_pressure(&mut self, psi: Option<u16>) {
+        self.pressure = psi;
+    }
+
+    /// Whether the dry-run guard has tripped
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Clear latched failures
+    pub fn reset_faults(&mut self) {
+        for stats in &mut self.stats {
+            stats.failed = false;
+        }
+        self.dry_run = false;
+        info!("Pumps: faults reset");
+    }
+
+    /// Stop all pumps (e.g. in maintenance mode), keeping the runtime counters
+    pub fn stop(&mut self, now: Duration) -> [bool; PUMP_COUNT] {
+        self.account(now);
+        if !self.is_idle() {
+            self.halt(now);
+        }
+        self.running
+    }
+
+    /// Run one control step and return the desired relay states
+    pub fn update(&mut self, level: u8, now: Duration) -> [bool; PUMP_COUNT] {
+        self.account(now);
+        let s = self.settings;
+
+        if self.dry_run {
+            return self.running;
+        }
+
+        if self.is_idle() {
+            self.dry_since = None;
+            let resting = matches!(self.stopped_at, Some(at) if now.saturating_sub(at) < s.min_off);
+            if level <= s.start_percent && !resting {
+                if let Some(pump) = self.healthy_from(self.lead) {
+                    self.lead = pump;
+                    self.start(pump);
+                    self.progress = Some((now, level));
+                    self.cycle_started = Some(now);
+                }
+            }
+            return self.running;
+        }
+
+        let short_cycle = matches!(self.cycle_started, Some(at) if now.saturating_sub(at) < s.min_on);
+        if level >= 100 || (level >= s.stop_percent && !short_cycle) {
+            self.halt(now);
+            // Alternate the lead for the next cycle
+            self.lead = (self.lead + 1) % PUMP_COUNT;
+            return self.running;
+        }
+
+        // Dry-run guard
+        let dry = s.dry_psi > 0 && matches!(self.pressure, Some(psi) if psi < s.dry_psi);
+        if !dry {
+            self.dry_since = None;
+        } else if now.saturating_sub(*self.dry_since.get_or_insert(now)) >= s.dry_delay {
+            warn!("Pumps: pressure below {} PSI for {} s, dry run", s.dry_psi, s.dry_delay.as_secs());
+            self.dry_run = true;
+            self.halt(now);
+            return self.running;
+        }
+
+        // Failure detection: only meaningful with a single pump running
+        match self.progress {
+            Some((_, best)) if level > best => self.progress = Some((now, level)),
+            Some((since, _)) if self.running_count() == 1 && now - since >= s.fail_timeout => {
+                let pump = self.lead;
+                warn!("Pump {}: no level rise in {} s, marking failed", pump + 1, s.fail_timeout.as_secs());
+                self.stats[pump].failed = true;
+                self.running[pump] = false;
+                if let Some(other) = self.healthy_from(pump) {
+                    self.lead = other;
+                    self.start(other);
+                }
+                self.progress = Some((now, level));
+            }
+            _ => {}
+        }
+
+        // Lag assist when the level keeps falling
+        let assist_level = s.start_percent.saturating_sub(s.assist_drop_percent);
+        if self.running_count() == 1 && level <= assist_level {
+            let lag = (self.lead + 1) % PUMP_COUNT;
+            if !self.stats[lag].failed && !self.running[lag] {
+                info!("Pump {}: lag assist at {}%", lag + 1, level);
+                self.start(lag);
+            }
+        }
+
+        self.running
+    }
+
+    /// Stop all pumps, ending the cycle
+    fn halt(&mut self, now: Duration) {
+        self.running = [false; PUMP_COUNT];
+        self.progress = None;
+        self.dry_since = None;
+        self.stopped_at = Some(now);
+    }
+
+    fn start(&mut self, pump: usize) {
+        self.running[pump] = true;
+        self.stats[pump].starts += 1;
+        info!("Pump {}: start", pump + 1);
+    }
+
+    fn running_count(&self) -> usize {
+        self.running.iter().filter(|&&r| r).count()
+    }
+
+    /// First healthy pump starting at `from` (wrapping), if any
+    fn healthy_from(&self, from: usize) -> Option<usize> {
+        (0..PUMP_COUNT)
+            .map(|i| (from + i) % PUMP_COUNT)
+            .find(|&pump| !self.stats[pump].failed)
+    }
+
+    /// Add the time since the last update to the running pumps
+    fn account(&mut self, now: Duration) {
+        if let Some(last) = self.last_update {
+            let dt = now.saturating_sub(last);
+            for (stats, &running) in self.stats.iter_mut().zip(&self.running) {
+                if running {
+                    stats.runtime += dt;
+                }
+            }
+        }
+        self.last_update = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: PumpSettings = PumpSettings {
+        start_percent: 30,
+        stop_percent: 90,
+        assist_drop_percent: 10,
+        fail_timeout: Duration::from_secs(600),
+        min_on: Duration::ZERO,
+        min_off: Duration::ZERO,
+        dry_psi: 5,
+        dry_delay: Duration::from_secs(30),
+    };
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_lead_alternates_per_cycle() {
+        let mut pumps = PumpController::new(SETTINGS);
+        assert_eq!(pumps.update(30, secs(0)), [true, false]);
+        assert_eq!(pumps.update(90, secs(60)), [false, false]);
+        assert_eq!(pumps.update(25, secs(120)), [false, true]);
+        assert_eq!(pumps.stats()[0].runtime, secs(60));
+    }
+
+    #[test]
+    fn test_lag_assist_when_level_keeps_falling() {
+        let mut pumps = PumpController::new(SETTINGS);
+        pumps.update(30, secs(0));
+        assert_eq!(pumps.update(25, secs(30)), [true, false]);
+        assert_eq!(pumps.update(20, secs(60)), [true, true]);
+        assert_eq!(pumps.update(90, secs(90)), [false, false]);
+    }
+
+    #[test]
+    fn test_failed_pump_hands_over() {
+        let mut pumps = PumpController::new(SETTINGS);
+        pumps.update(30, secs(0));
+        assert_eq!(pumps.update(29, secs(600)), [false, true]);
+        assert!(pumps.stats()[0].failed);
+
+        // Next cycle skips the failed pump until faults are reset
+        pumps.update(90, secs(700));
+        assert_eq!(pumps.update(30, secs(800)), [false, true]);
+        pumps.reset_faults();
+        pumps.update(90, secs(900));
+        assert_eq!(pumps.update(30, secs(1000)), [true, false]);
+    }
+
+    #[test]
+    fn test_min_on_and_min_off_times() {
+        let mut pumps = PumpController::new(PumpSettings { min_on: secs(120), min_off: secs(300), ..SETTINGS });
+        pumps.update(30, secs(0));
+        // Stop level reached early: keep running until min_on, but never past full
+        assert_eq!(pumps.update(90, secs(60)), [true, false]);
+        assert_eq!(pumps.update(92, secs(120)), [false, false]);
+        // Start level reached again: wait out min_off
+        assert_eq!(pumps.update(30, secs(200)), [false, false]);
+        assert_eq!(pumps.update(30, secs(420)), [false, true]);
+        assert_eq!(pumps.update(100, secs(450)), [false, false]);
+    }
+
+    #[test]
+    fn test_dry_run_latches_until_reset() {
+        let mut pumps = PumpController::new(SETTINGS);
+        pumps.set_pressure(Some(2));
+        // Low pressure while idle doesn't count
+        assert_eq!(pumps.update(50, secs(0)), [false, false]);
+        assert_eq!(pumps.update(30, secs(10)), [true, false]);
+        // A brief recovery restarts the delay
+        assert_eq!(pumps.update(31, secs(30)), [true, false]);
+        pumps.set_pressure(Some(20));
+        assert_eq!(pumps.update(32, secs(35)), [true, false]);
+        pumps.set_pressure(Some(2));
+        assert_eq!(pumps.update(32, secs(40)), [true, false]);
+        assert_eq!(pumps.update(32, secs(70)), [false, false]);
+        assert!(pumps.is_dry_run());
+        assert_eq!(pumps.update(20, secs(100)), [false, false]);
+        pumps.reset_faults();
+        assert_eq!(pumps.update(20, secs(110)), [true, false]);
+    }
+}
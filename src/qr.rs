@@ -0,0 +1,475 @@
+//! QR code encoder
+//!
+//! Just enough of ISO/IEC 18004 to put a link on the display: byte mode,
+//! error correction level M (about 15% of the symbol can be lost) and
+//! versions 1-10, which hold up to 213 bytes. The data mask is picked with
+//! the standard penalty rules, so phones read it as well as any generator's.
+
+/// Largest version encoded (57 x 57 modules)
+pub const MAX_VERSION: u8 = 10;
+
+/// Error correction codewords per block at level M, by version
+const ECC_PER_BLOCK: [usize; MAX_VERSION as usize + 1] = [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26];
+/// Error correction blocks at level M, by version
+const BLOCKS: [usize; MAX_VERSION as usize + 1] = [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5];
+/// Level M in the format information
+const LEVEL_M_BITS: u32 = 0b00;
+/// Byte mode indicator
+const MODE_BYTE: u32 = 0b0100;
+
+/// Penalty weights of the mask rules
+const PENALTY_RUN: usize = 3;
+const PENALTY_BLOCK: usize = 3;
+const PENALTY_FINDER: usize = 40;
+const PENALTY_BALANCE: usize = 10;
+
+/// An encoded symbol: a square of dark and light modules
+#[derive(Debug, Clone, PartialEq)]
+pub struct QrCode {
+    version: u8,
+    size: usize,
+    /// Dark modules, row by row
+    modules: Vec<bool>,
+    /// Finder, timing, alignment and format modules, which masks leave alone
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encode `data` in the smallest version that holds it, `None` if it's too long
+    pub fn encode(data: &[u8]) -> Option<Self> {
+        let version = (1..=MAX_VERSION).find(|&v| data.len() <= capacity(v))?;
+        let mut code = Self::blank(version);
+        code.draw_function_patterns();
+        code.draw_codewords(&interleave(version, &data_codewords(version, data)));
+
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                code.apply_mask(mask);
+                code.draw_format_bits(mask);
+                let penalty = code.penalty();
+                code.apply_mask(mask);
+                penalty
+            })
+            .unwrap_or(0);
+        code.apply_mask(mask);
+        code.draw_format_bits(mask);
+        Some(code)
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Modules per side, without the quiet zone
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the module at column `x`, row `y` is dark
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn blank(version: u8) -> Self {
+        let size = version as usize * 4 + 17;
+        Self {
+            version,
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        }
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        let size = self.size;
+        // Timing patterns
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+        // Finder patterns with their separators
+        self.draw_finder(3, 3);
+        self.draw_finder(size - 4, 3);
+        self.draw_finder(3, size - 4);
+        // Alignment patterns, except where they'd overlap the finders
+        let positions = alignment_positions(self.version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                let finder = (i == 0 && (j == 0 || j == last)) || (i == last && j == 0);
+                if !finder {
+                    self.draw_alignment(x, y);
+                }
+            }
+        }
+        // Reserve the format areas until the mask is known
+        self.draw_format_bits(0);
+        self.draw_version();
+    }
+
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4..=4_isize {
+            for dx in -4..=4_isize {
+                let (xx, yy) = (x as isize + dx, y as isize + dy);
+                if (0..self.size as isize).contains(&xx) && (0..self.size as isize).contains(&yy) {
+                    let ring = dx.abs().max(dy.abs());
+                    self.set_function(xx as usize, yy as usize, ring != 2 && ring != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2..=2_isize {
+            for dx in -2..=2_isize {
+                let ring = dx.abs().max(dy.abs());
+                self.set_function((x as isize + dx) as usize, (y as isize + dy) as usize, ring != 1);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u8) {
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        let size = self.size;
+        // Around the top left finder
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        // Split between the other two finders
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        // Always dark
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+        let bits = version_bits(self.version);
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let (a, b) = (self.size - 11 + i % 3, i / 3);
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    /// Place the codewords in the zigzag of two-module columns, bottom right first
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let total_bits = codewords.len() * 8;
+        let mut i = 0;
+        let mut right = size as isize - 1;
+        while right >= 1 {
+            // The vertical timing column is skipped
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vert in 0..size {
+                let y = if upward { size - 1 - vert } else { vert };
+                for j in 0..2 {
+                    let x = right as usize - j;
+                    if !self.function[y * size + x] && i < total_bits {
+                        self.modules[y * size + x] = (codewords[i >> 3] >> (7 - (i & 7))) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    /// Flip the data modules selected by `mask`; applying it twice undoes it
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let flip = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                if flip && !self.function[index] {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    /// Penalty score of the current modules, lower reads better
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let row = |y: usize| -> Vec<bool> { (0..size).map(|x| self.is_dark(x, y)).collect() };
+        let column = |x: usize| -> Vec<bool> { (0..size).map(|y| self.is_dark(x, y)).collect() };
+        let lines: Vec<Vec<bool>> = (0..size).map(row).chain((0..size).map(column)).collect();
+
+        let mut penalty = 0;
+        for line in &lines {
+            // Runs of five or more modules of one color
+            let mut run = 1;
+            for i in 1..=line.len() {
+                if i < line.len() && line[i] == line[i - 1] {
+                    run += 1;
+                } else {
+                    if run >= 5 {
+                        penalty += PENALTY_RUN + run - 5;
+                    }
+                    run = 1;
+                }
+            }
+            // Finder look-alikes: 1:1:3:1:1 with four light modules on one side
+            const FINDER: [bool; 7] = [true, false, true, true, true, false, true];
+            for start in 0..line.len().saturating_sub(6) {
+                if line[start..start + 7] == FINDER {
+                    let light = |from: isize| (from..from + 4).all(|i| i < 0 || i >= line.len() as isize || !line[i as usize]);
+                    if light(start as isize - 4) || light(start as isize + 7) {
+                        penalty += PENALTY_FINDER;
+                    }
+                }
+            }
+        }
+        // 2x2 blocks of one color
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.is_dark(x, y);
+                if dark == self.is_dark(x + 1, y) && dark == self.is_dark(x, y + 1) && dark == self.is_dark(x + 1, y + 1) {
+                    penalty += PENALTY_BLOCK;
+                }
+            }
+        }
+        // Balance of dark and light, per 5% away from half
+        let dark = self.modules.iter().filter(|&&m| m).count();
+        let percent = dark * 100 / self.modules.len();
+        penalty + PENALTY_BALANCE * (percent.abs_diff(50) / 5)
+    }
+}
+
+/// Most bytes `version` holds at level M
+pub fn capacity(version: u8) -> usize {
+    (total_data_codewords(version) * 8 - 4 - count_bits(version)) / 8
+}
+
+/// Width of the byte count field
+fn count_bits(version: u8) -> usize {
+    if version < 10 {
+        8
+    } else {
+        16
+    }
+}
+
+/// Modules left for codewords once the function patterns are drawn
+fn raw_data_modules(version: u8) -> usize {
+    let v = version as usize;
+    let mut modules = (16 * v + 128) * v + 64;
+    if v >= 2 {
+        let align = v / 7 + 2;
+        modules -= (25 * align - 10) * align - 55;
+        if v >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+fn total_data_codewords(version: u8) -> usize {
+    raw_data_modules(version) / 8 - ECC_PER_BLOCK[version as usize] * BLOCKS[version as usize]
+}
+
+/// Centers of the alignment patterns along each axis
+fn alignment_positions(version: u8) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let v = version as usize;
+    let count = v / 7 + 2;
+    let size = v * 4 + 17;
+    let step = (v * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let mut positions: Vec<usize> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+/// Mode, count, data, terminator and padding, as codewords
+fn data_codewords(version: u8, data: &[u8]) -> Vec<u8> {
+    let capacity_bits = total_data_codewords(version) * 8;
+    let mut bits: Vec<bool> = Vec::with_capacity(capacity_bits);
+    let mut push = |value: u32, len: usize| bits.extend((0..len).rev().map(|i| (value >> i) & 1 != 0));
+    push(MODE_BYTE, 4);
+    push(data.len() as u32, count_bits(version));
+    for &byte in data {
+        push(byte as u32, 8);
+    }
+    let terminator = (capacity_bits - bits.len()).min(4);
+    bits.resize(bits.len() + terminator, false);
+    bits.resize(bits.len().div_ceil(8) * 8, false);
+
+    let mut codewords: Vec<u8> = bits.chunks(8).map(|byte| byte.iter().fold(0, |acc, &b| acc << 1 | b as u8)).collect();
+    for pad in [0xEC, 0x11].into_iter().cycle() {
+        if codewords.len() * 8 >= capacity_bits {
+            break;
+        }
+        codewords.push(pad);
+    }
+    codewords
+}
+
+/// Split into blocks, add their error correction and interleave them
+fn interleave(version: u8, data: &[u8]) -> Vec<u8> {
+    let blocks = BLOCKS[version as usize];
+    let ecc_len = ECC_PER_BLOCK[version as usize];
+    let raw_codewords = raw_data_modules(version) / 8;
+    // Short blocks come first, long ones carry one more data codeword
+    let short_blocks = blocks - raw_codewords % blocks;
+    let short_len = raw_codewords / blocks;
+    let divisor = rs_divisor(ecc_len);
+
+    let mut split = Vec::with_capacity(blocks);
+    let mut start = 0;
+    for i in 0..blocks {
+        let len = short_len - ecc_len + usize::from(i >= short_blocks);
+        let mut block = data[start..start + len].to_vec();
+        start += len;
+        let ecc = rs_remainder(&block, &divisor);
+        if i < short_blocks {
+            // Placeholder so all blocks line up; skipped below
+            block.push(0);
+        }
+        block.extend(ecc);
+        split.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..split[0].len() {
+        for (j, block) in split.iter().enumerate() {
+            if i != short_len - ecc_len || j >= short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+/// Reed-Solomon generator polynomial of `degree`, highest coefficient dropped
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_mul(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_mul(root, 0x02);
+    }
+    result
+}
+
+/// Error correction codewords of `data`
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_mul(d, factor);
+        }
+    }
+    result
+}
+
+/// Product in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
+
+/// Level and mask with their BCH code, masked as the standard says
+fn format_bits(mask: u8) -> u32 {
+    let data = LEVEL_M_BITS << 3 | mask as u32;
+    let mut rem = data;
+    for _ in 0..10 {
+        rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+    }
+    (data << 10 | rem) ^ 0x5412
+}
+
+/// Version number with its BCH code (versions 7 and up)
+fn version_bits(version: u8) -> u32 {
+    let mut rem = version as u32;
+    for _ in 0..12 {
+        rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+    }
+    (version as u32) << 12 | rem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_correction_and_format() {
+        // "HELLO WORLD" at 1-M, codewords from the usual worked example
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        assert_eq!(rs_remainder(&data, &rs_divisor(10)), vec![196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+        assert_eq!(format_bits(0), 0b101010000010010);
+        assert_eq!(format_bits(5), 0b100000011001110);
+        assert_eq!(version_bits(7), 0b000111110010010100);
+        assert_eq!(alignment_positions(7), vec![6, 22, 38]);
+        assert_eq!([1, 2, 9, 10].map(capacity), [14, 26, 180, 213]);
+    }
+
+    #[test]
+    fn test_encode_symbol() {
+        let url = b"https://wiki.example.com/rb?alarm=AL03";
+        let code = QrCode::encode(url).unwrap();
+        assert_eq!(code.version(), 3);
+        assert_eq!(code.size(), 29);
+        // Finder pattern corners, the separator and the timing row
+        for (x, y) in [(0, 0), (6, 6), (28, 0), (22, 6), (0, 28), (2, 24)] {
+            assert!(code.is_dark(x, y), "({}, {})", x, y);
+        }
+        assert!(!code.is_dark(7, 0) && !code.is_dark(1, 1) && !code.is_dark(21, 0));
+        assert!((8..21).all(|x| code.is_dark(x, 6) == (x % 2 == 0)));
+        assert!(code.is_dark(8, code.size() - 8));
+        // Both copies of the format information agree
+        let first: Vec<bool> = (0..6).map(|y| code.is_dark(8, y)).collect();
+        let second: Vec<bool> = (0..6).map(|i| code.is_dark(code.size() - 1 - i, 8)).collect();
+        assert_eq!(first, second);
+
+        assert_eq!(QrCode::encode(&[b'x'; 14]).unwrap().size(), 21);
+        assert_eq!(QrCode::encode(&[b'x'; 15]).unwrap().version(), 2);
+        assert_eq!(QrCode::encode(&[b'x'; 213]).unwrap().size(), 57);
+        assert_eq!(QrCode::encode(&[b'x'; 214]), None);
+    }
+}
@@ -0,0 +1,215 @@
+//! MQTT reconnect policy and offline buffering
+//!
+//! ESP-MQTT's own reconnect retries at a fixed interval and leaves the
+//! session to the application, so it is disabled and this state machine
+//! drives reconnects instead. After a drop it waits `INITIAL_DELAY`, doubling
+//! the wait after every failed attempt up to `MAX_DELAY`. Every new
+//! connection asks for a resync: command topics subscribed again and
+//! discovery re-sent (a broker restarted without persistence has lost both),
+//! then the offline queue flushed.
+//!
+//! `OfflineQueue` keeps the last few state payloads that couldn't be
+//! published, oldest first. When it is full the oldest one is dropped. Each
+//! carries the time it was taken, so a flushed sample isn't mistaken for a
+//! current one.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Wait before the first reconnect attempt
+pub const INITIAL_DELAY: Duration = Duration::from_secs(2);
+/// Longest wait between attempts
+pub const MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+/// An attempt that hasn't connected or failed by then counts as failed
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What the client should do next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    None,
+    /// Start a connection attempt
+    Reconnect,
+    /// A new connection is up: subscribe, send discovery, flush the queue
+    Resync,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Link {
+    Connecting { since: Duration },
+    Connected,
+    Waiting { until: Duration },
+}
+
+/// Reconnect state machine with exponential backoff
+#[derive(Debug)]
+pub struct Reconnect {
+    link: Link,
+    delay: Duration,
+    /// Attempts since the last connection
+    attempts: u32,
+    /// Connection and drop counts seen on the last update
+    connects: u32,
+    drops: u32,
+}
+
+impl Reconnect {
+    /// Start with the client's first connection attempt in progress
+    pub fn new(now: Duration) -> Self {
+        Self { link: Link::Connecting { since: now }, delay: INITIAL_DELAY, attempts: 1, connects: 0, drops: 0 }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.link == Link::Connected
+    }
+
+    /// Connection attempts since the link was last up
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Current wait between attempts
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Feed the connection state from the client events
+    ///
+    /// `connects` and `drops` count connected and disconnected events since
+    /// boot, so a drop and reconnect between two updates isn't missed.
+    pub fn update(&mut self, connected: bool, connects: u32, drops: u32, now: Duration) -> Action {
+        let new_session = connects != self.connects;
+        let dropped = drops != self.drops;
+        self.connects = connects;
+        self.drops = drops;
+        if new_session {
+            self.link = Link::Connected;
+            self.delay = INITIAL_DELAY;
+            self.attempts = 0;
+        }
+        if connected {
+            return if new_session { Action::Resync } else { Action::None };
+        }
+
+        match self.link {
+            Link::Connected => {
+                self.link = Link::Waiting { until: now + self.delay };
+            }
+            Link::Connecting { since } if dropped || now >= since + CONNECT_TIMEOUT => {
+                self.delay = (self.delay * 2).min(MAX_DELAY);
+                self.link = Link::Waiting { until: now + self.delay };
+            }
+            Link::Waiting { until } if now >= until => {
+                self.link = Link::Connecting { since: now };
+                self.attempts += 1;
+                return Action::Reconnect;
+            }
+            _ => {}
+        }
+        Action::None
+    }
+}
+
+/// Bounded FIFO of payloads waiting for the connection
+#[derive(Debug)]
+pub struct OfflineQueue {
+    items: VecDeque<String>,
+    capacity: usize,
+    /// Payloads dropped because the queue was full
+    dropped: u32,
+}
+
+impl OfflineQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self { items: VecDeque::with_capacity(capacity), capacity, dropped: 0 }
+    }
+
+    /// Queue a payload, dropping the oldest one when full
+    pub fn push(&mut self, payload: String) {
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.items.len() >= self.capacity {
+            self.items.pop_front();
+            self.dropped += 1;
+        }
+        self.items.push_back(payload);
+    }
+
+    /// Oldest payload
+    pub fn pop(&mut self) -> Option<String> {
+        self.items.pop_front()
+    }
+
+    /// Put back a payload that failed to publish, ahead of the rest
+    pub fn unpop(&mut self, payload: String) {
+        if self.items.len() < self.capacity {
+            self.items.push_front(payload);
+        } else {
+            self.dropped += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_backoff_and_resync() {
+        let mut link = Reconnect::new(secs(0));
+        assert_eq!(link.update(true, 1, 0, secs(1)), Action::Resync);
+        assert_eq!(link.update(true, 1, 0, secs(2)), Action::None);
+
+        // Broker goes away: 2 s, then 4 s, then 8 s between attempts
+        assert_eq!(link.update(false, 1, 1, secs(10)), Action::None);
+        assert!(!link.is_connected());
+        assert_eq!(link.update(false, 1, 1, secs(11)), Action::None);
+        assert_eq!(link.update(false, 1, 1, secs(12)), Action::Reconnect);
+        assert_eq!(link.update(false, 1, 2, secs(13)), Action::None);
+        assert_eq!(link.update(false, 1, 2, secs(17)), Action::Reconnect);
+        // No answer at all times out
+        assert_eq!(link.update(false, 1, 2, secs(46)), Action::None);
+        assert_eq!(link.update(false, 1, 2, secs(47)), Action::None);
+        assert_eq!(link.delay(), secs(8));
+        assert_eq!(link.update(false, 1, 2, secs(55)), Action::Reconnect);
+        assert_eq!(link.attempts(), 3);
+
+        // Back up: resync and start over from the initial delay
+        assert_eq!(link.update(true, 2, 2, secs(56)), Action::Resync);
+        assert_eq!((link.attempts(), link.delay()), (0, INITIAL_DELAY));
+        // A drop and reconnect between updates still resyncs
+        assert_eq!(link.update(true, 3, 3, secs(60)), Action::Resync);
+    }
+
+    #[test]
+    fn test_offline_queue_keeps_newest() {
+        let mut queue = OfflineQueue::new(3);
+        for i in 0..5 {
+            queue.push(i.to_string());
+        }
+        assert_eq!((queue.len(), queue.dropped()), (3, 2));
+        let first = queue.pop().unwrap();
+        assert_eq!(first, "2");
+        queue.unpop(first);
+        let flushed: Vec<String> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(flushed, ["2", "3", "4"]);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+//! Remote display panel
+//!
+//! A unit built with the `remote` feature has no sensors or outputs. It
+//! subscribes to the main controller's `watercontroller/state` and
+//! `watercontroller/maintenance` topics and draws the same dashboard from
+//! them, for a second display somewhere else in the house. It only ever
+//! subscribes, so it doesn't show up in Home Assistant as a second device.
+//!
+//! The broker is the one configured for MQTT (`mqtt_host`, `mqtt_port`,
+//! `mqtt_user`, `mqtt_pass`, `mqtt_tls`). A panel that hasn't heard from the
+//! controller for `STALE_AFTER` shows it as offline rather than keep showing
+//! old readings.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::alarms::Alarm;
+
+/// State document topic of the main controller
+pub const STATE_TOPIC: &str = "watercontroller/state";
+/// Maintenance mode topic (retained, `ON`/`OFF`)
+pub const MAINTENANCE_TOPIC: &str = "watercontroller/maintenance";
+
+/// Readings older than this are not shown (the controller publishes every 5 s)
+pub const STALE_AFTER: Duration = Duration::from_secs(60);
+
+/// The part of the controller's state document the dashboard shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PanelState {
+    #[serde(rename = "capacity_pct")]
+    pub capacity_percent: u8,
+    pub gallons: u16,
+    pub pressure_psi: u16,
+    pub max_psi: u16,
+    pub alarm_low_level: bool,
+    pub alarm_high_pressure: bool,
+    pub alarm_sensor_fault: bool,
+    pub alarm_pipe_burst: bool,
+    pub alarm_filter_clogged: bool,
+    /// From the maintenance topic
+    #[serde(skip)]
+    pub maintenance: bool,
+}
+
+impl PanelState {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    /// Active alarms, in `Alarm::ALL` order
+    pub fn alarms(&self) -> [bool; Alarm::ALL.len()] {
+        [
+            self.alarm_low_level,
+            self.alarm_high_pressure,
+            self.alarm_sensor_fault,
+            self.alarm_pipe_burst,
+            self.alarm_filter_clogged,
+        ]
+    }
+}
+
+#[cfg(target_os = "espidf")]
+pub use client::RemotePanel;
+
+#[cfg(target_os = "espidf")]
+mod client {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    use esp_idf_svc::mqtt::client::{EspMqttClient, EventPayload, MqttClientConfiguration, QoS};
+    use esp_idf_svc::sys::EspError;
+    use log::*;
+
+    use super::{PanelState, MAINTENANCE_TOPIC, STALE_AFTER, STATE_TOPIC};
+
+    /// Latest state received, and when
+    #[derive(Default)]
+    struct Latest {
+        state: Option<(PanelState, Instant)>,
+        maintenance: bool,
+    }
+
+    /// Subscribe-only MQTT client following the main controller
+    pub struct RemotePanel {
+        client: EspMqttClient<'static>,
+        latest: Arc<Mutex<Latest>>,
+        /// Set on each (re)connect; the subscriptions are sent from `poll`
+        subscribe_pending: Arc<AtomicBool>,
+    }
+
+    impl RemotePanel {
+        pub fn start(broker: &str, port: u16, username: &str, password: &str, tls: bool) -> Result<Self, EspError> {
+            let scheme = if tls { "mqtts" } else { "mqtt" };
+            let broker_url = format!("{}://{}:{}", scheme, broker, port);
+            info!("Remote panel: following the controller at {}", broker_url);
+
+            let mut mqtt_config = MqttClientConfiguration {
+                // The client id defaults to one derived from the MAC, so
+                // several panels can share a broker
+                username: if username.is_empty() { None } else { Some(username) },
+                password: if password.is_empty() { None } else { Some(password) },
+                ..Default::default()
+            };
+            if tls {
+                mqtt_config.crt_bundle_attach = Some(esp_idf_svc::sys::esp_crt_bundle_attach);
+            }
+
+            let latest = Arc::new(Mutex::new(Latest::default()));
+            let latest_cb = latest.clone();
+            let subscribe_pending = Arc::new(AtomicBool::new(false));
+            let subscribe_pending_cb = subscribe_pending.clone();
+            let client = EspMqttClient::new_cb(&broker_url, &mqtt_config, move |event| match event.payload() {
+                EventPayload::Connected(_) => {
+                    info!("Remote panel: connected");
+                    subscribe_pending_cb.store(true, Ordering::Relaxed);
+                }
+                EventPayload::Disconnected => warn!("Remote panel: disconnected"),
+                EventPayload::Received { topic: Some(topic), data, .. } => {
+                    let Ok(payload) = std::str::from_utf8(data) else { return };
+                    let mut latest = latest_cb.lock().unwrap();
+                    if topic == STATE_TOPIC {
+                        match PanelState::parse(payload) {
+                            Ok(state) => latest.state = Some((state, Instant::now())),
+                            Err(e) => warn!("Remote panel: bad state: {}", e),
+                        }
+                    } else if topic == MAINTENANCE_TOPIC {
+                        latest.maintenance = payload.trim().eq_ignore_ascii_case("ON");
+                    }
+                }
+                _ => {}
+            })?;
+
+            Ok(Self { client, latest, subscribe_pending })
+        }
+
+        /// Subscribe after a (re)connect; call on every loop pass
+        pub fn poll(&mut self) {
+            if !self.subscribe_pending.swap(false, Ordering::Relaxed) {
+                return;
+            }
+            for topic in [STATE_TOPIC, MAINTENANCE_TOPIC] {
+                if let Err(e) = self.client.subscribe(topic, QoS::AtMostOnce) {
+                    warn!("Remote panel: subscribe to {} failed: {:?}", topic, e);
+                    // Retried on the next pass
+                    self.subscribe_pending.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+
+        /// Latest state, or `None` while nothing recent has been received
+        pub fn state(&self) -> Option<PanelState> {
+            let latest = self.latest.lock().unwrap();
+            let (state, received) = latest.state?;
+            (received.elapsed() < STALE_AFTER).then_some(PanelState { maintenance: latest.maintenance, ..state })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_state_document() {
+        let json = r#"{"capacity_pct":72,"gallons":1234,"pressure_psi":58,"tank_capacity":1700,"max_psi":100,
+            "pump1_running":true,"pump_dry_run":false,"alarm_low_level":false,"alarm_high_pressure":true,
+            "alarm_sensor_fault":false,"used_today":310}"#;
+        let state = PanelState::parse(json).unwrap();
+        assert_eq!((state.capacity_percent, state.gallons, state.pressure_psi, state.max_psi), (72, 1234, 58, 100));
+        assert_eq!(state.alarms(), [false, true, false, false, false]);
+        assert!(!state.maintenance);
+    }
+
+    #[test]
+    fn test_rejects_malformed_state() {
+        assert!(PanelState::parse("ON").is_err());
+        assert!(PanelState::parse(r#"{"capacity_pct":"full"}"#).is_err());
+        // Keys an older controller doesn't send fall back to the defaults
+        assert_eq!(PanelState::parse(r#"{"gallons":5}"#).unwrap(), PanelState { gallons: 5, ..Default::default() });
+    }
+}
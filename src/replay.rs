@@ -0,0 +1,176 @@
+//! Replay of recorded field traces
+//!
+//! Turns field incidents into regression tests. A recorded trace of tank level
+//! and pressure is fed through the level filter, the pump controller, fill
+//! cycle tracking, the alarms and the alarm monitor the way the main loop
+//! feeds them, and
+//! the decisions they take are returned for the test to check. Nothing here
+//! touches hardware, so traces replay on the host:
+//!
+//! ```text
+//! cargo test --lib --no-default-features --features pump,notify,efficiency --target x86_64-unknown-linux-gnu replay
+//! ```
+//!
+//! Traces are CSV with the fields of a history sample (see `trace`). Recorded
+//! levels are already filtered, so replays of them use a pass-through filter.
+//! The traces the tests replay are kept in `traces/`.
+
+use std::time::Duration;
+
+#[cfg(feature = "efficiency")]
+use crate::efficiency::{Cycle, CycleTracker};
+use crate::alarms::{Alarm, Alarms, Readings, Thresholds};
+use crate::filter::LevelFilter;
+use crate::notify::{AlarmMonitor, Event, Inputs};
+use crate::pump::{PumpController, PumpSettings, PUMP_COUNT};
+pub use crate::trace::{parse_trace, TracePoint};
+
+/// A control decision
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// New pump relay states
+    Pumps([bool; PUMP_COUNT]),
+    /// Alarm event, with the pump index for pump events
+    Alarm(Event, Option<usize>),
+    /// Completed fill cycle
+    #[cfg(feature = "efficiency")]
+    Cycle(Cycle),
+}
+
+/// A decision and when it was taken
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// Seconds since the first sample
+    pub at: u32,
+    pub decision: Decision,
+}
+
+/// Control logic under replay
+pub struct Replay {
+    filter: LevelFilter,
+    pumps: PumpController,
+    alarm_state: Alarms,
+    alarms: AlarmMonitor,
+    /// Only the low-level alarm is set
+    thresholds: Thresholds,
+    #[cfg(feature = "efficiency")]
+    cycles: CycleTracker,
+}
+
+impl Replay {
+    pub fn new(settings: PumpSettings, filter: LevelFilter, low_percent: u16) -> Self {
+        Self {
+            filter,
+            pumps: PumpController::new(settings),
+            alarm_state: Alarms::new(),
+            alarms: AlarmMonitor::new(),
+            thresholds: Thresholds { low_percent, high_psi: 0, fault_after: Duration::ZERO, clog_psi: 0 },
+            #[cfg(feature = "efficiency")]
+            cycles: CycleTracker::new(),
+        }
+    }
+
+    /// Run a trace, returning the decisions in the order they were taken
+    ///
+    /// A sample older than the first (a trace not from `parse_trace`) counts
+    /// as taken at the start.
+    pub fn run(&mut self, trace: &[TracePoint]) -> Vec<Step> {
+        let Some(start) = trace.first().map(|p| p.timestamp) else {
+            return Vec::new();
+        };
+        let mut steps = Vec::new();
+        let mut relays = self.pumps.running();
+        for point in trace {
+            let at = point.timestamp.saturating_sub(start);
+            let now = Duration::from_secs(at as u64);
+            let level = self.filter.update(point.capacity_percent as u16).min(100) as u8;
+
+            self.pumps.set_pressure(Some(point.pressure_psi));
+            let outputs = self.pumps.update(level, now);
+            if outputs != relays {
+                relays = outputs;
+                steps.push(Step { at, decision: Decision::Pumps(outputs) });
+            }
+            #[cfg(feature = "efficiency")]
+            if let Some(cycle) = self.cycles.update(outputs, point.gallons, now, point.timestamp) {
+                steps.push(Step { at, decision: Decision::Cycle(cycle) });
+            }
+
+            let pump_failed: Vec<bool> = self.pumps.stats().iter().map(|stats| stats.failed).collect();
+            let readings = Readings { level: Some(level), ..Default::default() };
+            self.alarm_state.update(&readings, &self.thresholds, now);
+            let inputs = Inputs {
+                low_level: self.alarm_state.is_active(Alarm::LowLevel),
+                pump_failed: &pump_failed,
+                dry_run: self.pumps.is_dry_run(),
+                ..Default::default()
+            };
+            for (event, pump) in self.alarms.update(&inputs) {
+                steps.push(Step { at, decision: Decision::Alarm(event, pump) });
+            }
+        }
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> PumpSettings {
+        PumpSettings {
+            start_percent: 40,
+            stop_percent: 90,
+            assist_drop_percent: 10,
+            fail_timeout: Duration::from_secs(10 * 60),
+            min_on: Duration::from_secs(60),
+            min_off: Duration::from_secs(60),
+            dry_psi: 20,
+            dry_delay: Duration::from_secs(60),
+        }
+    }
+
+    fn replay(csv: &str) -> Vec<(u32, Decision)> {
+        let trace = parse_trace(csv).unwrap();
+        let mut replay = Replay::new(settings(), LevelFilter::new(1, 1.0), 25);
+        replay.run(&trace).into_iter().map(|step| (step.at, step.decision)).collect()
+    }
+
+    #[test]
+    fn test_dry_well_trace() {
+        assert_eq!(
+            replay(include_str!("../traces/dry_well.csv")),
+            [
+                (60, Decision::Pumps([true, false])),
+                (150, Decision::Pumps([false, false])),
+                (150, Decision::Alarm(Event::DryRun, None)),
+                (2400, Decision::Alarm(Event::LowLevel, None)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed_pump_trace() {
+        assert_eq!(
+            replay(include_str!("../traces/failed_pump.csv")),
+            [
+                (60, Decision::Pumps([true, false])),
+                (660, Decision::Pumps([false, true])),
+                (660, Decision::Alarm(Event::PumpFailed, Some(0))),
+                (2280, Decision::Pumps([false, false])),
+            ]
+        );
+
+        assert_eq!(parse_trace("1767225600,42,55").unwrap_err(), "line 1: expected 4 fields");
+        assert_eq!(parse_trace("1767225600,142,55,336").unwrap_err(), "line 1: bad capacity_percent");
+        assert_eq!(parse_trace("20,40,50,320\n10,40,50,320").unwrap_err(), "line 2: timestamp goes backwards");
+    }
+
+    #[test]
+    fn test_sample_before_start() {
+        let point = |timestamp| TracePoint { timestamp, capacity_percent: 30, pressure_psi: 50, gallons: 240 };
+        let mut replay = Replay::new(settings(), LevelFilter::new(1, 1.0), 25);
+        let steps = replay.run(&[point(100), point(40)]);
+        assert!(steps.iter().all(|step| step.at == 0), "{:?}", steps);
+    }
+}
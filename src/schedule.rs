@@ -0,0 +1,105 @@
+//! Maintenance reboot schedule
+//!
+//! esp-idf networking occasionally degrades after weeks of uptime (stalled
+//! sockets, leaked buffers). An optional reboot at a fixed local hour, daily or
+//! on one weekday, replaces an eventual random watchdog reset with a planned
+//! one at a quiet time. Disabled by default.
+//!
+//! Local time comes from the `Clock`, set by SNTP and the configured POSIX
+//! `TZ` string. The schedule never fires before the clock is synced, nor
+//! while a fill cycle runs or an alarm is active: the reboot waits for the
+//! next scheduled hour instead.
+
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+/// `reboot_day` value: schedule disabled
+pub const DAY_DISABLED: u16 = 0;
+/// `reboot_day` value: reboot every day
+pub const DAY_DAILY: u16 = 8;
+
+/// Minimum uptime before a scheduled reboot, so the device can't reboot
+/// again within the same scheduled hour
+const MIN_UPTIME: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// When to perform the maintenance reboot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RebootSchedule {
+    /// 0 = disabled, 1-7 = Monday-Sunday, 8 = daily
+    pub day: u16,
+    /// Local hour (0-23)
+    pub hour: u16,
+}
+
+impl RebootSchedule {
+    pub fn enabled(&self) -> bool {
+        self.day != DAY_DISABLED
+    }
+
+    /// Whether a reboot is due now
+    pub fn is_due(&self, clock: &impl Clock) -> bool {
+        if !self.enabled() || clock.uptime() < MIN_UPTIME {
+            return false;
+        }
+        let Some(now) = clock.local_time() else {
+            return false;
+        };
+        let day_matches = self.day == DAY_DAILY || self.day == now.weekday as u16;
+        day_matches && self.hour == now.hour as u16
+    }
+
+    /// Whether to reboot now: due, with the pumps idle and no alarm active
+    pub fn should_reboot(&self, clock: &impl Clock, pumps_idle: bool, alarm_active: bool) -> bool {
+        pumps_idle && !alarm_active && self.is_due(clock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{LocalTime, MockClock};
+
+    const LONG_UPTIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    fn clock_at(weekday: u8, hour: u8, uptime: Duration) -> MockClock {
+        let clock = MockClock::new();
+        clock.advance(uptime);
+        clock.set_local_time(Some(LocalTime { weekday, hour, minute: 0 }));
+        clock
+    }
+
+    #[test]
+    fn test_weekly_schedule() {
+        let schedule = RebootSchedule { day: 7, hour: 3 };
+        assert!(schedule.is_due(&clock_at(7, 3, LONG_UPTIME)));
+        assert!(!schedule.is_due(&clock_at(6, 3, LONG_UPTIME)));
+        assert!(!schedule.is_due(&clock_at(7, 4, LONG_UPTIME)));
+    }
+
+    #[test]
+    fn test_disabled_and_fresh_boot_never_due() {
+        let clock = clock_at(1, 3, LONG_UPTIME);
+        assert!(!RebootSchedule { day: DAY_DISABLED, hour: 3 }.is_due(&clock));
+        assert!(RebootSchedule { day: DAY_DAILY, hour: 3 }.is_due(&clock));
+        assert!(!RebootSchedule { day: DAY_DAILY, hour: 3 }.is_due(&clock_at(1, 3, Duration::from_secs(60))));
+    }
+
+    #[test]
+    fn test_unsynced_clock_never_due() {
+        let clock = clock_at(1, 3, LONG_UPTIME);
+        clock.set_local_time(None);
+        assert!(!RebootSchedule { day: DAY_DAILY, hour: 3 }.is_due(&clock));
+    }
+
+    #[test]
+    fn test_waits_for_idle_pumps_and_no_alarms() {
+        let clock = clock_at(1, 3, LONG_UPTIME);
+        let schedule = RebootSchedule { day: DAY_DAILY, hour: 3 };
+        assert!(schedule.should_reboot(&clock, true, false));
+        assert!(!schedule.should_reboot(&clock, false, false));
+        assert!(!schedule.should_reboot(&clock, true, true));
+        // Not due: idle and quiet doesn't matter
+        assert!(!schedule.should_reboot(&clock_at(1, 4, LONG_UPTIME), true, false));
+    }
+}
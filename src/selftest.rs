@@ -0,0 +1,247 @@
+//! End-to-end self-test
+//!
+//! Remote support starts it with `POST /api/selftest` and reads the report
+//! with `GET /api/selftest` (see `web`). The checks need the sensors and
+//! connections the main loop owns, so the loop runs them, one step per pass,
+//! and shows the progress on the display. Each step ends passed or failed
+//! with a detail (a reading, an error), or skipped when the hardware isn't in
+//! the build or the service isn't configured:
+//!
+//! - `heap`: free heap above the low-memory threshold
+//! - `network`: link up with an address
+//! - `mqtt`: connected to the broker
+//! - `radar`: a fresh distance reading
+//! - `pressure`: a fresh reading within the transducer range
+//! - `display`: the progress screen drawn and sent to the panel
+//!
+//! Every change is pushed as a `selftest` event on the event stream. The
+//! report of the last run is kept until the next one; a run can't be
+//! started while one is going.
+
+use crate::json::escape;
+
+/// One check of the self-test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Heap,
+    Network,
+    Mqtt,
+    Radar,
+    Pressure,
+    Display,
+}
+
+impl Step {
+    /// Every step, in the order they run
+    pub const ALL: [Step; 6] = [Step::Heap, Step::Network, Step::Mqtt, Step::Radar, Step::Pressure, Step::Display];
+
+    /// Identifier in the report
+    pub fn key(self) -> &'static str {
+        match self {
+            Step::Heap => "heap",
+            Step::Network => "network",
+            Step::Mqtt => "mqtt",
+            Step::Radar => "radar",
+            Step::Pressure => "pressure",
+            Step::Display => "display",
+        }
+    }
+
+    /// Name on the display
+    pub fn label(self) -> &'static str {
+        match self {
+            Step::Heap => "Memory",
+            Step::Network => "Network",
+            Step::Mqtt => "MQTT broker",
+            Step::Radar => "Radar sensor",
+            Step::Pressure => "Pressure sensor",
+            Step::Display => "Display",
+        }
+    }
+}
+
+/// Result of a step
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// Not run yet
+    Pending,
+    Passed(String),
+    Failed(String),
+    Skipped(&'static str),
+}
+
+impl Outcome {
+    /// `pending`, `passed`, `failed` or `skipped`
+    pub fn status(&self) -> &'static str {
+        match self {
+            Outcome::Pending => "pending",
+            Outcome::Passed(_) => "passed",
+            Outcome::Failed(_) => "failed",
+            Outcome::Skipped(_) => "skipped",
+        }
+    }
+
+    /// Reading, error or reason for skipping
+    pub fn detail(&self) -> &str {
+        match self {
+            Outcome::Pending => "",
+            Outcome::Passed(detail) | Outcome::Failed(detail) => detail,
+            Outcome::Skipped(reason) => reason,
+        }
+    }
+}
+
+/// Steps of one run and their outcomes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Uptime when the run started (s)
+    pub started_secs: u64,
+    pub steps: Vec<(Step, Outcome)>,
+}
+
+impl Report {
+    fn new(started_secs: u64) -> Self {
+        Self { started_secs, steps: Step::ALL.iter().map(|&step| (step, Outcome::Pending)).collect() }
+    }
+
+    /// Next step to run, `None` once all have run
+    pub fn next(&self) -> Option<Step> {
+        self.steps.iter().find(|(_, outcome)| *outcome == Outcome::Pending).map(|&(step, _)| step)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next().is_none()
+    }
+
+    /// Steps that failed so far
+    pub fn failures(&self) -> usize {
+        self.steps.iter().filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_))).count()
+    }
+
+    /// JSON document served at `/api/selftest` and sent as `selftest` events
+    pub fn to_json(&self) -> String {
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|(step, outcome)| {
+                format!(
+                    r#"{{"step":"{}","label":"{}","status":"{}","detail":"{}"}}"#,
+                    step.key(),
+                    step.label(),
+                    outcome.status(),
+                    escape(outcome.detail())
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"started_secs":{},"finished":{},"passed":{},"steps":[{}]}}"#,
+            self.started_secs,
+            self.is_finished(),
+            self.is_finished() && self.failures() == 0,
+            steps.join(",")
+        )
+    }
+}
+
+/// Self-test requests from the web server and the report, shared with the main loop
+#[derive(Debug, Default)]
+pub struct SelfTest {
+    requested: bool,
+    report: Option<Report>,
+}
+
+impl SelfTest {
+    /// Ask the main loop for a run; `false` while one is already going
+    pub fn request(&mut self) -> bool {
+        if self.is_running() {
+            return false;
+        }
+        self.requested = true;
+        true
+    }
+
+    /// Requested or started, and not finished yet
+    pub fn is_running(&self) -> bool {
+        self.requested || self.report.as_ref().is_some_and(|report| !report.is_finished())
+    }
+
+    /// Start a requested run at `uptime_secs`; `true` when one was started
+    pub fn start(&mut self, uptime_secs: u64) -> bool {
+        if !std::mem::take(&mut self.requested) {
+            return false;
+        }
+        self.report = Some(Report::new(uptime_secs));
+        true
+    }
+
+    /// Next step of the current run
+    pub fn next(&self) -> Option<Step> {
+        self.report.as_ref().and_then(Report::next)
+    }
+
+    /// Store the outcome of `step`
+    pub fn record(&mut self, step: Step, outcome: Outcome) {
+        if let Some(slot) = self.report.as_mut().and_then(|r| r.steps.iter_mut().find(|(s, _)| *s == step)) {
+            slot.1 = outcome;
+        }
+    }
+
+    /// Report of the current or last run, `None` before the first one
+    pub fn report(&self) -> Option<&Report> {
+        self.report.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_through_steps() {
+        let mut selftest = SelfTest::default();
+        assert!(!selftest.start(10));
+        assert!(selftest.request());
+        assert!(selftest.is_running());
+        assert!(!selftest.request());
+        assert!(selftest.start(10));
+
+        let mut ran = Vec::new();
+        while let Some(step) = selftest.next() {
+            ran.push(step);
+            let outcome = match step {
+                Step::Mqtt => Outcome::Skipped("not configured"),
+                Step::Radar => Outcome::Failed("Timeout".to_string()),
+                _ => Outcome::Passed("ok".to_string()),
+            };
+            selftest.record(step, outcome);
+        }
+        assert_eq!(ran, Step::ALL);
+        assert!(!selftest.is_running());
+        let report = selftest.report().unwrap();
+        assert_eq!(report.failures(), 1);
+        // A finished run can be repeated; the old report stays until it starts
+        assert!(selftest.request());
+        assert_eq!(selftest.report().unwrap().started_secs, 10);
+        assert!(selftest.start(99));
+        assert_eq!(selftest.next(), Some(Step::Heap));
+    }
+
+    #[test]
+    fn test_report_json() {
+        let mut report = Report::new(42);
+        assert!(report.to_json().starts_with(
+            r#"{"started_secs":42,"finished":false,"passed":false,"steps":[{"step":"heap","label":"Memory","status":"pending","detail":""},"#
+        ));
+        for (step, outcome) in report.steps.iter_mut() {
+            *outcome = match step {
+                Step::Display => Outcome::Skipped("no display"),
+                _ => Outcome::Passed("ok".to_string()),
+            };
+        }
+        report.steps[3].1 = Outcome::Passed("1234 mm \"filtered\"".to_string());
+        let json = report.to_json();
+        assert!(json.starts_with(r#"{"started_secs":42,"finished":true,"passed":true,"#));
+        assert!(json.contains(r#"{"step":"radar","label":"Radar sensor","status":"passed","detail":"1234 mm \"filtered\""}"#));
+        assert!(json.ends_with(r#"{"step":"display","label":"Display","status":"skipped","detail":"no display"}]}"#));
+    }
+}
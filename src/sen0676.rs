@@ -1,24 +1,58 @@
 //! DFRobot SEN0676 80GHz mmWave Radar Liquid Level Sensor Driver
 //!
-//! Communicates via Modbus-RTU over UART.
+//! Communicates via Modbus-RTU over UART. Consecutive registers can be read
+//! in one transaction with `read_registers`; `read_measurements` uses that to
+//! fetch empty height and water level, filtered and real-time, in a single
+//! round trip. The filtered values are the sensor's own smoothing; the
+//! real-time ones are the unfiltered distance of the latest radar sweep.
+//!
+//! Every transaction waits at most `response_timeout` for the reply. CRC
+//! errors, timeouts and short replies are retried inside the driver, up to
+//! `retries` more times, after discarding whatever is left in the receive
+//! buffer; only a transaction that fails every attempt returns an error.
+//! Stray bytes ahead of a reply (noise on the line, a boot message) are
+//! skipped up to the sensor's address.
+//! Frames are kept at least `inter_frame_delay` apart. The counters in
+//! `diagnostics()` show how often that happens.
+//!
+//! The calls above wait for the reply. `start_measurements` and
+//! `poll_measurements` do the same read without waiting: each poll sends
+//! the request when due and only takes the bytes already received, so a
+//! silent sensor doesn't hold up the caller for the timeout and retries.
+//! A blocking call made meanwhile cancels the pending read.
+//!
+//! New sensors don't always ship on the default address. `scan_bus` probes
+//! every address with a short timeout, and `detect_address` switches to the
+//! first sensor that answers when the configured address stays silent.
+//!
+//! The UART is any `embedded_io` reader and writer, so the driver also
+//! builds for the host, where the tests run it against a scripted UART.
 //!
 //! # Register Map
 //! | Register | R/W | Name | Unit |
 //! |----------|-----|------|------|
-//! | 0x0001 | R | empty_height | mm |
-//! | 0x0003 | R | water_level | mm |
+//! | 0x0001 | R | empty_height (filtered) | mm |
+//! | 0x0002 | R | empty_height (real-time) | mm |
+//! | 0x0003 | R | water_level (filtered) | mm |
+//! | 0x0004 | R | water_level (real-time) | mm |
 //! | 0x0005 | R/W | installation_height | cm |
 //! | 0x03F4 | R/W | device_address | - |
 //! | 0x03F6 | R/W | baud_rate | baud/100 |
 //! | 0x07D4 | R/W | range | m |
 
-use esp_idf_svc::hal::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+use embedded_io::{Read, ReadReady, Write};
 use log::debug;
 
+use crate::diag::RadarDiag;
+
 /// Modbus register addresses
 mod registers {
   pub const EMPTY_HEIGHT: u16 = 0x0001;
+  pub const EMPTY_HEIGHT_RAW: u16 = 0x0002;
   pub const WATER_LEVEL: u16 = 0x0003;
+  pub const WATER_LEVEL_RAW: u16 = 0x0004;
   pub const INSTALLATION_HEIGHT: u16 = 0x0005;
   pub const DEVICE_ADDRESS: u16 = 0x03F4;
   pub const BAUD_RATE: u16 = 0x03F6;
@@ -35,6 +69,65 @@ mod function {
 pub const DEFAULT_ADDRESS: u8 = 0x01;
 pub const DEFAULT_BAUD_RATE: u32 = 115200;
 
+/// Most registers one read can return (250 data bytes)
+pub const MAX_READ_REGISTERS: u16 = 125;
+/// Registers 0x0001-0x0004 read by `read_measurements`
+const MEASUREMENT_REGISTERS: u16 = registers::WATER_LEVEL_RAW - registers::EMPTY_HEIGHT + 1;
+
+/// Lowest and highest address a sensor can be set to
+pub const MIN_ADDRESS: u8 = 0x01;
+pub const MAX_ADDRESS: u8 = 0xFD;
+
+/// Reply wait for each address probed by a bus scan
+pub const SCAN_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// How often the receive buffer is checked while waiting for a reply
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// Most stray bytes skipped ahead of a reply
+const MAX_NOISE: usize = 64;
+
+/// Timing and retry settings of the Modbus link
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sen0676Options {
+  /// Longest wait for a complete reply
+  pub response_timeout: Duration,
+  /// Extra attempts after a CRC error, timeout or short reply
+  pub retries: u8,
+  /// Minimum silence between the end of one transaction and the next request
+  pub inter_frame_delay: Duration,
+}
+
+impl Default for Sen0676Options {
+  fn default() -> Self {
+    Self {
+      response_timeout: Duration::from_millis(200),
+      retries: 2,
+      inter_frame_delay: Duration::from_millis(5),
+    }
+  }
+}
+
+impl Sen0676Options {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn response_timeout(mut self, timeout: Duration) -> Self {
+    self.response_timeout = timeout;
+    self
+  }
+
+  pub fn retries(mut self, retries: u8) -> Self {
+    self.retries = retries;
+    self
+  }
+
+  pub fn inter_frame_delay(mut self, delay: Duration) -> Self {
+    self.inter_frame_delay = delay;
+    self
+  }
+}
+
 /// Errors that can occur during communication
 #[derive(Debug)]
 pub enum Error {
@@ -56,17 +149,69 @@ pub enum Error {
   InvalidBaudRate,
   /// Invalid device address (must be 0x01-0xFD)
   InvalidAddress,
+  /// No sensor answered on any address
+  NotFound,
+  /// Polled without a read in progress
+  NotStarted,
+}
+
+/// Progress of a read started with `start_measurements`
+#[derive(Debug)]
+pub enum Poll<T> {
+  /// Waiting for the reply, or for a retry to go out
+  Pending,
+  Ready(T),
+  /// Failed every attempt
+  Err(Error),
+}
+
+impl Error {
+  /// A garbled or missing reply, worth another attempt
+  fn is_transient(&self) -> bool {
+    matches!(self, Error::CrcMismatch | Error::Timeout | Error::InvalidLength)
+  }
+}
+
+/// Empty height and water level read together
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurements {
+  /// Distance from sensor to liquid surface, filtered (mm)
+  pub empty_height_mm: u16,
+  /// Distance from sensor to liquid surface, real-time (mm)
+  pub empty_height_raw_mm: u16,
+  /// Installation height minus empty height, filtered (mm)
+  pub water_level_mm: u16,
+  /// Installation height minus empty height, real-time (mm)
+  pub water_level_raw_mm: u16,
+}
+
+/// Read in progress without blocking
+struct PendingRead {
+  start: u16,
+  count: u16,
+  /// Attempts after the first
+  tries: u8,
+  /// When the current attempt went out, `None` until it has
+  sent_at: Option<Instant>,
+  response: Vec<u8>,
 }
 
 /// DFRobot SEN0676 80GHz mmWave Radar driver
 pub struct Sen0676<U> {
   uart: U,
   address: u8,
+  options: Sen0676Options,
+  stats: RadarDiag,
+  /// End of the last transaction, for the inter-frame delay
+  last_frame: Option<Instant>,
+  /// Result of the last successful `read_measurements`
+  last_measurements: Option<Measurements>,
+  pending: Option<PendingRead>,
 }
 
 impl<U> Sen0676<U>
 where
-  U: Read + Write,
+  U: Read + ReadReady + Write,
 {
   /// Create a new sensor instance
   ///
@@ -74,9 +219,28 @@ where
   /// * `uart` - UART peripheral implementing Read + Write
   /// * `address` - Modbus device address (default: 0x01)
   pub fn new(uart: U, address: u8) -> Self {
+    Self::with_options(uart, address, Sen0676Options::default())
+  }
+
+  /// Create a new sensor instance with custom timing and retries
+  pub fn with_options(uart: U, address: u8, options: Sen0676Options) -> Self {
+    #[cfg(target_os = "espidf")]
     esp_idf_svc::log::set_target_level(module_path!(), log::LevelFilter::Debug)
       .unwrap();
-    Self { uart, address }
+    Self {
+      uart,
+      address,
+      options,
+      stats: RadarDiag::default(),
+      last_frame: None,
+      last_measurements: None,
+      pending: None,
+    }
+  }
+
+  /// Transaction, retry and error counters since boot
+  pub fn diagnostics(&self) -> RadarDiag {
+    self.stats.clone()
   }
 
   /// Create a new sensor instance with default address (0x01)
@@ -134,6 +298,13 @@ where
     self.read_register(registers::EMPTY_HEIGHT)
   }
 
+  /// Read the empty height of the latest sweep, before the sensor's filtering
+  ///
+  /// Returns distance in millimeters (real-time data)
+  pub fn read_empty_height_raw(&mut self) -> Result<u16, Error> {
+    self.read_register(registers::EMPTY_HEIGHT_RAW)
+  }
+
   /// Read the calculated water level
   ///
   /// Returns water level in millimeters (filtered data)
@@ -142,6 +313,13 @@ where
     self.read_register(registers::WATER_LEVEL)
   }
 
+  /// Read the water level of the latest sweep, before the sensor's filtering
+  ///
+  /// Returns water level in millimeters (real-time data)
+  pub fn read_water_level_raw(&mut self) -> Result<u16, Error> {
+    self.read_register(registers::WATER_LEVEL_RAW)
+  }
+
   /// Read the configured installation height
   ///
   /// Returns height in centimeters
@@ -172,7 +350,7 @@ where
   ///
   /// Note: After changing the address, create a new `Sen0676` instance with the new address
   pub fn set_device_address(&mut self, addr: u8) -> Result<(), Error> {
-    if addr == 0 || addr > 0xFD {
+    if !(MIN_ADDRESS..=MAX_ADDRESS).contains(&addr) {
       return Err(Error::InvalidAddress);
     }
     self.write_register(registers::DEVICE_ADDRESS, addr as u16)?;
@@ -180,6 +358,54 @@ where
     Ok(())
   }
 
+  /// Addresses of all sensors answering on the bus behind `uart`
+  ///
+  /// Probes 0x01-0xFD in turn, reading the device address register with
+  /// `SCAN_TIMEOUT` and no retries; a full scan takes about 6 s.
+  pub fn scan_bus(uart: &mut U) -> Vec<u8> {
+    Sen0676::new(uart, DEFAULT_ADDRESS).scan()
+  }
+
+  /// Probe every address on this sensor's bus, keeping the current one afterwards
+  ///
+  /// Waits at most `SCAN_TIMEOUT` per address, or less if `response_timeout`
+  /// is shorter. A Modbus exception counts as an answer: only the addressed
+  /// device sends one.
+  pub fn scan(&mut self) -> Vec<u8> {
+    let (address, options) = (self.address, self.options);
+    self.options = options.response_timeout(options.response_timeout.min(SCAN_TIMEOUT)).retries(0);
+    let found = (MIN_ADDRESS..=MAX_ADDRESS)
+      .filter(|&addr| {
+        self.address = addr;
+        matches!(
+          self.read_register(registers::DEVICE_ADDRESS),
+          Ok(_) | Err(Error::ModbusException(_))
+        )
+      })
+      .collect();
+    self.address = address;
+    self.options = options;
+    found
+  }
+
+  /// Make sure a sensor answers, adopting the first address found by a scan if not
+  ///
+  /// Returns the address in use. The configured address is tried first, so
+  /// a sensor where it's expected costs a single read.
+  pub fn detect_address(&mut self) -> Result<u8, Error> {
+    if self.read_device_address().is_ok() {
+      return Ok(self.address);
+    }
+    let addr = *self.scan().first().ok_or(Error::NotFound)?;
+    self.address = addr;
+    Ok(addr)
+  }
+
+  /// Modbus address the driver talks to
+  pub fn address(&self) -> u8 {
+    self.address
+  }
+
   /// Read the current baud rate
   ///
   /// Returns actual baud rate (e.g., 115200)
@@ -225,26 +451,92 @@ where
     self.write_register(registers::RANGE, meters)
   }
 
+  /// Read empty height and water level, filtered and real-time, in one transaction
+  ///
+  /// One round trip instead of four separate register reads. The result is
+  /// also kept for `last_measurements`.
+  pub fn read_measurements(&mut self) -> Result<Measurements, Error> {
+    let values = self.read_registers(registers::EMPTY_HEIGHT, MEASUREMENT_REGISTERS)?;
+    Ok(self.store_measurements(&values))
+  }
+
+  /// Begin the `read_measurements` read without waiting for the reply
+  ///
+  /// The request goes out on the first `poll_measurements`, once the
+  /// inter-frame delay allows; keep polling until it's no longer pending.
+  /// Does nothing while a read is already in progress.
+  pub fn start_measurements(&mut self) {
+    if self.pending.is_some() {
+      return;
+    }
+    self.stats.requests = self.stats.requests.saturating_add(1);
+    self.pending = Some(PendingRead {
+      start: registers::EMPTY_HEIGHT,
+      count: MEASUREMENT_REGISTERS,
+      tries: 0,
+      sent_at: None,
+      response: Vec::new(),
+    });
+  }
+
+  /// Check on the read started by `start_measurements`, without blocking
+  ///
+  /// `response_timeout` and `retries` apply as for `read_measurements`; a
+  /// retry goes out from a later poll once the inter-frame delay has passed.
+  pub fn poll_measurements(&mut self) -> Poll<Measurements> {
+    match self.poll_read() {
+      Poll::Ready(values) => Poll::Ready(self.store_measurements(&values)),
+      Poll::Pending => Poll::Pending,
+      Poll::Err(e) => Poll::Err(e),
+    }
+  }
+
+  /// A read started by `start_measurements` hasn't finished yet
+  pub fn is_busy(&self) -> bool {
+    self.pending.is_some()
+  }
+
+  fn store_measurements(&mut self, values: &[u16]) -> Measurements {
+    let value = |register: u16| values[(register - registers::EMPTY_HEIGHT) as usize];
+    let measurements = Measurements {
+      empty_height_mm: value(registers::EMPTY_HEIGHT),
+      empty_height_raw_mm: value(registers::EMPTY_HEIGHT_RAW),
+      water_level_mm: value(registers::WATER_LEVEL),
+      water_level_raw_mm: value(registers::WATER_LEVEL_RAW),
+    };
+    self.last_measurements = Some(measurements);
+    measurements
+  }
+
+  /// Measurements of the last successful `read_measurements`
+  pub fn last_measurements(&self) -> Option<Measurements> {
+    self.last_measurements
+  }
+
   /// Read a single holding register
   fn read_register(&mut self, register: u16) -> Result<u16, Error> {
-    // Build request: [addr] [0x03] [reg_hi] [reg_lo] [count_hi] [count_lo] [crc_lo] [crc_hi]
-    let mut request = [0u8; 8];
-    request[0] = self.address;
-    request[1] = function::READ_HOLDING_REGISTERS;
-    request[2] = (register >> 8) as u8;
-    request[3] = register as u8;
-    request[4] = 0x00; // Number of registers (high byte)
-    request[5] = 0x01; // Number of registers (low byte) - reading 1 register
+    Ok(self.read_registers(register, 1)?[0])
+  }
 
-    let crc = crc16(&request[0..6]);
-    request[6] = crc as u8; // CRC low byte
-    request[7] = (crc >> 8) as u8; // CRC high byte
+  /// Read `count` consecutive holding registers starting at `start`
+  ///
+  /// `count` must be 1-125, the most one Modbus response can carry.
+  pub fn read_registers(&mut self, start: u16, count: u16) -> Result<Vec<u16>, Error> {
+    if count == 0 || count > MAX_READ_REGISTERS {
+      return Err(Error::InvalidLength);
+    }
+    self.transact(|sensor| sensor.read_registers_once(start, count))
+  }
 
+  /// Single attempt of `read_registers`
+  fn read_registers_once(&mut self, start: u16, count: u16) -> Result<Vec<u16>, Error> {
+    let request = read_request(self.address, start, count);
     self.uart.write(&request).map_err(|_| Error::Io)?;
 
-    // Read response: [addr] [0x03] [byte_count] [data_hi] [data_lo] [crc_lo] [crc_hi]
-    let mut response = [0u8; 7];
-    self.read_exact(&mut response)?;
+    let mut response = vec![self.read_frame_start()?, 0, 0];
+    self.read_exact(&mut response[1..])?;
+    response.resize(response_len(&response), 0);
+    self.read_exact(&mut response[3..])?;
 
     debug!("TX: {:02X?}", &request);
     debug!(
@@ -253,40 +545,16 @@ where
       core::str::from_utf8(&response).unwrap_or("N/A")
     );
 
-    // Verify CRC
-    let received_crc = (response[6] as u16) << 8 | response[5] as u16;
-    let calculated_crc = crc16(&response[0..5]);
-    if received_crc != calculated_crc {
-      debug!(
-        "CRC mismatch: received 0x{:04X}, calculated 0x{:04X}",
-        received_crc, calculated_crc
-      );
-      return Err(Error::CrcMismatch);
-    }
-
-    // Check for exception response
-    if response[1] & 0x80 != 0 {
-      return Err(Error::ModbusException(response[2]));
-    }
-
-    // Verify address and function
-    if response[0] != self.address {
-      return Err(Error::AddressMismatch);
-    }
-    if response[1] != function::READ_HOLDING_REGISTERS {
-      return Err(Error::FunctionMismatch);
-    }
-    if response[2] != 2 {
-      return Err(Error::InvalidLength);
-    }
-
-    // Extract value (big-endian)
-    let value = (response[3] as u16) << 8 | response[4] as u16;
-    Ok(value)
+    parse_read_response(self.address, &response, count)
   }
 
   /// Write a single holding register
   fn write_register(&mut self, register: u16, value: u16) -> Result<(), Error> {
+    self.transact(|sensor| sensor.write_register_once(register, value))
+  }
+
+  /// Single attempt of `write_register`
+  fn write_register_once(&mut self, register: u16, value: u16) -> Result<(), Error> {
     // Build request: [addr] [0x06] [reg_hi] [reg_lo] [val_hi] [val_lo] [crc_lo] [crc_hi]
     let mut request = [0u8; 8];
     request[0] = self.address;
@@ -303,12 +571,17 @@ where
     self.uart.write(&request).map_err(|_| Error::Io)?;
 
     // Read response (echo of request): [addr] [0x06] [reg_hi] [reg_lo] [val_hi] [val_lo] [crc_lo] [crc_hi]
+    // or exception:                     [addr] [0x86] [exception_code] [crc_lo] [crc_hi]
     let mut response = [0u8; 8];
-    self.read_exact(&mut response)?;
+    response[0] = self.read_frame_start()?;
+    self.read_exact(&mut response[1..3])?;
+    let n = if response[1] & 0x80 != 0 { 5 } else { 8 };
+    self.read_exact(&mut response[3..n])?;
+    let response = &response[..n];
 
     // Verify CRC
-    let received_crc = (response[7] as u16) << 8 | response[6] as u16;
-    let calculated_crc = crc16(&response[0..6]);
+    let received_crc = (response[n - 1] as u16) << 8 | response[n - 2] as u16;
+    let calculated_crc = crc16(&response[..n - 2]);
     if received_crc != calculated_crc {
       debug!(
         "CRC mismatch: received 0x{:04X}, calculated 0x{:04X}",
@@ -333,11 +606,153 @@ where
     Ok(())
   }
 
-  /// Read exact number of bytes from UART
+  /// Run a transaction, retrying transient errors
+  ///
+  /// Cancels a read in progress from `start_measurements`.
+  fn transact<T>(&mut self, mut attempt: impl FnMut(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+    self.pending = None;
+    self.stats.requests = self.stats.requests.saturating_add(1);
+    let mut tries = 0;
+    loop {
+      if let Some(elapsed) = self.last_frame.map(|t| t.elapsed()) {
+        if elapsed < self.options.inter_frame_delay {
+          std::thread::sleep(self.options.inter_frame_delay - elapsed);
+        }
+      }
+      // Leftovers of an earlier garbled reply would shift this one
+      self.discard_input()?;
+      let result = attempt(self);
+      self.last_frame = Some(Instant::now());
+      match result {
+        Ok(value) => return Ok(value),
+        Err(e) => {
+          if self.should_retry(&e, tries) {
+            tries += 1;
+            continue;
+          }
+          return Err(e);
+        }
+      }
+    }
+  }
+
+  /// Count a failed attempt; `true` if another one is due
+  fn should_retry(&mut self, e: &Error, tries: u8) -> bool {
+    match e {
+      Error::CrcMismatch => self.stats.crc_errors = self.stats.crc_errors.saturating_add(1),
+      Error::Timeout => self.stats.timeouts = self.stats.timeouts.saturating_add(1),
+      _ => {}
+    }
+    if e.is_transient() && tries < self.options.retries {
+      self.stats.retries = self.stats.retries.saturating_add(1);
+      debug!("Retrying after {:?} ({}/{})", e, tries + 1, self.options.retries);
+      return true;
+    }
+    self.stats.failures = self.stats.failures.saturating_add(1);
+    false
+  }
+
+  /// Advance the pending read: send the request, collect the reply, retry
+  fn poll_read(&mut self) -> Poll<Vec<u16>> {
+    let Some(mut read) = self.pending.take() else {
+      return Poll::Err(Error::NotStarted);
+    };
+    match self.advance(&mut read) {
+      Ok(None) => {
+        self.pending = Some(read);
+        Poll::Pending
+      }
+      Ok(Some(values)) => {
+        self.last_frame = Some(Instant::now());
+        Poll::Ready(values)
+      }
+      Err(e) => {
+        self.last_frame = Some(Instant::now());
+        if self.should_retry(&e, read.tries) {
+          read.tries += 1;
+          read.sent_at = None;
+          read.response.clear();
+          self.pending = Some(read);
+          return Poll::Pending;
+        }
+        Poll::Err(e)
+      }
+    }
+  }
+
+  /// One non-blocking step of `read`, `Ok(None)` while it isn't complete
+  fn advance(&mut self, read: &mut PendingRead) -> Result<Option<Vec<u16>>, Error> {
+    let Some(sent_at) = read.sent_at else {
+      if self.last_frame.is_some_and(|t| t.elapsed() < self.options.inter_frame_delay) {
+        return Ok(None);
+      }
+      self.discard_input()?;
+      self.uart.write(&read_request(self.address, read.start, read.count)).map_err(|_| Error::Io)?;
+      read.sent_at = Some(Instant::now());
+      return Ok(None);
+    };
+
+    let mut byte = [0u8; 1];
+    while read.response.len() < response_len(&read.response) && self.uart.read_ready().map_err(|_| Error::Io)? {
+      if self.uart.read(&mut byte).map_err(|_| Error::Io)? == 0 {
+        break;
+      }
+      if read.response.is_empty() && byte[0] != self.address {
+        debug!("Skipping stray byte {:02X}", byte[0]);
+        continue;
+      }
+      read.response.push(byte[0]);
+    }
+    if read.response.len() == response_len(&read.response) {
+      debug!("RX: {:02X?}", &read.response);
+      return parse_read_response(self.address, &read.response, read.count).map(Some);
+    }
+    if sent_at.elapsed() >= self.options.response_timeout {
+      return Err(Error::Timeout);
+    }
+    Ok(None)
+  }
+
+  /// Drop any bytes waiting in the receive buffer
+  fn discard_input(&mut self) -> Result<(), Error> {
+    let mut byte = [0u8; 1];
+    while self.uart.read_ready().map_err(|_| Error::Io)? {
+      if self.uart.read(&mut byte).map_err(|_| Error::Io)? == 0 {
+        break;
+      }
+    }
+    Ok(())
+  }
+
+  /// Read exact number of bytes from UART, within `response_timeout`
+  ///
+  /// Only bytes already received are read, one at a time, so a missing
+  /// reply can't block past the deadline.
+  /// Wait for the first byte of a reply, skipping stray bytes ahead of it
+  fn read_frame_start(&mut self) -> Result<u8, Error> {
+    let mut byte = [0u8; 1];
+    for _ in 0..MAX_NOISE {
+      self.read_exact(&mut byte)?;
+      if byte[0] == self.address {
+        return Ok(byte[0]);
+      }
+      debug!("Skipping stray byte {:02X}", byte[0]);
+    }
+    Err(Error::InvalidLength)
+  }
+
   fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+    let deadline = Instant::now() + self.options.response_timeout;
     let mut pos = 0;
     while pos < buf.len() {
-      match self.uart.read(&mut buf[pos..]) {
+      if !self.uart.read_ready().map_err(|_| Error::Io)? {
+        if Instant::now() >= deadline {
+          return Err(Error::Timeout);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+        continue;
+      }
+      match self.uart.read(&mut buf[pos..pos + 1]) {
         Ok(0) => return Err(Error::Timeout),
         Ok(n) => pos += n,
         Err(_) => return Err(Error::Io),
@@ -347,6 +762,78 @@ where
   }
 }
 
+/// Read holding registers request: [addr] [0x03] [reg_hi] [reg_lo] [count_hi] [count_lo] [crc_lo] [crc_hi]
+fn read_request(address: u8, start: u16, count: u16) -> [u8; 8] {
+  let mut request = [0u8; 8];
+  request[0] = address;
+  request[1] = function::READ_HOLDING_REGISTERS;
+  request[2] = (start >> 8) as u8;
+  request[3] = start as u8;
+  request[4] = (count >> 8) as u8;
+  request[5] = count as u8;
+
+  let crc = crc16(&request[0..6]);
+  request[6] = crc as u8; // CRC low byte
+  request[7] = (crc >> 8) as u8; // CRC high byte
+  request
+}
+
+/// Full length of a read response, judged from what has arrived of it
+///
+/// Data:      [addr] [0x03] [byte_count] [data...] [crc_lo] [crc_hi]
+/// Exception: [addr] [0x83] [exception_code] [crc_lo] [crc_hi]
+fn response_len(received: &[u8]) -> usize {
+  match received {
+    [_, function, ..] if function & 0x80 != 0 => 5,
+    [_, _, byte_count, ..] => *byte_count as usize + 5,
+    _ => 3,
+  }
+}
+
+/// Check a complete read holding registers response and extract `count` values
+fn parse_read_response(address: u8, response: &[u8], count: u16) -> Result<Vec<u16>, Error> {
+  if response.len() < 5 {
+    return Err(Error::InvalidLength);
+  }
+
+  // Verify CRC
+  let n = response.len();
+  let received_crc = (response[n - 1] as u16) << 8 | response[n - 2] as u16;
+  let calculated_crc = crc16(&response[..n - 2]);
+  if received_crc != calculated_crc {
+    debug!(
+      "CRC mismatch: received 0x{:04X}, calculated 0x{:04X}",
+      received_crc, calculated_crc
+    );
+    return Err(Error::CrcMismatch);
+  }
+
+  // Check for exception response
+  if response[1] & 0x80 != 0 {
+    return Err(Error::ModbusException(response[2]));
+  }
+
+  // Verify address and function
+  if response[0] != address {
+    return Err(Error::AddressMismatch);
+  }
+  if response[1] != function::READ_HOLDING_REGISTERS {
+    return Err(Error::FunctionMismatch);
+  }
+  let byte_count = response[2] as usize;
+  if byte_count != count as usize * 2 || n != byte_count + 5 {
+    return Err(Error::InvalidLength);
+  }
+
+  // Extract values (big-endian)
+  Ok(
+    response[3..3 + byte_count]
+      .chunks_exact(2)
+      .map(|pair| (pair[0] as u16) << 8 | pair[1] as u16)
+      .collect(),
+  )
+}
+
 /// Calculate CRC16 with Modbus polynomial (0xA001)
 fn crc16(data: &[u8]) -> u16 {
   let mut crc: u16 = 0xFFFF;
@@ -384,4 +871,275 @@ mod tests {
     let crc = crc16(&data);
     assert_eq!(crc, 0x7599);
   }
+
+  /// Reply frame with its CRC appended
+  fn frame(payload: &[u8]) -> Vec<u8> {
+    let crc = crc16(payload);
+    let mut frame = payload.to_vec();
+    frame.extend([crc as u8, (crc >> 8) as u8]);
+    frame
+  }
+
+  /// Scripted UART: answers each request with the next canned reply and records what was sent
+  struct MockUart {
+    replies: std::collections::VecDeque<Vec<u8>>,
+    /// Addresses answering register reads once the canned replies are used up
+    devices: Vec<u8>,
+    rx: std::collections::VecDeque<u8>,
+    tx: Vec<u8>,
+  }
+
+  impl MockUart {
+    fn answering(replies: Vec<Vec<u8>>) -> Self {
+      Self {
+        replies: replies.into(),
+        devices: Vec::new(),
+        rx: Default::default(),
+        tx: Vec::new(),
+      }
+    }
+
+    fn with_devices(devices: Vec<u8>) -> Self {
+      Self {
+        devices,
+        ..Self::answering(Vec::new())
+      }
+    }
+  }
+
+  impl embedded_io::ErrorType for MockUart {
+    type Error = core::convert::Infallible;
+  }
+
+  impl Read for MockUart {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+      let n = buf.len().min(self.rx.len());
+      for (slot, byte) in buf.iter_mut().zip(self.rx.drain(..n)) {
+        *slot = byte;
+      }
+      Ok(n)
+    }
+  }
+
+  impl ReadReady for MockUart {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+      Ok(!self.rx.is_empty())
+    }
+  }
+
+  impl Write for MockUart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+      self.tx.extend_from_slice(buf);
+      if let Some(reply) = self.replies.pop_front() {
+        self.rx.extend(reply);
+      } else if self.devices.contains(&buf[0]) {
+        self.rx.extend(frame(&[buf[0], 0x03, 0x02, 0x00, buf[0]]));
+      }
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_read_measurements() {
+    // Registers 0x0001-0x0004: empty height 1200 mm (1185 real-time), water level 800 mm (815)
+    let uart = MockUart::answering(vec![frame(&[
+      0x01, 0x03, 0x08, 0x04, 0xB0, 0x04, 0xA1, 0x03, 0x20, 0x03, 0x2F,
+    ])]);
+    let mut sensor = Sen0676::new_default(uart);
+    assert_eq!(sensor.last_measurements(), None);
+    let expected = Measurements {
+      empty_height_mm: 1200,
+      empty_height_raw_mm: 1185,
+      water_level_mm: 800,
+      water_level_raw_mm: 815,
+    };
+    assert_eq!(sensor.read_measurements().unwrap(), expected);
+    assert_eq!(sensor.last_measurements(), Some(expected));
+    assert_eq!(&sensor.uart.tx[..6], &[0x01, 0x03, 0x00, 0x01, 0x00, 0x04]);
+
+    // Exception responses are shorter than a data response, and not retried
+    let uart = MockUart::answering(vec![frame(&[0x01, 0x83, 0x02])]);
+    let mut sensor = Sen0676::new_default(uart);
+    assert!(matches!(sensor.read_registers(0x0001, 2), Err(Error::ModbusException(0x02))));
+    assert!(matches!(sensor.read_registers(0x0001, 0), Err(Error::InvalidLength)));
+    assert_eq!(sensor.diagnostics().retries, 0);
+  }
+
+  #[test]
+  fn test_retries_transient_errors() {
+    let options = Sen0676Options::new()
+      .response_timeout(Duration::from_millis(5))
+      .retries(2)
+      .inter_frame_delay(Duration::ZERO);
+    let mut garbled = frame(&[0x01, 0x03, 0x02, 0x04, 0xB0]);
+    garbled[4] ^= 0x01;
+    let good = frame(&[0x01, 0x03, 0x02, 0x04, 0xB0]);
+    // A bad CRC, then no reply at all, then the real one
+    let uart = MockUart::answering(vec![garbled.clone(), Vec::new(), good]);
+    let mut sensor = Sen0676::with_options(uart, DEFAULT_ADDRESS, options);
+    assert_eq!(sensor.read_empty_height().unwrap(), 1200);
+    assert_eq!(
+      sensor.diagnostics(),
+      RadarDiag { requests: 1, retries: 2, crc_errors: 1, timeouts: 1, failures: 0 }
+    );
+
+    // Out of retries: the last error is returned
+    let uart = MockUart::answering(vec![garbled.clone(), garbled.clone(), garbled]);
+    let mut sensor = Sen0676::with_options(uart, DEFAULT_ADDRESS, options);
+    assert!(matches!(sensor.read_empty_height(), Err(Error::CrcMismatch)));
+    assert_eq!(sensor.diagnostics().failures, 1);
+    assert_eq!(sensor.uart.tx.len(), 3 * 8);
+  }
+
+  #[test]
+  fn test_request_framing() {
+    let uart = MockUart::answering(vec![
+      frame(&[0x01, 0x03, 0x02, 0x04, 0xB0]),
+      frame(&[0x01, 0x06, 0x00, 0x05, 0x03, 0xE8]),
+      frame(&[0x01, 0x86, 0x03]),
+    ]);
+    let mut sensor = Sen0676::new_default(uart);
+    assert_eq!(sensor.read_empty_height().unwrap(), 1200);
+    sensor.write_register(registers::INSTALLATION_HEIGHT, 1000).unwrap();
+    // A rejected write answers with a short exception frame
+    assert!(matches!(
+      sensor.write_register(registers::INSTALLATION_HEIGHT, 9999),
+      Err(Error::ModbusException(0x03))
+    ));
+    assert_eq!(
+      &sensor.uart.tx[..16],
+      &[
+        0x01, 0x03, 0x00, 0x01, 0x00, 0x01, 0xD5, 0xCA, // read empty height
+        0x01, 0x06, 0x00, 0x05, 0x03, 0xE8, 0x99, 0x75, // write installation height 1000
+      ]
+    );
+    assert_eq!(sensor.diagnostics().retries, 0);
+  }
+
+  #[test]
+  fn test_truncated_and_noisy_replies() {
+    let options = Sen0676Options::new()
+      .response_timeout(Duration::from_millis(5))
+      .retries(1)
+      .inter_frame_delay(Duration::ZERO);
+    let good = frame(&[0x01, 0x03, 0x02, 0x04, 0xB0]);
+    // Stray bytes ahead of the reply are skipped without a retry
+    let mut noisy = vec![0x00, 0xFF, b'O', b'K', b'\n'];
+    noisy.extend(&good);
+    let uart = MockUart::answering(vec![noisy.clone()]);
+    let mut sensor = Sen0676::with_options(uart, DEFAULT_ADDRESS, options);
+    assert_eq!(sensor.read_empty_height().unwrap(), 1200);
+    assert_eq!(sensor.diagnostics().retries, 0);
+
+    // Polled reads skip them too
+    let mut noisy = vec![0xFF, 0x00];
+    noisy.extend(frame(&[0x01, 0x03, 0x08, 0x04, 0xB0, 0x04, 0xA1, 0x03, 0x20, 0x03, 0x2F]));
+    sensor.uart.replies.push_back(noisy);
+    sensor.start_measurements();
+    assert!(matches!(sensor.poll_measurements(), Poll::Pending));
+    assert!(matches!(sensor.poll_measurements(), Poll::Ready(Measurements { empty_height_mm: 1200, .. })));
+
+    // A reply cut short times out and is retried
+    let uart = MockUart::answering(vec![good[..4].to_vec(), good.clone()]);
+    let mut sensor = Sen0676::with_options(uart, DEFAULT_ADDRESS, options);
+    assert_eq!(sensor.read_empty_height().unwrap(), 1200);
+    assert_eq!(sensor.diagnostics().timeouts, 1);
+
+    // Nothing but noise, or a byte count that doesn't match the request
+    let uart = MockUart::answering(vec![vec![0x55; 100], frame(&[0x01, 0x03, 0x04, 0x04, 0xB0, 0x00, 0x00])]);
+    let mut sensor = Sen0676::with_options(uart, DEFAULT_ADDRESS, options);
+    assert!(matches!(sensor.read_empty_height(), Err(Error::InvalidLength)));
+    assert_eq!(sensor.diagnostics().failures, 1);
+  }
+
+  #[test]
+  fn test_poll_measurements() {
+    let options = Sen0676Options::new()
+      .response_timeout(Duration::from_millis(5))
+      .retries(1)
+      .inter_frame_delay(Duration::ZERO);
+    let reply = frame(&[0x01, 0x03, 0x08, 0x04, 0xB0, 0x04, 0xA1, 0x03, 0x20, 0x03, 0x2F]);
+    // Silent on the first attempt, then the reply trickles in
+    let uart = MockUart::answering(vec![Vec::new(), Vec::new()]);
+    let mut sensor = Sen0676::with_options(uart, DEFAULT_ADDRESS, options);
+    assert!(matches!(sensor.poll_measurements(), Poll::Err(Error::NotStarted)));
+    sensor.start_measurements();
+    assert!(sensor.is_busy());
+    assert!(matches!(sensor.poll_measurements(), Poll::Pending));
+    assert_eq!(sensor.uart.tx.len(), 8);
+    std::thread::sleep(Duration::from_millis(6));
+    assert!(matches!(sensor.poll_measurements(), Poll::Pending));
+    assert!(matches!(sensor.poll_measurements(), Poll::Pending));
+    assert_eq!(sensor.uart.tx.len(), 16);
+    sensor.uart.rx.extend(&reply[..5]);
+    assert!(matches!(sensor.poll_measurements(), Poll::Pending));
+    sensor.uart.rx.extend(&reply[5..]);
+    let Poll::Ready(measurements) = sensor.poll_measurements() else {
+      panic!("reply not taken");
+    };
+    assert_eq!(measurements.water_level_raw_mm, 815);
+    assert_eq!(sensor.last_measurements(), Some(measurements));
+    assert!(!sensor.is_busy());
+    assert_eq!(
+      sensor.diagnostics(),
+      RadarDiag { requests: 1, retries: 1, crc_errors: 0, timeouts: 1, failures: 0 }
+    );
+
+    // Out of retries, and a blocking read cancels a pending one
+    sensor.start_measurements();
+    assert!(matches!(sensor.poll_measurements(), Poll::Pending));
+    std::thread::sleep(Duration::from_millis(6));
+    assert!(matches!(sensor.poll_measurements(), Poll::Pending));
+    assert!(matches!(sensor.poll_measurements(), Poll::Pending));
+    assert_eq!(sensor.uart.tx.len(), 32);
+    std::thread::sleep(Duration::from_millis(6));
+    assert!(matches!(sensor.poll_measurements(), Poll::Err(Error::Timeout)));
+    assert_eq!(sensor.diagnostics().failures, 1);
+    sensor.start_measurements();
+    sensor.uart.replies.push_back(frame(&[0x01, 0x03, 0x02, 0x04, 0xB0]));
+    assert_eq!(sensor.read_empty_height().unwrap(), 1200);
+    assert!(!sensor.is_busy());
+  }
+
+  #[test]
+  fn test_scan_and_detect_address() {
+    let options = Sen0676Options::new()
+      .response_timeout(Duration::ZERO)
+      .retries(2)
+      .inter_frame_delay(Duration::ZERO);
+    let uart = MockUart::with_devices(vec![0x07, 0x20]);
+    let mut sensor = Sen0676::with_options(uart, DEFAULT_ADDRESS, options);
+    assert_eq!(sensor.scan(), vec![0x07, 0x20]);
+    // One probe per address, none retried
+    assert_eq!(sensor.uart.tx.len(), 253 * 8);
+    assert_eq!(sensor.address(), DEFAULT_ADDRESS);
+    assert_eq!(sensor.options, options);
+
+    assert_eq!(sensor.detect_address().unwrap(), 0x07);
+    assert_eq!(sensor.read_device_address().unwrap(), 0x07);
+    // Found where expected: no scan
+    sensor.uart.tx.clear();
+    assert_eq!(sensor.detect_address().unwrap(), 0x07);
+    assert_eq!(sensor.uart.tx.len(), 8);
+
+    let uart = MockUart::with_devices(Vec::new());
+    let mut sensor = Sen0676::with_options(uart, DEFAULT_ADDRESS, options);
+    assert!(matches!(sensor.detect_address(), Err(Error::NotFound)));
+  }
+
+  #[test]
+  fn test_parse_read_response() {
+    let mut response = vec![0x01, 0x03, 0x04, 0x00, 0x0A, 0x01, 0x00];
+    let crc = crc16(&response);
+    response.extend([crc as u8, (crc >> 8) as u8]);
+    assert_eq!(parse_read_response(0x01, &response, 2).unwrap(), vec![10, 256]);
+    assert!(matches!(parse_read_response(0x01, &response, 1), Err(Error::InvalidLength)));
+    assert!(matches!(parse_read_response(0x02, &response, 2), Err(Error::AddressMismatch)));
+    response[3] ^= 0xFF;
+    assert!(matches!(parse_read_response(0x01, &response, 2), Err(Error::CrcMismatch)));
+  }
 }
@@ -0,0 +1,197 @@
+//! Sensor abstractions
+//!
+//! The main loop reads the tank level and line pressure through these traits
+//! rather than the concrete drivers, so hardware can be swapped and the
+//! reading pipeline (correction, filtering, tank geometry) can be tested on
+//! the host with the mock sensors below. Only the `PressureSensor` impl is
+//! ESP32-only.
+//!
+//! - `LevelSensor`: distance to the water surface (`Sen0676`)
+//! - `PressureSource`: line pressure (`PressureSensor`)
+
+use core::fmt::Debug;
+
+use crate::correction::Correction;
+use crate::filter::LevelFilter;
+use crate::level::{TankGeometry, TankLevel};
+
+/// Source of the distance from the sensor down to the water surface
+pub trait LevelSensor {
+    type Error: Debug;
+
+    /// Distance to the water surface (mm)
+    fn read_empty_height_mm(&mut self) -> Result<u16, Self::Error>;
+}
+
+/// Source of the line pressure
+pub trait PressureSource {
+    type Error: Debug;
+
+    /// Pressure at the transducer (PSI)
+    fn read_psi(&mut self) -> Result<f32, Self::Error>;
+}
+
+#[cfg(feature = "radar")]
+impl<U> LevelSensor for crate::sen0676::Sen0676<U>
+where
+    U: embedded_io::Read + embedded_io::ReadReady + embedded_io::Write,
+{
+    type Error = crate::sen0676::Error;
+
+    /// Reads all four distance registers in one transaction, kept for `last_measurements`
+    fn read_empty_height_mm(&mut self) -> Result<u16, Self::Error> {
+        Ok(self.read_measurements()?.empty_height_mm)
+    }
+}
+
+#[cfg(all(target_os = "espidf", feature = "pressure"))]
+impl<P> PressureSource for crate::pressure::PressureSensor<'_, P>
+where
+    P: esp_idf_svc::hal::gpio::ADCPin<Adc = esp_idf_svc::hal::adc::ADC1>,
+{
+    type Error = esp_idf_svc::sys::EspError;
+
+    fn read_psi(&mut self) -> Result<f32, Self::Error> {
+        crate::pressure::PressureSensor::read_psi(self)
+    }
+}
+
+/// Corrected reading and resulting tank contents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelReading {
+    /// Raw empty height from the sensor (mm)
+    pub raw_mm: u16,
+    /// Raw empty height after correction (mm)
+    pub corrected_mm: u16,
+    /// Corrected empty height after filtering (mm)
+    pub empty_mm: u16,
+    pub level: TankLevel,
+}
+
+/// Read the level sensor, correct and filter the distance and convert it to contents
+pub fn read_level<S: LevelSensor>(
+    sensor: &mut S,
+    filter: &mut LevelFilter,
+    correction: &Correction,
+    geometry: &TankGeometry,
+) -> Result<LevelReading, S::Error> {
+    Ok(level_reading(sensor.read_empty_height_mm()?, filter, correction, geometry))
+}
+
+/// Correct and filter a distance read without `read_level`, e.g. polled
+pub fn level_reading(
+    raw_mm: u16,
+    filter: &mut LevelFilter,
+    correction: &Correction,
+    geometry: &TankGeometry,
+) -> LevelReading {
+    // Corrected first, so the filter smooths the distance the geometry uses
+    // and a calibration table's kinks don't bend the median
+    let corrected_mm = correction.apply(raw_mm as f32).round().clamp(0.0, u16::MAX as f32) as u16;
+    let empty_mm = filter.update(corrected_mm);
+    LevelReading {
+        raw_mm,
+        corrected_mm,
+        empty_mm,
+        level: geometry.level(empty_mm),
+    }
+}
+
+/// Read the pressure source, add the head compensation (see
+/// `correction::HeadMode`) and apply the correction (never below 0 PSI)
+pub fn read_pressure<P: PressureSource>(
+    source: &mut P,
+    head_psi: f32,
+    correction: &Correction,
+) -> Result<f32, P::Error> {
+    Ok(correction.apply(source.read_psi()? + head_psi).max(0.0))
+}
+
+/// Level sensor returning preset readings, for tests and simulation
+#[derive(Debug, Default, Clone)]
+pub struct MockLevelSensor {
+    /// Next reading; `None` simulates a sensor that doesn't answer
+    pub empty_mm: Option<u16>,
+}
+
+impl LevelSensor for MockLevelSensor {
+    type Error = ();
+
+    fn read_empty_height_mm(&mut self) -> Result<u16, ()> {
+        self.empty_mm.ok_or(())
+    }
+}
+
+/// Pressure source returning a preset reading, for tests and simulation
+#[derive(Debug, Default, Clone)]
+pub struct MockPressureSource {
+    /// Next reading (PSI); `None` simulates a read error
+    pub psi: Option<f32>,
+}
+
+impl PressureSource for MockPressureSource {
+    type Error = ();
+
+    fn read_psi(&mut self) -> Result<f32, ()> {
+        self.psi.ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::correction::parse_table;
+    use crate::level::TankShape;
+
+    const TANK: TankGeometry = TankGeometry {
+        install_mm: 2000,
+        deadzone_mm: 200,
+        capacity_gallons: 500,
+        shape: TankShape::Vertical,
+    };
+
+    #[test]
+    fn test_read_level_applies_correction() {
+        let mut radar = MockLevelSensor { empty_mm: Some(1080) };
+        let mut filter = LevelFilter::new(1, 1.0);
+        // Sensor mounted 20 mm higher than configured
+        let correction = Correction { offset: 20.0, ..Default::default() };
+        let reading = read_level(&mut radar, &mut filter, &correction, &TANK).unwrap();
+        assert_eq!(reading.empty_mm, 1100);
+        assert_eq!((reading.level.percent, reading.level.gallons), (50, 250));
+
+        radar.empty_mm = None;
+        assert!(read_level(&mut radar, &mut filter, &correction, &TANK).is_err());
+    }
+
+    #[test]
+    fn test_level_reading_corrects_before_filtering() {
+        let mut filter = LevelFilter::new(1, 0.5);
+        // Steeper above 1000 mm
+        let correction = Correction {
+            table: parse_table("0:0,1000:1000,2000:3000").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(level_reading(800, &mut filter, &correction, &TANK).empty_mm, 800);
+        let reading = level_reading(1200, &mut filter, &correction, &TANK);
+        assert_eq!((reading.raw_mm, reading.corrected_mm), (1200, 1400));
+        // Halfway between the corrected readings, not the corrected halfway point
+        assert_eq!(reading.empty_mm, 1100);
+    }
+
+    #[test]
+    fn test_read_pressure_applies_table() {
+        let mut sensor = MockPressureSource { psi: Some(50.0) };
+        let correction = Correction {
+            table: parse_table("0:0,100:90").unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(read_pressure(&mut sensor, 0.0, &correction), Ok(45.0));
+
+        let offset = Correction { offset: -60.0, ..Default::default() };
+        assert_eq!(read_pressure(&mut sensor, 0.0, &offset), Ok(0.0));
+
+        // The head goes in before the table, which is calibrated against the gauge
+        assert_eq!(read_pressure(&mut sensor, -10.0, &correction), Ok(36.0));
+    }
+}
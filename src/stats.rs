@@ -0,0 +1,172 @@
+//! Daily water usage statistics
+//!
+//! Integrates tank level changes into gallons consumed (level falling) and
+//! gallons refilled (level rising) for the current day, rolled over at local
+//! midnight. Published to Home Assistant as `total_increasing` sensors, which
+//! treat the midnight reset as the start of a new cycle.
+//!
+//! Changes smaller than `DEADBAND_GALLONS` are held back until they add up,
+//! so reading jitter around a steady level isn't counted as flow in both
+//! directions.
+//!
+//! The day's totals are saved to NVS every 15 minutes as
+//! `day,consumed,refilled` (`day` counted from 1970-01-01 in local time) and
+//! added back after a reboot on the same day, so a restart doesn't reset the
+//! count a daily water budget is measured against.
+
+use log::*;
+
+/// Level change (gallons) needed before it is counted
+const DEADBAND_GALLONS: u16 = 2;
+
+/// Totals for one day (gallons)
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DailyUsage {
+    pub consumed: u32,
+    pub refilled: u32,
+}
+
+impl DailyUsage {
+    /// Consumption as a percentage of a daily budget, `None` without one
+    pub fn budget_percent(&self, budget_gallons: u16) -> Option<u32> {
+        (budget_gallons > 0).then(|| (self.consumed * 100 + budget_gallons as u32 / 2) / budget_gallons as u32)
+    }
+
+    /// Whether consumption has gone past a daily budget (0 = none)
+    pub fn over_budget(&self, budget_gallons: u16) -> bool {
+        budget_gallons > 0 && self.consumed > budget_gallons as u32
+    }
+
+    /// Stored form for local day `day` (see `parse_saved`)
+    pub fn to_saved(&self, day: i64) -> String {
+        format!("{},{},{}", day, self.consumed, self.refilled)
+    }
+
+    /// Day and totals of a stored `day,consumed,refilled` entry
+    pub fn parse_saved(s: &str) -> Option<(i64, Self)> {
+        let mut parts = s.trim().splitn(3, ',');
+        let day = parts.next()?.parse().ok()?;
+        let consumed = parts.next()?.parse().ok()?;
+        let refilled = parts.next()?.parse().ok()?;
+        Some((day, Self { consumed, refilled }))
+    }
+}
+
+/// Usage integrator
+#[derive(Debug, Default)]
+pub struct UsageStats {
+    today: DailyUsage,
+    yesterday: Option<DailyUsage>,
+    /// Level the next change is measured from
+    reference: Option<u16>,
+    /// Day of week the current totals belong to (`None` until the clock is set)
+    day: Option<u8>,
+}
+
+impl UsageStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current day's totals
+    pub fn today(&self) -> DailyUsage {
+        self.today
+    }
+
+    /// Previous day's totals, once a midnight has passed since boot
+    pub fn yesterday(&self) -> Option<DailyUsage> {
+        self.yesterday
+    }
+
+    /// Day of week the current totals belong to, once the clock is set
+    pub fn weekday(&self) -> Option<u8> {
+        self.day
+    }
+
+    /// Add totals saved earlier today, before a reboot
+    pub fn restore(&mut self, saved: DailyUsage) {
+        self.today.consumed += saved.consumed;
+        self.today.refilled += saved.refilled;
+    }
+
+    /// Add a tank reading
+    ///
+    /// `weekday` is the local day of week (1-7), or `None` while the clock is
+    /// unsynced; totals roll over when it changes.
+    pub fn update(&mut self, gallons: u16, weekday: Option<u8>) {
+        if let Some(day) = weekday {
+            match self.day {
+                Some(prev) if prev != day => {
+                    info!(
+                        "Usage: day closed, {} gal consumed, {} gal refilled",
+                        self.today.consumed, self.today.refilled
+                    );
+                    self.yesterday = Some(self.today);
+                    self.today = DailyUsage::default();
+                }
+                _ => {}
+            }
+            self.day = Some(day);
+        }
+
+        let Some(reference) = self.reference else {
+            self.reference = Some(gallons);
+            return;
+        };
+        if gallons.abs_diff(reference) < DEADBAND_GALLONS {
+            return;
+        }
+        if gallons < reference {
+            self.today.consumed += (reference - gallons) as u32;
+        } else {
+            self.today.refilled += (gallons - reference) as u32;
+        }
+        self.reference = Some(gallons);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integrates_level_changes() {
+        let mut stats = UsageStats::new();
+        for gallons in [400, 401, 400, 399, 390, 380, 381, 450] {
+            stats.update(gallons, Some(3));
+        }
+        // Jitter of 1 gal is ignored; 400 -> 380 consumed, 380 -> 450 refilled
+        assert_eq!(stats.today(), DailyUsage { consumed: 20, refilled: 70 });
+    }
+
+    #[test]
+    fn test_rolls_over_at_midnight() {
+        let mut stats = UsageStats::new();
+        stats.update(400, None);
+        stats.update(390, Some(3));
+        stats.update(370, Some(4));
+        assert_eq!(stats.yesterday(), Some(DailyUsage { consumed: 10, refilled: 0 }));
+        assert_eq!(stats.today(), DailyUsage { consumed: 20, refilled: 0 });
+    }
+
+    #[test]
+    fn test_saved_totals_and_budget() {
+        let usage = DailyUsage { consumed: 150, refilled: 40 };
+        assert_eq!(usage.to_saved(20_740), "20740,150,40");
+        assert_eq!(DailyUsage::parse_saved("20740,150,40"), Some((20_740, usage)));
+        assert_eq!(DailyUsage::parse_saved(""), None);
+        assert_eq!(DailyUsage::parse_saved("20740,150"), None);
+
+        let mut stats = UsageStats::new();
+        stats.update(400, Some(3));
+        stats.update(390, Some(3));
+        stats.restore(usage);
+        assert_eq!(stats.today(), DailyUsage { consumed: 160, refilled: 40 });
+
+        assert_eq!(stats.today().budget_percent(0), None);
+        assert_eq!(stats.today().budget_percent(300), Some(53));
+        assert!(!stats.today().over_budget(160));
+        assert!(stats.today().over_budget(159));
+        assert!(!stats.today().over_budget(0));
+    }
+}
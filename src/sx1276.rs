@@ -0,0 +1,236 @@
+//! Semtech SX1276 LoRa transceiver driver
+//!
+//! Drives the radio over SPI in LoRa mode with explicit headers and the
+//! payload CRC on, so corrupted packets are dropped by the radio itself.
+//! Completion is polled from the IRQ flags register, so DIO0 and the reset
+//! line don't need to be wired.
+//!
+//! # Register Map (LoRa mode)
+//! | Register | Name | Use |
+//! |----------|------|-----|
+//! | 0x00 | Fifo | packet data |
+//! | 0x01 | OpMode | LoRa bit, sleep/standby/TX/RX |
+//! | 0x06-0x08 | Frf | carrier frequency |
+//! | 0x09 | PaConfig | PA_BOOST output power |
+//! | 0x12 | IrqFlags | RX done, CRC error |
+//! | 0x1D-0x1E | ModemConfig1/2 | bandwidth, coding rate, spreading factor, CRC |
+//! | 0x42 | Version | silicon revision (0x12) |
+
+use std::borrow::Borrow;
+
+use esp_idf_svc::hal::spi::{SpiDeviceDriver, SpiDriver};
+use esp_idf_svc::sys::EspError;
+use log::debug;
+
+/// Register addresses
+mod registers {
+  pub const FIFO: u8 = 0x00;
+  pub const OP_MODE: u8 = 0x01;
+  pub const FRF_MSB: u8 = 0x06;
+  pub const PA_CONFIG: u8 = 0x09;
+  pub const LNA: u8 = 0x0C;
+  pub const FIFO_ADDR_PTR: u8 = 0x0D;
+  pub const FIFO_TX_BASE_ADDR: u8 = 0x0E;
+  pub const FIFO_RX_BASE_ADDR: u8 = 0x0F;
+  pub const FIFO_RX_CURRENT_ADDR: u8 = 0x10;
+  pub const IRQ_FLAGS: u8 = 0x12;
+  pub const RX_NB_BYTES: u8 = 0x13;
+  pub const PKT_SNR_VALUE: u8 = 0x19;
+  pub const PKT_RSSI_VALUE: u8 = 0x1A;
+  pub const MODEM_CONFIG_1: u8 = 0x1D;
+  pub const MODEM_CONFIG_2: u8 = 0x1E;
+  pub const PREAMBLE_MSB: u8 = 0x20;
+  pub const PREAMBLE_LSB: u8 = 0x21;
+  pub const PAYLOAD_LENGTH: u8 = 0x22;
+  pub const MODEM_CONFIG_3: u8 = 0x26;
+  pub const SYNC_WORD: u8 = 0x39;
+  pub const VERSION: u8 = 0x42;
+  pub const PA_DAC: u8 = 0x4D;
+}
+
+/// Operating modes (RegOpMode, with the LoRa bit set)
+mod mode {
+  pub const LONG_RANGE: u8 = 0x80;
+  pub const SLEEP: u8 = 0x00;
+  pub const STANDBY: u8 = 0x01;
+  pub const TX: u8 = 0x03;
+  pub const RX_CONTINUOUS: u8 = 0x05;
+}
+
+/// Interrupt flags (RegIrqFlags)
+mod irq {
+  pub const RX_DONE: u8 = 0x40;
+  pub const PAYLOAD_CRC_ERROR: u8 = 0x20;
+}
+
+/// Silicon revision reported by every SX1276/77/78/79
+const VERSION: u8 = 0x12;
+
+/// Private network sync word (0x34 is reserved for LoRaWAN)
+const SYNC_WORD: u8 = 0x12;
+
+/// Crystal frequency (Hz)
+const FXOSC: u64 = 32_000_000;
+
+/// Largest LoRa payload
+pub const MAX_PAYLOAD: usize = 255;
+
+/// Transmit power on PA_BOOST (dBm)
+const TX_POWER_DBM: u8 = 17;
+
+/// Errors that can occur during communication
+#[derive(Debug)]
+pub enum Error {
+  /// SPI transfer failed
+  Spi(EspError),
+  /// No SX1276 answered (version register value)
+  NotFound(u8),
+  /// Payload longer than the FIFO
+  PayloadTooLong,
+}
+
+impl From<EspError> for Error {
+  fn from(e: EspError) -> Self {
+    Error::Spi(e)
+  }
+}
+
+/// A received packet
+#[derive(Debug, Clone, Copy)]
+pub struct Packet {
+  /// Payload length in the receive buffer
+  pub len: usize,
+  /// Signal strength (dBm)
+  pub rssi: i16,
+  /// Signal to noise ratio (dB)
+  pub snr: f32,
+}
+
+pub struct Sx1276<'d, T>
+where
+  T: Borrow<SpiDriver<'d>>,
+{
+  spi: SpiDeviceDriver<'d, T>,
+  /// Carrier below 525 MHz (the RSSI offset differs on the LF port)
+  low_band: bool,
+}
+
+impl<'d, T> Sx1276<'d, T>
+where
+  T: Borrow<SpiDriver<'d>>,
+{
+  /// Create a driver on an SPI device with its chip select (mode 0, up to 10 MHz)
+  pub fn new(spi: SpiDeviceDriver<'d, T>) -> Self {
+    Self { spi, low_band: false }
+  }
+
+  /// Check the radio and configure LoRa mode on `frequency_hz`
+  ///
+  /// 125 kHz bandwidth, spreading factor 9 and coding rate 4/5: a 20 byte
+  /// packet takes about 0.2 s on air and reaches a few kilometres.
+  pub fn init(&mut self, frequency_hz: u32) -> Result<(), Error> {
+    let version = self.read_register(registers::VERSION)?;
+    if version != VERSION {
+      return Err(Error::NotFound(version));
+    }
+    // The LoRa bit can only be changed in sleep mode
+    self.write_register(registers::OP_MODE, mode::SLEEP)?;
+    self.write_register(registers::OP_MODE, mode::LONG_RANGE | mode::SLEEP)?;
+
+    self.low_band = frequency_hz < 525_000_000;
+    let frf = ((frequency_hz as u64) << 19) / FXOSC;
+    self.spi.write(&[
+      registers::FRF_MSB | 0x80,
+      (frf >> 16) as u8,
+      (frf >> 8) as u8,
+      frf as u8,
+    ])?;
+
+    self.write_register(registers::FIFO_TX_BASE_ADDR, 0)?;
+    self.write_register(registers::FIFO_RX_BASE_ADDR, 0)?;
+    // LNA gain set by the AGC, HF boost on
+    self.write_register(registers::LNA, 0x23)?;
+    // PA_BOOST, Pout = 2 + OutputPower (dBm)
+    self.write_register(registers::PA_CONFIG, 0x80 | (TX_POWER_DBM - 2))?;
+    self.write_register(registers::PA_DAC, 0x84)?;
+    // BW 125 kHz, CR 4/5, explicit header
+    self.write_register(registers::MODEM_CONFIG_1, 0x72)?;
+    // SF9, payload CRC on
+    self.write_register(registers::MODEM_CONFIG_2, 0x94)?;
+    // AGC on
+    self.write_register(registers::MODEM_CONFIG_3, 0x04)?;
+    self.write_register(registers::PREAMBLE_MSB, 0)?;
+    self.write_register(registers::PREAMBLE_LSB, 8)?;
+    self.write_register(registers::SYNC_WORD, SYNC_WORD)?;
+
+    self.write_register(registers::OP_MODE, mode::LONG_RANGE | mode::STANDBY)?;
+    debug!("SX1276: ready on {} Hz", frequency_hz);
+    Ok(())
+  }
+
+  /// Start sending `payload`, returning without waiting for it to go out
+  ///
+  /// The radio drops back to standby once the packet is sent.
+  pub fn transmit(&mut self, payload: &[u8]) -> Result<(), Error> {
+    if payload.len() > MAX_PAYLOAD {
+      return Err(Error::PayloadTooLong);
+    }
+    self.write_register(registers::OP_MODE, mode::LONG_RANGE | mode::STANDBY)?;
+    self.write_register(registers::IRQ_FLAGS, 0xFF)?;
+    self.write_register(registers::FIFO_ADDR_PTR, 0)?;
+    let mut frame = Vec::with_capacity(payload.len() + 1);
+    frame.push(registers::FIFO | 0x80);
+    frame.extend_from_slice(payload);
+    self.spi.write(&frame)?;
+    self.write_register(registers::PAYLOAD_LENGTH, payload.len() as u8)?;
+    self.write_register(registers::OP_MODE, mode::LONG_RANGE | mode::TX)?;
+    Ok(())
+  }
+
+  /// Listen continuously; packets are picked up by `receive`
+  pub fn start_receive(&mut self) -> Result<(), Error> {
+    self.write_register(registers::IRQ_FLAGS, 0xFF)?;
+    self.write_register(registers::OP_MODE, mode::LONG_RANGE | mode::RX_CONTINUOUS)?;
+    Ok(())
+  }
+
+  /// Copy a packet received since the last call into `buf`
+  ///
+  /// Packets that fail the CRC are dropped. Listening goes on afterwards.
+  pub fn receive(&mut self, buf: &mut [u8]) -> Result<Option<Packet>, Error> {
+    let flags = self.read_register(registers::IRQ_FLAGS)?;
+    if flags & irq::RX_DONE == 0 {
+      return Ok(None);
+    }
+    self.write_register(registers::IRQ_FLAGS, 0xFF)?;
+    if flags & irq::PAYLOAD_CRC_ERROR != 0 {
+      debug!("SX1276: dropped a packet with a CRC error");
+      return Ok(None);
+    }
+
+    let len = (self.read_register(registers::RX_NB_BYTES)? as usize).min(buf.len());
+    let start = self.read_register(registers::FIFO_RX_CURRENT_ADDR)?;
+    self.write_register(registers::FIFO_ADDR_PTR, start)?;
+    let mut command = vec![0u8; len + 1];
+    command[0] = registers::FIFO;
+    let mut response = vec![0u8; len + 1];
+    self.spi.transfer(&mut response, &command)?;
+    buf[..len].copy_from_slice(&response[1..]);
+
+    let snr = self.read_register(registers::PKT_SNR_VALUE)? as i8 as f32 / 4.0;
+    let raw = self.read_register(registers::PKT_RSSI_VALUE)? as i16;
+    let rssi = if self.low_band { -164 + raw } else { -157 + raw };
+    Ok(Some(Packet { len, rssi, snr }))
+  }
+
+  fn read_register(&mut self, address: u8) -> Result<u8, Error> {
+    let mut response = [0u8; 2];
+    self.spi.transfer(&mut response, &[address & 0x7F, 0])?;
+    Ok(response[1])
+  }
+
+  fn write_register(&mut self, address: u8, value: u8) -> Result<(), Error> {
+    self.spi.write(&[address | 0x80, value])?;
+    Ok(())
+  }
+}
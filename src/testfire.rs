@@ -0,0 +1,162 @@
+//! Output test-fire
+//!
+//! Pulses one output for `PULSE` so an installer can check the wiring of the
+//! supply valve relays and the radar heater without forcing real tank
+//! conditions. The pump relays are left out: a pump started against a closed
+//! valve or a dry well can be damaged within seconds.
+//!
+//! Firing takes two steps on the web UI. Choosing an output arms it with a
+//! one-time code, and only a confirmation carrying that code within
+//! `CONFIRM_WINDOW` fires it. Pulses are only run in maintenance mode, where
+//! automation leaves the outputs alone.
+
+use std::time::Duration;
+
+/// How long an output is energized
+pub const PULSE: Duration = Duration::from_secs(1);
+/// Time to confirm after arming
+pub const CONFIRM_WINDOW: Duration = Duration::from_secs(60);
+
+/// Outputs that can be test-fired
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Heater,
+    ValveOpen,
+    ValveClose,
+}
+
+impl Output {
+    pub const ALL: [Output; 3] = [Output::Heater, Output::ValveOpen, Output::ValveClose];
+
+    /// Form value
+    pub fn key(self) -> &'static str {
+        match self {
+            Output::Heater => "heater",
+            Output::ValveOpen => "valve_open",
+            Output::ValveClose => "valve_close",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|o| o.key() == key)
+    }
+
+    /// Label on the web UI
+    pub fn label(self) -> &'static str {
+        match self {
+            Output::Heater => "Radar heater",
+            Output::ValveOpen => "Supply valve open relay",
+            Output::ValveClose => "Supply valve close relay",
+        }
+    }
+
+    /// Whether the output is built into this firmware
+    pub fn enabled(self) -> bool {
+        match self {
+            Output::Heater => cfg!(feature = "heater"),
+            Output::ValveOpen | Output::ValveClose => cfg!(feature = "valve"),
+        }
+    }
+}
+
+/// Why a confirmation was refused
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfirmError {
+    NotArmed,
+    /// The code or output doesn't match the armed one
+    Mismatch,
+    Expired,
+}
+
+impl ConfirmError {
+    pub fn message(self) -> &'static str {
+        match self {
+            ConfirmError::NotArmed => "Nothing armed, choose an output first",
+            ConfirmError::Mismatch => "Confirmation doesn't match the armed output",
+            ConfirmError::Expired => "Confirmation expired, arm again",
+        }
+    }
+}
+
+/// The output waiting for confirmation
+#[derive(Debug, Default)]
+pub struct Arming {
+    /// (output, code, armed at)
+    pending: Option<(Output, u32, Duration)>,
+}
+
+impl Arming {
+    /// Arm `output`, replacing anything armed before
+    pub fn arm(&mut self, output: Output, code: u32, now: Duration) {
+        self.pending = Some((output, code, now));
+    }
+
+    /// Check a confirmation; the code can only be used once either way
+    pub fn confirm(&mut self, output: Output, code: u32, now: Duration) -> Result<(), ConfirmError> {
+        let (armed, expected, at) = self.pending.take().ok_or(ConfirmError::NotArmed)?;
+        if armed != output || expected != code {
+            return Err(ConfirmError::Mismatch);
+        }
+        if now.saturating_sub(at) > CONFIRM_WINDOW {
+            return Err(ConfirmError::Expired);
+        }
+        Ok(())
+    }
+}
+
+/// A running pulse
+#[derive(Debug, Clone, Copy)]
+pub struct Pulse {
+    output: Output,
+    until: Duration,
+}
+
+impl Pulse {
+    pub fn new(output: Output, now: Duration) -> Self {
+        Self { output, until: now + PULSE }
+    }
+
+    pub fn target(&self) -> Output {
+        self.output
+    }
+
+    /// The energized output, or `None` once the pulse is over
+    pub fn output(&self, now: Duration) -> Option<Output> {
+        (now < self.until).then_some(self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confirmation_is_single_use() {
+        let mut arming = Arming::default();
+        let now = Duration::from_secs(100);
+        assert_eq!(arming.confirm(Output::Heater, 1, now), Err(ConfirmError::NotArmed));
+
+        arming.arm(Output::Heater, 42, now);
+        assert_eq!(arming.confirm(Output::Heater, 42, now + Duration::from_secs(5)), Ok(()));
+        assert_eq!(arming.confirm(Output::Heater, 42, now), Err(ConfirmError::NotArmed));
+
+        arming.arm(Output::ValveOpen, 7, now);
+        assert_eq!(arming.confirm(Output::ValveClose, 7, now), Err(ConfirmError::Mismatch));
+        // A refused attempt disarms
+        assert_eq!(arming.confirm(Output::ValveOpen, 7, now), Err(ConfirmError::NotArmed));
+
+        arming.arm(Output::ValveOpen, 7, now);
+        assert_eq!(arming.confirm(Output::ValveOpen, 7, now + CONFIRM_WINDOW * 2), Err(ConfirmError::Expired));
+        assert_eq!(Output::from_key("valve_close"), Some(Output::ValveClose));
+        assert_eq!(Output::from_key("pump1"), None);
+    }
+
+    #[test]
+    fn test_pulse_ends() {
+        let start = Duration::from_secs(10);
+        let pulse = Pulse::new(Output::ValveClose, start);
+        assert_eq!(pulse.output(start), Some(Output::ValveClose));
+        assert_eq!(pulse.output(start + PULSE / 2), Some(Output::ValveClose));
+        assert_eq!(pulse.output(start + PULSE), None);
+    }
+}
@@ -0,0 +1,339 @@
+//! ILI9341 / ST7789 Color TFT Driver
+//!
+//! 320x240 RGB565 SPI panels driven through the MIPI DCS command set.
+//!
+//! # Framebuffer
+//! A full RGB565 framebuffer would take 150 KiB, more than the ESP32 can spare.
+//! Pixels are instead stored as 4-bit indices into a 16-entry palette (37.5 KiB)
+//! and expanded to RGB565 line by line during flush. Widgets only use a handful
+//! of theme colors, so the palette fills on first use and never runs out; any
+//! further color is mapped to the nearest entry.
+//!
+//! Dirty-line tracking and the flush budget work as in the Sharp driver.
+//!
+//! # Wiring
+//! - SCLK/MOSI: SPI clock and data
+//! - CS: Chip select (active LOW, driven by the SPI driver)
+//! - DC: Data/command select
+//! - RST: Hardware reset (active LOW)
+
+use embedded_graphics::{
+  Pixel,
+  draw_target::DrawTarget,
+  geometry::{OriginDimensions, Size},
+  pixelcolor::{Rgb565, RgbColor, raw::RawU16},
+  prelude::RawData,
+};
+use esp_idf_svc::hal::{
+  gpio::{Output, OutputPin, PinDriver},
+  spi::{SpiDeviceDriver, SpiDriver},
+};
+
+use crate::diag::FlushStats;
+use crate::ui::Palette;
+
+/// Display width in pixels (landscape)
+pub const WIDTH: u16 = 320;
+/// Display height in pixels (landscape)
+pub const HEIGHT: u16 = 240;
+/// Bytes per framebuffer line (2 pixels per byte)
+const BYTES_PER_LINE: usize = WIDTH as usize / 2;
+/// Dirty line bitmap size
+const DIRTY_BITMAP_SIZE: usize = (HEIGHT as usize + 7) / 8;
+/// Bytes sent per line: CASET and RASET with 4 parameters each, RAMWR and the RGB565 pixels
+const LINE_BYTES: usize = 2 * 5 + 1 + WIDTH as usize * 2;
+/// Palette entries addressable by a 4-bit index
+const PALETTE_SIZE: usize = 16;
+
+/// MIPI DCS commands shared by both controllers
+mod cmd {
+  pub const SWRESET: u8 = 0x01;
+  pub const SLPOUT: u8 = 0x11;
+  pub const INVON: u8 = 0x21;
+  pub const DISPON: u8 = 0x29;
+  pub const CASET: u8 = 0x2A;
+  pub const RASET: u8 = 0x2B;
+  pub const RAMWR: u8 = 0x2C;
+  pub const MADCTL: u8 = 0x36;
+  pub const COLMOD: u8 = 0x3A;
+}
+
+/// Supported panel controllers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model {
+  Ili9341,
+  St7789,
+}
+
+impl Model {
+  /// MADCTL value for landscape orientation
+  fn madctl(self) -> u8 {
+    match self {
+      Model::Ili9341 => 0x28, // MV | BGR
+      Model::St7789 => 0x60,  // MX | MV
+    }
+  }
+}
+
+/// Color TFT driver with a palette-indexed framebuffer
+pub struct Tft<'d, SPI, DC, RST>
+where
+  SPI: std::borrow::Borrow<SpiDriver<'d>>,
+  DC: esp_idf_svc::hal::gpio::Pin,
+  RST: esp_idf_svc::hal::gpio::Pin,
+{
+  spi: SpiDeviceDriver<'d, SPI>,
+  dc: PinDriver<'d, DC, Output>,
+  rst: PinDriver<'d, RST, Output>,
+  model: Model,
+  framebuffer: Vec<u8>,
+  palette: [Rgb565; PALETTE_SIZE],
+  palette_len: usize,
+  dirty_lines: [u8; DIRTY_BITMAP_SIZE],
+  /// Maximum lines sent per flush (0 = unlimited)
+  flush_budget: u16,
+  /// Line the next budgeted flush resumes from
+  flush_cursor: u16,
+  /// Flush counters for the diagnostics page
+  stats: FlushStats,
+}
+
+impl<'d, SPI, DC, RST> Tft<'d, SPI, DC, RST>
+where
+  SPI: std::borrow::Borrow<SpiDriver<'d>>,
+  DC: OutputPin,
+  RST: OutputPin,
+{
+  /// Create a new display driver
+  pub fn new(
+    spi: SpiDeviceDriver<'d, SPI>,
+    dc: PinDriver<'d, DC, Output>,
+    rst: PinDriver<'d, RST, Output>,
+    model: Model,
+  ) -> Self {
+    // Entry 0 is the background cleared to
+    let mut palette = [Rgb565::BLACK; PALETTE_SIZE];
+    palette[0] = Palette::COLOR.background;
+    Self {
+      spi,
+      dc,
+      rst,
+      model,
+      framebuffer: vec![0; BYTES_PER_LINE * HEIGHT as usize], // Background
+      palette,
+      palette_len: 1,
+      dirty_lines: [0xFF; DIRTY_BITMAP_SIZE],
+      flush_budget: 0,
+      flush_cursor: 0,
+      stats: FlushStats::default(),
+    }
+  }
+
+  /// Reset and initialize the controller, then push the framebuffer
+  pub fn init(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+    self.rst.set_low()?;
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    self.rst.set_high()?;
+    std::thread::sleep(std::time::Duration::from_millis(120));
+
+    self.command(cmd::SWRESET, &[])?;
+    std::thread::sleep(std::time::Duration::from_millis(150));
+    self.command(cmd::SLPOUT, &[])?;
+    std::thread::sleep(std::time::Duration::from_millis(120));
+    self.command(cmd::COLMOD, &[0x55])?; // 16 bits per pixel
+    self.command(cmd::MADCTL, &[self.model.madctl()])?;
+    if self.model == Model::St7789 {
+      // ST7789 panels are wired for inverted colors
+      self.command(cmd::INVON, &[])?;
+    }
+    self.command(cmd::DISPON, &[])?;
+
+    self.mark_all_dirty();
+    self.flush_all()
+  }
+
+  /// Limit the number of lines sent per `flush()` (0 = unlimited)
+  pub fn set_flush_budget(&mut self, lines: u16) {
+    self.flush_budget = lines.min(HEIGHT);
+  }
+
+  /// Send a command byte followed by its parameters
+  fn command(&mut self, command: u8, params: &[u8]) -> Result<(), esp_idf_svc::sys::EspError> {
+    self.dc.set_low()?;
+    self.spi.write(&[command])?;
+    if !params.is_empty() {
+      self.dc.set_high()?;
+      self.spi.write(params)?;
+    }
+    Ok(())
+  }
+
+  /// Select a rectangular RAM window for the next RAMWR
+  fn set_window(&mut self, x0: u16, y0: u16, x1: u16, y1: u16) -> Result<(), esp_idf_svc::sys::EspError> {
+    self.command(cmd::CASET, &[(x0 >> 8) as u8, x0 as u8, (x1 >> 8) as u8, x1 as u8])?;
+    self.command(cmd::RASET, &[(y0 >> 8) as u8, y0 as u8, (y1 >> 8) as u8, y1 as u8])
+  }
+
+  /// Mark a line as dirty
+  #[inline]
+  fn mark_dirty(&mut self, line: u16) {
+    self.dirty_lines[line as usize / 8] |= 1 << (line % 8);
+  }
+
+  /// Check if a line is dirty
+  #[inline]
+  fn is_dirty(&self, line: u16) -> bool {
+    (self.dirty_lines[line as usize / 8] & (1 << (line % 8))) != 0
+  }
+
+  /// Clear the dirty flag of a line
+  #[inline]
+  fn clear_dirty(&mut self, line: u16) {
+    self.dirty_lines[line as usize / 8] &= !(1 << (line % 8));
+  }
+
+  /// Mark all lines as dirty (for full refresh)
+  pub fn mark_all_dirty(&mut self) {
+    self.dirty_lines.fill(0xFF);
+  }
+
+  /// Clear framebuffer to the background without sending to display
+  pub fn clear_framebuffer(&mut self) {
+    self.framebuffer.fill(0);
+    self.mark_all_dirty();
+  }
+
+  /// Write dirty lines to the display, at most the flush budget per call
+  pub fn flush(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+    let budget = if self.flush_budget == 0 { HEIGHT } else { self.flush_budget };
+    self.flush_lines(budget)
+  }
+
+  /// Write all dirty lines to the display, ignoring the flush budget
+  pub fn flush_all(&mut self) -> Result<(), esp_idf_svc::sys::EspError> {
+    self.flush_lines(HEIGHT)
+  }
+
+  /// Lines, bytes and time sent by flushes since boot
+  pub fn stats(&self) -> &FlushStats {
+    &self.stats
+  }
+
+  /// Write up to `budget` dirty lines, starting at the flush cursor
+  fn flush_lines(&mut self, budget: u16) -> Result<(), esp_idf_svc::sys::EspError> {
+    let started = std::time::Instant::now();
+    let mut sent = 0;
+    let mut line = self.flush_cursor;
+    for _ in 0..HEIGHT {
+      if sent >= budget {
+        break;
+      }
+
+      if self.is_dirty(line) {
+        // Expand palette indices to big-endian RGB565
+        let mut line_buf = [0u8; WIDTH as usize * 2];
+        let start = line as usize * BYTES_PER_LINE;
+        for (i, &pair) in self.framebuffer[start..start + BYTES_PER_LINE].iter().enumerate() {
+          for (n, index) in [pair & 0x0F, pair >> 4].into_iter().enumerate() {
+            let raw = RawU16::from(self.palette[index as usize]).into_inner();
+            let offset = (i * 2 + n) * 2;
+            line_buf[offset..offset + 2].copy_from_slice(&raw.to_be_bytes());
+          }
+        }
+
+        self.set_window(0, line, WIDTH - 1, line)?;
+        self.command(cmd::RAMWR, &line_buf)?;
+        self.clear_dirty(line);
+        sent += 1;
+      }
+
+      line = (line + 1) % HEIGHT;
+    }
+    self.flush_cursor = line;
+    self.stats.record(sent, sent as usize * LINE_BYTES, started.elapsed());
+    Ok(())
+  }
+
+  /// Find (or allocate) the palette index for a color
+  fn palette_index(&mut self, color: Rgb565) -> u8 {
+    if let Some(index) = self.palette[..self.palette_len].iter().position(|&c| c == color) {
+      return index as u8;
+    }
+    if self.palette_len < PALETTE_SIZE {
+      self.palette[self.palette_len] = color;
+      self.palette_len += 1;
+      return (self.palette_len - 1) as u8;
+    }
+
+    // Palette full: use the nearest existing entry
+    let distance = |c: Rgb565| {
+      let dr = c.r() as i32 - color.r() as i32;
+      let dg = c.g() as i32 - color.g() as i32;
+      let db = c.b() as i32 - color.b() as i32;
+      dr * dr + dg * dg + db * db
+    };
+    (0..PALETTE_SIZE)
+      .min_by_key(|&i| distance(self.palette[i]))
+      .unwrap_or(0) as u8
+  }
+
+  /// Set a pixel in the framebuffer (call flush() to update display)
+  pub fn set_pixel(&mut self, x: u16, y: u16, color: Rgb565) {
+    if x >= WIDTH || y >= HEIGHT {
+      return;
+    }
+
+    let index = self.palette_index(color);
+    let byte_idx = y as usize * BYTES_PER_LINE + (x / 2) as usize;
+    let old_byte = self.framebuffer[byte_idx];
+    // Even pixels live in the low nibble
+    self.framebuffer[byte_idx] = if x % 2 == 0 {
+      (old_byte & 0xF0) | index
+    } else {
+      (old_byte & 0x0F) | (index << 4)
+    };
+
+    // Mark line dirty only if pixel actually changed
+    if self.framebuffer[byte_idx] != old_byte {
+      self.mark_dirty(y);
+    }
+  }
+}
+
+/// embedded-graphics DrawTarget implementation
+impl<'d, SPI, DC, RST> DrawTarget for Tft<'d, SPI, DC, RST>
+where
+  SPI: std::borrow::Borrow<SpiDriver<'d>>,
+  DC: OutputPin,
+  RST: OutputPin,
+{
+  type Color = Rgb565;
+  type Error = core::convert::Infallible;
+
+  fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+  where
+    I: IntoIterator<Item = Pixel<Self::Color>>,
+  {
+    for Pixel(coord, color) in pixels.into_iter() {
+      if coord.x >= 0
+        && coord.x < WIDTH as i32
+        && coord.y >= 0
+        && coord.y < HEIGHT as i32
+      {
+        self.set_pixel(coord.x as u16, coord.y as u16, color);
+      }
+    }
+    Ok(())
+  }
+}
+
+impl<'d, SPI, DC, RST> OriginDimensions for Tft<'d, SPI, DC, RST>
+where
+  SPI: std::borrow::Borrow<SpiDriver<'d>>,
+  DC: OutputPin,
+  RST: OutputPin,
+{
+  fn size(&self) -> Size {
+    Size::new(WIDTH as u32, HEIGHT as u32)
+  }
+}
@@ -0,0 +1,49 @@
+//! Recorded sensor traces
+//!
+//! The CSV form of a run of history samples, shared by the host-side replay
+//! tests (`replay`) and bench playback on a unit (`playback`). One sample per
+//! line:
+//!
+//! ```text
+//! timestamp,capacity_percent,pressure_psi,gallons
+//! 1767225600,42,55,336
+//! ```
+//!
+//! The header, blank lines and `#` comments are skipped.
+
+/// One trace sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TracePoint {
+    /// Seconds since the Unix epoch
+    pub timestamp: u32,
+    pub capacity_percent: u8,
+    pub pressure_psi: u16,
+    pub gallons: u16,
+}
+
+/// Parse a CSV trace, oldest sample first
+pub fn parse_trace(csv: &str) -> Result<Vec<TracePoint>, String> {
+    let mut points: Vec<TracePoint> = Vec::new();
+    for (i, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("timestamp") {
+            continue;
+        }
+        let bad = |field: &str| format!("line {}: bad {}", i + 1, field);
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [timestamp, percent, psi, gallons] = fields[..] else {
+            return Err(format!("line {}: expected 4 fields", i + 1));
+        };
+        let point = TracePoint {
+            timestamp: timestamp.parse().map_err(|_| bad("timestamp"))?,
+            capacity_percent: percent.parse().ok().filter(|&p| p <= 100).ok_or_else(|| bad("capacity_percent"))?,
+            pressure_psi: psi.parse().map_err(|_| bad("pressure_psi"))?,
+            gallons: gallons.parse().map_err(|_| bad("gallons"))?,
+        };
+        if points.last().is_some_and(|last| point.timestamp < last.timestamp) {
+            return Err(format!("line {}: timestamp goes backwards", i + 1));
+        }
+        points.push(point);
+    }
+    Ok(points)
+}
@@ -0,0 +1,105 @@
+//! Trial configuration with automatic revert
+//!
+//! A trial applies a set of number settings for a limited period. Unless it
+//! is confirmed before the period ends, or if a new fault (a pump marked
+//! failed, the dry-run guard tripping or an alarm going active) appears while
+//! it runs, the settings it replaced are restored.
+//! This lets a threshold change be tried on a remote site without the risk
+//! of leaving it in a bad state when nobody is around to undo it.
+//!
+//! Faults that were already latched when the trial started don't count.
+
+use std::time::Duration;
+
+use crate::twin::Settings;
+
+/// Trial period when the request doesn't give one (minutes)
+pub const DEFAULT_MINUTES: u16 = 30;
+/// Longest trial period (minutes)
+pub const MAX_MINUTES: u16 = 24 * 60;
+
+/// Why a trial was reverted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevertReason {
+    /// Not confirmed within the trial period
+    Expired,
+    /// A fault appeared during the trial
+    Fault,
+}
+
+/// A running trial
+#[derive(Debug, Clone)]
+pub struct Trial {
+    /// Values the trial settings replaced
+    previous: Settings,
+    deadline: Duration,
+    /// Faults already present when the trial started
+    faults: usize,
+}
+
+impl Trial {
+    /// Start a trial of `settings`, remembering their `current` values
+    ///
+    /// Keys without a current value can't be restored and are not tracked.
+    pub fn new(
+        settings: &[(&'static str, u16)],
+        current: &[(&'static str, u16)],
+        now: Duration,
+        period: Duration,
+        faults: usize,
+    ) -> Self {
+        let previous = settings
+            .iter()
+            .filter_map(|(key, _)| current.iter().find(|(k, _)| k == key).copied())
+            .collect();
+        Self { previous, deadline: now + period, faults }
+    }
+
+    /// Settings to restore on revert
+    pub fn previous(&self) -> &[(&'static str, u16)] {
+        &self.previous
+    }
+
+    /// Time left before the trial reverts
+    pub fn remaining(&self, now: Duration) -> Duration {
+        self.deadline.saturating_sub(now)
+    }
+
+    /// Check the trial against the current fault count
+    ///
+    /// Returns the reason once the trial has to be reverted.
+    pub fn check(&self, now: Duration, faults: usize) -> Option<RevertReason> {
+        if faults > self.faults {
+            Some(RevertReason::Fault)
+        } else if now >= self.deadline {
+            Some(RevertReason::Expired)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_reverts_unconfirmed_trial() {
+        let current = [("pump_start", 30), ("pump_stop", 90), ("max_psi", 100)];
+        let trial = Trial::new(&[("pump_start", 20), ("pump_stop", 95)], &current, MIN, 10 * MIN, 0);
+        assert_eq!(trial.previous(), [("pump_start", 30), ("pump_stop", 90)]);
+        assert_eq!(trial.check(5 * MIN, 0), None);
+        assert_eq!(trial.remaining(5 * MIN), 6 * MIN);
+        assert_eq!(trial.check(11 * MIN, 0), Some(RevertReason::Expired));
+    }
+
+    #[test]
+    fn test_reverts_on_new_fault() {
+        // One pump was already failed before the trial
+        let trial = Trial::new(&[("pump_fail_min", 5)], &[("pump_fail_min", 10)], Duration::ZERO, 10 * MIN, 1);
+        assert_eq!(trial.check(MIN, 1), None);
+        assert_eq!(trial.check(2 * MIN, 2), Some(RevertReason::Fault));
+    }
+}
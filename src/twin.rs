@@ -0,0 +1,44 @@
+//! Desired/reported configuration ("device twin")
+//!
+//! The broker keeps a retained *desired* configuration document for the
+//! unit; the device publishes its *reported* configuration after applying
+//! changes. Both use the bulk configuration format (`{"tank_capacity": 800,
+//! ...}`), so a replacement unit picks up the same settings on first connect
+//! and a reported document can be copied to desired verbatim.
+
+/// Number settings keyed by their command topic suffix
+pub type Settings = Vec<(&'static str, u16)>;
+
+/// JSON object for a settings list
+pub fn to_json(settings: &[(&str, u16)]) -> String {
+    let members: Vec<String> = settings.iter().map(|(key, value)| format!(r#""{}":{}"#, key, value)).collect();
+    format!("{{{}}}", members.join(","))
+}
+
+/// Desired settings that differ from (or are missing in) the reported ones
+pub fn diff(desired: &[(&'static str, u16)], reported: &[(&str, u16)]) -> Settings {
+    desired
+        .iter()
+        .filter(|(key, value)| !reported.iter().any(|(k, v)| k == key && v == value))
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        assert_eq!(to_json(&[("tank_capacity", 800), ("pump_start", 30)]), r#"{"tank_capacity":800,"pump_start":30}"#);
+        assert_eq!(to_json(&[]), "{}");
+    }
+
+    #[test]
+    fn test_diff_returns_changed_settings() {
+        let reported = [("tank_capacity", 500), ("pump_start", 30), ("max_psi", 100)];
+        let desired = [("tank_capacity", 800), ("pump_start", 30), ("vfd_kp", 1200)];
+        assert_eq!(diff(&desired, &reported), vec![("tank_capacity", 800), ("vfd_kp", 1200)]);
+        assert!(diff(&reported, &reported).is_empty());
+    }
+}
@@ -1,20 +1,166 @@
 //! Display UI components for water controller
 //!
-//! - Water tank visualization with fill level and text overlay
+//! - Water tank visualization with fill level, text overlay and a name above
 //! - Analog pressure gauge (manometer) with digital readout
+//! - Tank level trend line chart
+//! - Daily water budget bar
+//! - Text pages and a page manager that switches between full-screen pages
+//! - Boot and firmware update progress with a status icon per step
+//! - Alert banner drawn over the page while an alarm is active
+//! - QR code screen linking an active alarm to its runbook
+//!
+//! Widgets are generic over the pixel color and take their styling from a
+//! `Theme` (colors, stroke widths, fonts, fill pattern), so the same code draws
+//! on the mono LCD and on color TFTs.
+
+use std::collections::VecDeque;
+use std::time::Duration;
 
 use embedded_graphics::{
     draw_target::DrawTarget,
     geometry::{Point, Size},
-    mono_font::{MonoTextStyle, MonoTextStyleBuilder, ascii::FONT_6X10, ascii::FONT_10X20},
-    pixelcolor::BinaryColor,
+    mono_font::{MonoFont, MonoTextStyle, MonoTextStyleBuilder, ascii::FONT_6X10, ascii::FONT_10X20},
+    pixelcolor::{BinaryColor, Rgb565},
     prelude::*,
-    primitives::{Circle, Line, PrimitiveStyle, Rectangle},
-    text::{Alignment, Text, TextStyleBuilder},
+    primitives::{Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
 };
 
+use crate::fixedmath;
+use crate::qr::QrCode;
+
+/// Colors used by the widgets
+#[derive(Debug, Clone, Copy)]
+pub struct Palette<C> {
+    /// Screen background and empty tank area
+    pub background: C,
+    /// Outlines, ticks, needle and text on the background
+    pub foreground: C,
+    /// Water fill
+    pub water: C,
+    /// Text drawn over the water fill
+    pub on_water: C,
+}
+
+impl Palette<BinaryColor> {
+    /// Black on white, water drawn black
+    pub const MONO: Self = Self {
+        background: BinaryColor::On,
+        foreground: BinaryColor::Off,
+        water: BinaryColor::Off,
+        on_water: BinaryColor::On,
+    };
+}
+
+impl Palette<Rgb565> {
+    /// White on black, water drawn blue
+    pub const COLOR: Self = Self {
+        background: Rgb565::BLACK,
+        foreground: Rgb565::WHITE,
+        water: Rgb565::new(0, 24, 31),
+        on_water: Rgb565::WHITE,
+    };
+}
+
+/// How the water portion of the tank is filled
+///
+/// Patterned fills leave most of the water area in the background color, so
+/// markers drawn inside the tank stay visible and text needs no color switch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillPattern {
+    /// Solid water color
+    Solid,
+    /// Diagonal hatch lines on the background
+    Hatched,
+    /// Sparse dot dither on the background
+    Dithered,
+}
+
+impl FillPattern {
+    /// Decode the numeric form stored in config (unknown values fall back to solid)
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1 => FillPattern::Hatched,
+            2 => FillPattern::Dithered,
+            _ => FillPattern::Solid,
+        }
+    }
+
+    /// Numeric form stored in config
+    pub fn code(self) -> u16 {
+        match self {
+            FillPattern::Solid => 0,
+            FillPattern::Hatched => 1,
+            FillPattern::Dithered => 2,
+        }
+    }
+
+    /// Whether a water pixel gets the water color (the rest show background)
+    fn is_set(self, p: Point) -> bool {
+        match self {
+            FillPattern::Solid => true,
+            FillPattern::Hatched => (p.x + p.y) % 4 == 0,
+            FillPattern::Dithered => p.x % 2 == 0 && p.y % 2 == 0,
+        }
+    }
+}
+
+/// Widget styling shared by all widgets
+#[derive(Clone, Copy)]
+pub struct Theme<C> {
+    /// Base colors
+    pub palette: Palette<C>,
+    /// Swap background/foreground and water/text-on-water colors
+    pub inverted: bool,
+    /// Tank and gauge outline width
+    pub outline_width: u32,
+    /// Gauge major tick width
+    pub major_tick_width: u32,
+    /// Gauge minor tick width
+    pub minor_tick_width: u32,
+    /// Gauge needle width
+    pub needle_width: u32,
+    /// Font for readouts
+    pub font: &'static MonoFont<'static>,
+    /// Font for gauge scale labels
+    pub label_font: &'static MonoFont<'static>,
+    /// Tank water fill
+    pub fill: FillPattern,
+}
+
+impl<C: PixelColor> Theme<C> {
+    /// Default styling with the given colors
+    pub fn new(palette: Palette<C>) -> Self {
+        Self {
+            palette,
+            inverted: false,
+            outline_width: 2,
+            major_tick_width: 2,
+            minor_tick_width: 1,
+            needle_width: 2,
+            font: &FONT_10X20,
+            label_font: &FONT_6X10,
+            fill: FillPattern::Solid,
+        }
+    }
+
+    /// Effective colors, taking inversion into account
+    pub fn colors(&self) -> Palette<C> {
+        if self.inverted {
+            Palette {
+                background: self.palette.foreground,
+                foreground: self.palette.background,
+                water: self.palette.on_water,
+                on_water: self.palette.water,
+            }
+        } else {
+            self.palette
+        }
+    }
+}
+
 /// Water tank visualization
-pub struct WaterTank {
+pub struct WaterTank<C> {
     /// Top-left corner position
     pub position: Point,
     /// Tank dimensions (width, height)
@@ -23,15 +169,21 @@ pub struct WaterTank {
     pub fill_percent: u8,
     /// Current volume in gallons
     pub gallons: u16,
+    /// Short name drawn centered above the tank (empty = none)
+    pub name: String,
+    /// Widget styling
+    pub theme: Theme<C>,
 }
 
-impl WaterTank {
-    pub fn new(position: Point, size: Size) -> Self {
+impl<C: PixelColor> WaterTank<C> {
+    pub fn new(position: Point, size: Size, theme: Theme<C>) -> Self {
         Self {
             position,
             size,
             fill_percent: 0,
             gallons: 0,
+            name: String::new(),
+            theme,
         }
     }
 
@@ -42,8 +194,9 @@ impl WaterTank {
 
     pub fn draw<D>(&self, display: &mut D) -> Result<(), D::Error>
     where
-        D: DrawTarget<Color = BinaryColor>,
+        D: DrawTarget<Color = C>,
     {
+        let colors = self.theme.colors();
         let x = self.position.x;
         let y = self.position.y;
         let w = self.size.width as i32;
@@ -53,30 +206,45 @@ impl WaterTank {
         let fill_height = (h * self.fill_percent as i32) / 100;
         let fill_top = y + h - fill_height;
 
-        // Clear entire tank area with white (empty portion)
+        // Clear the empty portion with the background
         let empty_height = h - fill_height;
         if empty_height > 0 {
             Rectangle::new(
                 self.position,
                 Size::new(self.size.width, empty_height as u32),
             )
-            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .into_styled(PrimitiveStyle::with_fill(colors.background))
             .draw(display)?;
         }
 
-        // Draw filled water portion (black = water)
+        // Draw filled water portion
         if fill_height > 0 {
-            Rectangle::new(
+            let water = Rectangle::new(
                 Point::new(x, fill_top),
                 Size::new(self.size.width, fill_height as u32),
-            )
-            .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
-            .draw(display)?;
+            );
+            match self.theme.fill {
+                FillPattern::Solid => {
+                    water
+                        .into_styled(PrimitiveStyle::with_fill(colors.water))
+                        .draw(display)?;
+                }
+                pattern => {
+                    let pixels = water.points().map(|p| {
+                        if pattern.is_set(p) {
+                            colors.water
+                        } else {
+                            colors.background
+                        }
+                    });
+                    display.fill_contiguous(&water, pixels)?;
+                }
+            }
         }
 
         // Draw tank outline
         Rectangle::new(self.position, self.size)
-            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::Off, 2))
+            .into_styled(PrimitiveStyle::with_stroke(colors.foreground, self.theme.outline_width))
             .draw(display)?;
 
         // Draw text overlay
@@ -92,40 +260,72 @@ impl WaterTank {
         let mut gallons_buf = [0u8; 12];
         let gallons_str = format_with_suffix(self.gallons, &mut gallons_buf, b" gal");
 
-        let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
+        self.draw_label(display, percent_str, Point::new(center_x, text_y_percent), fill_top, &colors)?;
+        self.draw_label(display, gallons_str, Point::new(center_x, text_y_gallons), fill_top, &colors)?;
 
-        // Draw percentage - determine color based on position relative to water level
-        let percent_color = if text_y_percent > fill_top {
-            BinaryColor::On // White text on black water
-        } else {
-            BinaryColor::Off // Black text on white background
-        };
-        let percent_font = MonoTextStyleBuilder::new()
-            .font(&FONT_10X20)
-            .text_color(percent_color)
-            .build();
-        Text::with_text_style(percent_str, Point::new(center_x, text_y_percent), percent_font, text_style)
-            .draw(display)?;
+        // Name in the label font, on a cleared band the tank's width so a
+        // shorter new name leaves nothing behind
+        if !self.name.is_empty() {
+            let height = self.theme.label_font.character_size.height + 2;
+            Rectangle::new(Point::new(x, y - height as i32 - 1), Size::new(self.size.width, height))
+                .into_styled(PrimitiveStyle::with_fill(colors.background))
+                .draw(display)?;
+            let style = MonoTextStyle::new(self.theme.label_font, colors.foreground);
+            let above = TextStyleBuilder::new().alignment(Alignment::Center).baseline(Baseline::Bottom).build();
+            Text::with_text_style(&self.name, Point::new(center_x, y - 2), style, above).draw(display)?;
+        }
+        Ok(())
+    }
 
-        // Draw gallons - determine color based on position relative to water level
-        let gallons_color = if text_y_gallons > fill_top {
-            BinaryColor::On // White text on black water
+    /// Draw a centered readout, keeping it legible over the water fill
+    fn draw_label<D>(
+        &self,
+        display: &mut D,
+        text: &str,
+        position: Point,
+        fill_top: i32,
+        colors: &Palette<C>,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let over_water = position.y > fill_top;
+        let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
+        let color = if over_water && self.theme.fill == FillPattern::Solid {
+            colors.on_water
         } else {
-            BinaryColor::Off // Black text on white background
+            colors.foreground
         };
-        let gallons_font = MonoTextStyleBuilder::new()
-            .font(&FONT_10X20)
-            .text_color(gallons_color)
+        let font = MonoTextStyleBuilder::new()
+            .font(self.theme.font)
+            .text_color(color)
             .build();
-        Text::with_text_style(gallons_str, Point::new(center_x, text_y_gallons), gallons_font, text_style)
-            .draw(display)?;
+        let label = Text::with_text_style(text, position, font, text_style);
+
+        // Patterned fill would break up the glyphs, so back the text with a box
+        if over_water && self.theme.fill != FillPattern::Solid {
+            label
+                .bounding_box()
+                .offset(2)
+                .into_styled(PrimitiveStyle::with_fill(colors.background))
+                .draw(display)?;
+        }
 
+        label.draw(display)?;
         Ok(())
     }
 }
 
 /// Analog pressure gauge (manometer)
-pub struct Manometer {
+///
+/// The face (outline, ticks and scale labels) is the heavy part to draw, with
+/// a sine and cosine per tick, and it only changes with the geometry or the
+/// scale. It is drawn once into a `GaugeFace` mask and copied onto the
+/// display on each `draw`, so only the needle and the readout are rendered
+/// per frame. Changing `center`, `radius` or `max_psi` redraws the mask on
+/// the next `draw`; after changing the theme's widths or fonts, call
+/// `invalidate`. Colors are applied when copying, so `inverted` needs neither.
+pub struct Manometer<C> {
     /// Center position
     pub center: Point,
     /// Radius of the gauge
@@ -134,15 +334,21 @@ pub struct Manometer {
     pub pressure_psi: u16,
     /// Maximum pressure (for scale)
     pub max_psi: u16,
+    /// Widget styling
+    pub theme: Theme<C>,
+    /// Face drawn for the current geometry and scale
+    face: Option<GaugeFace>,
 }
 
-impl Manometer {
-    pub fn new(center: Point, radius: i32) -> Self {
+impl<C: PixelColor> Manometer<C> {
+    pub fn new(center: Point, radius: i32, theme: Theme<C>) -> Self {
         Self {
             center,
             radius,
             pressure_psi: 0,
             max_psi: 150,
+            theme,
+            face: None,
         }
     }
 
@@ -150,105 +356,820 @@ impl Manometer {
         self.pressure_psi = psi.min(self.max_psi);
     }
 
-    pub fn draw<D>(&self, display: &mut D) -> Result<(), D::Error>
+    /// Redraw the face on the next `draw`
+    pub fn invalidate(&mut self) {
+        self.face = None;
+    }
+
+    pub fn draw<D>(&mut self, display: &mut D) -> Result<(), D::Error>
     where
-        D: DrawTarget<Color = BinaryColor>,
+        D: DrawTarget<Color = C>,
     {
-        // Clear bounding box with white
-        Rectangle::new(
+        let colors = self.theme.colors();
+
+        // Face, with the background of the bounding box
+        let bounds = Rectangle::new(
             Point::new(self.center.x - self.radius, self.center.y - self.radius),
             Size::new((self.radius * 2) as u32, (self.radius * 2) as u32),
+        );
+        if !self.face.as_ref().is_some_and(|face| face.fits(bounds, self.max_psi)) {
+            // The outline stroke is centered on the circle, half of it outside the box
+            let margin = self.theme.outline_width.div_ceil(2);
+            let mut face = GaugeFace::new(bounds, margin, self.max_psi);
+            // Drawing into the mask can't fail
+            let _ = self.draw_face(&mut face.translated(-face.area.top_left));
+            self.face = Some(face);
+        }
+        if let Some(face) = &self.face {
+            face.blit(display, colors.foreground, colors.background)?;
+        }
+
+        // Draw needle
+        let needle_len = self.radius * 75 / 100;
+        let needle_end = self.center + Point::from(fixedmath::polar(needle_len, self.angle(self.pressure_psi)));
+
+        // Needle line
+        Line::new(self.center, needle_end)
+            .into_styled(PrimitiveStyle::with_stroke(colors.foreground, self.theme.needle_width))
+            .draw(display)?;
+
+        // Center hub
+        Circle::new(
+            Point::new(self.center.x - 5, self.center.y - 5),
+            10,
         )
-        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .into_styled(PrimitiveStyle::with_fill(colors.foreground))
         .draw(display)?;
 
+        // Digital readout below center
+        let mut psi_buf = [0u8; 8];
+        let psi_str = format_with_suffix(self.pressure_psi, &mut psi_buf, b" PSI");
+
+        let psi_style = MonoTextStyle::new(self.theme.font, colors.foreground);
+        let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
+        Text::with_text_style(psi_str, Point::new(self.center.x, self.center.y + 35), psi_style, text_style)
+            .draw(display)?;
+
+        Ok(())
+    }
+
+    /// Outline, ticks and scale labels, in display coordinates, `On` for the foreground
+    fn draw_face<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let foreground = BinaryColor::On;
+
         // Draw outer circle
         Circle::new(
             Point::new(self.center.x - self.radius, self.center.y - self.radius),
             (self.radius * 2) as u32,
         )
-        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::Off, 2))
-        .draw(display)?;
-
-        // Draw tick marks and labels
-        // Gauge arc from 225° (min) to -45° (max) = 270° sweep
-        // 0 PSI at 225°, 150 PSI at -45° (315°)
-        let start_angle: f32 = 225.0;
-        let end_angle: f32 = -45.0;
-        let sweep = start_angle - end_angle; // 270 degrees
+        .into_styled(PrimitiveStyle::with_stroke(foreground, self.theme.outline_width))
+        .draw(target)?;
 
         // Draw all ticks: major every 30 PSI, minor every 10 PSI
         for i in 0..=15 {
             let psi = i * 10;
             let is_major = psi % 30 == 0;
-            let angle_deg = start_angle - (psi as f32 / self.max_psi as f32) * sweep;
-            let angle_rad = angle_deg * core::f32::consts::PI / 180.0;
-
-            let cos_a = libm::cosf(angle_rad);
-            let sin_a = libm::sinf(angle_rad);
+            let angle = self.angle(psi);
 
-            let inner_frac = if is_major { 0.80 } else { 0.88 };
-            let inner_r = (self.radius as f32 * inner_frac) as i32;
-            let outer_r = (self.radius as f32 * 0.95) as i32;
+            let inner_percent = if is_major { 80 } else { 88 };
+            let inner_r = self.radius * inner_percent / 100;
+            let outer_r = self.radius * 95 / 100;
 
-            let x1 = self.center.x + (cos_a * inner_r as f32) as i32;
-            let y1 = self.center.y - (sin_a * inner_r as f32) as i32;
-            let x2 = self.center.x + (cos_a * outer_r as f32) as i32;
-            let y2 = self.center.y - (sin_a * outer_r as f32) as i32;
+            let inner = self.center + Point::from(fixedmath::polar(inner_r, angle));
+            let outer = self.center + Point::from(fixedmath::polar(outer_r, angle));
 
-            let stroke_w = if is_major { 2 } else { 1 };
-            Line::new(Point::new(x1, y1), Point::new(x2, y2))
-                .into_styled(PrimitiveStyle::with_stroke(BinaryColor::Off, stroke_w))
-                .draw(display)?;
+            let stroke_w = if is_major {
+                self.theme.major_tick_width
+            } else {
+                self.theme.minor_tick_width
+            };
+            Line::new(inner, outer)
+                .into_styled(PrimitiveStyle::with_stroke(foreground, stroke_w))
+                .draw(target)?;
 
             if is_major {
-                let label_r = (self.radius as f32 * 0.65) as i32;
-                let label_x = self.center.x + (cos_a * label_r as f32) as i32;
-                let label_y = self.center.y - (sin_a * label_r as f32) as i32;
+                let label_r = self.radius * 65 / 100;
+                let label = self.center + Point::from(fixedmath::polar(label_r, angle));
 
                 let mut label_buf = [0u8; 4];
-                let label_str = format_number(psi as u16, &mut label_buf);
+                let label_str = format_number(psi, &mut label_buf);
 
-                let label_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::Off);
+                let label_style = MonoTextStyle::new(self.theme.label_font, foreground);
                 let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
-                Text::with_text_style(label_str, Point::new(label_x, label_y + 3), label_style, text_style)
-                    .draw(display)?;
+                Text::with_text_style(label_str, label + Point::new(0, 3), label_style, text_style)
+                    .draw(target)?;
             }
         }
 
-        // Draw needle
-        let pressure_angle_deg = start_angle - (self.pressure_psi as f32 / self.max_psi as f32) * sweep;
-        let pressure_angle_rad = pressure_angle_deg * core::f32::consts::PI / 180.0;
+        Ok(())
+    }
 
-        let cos_p = libm::cosf(pressure_angle_rad);
-        let sin_p = libm::sinf(pressure_angle_rad);
+    /// Angle of `psi` on the scale (tenths of a degree): the gauge arc runs
+    /// from 225° at 0 PSI clockwise to -45° at `max_psi`, a 270° sweep
+    fn angle(&self, psi: u16) -> i32 {
+        2250 - psi as i32 * 2700 / self.max_psi.max(1) as i32
+    }
+}
 
-        let needle_len = (self.radius as f32 * 0.75) as i32;
-        let needle_end_x = self.center.x + (cos_p * needle_len as f32) as i32;
-        let needle_end_y = self.center.y - (sin_p * needle_len as f32) as i32;
+/// Offscreen one-bit mask of the gauge face, set where the foreground goes
+///
+/// A bit per pixel keeps a 180 px gauge face to 4 KB, whatever the panel's
+/// color depth. The mask reaches `margin` pixels past the gauge's bounding
+/// box for the outline; copied back, the box gets every pixel and the margin
+/// only the foreground, as when the face was drawn directly.
+struct GaugeFace {
+    /// Gauge bounding box
+    bounds: Rectangle,
+    /// Masked area, the bounds and the margin around them
+    area: Rectangle,
+    /// `max_psi` the face was drawn for
+    max_psi: u16,
+    bits: Vec<u8>,
+}
 
-        // Needle line
-        Line::new(self.center, Point::new(needle_end_x, needle_end_y))
-            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::Off, 2))
+impl GaugeFace {
+    fn new(bounds: Rectangle, margin: u32, max_psi: u16) -> Self {
+        let area = bounds.offset(margin as i32);
+        let len = (area.size.width * area.size.height).div_ceil(8) as usize;
+        Self { bounds, area, max_psi, bits: vec![0; len] }
+    }
+
+    /// Drawn for this geometry and scale
+    fn fits(&self, bounds: Rectangle, max_psi: u16) -> bool {
+        self.bounds == bounds && self.max_psi == max_psi
+    }
+
+    fn is_set(&self, index: usize) -> bool {
+        self.bits[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+
+    /// Copy onto `display`
+    fn blit<D, C>(&self, display: &mut D, foreground: C, background: C) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+        C: PixelColor,
+    {
+        let pixels = self.area.points().enumerate().filter_map(|(index, point)| {
+            if self.is_set(index) {
+                Some(Pixel(point, foreground))
+            } else {
+                self.bounds.contains(point).then_some(Pixel(point, background))
+            }
+        });
+        display.draw_iter(pixels)
+    }
+}
+
+impl OriginDimensions for GaugeFace {
+    fn size(&self) -> Size {
+        self.area.size
+    }
+}
+
+impl DrawTarget for GaugeFace {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<BinaryColor>>,
+    {
+        let (width, height) = (self.area.size.width as i32, self.area.size.height as i32);
+        for Pixel(point, color) in pixels {
+            if !(0..width).contains(&point.x) || !(0..height).contains(&point.y) {
+                continue;
+            }
+            let index = (point.y * width + point.x) as usize;
+            let mask = 0x80 >> (index % 8);
+            if color.is_on() {
+                self.bits[index / 8] |= mask;
+            } else {
+                self.bits[index / 8] &= !mask;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tank level history as a line chart, newest sample on the right
+pub struct TrendGraph<C> {
+    /// Top-left corner position
+    pub position: Point,
+    /// Chart dimensions including the outline
+    pub size: Size,
+    /// Caption drawn above the chart (e.g. `24h`)
+    pub caption: &'static str,
+    /// Widget styling
+    pub theme: Theme<C>,
+    /// Level samples (0-100 %), oldest first
+    samples: VecDeque<u8>,
+    /// Samples spanning the chart width
+    capacity: usize,
+}
+
+impl<C: PixelColor> TrendGraph<C> {
+    pub fn new(position: Point, size: Size, capacity: usize, caption: &'static str, theme: Theme<C>) -> Self {
+        Self {
+            position,
+            size,
+            caption,
+            theme,
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(2),
+        }
+    }
+
+    /// Append a level sample, dropping the oldest once the chart is full
+    pub fn push(&mut self, percent: u8) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(percent.min(100));
+    }
+
+    pub fn draw<D>(&self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let colors = self.theme.colors();
+        let frame = Rectangle::new(self.position, self.size);
+        frame
+            .into_styled(
+                PrimitiveStyleBuilder::new()
+                    .fill_color(colors.background)
+                    .stroke_color(colors.foreground)
+                    .stroke_width(1)
+                    .build(),
+            )
             .draw(display)?;
 
-        // Center hub
-        Circle::new(
-            Point::new(self.center.x - 5, self.center.y - 5),
-            10,
+        let label_style = MonoTextStyle::new(self.theme.label_font, colors.foreground);
+        Text::new(self.caption, Point::new(self.position.x, self.position.y - 3), label_style).draw(display)?;
+
+        // Plot area inside the 1px frame
+        let left = self.position.x + 1;
+        let top = self.position.y + 1;
+        let width = self.size.width as i32 - 3;
+        let height = self.size.height as i32 - 3;
+        let right = left + width;
+        let step = width as f32 / (self.capacity - 1) as f32;
+        let point = |age: usize, percent: u8| {
+            Point::new(
+                right - (age as f32 * step) as i32,
+                top + height - height * percent as i32 / 100,
+            )
+        };
+
+        let style = PrimitiveStyle::with_stroke(colors.foreground, self.theme.minor_tick_width);
+        let newest = self.samples.len().saturating_sub(1);
+        let mut prev: Option<Point> = None;
+        for (i, &percent) in self.samples.iter().enumerate() {
+            let p = point(newest - i, percent);
+            match prev {
+                Some(q) => Line::new(q, p).into_styled(style).draw(display)?,
+                // A single sample shows as a dot
+                None if newest == 0 => Pixel(p, colors.foreground).draw(display)?,
+                None => {}
+            }
+            prev = Some(p);
+        }
+        Ok(())
+    }
+}
+
+/// Day's consumption against the daily water budget, captioned above the bar
+///
+/// The bar fills with the water color up to the budget and turns solid
+/// foreground once it is exceeded. Nothing is drawn without a budget.
+pub struct BudgetBar<C> {
+    /// Top-left corner position
+    pub position: Point,
+    /// Caption and bar dimensions
+    pub size: Size,
+    /// Widget styling
+    pub theme: Theme<C>,
+    used: u32,
+    budget: u16,
+}
+
+impl<C: PixelColor> BudgetBar<C> {
+    pub fn new(position: Point, size: Size, theme: Theme<C>) -> Self {
+        Self { position, size, theme, used: 0, budget: 0 }
+    }
+
+    /// Gallons used today and the budget (0 = none)
+    pub fn set_usage(&mut self, used: u32, budget: u16) {
+        self.used = used;
+        self.budget = budget;
+    }
+
+    pub fn draw<D>(&self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        if self.budget == 0 {
+            return Ok(());
+        }
+        let colors = self.theme.colors();
+        Rectangle::new(self.position, self.size)
+            .into_styled(PrimitiveStyle::with_fill(colors.background))
+            .draw(display)?;
+
+        let over = self.used > self.budget as u32;
+        let caption = format!(
+            "Budget: {} of {} gal{}",
+            self.used,
+            self.budget,
+            if over { " - OVER" } else { "" }
+        );
+        let label_style = MonoTextStyle::new(self.theme.label_font, colors.foreground);
+        Text::with_baseline(&caption, self.position, label_style, Baseline::Top).draw(display)?;
+
+        let caption_height = self.theme.label_font.character_size.height + 2;
+        let bar = Rectangle::new(
+            self.position + Point::new(0, caption_height as i32),
+            Size::new(self.size.width, self.size.height.saturating_sub(caption_height)),
+        );
+        bar.into_styled(PrimitiveStyle::with_stroke(colors.foreground, 1)).draw(display)?;
+        let inner = bar.size.saturating_sub(Size::new(4, 4));
+        let (width, fill) = if over {
+            (inner.width, colors.foreground)
+        } else {
+            ((inner.width as u64 * self.used as u64 / self.budget as u64) as u32, colors.water)
+        };
+        Rectangle::new(bar.top_left + Point::new(2, 2), Size::new(width, inner.height))
+            .into_styled(PrimitiveStyle::with_fill(fill))
+            .draw(display)?;
+        Ok(())
+    }
+}
+
+/// Full-screen display pages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Page {
+    /// Tank, gauge and trend
+    Overview,
+    /// Addresses, MQTT connection and device health
+    Network,
+    /// Level trend and daily usage
+    History,
+    /// Summary of the main settings
+    Config,
+}
+
+/// Selects the visible page, advancing on a timer or a button press
+///
+/// Pages share one framebuffer: after a switch the caller clears it and
+/// invalidates the page widgets (see `take_invalidated`).
+pub struct PageManager {
+    pages: Vec<Page>,
+    current: usize,
+    /// Time on each page before advancing (`ZERO` = button only)
+    interval: Duration,
+    /// When the current page was shown
+    shown_at: Duration,
+    /// Whole screen needs redrawing
+    invalidated: bool,
+}
+
+impl PageManager {
+    pub fn new(pages: Vec<Page>, interval: Duration) -> Self {
+        assert!(!pages.is_empty(), "no display pages");
+        Self {
+            pages,
+            current: 0,
+            interval,
+            shown_at: Duration::ZERO,
+            invalidated: true,
+        }
+    }
+
+    /// Visible page
+    pub fn current(&self) -> Page {
+        self.pages[self.current]
+    }
+
+    /// Change the auto-advance interval (`ZERO` = button only)
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Go to the next page (button press)
+    pub fn next(&mut self, now: Duration) {
+        self.select((self.current + 1) % self.pages.len(), now);
+    }
+
+    /// Jump to a page, if it is enabled
+    pub fn show(&mut self, page: Page, now: Duration) {
+        if let Some(index) = self.pages.iter().position(|&p| p == page) {
+            self.select(index, now);
+        }
+    }
+
+    /// Advance once the current page has been shown for the interval
+    pub fn update(&mut self, now: Duration) {
+        if self.interval > Duration::ZERO && self.pages.len() > 1 && now >= self.shown_at + self.interval {
+            self.next(now);
+        }
+    }
+
+    /// Request a full redraw, e.g. after an overlay message was shown
+    pub fn invalidate(&mut self) {
+        self.invalidated = true;
+    }
+
+    /// Whether the screen has to be cleared and redrawn, resetting the flag
+    pub fn take_invalidated(&mut self) -> bool {
+        core::mem::take(&mut self.invalidated)
+    }
+
+    fn select(&mut self, index: usize, now: Duration) {
+        if index != self.current {
+            self.current = index;
+            self.invalidated = true;
+        }
+        self.shown_at = now;
+    }
+}
+
+/// Text page: a title over lines of text
+///
+/// Only redrawn when the text changes or after `invalidate()`, so static
+/// pages cost no display bandwidth.
+pub struct TextPage<C> {
+    /// Top-left corner position
+    pub position: Point,
+    /// Area cleared before the text is redrawn
+    pub size: Size,
+    /// Heading, underlined
+    pub title: &'static str,
+    /// Widget styling
+    pub theme: Theme<C>,
+    lines: Vec<String>,
+    dirty: bool,
+}
+
+impl<C: PixelColor> TextPage<C> {
+    pub fn new(position: Point, size: Size, title: &'static str, theme: Theme<C>) -> Self {
+        Self {
+            position,
+            size,
+            title,
+            theme,
+            lines: Vec::new(),
+            dirty: true,
+        }
+    }
+
+    /// Replace the text; the page is redrawn only if it changed
+    pub fn set_lines(&mut self, lines: Vec<String>) {
+        if lines != self.lines {
+            self.lines = lines;
+            self.dirty = true;
+        }
+    }
+
+    /// Redraw on the next `draw`, e.g. after the framebuffer was cleared
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn draw<D>(&mut self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+
+        let colors = self.theme.colors();
+        Rectangle::new(self.position, self.size)
+            .into_styled(PrimitiveStyle::with_fill(colors.background))
+            .draw(display)?;
+
+        let style = MonoTextStyle::new(self.theme.font, colors.foreground);
+        let line_height = self.theme.font.character_size.height as i32 + 4;
+        let mut y = self.position.y + line_height;
+        Text::new(self.title, Point::new(self.position.x, y - 4), style).draw(display)?;
+        Line::new(
+            Point::new(self.position.x, y),
+            Point::new(self.position.x + self.size.width as i32 - 1, y),
         )
-        .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+        .into_styled(PrimitiveStyle::with_stroke(colors.foreground, 1))
         .draw(display)?;
 
-        // Digital readout below center
-        let mut psi_buf = [0u8; 8];
-        let psi_str = format_with_suffix(self.pressure_psi, &mut psi_buf, b" PSI");
+        y += 4;
+        for line in &self.lines {
+            y += line_height;
+            if y > self.position.y + self.size.height as i32 {
+                break;
+            }
+            Text::new(line, Point::new(self.position.x, y - 4), style).draw(display)?;
+        }
+        Ok(())
+    }
+}
 
-        let psi_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
-        let text_style = TextStyleBuilder::new().alignment(Alignment::Center).build();
-        Text::with_text_style(psi_str, Point::new(self.center.x, self.center.y + 35), psi_style, text_style)
+/// State of a progress step, shown as an icon in front of its line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepState {
+    /// Plain line without an icon (addresses, settings)
+    Info,
+    /// Started, not finished yet: hollow circle
+    Running,
+    /// Check mark
+    Done,
+    /// Cross
+    Failed,
+}
+
+/// Progress screen: a title over a list of steps with status icons
+///
+/// Shows the boot sequence and the firmware update progress. A running step
+/// counts as done once the next line is added, so the caller only reports
+/// failures. When the list outgrows the screen it scrolls: the latest lines
+/// stay below the title and the oldest ones go off the top.
+pub struct BootScreen<C> {
+    /// Top-left corner position
+    pub position: Point,
+    /// Area cleared before the screen is redrawn
+    pub size: Size,
+    /// Heading, underlined
+    pub title: String,
+    /// Widget styling
+    pub theme: Theme<C>,
+    lines: Vec<(StepState, String)>,
+}
+
+impl<C: PixelColor> BootScreen<C> {
+    pub fn new(position: Point, size: Size, title: impl Into<String>, theme: Theme<C>) -> Self {
+        Self {
+            position,
+            size,
+            title: title.into(),
+            theme,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Start a step; the one before it, if still running, is done
+    pub fn step(&mut self, text: impl Into<String>) {
+        self.finish();
+        self.lines.push((StepState::Running, text.into()));
+    }
+
+    /// Add a line without an icon; a running step is done
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.finish();
+        self.lines.push((StepState::Info, text.into()));
+    }
+
+    /// Mark the running step done
+    pub fn finish(&mut self) {
+        self.set_last(StepState::Done);
+    }
+
+    /// Mark the running step failed
+    pub fn fail(&mut self) {
+        self.set_last(StepState::Failed);
+    }
+
+    /// Drop all lines, to show a new sequence on the same screen
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    fn set_last(&mut self, state: StepState) {
+        if let Some(last) = self.lines.last_mut().filter(|(s, _)| *s == StepState::Running) {
+            last.0 = state;
+        }
+    }
+
+    fn line_height(&self) -> i32 {
+        self.theme.font.character_size.height as i32 + 4
+    }
+
+    /// Lines that fit below the title
+    fn visible_lines(&self) -> usize {
+        let below_title = self.size.height as i32 - self.line_height() - 4;
+        (below_title / self.line_height()).max(0) as usize
+    }
+
+    pub fn draw<D>(&self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let colors = self.theme.colors();
+        Rectangle::new(self.position, self.size)
+            .into_styled(PrimitiveStyle::with_fill(colors.background))
             .draw(display)?;
 
+        let style = MonoTextStyle::new(self.theme.font, colors.foreground);
+        let line_height = self.line_height();
+        let mut y = self.position.y + line_height;
+        Text::new(&self.title, Point::new(self.position.x, y - 4), style).draw(display)?;
+        Line::new(
+            Point::new(self.position.x, y),
+            Point::new(self.position.x + self.size.width as i32 - 1, y),
+        )
+        .into_styled(PrimitiveStyle::with_stroke(colors.foreground, 1))
+        .draw(display)?;
+
+        // Icons are a little smaller than the text, in a column of their own
+        let icon = self.theme.font.character_size.height as i32 - 6;
+        let text_x = self.position.x + icon + 8;
+        let stroke = PrimitiveStyle::with_stroke(colors.foreground, 2);
+        let skip = self.lines.len().saturating_sub(self.visible_lines());
+        y += 4;
+        for (state, text) in &self.lines[skip..] {
+            y += line_height;
+            // Icon box standing on the text baseline
+            let top = y - 4 - icon;
+            let left = self.position.x;
+            match state {
+                StepState::Info => {}
+                StepState::Running => {
+                    Circle::new(Point::new(left, top), icon as u32).into_styled(stroke).draw(display)?;
+                }
+                StepState::Done => {
+                    let corner = Point::new(left + icon / 3, top + icon - 1);
+                    Line::new(Point::new(left, top + icon / 2), corner).into_styled(stroke).draw(display)?;
+                    Line::new(corner, Point::new(left + icon - 1, top)).into_styled(stroke).draw(display)?;
+                }
+                StepState::Failed => {
+                    let (right, bottom) = (left + icon - 1, top + icon - 1);
+                    Line::new(Point::new(left, top), Point::new(right, bottom)).into_styled(stroke).draw(display)?;
+                    Line::new(Point::new(left, bottom), Point::new(right, top)).into_styled(stroke).draw(display)?;
+                }
+            }
+            Text::new(text, Point::new(text_x, y - 4), style).draw(display)?;
+        }
+        Ok(())
+    }
+}
+
+/// Alert banner: one line of text on an inverted bar, drawn over the page
+///
+/// Drawn on every frame after the page, so it stays on top of widgets that
+/// redraw themselves; unchanged pixels cost nothing to flush. Draws nothing
+/// while there is no text, and the caller redraws the page when the banner
+/// goes away (see `set_text`).
+pub struct AlertBanner<C> {
+    /// Top-left corner position
+    pub position: Point,
+    /// Bar dimensions
+    pub size: Size,
+    /// Widget styling
+    pub theme: Theme<C>,
+    text: Option<String>,
+}
+
+impl<C: PixelColor> AlertBanner<C> {
+    pub fn new(position: Point, size: Size, theme: Theme<C>) -> Self {
+        Self {
+            position,
+            size,
+            theme,
+            text: None,
+        }
+    }
+
+    /// Replace the text (`None` hides the banner); returns whether it changed
+    pub fn set_text(&mut self, text: Option<String>) -> bool {
+        let changed = text != self.text;
+        self.text = text;
+        changed
+    }
+
+    pub fn draw<D>(&self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let Some(text) = &self.text else {
+            return Ok(());
+        };
+
+        let colors = self.theme.colors();
+        Rectangle::new(self.position, self.size)
+            .into_styled(PrimitiveStyle::with_fill(colors.foreground))
+            .draw(display)?;
+        let style = MonoTextStyle::new(self.theme.font, colors.background);
+        let centered = TextStyleBuilder::new().alignment(Alignment::Center).baseline(Baseline::Middle).build();
+        let center = self.position + Point::new(self.size.width as i32 / 2, self.size.height as i32 / 2);
+        Text::with_text_style(text, center, style, centered).draw(display)?;
+        Ok(())
+    }
+}
+
+/// QR code screen: a title and a few lines on the left, the code on the right
+///
+/// The code takes the full height, or half the width on narrow screens, with
+/// the four-module quiet zone around it. Dark modules are drawn in the
+/// foreground color, so an inverted theme gives a light-on-dark code, which
+/// current phone cameras read as well. Redrawn only when the content changes
+/// or after `invalidate()`.
+pub struct QrScreen<C> {
+    /// Top-left corner position
+    pub position: Point,
+    /// Area cleared before the screen is redrawn
+    pub size: Size,
+    /// Widget styling
+    pub theme: Theme<C>,
+    title: String,
+    lines: Vec<String>,
+    data: String,
+    code: Option<QrCode>,
+    dirty: bool,
+}
+
+impl<C: PixelColor> QrScreen<C> {
+    pub fn new(position: Point, size: Size, theme: Theme<C>) -> Self {
+        Self {
+            position,
+            size,
+            theme,
+            title: String::new(),
+            lines: Vec::new(),
+            data: String::new(),
+            code: None,
+            dirty: true,
+        }
+    }
+
+    /// Replace the text and the encoded data; redrawn only if they changed
+    ///
+    /// Data too long for a QR code leaves the right side empty.
+    pub fn set(&mut self, title: &str, lines: Vec<String>, data: &str) {
+        if data != self.data {
+            self.data = data.to_string();
+            self.code = QrCode::encode(data.as_bytes());
+            self.dirty = true;
+        }
+        if title != self.title || lines != self.lines {
+            self.title = title.to_string();
+            self.lines = lines;
+            self.dirty = true;
+        }
+    }
+
+    /// Redraw on the next `draw`, e.g. after the framebuffer was cleared
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn draw<D>(&mut self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+
+        let colors = self.theme.colors();
+        Rectangle::new(self.position, self.size)
+            .into_styled(PrimitiveStyle::with_fill(colors.background))
+            .draw(display)?;
+
+        let side = self.size.height.min(self.size.width / 2) as i32;
+        let text_width = self.size.width as i32 - side - 8;
+        if let Some(code) = &self.code {
+            let modules = code.size() as i32;
+            let scale = side / (modules + 8);
+            let origin = self.position
+                + Point::new(
+                    self.size.width as i32 - (modules + 4) * scale,
+                    (self.size.height as i32 - modules * scale) / 2,
+                );
+            let dark = PrimitiveStyle::with_fill(colors.foreground);
+            for y in 0..code.size() {
+                for x in (0..code.size()).filter(|&x| code.is_dark(x, y)) {
+                    let corner = origin + Point::new(x as i32 * scale, y as i32 * scale);
+                    Rectangle::new(corner, Size::new(scale as u32, scale as u32)).into_styled(dark).draw(display)?;
+                }
+            }
+        }
+
+        let style = MonoTextStyle::new(self.theme.font, colors.foreground);
+        let line_height = self.theme.font.character_size.height as i32 + 4;
+        let mut y = self.position.y + line_height;
+        Text::new(&self.title, Point::new(self.position.x, y - 4), style).draw(display)?;
+        Line::new(Point::new(self.position.x, y), Point::new(self.position.x + text_width, y))
+            .into_styled(PrimitiveStyle::with_stroke(colors.foreground, 1))
+            .draw(display)?;
+
+        y += 4;
+        for line in &self.lines {
+            y += line_height;
+            if y > self.position.y + self.size.height as i32 {
+                break;
+            }
+            Text::new(line, Point::new(self.position.x, y - 4), style).draw(display)?;
+        }
         Ok(())
     }
 }
@@ -281,3 +1202,226 @@ fn format_with_suffix<'a>(n: u16, buf: &'a mut [u8], suffix: &[u8]) -> &'a str {
     buf[i..i + suffix.len()].copy_from_slice(suffix);
     unsafe { core::str::from_utf8_unchecked(&buf[..i + suffix.len()]) }
 }
+
+#[cfg(test)]
+mod tests {
+    //! Golden-image tests: each widget is drawn on the mono palette into a
+    //! `Framebuffer` a little larger than the widget, so anything drawn past
+    //! its edges shows too, and compared pixel for pixel with a binary PBM in
+    //! `golden/`. A mismatch writes the actual image next to the golden one
+    //! as `<name>.actual.pbm` to look at. After an intended change, rerun
+    //! with `BLESS=1` to write the new images, and check them in:
+    //!
+    //! ```text
+    //! BLESS=1 cargo test --lib --no-default-features --features display --target x86_64-unknown-linux-gnu ui::
+    //! ```
+
+    use super::*;
+    use std::convert::Infallible;
+    use std::path::PathBuf;
+
+    /// Mono framebuffer starting out in the background color
+    #[derive(Debug, PartialEq)]
+    struct Framebuffer {
+        size: Size,
+        pixels: Vec<BinaryColor>,
+    }
+
+    impl Framebuffer {
+        fn new(width: u32, height: u32) -> Self {
+            Self { size: Size::new(width, height), pixels: vec![Palette::MONO.background; (width * height) as usize] }
+        }
+
+        /// Binary PBM (P4): rows padded to whole bytes, a set bit is black
+        fn to_pbm(&self) -> Vec<u8> {
+            let Size { width, height } = self.size;
+            let mut pbm = format!("P4\n{} {}\n", width, height).into_bytes();
+            for row in self.pixels.chunks(width as usize) {
+                for byte in row.chunks(8) {
+                    let bits = byte.iter().enumerate().filter(|(_, c)| c.is_off()).fold(0u8, |b, (i, _)| b | 0x80 >> i);
+                    pbm.push(bits);
+                }
+            }
+            pbm
+        }
+
+        /// Read a PBM as written by `to_pbm`
+        fn from_pbm(pbm: &[u8]) -> Option<Self> {
+            let mut header = pbm.splitn(4, |b| b.is_ascii_whitespace());
+            let magic = header.next()?;
+            let width: u32 = std::str::from_utf8(header.next()?).ok()?.parse().ok()?;
+            let height: u32 = std::str::from_utf8(header.next()?).ok()?.parse().ok()?;
+            let data = header.next()?;
+            let stride = width.div_ceil(8) as usize;
+            if magic != b"P4" || data.len() != stride * height as usize {
+                return None;
+            }
+            let mut image = Self::new(width, height);
+            for (i, pixel) in image.pixels.iter_mut().enumerate() {
+                let (x, y) = (i % width as usize, i / width as usize);
+                if data[y * stride + x / 8] & (0x80 >> (x % 8)) != 0 {
+                    *pixel = BinaryColor::Off;
+                }
+            }
+            Some(image)
+        }
+    }
+
+    impl OriginDimensions for Framebuffer {
+        fn size(&self) -> Size {
+            self.size
+        }
+    }
+
+    impl DrawTarget for Framebuffer {
+        type Color = BinaryColor;
+        type Error = Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            let Size { width, height } = self.size;
+            for Pixel(p, color) in pixels {
+                if (0..width as i32).contains(&p.x) && (0..height as i32).contains(&p.y) {
+                    self.pixels[(p.y as u32 * width + p.x as u32) as usize] = color;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn theme() -> Theme<BinaryColor> {
+        Theme::new(Palette::MONO)
+    }
+
+    /// Compare `image` with `golden/<name>.pbm`, or replace it with `BLESS` set
+    fn assert_golden(name: &str, image: &Framebuffer) {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden");
+        let path = dir.join(format!("{}.pbm", name));
+        let actual_path = dir.join(format!("{}.actual.pbm", name));
+        if std::env::var_os("BLESS").is_some() {
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(&path, image.to_pbm()).unwrap();
+            let _ = std::fs::remove_file(&actual_path);
+            return;
+        }
+        let golden = match std::fs::read(&path) {
+            Ok(pbm) => Framebuffer::from_pbm(&pbm).unwrap_or_else(|| panic!("{}: not a binary PBM", path.display())),
+            Err(e) => panic!("{}: {}; run with BLESS=1 to create it", path.display(), e),
+        };
+        if golden == *image {
+            let _ = std::fs::remove_file(&actual_path);
+            return;
+        }
+        std::fs::write(&actual_path, image.to_pbm()).unwrap();
+        assert_eq!(golden.size, image.size, "{}: size changed, see {}", name, actual_path.display());
+        let width = image.size.width as usize;
+        let differ: Vec<usize> = (0..image.pixels.len()).filter(|&i| golden.pixels[i] != image.pixels[i]).collect();
+        panic!(
+            "{}: {} pixels differ, the first at ({}, {}); see {}, or rerun with BLESS=1 if intended",
+            name,
+            differ.len(),
+            differ[0] % width,
+            differ[0] / width,
+            actual_path.display()
+        );
+    }
+
+    #[test]
+    fn test_pbm_round_trip() {
+        let mut image = Framebuffer::new(11, 3);
+        Line::new(Point::new(0, 0), Point::new(10, 2))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::Off, 1))
+            .draw(&mut image)
+            .unwrap();
+        let pbm = image.to_pbm();
+        assert_eq!(&pbm[..8], b"P4\n11 3\n");
+        assert_eq!(pbm[8..], [0b1110_0000, 0, 0b0001_1111, 0, 0, 0b1110_0000]);
+        assert_eq!(Framebuffer::from_pbm(&pbm), Some(image));
+        assert_eq!(Framebuffer::from_pbm(b"P4\n11 3\n\0"), None);
+    }
+
+    #[test]
+    fn test_golden_overview() {
+        let mut image = Framebuffer::new(120, 220);
+        let mut tank = WaterTank::new(Point::new(10, 10), Size::new(100, 200), theme());
+        tank.set_level(62, 1234);
+        tank.draw(&mut image).unwrap();
+        assert_golden("tank", &image);
+
+        // The longest name there is room for
+        let mut image = Framebuffer::new(120, 224);
+        tank.position = Point::new(10, 14);
+        tank.name = "PRESSURE TANK 02".to_string();
+        tank.draw(&mut image).unwrap();
+        assert_golden("tank_named", &image);
+
+        let mut image = Framebuffer::new(80, 140);
+        let mut tank = WaterTank::new(Point::new(10, 10), Size::new(60, 120), Theme { fill: FillPattern::Hatched, ..theme() });
+        tank.set_level(35, 420);
+        tank.draw(&mut image).unwrap();
+        assert_golden("tank_hatched", &image);
+
+        let mut image = Framebuffer::new(200, 200);
+        let mut gauge = Manometer::new(Point::new(100, 100), 90, theme());
+        gauge.set_pressure(57);
+        gauge.draw(&mut image).unwrap();
+        assert_golden("manometer", &image);
+        // Redrawn from the cached face at another pressure and back
+        gauge.set_pressure(140);
+        gauge.draw(&mut image).unwrap();
+        gauge.set_pressure(57);
+        gauge.draw(&mut image).unwrap();
+        assert_golden("manometer", &image);
+
+        let mut image = Framebuffer::new(120, 80);
+        let mut trend = TrendGraph::new(Point::new(4, 14), Size::new(112, 62), 24, "24h", theme());
+        for level in [80, 78, 75, 71, 66, 60, 55, 52, 50, 49, 60, 72, 85, 95, 96, 95, 93, 90, 88, 85, 84, 82, 80, 79] {
+            trend.push(level);
+        }
+        trend.draw(&mut image).unwrap();
+        assert_golden("trend", &image);
+    }
+
+    #[test]
+    fn test_golden_pages() {
+        let mut image = Framebuffer::new(240, 112);
+        let mut page = TextPage::new(Point::new(4, 4), Size::new(232, 104), "Network", theme());
+        page.set_lines(vec!["IP: 192.168.1.20".to_string(), "MQTT: connected".to_string(), "Uptime: 3d 04:05".to_string()]);
+        page.draw(&mut image).unwrap();
+        assert_golden("text_page", &image);
+
+        let mut image = Framebuffer::new(240, 80);
+        let mut under = BudgetBar::new(Point::new(4, 4), Size::new(232, 26), theme());
+        under.set_usage(120, 400);
+        under.draw(&mut image).unwrap();
+        let mut over = BudgetBar::new(Point::new(4, 44), Size::new(232, 26), theme());
+        over.set_usage(450, 400);
+        over.draw(&mut image).unwrap();
+        assert_golden("budget_bar", &image);
+
+        let mut image = Framebuffer::new(240, 136);
+        let mut boot = BootScreen::new(Point::new(4, 4), Size::new(232, 128), "Booting", theme());
+        boot.step("NVS");
+        boot.step("Ethernet");
+        boot.info("IP 10.0.0.7");
+        boot.step("MQTT");
+        boot.fail();
+        boot.step("Display");
+        boot.draw(&mut image).unwrap();
+        assert_golden("boot_screen", &image);
+
+        let mut image = Framebuffer::new(240, 28);
+        let mut banner = AlertBanner::new(Point::new(0, 4), Size::new(240, 20), theme());
+        banner.set_text(Some("LOW WATER".to_string()));
+        banner.draw(&mut image).unwrap();
+        assert_golden("alert_banner", &image);
+
+        let mut image = Framebuffer::new(400, 240);
+        let mut qr = QrScreen::new(Point::new(10, 4), Size::new(380, 232), theme());
+        qr.set("AL01 Low Water", vec!["Tank below 20%".to_string()], "https://example.com/runbook/AL01");
+        qr.draw(&mut image).unwrap();
+        assert_golden("qr_screen", &image);
+    }
+}
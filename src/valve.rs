@@ -0,0 +1,324 @@
+//! Motorized supply valve
+//!
+//! A two-wire motorized ball valve on the supply line is driven through an
+//! open relay and a close relay. A move energizes one of them until the
+//! valve reaches its end position:
+//!
+//! - With limit switches fitted, the move ends when the switch for the
+//!   target position closes. A move that hasn't reached it within
+//!   `FAULT_FACTOR` times the travel time is stopped and flagged as a fault.
+//! - Without them, the move ends after the configured travel time and the
+//!   end position is assumed.
+//!
+//! The relays are never on together, and a move against the direction the
+//! motor last ran waits until both have been off for `DEAD_TIME`, whether
+//! the earlier move was reversed, stopped or finished, so the motor stops
+//! before it is driven the other way.
+//! The position is unknown after boot until the first move completes (or a
+//! limit switch reports it). A test pulse from the web UI runs the motor for
+//! a fixed time like a move, and leaves the valve `Partial` unless it
+//! reached an end.
+
+use std::time::Duration;
+
+use log::*;
+
+/// Both relays off between a move and one in the other direction
+pub const DEAD_TIME: Duration = Duration::from_millis(500);
+/// Travel time multiple after which a limit switch is considered missing
+const FAULT_FACTOR: u32 = 2;
+
+/// Requested valve action
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValveCommand {
+    Open,
+    Close,
+    Stop,
+}
+
+/// Valve position as far as the controller knows it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValveState {
+    Unknown,
+    Open,
+    Closed,
+    Opening,
+    Closing,
+    /// Stopped between the end positions
+    Partial,
+}
+
+impl ValveState {
+    /// State payload for the Home Assistant valve entity
+    ///
+    /// A partly open valve still lets water through, so it reports `open`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ValveState::Unknown => "unknown",
+            ValveState::Open | ValveState::Partial => "open",
+            ValveState::Closed => "closed",
+            ValveState::Opening => "opening",
+            ValveState::Closing => "closing",
+        }
+    }
+}
+
+/// Limit switch readings (`true` = at that end position)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    pub open: bool,
+    pub closed: bool,
+}
+
+/// Relay outputs
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Drive {
+    pub open: bool,
+    pub close: bool,
+}
+
+/// A move in progress
+#[derive(Debug, Clone, Copy)]
+struct Move {
+    opening: bool,
+    /// When the motor is energized (later than the command after a reversal)
+    since: Duration,
+    /// End of a test pulse, `None` for a move to an end position
+    until: Option<Duration>,
+}
+
+/// Valve state machine
+pub struct ValveController {
+    travel: Duration,
+    state: ValveState,
+    motion: Option<Move>,
+    /// Direction the motor last ran and when its relay was released
+    released: Option<(bool, Duration)>,
+    /// A limit switch wasn't reached in time (cleared by the next command)
+    fault: bool,
+}
+
+impl ValveController {
+    pub fn new(travel: Duration) -> Self {
+        Self { travel, state: ValveState::Unknown, motion: None, released: None, fault: false }
+    }
+
+    /// Change the travel time (applies to the next check)
+    pub fn set_travel(&mut self, travel: Duration) {
+        self.travel = travel;
+    }
+
+    pub fn state(&self) -> ValveState {
+        self.state
+    }
+
+    pub fn is_fault(&self) -> bool {
+        self.fault
+    }
+
+    /// Start or stop a move
+    pub fn command(&mut self, command: ValveCommand, now: Duration) {
+        let opening = match command {
+            ValveCommand::Stop => {
+                if let Some(m) = self.motion.take() {
+                    info!("Valve: stopped");
+                    self.release(m, now);
+                    self.state = ValveState::Partial;
+                }
+                return;
+            }
+            ValveCommand::Open => true,
+            ValveCommand::Close => false,
+        };
+        self.fault = false;
+        match self.motion.take() {
+            // A pulse the same way carries on to the end position
+            Some(m) if m.opening == opening => {
+                self.motion = Some(Move { until: None, ..m });
+                return;
+            }
+            Some(m) => self.release(m, now),
+            None => {}
+        }
+        info!("Valve: {}", if opening { "opening" } else { "closing" });
+        self.start(opening, None, now);
+    }
+
+    /// Run the motor for `length` to test the wiring, unless it is moving
+    pub fn pulse(&mut self, opening: bool, length: Duration, now: Duration) {
+        if self.motion.is_some() {
+            return;
+        }
+        info!("Valve: test pulse {}", if opening { "opening" } else { "closing" });
+        self.start(opening, Some(length), now);
+    }
+
+    /// Begin a move, after the dead time if the motor last ran the other way
+    fn start(&mut self, opening: bool, length: Option<Duration>, now: Duration) {
+        let since = match self.released {
+            Some((was_opening, at)) if was_opening != opening => now.max(at + DEAD_TIME),
+            _ => now,
+        };
+        self.motion = Some(Move { opening, since, until: length.map(|length| since + length) });
+        self.state = if opening { ValveState::Opening } else { ValveState::Closing };
+    }
+
+    /// Note when the relay of a move went off, unless it hadn't come on yet
+    fn release(&mut self, m: Move, now: Duration) {
+        if now >= m.since {
+            self.released = Some((m.opening, now));
+        }
+    }
+
+    /// Advance the current move, returning the relay outputs
+    ///
+    /// `limits` is `None` when no limit switches are fitted.
+    pub fn update(&mut self, limits: Option<Limits>, now: Duration) -> Drive {
+        let Some(m) = self.motion else {
+            // Follow the switches while idle, e.g. after a manual override
+            if let Some(limits) = limits {
+                self.state = match (limits.open, limits.closed) {
+                    (true, false) => ValveState::Open,
+                    (false, true) => ValveState::Closed,
+                    _ if self.state == ValveState::Unknown => ValveState::Unknown,
+                    _ => ValveState::Partial,
+                };
+            }
+            return Drive::default();
+        };
+        if now < m.since {
+            return Drive::default();
+        }
+        let elapsed = now - m.since;
+        let reached = match limits {
+            Some(limits) if m.opening => limits.open,
+            Some(limits) => limits.closed,
+            None => m.until.is_none() && elapsed >= self.travel,
+        };
+        if reached {
+            self.motion = None;
+            self.release(m, now);
+            self.state = if m.opening { ValveState::Open } else { ValveState::Closed };
+            info!("Valve: {}", self.state.name());
+            return Drive::default();
+        }
+        if m.until.is_some_and(|until| now >= until) {
+            self.motion = None;
+            self.release(m, now);
+            self.state = ValveState::Partial;
+            info!("Valve: test pulse done");
+            return Drive::default();
+        }
+        if elapsed >= self.travel * FAULT_FACTOR {
+            warn!("Valve: limit switch not reached after {} s", elapsed.as_secs());
+            self.motion = None;
+            self.release(m, now);
+            self.state = ValveState::Partial;
+            self.fault = true;
+            return Drive::default();
+        }
+        Drive { open: m.opening, close: !m.opening }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRAVEL: Duration = Duration::from_secs(30);
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_timed_move_and_reversal() {
+        let mut valve = ValveController::new(TRAVEL);
+        assert_eq!(valve.state().name(), "unknown");
+        valve.command(ValveCommand::Close, secs(0));
+        assert_eq!(valve.update(None, secs(1)), Drive { open: false, close: true });
+        assert_eq!(valve.state(), ValveState::Closing);
+        assert_eq!(valve.update(None, secs(30)), Drive::default());
+        assert_eq!(valve.state(), ValveState::Closed);
+
+        // Reversing mid-move waits out the dead time with both relays off
+        valve.command(ValveCommand::Open, secs(40));
+        assert_eq!(valve.update(None, secs(50)), Drive { open: true, close: false });
+        valve.command(ValveCommand::Close, secs(50));
+        assert_eq!(valve.update(None, secs(50)), Drive::default());
+        assert_eq!(valve.update(None, secs(51)), Drive { open: false, close: true });
+        valve.command(ValveCommand::Stop, secs(52));
+        assert_eq!(valve.update(None, secs(53)), Drive::default());
+        assert_eq!(valve.state().name(), "open");
+    }
+
+    #[test]
+    fn test_dead_time_after_stop_and_end() {
+        let mut valve = ValveController::new(TRAVEL);
+        let ms = Duration::from_millis;
+        valve.command(ValveCommand::Open, secs(0));
+        assert!(valve.update(None, secs(5)).open);
+        // Stopped, then reversed at once: the dead time still applies
+        valve.command(ValveCommand::Stop, secs(10));
+        valve.command(ValveCommand::Close, secs(10));
+        assert_eq!(valve.update(None, secs(10) + ms(400)), Drive::default());
+        assert!(valve.update(None, secs(10) + ms(500)).close);
+
+        // Same after a move that ran to its end
+        assert_eq!(valve.update(None, secs(40) + ms(500)), Drive::default());
+        assert_eq!(valve.state(), ValveState::Closed);
+        valve.command(ValveCommand::Open, secs(40) + ms(600));
+        assert_eq!(valve.update(None, secs(40) + ms(700)), Drive::default());
+        assert!(valve.update(None, secs(41)).open);
+
+        // Carrying on in the same direction needs no pause
+        valve.command(ValveCommand::Stop, secs(42));
+        valve.command(ValveCommand::Open, secs(42));
+        assert!(valve.update(None, secs(42)).open);
+    }
+
+    #[test]
+    fn test_pulse() {
+        let mut valve = ValveController::new(TRAVEL);
+        let ms = Duration::from_millis;
+        valve.pulse(true, secs(1), secs(0));
+        assert!(valve.update(None, ms(900)).open);
+        assert_eq!(valve.update(None, secs(1)), Drive::default());
+        assert_eq!(valve.state(), ValveState::Partial);
+
+        // The other way waits out the dead time, and a move takes over
+        valve.pulse(false, secs(1), secs(1));
+        assert_eq!(valve.update(None, ms(1400)), Drive::default());
+        assert!(valve.update(None, ms(1500)).close);
+        valve.pulse(true, secs(1), ms(1600));
+        valve.command(ValveCommand::Close, ms(1700));
+        assert!(valve.update(None, secs(3)).close);
+        assert_eq!(valve.state(), ValveState::Closing);
+        assert_eq!(valve.update(None, ms(31500)), Drive::default());
+        assert_eq!(valve.state(), ValveState::Closed);
+    }
+
+    #[test]
+    fn test_limit_switches() {
+        let mut valve = ValveController::new(TRAVEL);
+        let open = Limits { open: true, closed: false };
+        let between = Limits::default();
+        assert_eq!(valve.update(Some(open), secs(0)), Drive::default());
+        assert_eq!(valve.state(), ValveState::Open);
+
+        // Not done at the travel time, done when the switch closes
+        valve.command(ValveCommand::Close, secs(0));
+        assert!(valve.update(Some(between), secs(45)).close);
+        valve.update(Some(Limits { open: false, closed: true }), secs(46));
+        assert_eq!(valve.state(), ValveState::Closed);
+
+        // A switch that never closes stops the motor and latches a fault
+        valve.command(ValveCommand::Open, secs(100));
+        assert!(valve.update(Some(between), secs(159)).open);
+        assert_eq!(valve.update(Some(between), secs(160)), Drive::default());
+        assert!(valve.is_fault());
+        assert_eq!(valve.state(), ValveState::Partial);
+        valve.command(ValveCommand::Open, secs(200));
+        assert!(!valve.is_fault());
+    }
+}
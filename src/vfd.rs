@@ -0,0 +1,52 @@
+//! VFD speed reference output
+//!
+//! Commands pump speed through a VFD's 0-10 V analog input, turning the
+//! controller into a constant-pressure system: the speed follows a PID loop
+//! on the pressure error (see `pid`).
+//!
+//! The ESP32 DAC channels sit on GPIO25/26, which the wESP32 uses for the
+//! RMII PHY, so the reference is a PWM signal smoothed by an RC filter and
+//! scaled by an op-amp:
+//!
+//! ```text
+//! GPIO2 ──[10kΩ]──┬──(+) op-amp, gain 3.03 ── VFD AI (0-10 V)
+//!                 │
+//!               [10µF]   (fc ≈ 1.6 Hz, ripple < 1% at 5 kHz)
+//!                 │
+//!                GND
+//! ```
+
+use esp_idf_svc::hal::ledc::LedcDriver;
+use esp_idf_svc::sys::EspError;
+
+/// PWM carrier frequency; far above the RC corner, below the op-amp slew limit
+pub const PWM_FREQUENCY_HZ: u32 = 5_000;
+
+/// Analog speed reference on a PWM pin
+pub struct SpeedOutput<'d> {
+    pwm: LedcDriver<'d>,
+    speed: f32,
+}
+
+impl<'d> SpeedOutput<'d> {
+    /// Wrap a configured LEDC channel, starting at zero speed
+    pub fn new(mut pwm: LedcDriver<'d>) -> Result<Self, EspError> {
+        pwm.set_duty(0)?;
+        pwm.enable()?;
+        Ok(Self { pwm, speed: 0.0 })
+    }
+
+    /// Current speed reference (0.0-1.0)
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Set the speed reference as a fraction of full scale (0.0-1.0)
+    pub fn set_speed(&mut self, fraction: f32) -> Result<(), EspError> {
+        let fraction = fraction.clamp(0.0, 1.0);
+        let duty = (fraction * self.pwm.get_max_duty() as f32) as u32;
+        self.pwm.set_duty(duty)?;
+        self.speed = fraction;
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+//! Sensor warm-up tracking
+//!
+//! Sensors need a while after power-up to deliver trustworthy values: the
+//! SEN0676 radar runs its echo filter for several seconds, and the pressure
+//! transducer's output settles once its supply has stabilised. Readings taken
+//! during warm-up are still displayed and published, but control loops skip
+//! them and the display shows "Stabilizing...".
+//!
+//! Warm-up starts at boot and restarts when a sensor comes back after failing
+//! to respond, which is what a power blip on the sensor supply looks like.
+
+use std::time::Duration;
+
+/// Warm-up window for one sensor
+#[derive(Debug, Clone)]
+pub struct Warmup {
+    period: Duration,
+    /// Uptime at which the current warm-up started
+    since: Duration,
+    /// Sensor failed to respond on the last read
+    faulted: bool,
+}
+
+impl Warmup {
+    /// Warm-up of `period` counted from boot
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            since: Duration::ZERO,
+            faulted: false,
+        }
+    }
+
+    /// Change the warm-up length (applies to the running window too)
+    pub fn set_period(&mut self, period: Duration) {
+        self.period = period;
+    }
+
+    /// Record the outcome of a sensor read at `now`; returns `true` if the
+    /// sensor just recovered and warm-up restarted
+    pub fn record(&mut self, ok: bool, now: Duration) -> bool {
+        let recovered = ok && self.faulted;
+        if recovered {
+            self.since = now;
+        }
+        self.faulted = !ok;
+        recovered
+    }
+
+    /// Whether the sensor answered its last read
+    pub fn responding(&self) -> bool {
+        !self.faulted
+    }
+
+    /// Whether readings can be used for control
+    pub fn ready(&self, now: Duration) -> bool {
+        !self.faulted && now.saturating_sub(self.since) >= self.period
+    }
+
+    /// Time left until the sensor is considered settled
+    pub fn remaining(&self, now: Duration) -> Duration {
+        self.period.saturating_sub(now.saturating_sub(self.since))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+
+    #[test]
+    fn test_ready_after_boot_period() {
+        let clock = MockClock::new();
+        let warmup = Warmup::new(Duration::from_secs(30));
+        assert!(!warmup.ready(clock.uptime()));
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(warmup.remaining(clock.uptime()), Duration::from_secs(1));
+        clock.advance(Duration::from_secs(1));
+        assert!(warmup.ready(clock.uptime()));
+    }
+
+    #[test]
+    fn test_restarts_after_sensor_recovers() {
+        let clock = MockClock::new();
+        let mut warmup = Warmup::new(Duration::from_secs(30));
+        clock.advance(Duration::from_secs(60));
+        assert!(!warmup.record(true, clock.uptime()));
+        assert!(warmup.ready(clock.uptime()));
+
+        // Sensor drops out, then answers again
+        assert!(!warmup.record(false, clock.uptime()));
+        assert!(!warmup.ready(clock.uptime()));
+        assert!(!warmup.responding());
+        clock.advance(Duration::from_secs(5));
+        assert!(warmup.record(true, clock.uptime()));
+        assert!(!warmup.ready(clock.uptime()));
+        clock.advance(Duration::from_secs(30));
+        assert!(warmup.ready(clock.uptime()));
+    }
+}
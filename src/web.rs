@@ -2,15 +2,97 @@
 //!
 //! Serves a simple web page for configuring MQTT broker connection settings.
 //! Settings are stored in NVS and persist across reboots.
+//!
+//! # Endpoints
+//! - `/`: MQTT setup form, with the WiFi network on `wifi` builds; on the
+//!   WiFi setup portal unknown pages redirect here (see `wifi`)
+//! - `/status`: live tank and gauge view, drawn as SVG in the browser
+//! - `/api/status`: current readings as JSON (polled by `/status`)
+//! - `/events`: the MQTT state document as server-sent events, pushed each
+//!   publish interval (see `events`)
+//! - `/api/diag`: heap, uptime, MQTT connection and traffic, display flush, cellular link and pressure ADC diagnostics as JSON (admin)
+//! - `/api/diagnostics.zip`: support bundle with the settings (no secrets),
+//!   `/api/diag`, the boot and recent log lines, the alarm history and the
+//!   firmware version and features, as a ZIP download (admin)
+//! - `/api/selftest`: `POST` starts the self-test, `GET` returns the report of
+//!   the last run as JSON; with `?stream=1` (or `Accept: text/event-stream`)
+//!   it subscribes to the event stream instead, which carries a `selftest`
+//!   event per step (admin, see `selftest`)
+//! - `/api/v1/state`: readings, configured tank size and gauge range, maintenance
+//!   flag and uptime as JSON, for scripts (Node-RED, curl)
+//! - `/api/v1/config`: settings as JSON (admin); `PATCH` with a JSON object of
+//!   the changed keys validates and stores them together, using the console
+//!   provisioning keys (see `provision`), and answers with the updated settings
+//! - `/config/export`: every stored setting as a JSON download, in the
+//!   `/api/v1/config` format; `?secrets=1` adds the passwords, token and
+//!   private key (admin)
+//! - `/config/import` (POST): a saved export, validated as a whole and stored
+//!   through the same setters as `PATCH /api/v1/config` (admin)
+//! - `/login` (POST): web user, password (the admin token) and whether the
+//!   status pages need it too, asked for by `/` on first boot (admin)
+//! - `/maintenance` (POST): toggle maintenance mode
+//! - `/testfire`: pulse an output to check its wiring, armed and then
+//!   confirmed, in maintenance mode only (admin, see `testfire`)
+//! - `/lockout`: engage the lockout/tagout interlock with a PIN, or release
+//!   it after a button press at the unit (admin, `lockout` feature)
+//! - `/ota`: signed firmware upload from the browser, or POSTed directly
+//!   (admin, `ota` feature, see `ota`)
+//! - `/probes`: name the DS18B20 probes found on the bus (admin, `ds18b20` feature)
+//! - `/tls`: MQTT TLS switch, CA certificate and client certificate (admin)
+//! - `/notify`: tank name, webhook URL and alarm message templates (admin, `notify` feature)
+//! - `/playback`: upload a sensor trace to play back in place of the sensors,
+//!   in real time or faster, or stop it (admin, `sim-sensors` feature, see
+//!   `playback`)
+//!
+//! # Access levels
+//! Status pages are open to any viewer on the LAN, unless `web_login` is on.
+//! Configuration requires the admin role: HTTP Basic auth with the web user
+//! (`admin` by default) and the admin token as the password. On first boot,
+//! before a token is set, the setup page asks for a login first; until then
+//! the device stays open so it can be configured.
 
+use std::ffi::{c_char, c_int, c_void};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
-use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::http::server::{Configuration, EspHttpConnection, EspHttpServer, Request};
 use esp_idf_svc::http::Method;
 use esp_idf_svc::io::Write;
+use esp_idf_svc::sys::{
+    esp, httpd_handle_t, httpd_queue_work, httpd_req_to_sockfd, httpd_sess_set_send_override,
+    httpd_sess_trigger_close, lwip_send, MSG_DONTWAIT,
+};
+#[cfg(feature = "wifi")]
+use esp_idf_svc::sys::{
+    esp_err_t, httpd_err_code_t, httpd_err_code_t_HTTPD_404_NOT_FOUND, httpd_register_err_handler, httpd_req_t,
+    httpd_resp_send, httpd_resp_set_hdr, httpd_resp_set_status, EspError,
+};
 use log::*;
 
-use crate::config::Config;
+use crate::alarms::AlarmLog;
+use crate::build_info;
+use crate::clock;
+use crate::config::{Config, MAX_PEM_LEN, MAX_TANK_LABEL_LEN};
+use crate::correction::parse_table;
+use crate::diag::Diagnostics;
+use crate::events::{self, EventStream};
+use crate::json;
+use crate::logging;
+use crate::provision;
+use crate::selftest::SelfTest;
+#[cfg(any(feature = "lockout", feature = "sim-sensors"))]
+use crate::clock::{Clock, SystemClock};
+#[cfg(feature = "lockout")]
+use crate::lockout::{self, Lockout, LockoutError};
+#[cfg(feature = "ds18b20")]
+use crate::ds18b20::{self, Probe};
+#[cfg(feature = "notify")]
+use crate::notify::{self, Event};
+#[cfg(feature = "sim-sensors")]
+use crate::playback::{self, Playback};
+use crate::testfire::{Arming, Output, PULSE};
+use crate::zip::ZipWriter;
 
 const HTML_HEADER: &str = r#"<!DOCTYPE html>
 <html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width">
@@ -18,48 +100,383 @@ const HTML_HEADER: &str = r#"<!DOCTYPE html>
 <style>
 body{font-family:sans-serif;max-width:400px;margin:40px auto;padding:0 20px}
 h1{font-size:1.3em}
+h2{font-size:1.1em;margin-top:24px}
+.hint{font-size:.85em;color:#888}
 label{display:block;margin-top:12px;font-weight:bold}
-input{width:100%;padding:6px;box-sizing:border-box;margin-top:4px}
+input,textarea{width:100%;padding:6px;box-sizing:border-box;margin-top:4px}
+textarea{font-family:monospace;font-size:.75em;height:8em}
+input[type=checkbox]{width:auto}
 input[type=submit]{margin-top:20px;background:#0066cc;color:#fff;border:none;
 padding:10px;cursor:pointer;font-size:1em}
+a{color:#0066cc}
+@media(prefers-color-scheme:dark){body{background:#111;color:#eee}
+input,textarea{background:#222;color:#eee;border:1px solid #444}a{color:#4da3ff}}
 </style></head><body>
 <h1>Water Controller Setup</h1>
+<p><a href="/status">Live status</a></p>
 "#;
 
+/// Live status page
+///
+/// Mirrors the LCD widgets: a tank with fill level and an analog gauge with a
+/// 270 degree sweep. Readings are fetched from `/api/status` every 5 seconds,
+/// the same cadence the main loop samples the sensors at.
+const STATUS_HTML: &str = r##"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width,initial-scale=1">
+<title>Water Controller</title>
+<style>
+:root{--bg:#fff;--fg:#000;--water:#0066cc;--muted:#888}
+@media(prefers-color-scheme:dark){:root{--bg:#111;--fg:#eee;--water:#1e7fe0;--muted:#777}}
+body{font-family:sans-serif;background:var(--bg);color:var(--fg);margin:0;padding:16px}
+h1{font-size:1.3em;text-align:center;margin:0 0 16px}
+.panel{display:flex;flex-wrap:wrap;justify-content:center;gap:24px}
+.panel svg{width:45vw;max-width:260px;min-width:140px;height:auto}
+svg text{fill:var(--fg);font-family:monospace;text-anchor:middle}
+#stale{text-align:center;color:var(--muted);min-height:1.2em}
+#maint{display:none;text-align:center;background:#c80;color:#000;padding:6px;margin-bottom:12px}
+a{color:var(--water)}
+footer{text-align:center;margin-top:16px}
+</style></head><body>
+<h1>Water Controller</h1>
+<div id="maint">Maintenance mode: automation and MQTT paused</div>
+<div class="panel">
+<svg viewBox="0 0 140 220" aria-label="Tank level">
+<rect id="water" x="10" y="210" width="120" height="0" fill="var(--water)"/>
+<rect x="10" y="10" width="120" height="200" fill="none" stroke="var(--fg)" stroke-width="2"/>
+<text id="pct" x="70" y="105" font-size="20">--%</text>
+<text id="gal" x="70" y="130" font-size="16">-- gal</text>
+</svg>
+<svg viewBox="0 0 220 220" aria-label="Pressure gauge">
+<circle cx="110" cy="110" r="100" fill="none" stroke="var(--fg)" stroke-width="2"/>
+<g id="ticks" stroke="var(--fg)"></g>
+<line id="needle" x1="110" y1="110" x2="110" y2="35" stroke="var(--fg)" stroke-width="3"
+transform="rotate(-135 110 110)"/>
+<circle cx="110" cy="110" r="5" fill="var(--fg)"/>
+<text id="psi" x="110" y="155" font-size="18">-- PSI</text>
+</svg>
+</div>
+<div id="stale"></div>
+<footer><a href="/">Setup</a></footer>
+<script>
+var maxPsi=0;
+function el(id){return document.getElementById(id)}
+function ticks(max){
+var g=el('ticks'),ns='http://www.w3.org/2000/svg';g.innerHTML='';
+for(var i=0;i<=15;i++){
+var major=i%3==0,a=(-135+270*i/15)*Math.PI/180,s=Math.sin(a),c=Math.cos(a);
+var r1=major?80:88,l=document.createElementNS(ns,'line');
+l.setAttribute('x1',110+s*r1);l.setAttribute('y1',110-c*r1);
+l.setAttribute('x2',110+s*95);l.setAttribute('y2',110-c*95);
+l.setAttribute('stroke-width',major?2:1);g.appendChild(l);
+if(major){var t=document.createElementNS(ns,'text');
+t.setAttribute('x',110+s*65);t.setAttribute('y',114-c*65);t.setAttribute('font-size','10');
+t.textContent=Math.round(max*i/15);g.appendChild(t)}}}
+function update(d){
+if(d.max_psi!=maxPsi){maxPsi=d.max_psi;ticks(maxPsi)}
+var h=2*Math.min(d.capacity_pct,100),w=el('water');
+w.setAttribute('y',210-h);w.setAttribute('height',h);
+el('pct').textContent=d.capacity_pct+'%';
+el('gal').textContent=d.gallons+' gal';
+var p=Math.min(d.pressure_psi,maxPsi);
+el('needle').setAttribute('transform','rotate('+(-135+270*p/maxPsi)+' 110 110)');
+el('psi').textContent=d.pressure_psi+' PSI';
+el('maint').style.display=d.maintenance?'block':'none';
+el('stale').textContent=''}
+function poll(){
+fetch('/api/status').then(function(r){return r.json()}).then(update)
+.catch(function(){el('stale').textContent='Connection lost, retrying...'})}
+poll();setInterval(poll,5000);
+</script></body></html>"##;
+
 const HTML_FOOTER: &str = "</body></html>";
 
+/// Sent with 401 responses so browsers prompt for credentials
+const AUTH_HEADERS: &[(&str, &str)] = &[("WWW-Authenticate", r#"Basic realm="Water Controller""#)];
+
+/// Access level of a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    /// Status and history only
+    Viewer,
+    /// Configuration and device control
+    Admin,
+}
+
+impl Role {
+    /// Resolve the role from an `Authorization` header
+    ///
+    /// `None` means the request has to log in first.
+    pub fn from_authorization(authorization: Option<&str>, cfg: &Config) -> Option<Self> {
+        // No token configured yet: first-time setup
+        if cfg.admin_token.is_empty() {
+            return Some(Role::Admin);
+        }
+        let expected = base64_encode(format!("{}:{}", cfg.web_user, cfg.admin_token).as_bytes());
+        match authorization.and_then(|h| h.strip_prefix("Basic ")) {
+            Some(credentials) if constant_time_eq(credentials.trim().as_bytes(), expected.as_bytes()) => Some(Role::Admin),
+            _ if cfg.web_login == 0 => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+}
+
+/// Headers for JSON responses
+const JSON_HEADERS: &[(&str, &str)] = &[("Content-Type", "application/json")];
+
+/// Largest `PATCH /api/v1/config` body, enough for the PEM files
+const MAX_API_BODY: usize = 16 * 1024;
+
+/// Body of a JSON error response
+fn json_error(message: &str) -> String {
+    format!(r#"{{"error":"{}"}}"#, json::escape(message))
+}
+
+/// Latest sensor readings, updated by the main loop
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LiveStatus {
+    /// Tank capacity percentage (0-100)
+    pub capacity_percent: u8,
+    /// Tank volume in gallons
+    pub gallons: u16,
+    /// Water pressure in PSI
+    pub pressure_psi: u16,
+}
+
 pub struct WebServer {
-    _server: EspHttpServer<'static>,
+    server: EspHttpServer<'static>,
+    events: Arc<Mutex<EventStream>>,
 }
 
 impl WebServer {
-    pub fn start(config: Arc<Mutex<Config>>) -> anyhow::Result<Self> {
+    pub fn start(
+        config: Arc<Mutex<Config>>,
+        status: Arc<Mutex<LiveStatus>>,
+        maintenance: Arc<AtomicBool>,
+        diagnostics: Arc<Mutex<Diagnostics>>,
+        test_fire: Arc<Mutex<Option<Output>>>,
+        selftest: Arc<Mutex<SelfTest>>,
+        alarm_log: Arc<Mutex<AlarmLog>>,
+        #[cfg(feature = "lockout")] lockout: Arc<Mutex<Lockout>>,
+        #[cfg(feature = "ds18b20")] probes: Arc<Mutex<Vec<Probe>>>,
+        #[cfg(feature = "sim-sensors")] playback: Arc<Mutex<Option<Playback>>>,
+    ) -> anyhow::Result<Self> {
         let server_config = Configuration {
             stack_size: 10240,
+            // Room for the event stream subscribers next to page loads
+            max_open_sockets: 4 + events::MAX_CLIENTS as u16,
+            // Every page with all features enabled, with room to spare
+            max_uri_handlers: 40,
             ..Default::default()
         };
         let mut server = EspHttpServer::new(&server_config)?;
 
         let config_get = config.clone();
+        let maintenance_get = maintenance.clone();
         server.fn_handler::<anyhow::Error, _>("/", Method::Get, move |req| {
             let cfg = config_get.lock().unwrap();
+            if Role::from_authorization(req.header("Authorization"), &cfg) != Some(Role::Admin) {
+                drop(cfg);
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            // First boot: choose a login before anything else
+            if cfg.admin_token.is_empty() && !req.uri().contains("skip=1") {
+                let body = format!(
+                    r#"{HTML_HEADER}<h2>Choose a Login</h2>
+<p class="hint">Anyone on the network can change these settings until a password is set.</p>
+<form method="post" action="/login">
+<label>Username</label>
+<input name="web_user" type="text" value="{web_user}" maxlength="32" required>
+<label>Password</label>
+<input name="admin_token" type="password" minlength="8" maxlength="64" required>
+<label>Repeat Password</label>
+<input name="confirm" type="password" required>
+<label><input name="web_login" type="checkbox" value="1"> Require the login for the status pages too</label>
+<input type="submit" value="Set Login">
+</form>
+<p><a href="/?skip=1">Skip for now</a></p>{HTML_FOOTER}"#,
+                    web_user = html_escape(&cfg.web_user),
+                );
+                drop(cfg);
+                let mut resp = req.into_ok_response()?;
+                resp.write_all(body.as_bytes())?;
+                return Ok(());
+            }
             let body = format!(
-                r#"{header}<form method="post" action="/">
+                r#"{header}<p><a href="/tls">MQTT TLS</a></p>{probes_link}{notify_link}<form method="post" action="/">{wifi_fields}
 <label>MQTT Broker Host</label>
 <input name="broker" type="text" value="{broker}" placeholder="homeassistant.local" required>
 <label>MQTT Port</label>
 <input name="port" type="number" value="{port}" min="1" max="65535">
+<p class="hint">Usually 1883, or 8883 with TLS</p>
 <label>Username</label>
 <input name="username" type="text" value="{username}">
 <label>Password</label>
-<input name="password" type="password" value="{password}">
+<input name="password" type="password" placeholder="leave empty to keep current">
+<label>Timezone (POSIX TZ)</label>
+<input name="timezone" type="text" value="{timezone}" placeholder="UTC0">
+<label>Web Username</label>
+<input name="web_user" type="text" value="{web_user}" maxlength="32" required>
+<label>Admin Token</label>
+<input name="admin_token" type="password" placeholder="unchanged">
+<p class="hint">The web password, together with the web username</p>
+<label><input name="web_login" type="checkbox" value="1"{web_login}> Require the login for the status pages too</label>
+<label>Tank Label</label>
+<input name="tank_label" type="text" value="{tank_label}" maxlength="{tank_label_max}" placeholder="none">
+<p class="hint">Short name drawn above the tank on the display and put before the tank's entity names in Home Assistant, e.g. CISTERN</p>{ha_fields}
+<h2>Logging</h2>
+<label>Syslog Server</label>
+<input name="syslog_server" type="text" value="{syslog_server}" maxlength="64" placeholder="host or host:port">
+<p class="hint">Log messages are also sent there over UDP (port 514 unless given); leave empty for the serial console only</p>
+<label>Syslog Level</label>
+<select name="syslog_level">
+<option value="1"{syslog_error}>Errors</option>
+<option value="2"{syslog_warn}>Warnings</option>
+<option value="3"{syslog_info}>Info</option>
+<option value="4"{syslog_debug}>Debug</option>
+</select>
+<h2>Alarms</h2>
+<label>Runbook URL</label>
+<input name="runbook_url" type="url" value="{runbook_url}" maxlength="200" placeholder="none">
+<p class="hint">Shown as a QR code on the display while an alarm is active. {{code}} is replaced by the alarm code (AL01 low level, AL02 high pressure, AL03 sensor fault, AL04 pipe burst); without it, ?alarm=AL01 is added.</p>
+<h2>Sensor Correction</h2>
+<p class="hint">corrected = table(raw &times; gain + offset); table as raw:actual pairs, e.g. 0:0,500:520</p>
+<label>Radar Offset (mm)</label>
+<input name="radar_offset" type="number" value="{radar_offset}" min="-1000" max="1000">
+<label>Radar Gain</label>
+<input name="radar_gain" type="number" value="{radar_gain:.3}" min="0.5" max="2" step="0.001">
+<label>Radar Table (mm)</label>
+<input name="radar_table" type="text" value="{radar_table}">
+<label>Pressure Offset (PSI)</label>
+<input name="psi_offset" type="number" value="{psi_offset:.2}" min="-20" max="20" step="0.01">
+<label>Pressure Gain</label>
+<input name="psi_gain" type="number" value="{psi_gain:.3}" min="0.5" max="2" step="0.001">
+<label>Pressure Table (PSI)</label>
+<input name="psi_table" type="text" value="{psi_table}">
+<h2>Pressure Sensor</h2>
+<label>Transducer Full Scale (PSI)</label>
+<input name="psi_range" type="number" value="{psi_range}" min="30" max="300">
+<p class="hint">The pressure printed on the transducer: 100, 150, 200 or 300</p>
+<label>Output at 0 PSI (mV)</label>
+<input name="psi_min_mv" type="number" value="{psi_min_mv}" min="0" max="2500">
+<label>Output at Full Scale (mV)</label>
+<input name="psi_max_mv" type="number" value="{psi_max_mv}" min="1000" max="5500">
+<p class="hint">500 and 4500 for the common 0.5-4.5 V part; "Calibrate Pressure Zero" in Home Assistant moves both to the reading with the line depressurized</p>
+<label><input name="psi_ratiometric" type="checkbox" value="1"{psi_ratiometric}> Ratiometric transducer: output in proportion to its supply, with the outputs above given at 5 V</label>
+<label>Transducer Supply (mV)</label>
+<input name="psi_supply_mv" type="number" value="{psi_supply_mv}" min="3000" max="5500">
+<p class="hint">Water adds 0.433 PSI per foot of height. A sensor mounted above the gauge (or the point the pressure switch sees) reads low by that much, so the difference is added; a sensor mounted below it reads high, so it is subtracted. Choose Off when the sensor is level with the gauge.</p>
+<label>Sensor Position</label>
+<select name="head_mode">
+<option value="1"{head_above}>Above the gauge: add the height</option>
+<option value="2"{head_below}>Below the gauge: subtract the height</option>
+<option value="0"{head_off}>Off: no height compensation</option>
+</select>
+<label>Height Difference (ft)</label>
+<input name="sensor_height" type="number" value="{sensor_height}" min="0" max="50">
 <input type="submit" value="Save &amp; Reboot">
-</form>{footer}"#,
+</form>
+<form method="post" action="/maintenance">
+<input type="hidden" name="enabled" value="{maint_next}">
+<input type="submit" value="{maint_action}">
+</form>
+<p><a href="/testfire">Test outputs</a></p>
+<p><a href="/config/export">Download settings</a></p>{lockout_link}{ota_link}{playback_link}{footer}"#,
                 header = HTML_HEADER,
-                broker = cfg.mqtt_broker,
+                probes_link = if cfg!(feature = "ds18b20") {
+                    r#"<p><a href="/probes">Temperature probes</a></p>"#
+                } else {
+                    ""
+                },
+                notify_link = if cfg!(feature = "notify") {
+                    r#"<p><a href="/notify">Notifications</a></p>"#
+                } else {
+                    ""
+                },
+                lockout_link = if cfg!(feature = "lockout") {
+                    r#"<p><a href="/lockout">Lockout/tagout</a></p>"#
+                } else {
+                    ""
+                },
+                ota_link = if cfg!(feature = "ota") {
+                    r#"<p><a href="/ota">Firmware update</a></p>"#
+                } else {
+                    ""
+                },
+                playback_link = if cfg!(feature = "sim-sensors") {
+                    r#"<p><a href="/playback">Sensor playback</a></p>"#
+                } else {
+                    ""
+                },
+                wifi_fields = if cfg!(feature = "wifi") {
+                    format!(
+                        r#"
+<label>WiFi Network</label>
+<input name="wifi_ssid" type="text" value="{}" maxlength="32">
+<p class="hint">Joined when there is no Ethernet link; leave empty to keep the setup access point</p>
+<label>WiFi Password</label>
+<input name="wifi_pass" type="password" maxlength="64" placeholder="unchanged">"#,
+                        html_escape(&cfg.wifi_ssid),
+                    )
+                } else {
+                    String::new()
+                },
+                ha_fields = if cfg!(feature = "mqtt") {
+                    format!(
+                        r#"
+<h2>Home Assistant</h2>
+<label>Device Name</label>
+<input name="ha_name" type="text" value="{}" maxlength="64" placeholder="Water Controller">
+<label>Area</label>
+<input name="ha_area" type="text" value="{}" maxlength="64">
+<label>Entity Name Prefix</label>
+<input name="ha_prefix" type="text" value="{}" maxlength="32">
+<p class="hint">Tells several controllers apart: with "Barn" the level sensor is "Barn Water Capacity". Entity ids keep the names HA saw first.</p>
+<label><input name="mqtt_read_only" type="checkbox" value="1"{}> Read-only: publish readings, accept no commands over MQTT</label>
+<p class="hint">For a shared broker. Settings, switches and buttons are removed from Home Assistant; change settings here instead.</p>"#,
+                        html_escape(&cfg.ha_device_name),
+                        html_escape(&cfg.ha_area),
+                        html_escape(&cfg.ha_prefix),
+                        if cfg.mqtt_read_only == 1 { " checked" } else { "" },
+                    )
+                } else {
+                    String::new()
+                },
+                syslog_server = html_escape(&cfg.syslog_server),
+                syslog_error = if cfg.syslog_level == 1 { " selected" } else { "" },
+                syslog_warn = if cfg.syslog_level == 2 { " selected" } else { "" },
+                syslog_info = if cfg.syslog_level == 3 { " selected" } else { "" },
+                syslog_debug = if cfg.syslog_level >= 4 { " selected" } else { "" },
+                runbook_url = html_escape(&cfg.runbook_url),
+                tank_label = html_escape(&cfg.tank_label),
+                tank_label_max = MAX_TANK_LABEL_LEN,
+                broker = html_escape(&cfg.mqtt_broker),
                 port = cfg.mqtt_port,
-                username = cfg.mqtt_username,
-                password = cfg.mqtt_password,
+                username = html_escape(&cfg.mqtt_username),
+                timezone = html_escape(&cfg.timezone),
+                web_user = html_escape(&cfg.web_user),
+                web_login = if cfg.web_login == 1 { " checked" } else { "" },
+                radar_offset = cfg.radar_offset_mm,
+                radar_gain = cfg.radar_gain_milli as f32 / 1000.0,
+                radar_table = html_escape(&cfg.radar_table),
+                psi_offset = cfg.pressure_offset_centi as f32 / 100.0,
+                psi_gain = cfg.pressure_gain_milli as f32 / 1000.0,
+                psi_table = html_escape(&cfg.pressure_table),
+                head_above = if cfg.head_mode == 1 { " selected" } else { "" },
+                head_below = if cfg.head_mode == 2 { " selected" } else { "" },
+                head_off = if cfg.head_mode == 0 { " selected" } else { "" },
+                sensor_height = cfg.sensor_height_feet,
+                psi_range = cfg.psi_range,
+                psi_min_mv = cfg.psi_min_mv,
+                psi_max_mv = cfg.psi_max_mv,
+                psi_ratiometric = if cfg.psi_ratiometric == 1 { " checked" } else { "" },
+                psi_supply_mv = cfg.psi_supply_mv,
+                maint_next = if maintenance_get.load(Ordering::Relaxed) { 0 } else { 1 },
+                maint_action = if maintenance_get.load(Ordering::Relaxed) {
+                    "End Maintenance Mode"
+                } else {
+                    "Start Maintenance Mode"
+                },
                 footer = HTML_FOOTER,
             );
             drop(cfg);
@@ -68,10 +485,361 @@ impl WebServer {
             Ok(())
         })?;
 
+        let config_status = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/status", Method::Get, move |req| {
+            if Role::from_authorization(req.header("Authorization"), &config_status.lock().unwrap()).is_none() {
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let mut resp = req.into_ok_response()?;
+            resp.write_all(STATUS_HTML.as_bytes())?;
+            Ok(())
+        })?;
+
+        // Event stream: the socket is handed over to `WebServer::publish_state`
+        // and the handler returns, so the server task isn't held up
+        let events = Arc::new(Mutex::new(EventStream::default()));
+        let events_get = events.clone();
+        let config_events = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/events", Method::Get, move |req| {
+            if Role::from_authorization(req.header("Authorization"), &config_events.lock().unwrap()).is_none() {
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            subscribe_events(req, &events_get, None)
+        })?;
+
+        let config_api = config.clone();
+        let maintenance_api = maintenance.clone();
+        let status_state = status.clone();
+        server.fn_handler::<anyhow::Error, _>("/api/status", Method::Get, move |req| {
+            let cfg = config_api.lock().unwrap();
+            if Role::from_authorization(req.header("Authorization"), &cfg).is_none() {
+                drop(cfg);
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let live = *status.lock().unwrap();
+            let body = format!(
+                r#"{{"capacity_pct":{},"gallons":{},"pressure_psi":{},"max_psi":{},"tank_capacity":{},"maintenance":{}}}"#,
+                live.capacity_percent,
+                live.gallons,
+                live.pressure_psi,
+                cfg.max_psi,
+                cfg.tank_capacity_gallons,
+                maintenance_api.load(Ordering::Relaxed),
+            );
+            drop(cfg);
+            let mut resp = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        let config_state = config.clone();
+        let maintenance_state = maintenance.clone();
+        let diagnostics_state = diagnostics.clone();
+        server.fn_handler::<anyhow::Error, _>("/api/v1/state", Method::Get, move |req| {
+            let cfg = config_state.lock().unwrap();
+            if Role::from_authorization(req.header("Authorization"), &cfg).is_none() {
+                drop(cfg);
+                let mut resp = req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                resp.write_all(json_error("authentication required").as_bytes())?;
+                return Ok(());
+            }
+            let live = *status_state.lock().unwrap();
+            let uptime_secs = diagnostics_state.lock().unwrap().uptime_secs;
+            let body = format!(
+                r#"{{"capacity_pct":{},"gallons":{},"pressure_psi":{},"tank_capacity":{},"max_psi":{},"maintenance":{},"uptime_secs":{}}}"#,
+                live.capacity_percent,
+                live.gallons,
+                live.pressure_psi,
+                cfg.tank_capacity_gallons,
+                cfg.max_psi,
+                maintenance_state.load(Ordering::Relaxed),
+                uptime_secs,
+            );
+            drop(cfg);
+            let mut resp = req.into_response(200, None, JSON_HEADERS)?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        let config_api_get = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/api/v1/config", Method::Get, move |req| {
+            let cfg = config_api_get.lock().unwrap();
+            if Role::from_authorization(req.header("Authorization"), &cfg) != Some(Role::Admin) {
+                drop(cfg);
+                let mut resp = req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                resp.write_all(json_error("admin authentication required").as_bytes())?;
+                return Ok(());
+            }
+            let body = provision::config_json(&cfg, false);
+            drop(cfg);
+            let mut resp = req.into_response(200, None, JSON_HEADERS)?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        let config_api_patch = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/api/v1/config", Method::Patch, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_api_patch.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated API config change");
+                let mut resp = req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                resp.write_all(json_error("admin authentication required").as_bytes())?;
+                return Ok(());
+            }
+            if req.header("Content-Type").is_some_and(|t| !t.starts_with("application/json")) {
+                let mut resp = req.into_response(415, Some("Unsupported Media Type"), JSON_HEADERS)?;
+                resp.write_all(json_error("expected application/json").as_bytes())?;
+                return Ok(());
+            }
+
+            let mut buf = vec![0u8; MAX_API_BODY + 1];
+            let mut total = 0;
+            while total < buf.len() {
+                match req.read(&mut buf[total..]) {
+                    Ok(0) => break,
+                    Ok(n) => total += n,
+                    Err(e) => {
+                        warn!("Web PATCH read error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            if total > MAX_API_BODY {
+                let mut resp = req.into_response(413, Some("Payload Too Large"), JSON_HEADERS)?;
+                resp.write_all(json_error(&format!("body larger than {} bytes", MAX_API_BODY)).as_bytes())?;
+                return Ok(());
+            }
+            let body = String::from_utf8_lossy(&buf[..total]);
+
+            // Nothing is stored unless every key is valid
+            let request = match provision::parse_request(&body) {
+                Ok(request) => request,
+                Err(e) => {
+                    let mut resp = req.into_response(400, Some("Bad Request"), JSON_HEADERS)?;
+                    resp.write_all(json_error(&e).as_bytes())?;
+                    return Ok(());
+                }
+            };
+            let mut cfg = config_api_patch.lock().unwrap();
+            if let Err(e) = request.apply(&mut cfg) {
+                drop(cfg);
+                warn!("Web: failed to store API settings: {:?}", e);
+                let mut resp = req.into_response(500, Some("Internal Server Error"), JSON_HEADERS)?;
+                resp.write_all(json_error(&e.to_string()).as_bytes())?;
+                return Ok(());
+            }
+            info!("Web: API stored {} settings", request.len());
+            let body = provision::config_json(&cfg, false);
+            if request.reboot {
+                cfg.flush();
+            }
+            drop(cfg);
+            let mut resp = req.into_response(200, None, JSON_HEADERS)?;
+            resp.write_all(body.as_bytes())?;
+            drop(resp);
+
+            if request.reboot {
+                info!("Web: rebooting after API config change");
+                std::thread::sleep(std::time::Duration::from_secs(1));
+                unsafe { esp_idf_svc::sys::esp_restart(); }
+            }
+            Ok(())
+        })?;
+
+        let config_export = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/config/export", Method::Get, move |req| {
+            let cfg = config_export.lock().unwrap();
+            if Role::from_authorization(req.header("Authorization"), &cfg) != Some(Role::Admin) {
+                drop(cfg);
+                let mut resp = req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                resp.write_all(json_error("admin authentication required").as_bytes())?;
+                return Ok(());
+            }
+            let secrets = req.uri().contains("secrets=1");
+            let body = provision::config_json(&cfg, secrets);
+            drop(cfg);
+            if secrets {
+                warn!("Web: configuration exported with secrets");
+            }
+            let mut resp = req.into_response(200, None, &[
+                ("Content-Type", "application/json"),
+                ("Content-Disposition", r#"attachment; filename="watercontroller-config.json""#),
+            ])?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        let config_import = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/config/import", Method::Post, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_import.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated config import");
+                let mut resp = req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                resp.write_all(json_error("admin authentication required").as_bytes())?;
+                return Ok(());
+            }
+
+            let mut buf = vec![0u8; MAX_API_BODY + 1];
+            let mut total = 0;
+            while total < buf.len() {
+                match req.read(&mut buf[total..]) {
+                    Ok(0) => break,
+                    Ok(n) => total += n,
+                    Err(e) => {
+                        warn!("Web import read error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            if total > MAX_API_BODY {
+                let mut resp = req.into_response(413, Some("Payload Too Large"), JSON_HEADERS)?;
+                resp.write_all(json_error(&format!("body larger than {} bytes", MAX_API_BODY)).as_bytes())?;
+                return Ok(());
+            }
+            let body = String::from_utf8_lossy(&buf[..total]);
+
+            // An export from another firmware version may carry keys this one
+            // doesn't know; the whole file is rejected rather than half applied
+            let request = match provision::parse_request(&body) {
+                Ok(request) => request,
+                Err(e) => {
+                    let mut resp = req.into_response(400, Some("Bad Request"), JSON_HEADERS)?;
+                    resp.write_all(json_error(&e).as_bytes())?;
+                    return Ok(());
+                }
+            };
+            let mut cfg = config_import.lock().unwrap();
+            if let Err(e) = request.apply(&mut cfg) {
+                drop(cfg);
+                warn!("Web: failed to store imported settings: {:?}", e);
+                let mut resp = req.into_response(500, Some("Internal Server Error"), JSON_HEADERS)?;
+                resp.write_all(json_error(&e.to_string()).as_bytes())?;
+                return Ok(());
+            }
+            cfg.flush();
+            drop(cfg);
+            info!("Web: imported {} settings", request.len());
+            let mut resp = req.into_response(200, None, JSON_HEADERS)?;
+            resp.write_all(format!(r#"{{"stored":{}}}"#, request.len()).as_bytes())?;
+            drop(resp);
+
+            if request.reboot {
+                info!("Web: rebooting after config import");
+                std::thread::sleep(std::time::Duration::from_secs(1));
+                unsafe { esp_idf_svc::sys::esp_restart(); }
+            }
+            Ok(())
+        })?;
+
+        let config_bundle = config.clone();
+        let diagnostics_bundle = diagnostics.clone();
+        server.fn_handler::<anyhow::Error, _>("/api/diagnostics.zip", Method::Get, move |req| {
+            let cfg = config_bundle.lock().unwrap();
+            if Role::from_authorization(req.header("Authorization"), &cfg) != Some(Role::Admin) {
+                drop(cfg);
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let settings = provision::config_json(&cfg, false);
+            drop(cfg);
+            let mut zip = ZipWriter::new();
+            zip.add("config.json", settings.as_bytes());
+            zip.add("diagnostics.json", diagnostics_bundle.lock().unwrap().to_json().as_bytes());
+            zip.add("version.txt", build_info::report().as_bytes());
+            zip.add("boot.log", logging::boot_log().as_bytes());
+            zip.add("recent.log", logging::recent_log().as_bytes());
+            zip.add("alarms.txt", alarm_log.lock().unwrap().to_text().as_bytes());
+            let body = zip.finish();
+            info!("Web: diagnostic bundle downloaded ({} bytes)", body.len());
+            let mut resp = req.into_response(200, None, &[
+                ("Content-Type", "application/zip"),
+                ("Content-Disposition", r#"attachment; filename="watercontroller-diagnostics.zip""#),
+            ])?;
+            resp.write_all(&body)?;
+            Ok(())
+        })?;
+
+        let config_diag = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/api/diag", Method::Get, move |req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_diag.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let body = diagnostics.lock().unwrap().to_json();
+            let mut resp = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        let config_selftest = config.clone();
+        let selftest_get = selftest.clone();
+        let events_selftest = events.clone();
+        server.fn_handler::<anyhow::Error, _>("/api/selftest", Method::Get, move |req| {
+            let role = Role::from_authorization(req.header("Authorization"), &config_selftest.lock().unwrap());
+            if role != Some(Role::Admin) {
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let report = selftest_get.lock().unwrap().report().map(|r| r.to_json());
+            let stream = req.uri().contains("stream=1")
+                || req.header("Accept").is_some_and(|accept| accept.contains("text/event-stream"));
+            if stream {
+                // The current report first, so a late subscriber sees the steps already run
+                let first = report.map(|json| events::frame("selftest", &json));
+                return subscribe_events(req, &events_selftest, first);
+            }
+            let Some(body) = report else {
+                req.into_response(404, Some("Not Found"), JSON_HEADERS)?.write_all(json_error("No self-test has run").as_bytes())?;
+                return Ok(());
+            };
+            req.into_response(200, None, JSON_HEADERS)?.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        let config_selftest_post = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/api/selftest", Method::Post, move |req| {
+            let role = Role::from_authorization(req.header("Authorization"), &config_selftest_post.lock().unwrap());
+            if role != Some(Role::Admin) {
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            if !selftest.lock().unwrap().request() {
+                req.into_response(409, Some("Conflict"), JSON_HEADERS)?.write_all(json_error("A self-test is running").as_bytes())?;
+                return Ok(());
+            }
+            info!("Web: self-test requested");
+            req.into_response(202, Some("Accepted"), JSON_HEADERS)?.write_all(br#"{"started":true}"#)?;
+            Ok(())
+        })?;
+
         let config_post = config.clone();
         server.fn_handler::<anyhow::Error, _>("/", Method::Post, move |mut req| {
-            // Read POST body into fixed buffer
-            let mut buf = [0u8; 1024];
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_post.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated config change");
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+
+            // Read POST body; with the correction tables and the runbook URL
+            // filled in it can pass 2 KB, too much for the handler stack
+            let mut buf = vec![0u8; 3072];
             let mut total = 0;
             loop {
                 match req.read(&mut buf[total..]) {
@@ -92,6 +860,31 @@ impl WebServer {
             let mut port: u16 = 1883;
             let mut username = String::new();
             let mut password = String::new();
+            let mut admin_token = String::new();
+            let mut web_user = String::new();
+            let mut web_login = 0;
+            let mut timezone = String::new();
+            let mut radar_offset: Option<f32> = None;
+            let mut radar_gain: Option<f32> = None;
+            let mut radar_table = String::new();
+            let mut psi_offset: Option<f32> = None;
+            let mut psi_gain: Option<f32> = None;
+            let mut psi_table = String::new();
+            let mut head_mode: Option<u16> = None;
+            let mut sensor_height: Option<u16> = None;
+            let mut psi_range: Option<u16> = None;
+            let mut psi_min_mv: Option<u16> = None;
+            let mut psi_max_mv: Option<u16> = None;
+            let mut psi_ratiometric = 0;
+            let mut psi_supply_mv: Option<u16> = None;
+            let mut wifi_ssid: Option<String> = None;
+            let mut wifi_pass = String::new();
+            let mut ha_naming: Option<(String, String, String)> = None;
+            let mut mqtt_read_only = 0;
+            let mut syslog_server: Option<String> = None;
+            let mut syslog_level: Option<u16> = None;
+            let mut runbook_url: Option<String> = None;
+            let mut tank_label: Option<String> = None;
 
             for pair in body.split('&') {
                 let mut kv = pair.splitn(2, '=');
@@ -102,6 +895,33 @@ impl WebServer {
                     "port" => port = val.parse().unwrap_or(1883),
                     "username" => username = val,
                     "password" => password = val,
+                    "admin_token" => admin_token = val,
+                    "web_user" => web_user = val,
+                    "web_login" => web_login = 1,
+                    "timezone" => timezone = val,
+                    "radar_offset" => radar_offset = val.parse().ok(),
+                    "radar_gain" => radar_gain = val.parse().ok(),
+                    "radar_table" => radar_table = val,
+                    "psi_offset" => psi_offset = val.parse().ok(),
+                    "psi_gain" => psi_gain = val.parse().ok(),
+                    "psi_table" => psi_table = val,
+                    "head_mode" => head_mode = val.parse().ok(),
+                    "sensor_height" => sensor_height = val.parse().ok(),
+                    "psi_range" => psi_range = val.parse().ok(),
+                    "psi_min_mv" => psi_min_mv = val.parse().ok(),
+                    "psi_max_mv" => psi_max_mv = val.parse().ok(),
+                    "psi_ratiometric" => psi_ratiometric = 1,
+                    "psi_supply_mv" => psi_supply_mv = val.parse().ok(),
+                    "wifi_ssid" => wifi_ssid = Some(val),
+                    "wifi_pass" => wifi_pass = val,
+                    "syslog_server" => syslog_server = Some(val),
+                    "syslog_level" => syslog_level = val.parse().ok(),
+                    "runbook_url" => runbook_url = Some(val),
+                    "tank_label" => tank_label = Some(val),
+                    "ha_name" => ha_naming.get_or_insert_with(Default::default).0 = val,
+                    "ha_area" => ha_naming.get_or_insert_with(Default::default).1 = val,
+                    "ha_prefix" => ha_naming.get_or_insert_with(Default::default).2 = val,
+                    "mqtt_read_only" => mqtt_read_only = 1,
                     _ => {}
                 }
             }
@@ -113,9 +933,117 @@ impl WebServer {
                 let _ = cfg.set_mqtt_broker(&broker);
                 let _ = cfg.set_mqtt_port(port);
                 let _ = cfg.set_mqtt_username(&username);
-                let _ = cfg.set_mqtt_password(&password);
+                // The form never shows the stored password, so empty keeps it
+                if !password.is_empty() {
+                    let _ = cfg.set_mqtt_password(&password);
+                }
+                let timezone = timezone.trim();
+                if clock::valid_timezone(timezone) {
+                    let _ = cfg.set_timezone(timezone);
+                } else if !timezone.is_empty() {
+                    warn!("Web: invalid timezone '{}', unchanged", timezone);
+                }
+                if !admin_token.is_empty() {
+                    let _ = cfg.set_admin_token(&admin_token);
+                }
+                let web_user = web_user.trim();
+                if !web_user.is_empty() && web_user.len() <= 32 && !web_user.contains(':') {
+                    let _ = cfg.set_web_user(web_user);
+                }
+                let _ = cfg.set_web_login(web_login);
+                if let Some(mode) = head_mode {
+                    let _ = cfg.set_head_mode(mode);
+                }
+                if let Some(feet) = sensor_height {
+                    let _ = cfg.set_sensor_height(feet);
+                }
+                if let Some(psi) = psi_range {
+                    let _ = cfg.set_psi_range(psi);
+                }
+                // Only with both outputs, which the form always sends together
+                if let (Some(min_mv), Some(max_mv)) = (psi_min_mv, psi_max_mv) {
+                    if max_mv > min_mv {
+                        let _ = cfg.set_psi_min_mv(min_mv);
+                        let _ = cfg.set_psi_max_mv(max_mv);
+                        let _ = cfg.set_psi_ratiometric(psi_ratiometric);
+                    } else {
+                        warn!("Web: transducer output {}-{} mV is backwards, unchanged", min_mv, max_mv);
+                    }
+                }
+                if let Some(mv) = psi_supply_mv {
+                    let _ = cfg.set_psi_supply_mv(mv);
+                }
+                if let Some(server) = syslog_server {
+                    let server = server.trim();
+                    if server.is_empty() || (server.len() <= 64 && logging::parse_server(server).is_some()) {
+                        let _ = cfg.set_syslog_server(server);
+                    } else {
+                        warn!("Web: invalid syslog server '{}', unchanged", server);
+                    }
+                }
+                if let Some(level) = syslog_level {
+                    let _ = cfg.set_syslog_level(level);
+                }
+                if let Some(url) = runbook_url {
+                    let url = url.trim();
+                    if url.is_empty() || (url.len() <= 200 && (url.starts_with("http://") || url.starts_with("https://"))) {
+                        let _ = cfg.set_runbook_url(url);
+                    } else {
+                        warn!("Web: ignoring runbook URL '{}'", url);
+                    }
+                }
+                if let Some(label) = tank_label {
+                    let label: String = label.chars().filter(|c| !c.is_control()).collect();
+                    if label.trim().len() <= MAX_TANK_LABEL_LEN {
+                        let _ = cfg.set_tank_label(label.trim());
+                    } else {
+                        warn!("Web: tank label '{}' is too long, unchanged", label.trim());
+                    }
+                }
+                // Only on mqtt builds, which have the fields
+                if let Some((name, area, prefix)) = ha_naming {
+                    if name.trim().len() <= 64 && area.trim().len() <= 64 && prefix.trim().len() <= 32 {
+                        let _ = cfg.set_ha_device_name(name.trim());
+                        let _ = cfg.set_ha_area(area.trim());
+                        let _ = cfg.set_ha_prefix(prefix.trim());
+                    }
+                    let _ = cfg.set_mqtt_read_only(mqtt_read_only);
+                }
+                // Only on wifi builds, which have the fields
+                if let Some(ssid) = wifi_ssid {
+                    let ssid = ssid.trim();
+                    if ssid.len() <= 32 {
+                        let _ = cfg.set_wifi_ssid(ssid);
+                    }
+                    if !wifi_pass.is_empty() && wifi_pass.len() <= 64 {
+                        let _ = cfg.set_wifi_pass(&wifi_pass);
+                    }
+                }
+                if let (Some(offset), Some(gain)) = (radar_offset, radar_gain) {
+                    if parse_table(&radar_table).is_some() {
+                        let _ = cfg.set_radar_correction(
+                            offset.round().clamp(-1000.0, 1000.0) as i16,
+                            (gain * 1000.0).round() as u16,
+                            radar_table.trim(),
+                        );
+                    } else {
+                        warn!("Web: invalid radar table '{}', correction unchanged", radar_table);
+                    }
+                }
+                if let (Some(offset), Some(gain)) = (psi_offset, psi_gain) {
+                    if parse_table(&psi_table).is_some() {
+                        let _ = cfg.set_pressure_correction(
+                            (offset * 100.0).round().clamp(-2000.0, 2000.0) as i16,
+                            (gain * 1000.0).round() as u16,
+                            psi_table.trim(),
+                        );
+                    } else {
+                        warn!("Web: invalid pressure table '{}', correction unchanged", psi_table);
+                    }
+                }
             }
 
+            config_post.lock().unwrap().flush();
             let resp_body = format!(
                 "{}<p>Settings saved. Rebooting...</p>{}",
                 HTML_HEADER, HTML_FOOTER,
@@ -129,10 +1057,1026 @@ impl WebServer {
             unsafe { esp_idf_svc::sys::esp_restart(); }
         })?;
 
+        let config_login = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/login", Method::Post, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_login.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated login change");
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+
+            let mut buf = [0u8; 512];
+            let mut total = 0;
+            while total < buf.len() {
+                match req.read(&mut buf[total..]) {
+                    Ok(0) => break,
+                    Ok(n) => total += n,
+                    Err(e) => {
+                        warn!("Web POST read error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            let body = String::from_utf8_lossy(&buf[..total]);
+            let (mut user, mut password, mut confirm, mut login) = (String::new(), String::new(), String::new(), 0);
+            for pair in body.split('&') {
+                let (key, val) = pair.split_once('=').unwrap_or((pair, ""));
+                match key {
+                    "web_user" => user = url_decode(val).trim().to_string(),
+                    "admin_token" => password = url_decode(val),
+                    "confirm" => confirm = url_decode(val),
+                    "web_login" => login = 1,
+                    _ => {}
+                }
+            }
+            let problem = if user.is_empty() || user.len() > 32 || user.contains(':') {
+                Some("The username needs 1 to 32 characters, without ':'")
+            } else if password.len() < 8 || password.len() > 64 {
+                Some("The password needs 8 to 64 characters")
+            } else if password != confirm {
+                Some("The passwords don't match")
+            } else {
+                None
+            };
+            if let Some(message) = problem {
+                req.into_response(400, Some("Bad Request"), &[])?.write_all(message.as_bytes())?;
+                return Ok(());
+            }
+
+            {
+                let mut cfg = config_login.lock().unwrap();
+                let _ = cfg.set_web_user(&user);
+                let _ = cfg.set_admin_token(&password);
+                let _ = cfg.set_web_login(login);
+                cfg.flush();
+            }
+            info!("Web: login set for '{}'", user);
+
+            // The browser asks for the new login on the way back
+            req.into_response(303, Some("See Other"), &[("Location", "/")])?;
+            Ok(())
+        })?;
+
+        let config_maint = config.clone();
+        let maintenance_fire = maintenance.clone();
+        server.fn_handler::<anyhow::Error, _>("/maintenance", Method::Post, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_maint.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated maintenance toggle");
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+
+            let mut buf = [0u8; 64];
+            let mut total = 0;
+            while total < buf.len() {
+                match req.read(&mut buf[total..]) {
+                    Ok(0) => break,
+                    Ok(n) => total += n,
+                    Err(e) => {
+                        warn!("Web POST read error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            let body = String::from_utf8_lossy(&buf[..total]);
+            let enabled = body.split('&').any(|pair| pair == "enabled=1");
+            maintenance.store(enabled, Ordering::Relaxed);
+            info!("Web: maintenance mode {}", if enabled { "on" } else { "off" });
+
+            // Back to the setup page
+            req.into_response(303, Some("See Other"), &[("Location", "/")])?;
+            Ok(())
+        })?;
+
+        let config_fire = config.clone();
+        let maintenance_fire_get = maintenance_fire.clone();
+        server.fn_handler::<anyhow::Error, _>("/testfire", Method::Get, move |req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_fire.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let outputs: Vec<Output> = Output::ALL.into_iter().filter(|o| o.enabled()).collect();
+            let content = if !maintenance_fire_get.load(Ordering::Relaxed) {
+                "<p>Start maintenance mode on the setup page first, so automation leaves the outputs alone.</p>\n".to_string()
+            } else if outputs.is_empty() {
+                "<p>No testable outputs in this firmware.</p>\n".to_string()
+            } else {
+                let mut rows = String::new();
+                for output in &outputs {
+                    rows += &format!(
+                        r#"<label><input type="radio" name="output" value="{key}" required> {label}</label>
+"#,
+                        key = output.key(),
+                        label = output.label(),
+                    );
+                }
+                format!(
+                    r#"<form method="post" action="/testfire">
+{rows}<input type="submit" value="Arm">
+</form>
+"#
+                )
+            };
+            let body = format!(
+                r#"{HTML_HEADER}<h2>Test Outputs</h2>
+<p class="hint">Energizes one output for {secs} s to check its wiring. Pump relays are never test-fired.</p>
+{content}<p><a href="/">Setup</a></p>{HTML_FOOTER}"#,
+                secs = PULSE.as_secs(),
+            );
+            let mut resp = req.into_ok_response()?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        let config_fire_post = config.clone();
+        #[cfg(feature = "lockout")]
+        let lockout_fire = lockout.clone();
+        let arming = Mutex::new(Arming::default());
+        let started = std::time::Instant::now();
+        server.fn_handler::<anyhow::Error, _>("/testfire", Method::Post, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_fire_post.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated test-fire");
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+
+            let mut buf = [0u8; 128];
+            let mut total = 0;
+            while total < buf.len() {
+                match req.read(&mut buf[total..]) {
+                    Ok(0) => break,
+                    Ok(n) => total += n,
+                    Err(e) => {
+                        warn!("Web POST read error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            let body = String::from_utf8_lossy(&buf[..total]);
+            let mut output = None;
+            let mut code = None;
+            for pair in body.split('&') {
+                match pair.split_once('=') {
+                    Some(("output", val)) => output = Output::from_key(val).filter(|o| o.enabled()),
+                    Some(("code", val)) => code = val.parse::<u32>().ok(),
+                    _ => {}
+                }
+            }
+            let Some(output) = output else {
+                req.into_response(400, Some("Bad Request"), &[])?.write_all(b"Unknown output")?;
+                return Ok(());
+            };
+            if !maintenance_fire.load(Ordering::Relaxed) {
+                req.into_response(409, Some("Conflict"), &[])?.write_all(b"Maintenance mode is off")?;
+                return Ok(());
+            }
+            #[cfg(feature = "lockout")]
+            if lockout_fire.lock().unwrap().is_engaged() {
+                req.into_response(409, Some("Conflict"), &[])?.write_all(b"Outputs are locked out")?;
+                return Ok(());
+            }
+
+            let now = started.elapsed();
+            let Some(code) = code else {
+                // Step one: arm and ask for confirmation
+                let code = unsafe { esp_idf_svc::sys::esp_random() };
+                arming.lock().unwrap().arm(output, code, now);
+                let body = format!(
+                    r#"{HTML_HEADER}<h2>Confirm Test</h2>
+<p>Energize <b>{label}</b> for {secs} s? Keep clear of anything it drives.</p>
+<form method="post" action="/testfire">
+<input type="hidden" name="output" value="{key}">
+<input type="hidden" name="code" value="{code}">
+<input type="submit" value="Fire">
+</form>
+<p><a href="/testfire">Cancel</a></p>{HTML_FOOTER}"#,
+                    label = output.label(),
+                    key = output.key(),
+                    secs = PULSE.as_secs(),
+                );
+                let mut resp = req.into_ok_response()?;
+                resp.write_all(body.as_bytes())?;
+                return Ok(());
+            };
+
+            // Step two: fire if the confirmation matches
+            if let Err(e) = arming.lock().unwrap().confirm(output, code, now) {
+                warn!("Web: test-fire of {} refused: {}", output.key(), e.message());
+                req.into_response(409, Some("Conflict"), &[])?.write_all(e.message().as_bytes())?;
+                return Ok(());
+            }
+            info!("Web: test-firing {}", output.key());
+            *test_fire.lock().unwrap() = Some(output);
+
+            req.into_response(303, Some("See Other"), &[("Location", "/testfire")])?;
+            Ok(())
+        })?;
+
+        #[cfg(feature = "ota")]
+        let config_ota_page = config.clone();
+        #[cfg(feature = "ota")]
+        server.fn_handler::<anyhow::Error, _>("/ota", Method::Get, move |req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_ota_page.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let slots = match crate::ota::slots() {
+                Ok((running, update)) => format!("Running from {}, the update is written to {}.", running, update),
+                Err(e) => html_escape(&e),
+            };
+            let key_hint = if crate::ota::signing_enabled() {
+                "Images must be signed with the OTA key and not older than the running version."
+            } else {
+                "This firmware was built without an OTA public key and rejects every update."
+            };
+            // The signature file is DER; the handler takes it base64 encoded in a header
+            let body = format!(
+                r#"{HTML_HEADER}<h2>Firmware Update</h2>
+<p>Version {version}. {slots}</p>
+<p class="hint">{key_hint}</p>
+<form id="ota">
+<label>Firmware Image (.bin)</label>
+<input id="image" type="file" accept=".bin" required>
+<label>Signature (.sig)</label>
+<input id="sig" type="file" accept=".sig" required>
+<input type="submit" value="Upload &amp; Reboot">
+</form>
+<p id="result" class="hint"></p>
+<script>
+document.getElementById('ota').onsubmit=async e=>{{
+e.preventDefault();
+const out=document.getElementById('result');
+const sig=new Uint8Array(await document.getElementById('sig').files[0].arrayBuffer());
+out.textContent='Uploading...';
+try{{
+const r=await fetch('/ota',{{method:'POST',headers:{{'X-Signature':btoa(String.fromCharCode(...sig))}},body:document.getElementById('image').files[0]}});
+out.textContent=await r.text();
+}}catch(err){{out.textContent='Upload failed: '+err;}}
+}};
+</script>
+<p><a href="/">Setup</a></p>{HTML_FOOTER}"#,
+                version = env!("CARGO_PKG_VERSION"),
+            );
+            let mut resp = req.into_ok_response()?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        #[cfg(feature = "ota")]
+        let config_ota = config.clone();
+        #[cfg(feature = "ota")]
+        server.fn_handler::<anyhow::Error, _>("/ota", Method::Post, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_ota.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated firmware upload");
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let Some(signature) = req.header("X-Signature").and_then(crate::ota::base64_decode) else {
+                req.into_response(400, Some("Bad Request"), &[])?
+                    .write_all(b"missing or malformed X-Signature header")?;
+                return Ok(());
+            };
+
+            info!("Web: firmware upload started");
+            match crate::ota::install(&signature, |buf| req.read(buf)) {
+                Ok(version) => {
+                    let mut resp = req.into_ok_response()?;
+                    resp.write_all(format!("installed {}, rebooting", version).as_bytes())?;
+                    drop(resp);
+                    config_ota.lock().unwrap().flush();
+
+                    // Give the response time to be sent
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                    unsafe { esp_idf_svc::sys::esp_restart(); }
+                }
+                Err(e) => {
+                    req.into_response(400, Some("Bad Request"), &[])?.write_all(e.as_bytes())?;
+                }
+            }
+            Ok(())
+        })?;
+
+        let config_tls = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/tls", Method::Get, move |req| {
+            let cfg = config_tls.lock().unwrap();
+            if Role::from_authorization(req.header("Authorization"), &cfg) != Some(Role::Admin) {
+                drop(cfg);
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            // The private key is never sent back
+            let body = format!(
+                r#"{HTML_HEADER}<h2>MQTT TLS</h2>
+<form method="post" action="/tls">
+<label><input name="tls" type="checkbox" value="1"{checked}> Connect with TLS (mqtts)</label>
+<p class="hint">The broker is verified against this CA, else the one built into the firmware, else the ESP-IDF bundle of public CAs. Its certificate must name the broker host as entered on the setup page.</p>
+<label>CA Certificate (PEM)</label>
+<textarea name="ca_cert" placeholder="-----BEGIN CERTIFICATE-----">{ca_cert}</textarea>
+<label>Client Certificate (PEM)</label>
+<textarea name="client_cert" placeholder="none">{client_cert}</textarea>
+<label>Client Private Key (PEM)</label>
+<textarea name="client_key" placeholder="{key_hint}"></textarea>
+<input type="submit" value="Save &amp; Reboot">
+</form>
+<p><a href="/">Setup</a></p>{HTML_FOOTER}"#,
+                checked = if cfg.mqtt_tls == 1 { " checked" } else { "" },
+                ca_cert = html_escape(&cfg.mqtt_ca_cert),
+                client_cert = html_escape(&cfg.mqtt_client_cert),
+                key_hint = if cfg.mqtt_client_key.is_empty() { "none" } else { "stored, leave empty to keep" },
+            );
+            drop(cfg);
+            let mut resp = req.into_ok_response()?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        let config_tls_post = config.clone();
+        server.fn_handler::<anyhow::Error, _>("/tls", Method::Post, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_tls_post.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated TLS change");
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+
+            // Three percent-encoded PEM files
+            let mut buf = vec![0u8; 16 * 1024];
+            let mut total = 0;
+            while total < buf.len() {
+                match req.read(&mut buf[total..]) {
+                    Ok(0) => break,
+                    Ok(n) => total += n,
+                    Err(e) => {
+                        warn!("Web POST read error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            let body = String::from_utf8_lossy(&buf[..total]);
+
+            let mut tls = 0;
+            let mut ca_cert = String::new();
+            let mut client_cert = String::new();
+            let mut client_key = String::new();
+            for pair in body.split('&') {
+                let (key, val) = pair.split_once('=').unwrap_or((pair, ""));
+                let val = url_decode(val).replace("\r\n", "\n").trim().to_string();
+                match key {
+                    "tls" => tls = 1,
+                    "ca_cert" => ca_cert = val,
+                    "client_cert" => client_cert = val,
+                    "client_key" => client_key = val,
+                    _ => {}
+                }
+            }
+            if let Some(bad) = [&ca_cert, &client_cert, &client_key].into_iter().find(|pem| !is_pem(pem)) {
+                let message = format!("Not a PEM file (or longer than {} bytes): {:.40}", MAX_PEM_LEN, bad);
+                req.into_response(400, Some("Bad Request"), &[])?.write_all(message.as_bytes())?;
+                return Ok(());
+            }
+
+            {
+                let mut cfg = config_tls_post.lock().unwrap();
+                let _ = cfg.set_mqtt_tls(tls);
+                let _ = cfg.set_mqtt_ca_cert(&ca_cert);
+                let _ = cfg.set_mqtt_client_cert(&client_cert);
+                // No certificate, no key; an empty key field keeps the stored one
+                if client_cert.is_empty() {
+                    let _ = cfg.set_mqtt_client_key("");
+                } else if !client_key.is_empty() {
+                    let _ = cfg.set_mqtt_client_key(&client_key);
+                }
+            }
+
+            config_tls_post.lock().unwrap().flush();
+            let resp_body = format!("{}<p>TLS settings saved. Rebooting...</p>{}", HTML_HEADER, HTML_FOOTER);
+            let mut resp = req.into_ok_response()?;
+            resp.write_all(resp_body.as_bytes())?;
+            drop(resp);
+
+            // Give the response time to be sent
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            unsafe { esp_idf_svc::sys::esp_restart(); }
+        })?;
+
+        #[cfg(feature = "lockout")]
+        let config_lockout = config.clone();
+        #[cfg(feature = "lockout")]
+        let lockout_get = lockout.clone();
+        #[cfg(feature = "lockout")]
+        server.fn_handler::<anyhow::Error, _>("/lockout", Method::Get, move |req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_lockout.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let lockout = lockout_get.lock().unwrap();
+            let content = if lockout.is_engaged() {
+                let presence = if lockout.is_present(SystemClock.uptime()) {
+                    "Button press registered, enter the PIN to release."
+                } else {
+                    "Press the button on the unit, then enter the PIN within 2 minutes."
+                };
+                format!(
+                    r#"<p><b>ENGAGED:</b> pumps, VFD, heater and valve are off and ignore all commands.</p>
+<form method="post" action="/lockout">
+<input type="hidden" name="action" value="release">
+<p class="hint">{presence}</p>
+<label>PIN</label>
+<input name="pin" type="password" inputmode="numeric" required>
+<input type="submit" value="Release Lockout">
+</form>
+"#
+                )
+            } else {
+                format!(
+                    r#"<p>Released: outputs run normally.</p>
+<form method="post" action="/lockout">
+<input type="hidden" name="action" value="engage">
+<label>PIN ({min} to {max} digits)</label>
+<input name="pin" type="password" inputmode="numeric" pattern="[0-9]{{{min},{max}}}" required>
+<p class="hint">Releasing needs this PIN and a button press at the unit.</p>
+<input type="submit" value="Engage Lockout">
+</form>
+"#,
+                    min = lockout::PIN_LEN.start(),
+                    max = lockout::PIN_LEN.end(),
+                )
+            };
+            drop(lockout);
+            let body = format!(
+                r#"{HTML_HEADER}<h2>Lockout/Tagout</h2>
+{content}<p><a href="/">Setup</a></p>{HTML_FOOTER}"#,
+            );
+            let mut resp = req.into_ok_response()?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        #[cfg(feature = "lockout")]
+        let config_lockout_post = config.clone();
+        #[cfg(feature = "lockout")]
+        server.fn_handler::<anyhow::Error, _>("/lockout", Method::Post, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_lockout_post.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated lockout change");
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+
+            let mut buf = [0u8; 128];
+            let mut total = 0;
+            while total < buf.len() {
+                match req.read(&mut buf[total..]) {
+                    Ok(0) => break,
+                    Ok(n) => total += n,
+                    Err(e) => {
+                        warn!("Web POST read error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            let body = String::from_utf8_lossy(&buf[..total]);
+            let mut engage = false;
+            let mut pin = String::new();
+            for pair in body.split('&') {
+                match pair.split_once('=') {
+                    Some(("action", val)) => engage = val == "engage",
+                    Some(("pin", val)) => pin = url_decode(val),
+                    _ => {}
+                }
+            }
+
+            let result: Result<(), LockoutError> = {
+                let mut lockout = lockout.lock().unwrap();
+                if engage {
+                    lockout.engage(&pin)
+                } else {
+                    lockout.release(&pin, SystemClock.uptime())
+                }
+            };
+            if let Err(e) = result {
+                warn!("Web: lockout {} refused: {}", if engage { "engage" } else { "release" }, e.message());
+                req.into_response(409, Some("Conflict"), &[])?.write_all(e.message().as_bytes())?;
+                return Ok(());
+            }
+            // Kept in NVS so a reboot doesn't release it
+            let stored = if engage { pin.as_str() } else { "" };
+            if let Err(e) = config_lockout_post.lock().unwrap().set_lockout_pin(stored) {
+                warn!("Web: failed to store lockout: {:?}", e);
+            }
+            warn!("Web: lockout {}", if engage { "engaged" } else { "released" });
+
+            req.into_response(303, Some("See Other"), &[("Location", "/lockout")])?;
+            Ok(())
+        })?;
+
+        #[cfg(feature = "ds18b20")]
+        let config_probes = config.clone();
+        #[cfg(feature = "ds18b20")]
+        server.fn_handler::<anyhow::Error, _>("/probes", Method::Get, move |req| {
+            let cfg = config_probes.lock().unwrap();
+            if Role::from_authorization(req.header("Authorization"), &cfg) != Some(Role::Admin) {
+                drop(cfg);
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let names = ds18b20::parse_names(&cfg.probe_names);
+            drop(cfg);
+
+            let found = probes.lock().unwrap().clone();
+            let mut rows = String::new();
+            for probe in &found {
+                let rom = ds18b20::rom_to_hex(probe.rom);
+                let name = names.iter().find(|(r, _)| *r == probe.rom).map_or("", |(_, n)| n.as_str());
+                let reading = match probe.celsius {
+                    Some(celsius) => format!("{:.1} &deg;C", celsius),
+                    None => "no reading".to_string(),
+                };
+                rows += &format!(
+                    r#"<label>{rom} ({reading})</label>
+<input name="{rom}" type="text" value="{name}" maxlength="{max}" placeholder="unnamed">
+"#,
+                    max = ds18b20::MAX_NAME_LEN,
+                );
+            }
+            if found.is_empty() {
+                rows = "<p>No probes found on the bus.</p>\n".to_string();
+            }
+            let body = format!(
+                r#"{HTML_HEADER}<h2>Temperature Probes</h2>
+<p class="hint">The bus is rescanned every 10 seconds. Clear a name to remove it.</p>
+<form method="post" action="/probes">
+{rows}<input type="submit" value="Save">
+</form>
+<p><a href="/">Setup</a></p>{HTML_FOOTER}"#,
+            );
+            let mut resp = req.into_ok_response()?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        #[cfg(feature = "ds18b20")]
+        let config_probes_post = config.clone();
+        #[cfg(feature = "ds18b20")]
+        server.fn_handler::<anyhow::Error, _>("/probes", Method::Post, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_probes_post.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated probe naming");
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+
+            let mut buf = [0u8; 1024];
+            let mut total = 0;
+            while total < buf.len() {
+                match req.read(&mut buf[total..]) {
+                    Ok(0) => break,
+                    Ok(n) => total += n,
+                    Err(e) => {
+                        warn!("Web POST read error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            let body = String::from_utf8_lossy(&buf[..total]);
+
+            // Probes not on the bus right now keep their names
+            let mut cfg = config_probes_post.lock().unwrap();
+            let mut names = ds18b20::parse_names(&cfg.probe_names);
+            for pair in body.split('&') {
+                let (key, val) = pair.split_once('=').unwrap_or((pair, ""));
+                let Some(rom) = ds18b20::parse_rom(key) else { continue };
+                names.retain(|(r, _)| *r != rom);
+                let name = ds18b20::sanitize_name(&url_decode(val));
+                if name.is_empty() {
+                    continue;
+                }
+                if names.len() >= ds18b20::MAX_PROBES {
+                    warn!("Web: more than {} probe names, ignoring {}", ds18b20::MAX_PROBES, key);
+                    continue;
+                }
+                names.push((rom, name));
+            }
+            let _ = cfg.set_probe_names(&ds18b20::format_names(&names));
+            drop(cfg);
+
+            req.into_response(303, Some("See Other"), &[("Location", "/probes")])?;
+            Ok(())
+        })?;
+
+        #[cfg(feature = "notify")]
+        let config_notify = config.clone();
+        #[cfg(feature = "notify")]
+        server.fn_handler::<anyhow::Error, _>("/notify", Method::Get, move |req| {
+            let cfg = config_notify.lock().unwrap();
+            if Role::from_authorization(req.header("Authorization"), &cfg) != Some(Role::Admin) {
+                drop(cfg);
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let templates = notify::parse_templates(&cfg.notify_templates);
+            let mut rows = String::new();
+            for event in Event::ALL {
+                let template = templates.iter().find(|(e, _)| *e == event).map_or("", |(_, t)| t.as_str());
+                rows += &format!(
+                    r#"<label>{label}</label>
+<input name="{key}" type="text" value="{value}" maxlength="{max}" placeholder="{default}">
+"#,
+                    label = event.label(),
+                    key = event.key(),
+                    value = html_escape(template),
+                    max = notify::MAX_TEMPLATE_LEN,
+                    default = html_escape(event.default_template()),
+                );
+            }
+            let body = format!(
+                r#"{HTML_HEADER}<h2>Notifications</h2>
+<form method="post" action="/notify">
+<label>Tank Name</label>
+<input name="tank_name" type="text" value="{tank_name}" maxlength="64">
+<label>Webhook URL</label>
+<input name="webhook_url" type="url" value="{webhook_url}" maxlength="200" placeholder="none">
+<h2>Messages</h2>
+<p class="hint">Placeholders: {{tank}} {{level}} {{gallons}} {{psi}} {{pump}} {{time}}. Leave empty for the default text.</p>
+{rows}<input type="submit" value="Save">
+</form>
+<p><a href="/">Setup</a></p>{HTML_FOOTER}"#,
+                tank_name = html_escape(&cfg.tank_name),
+                webhook_url = html_escape(&cfg.webhook_url),
+            );
+            drop(cfg);
+            let mut resp = req.into_ok_response()?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        #[cfg(feature = "notify")]
+        let config_notify_post = config.clone();
+        #[cfg(feature = "notify")]
+        server.fn_handler::<anyhow::Error, _>("/notify", Method::Post, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_notify_post.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated notification settings");
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+
+            // Percent-encoded non-ASCII templates take three bytes per byte
+            let mut buf = vec![0u8; 4096];
+            let mut total = 0;
+            while total < buf.len() {
+                match req.read(&mut buf[total..]) {
+                    Ok(0) => break,
+                    Ok(n) => total += n,
+                    Err(e) => {
+                        warn!("Web POST read error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            let body = String::from_utf8_lossy(&buf[..total]);
+
+            let mut cfg = config_notify_post.lock().unwrap();
+            let mut templates = Vec::new();
+            for pair in body.split('&') {
+                let (key, val) = pair.split_once('=').unwrap_or((pair, ""));
+                let val = url_decode(val);
+                match key {
+                    "tank_name" => {
+                        let name: String = val.chars().filter(|c| !c.is_control()).take(64).collect();
+                        let _ = cfg.set_tank_name(name.trim());
+                    }
+                    "webhook_url" => {
+                        let url = val.trim();
+                        if url.is_empty() || (url.len() <= 200 && (url.starts_with("http://") || url.starts_with("https://"))) {
+                            let _ = cfg.set_webhook_url(url);
+                        } else {
+                            warn!("Web: ignoring webhook URL '{}'", url);
+                        }
+                    }
+                    _ => {
+                        let Some(event) = Event::from_key(key) else { continue };
+                        let template = notify::sanitize_template(&val);
+                        if !template.is_empty() {
+                            templates.push((event, template));
+                        }
+                    }
+                }
+            }
+            let _ = cfg.set_notify_templates(&notify::format_templates(&templates));
+            drop(cfg);
+
+            req.into_response(303, Some("See Other"), &[("Location", "/notify")])?;
+            Ok(())
+        })?;
+
+        #[cfg(feature = "sim-sensors")]
+        let config_playback = config.clone();
+        #[cfg(feature = "sim-sensors")]
+        let playback_get = playback.clone();
+        #[cfg(feature = "sim-sensors")]
+        server.fn_handler::<anyhow::Error, _>("/playback", Method::Get, move |req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_playback.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let status = match playback_get.lock().unwrap().as_ref() {
+                Some(playback) => {
+                    let (played, length) = playback.position(SystemClock.uptime());
+                    format!(
+                        r#"<p><b>PLAYING:</b> {samples} samples at {speed}x, {played} of {length} min. The sensors are ignored until it ends.</p>
+<form method="post" action="/playback/stop">
+<input type="submit" value="Stop &amp; Use Sensors">
+</form>
+"#,
+                        samples = playback.len(),
+                        speed = playback.speed(),
+                        played = played / 60,
+                        length = length / 60,
+                    )
+                }
+                None => "<p>Idle: readings come from the sensors.</p>\n".to_string(),
+            };
+            let body = format!(
+                r#"{HTML_HEADER}<h2>Sensor Playback</h2>
+{status}<form id="playback">
+<label>Trace (.csv)</label>
+<input id="trace" type="file" accept=".csv,text/csv" required>
+<p class="hint">timestamp,capacity_percent,pressure_psi,gallons per line, up to {max_kb} KB</p>
+<label>Speed</label>
+<select id="speed"><option value="1">Real time</option><option value="10">10x</option>
+<option value="60">60x (1 min/s)</option><option value="600">600x</option><option value="3600">3600x (1 h/s)</option></select>
+<input type="submit" value="Upload &amp; Play">
+</form>
+<p id="result" class="hint"></p>
+<script>
+document.getElementById('playback').onsubmit=async e=>{{
+e.preventDefault();
+const out=document.getElementById('result');
+out.textContent='Uploading...';
+try{{
+const r=await fetch('/playback?speed='+document.getElementById('speed').value,{{method:'POST',body:document.getElementById('trace').files[0]}});
+out.textContent=await r.text();
+}}catch(err){{out.textContent='Upload failed: '+err;}}
+}};
+</script>
+<p><a href="/">Setup</a></p>{HTML_FOOTER}"#,
+                max_kb = playback::MAX_UPLOAD / 1024,
+            );
+            let mut resp = req.into_ok_response()?;
+            resp.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+
+        #[cfg(feature = "sim-sensors")]
+        let config_playback_post = config.clone();
+        #[cfg(feature = "sim-sensors")]
+        let playback_post = playback.clone();
+        #[cfg(feature = "sim-sensors")]
+        server.fn_handler::<anyhow::Error, _>("/playback", Method::Post, move |mut req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_playback_post.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                warn!("Web: rejected unauthenticated trace upload");
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            let speed = req
+                .uri()
+                .split_once("speed=")
+                .and_then(|(_, v)| v.split('&').next()?.parse().ok())
+                .unwrap_or(1);
+
+            let mut buf = vec![0u8; playback::MAX_UPLOAD + 1];
+            let mut total = 0;
+            while total < buf.len() {
+                match req.read(&mut buf[total..]) {
+                    Ok(0) => break,
+                    Ok(n) => total += n,
+                    Err(e) => {
+                        warn!("Web trace read error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+            if total > playback::MAX_UPLOAD {
+                req.into_response(413, Some("Payload Too Large"), &[])?
+                    .write_all(format!("trace larger than {} KB", playback::MAX_UPLOAD / 1024).as_bytes())?;
+                return Ok(());
+            }
+            let result = crate::trace::parse_trace(&String::from_utf8_lossy(&buf[..total]))
+                .and_then(|trace| Playback::new(trace, speed, SystemClock.uptime()));
+            drop(buf);
+            match result {
+                Ok(started) => {
+                    let (_, length) = started.position(SystemClock.uptime());
+                    let message = format!("playing {} samples ({} min) at {}x", started.len(), length / 60, speed);
+                    warn!("Web: {}, sensors ignored", message);
+                    *playback_post.lock().unwrap() = Some(started);
+                    req.into_ok_response()?.write_all(message.as_bytes())?;
+                }
+                Err(e) => {
+                    req.into_response(400, Some("Bad Request"), &[])?.write_all(e.as_bytes())?;
+                }
+            }
+            Ok(())
+        })?;
+
+        #[cfg(feature = "sim-sensors")]
+        let config_playback_stop = config.clone();
+        #[cfg(feature = "sim-sensors")]
+        server.fn_handler::<anyhow::Error, _>("/playback/stop", Method::Post, move |req| {
+            let role = Role::from_authorization(
+                req.header("Authorization"),
+                &config_playback_stop.lock().unwrap(),
+            );
+            if role != Some(Role::Admin) {
+                req.into_response(401, Some("Unauthorized"), AUTH_HEADERS)?;
+                return Ok(());
+            }
+            if playback.lock().unwrap().take().is_some() {
+                info!("Web: playback stopped, back to the sensors");
+            }
+            req.into_response(303, Some("See Other"), &[("Location", "/playback")])?;
+            Ok(())
+        })?;
+
         info!("Web server started on port 80");
 
-        Ok(Self { _server: server })
+        Ok(Self { server, events })
+    }
+
+    /// Push the state document to the `/events` subscribers
+    pub fn publish_state(&self, json: &str) {
+        let event = self.events.lock().unwrap().publish(json);
+        self.send_event(event);
+    }
+
+    /// Push a self-test report (see `selftest`) to the event stream subscribers
+    pub fn publish_selftest(&self, json: &str) {
+        self.send_event(events::frame("selftest", json));
+    }
+
+    /// Queue `event` for every subscriber, on the server task
+    fn send_event(&self, event: String) {
+        if self.events.lock().unwrap().clients().is_empty() {
+            return;
+        }
+        let work = Box::into_raw(Box::new(Broadcast {
+            server: self.server.handle(),
+            events: self.events.clone(),
+            event,
+        }));
+        if let Err(e) = esp!(unsafe { httpd_queue_work(self.server.handle(), Some(broadcast), work as *mut c_void) }) {
+            drop(unsafe { Box::from_raw(work) });
+            warn!("Events: queue error: {:?}", e);
+        }
+    }
+
+    /// Send requests for unknown pages to the setup page
+    ///
+    /// For the WiFi setup portal: the connectivity checks of phones and
+    /// laptops (`/generate_204`, `/hotspot-detect.html`) get a redirect
+    /// instead of the expected answer, so they open the page by themselves.
+    #[cfg(feature = "wifi")]
+    pub fn redirect_unknown(&self) -> Result<(), EspError> {
+        esp!(unsafe { httpd_register_err_handler(self.server.handle(), httpd_err_code_t_HTTPD_404_NOT_FOUND, Some(redirect_to_setup)) })?;
+        info!("Web: redirecting unknown pages to the setup page");
+        Ok(())
+    }
+}
+
+/// 404 handler answering with a redirect to the setup page on the portal address
+#[cfg(feature = "wifi")]
+unsafe extern "C" fn redirect_to_setup(req: *mut httpd_req_t, _error: httpd_err_code_t) -> esp_err_t {
+    // By address (`captive::AP_ADDRESS`), since the name the client asked for
+    // only resolves to the unit while it is on the portal
+    httpd_resp_set_status(req, c"302 Found".as_ptr());
+    httpd_resp_set_hdr(req, c"Location".as_ptr(), c"http://192.168.71.1/".as_ptr());
+    httpd_resp_send(req, std::ptr::null(), 0)
+}
+
+/// An event on its way to the subscribers
+struct Broadcast {
+    server: httpd_handle_t,
+    events: Arc<Mutex<EventStream>>,
+    event: String,
+}
+
+/// Send an event to every subscriber
+///
+/// Runs on the server task, where sockets are closed, so a socket can't be
+/// closed and reused for another client halfway through.
+unsafe extern "C" fn broadcast(arg: *mut c_void) {
+    let work = Box::from_raw(arg as *mut Broadcast);
+    let mut events = work.events.lock().unwrap();
+    for fd in events.clients().to_vec() {
+        let sent = lwip_send(fd, work.event.as_ptr() as *const c_void, work.event.len(), MSG_DONTWAIT as c_int);
+        // Gone, or too slow to keep up with the updates
+        if sent < 0 || sent as usize != work.event.len() {
+            debug!("Events: dropping subscriber {}", fd);
+            events.unsubscribe(fd);
+            httpd_sess_trigger_close(work.server, fd);
+        }
+    }
+}
+
+/// Hand the socket of `req` over to the event stream, sending `first` after the greeting
+///
+/// The handler returns right after, so the server task isn't held up; the
+/// events are written by `broadcast`.
+fn subscribe_events(
+    mut req: Request<&mut EspHttpConnection<'_>>,
+    events: &Arc<Mutex<EventStream>>,
+    first: Option<String>,
+) -> anyhow::Result<()> {
+    let raw = req.connection().handle();
+    let (handle, fd) = unsafe { ((*raw).handle, httpd_req_to_sockfd(raw)) };
+    let Some(mut greeting) = events.lock().unwrap().subscribe(fd) else {
+        req.into_response(503, Some("Too Many Subscribers"), &[])?;
+        return Ok(());
+    };
+    greeting.extend(first);
+    req.connection().raw_connection()?.write_all(greeting.as_bytes())?;
+    unsafe {
+        // Unsubscribed when the server closes the socket
+        if (*raw).sess_ctx.is_null() {
+            (*raw).sess_ctx = Box::into_raw(Box::new((events.clone(), fd))) as *mut c_void;
+            (*raw).free_ctx = Some(unsubscribe);
+        }
+        // The server still ends the response after this handler; keep
+        // that out of the stream
+        esp!(httpd_sess_set_send_override(handle, fd, Some(discard)))?;
     }
+    debug!("Events: subscriber {}", fd);
+    Ok(())
+}
+
+/// Session context destructor of an event stream socket
+unsafe extern "C" fn unsubscribe(ctx: *mut c_void) {
+    let (events, fd) = *Box::from_raw(ctx as *mut (Arc<Mutex<EventStream>>, i32));
+    events.lock().unwrap().unsubscribe(fd);
+}
+
+/// Send override of an event stream socket, which is written with `lwip_send`
+unsafe extern "C" fn discard(_server: httpd_handle_t, _fd: c_int, _buf: *const c_char, len: usize, _flags: c_int) -> c_int {
+    len as c_int
+}
+
+/// Escape text for an HTML attribute value or element content
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Whether `s` is empty or looks like a PEM certificate or key that fits NVS
+fn is_pem(s: &str) -> bool {
+    s.is_empty() || (s.len() <= MAX_PEM_LEN && s.starts_with("-----BEGIN ") && s.contains("-----END "))
 }
 
 /// Minimal URL percent-decoding and '+' to space conversion
@@ -165,3 +2109,27 @@ fn url_decode(input: &str) -> String {
     }
     String::from_utf8_lossy(&out).into_owned()
 }
+
+/// Byte comparison that doesn't stop at the first mismatch, so the response
+/// time doesn't tell how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Standard base64 encoding with padding
+fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((input.len() + 2) / 3 * 4);
+    for chunk in input.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
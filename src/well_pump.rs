@@ -0,0 +1,132 @@
+//! Well pump state inferred from pressure
+//!
+//! On retrofit installs a pressure switch runs the well pump and the
+//! controller only watches the line pressure. The pump still leaves a clear
+//! signature: once it cuts in, pressure climbs steadily towards the cut-out
+//! level, and it stops climbing the moment the pump does. The detector takes
+//! the pressure samples of the 5 second update and reports the pump as
+//! running after a steady rise of at least `well_rise` PSI over 15 seconds,
+//! and as stopped once pressure has not risen for another 15 seconds. Starts
+//! and run time are counted from that, like the relay-driven pumps.
+//!
+//! Heavy draw while the pump runs can hold the pressure flat, which reads as
+//! a stop; the next rise counts as a new start.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Samples spanning the rise and stop windows
+const WINDOW: usize = 4;
+/// Time a window spans at the 5 s update
+const SPAN: Duration = Duration::from_secs(15);
+
+/// Pump state and statistics inferred from the pressure signature
+#[derive(Debug, Clone)]
+pub struct WellPump {
+    /// Steady rise over the window that means the pump cut in (PSI)
+    rise_psi: u16,
+    recent: VecDeque<u16>,
+    /// Uptime of the current run's start
+    running_since: Option<Duration>,
+    /// Run time of finished runs
+    runtime: Duration,
+    starts: u32,
+}
+
+impl WellPump {
+    pub fn new(rise_psi: u16) -> Self {
+        Self { rise_psi, recent: VecDeque::with_capacity(WINDOW), running_since: None, runtime: Duration::ZERO, starts: 0 }
+    }
+
+    /// Apply a changed cut-in rise
+    pub fn set_rise(&mut self, rise_psi: u16) {
+        self.rise_psi = rise_psi;
+    }
+
+    /// Feed a pressure sample; returns the new state when it changes
+    ///
+    /// Only good readings belong here: a failed read would look like a
+    /// pressure drop and the recovery like a pump start.
+    pub fn update(&mut self, psi: u16, now: Duration) -> Option<bool> {
+        if self.recent.len() == WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(psi);
+        if self.recent.len() < WINDOW {
+            return None;
+        }
+        let first = self.recent[0];
+        match self.running_since {
+            None => {
+                let steady = self.recent.iter().zip(self.recent.iter().skip(1)).all(|(a, b)| b >= a);
+                if steady && psi >= first + self.rise_psi {
+                    // The rise began a window ago
+                    self.running_since = Some(now.saturating_sub(SPAN));
+                    self.starts += 1;
+                    return Some(true);
+                }
+            }
+            Some(since) if psi <= first => {
+                // Stopped climbing when the window began
+                self.runtime += now.saturating_sub(SPAN).saturating_sub(since);
+                self.running_since = None;
+                return Some(false);
+            }
+            Some(_) => {}
+        }
+        None
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running_since.is_some()
+    }
+
+    /// Run time since boot, including the current run
+    pub fn runtime(&self, now: Duration) -> Duration {
+        self.runtime + self.running_since.map_or(Duration::ZERO, |since| now.saturating_sub(since))
+    }
+
+    /// Starts since boot
+    pub fn starts(&self) -> u32 {
+        self.starts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed samples 5 s apart, returning the state changes and when they happened
+    fn feed(pump: &mut WellPump, samples: &[u16]) -> Vec<(u64, bool)> {
+        samples
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &psi)| {
+                let at = 5 * i as u64;
+                pump.update(psi, Duration::from_secs(at)).map(|running| (at, running))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detects_pump_cycle() {
+        let mut pump = WellPump::new(3);
+        // Drawdown to cut-in at 40, pump run to cut-out at 60, then drawdown again
+        let samples = [46, 44, 42, 40, 40, 42, 44, 47, 50, 53, 56, 59, 60, 60, 59, 59, 58];
+        assert_eq!(feed(&mut pump, &samples), [(30, true), (70, false)]);
+        assert_eq!(pump.starts(), 1);
+        // Both ends are dated back to the start of their window
+        assert_eq!(pump.runtime(Duration::from_secs(200)), Duration::from_secs(40));
+        assert!(!pump.is_running());
+    }
+
+    #[test]
+    fn test_ignores_noise() {
+        let mut pump = WellPump::new(3);
+        // A 1 PSI jitter and a single jump aren't a steady rise
+        let samples = [50, 51, 50, 51, 50, 54, 51, 50, 51, 52, 51];
+        assert_eq!(feed(&mut pump, &samples), []);
+        assert_eq!(pump.starts(), 0);
+        assert_eq!(pump.runtime(Duration::from_secs(60)), Duration::ZERO);
+    }
+}
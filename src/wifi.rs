@@ -0,0 +1,215 @@
+//! WiFi fallback with a setup portal
+//!
+//! Not every install site has Ethernet near the tank. A `wifi` build waits a
+//! while for an Ethernet link at boot, and without one joins the WiFi network
+//! stored in `wifi_ssid` and `wifi_pass`. When none is stored, or it can't be
+//! joined, the unit opens an access point named `WaterController-XXXX` (from
+//! its MAC) with a captive portal (see `captive`): a phone joining it is sent
+//! to the usual setup page, where the WiFi network is entered next to the MQTT
+//! settings. Saving reboots, and the unit comes up in station mode.
+//!
+//! While the portal is up the stored network is retried every minute, so a
+//! unit that came up before the router joins it by itself.
+
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::eventloop::{EspSubscription, EspSystemEventLoop, System};
+use esp_idf_svc::hal::modem::Modem;
+use esp_idf_svc::ipv4::{self, DHCPClientSettings, RouterConfiguration, Subnet};
+use esp_idf_svc::netif::{EspNetif, NetifConfiguration};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sys::EspError;
+use esp_idf_svc::wifi::{
+    AccessPointConfiguration, AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi, WifiDeviceId,
+    WifiDriver, WifiEvent,
+};
+use log::*;
+
+use crate::captive::{self, AP_ADDRESS};
+use crate::net::{unit_suffix, HOSTNAME};
+
+/// How often the stored network is retried while the portal is up
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// Phones and laptops on the setup access point at once
+const MAX_PORTAL_CLIENTS: u16 = 4;
+
+/// Station link, or the setup portal until one is configured
+pub struct WifiLink {
+    wifi: BlockingWifi<EspWifi<'static>>,
+    /// Stored network (`None` = not configured)
+    station: Option<ClientConfiguration>,
+    portal: Arc<AtomicBool>,
+    /// Stops the captive DNS responder the portal started
+    dns_stop: Arc<AtomicBool>,
+    portal_ssid: String,
+    last_attempt: Instant,
+    _subscription: EspSubscription<'static, System>,
+}
+
+impl WifiLink {
+    /// Join the stored network, or open the setup portal
+    ///
+    /// Blocks for up to the connect timeout while joining.
+    pub fn start(
+        modem: Modem,
+        sysloop: EspSystemEventLoop,
+        nvs: EspDefaultNvsPartition,
+        ssid: &str,
+        password: &str,
+    ) -> Result<Self, EspError> {
+        let sta_netif = EspNetif::new_with_conf(&NetifConfiguration {
+            ip_configuration: Some(ipv4::Configuration::Client(ipv4::ClientConfiguration::DHCP(DHCPClientSettings {
+                hostname: Some(HOSTNAME.try_into().unwrap()),
+            }))),
+            ..NetifConfiguration::wifi_default_client()
+        })?;
+        // The portal's DHCP server hands out the unit as the DNS server, so
+        // the captive DNS answers every lookup
+        let ap_netif = EspNetif::new_with_conf(&NetifConfiguration {
+            ip_configuration: Some(ipv4::Configuration::Router(RouterConfiguration {
+                subnet: Subnet { gateway: AP_ADDRESS, mask: ipv4::Mask(24) },
+                dhcp_enabled: true,
+                dns: Some(AP_ADDRESS),
+                secondary_dns: None,
+            })),
+            ..NetifConfiguration::wifi_default_router()
+        })?;
+        let driver = WifiDriver::new(modem, sysloop.clone(), Some(nvs))?;
+        let mut wifi = BlockingWifi::wrap(EspWifi::wrap_all(driver, sta_netif, ap_netif)?, sysloop.clone())?;
+
+        let mac = wifi.wifi().get_mac(WifiDeviceId::Ap)?;
+        let portal_ssid = format!("WaterController-{}", unit_suffix(mac));
+
+        let station = (!ssid.is_empty()).then(|| ClientConfiguration {
+            ssid: ssid.try_into().unwrap_or_default(),
+            password: password.try_into().unwrap_or_default(),
+            auth_method: if password.is_empty() { AuthMethod::None } else { AuthMethod::WPA2Personal },
+            ..Default::default()
+        });
+
+        // Rejoin after the access point drops us, like the Ethernet link
+        // coming back; the portal retries on its own schedule
+        let portal = Arc::new(AtomicBool::new(false));
+        let portal_cb = portal.clone();
+        let subscription = sysloop.subscribe::<WifiEvent, _>(move |event| {
+            if let WifiEvent::StaDisconnected(_) = event {
+                if !portal_cb.load(Ordering::Relaxed) {
+                    warn!("WiFi: disconnected, reconnecting");
+                    unsafe { esp_idf_svc::sys::esp_wifi_connect() };
+                }
+            }
+        })?;
+
+        let mut link = Self {
+            wifi,
+            station,
+            portal,
+            dns_stop: Arc::new(AtomicBool::new(false)),
+            portal_ssid,
+            last_attempt: Instant::now(),
+            _subscription: subscription,
+        };
+        if !link.join()? {
+            link.open_portal()?;
+        }
+        Ok(link)
+    }
+
+    /// Station address and gateway, `None` while the portal is up
+    pub fn address(&self) -> Option<(Ipv4Addr, Ipv4Addr)> {
+        if self.is_portal() {
+            return None;
+        }
+        let info = self.wifi.wifi().sta_netif().get_ip_info().ok()?;
+        Some((info.ip, info.subnet.gateway))
+    }
+
+    pub fn is_portal(&self) -> bool {
+        self.portal.load(Ordering::Relaxed)
+    }
+
+    /// Name of the setup access point
+    pub fn portal_ssid(&self) -> &str {
+        &self.portal_ssid
+    }
+
+    /// Retry the stored network while the portal is up; call on every loop pass
+    ///
+    /// Returns true once it has been joined. The caller restarts then, so
+    /// MQTT and the rest come up in station mode like on any other boot.
+    pub fn poll(&mut self) -> bool {
+        if !self.is_portal() || self.station.is_none() {
+            return false;
+        }
+        if self.wifi.wifi().sta_netif().is_up().unwrap_or(false) {
+            info!("WiFi: joined {} from the setup portal", self.station.as_ref().map_or("", |s| s.ssid.as_str()));
+            return true;
+        }
+        if self.last_attempt.elapsed() >= RETRY_INTERVAL {
+            self.last_attempt = Instant::now();
+            debug!("WiFi: retrying {}", self.station.as_ref().map_or("", |s| s.ssid.as_str()));
+            // Non-blocking, so the portal stays responsive
+            if let Err(e) = self.wifi.wifi_mut().connect() {
+                debug!("WiFi: connect failed: {:?}", e);
+            }
+        }
+        false
+    }
+
+    /// Join the stored network; false if there is none or it can't be joined
+    fn join(&mut self) -> Result<bool, EspError> {
+        let Some(station) = self.station.clone() else {
+            info!("WiFi: no network configured");
+            return Ok(false);
+        };
+        let station_ssid = station.ssid.clone();
+        info!("WiFi: joining {}", station_ssid);
+        self.wifi.set_configuration(&Configuration::Client(station))?;
+        self.wifi.start()?;
+        match self.wifi.connect().and_then(|_| self.wifi.wait_netif_up()) {
+            Ok(()) => {
+                info!("WiFi: connected to {}", station_ssid);
+                Ok(true)
+            }
+            Err(e) => {
+                warn!("WiFi: can't join {}: {:?}", station_ssid, e);
+                self.wifi.stop()?;
+                Ok(false)
+            }
+        }
+    }
+
+    fn open_portal(&mut self) -> Result<(), EspError> {
+        let access_point = AccessPointConfiguration {
+            ssid: self.portal_ssid.as_str().try_into().unwrap(),
+            auth_method: AuthMethod::None,
+            max_connections: MAX_PORTAL_CLIENTS,
+            ..Default::default()
+        };
+        // Keep the station side to retry the stored network
+        let conf = match &self.station {
+            Some(station) => Configuration::Mixed(station.clone(), access_point),
+            None => Configuration::AccessPoint(access_point),
+        };
+        self.portal.store(true, Ordering::Relaxed);
+        self.wifi.set_configuration(&conf)?;
+        // The access point side is up once started
+        self.wifi.start()?;
+        self.last_attempt = Instant::now();
+        if let Err(e) = captive::start(AP_ADDRESS, self.dns_stop.clone()) {
+            // The page still works when opened by address
+            warn!("WiFi: captive DNS failed to start: {}", e);
+        }
+        info!("WiFi: setup portal up, join {} and open http://{}/", self.portal_ssid, AP_ADDRESS);
+        Ok(())
+    }
+}
+
+impl Drop for WifiLink {
+    fn drop(&mut self) {
+        self.dns_stop.store(true, Ordering::Relaxed);
+    }
+}
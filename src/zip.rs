@@ -0,0 +1,153 @@
+//! Minimal ZIP archive writer
+//!
+//! Enough for the diagnostic bundle on `/api/diagnostics.zip`: a handful of
+//! small text files stored without compression, so any unzip tool opens the
+//! archive and the firmware carries no deflate code. Entries are dated
+//! 1980-01-01, the earliest date ZIP can hold; the files carry their own
+//! timestamps where they matter.
+
+/// Archive built in memory, one entry at a time
+#[derive(Debug, Default)]
+pub struct ZipWriter {
+    data: Vec<u8>,
+    /// Central directory records, written by `finish`
+    directory: Vec<u8>,
+    entries: u16,
+}
+
+/// DOS date of 1980-01-01
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file stored as is
+    pub fn add(&mut self, name: &str, contents: &[u8]) {
+        let crc = crc32(contents);
+        let offset = self.data.len() as u32;
+        let size = contents.len() as u32;
+
+        // Local file header
+        self.data.extend(0x0403_4b50u32.to_le_bytes());
+        self.data.extend(Self::entry_fields(name, crc, size));
+        self.data.extend(name.as_bytes());
+        self.data.extend(contents);
+
+        // Central directory record: made by 2.0, then the same fields plus
+        // no comment, disk 0, no attributes and the local header offset
+        self.directory.extend(0x0201_4b50u32.to_le_bytes());
+        self.directory.extend(20u16.to_le_bytes());
+        self.directory.extend(Self::entry_fields(name, crc, size));
+        self.directory.extend([0; 10]);
+        self.directory.extend(offset.to_le_bytes());
+        self.directory.extend(name.as_bytes());
+        self.entries += 1;
+    }
+
+    /// Files added so far
+    pub fn len(&self) -> usize {
+        self.entries as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// The complete archive
+    pub fn finish(mut self) -> Vec<u8> {
+        let directory_offset = self.data.len() as u32;
+        let directory_size = self.directory.len() as u32;
+        self.data.append(&mut self.directory);
+        // End of central directory: single disk, no comment
+        self.data.extend(0x0605_4b50u32.to_le_bytes());
+        self.data.extend([0; 4]);
+        self.data.extend(self.entries.to_le_bytes());
+        self.data.extend(self.entries.to_le_bytes());
+        self.data.extend(directory_size.to_le_bytes());
+        self.data.extend(directory_offset.to_le_bytes());
+        self.data.extend([0; 2]);
+        self.data
+    }
+
+    /// Fields shared by the local header and the central record, from "version needed"
+    fn entry_fields(name: &str, crc: u32, size: u32) -> Vec<u8> {
+        let mut fields = Vec::with_capacity(26);
+        fields.extend(10u16.to_le_bytes()); // version needed: 1.0, stored
+        fields.extend(0u16.to_le_bytes()); // flags
+        fields.extend(0u16.to_le_bytes()); // method: stored
+        fields.extend(0u16.to_le_bytes()); // time 00:00
+        fields.extend(DOS_DATE.to_le_bytes());
+        fields.extend(crc.to_le_bytes());
+        fields.extend(size.to_le_bytes()); // compressed
+        fields.extend(size.to_le_bytes()); // uncompressed
+        fields.extend((name.len() as u16).to_le_bytes());
+        fields.extend(0u16.to_le_bytes()); // extra field length
+        fields
+    }
+}
+
+/// CRC-32 (IEEE 802.3, reflected, polynomial 0xEDB88320)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(data: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([data[at], data[at + 1]])
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414F_A339);
+    }
+
+    #[test]
+    fn test_archive_layout() {
+        let mut zip = ZipWriter::new();
+        assert!(zip.is_empty());
+        zip.add("a.txt", b"hello");
+        zip.add("logs/b.log", b"");
+        assert_eq!(zip.len(), 2);
+        let data = zip.finish();
+
+        // First local header and its contents
+        assert_eq!(u32_at(&data, 0), 0x0403_4b50);
+        assert_eq!(u16_at(&data, 8), 0);
+        assert_eq!(u32_at(&data, 14), crc32(b"hello"));
+        assert_eq!(u32_at(&data, 18), 5);
+        assert_eq!(u16_at(&data, 26), 5);
+        assert_eq!(&data[30..40], b"a.txthello");
+        assert_eq!(u32_at(&data, 40), 0x0403_4b50);
+
+        // End record points at both central records
+        let end = data.len() - 22;
+        assert_eq!(u32_at(&data, end), 0x0605_4b50);
+        assert_eq!(u16_at(&data, end + 10), 2);
+        let (size, offset) = (u32_at(&data, end + 12) as usize, u32_at(&data, end + 16) as usize);
+        assert_eq!(offset + size, end);
+        assert_eq!(u32_at(&data, offset), 0x0201_4b50);
+        assert_eq!(u32_at(&data, offset + 42), 0);
+        let second = offset + 46 + 5;
+        assert_eq!(u32_at(&data, second), 0x0201_4b50);
+        assert_eq!(u32_at(&data, second + 42), 40);
+        assert_eq!(&data[second + 46..second + 56], b"logs/b.log");
+    }
+}